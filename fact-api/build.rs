@@ -1,8 +1,11 @@
 use anyhow::Context;
 
 fn main() -> anyhow::Result<()> {
+    // The server side is only needed for testing the gRPC output
+    // against a fake Sensor.
+    let build_server = std::env::var_os("CARGO_FEATURE_SERVER").is_some();
     tonic_prost_build::configure()
-        .build_server(false)
+        .build_server(build_server)
         .compile_protos(
             &["../third_party/stackrox/proto/internalapi/sensor/sfa_iservice.proto"],
             &["../third_party/stackrox/proto"],
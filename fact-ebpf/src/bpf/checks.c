@@ -19,3 +19,13 @@ int BPF_PROG(check_inode_set_acl, struct mnt_idmap* idmap, struct dentry* dentry
              struct posix_acl* kacl) {
   return 0;
 }
+
+SEC("lsm/file_ioctl")
+int BPF_PROG(check_file_ioctl, struct file* file, unsigned int cmd, unsigned long arg) {
+  return 0;
+}
+
+SEC("lsm/file_open")
+int BPF_PROG(check_lsm_attach, struct file* file) {
+  return 0;
+}
@@ -12,6 +12,9 @@
 #include <bpf/bpf_helpers.h>
 // clang-format on
 
+// Events of processes whose lineage was already sent end before it.
+#define EVENT_NO_LINEAGE_SIZE (offsetof(struct event_t, process) + offsetof(process_t, lineage))
+
 struct submit_event_args_t {
   struct event_t* event;
   struct metrics_by_hook_t* metrics;
@@ -19,10 +22,33 @@ struct submit_event_args_t {
   inode_key_t inode;
   inode_key_t parent_inode;
   monitored_t monitored;
+  bool blocked;
+  tmpfile_t tmpfile;
+  bool lineage_cached;
 };
 
+// Activity of fact itself is dropped at the top of every hook, before
+// it can be looked up, enforced or tracked.
+__always_inline static bool skip_self(struct metrics_by_hook_t* metrics) {
+  if (!report_self && is_fact()) {
+    metrics->self_activity++;
+    return true;
+  }
+  return false;
+}
+
 __always_inline static bool reserve_event(struct submit_event_args_t* args) {
-  args->event = bpf_ringbuf_reserve(&rb, sizeof(struct event_t), 0);
+  if (is_paused()) {
+    args->metrics->paused++;
+    return false;
+  }
+
+  args->lineage_cached = lineage_is_cached();
+  if (args->lineage_cached) {
+    args->event = bpf_ringbuf_reserve(&rb, EVENT_NO_LINEAGE_SIZE, 0);
+  } else {
+    args->event = bpf_ringbuf_reserve(&rb, sizeof(struct event_t), 0);
+  }
   if (args->event == NULL) {
     args->metrics->ringbuffer_full++;
     return false;
@@ -35,6 +61,8 @@ __always_inline static void __submit_event(struct submit_event_args_t* args,
   struct event_t* event = args->event;
   event->timestamp = bpf_ktime_get_boot_ns();
   event->monitored = args->monitored;
+  event->blocked = args->blocked;
+  event->tmpfile = args->tmpfile;
   inode_copy(&event->inode, &args->inode);
   inode_copy(&event->parent_inode, &args->parent_inode);
   if (args->filename != NULL) {
@@ -48,7 +76,7 @@ __always_inline static void __submit_event(struct submit_event_args_t* args,
     goto error;
   }
 
-  int64_t err = process_fill(&event->process, use_bpf_d_path);
+  int64_t err = process_fill(&event->process, args->lineage_cached, use_bpf_d_path);
   if (err) {
     bpf_printk("Failed to fill process information: %d", err);
     goto error;
@@ -73,6 +101,15 @@ __always_inline static void submit_open_event(struct submit_event_args_t* args,
   __submit_event(args, true);
 }
 
+__always_inline static void submit_write_event(struct submit_event_args_t* args) {
+  if (!reserve_event(args)) {
+    return;
+  }
+  args->event->type = FILE_ACTIVITY_WRITE;
+
+  __submit_event(args, true);
+}
+
 __always_inline static void submit_unlink_event(struct submit_event_args_t* args) {
   if (!reserve_event(args)) {
     return;
@@ -117,7 +154,8 @@ __always_inline static void submit_ownership_event(struct submit_event_args_t* a
 __always_inline static void submit_rename_event(struct submit_event_args_t* args,
                                                 const char old_filename[PATH_MAX],
                                                 inode_key_t* old_inode,
-                                                monitored_t old_monitored) {
+                                                monitored_t old_monitored,
+                                                bool overwrote_existing) {
   if (!reserve_event(args)) {
     return;
   }
@@ -126,6 +164,16 @@ __always_inline static void submit_rename_event(struct submit_event_args_t* args
   bpf_probe_read_str(args->event->rename.filename, PATH_MAX, old_filename);
   inode_copy(&args->event->rename.inode, old_inode);
   args->event->rename.monitored = old_monitored;
+  args->event->rename.overwrote_existing = overwrote_existing;
+
+  __submit_event(args, path_hooks_support_bpf_d_path);
+}
+
+__always_inline static void submit_link_event(struct submit_event_args_t* args) {
+  if (!reserve_event(args)) {
+    return;
+  }
+  args->event->type = FILE_ACTIVITY_CREATION;
 
   __submit_event(args, path_hooks_support_bpf_d_path);
 }
@@ -161,6 +209,19 @@ __always_inline static void submit_xattr_event(struct submit_event_args_t* args,
   __submit_event(args, false);
 }
 
+__always_inline static void submit_attributes_event(struct submit_event_args_t* args,
+                                                    unsigned int old_flags,
+                                                    unsigned int new_flags) {
+  if (!reserve_event(args)) {
+    return;
+  }
+  args->event->type = FILE_ACTIVITY_ATTRIBUTES;
+  args->event->attributes.old = old_flags;
+  args->event->attributes.new = new_flags;
+
+  __submit_event(args, false);
+}
+
 __always_inline static void submit_acl_event(struct submit_event_args_t* args,
                                              const char* acl_name,
                                              struct posix_acl* kacl) {
@@ -27,6 +27,27 @@ __always_inline static bool path_is_monitored(struct bound_path_t* path) {
   return res;
 }
 
+// Check if an operation on the path needs to be denied.
+//
+// fact itself and pid 1 are never blocked, so the agent can keep
+// working and a misconfiguration cannot take down the whole node.
+__always_inline static bool path_is_enforced(struct bound_path_t* path) {
+  if ((bpf_get_current_pid_tgid() >> 32) == 1 || is_fact()) {
+    return false;
+  }
+
+  unsigned int len = path->len;
+
+  if (path->len > LPM_SIZE_MAX) {
+    path->len = LPM_SIZE_MAX;
+  }
+  path->len = path->len * 8;
+
+  char* action = bpf_map_lookup_elem(&path_prefix, path);
+  path->len = len;
+  return action != NULL && *action == PATH_PREFIX_ENFORCE;
+}
+
 __always_inline static monitored_t is_monitored(const inode_key_t* inode, struct bound_path_t* path, const inode_key_t* parent) {
   const inode_value_t* volatile inode_value = inode_get(inode);
   const inode_value_t* volatile parent_value = inode_get(parent);
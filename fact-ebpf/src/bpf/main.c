@@ -19,6 +19,35 @@ char _license[] SEC("license") = "Dual MIT/GPL";
 #define FMODE_PWRITE ((fmode_t)(1 << 4))
 #define FMODE_CREATED ((fmode_t)(1 << 20))
 
+#define __O_TMPFILE 020000000
+
+#define MAY_WRITE 0x00000002
+
+#define RENAME_EXCHANGE (1 << 1)
+
+#define EPERM 1
+
+// ioctl commands changing the attribute flags of a file, with the
+// encoding of x86_64 and arm64.
+#define FS_IOC_SETFLAGS 0x40086602
+#define FS_IOC32_SETFLAGS 0x40046602
+#define FS_IOC_FSSETXATTR 0x401c5820
+#define FS_IOC_ENABLE_VERITY 0x40806685
+
+// Flags of struct fsxattr matching FACT_FS_IMMUTABLE_FL and
+// FACT_FS_APPEND_FL.
+#define FS_XFLAG_IMMUTABLE 0x00000008
+#define FS_XFLAG_APPEND 0x00000010
+
+// Flags of struct inode, set from the on-disk attributes.
+#define S_APPEND (1 << 2)
+#define S_IMMUTABLE (1 << 3)
+#define S_VERITY (1 << 16)
+
+// Writes of a task to a file closer than this to the last reported one
+// are not reported again.
+#define WRITE_DEDUP_WINDOW_NS (1000ULL * 1000 * 1000)
+
 SEC("lsm/file_open")
 int BPF_PROG(trace_file_open, struct file* file) {
   struct metrics_t* m = get_metrics();
@@ -28,9 +57,17 @@ int BPF_PROG(trace_file_open, struct file* file) {
   struct submit_event_args_t args = {.metrics = &m->file_open};
 
   args.metrics->total++;
+  if (skip_self(args.metrics)) {
+    return 0;
+  }
 
   file_activity_type_t event_type = FILE_ACTIVITY_INIT;
-  if ((file->f_mode & FMODE_CREATED) != 0) {
+  if ((file->f_flags & __O_TMPFILE) != 0) {
+    // The file has no name yet, it is only reported if its directory
+    // is monitored and tracked once linked, see path_link.
+    event_type = FILE_ACTIVITY_CREATION;
+    args.tmpfile = TMPFILE_UNNAMED;
+  } else if ((file->f_mode & FMODE_CREATED) != 0) {
     event_type = FILE_ACTIVITY_CREATION;
   } else if ((file->f_mode & (FMODE_WRITE | FMODE_PWRITE)) != 0) {
     event_type = FILE_ACTIVITY_OPEN;
@@ -73,19 +110,94 @@ int BPF_PROG(trace_file_open, struct file* file) {
     goto ignored;
   }
 
-  if (args.monitored == MONITORED_BY_PARENT && event_type == FILE_ACTIVITY_CREATION) {
+  args.blocked = path_is_enforced(path);
+  if (args.blocked) {
+    m->file_open.blocked++;
+  } else if (args.monitored == MONITORED_BY_PARENT && event_type == FILE_ACTIVITY_CREATION &&
+             args.tmpfile == NOT_TMPFILE) {
     inode_add(&args.inode);
   }
 
   submit_open_event(&args, event_type);
 
-  return 0;
+  return args.blocked ? -EPERM : 0;
 
 ignored:
   m->file_open.ignored++;
   return 0;
 }
 
+/* file_permission runs for every read and write, checks must stay
+   cheap until a write we have not reported recently is found. */
+SEC("lsm/file_permission")
+int BPF_PROG(trace_file_permission, struct file* file, int mask) {
+  if ((mask & MAY_WRITE) == 0) {
+    return 0;
+  }
+
+  struct metrics_t* m = get_metrics();
+  if (m == NULL) {
+    return 0;
+  }
+  struct submit_event_args_t args = {.metrics = &m->file_permission};
+
+  args.metrics->total++;
+  if (skip_self(args.metrics)) {
+    return 0;
+  }
+
+  // Writes to overlayfs are checked on the overlay file and then on
+  // the underlying one, we keep the first one like in file_open.
+  __u64 pid_tgid = bpf_get_current_pid_tgid();
+  if (inode_is_overlayfs(file->f_inode)) {
+    char flag = 1;
+    bpf_map_update_elem(&overlayfs_write_dedup, &pid_tgid, &flag, BPF_ANY);
+  } else {
+    char* flag = bpf_map_lookup_elem(&overlayfs_write_dedup, &pid_tgid);
+    if (flag != NULL) {
+      bpf_map_delete_elem(&overlayfs_write_dedup, &pid_tgid);
+      goto ignored;
+    }
+  }
+
+  args.inode = inode_to_key(file->f_inode);
+
+  // The timestamp is recorded before checking whether the file is
+  // monitored, so writes to other files don't read the path every time
+  // either.
+  struct write_dedup_key_t key = {.pid_tgid = pid_tgid, .inode = args.inode};
+  __u64 now = bpf_ktime_get_boot_ns();
+  __u64* last = bpf_map_lookup_elem(&write_dedup, &key);
+  if (last != NULL && now - *last < WRITE_DEDUP_WINDOW_NS) {
+    goto ignored;
+  }
+  bpf_map_update_elem(&write_dedup, &key, &now, BPF_ANY);
+
+  struct bound_path_t* path = path_read_unchecked(&file->f_path);
+  if (path == NULL) {
+    bpf_printk("Failed to read path");
+    args.metrics->error++;
+    return 0;
+  }
+  args.filename = path->path;
+
+  struct dentry* parent_dentry = BPF_CORE_READ(file, f_path.dentry, d_parent);
+  struct inode* parent_inode_ptr = parent_dentry ? BPF_CORE_READ(parent_dentry, d_inode) : NULL;
+  args.parent_inode = inode_to_key(parent_inode_ptr);
+
+  args.monitored = is_monitored(&args.inode, path, &args.parent_inode);
+  if (args.monitored == NOT_MONITORED) {
+    goto ignored;
+  }
+
+  submit_write_event(&args);
+  return 0;
+
+ignored:
+  args.metrics->ignored++;
+  return 0;
+}
+
 SEC("lsm/path_unlink")
 int BPF_PROG(trace_path_unlink, struct path* dir, struct dentry* dentry) {
   struct metrics_t* m = get_metrics();
@@ -95,6 +207,9 @@ int BPF_PROG(trace_path_unlink, struct path* dir, struct dentry* dentry) {
   struct submit_event_args_t args = {.metrics = &m->path_unlink};
 
   args.metrics->total++;
+  if (skip_self(args.metrics)) {
+    return 0;
+  }
 
   struct bound_path_t* path = path_read_append_d_entry(dir, dentry);
   if (path == NULL) {
@@ -112,11 +227,16 @@ int BPF_PROG(trace_path_unlink, struct path* dir, struct dentry* dentry) {
     return 0;
   }
 
-  // We only support files with one link for now
-  inode_remove(&args.inode);
+  args.blocked = path_is_enforced(path);
+  if (args.blocked) {
+    m->path_unlink.blocked++;
+  } else {
+    // We only support files with one link for now
+    inode_remove(&args.inode);
+  }
 
   submit_unlink_event(&args);
-  return 0;
+  return args.blocked ? -EPERM : 0;
 }
 
 SEC("lsm/path_chmod")
@@ -128,6 +248,9 @@ int BPF_PROG(trace_path_chmod, struct path* path, umode_t mode) {
   struct submit_event_args_t args = {.metrics = &m->path_chmod};
 
   args.metrics->total++;
+  if (skip_self(args.metrics)) {
+    return 0;
+  }
 
   struct bound_path_t* bound_path = path_read(path);
   if (bound_path == NULL) {
@@ -163,6 +286,9 @@ int BPF_PROG(trace_path_chown, struct path* path, unsigned long long uid, unsign
   struct submit_event_args_t args = {.metrics = &m->path_chown};
 
   args.metrics->total++;
+  if (skip_self(args.metrics)) {
+    return 0;
+  }
 
   struct bound_path_t* bound_path = path_read(path);
   if (bound_path == NULL) {
@@ -200,6 +326,9 @@ int BPF_PROG(trace_path_rename, struct path* old_dir,
   struct submit_event_args_t args = {.metrics = &m->path_rename};
 
   args.metrics->total++;
+  if (skip_self(args.metrics)) {
+    return 0;
+  }
 
   struct bound_path_t* new_path = path_read_append_d_entry(new_dir, new_dentry);
   if (new_path == NULL) {
@@ -221,6 +350,10 @@ int BPF_PROG(trace_path_rename, struct path* old_dir,
   inode_key_t old_inode = inode_to_key(old_dentry->d_inode);
   monitored_t old_monitored = is_monitored(&old_inode, old_path, NULL);
 
+  // Exchanged files are both kept, only a plain rename onto an
+  // existing file replaces it.
+  bool overwrote_existing = new_dentry->d_inode != NULL && (flags & RENAME_EXCHANGE) == 0;
+
   // From this point on we need to handle inode tracking.
   //
   // The result will be a combination of whether we are already tracking
@@ -283,7 +416,7 @@ int BPF_PROG(trace_path_rename, struct path* old_dir,
       break;
   }
 
-  submit_rename_event(&args, old_path->path, &old_inode, old_monitored);
+  submit_rename_event(&args, old_path->path, &old_inode, old_monitored, overwrote_existing);
   return 0;
 
 error:
@@ -299,6 +432,9 @@ int BPF_PROG(trace_path_mkdir, struct path* dir, struct dentry* dentry, umode_t
   }
 
   m->path_mkdir.total++;
+  if (skip_self(&m->path_mkdir)) {
+    return 0;
+  }
 
   struct bound_path_t* path = path_read_append_d_entry(dir, dentry);
   if (path == NULL) {
@@ -356,6 +492,9 @@ int BPF_PROG(trace_d_instantiate, struct dentry* dentry, struct inode* inode) {
   struct submit_event_args_t args = {.metrics = &m->d_instantiate};
 
   args.metrics->total++;
+  if (skip_self(args.metrics)) {
+    return 0;
+  }
 
   __u64 pid_tgid = bpf_get_current_pid_tgid();
 
@@ -396,6 +535,9 @@ __always_inline static int handle_xattr(struct metrics_by_hook_t* hook_metrics,
   struct submit_event_args_t args = {.metrics = hook_metrics};
 
   args.metrics->total++;
+  if (skip_self(args.metrics)) {
+    return 0;
+  }
 
   args.inode = inode_to_key(dentry->d_inode);
   args.parent_inode = inode_to_key(BPF_CORE_READ(dentry, d_parent, d_inode));
@@ -441,6 +583,9 @@ int BPF_PROG(trace_inode_set_acl, struct mnt_idmap* idmap, struct dentry* dentry
   struct submit_event_args_t args = {.metrics = &m->inode_set_acl};
 
   args.metrics->total++;
+  if (skip_self(args.metrics)) {
+    return 0;
+  }
 
   args.inode = inode_to_key(dentry->d_inode);
   args.parent_inode = inode_to_key(BPF_CORE_READ(dentry, d_parent, d_inode));
@@ -456,6 +601,89 @@ int BPF_PROG(trace_inode_set_acl, struct mnt_idmap* idmap, struct dentry* dentry
   return 0;
 }
 
+/* Attribute flags are changed through ioctls on an open file. The
+   hook runs before the filesystem checks the change, so a change
+   denied for lack of CAP_LINUX_IMMUTABLE is reported as well. */
+SEC("lsm/file_ioctl")
+int BPF_PROG(trace_file_ioctl, struct file* file, unsigned int cmd, unsigned long arg) {
+  if (cmd != FS_IOC_SETFLAGS && cmd != FS_IOC32_SETFLAGS && cmd != FS_IOC_FSSETXATTR &&
+      cmd != FS_IOC_ENABLE_VERITY) {
+    return 0;
+  }
+
+  struct metrics_t* m = get_metrics();
+  if (m == NULL) {
+    return 0;
+  }
+  struct submit_event_args_t args = {.metrics = &m->file_ioctl};
+
+  args.metrics->total++;
+  if (skip_self(args.metrics)) {
+    return 0;
+  }
+
+  struct inode* inode = BPF_CORE_READ(file, f_inode);
+  unsigned int i_flags = BPF_CORE_READ(inode, i_flags);
+  unsigned int old_flags = 0;
+  if (i_flags & S_IMMUTABLE) {
+    old_flags |= FACT_FS_IMMUTABLE_FL;
+  }
+  if (i_flags & S_APPEND) {
+    old_flags |= FACT_FS_APPEND_FL;
+  }
+  if (i_flags & S_VERITY) {
+    old_flags |= FACT_FS_VERITY_FL;
+  }
+
+  unsigned int new_flags = 0;
+  if (cmd == FS_IOC_ENABLE_VERITY) {
+    new_flags = old_flags | FACT_FS_VERITY_FL;
+  } else if (cmd == FS_IOC_FSSETXATTR) {
+    // fsx_xflags is the first field of struct fsxattr
+    unsigned int xflags = 0;
+    if (bpf_probe_read_user(&xflags, sizeof(xflags), (const void*)arg) != 0) {
+      args.metrics->error++;
+      return 0;
+    }
+    new_flags = old_flags & FACT_FS_VERITY_FL;
+    if (xflags & FS_XFLAG_IMMUTABLE) {
+      new_flags |= FACT_FS_IMMUTABLE_FL;
+    }
+    if (xflags & FS_XFLAG_APPEND) {
+      new_flags |= FACT_FS_APPEND_FL;
+    }
+  } else {
+    // Both commands take a pointer to an int, whatever their encoding
+    // says.
+    int flags = 0;
+    if (bpf_probe_read_user(&flags, sizeof(flags), (const void*)arg) != 0) {
+      args.metrics->error++;
+      return 0;
+    }
+    // fs-verity can't be disabled, FS_IOC_SETFLAGS ignores the bit
+    new_flags = (flags & (FACT_FS_IMMUTABLE_FL | FACT_FS_APPEND_FL)) | (old_flags & FACT_FS_VERITY_FL);
+  }
+
+  if (old_flags == new_flags) {
+    goto ignored;
+  }
+
+  args.inode = inode_to_key(inode);
+  args.parent_inode = inode_to_key(BPF_CORE_READ(file, f_path.dentry, d_parent, d_inode));
+
+  args.monitored = inode_is_monitored(inode_get(&args.inode), inode_get(&args.parent_inode));
+  if (args.monitored == NOT_MONITORED) {
+    goto ignored;
+  }
+
+  submit_attributes_event(&args, old_flags, new_flags);
+  return 0;
+
+ignored:
+  args.metrics->ignored++;
+  return 0;
+}
+
 SEC("lsm/path_rmdir")
 int BPF_PROG(trace_path_rmdir, struct path* dir, struct dentry* dentry) {
   struct metrics_t* m = get_metrics();
@@ -465,6 +693,9 @@ int BPF_PROG(trace_path_rmdir, struct path* dir, struct dentry* dentry) {
   struct submit_event_args_t args = {.metrics = &m->path_rmdir};
 
   args.metrics->total++;
+  if (skip_self(args.metrics)) {
+    return 0;
+  }
 
   struct bound_path_t* path = path_read_append_d_entry(dir, dentry);
   if (path == NULL) {
@@ -484,3 +715,54 @@ int BPF_PROG(trace_path_rmdir, struct path* dir, struct dentry* dentry) {
   submit_rmdir_event(&args);
   return 0;
 }
+
+/* Links are only reported for files opened with O_TMPFILE, getting
+   their first name. Other links add a name to a file that already has
+   one and are not reported. */
+SEC("lsm/path_link")
+int BPF_PROG(trace_path_link, struct dentry* old_dentry, struct path* new_dir,
+             struct dentry* new_dentry) {
+  struct metrics_t* m = get_metrics();
+  if (m == NULL) {
+    return 0;
+  }
+  struct submit_event_args_t args = {.metrics = &m->path_link};
+
+  args.metrics->total++;
+  if (skip_self(args.metrics)) {
+    return 0;
+  }
+
+  // Only files opened with O_TMPFILE can be linked without a name
+  struct inode* inode = BPF_CORE_READ(old_dentry, d_inode);
+  if (inode == NULL || BPF_CORE_READ(inode, i_nlink) != 0) {
+    goto ignored;
+  }
+
+  struct bound_path_t* path = path_read_append_d_entry(new_dir, new_dentry);
+  if (path == NULL) {
+    bpf_printk("Failed to read path");
+    args.metrics->error++;
+    return 0;
+  }
+  args.filename = path->path;
+
+  args.inode = inode_to_key(inode);
+  args.parent_inode = inode_to_key(BPF_CORE_READ(new_dir, dentry, d_inode));
+  args.monitored = is_monitored(&args.inode, path, &args.parent_inode);
+  if (args.monitored == NOT_MONITORED) {
+    goto ignored;
+  }
+
+  if (args.monitored == MONITORED_BY_PARENT) {
+    inode_add(&args.inode);
+  }
+  args.tmpfile = TMPFILE_LINKED;
+
+  submit_link_event(&args);
+  return 0;
+
+ignored:
+  args.metrics->ignored++;
+  return 0;
+}
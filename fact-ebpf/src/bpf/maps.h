@@ -31,7 +31,7 @@ struct {
   __uint(type, BPF_MAP_TYPE_LPM_TRIE);
   __type(key, struct path_prefix_t);
   __type(value, char);
-  __uint(max_entries, 256);
+  __uint(max_entries, PATH_PREFIX_MAX_ENTRIES);
   __uint(map_flags, BPF_F_NO_PREALLOC);
 } path_prefix SEC(".maps");
 
@@ -90,6 +90,16 @@ struct {
   __uint(max_entries, 16384);
 } mkdir_context SEC(".maps");
 
+// Processes whose lineage was sent to userspace, with the ID of the
+// parent it was read from. Later events of the process leave the
+// lineage out while the parent is the same, userspace keeps a copy.
+struct {
+  __uint(type, BPF_MAP_TYPE_LRU_HASH);
+  __type(key, lineage_key_t);
+  __type(value, __u32);
+  __uint(max_entries, LINEAGE_CACHE_MAX);
+} lineage_cache SEC(".maps");
+
 struct {
   __uint(type, BPF_MAP_TYPE_PERCPU_ARRAY);
   __type(key, __u32);
@@ -106,11 +116,68 @@ struct {
   __uint(max_entries, 256);
 } overlayfs_dedup SEC(".maps");
 
+/**
+ * Key for the write_dedup map, a task writing to a file.
+ *
+ * We don't need access from userspace to this type, so we don't need
+ * to define it in types.h.
+ */
+struct write_dedup_key_t {
+  __u64 pid_tgid;
+  inode_key_t inode;
+};
+
+// Last time a task was checked for writing to a file, in nanoseconds
+// since boot. Used for reporting a single event for a burst of writes.
+struct {
+  __uint(type, BPF_MAP_TYPE_LRU_HASH);
+  __type(key, struct write_dedup_key_t);
+  __type(value, __u64);
+  __uint(max_entries, 16384);
+} write_dedup SEC(".maps");
+
+// Same as overlayfs_dedup, for the write checks of file_permission.
+struct {
+  __uint(type, BPF_MAP_TYPE_LRU_HASH);
+  __type(key, __u64);
+  __type(value, char);
+  __uint(max_entries, 256);
+} overlayfs_write_dedup SEC(".maps");
+
 __always_inline static struct metrics_t* get_metrics() {
   unsigned int zero = 0;
   return bpf_map_lookup_elem(&metrics, &zero);
 }
 
+// Event emission is paused while the value in this map is not zero,
+// hooks keep running and counting the events they would have sent.
+struct {
+  __uint(type, BPF_MAP_TYPE_ARRAY);
+  __type(key, __u32);
+  __type(value, __u32);
+  __uint(max_entries, 1);
+} paused SEC(".maps");
+
+__always_inline static bool is_paused() {
+  unsigned int zero = 0;
+  __u32* paused_flag = bpf_map_lookup_elem(&paused, &zero);
+  return paused_flag != NULL && *paused_flag != 0;
+}
+
 uint64_t host_mount_ns;
 
+// Copy the arguments of processes into events, unset for privacy.
+volatile const bool collect_args = true;
+
+// Process ID of fact in the host PID namespace, exempt from enforcement
+unsigned int fact_tgid;
+
+// Report the activity of fact itself, like its scans of the monitored
+// paths, skipped unless set for debugging.
+volatile const bool report_self = false;
+
+__always_inline static bool is_fact() {
+  return (bpf_get_current_pid_tgid() >> 32) == fact_tgid;
+}
+
 // clang-format on
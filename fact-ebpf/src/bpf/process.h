@@ -77,9 +77,38 @@ __always_inline static const char* get_memory_cgroup(struct helper_t* helper) {
   return helper->buf;
 }
 
-__always_inline static void process_fill_lineage(process_t* p, struct helper_t* helper, bool use_bpf_d_path) {
+__always_inline static lineage_key_t lineage_key(struct task_struct* task) {
+  lineage_key_t key = {
+      .start_time = task->group_leader->start_boottime,
+      .pid = task->tgid,
+      .reserved = 0,
+  };
+  return key;
+}
+
+// Whether the lineage of the current process was sent with an earlier
+// event, the record of the event can then leave it out.
+__always_inline static bool lineage_is_cached() {
+  struct task_struct* task = (struct task_struct*)bpf_get_current_task_btf();
+
+  // A process reparented after its parent exits has a new lineage
+  lineage_key_t key = lineage_key(task);
+  __u32* cached = bpf_map_lookup_elem(&lineage_cache, &key);
+  return cached != NULL && *cached == task->real_parent->tgid;
+}
+
+__always_inline static void process_fill_lineage(process_t* p, bool cached, bool use_bpf_d_path) {
   struct task_struct* task = (struct task_struct*)bpf_get_current_task_btf();
   p->lineage_len = 0;
+  p->lineage_cached = cached;
+  if (cached) {
+    // The record has no room for the lineage.
+    return;
+  }
+
+  lineage_key_t key = lineage_key(task);
+  __u32 parent_tgid = task->real_parent->tgid;
+  bpf_map_update_elem(&lineage_cache, &key, &parent_tgid, BPF_ANY);
 
   for (int i = 0; i < LINEAGE_MAX; i++) {
     struct task_struct* parent = task->real_parent;
@@ -96,33 +125,62 @@ __always_inline static void process_fill_lineage(process_t* p, struct helper_t*
   }
 }
 
+__always_inline static unsigned long get_cap_effective(struct task_struct* task) {
+  const struct cred* cred = task->cred;
+  if (bpf_core_field_exists(cred->cap_effective.val)) {
+    return BPF_CORE_READ(cred, cap_effective.val);
+  }
+
+  // Before 6.3 capabilities were split in two 32 bit words.
+  struct kernel_cap_struct___pre6_3 {
+    __u32 cap[2];
+  };
+  struct cred___pre6_3 {
+    struct kernel_cap_struct___pre6_3 cap_effective;
+  };
+  struct cred___pre6_3* cred_old = (void*)cred;
+  unsigned long low = BPF_CORE_READ(cred_old, cap_effective.cap[0]);
+  unsigned long high = BPF_CORE_READ(cred_old, cap_effective.cap[1]);
+  return (high << 32) | low;
+}
+
 __always_inline static unsigned long get_mount_ns() {
   struct task_struct* task = (struct task_struct*)bpf_get_current_task_btf();
   return task->nsproxy->mnt_ns->ns.inum;
 }
 
-__always_inline static int64_t process_fill(process_t* p, bool use_bpf_d_path) {
+__always_inline static int64_t process_fill(process_t* p, bool lineage_cached, bool use_bpf_d_path) {
   struct task_struct* task = (struct task_struct*)bpf_get_current_task_btf();
   uint32_t key = 0;
   uint64_t uid_gid = bpf_get_current_uid_gid();
   p->uid = uid_gid & 0xFFFFFFFF;
   p->gid = (uid_gid >> 32) & 0xFFFFFFFF;
   p->login_uid = task->loginuid.val;
+  p->session_id = task->sessionid;
   p->pid = (bpf_get_current_pid_tgid() >> 32) & 0xFFFFFFFF;
+  p->start_time = task->group_leader->start_boottime;
+  p->cap_effective = get_cap_effective(task);
   u_int64_t err = bpf_get_current_comm(p->comm, TASK_COMM_LEN);
   if (err != 0) {
     bpf_printk("Failed to fill task comm");
     return err;
   }
 
-  unsigned long arg_start = task->mm->arg_start;
-  unsigned long arg_end = task->mm->arg_end;
-  p->args_len = (arg_end - arg_start) & 0xFFF;
-  p->args[4095] = '\0';  // Ensure string termination at end of buffer
-  err = bpf_probe_read_user(p->args, p->args_len, (const char*)arg_start);
-  if (err != 0) {
-    bpf_printk("Failed to fill task args");
-    return err;
+  p->args_len = 0;
+  p->args_truncated = 0;
+  if (collect_args) {
+    unsigned long arg_start = task->mm->arg_start;
+    unsigned long len = task->mm->arg_end - arg_start;
+    if (len > ARGS_MAX) {
+      len = ARGS_MAX;
+      p->args_truncated = 1;
+    }
+    p->args_len = len;
+    err = bpf_probe_read_user(p->args, len, (const char*)arg_start);
+    if (err != 0) {
+      bpf_printk("Failed to fill task args");
+      return err;
+    }
   }
 
   struct helper_t* helper = bpf_map_lookup_elem(&helper_map, &key);
@@ -140,7 +198,7 @@ __always_inline static int64_t process_fill(process_t* p, bool use_bpf_d_path) {
 
   p->in_root_mount_ns = get_mount_ns() == host_mount_ns;
 
-  process_fill_lineage(p, helper, use_bpf_d_path);
+  process_fill_lineage(p, lineage_cached, use_bpf_d_path);
 
   return 0;
 }
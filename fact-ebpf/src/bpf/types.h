@@ -15,35 +15,69 @@
 
 #define LINEAGE_MAX 2
 
+// Processes whose lineage was sent to userspace that are remembered,
+// see lineage_cache.
+#define LINEAGE_CACHE_MAX 8192
+
+// Most bytes of the arguments of a process that are captured.
+#define ARGS_MAX 4096
+
 // Matches Linux kernel XATTR_NAME_MAX (255) + null terminator.
 // https://github.com/torvalds/linux/blob/66affa37cfac0aec061cc4bcf4a065b0c52f7e19/include/uapi/linux/limits.h#L15
 #define XATTR_NAME_MAX_LEN 256
 
 #define LPM_SIZE_MAX 256
 
+// Number of distinct prefixes the path_prefix map can hold.
+#define PATH_PREFIX_MAX_ENTRIES 256
+
 typedef struct lineage_t {
   unsigned int uid;
   char exe_path[PATH_MAX];
 } lineage_t;
 
+// Key of the lineage_cache map. Process IDs are reused, the start time
+// tells processes with the same ID apart.
+typedef struct lineage_key_t {
+  unsigned long start_time;
+  unsigned int pid;
+  // Keeps the key free of padding, always 0.
+  unsigned int reserved;
+} lineage_key_t;
+
 typedef struct process_t {
   char comm[TASK_COMM_LEN];
-  char args[4096];
+  char args[ARGS_MAX];
   unsigned int args_len;
+  // The arguments did not fit in args and were cut short.
+  char args_truncated;
   char exe_path[PATH_MAX];
   char memory_cgroup[PATH_MAX];
   unsigned int uid;
   unsigned int gid;
   unsigned int login_uid;
+  // Audit session of the process, (unsigned int)-1 when unset.
+  unsigned int session_id;
   unsigned int pid;
-  lineage_t lineage[LINEAGE_MAX];
+  // Start of the process, in nanoseconds since boot.
+  unsigned long start_time;
+  // Effective capabilities, one bit per capability number.
+  unsigned long cap_effective;
   unsigned int lineage_len;
   char in_root_mount_ns;
+  // The lineage was sent with an earlier event of the process and is
+  // left out, lineage_len is 0.
+  char lineage_cached;
+  // Must stay last, records of events with lineage_cached set end
+  // before it.
+  lineage_t lineage[LINEAGE_MAX];
 } process_t;
 
+// Both numbers are 64 bits wide on every architecture, like st_ino and
+// st_dev in userspace, XFS and btrfs hand out inode numbers past 2^32.
 typedef struct inode_key_t {
-  unsigned long inode;
-  unsigned long dev;
+  unsigned long long inode;
+  unsigned long long dev;
 } inode_key_t;
 
 typedef enum monitored_t {
@@ -96,6 +130,14 @@ typedef enum acl_tag_t {
   FACT_ACL_TAG_OTHER = 0x20,
 } acl_tag_t;
 
+// Attribute flags reported by FILE_ACTIVITY_ATTRIBUTES events, with the
+// values of the FS_IOC_GETFLAGS/FS_IOC_SETFLAGS bits. Other bits are
+// not reported.
+// https://github.com/torvalds/linux/blob/d2c9a99135da931377240942d44f3dea104cedb8/include/uapi/linux/fs.h#L246-L282
+#define FACT_FS_IMMUTABLE_FL 0x00000010
+#define FACT_FS_APPEND_FL 0x00000020
+#define FACT_FS_VERITY_FL 0x00100000
+
 struct acl_entry_t {
   acl_tag_t e_tag;
   unsigned short e_perm;
@@ -115,16 +157,29 @@ typedef enum file_activity_type_t {
   FILE_ACTIVITY_SETXATTR,
   FILE_ACTIVITY_REMOVEXATTR,
   FILE_ACTIVITY_ACL_SET,
+  FILE_ACTIVITY_WRITE,
+  FILE_ACTIVITY_ATTRIBUTES,
 } file_activity_type_t;
 
+// Files opened with O_TMPFILE have no name until they are linked.
+typedef enum tmpfile_t {
+  NOT_TMPFILE = 0,
+  // Created without a name in the directory of the event
+  TMPFILE_UNNAMED,
+  // Linked under the name of the event
+  TMPFILE_LINKED,
+} tmpfile_t;
+
 struct event_t {
   unsigned long timestamp;
-  process_t process;
   char filename[PATH_MAX];
   inode_key_t inode;
   inode_key_t parent_inode;
   monitored_t monitored;
   file_activity_type_t type;
+  // The operation was denied because of an enforced protected path
+  char blocked;
+  tmpfile_t tmpfile;
   union {
     struct {
       short unsigned int new;
@@ -140,6 +195,9 @@ struct event_t {
       char filename[PATH_MAX];
       inode_key_t inode;
       monitored_t monitored;
+      // The rename replaced an existing file, whose inode is the one
+      // of the event
+      char overwrote_existing;
     } rename;
     struct {
       char name[XATTR_NAME_MAX_LEN];
@@ -149,7 +207,13 @@ struct event_t {
       acl_type_t acl_type;
       struct acl_entry_t entries[FACT_MAX_ACL_ENTRIES];
     } acl;
+    struct {
+      unsigned int old;
+      unsigned int new;
+    } attributes;
   };
+  // Must stay last, see process_t.lineage.
+  process_t process;
 };
 
 /**
@@ -166,6 +230,18 @@ struct path_prefix_t {
   const char path[LPM_SIZE_MAX];
 };
 
+/**
+ * Values stored in the path_prefix map.
+ *
+ * Write opens and unlinks on prefixes with PATH_PREFIX_ENFORCE are
+ * denied. Userspace only sets this value when enforcement is globally
+ * enabled, so it can be removed by updating the map values.
+ */
+typedef enum path_prefix_action_t {
+  PATH_PREFIX_MONITOR = 0,
+  PATH_PREFIX_ENFORCE = 1,
+} path_prefix_action_t;
+
 // Context for correlating mkdir operations
 struct mkdir_context_t {
   char path[PATH_MAX];
@@ -180,6 +256,9 @@ struct metrics_by_hook_t {
   unsigned long long error;
   unsigned long long ignored;
   unsigned long long ringbuffer_full;
+  unsigned long long blocked;
+  unsigned long long paused;
+  unsigned long long self_activity;
 };
 
 struct metrics_t {
@@ -194,4 +273,7 @@ struct metrics_t {
   struct metrics_by_hook_t inode_setxattr;
   struct metrics_by_hook_t inode_removexattr;
   struct metrics_by_hook_t inode_set_acl;
+  struct metrics_by_hook_t file_permission;
+  struct metrics_by_hook_t path_link;
+  struct metrics_by_hook_t file_ioctl;
 };
@@ -12,6 +12,8 @@ use serde::{
 
 include!(concat!(env!("OUT_DIR"), "/bindings.rs"));
 
+pub mod names;
+
 #[derive(Debug)]
 pub struct PathPrefixError {
     prefix: String,
@@ -25,6 +27,20 @@ impl Display for PathPrefixError {
     }
 }
 
+/// Get the prefix of a monitored path that is matched in the kernel.
+///
+/// Take the start of the path until the first occurence of a wildcard
+/// character, limited to the size of the keys in the path prefix map.
+/// This is used as a filter in the kernel in cases where the inode has
+/// failed to match. The full wildcard string is used for further
+/// processing in userspace.
+pub fn glob_prefix(path: &str) -> &[u8] {
+    // unwrap is safe here - if there are no matches, the full string is the
+    // only item in the iterator
+    let prefix = path.split(['*', '?', '[', '{']).next().unwrap().as_bytes();
+    &prefix[..prefix.len().min(LPM_SIZE_MAX as usize)]
+}
+
 impl TryFrom<&PathBuf> for path_prefix_t {
     type Error = PathPrefixError;
 
@@ -35,15 +51,8 @@ impl TryFrom<&PathBuf> for path_prefix_t {
             });
         };
 
-        // Take the start of the path until the first occurence of a wildcard
-        // character. This is used as a filter in the kernel in cases where
-        // the inode has failed to match. The full wildcard string is used
-        // for further processing in userspace.
-        //
-        // unwrap is safe here - if there are no matches, the full string is the
-        // only item in the iterator
-        let filename_prefix = filename.split(['*', '?', '[', '{']).next().unwrap();
-        let len = filename_prefix.len().min(LPM_SIZE_MAX as usize);
+        let filename_prefix = glob_prefix(filename);
+        let len = filename_prefix.len();
 
         unsafe {
             let mut cfg: path_prefix_t = std::mem::zeroed();
@@ -72,6 +81,10 @@ impl PartialEq for path_prefix_t {
 
 unsafe impl Pod for path_prefix_t {}
 
+// Keys are shared with the kernel through the BPF maps, the layout must
+// not depend on the width of the host's long.
+const _: () = assert!(std::mem::size_of::<inode_key_t>() == 16);
+
 impl inode_key_t {
     pub fn empty(&self) -> bool {
         self.inode == 0 && self.dev == 0
@@ -152,6 +165,8 @@ impl<'de> Deserialize<'de> for inode_key_t {
 
 unsafe impl Pod for inode_key_t {}
 
+unsafe impl Pod for lineage_key_t {}
+
 impl Default for monitored_t {
     fn default() -> Self {
         monitored_t::NOT_MONITORED
@@ -199,6 +214,9 @@ impl metrics_by_hook_t {
         self.error += other.error;
         self.ignored += other.ignored;
         self.ringbuffer_full += other.ringbuffer_full;
+        self.blocked += other.blocked;
+        self.paused += other.paused;
+        self.self_activity += other.self_activity;
         self
     }
 }
@@ -226,6 +244,9 @@ impl_metrics_t!(
     inode_setxattr,
     inode_removexattr,
     inode_set_acl,
+    file_permission,
+    path_link,
+    file_ioctl,
 );
 
 unsafe impl Pod for metrics_t {}
@@ -0,0 +1,71 @@
+//! Names of the programs, maps and globals in the BPF objects.
+//!
+//! These must match the C sources in `src/bpf`, userspace looks them up
+//! by name once the objects are loaded.
+
+/// Programs in `main.o` are named `trace_<hook>`, after the LSM hook
+/// they attach to.
+pub const PROGRAM_PREFIX: &str = "trace_";
+
+/// Hooks with a program in `main.o`.
+pub const HOOKS: &[&str] = &[
+    "file_open",
+    "file_permission",
+    "path_unlink",
+    "path_chmod",
+    "path_chown",
+    "path_rename",
+    "path_mkdir",
+    "d_instantiate",
+    "inode_setxattr",
+    "inode_removexattr",
+    "inode_set_acl",
+    "path_rmdir",
+    "path_link",
+    "file_ioctl",
+];
+
+/// Maps in `main.o`.
+pub mod maps {
+    pub const RINGBUFFER: &str = "rb";
+    pub const INODE_MAP: &str = "inode_map";
+    pub const PATH_PREFIX: &str = "path_prefix";
+    pub const METRICS: &str = "metrics";
+    pub const PAUSED: &str = "paused";
+    pub const LINEAGE_CACHE: &str = "lineage_cache";
+
+    /// Every map in the object, including the ones only used by the
+    /// BPF programs. Global data sections, like `.rodata`, are not
+    /// included.
+    pub const ALL: &[&str] = &[
+        "helper_map",
+        PATH_PREFIX,
+        "bound_path_heap",
+        RINGBUFFER,
+        INODE_MAP,
+        "mkdir_context",
+        LINEAGE_CACHE,
+        METRICS,
+        "overlayfs_dedup",
+        "write_dedup",
+        "overlayfs_write_dedup",
+        PAUSED,
+    ];
+}
+
+/// Globals in `main.o` set when the object is loaded.
+pub mod globals {
+    pub const HOST_MOUNT_NS: &str = "host_mount_ns";
+    pub const FACT_TGID: &str = "fact_tgid";
+    pub const COLLECT_ARGS: &str = "collect_args";
+    pub const REPORT_SELF: &str = "report_self";
+    pub const PATH_HOOKS_SUPPORT_BPF_D_PATH: &str = "path_hooks_support_bpf_d_path";
+}
+
+/// Programs in `checks.o`, probing the features of the running kernel.
+pub mod checks {
+    pub const PATH_UNLINK_SUPPORTS_BPF_D_PATH: &str = "check_path_unlink_supports_bpf_d_path";
+    pub const INODE_SET_ACL: &str = "check_inode_set_acl";
+    pub const FILE_IOCTL: &str = "check_file_ioctl";
+    pub const LSM_ATTACH: &str = "check_lsm_attach";
+}
@@ -0,0 +1,84 @@
+#!/usr/bin/env python3
+"""Compare benchmark results against the recorded baseline.
+
+Reads the estimates criterion leaves under target/criterion and reports
+benchmarks slower than the baseline by more than the threshold, exiting
+with an error if any is found. With --update the baseline is replaced
+by the current results instead.
+"""
+
+import argparse
+import json
+import sys
+from pathlib import Path
+
+HERE = Path(__file__).parent
+
+
+def results(criterion_dir):
+    """Mean time in nanoseconds of the last run of every benchmark."""
+    out = {}
+    for bench in criterion_dir.glob('**/new/benchmark.json'):
+        full_id = json.loads(bench.read_text())['full_id']
+        estimates = json.loads((bench.parent / 'estimates.json').read_text())
+        out[full_id] = round(estimates['mean']['point_estimate'], 1)
+    return dict(sorted(out.items()))
+
+
+def main():
+    parser = argparse.ArgumentParser(description=__doc__)
+    parser.add_argument(
+        '--criterion',
+        type=Path,
+        default=HERE.parent.parent / 'target' / 'criterion',
+        help='Directory criterion writes its results to',
+    )
+    parser.add_argument(
+        '--baseline',
+        type=Path,
+        default=HERE / 'baseline.json',
+    )
+    parser.add_argument(
+        '--threshold',
+        type=float,
+        default=0.15,
+        help='Slowdown reported as a regression, 0.15 is 15%%',
+    )
+    parser.add_argument(
+        '--update',
+        action='store_true',
+        help='Record the current results as the baseline',
+    )
+    args = parser.parse_args()
+
+    current = results(args.criterion)
+    if not current:
+        sys.exit(f'No results found in {args.criterion}, run make bench')
+
+    if args.update:
+        args.baseline.write_text(json.dumps(current, indent=2) + '\n')
+        print(f'Recorded {len(current)} benchmarks in {args.baseline}')
+        return
+
+    baseline = json.loads(args.baseline.read_text())
+    regressions = []
+    for name, base in baseline.items():
+        if name not in current:
+            print(f'{name}: missing from the results')
+            continue
+        change = current[name] / base - 1
+        print(f'{name}: {base:.1f}ns -> {current[name]:.1f}ns ({change:+.1%})')
+        if change > args.threshold:
+            regressions.append(name)
+    for name in current.keys() - baseline.keys():
+        print(f'{name}: not in the baseline')
+
+    if regressions:
+        sys.exit(
+            f'{len(regressions)} benchmarks regressed by more than '
+            f'{args.threshold:.0%}: {", ".join(regressions)}'
+        )
+
+
+if __name__ == '__main__':
+    main()
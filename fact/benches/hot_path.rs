@@ -0,0 +1,105 @@
+//! Per-event costs of the userspace pipeline.
+//!
+//! Run with `make bench`, results are compared against `baseline.json`
+//! by `compare.py`.
+
+use std::{hint::black_box, path::Path};
+
+use criterion::{BatchSize, Criterion, criterion_group, criterion_main};
+use fact::bench::{
+    CompactPaths, Filters, FullPaths, Prefixes, RawLineage, Size, image_store, parse, raw_event,
+};
+
+fn parsing(c: &mut Criterion) {
+    let mut group = c.benchmark_group("parse");
+    for (name, size) in [("small", Size::Small), ("large", Size::Large)] {
+        let data = raw_event(size);
+        group.bench_function(name, |b| b.iter(|| parse(black_box(&data)).unwrap()));
+    }
+    group.finish();
+}
+
+/// Lineages repeated across events are parsed and allocated once, the
+/// difference between both is what a cache hit saves.
+fn lineage(c: &mut Criterion) {
+    let mut group = c.benchmark_group("lineage");
+    for (name, size) in [("small", Size::Small), ("large", Size::Large)] {
+        let lineage = RawLineage::new(size);
+        group.bench_function(format!("{name}/cached"), |b| {
+            b.iter(|| black_box(&lineage).parse())
+        });
+        group.bench_function(format!("{name}/uncached"), |b| {
+            b.iter(|| black_box(&lineage).parse_uncached())
+        });
+    }
+    group.finish();
+}
+
+fn prefixes(c: &mut Criterion) {
+    // No prefix matches, every one of them is checked
+    let path = Path::new("/etc/ssh/sshd_config");
+    let mut group = c.benchmark_group("prefix_match");
+    for count in [1, 8, 64] {
+        let prefixes = Prefixes::new(count);
+        group.bench_function(count.to_string(), |b| {
+            b.iter(|| prefixes.matches(black_box(path)))
+        });
+    }
+    group.finish();
+}
+
+fn filters(c: &mut Criterion) {
+    let filters = Filters::new();
+    let event = parse(&raw_event(Size::Small)).unwrap();
+    c.bench_function("filter", |b| b.iter(|| filters.keep(black_box(&event))));
+}
+
+fn serialization(c: &mut Criterion) {
+    let mut group = c.benchmark_group("serialize");
+    for (name, size) in [("small", Size::Small), ("large", Size::Large)] {
+        let event = parse(&raw_event(size)).unwrap();
+        group.bench_function(format!("json/{name}"), |b| {
+            b.iter(|| serde_json::to_vec(black_box(&event)).unwrap())
+        });
+        group.bench_function(format!("proto/{name}"), |b| {
+            b.iter_batched(
+                || event.clone(),
+                fact_api::FileActivity::try_from,
+                BatchSize::SmallInput,
+            )
+        });
+    }
+    group.finish();
+}
+
+/// Host path lookups on a tree of a million files. Memory is what
+/// matters here, the estimates are printed before the lookups run.
+fn inode_map(c: &mut Criterion) {
+    let tree = image_store(1_000_000);
+    let compact = CompactPaths::new(&tree);
+    let full = FullPaths::new(&tree);
+    let mib = |bytes: usize| bytes as f64 / (1 << 20) as f64;
+    eprintln!(
+        "inode_map memory for {} files: compact {:.1} MiB, full {:.1} MiB",
+        tree.len(),
+        mib(compact.memory_usage()),
+        mib(full.memory_usage()),
+    );
+
+    let (inode, _) = &tree[tree.len() / 2];
+    let mut group = c.benchmark_group("inode_map");
+    group.bench_function("compact", |b| b.iter(|| compact.get(black_box(inode))));
+    group.bench_function("full", |b| b.iter(|| full.get(black_box(inode))));
+    group.finish();
+}
+
+criterion_group!(
+    benches,
+    parsing,
+    lineage,
+    prefixes,
+    filters,
+    serialization,
+    inode_map
+);
+criterion_main!(benches);
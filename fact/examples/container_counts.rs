@@ -0,0 +1,75 @@
+//! Run the fact pipeline in-process and print how many events were
+//! seen for each container, events from processes on the host are
+//! counted under `host`.
+//!
+//! The configuration is read the same way the fact binary does, from
+//! the configuration files, environment and arguments. Loading the BPF
+//! programs requires root privileges:
+//!
+//! ```sh
+//! cargo build --example container_counts
+//! sudo target/debug/examples/container_counts --paths /etc
+//! ```
+
+use std::{
+    collections::BTreeMap,
+    time::{Duration, Instant},
+};
+
+use fact::{
+    config::FactConfig,
+    events::Event,
+    pipeline::{EventSink, Pipeline},
+};
+
+/// How often counts are printed while events come in.
+const REPORT_INTERVAL: Duration = Duration::from_secs(5);
+
+struct ContainerCounts {
+    counts: BTreeMap<String, u64>,
+    last_report: Instant,
+}
+
+impl ContainerCounts {
+    fn report(&mut self) {
+        let counts = self
+            .counts
+            .iter()
+            .map(|(container, n)| format!("{container}={n}"))
+            .collect::<Vec<_>>();
+        println!("{}", counts.join(" "));
+        self.last_report = Instant::now();
+    }
+}
+
+impl EventSink for ContainerCounts {
+    fn name(&self) -> &str {
+        "container counts"
+    }
+
+    fn handle(&mut self, event: &Event) -> anyhow::Result<()> {
+        let container = event.get_process().container_id().unwrap_or("host");
+        *self.counts.entry(container.to_owned()).or_default() += 1;
+        if self.last_report.elapsed() >= REPORT_INTERVAL {
+            self.report();
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> anyhow::Result<()> {
+        self.report();
+        Ok(())
+    }
+}
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    fact::init_log()?;
+    let config = FactConfig::new()?;
+    let counts = ContainerCounts {
+        counts: BTreeMap::new(),
+        last_report: Instant::now(),
+    };
+
+    Pipeline::new(config).sink(counts).run().await
+}
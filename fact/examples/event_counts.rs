@@ -0,0 +1,54 @@
+//! Follow a file with the JSON output of fact and print how many
+//! events of each type were seen so far.
+//!
+//! ```sh
+//! fact --json > events.jsonl &
+//! cargo run --example event_counts -- events.jsonl
+//! ```
+
+use std::{
+    collections::BTreeMap, env, fs::File, io::BufReader, process::ExitCode, thread, time::Duration,
+};
+
+use fact::events::{FileData, JsonLines};
+
+fn main() -> ExitCode {
+    let Some(path) = env::args().nth(1) else {
+        eprintln!("usage: event_counts <file>");
+        return ExitCode::FAILURE;
+    };
+    let file = match File::open(&path) {
+        Ok(file) => file,
+        Err(e) => {
+            eprintln!("Failed to open {path}: {e}");
+            return ExitCode::FAILURE;
+        }
+    };
+
+    let mut counts = FileData::EVENT_TYPES
+        .iter()
+        .map(|t| (*t, 0u64))
+        .collect::<BTreeMap<_, _>>();
+    let mut events = JsonLines::new(BufReader::new(file));
+    loop {
+        let mut changed = false;
+        for event in events.by_ref() {
+            match event {
+                Ok(event) => {
+                    *counts.entry(event.event_type()).or_default() += 1;
+                    changed = true;
+                }
+                Err(e) => eprintln!("{e}"),
+            }
+        }
+
+        if changed {
+            let counts = counts
+                .iter()
+                .map(|(t, n)| format!("{t}={n}"))
+                .collect::<Vec<_>>();
+            println!("{}", counts.join(" "));
+        }
+        thread::sleep(Duration::from_secs(1));
+    }
+}
@@ -0,0 +1,250 @@
+//! Fixtures and entry points for the benchmarks under `fact/benches`.
+//!
+//! Only built with the `bench` feature, the pipeline internals exposed
+//! here are not part of the public API.
+
+use std::{
+    collections::HashMap,
+    mem::size_of,
+    path::{Path, PathBuf},
+};
+
+use fact_ebpf::{
+    ARGS_MAX, LINEAGE_MAX, PATH_MAX, event_t, file_activity_type_t, inode_key_t, lineage_t,
+    monitored_t,
+};
+
+use crate::{
+    config::PathLabels,
+    event::{
+        self, Event,
+        lineage::{self, Lineages},
+    },
+    filter::{self, Filter, FilterAction},
+    generate::{as_bytes, copy_str},
+    inode_map::{InodeMap, Source},
+    labels::PathLabeler,
+};
+
+/// Shape of the process in a fixture event.
+#[derive(Debug, Clone, Copy)]
+pub enum Size {
+    /// A short command line without lineage.
+    Small,
+    /// Arguments and lineage filled up to the limits of the kernel.
+    Large,
+}
+
+/// Bytes of an event as read from the ringbuffer.
+pub fn raw_event(size: Size) -> Vec<u8> {
+    as_bytes(&fixture(size)).to_vec()
+}
+
+fn fixture(size: Size) -> event_t {
+    let mut event = event_t {
+        type_: file_activity_type_t::FILE_ACTIVITY_OPEN,
+        timestamp: 1_000_000,
+        inode: inode_key_t { inode: 42, dev: 1 },
+        parent_inode: inode_key_t { inode: 41, dev: 1 },
+        monitored: monitored_t::MONITORED_BY_PATH,
+        ..Default::default()
+    };
+    let process = &mut event.process;
+    process.uid = 1000;
+    process.gid = 1000;
+    process.login_uid = u32::MAX;
+    process.session_id = u32::MAX;
+    process.pid = 4242;
+    copy_str(
+        &mut process.memory_cgroup,
+        "/kubepods/besteffort/pod1/0123456789abcdef0123456789abcdef0123456789abcdef0123456789abcdef",
+    );
+
+    let args = match size {
+        Size::Small => {
+            copy_str(&mut event.filename, "/etc/passwd");
+            copy_str(&mut process.comm, "cat");
+            copy_str(&mut process.exe_path, "/usr/bin/cat");
+            "cat\0/etc/passwd\0".to_string()
+        }
+        Size::Large => {
+            let dir = "/var/lib/application/data/".repeat(PATH_MAX as usize / 64);
+            copy_str(&mut event.filename, &format!("{dir}file.db"));
+            copy_str(&mut process.comm, "application-srv");
+            copy_str(&mut process.exe_path, &format!("{dir}bin/application"));
+            for (i, lineage) in process.lineage.iter_mut().enumerate() {
+                lineage.uid = i as u32;
+                copy_str(&mut lineage.exe_path, &format!("{dir}bin/parent-{i}"));
+            }
+            process.lineage_len = LINEAGE_MAX;
+
+            let mut args = String::new();
+            let mut i = 0;
+            while args.len() < ARGS_MAX as usize - 16 {
+                args.push_str(&format!("--option-{i}=value\0"));
+                i += 1;
+            }
+            args
+        }
+    };
+    let args = &args[..args.len().min(ARGS_MAX as usize - 1)];
+    copy_str(&mut process.args, args);
+    process.args_len = args.len() as u32;
+
+    event
+}
+
+/// Ancestors of the process of a fixture event, as read from the
+/// kernel, along with the lineages cached by a BPF worker.
+pub struct RawLineage(Vec<lineage_t>, Lineages);
+
+impl RawLineage {
+    /// With [`Size::Small`], a shell run from a terminal multiplexer,
+    /// the common case for a cache hit.
+    pub fn new(size: Size) -> Self {
+        let lineage = match size {
+            Size::Small => [(1000, "/usr/bin/bash"), (1000, "/usr/bin/tmux")]
+                .into_iter()
+                .map(|(uid, path)| {
+                    let mut lineage = lineage_t {
+                        uid,
+                        exe_path: [0; PATH_MAX as usize],
+                    };
+                    copy_str(&mut lineage.exe_path, path);
+                    lineage
+                })
+                .collect(),
+            Size::Large => {
+                let process = fixture(size).process;
+                process.lineage[..process.lineage_len as usize].to_vec()
+            }
+        };
+        RawLineage(lineage, Lineages::default())
+    }
+
+    /// Parse the lineage like the BPF worker does, sharing it with the
+    /// earlier events with the same ancestors.
+    pub fn parse(&self) -> usize {
+        self.1.parse(&self.0).unwrap().len()
+    }
+
+    /// Parse the lineage into new allocations, like for an event with
+    /// ancestors not seen before.
+    pub fn parse_uncached(&self) -> usize {
+        lineage::parse_uncached(&self.0).unwrap().len()
+    }
+}
+
+/// Parse an event the same way the BPF worker does.
+pub fn parse(data: &[u8]) -> anyhow::Result<Event> {
+    event::parse_raw(data)
+}
+
+/// Labeled path prefixes events are matched against.
+pub struct Prefixes(PathLabeler);
+
+impl Prefixes {
+    pub fn new(count: usize) -> Self {
+        let paths = (0..count)
+            .map(|i| PathLabels {
+                path: PathBuf::from(format!("/var/lib/app-{i}/data/**")),
+                labels: [("app".to_string(), i.to_string())].into(),
+            })
+            .collect::<Vec<_>>();
+        Prefixes(PathLabeler::new(&paths))
+    }
+
+    pub fn matches(&self, path: &Path) -> bool {
+        self.0.resolve(path).is_some()
+    }
+}
+
+/// A handful of filters like the ones used to drop package manager
+/// noise.
+pub struct Filters(Vec<Filter>);
+
+impl Filters {
+    pub fn new() -> Self {
+        let filters = [
+            r#"event == "unlink" and exe_path == "/usr/bin/dnf" and path starts_with "/var/cache""#,
+            r#"has container_id and uid >= 1000 and path starts_with "/tmp/""#,
+            r#"not (event == "open" or event == "creation") and exe_path starts_with "/usr/sbin""#,
+        ]
+        .into_iter()
+        .map(|f| Filter::new(FilterAction::Drop, f).expect("Invalid filter"))
+        .collect();
+        Filters(filters)
+    }
+
+    pub fn keep(&self, event: &Event) -> bool {
+        filter::keep(&self.0, event)
+    }
+}
+
+impl Default for Filters {
+    fn default() -> Self {
+        Filters::new()
+    }
+}
+
+/// Host paths of a synthetic tree of `files` files, laid out like the
+/// layers of a container image store: 100 files per directory, 100
+/// directories per layer.
+pub fn image_store(files: usize) -> Vec<(inode_key_t, PathBuf)> {
+    (0..files)
+        .map(|i| {
+            let inode = inode_key_t {
+                inode: i as u64,
+                dev: 1,
+            };
+            let path = format!(
+                "/var/lib/containers/storage/overlay/{:064x}/diff/usr/lib/python3/site-packages/pkg-{}/module-{}.py",
+                i / 10_000,
+                i / 100 % 100,
+                i % 100,
+            );
+            (inode, PathBuf::from(path))
+        })
+        .collect()
+}
+
+/// The host paths of inodes, as kept by the host scanner.
+pub struct CompactPaths(InodeMap);
+
+impl CompactPaths {
+    pub fn new(tree: &[(inode_key_t, PathBuf)]) -> Self {
+        let mut map = InodeMap::new();
+        for (inode, path) in tree {
+            map.insert(*inode, path, Source::StartupScan);
+        }
+        CompactPaths(map)
+    }
+
+    pub fn get(&self, inode: &inode_key_t) -> Option<PathBuf> {
+        self.0.get(inode)
+    }
+
+    pub fn memory_usage(&self) -> usize {
+        self.0.memory_usage()
+    }
+}
+
+/// The host paths of inodes stored in full, for comparison.
+pub struct FullPaths(HashMap<inode_key_t, PathBuf>);
+
+impl FullPaths {
+    pub fn new(tree: &[(inode_key_t, PathBuf)]) -> Self {
+        FullPaths(tree.iter().cloned().collect())
+    }
+
+    pub fn get(&self, inode: &inode_key_t) -> Option<PathBuf> {
+        self.0.get(inode).cloned()
+    }
+
+    /// Estimate of the heap bytes used, counted like
+    /// [`CompactPaths::memory_usage`].
+    pub fn memory_usage(&self) -> usize {
+        self.0.capacity() * (size_of::<(inode_key_t, PathBuf)>() + 1)
+            + self.0.values().map(|path| path.capacity()).sum::<usize>()
+    }
+}
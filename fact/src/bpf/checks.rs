@@ -1,10 +1,12 @@
-use anyhow::Context;
+use anyhow::{Context, bail};
 use aya::{Btf, programs::Lsm};
+use fact_ebpf::names::checks as names;
 use log::debug;
 
 pub(super) struct Checks {
     pub(super) path_hooks_support_bpf_d_path: bool,
     supports_inode_set_acl: bool,
+    supports_file_ioctl: bool,
 }
 
 impl Checks {
@@ -15,22 +17,52 @@ impl Checks {
 
         let path_hooks_support_bpf_d_path = Self::probe_hook(
             &mut obj,
-            "check_path_unlink_supports_bpf_d_path",
+            names::PATH_UNLINK_SUPPORTS_BPF_D_PATH,
             "path_unlink",
             btf,
         );
         debug!("path_hooks_support_bpf_d_path: {path_hooks_support_bpf_d_path}");
 
         let supports_inode_set_acl =
-            Self::probe_hook(&mut obj, "check_inode_set_acl", "inode_set_acl", btf);
+            Self::probe_hook(&mut obj, names::INODE_SET_ACL, "inode_set_acl", btf);
         debug!("supports_inode_set_acl: {supports_inode_set_acl}");
 
+        let supports_file_ioctl = Self::probe_hook(&mut obj, names::FILE_IOCTL, "file_ioctl", btf);
+        debug!("supports_file_ioctl: {supports_file_ioctl}");
+
         Ok(Checks {
             path_hooks_support_bpf_d_path,
             supports_inode_set_acl,
+            supports_file_ioctl,
         })
     }
 
+    /// Check if BPF LSM programs can be attached on the running
+    /// kernel.
+    ///
+    /// The program is detached once the object is dropped at the end
+    /// of this function.
+    pub(super) fn probe_attach(btf: &Btf) -> anyhow::Result<()> {
+        let mut obj = aya::EbpfLoader::new()
+            .load(fact_ebpf::CHECKS_OBJ)
+            .context("Failed to load checks.o")?;
+        if obj.program(names::LSM_ATTACH).is_none() {
+            let present = obj.programs().map(|(name, _)| name).collect::<Vec<_>>();
+            bail!(
+                "{} program not found, checks.o has: {}",
+                names::LSM_ATTACH,
+                present.join(", ")
+            );
+        }
+        let prog: &mut Lsm = obj
+            .program_mut(names::LSM_ATTACH)
+            .expect("program is present")
+            .try_into()?;
+        prog.load("file_open", btf)?;
+        prog.attach()?;
+        Ok(())
+    }
+
     fn probe_hook(obj: &mut aya::Ebpf, prog_name: &str, hook: &str, btf: &Btf) -> bool {
         let Some(prog) = obj.program_mut(prog_name) else {
             return false;
@@ -42,6 +74,10 @@ impl Checks {
     }
 
     pub(super) fn is_unsupported_hook(&self, hook: &str) -> bool {
-        hook == "inode_set_acl" && !self.supports_inode_set_acl
+        match hook {
+            "inode_set_acl" => !self.supports_inode_set_acl,
+            "file_ioctl" => !self.supports_file_ioctl,
+            _ => false,
+        }
     }
 }
@@ -0,0 +1,221 @@
+//! State of the BPF programs and maps, served at `/debug/bpf`.
+//!
+//! Maps are handed out to several components once fact starts, the IDs
+//! of programs and maps are kept instead, so they can be reopened from
+//! the kernel when a snapshot is taken.
+
+use std::{
+    collections::HashMap as StdHashMap,
+    fs,
+    sync::{
+        Arc,
+        atomic::{AtomicBool, Ordering},
+    },
+};
+
+use aya::{
+    Ebpf,
+    maps::{HashMap, LpmTrie, Map, MapData},
+    programs::{Program, ProgramInfo, loaded_programs},
+};
+use fact_ebpf::{
+    LPM_SIZE_MAX, inode_key_t, inode_value_t,
+    names::{self, maps},
+};
+use libc::c_char;
+use log::warn;
+use serde::Serialize;
+
+use crate::event::lineage::Lineages;
+
+#[derive(Debug, PartialEq, Serialize)]
+pub struct ProgramSnapshot {
+    pub name: String,
+    pub loaded: bool,
+    pub attached: bool,
+    /// Only reported while `kernel.bpf_stats_enabled` is set.
+    pub run_count: Option<u64>,
+    pub run_time_ns: Option<u64>,
+}
+
+#[derive(Debug, PartialEq, Serialize)]
+pub struct MapSnapshot {
+    pub name: String,
+    pub max_entries: Option<u32>,
+    /// Only counted for maps whose size matters for monitoring.
+    pub entries: Option<usize>,
+}
+
+#[derive(Debug, PartialEq, Serialize)]
+pub struct Snapshot {
+    pub programs: Vec<ProgramSnapshot>,
+    pub maps: Vec<MapSnapshot>,
+    /// Size of the ringbuffer in bytes, smaller than the configured
+    /// one if the kernel could not allocate it.
+    pub ringbuf_size: u64,
+    pub ringbuf_requested_size: u64,
+    pub stats_enabled: bool,
+}
+
+#[derive(Debug)]
+struct Inner {
+    /// Hooks with the ID of their program, if it was loaded.
+    programs: Vec<(String, Option<u32>)>,
+    maps: Vec<(String, u32)>,
+    ringbuf_size: u64,
+    ringbuf_requested_size: u64,
+    attached: Arc<AtomicBool>,
+    lineages: Lineages,
+}
+
+/// Handle on the loaded BPF object for taking snapshots of it, shared
+/// with the endpoints server.
+#[derive(Debug, Clone)]
+pub struct Diagnostics(Arc<Inner>);
+
+impl Diagnostics {
+    /// Keep the IDs of the programs and maps in `obj`, must be called
+    /// once programs are loaded and before any map is taken.
+    pub(super) fn new(
+        obj: &Ebpf,
+        ringbuf_size: u64,
+        ringbuf_requested_size: u64,
+        attached: Arc<AtomicBool>,
+        lineages: Lineages,
+    ) -> Self {
+        let mut programs = obj
+            .programs()
+            .filter_map(|(name, prog)| {
+                let hook = name.strip_prefix(names::PROGRAM_PREFIX)?;
+                let id = match prog {
+                    Program::Lsm(prog) if prog.fd().is_ok() => {
+                        prog.info().ok().map(|info| info.id())
+                    }
+                    _ => None,
+                };
+                Some((hook.to_owned(), id))
+            })
+            .collect::<Vec<_>>();
+        programs.sort();
+
+        // Sections like .rodata are maps too, they are not interesting
+        let mut maps = obj
+            .maps()
+            .filter(|(name, _)| !name.starts_with('.'))
+            .filter_map(|(name, map)| {
+                let id = map_data(map)?.info().ok()?.id();
+                Some((name.to_owned(), id))
+            })
+            .collect::<Vec<_>>();
+        maps.sort();
+
+        Diagnostics(Arc::new(Inner {
+            programs,
+            maps,
+            ringbuf_size,
+            ringbuf_requested_size,
+            attached,
+            lineages,
+        }))
+    }
+
+    /// The lineages cached for the events read from the ringbuffer.
+    pub fn lineages(&self) -> &Lineages {
+        &self.0.lineages
+    }
+
+    pub fn snapshot(&self) -> Snapshot {
+        let stats_enabled = stats_enabled();
+        let attached = self.0.attached.load(Ordering::Relaxed);
+        let infos = if stats_enabled {
+            loaded_programs()
+                .filter_map(Result::ok)
+                .map(|info| (info.id(), info))
+                .collect()
+        } else {
+            StdHashMap::new()
+        };
+
+        let programs = self
+            .0
+            .programs
+            .iter()
+            .map(|(name, id)| {
+                let info = id.and_then(|id| infos.get(&id));
+                ProgramSnapshot {
+                    name: name.clone(),
+                    loaded: id.is_some(),
+                    attached: attached && id.is_some(),
+                    run_count: info.map(ProgramInfo::run_count),
+                    run_time_ns: info.map(|info| info.run_time().as_nanos() as u64),
+                }
+            })
+            .collect();
+
+        let maps = self
+            .0
+            .maps
+            .iter()
+            .map(|(name, id)| match MapData::from_id(*id) {
+                Ok(data) => MapSnapshot {
+                    name: name.clone(),
+                    max_entries: data.info().ok().map(|info| info.max_entries()),
+                    entries: count_entries(name, data),
+                },
+                Err(e) => {
+                    warn!("Failed to open map {name}: {e}");
+                    MapSnapshot {
+                        name: name.clone(),
+                        max_entries: None,
+                        entries: None,
+                    }
+                }
+            })
+            .collect();
+
+        Snapshot {
+            programs,
+            maps,
+            ringbuf_size: self.0.ringbuf_size,
+            ringbuf_requested_size: self.0.ringbuf_requested_size,
+            stats_enabled,
+        }
+    }
+}
+
+fn map_data(map: &Map) -> Option<&MapData> {
+    match map {
+        Map::Array(data)
+        | Map::HashMap(data)
+        | Map::LpmTrie(data)
+        | Map::LruHashMap(data)
+        | Map::PerCpuArray(data)
+        | Map::RingBuf(data) => Some(data),
+        _ => None,
+    }
+}
+
+/// Count the entries in the maps that fill up as fact runs.
+fn count_entries(name: &str, data: MapData) -> Option<usize> {
+    let count = match name {
+        maps::INODE_MAP => HashMap::<_, inode_key_t, inode_value_t>::try_from(Map::HashMap(data))
+            .ok()?
+            .keys()
+            .filter(Result::is_ok)
+            .count(),
+        maps::PATH_PREFIX => {
+            LpmTrie::<_, [c_char; LPM_SIZE_MAX as usize], c_char>::try_from(Map::LpmTrie(data))
+                .ok()?
+                .keys()
+                .filter(Result::is_ok)
+                .count()
+        }
+        _ => return None,
+    };
+    Some(count)
+}
+
+/// Whether the kernel collects run time statistics of BPF programs.
+fn stats_enabled() -> bool {
+    fs::read_to_string("/proc/sys/kernel/bpf_stats_enabled").is_ok_and(|s| s.trim() == "1")
+}
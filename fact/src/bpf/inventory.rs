@@ -0,0 +1,196 @@
+//! Programs and maps of the loaded BPF object.
+//!
+//! The object is checked against the names in `fact_ebpf::names` once
+//! loaded, so a mismatch between the BPF sources and userspace fails at
+//! startup instead of when a map is first used.
+
+use std::{collections::BTreeSet, fmt};
+
+use anyhow::anyhow;
+use aya::{Ebpf, maps::Map};
+use fact_ebpf::names;
+
+/// Programs and maps of the object differing from the expected ones.
+#[derive(thiserror::Error, Debug, Default, PartialEq, Eq)]
+pub struct InventoryError {
+    missing_programs: Vec<String>,
+    unexpected_programs: Vec<String>,
+    missing_maps: Vec<String>,
+    unexpected_maps: Vec<String>,
+}
+
+impl InventoryError {
+    fn is_empty(&self) -> bool {
+        self.missing_programs.is_empty()
+            && self.unexpected_programs.is_empty()
+            && self.missing_maps.is_empty()
+            && self.unexpected_maps.is_empty()
+    }
+}
+
+impl fmt::Display for InventoryError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("BPF object does not match fact")?;
+        let parts = [
+            ("missing programs", &self.missing_programs),
+            ("unexpected programs", &self.unexpected_programs),
+            ("missing maps", &self.missing_maps),
+            ("unexpected maps", &self.unexpected_maps),
+        ];
+        let mut sep = ": ";
+        for (label, names) in parts {
+            if !names.is_empty() {
+                write!(f, "{sep}{label} {}", names.join(", "))?;
+                sep = "; ";
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Names of the programs `main.o` is expected to have.
+pub(super) fn expected_programs() -> BTreeSet<String> {
+    names::HOOKS
+        .iter()
+        .map(|hook| format!("{}{hook}", names::PROGRAM_PREFIX))
+        .collect()
+}
+
+/// Names of the maps in `obj`, without global data sections.
+fn map_names(obj: &Ebpf) -> impl Iterator<Item = &str> {
+    obj.maps()
+        .map(|(name, _)| name)
+        .filter(|name| !name.starts_with('.'))
+}
+
+/// Check the programs and maps in `obj` are the ones fact uses.
+pub(super) fn check(obj: &Ebpf) -> Result<(), InventoryError> {
+    diff(
+        &expected_programs(),
+        &names::maps::ALL.iter().map(|m| m.to_string()).collect(),
+        obj.programs().map(|(name, _)| name),
+        map_names(obj),
+    )
+}
+
+fn diff<'a>(
+    expected_programs: &BTreeSet<String>,
+    expected_maps: &BTreeSet<String>,
+    programs: impl Iterator<Item = &'a str>,
+    maps: impl Iterator<Item = &'a str>,
+) -> Result<(), InventoryError> {
+    fn split(
+        expected: &BTreeSet<String>,
+        actual: impl Iterator<Item = impl Into<String>>,
+    ) -> (Vec<String>, Vec<String>) {
+        let actual = actual.map(Into::into).collect::<BTreeSet<String>>();
+        (
+            expected.difference(&actual).cloned().collect(),
+            actual.difference(expected).cloned().collect(),
+        )
+    }
+
+    let (missing_programs, unexpected_programs) = split(expected_programs, programs);
+    let (missing_maps, unexpected_maps) = split(expected_maps, maps);
+    let err = InventoryError {
+        missing_programs,
+        unexpected_programs,
+        missing_maps,
+        unexpected_maps,
+    };
+    if err.is_empty() { Ok(()) } else { Err(err) }
+}
+
+fn map_not_found(obj: &Ebpf, name: &str) -> anyhow::Error {
+    let mut present = map_names(obj).collect::<Vec<_>>();
+    present.sort();
+    anyhow!(
+        "{name} map not found, the BPF object has: {}",
+        present.join(", ")
+    )
+}
+
+/// Take the map `name` out of `obj`.
+pub(super) fn take_map(obj: &mut Ebpf, name: &str) -> anyhow::Result<Map> {
+    if obj.map(name).is_none() {
+        return Err(map_not_found(obj, name));
+    }
+    Ok(obj.take_map(name).expect("map is present"))
+}
+
+/// Borrow the map `name` in `obj`.
+pub(super) fn map_mut<'a>(obj: &'a mut Ebpf, name: &str) -> anyhow::Result<&'a mut Map> {
+    if obj.map(name).is_none() {
+        return Err(map_not_found(obj, name));
+    }
+    Ok(obj.map_mut(name).expect("map is present"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn set(names: &[&str]) -> BTreeSet<String> {
+        names.iter().map(|n| n.to_string()).collect()
+    }
+
+    #[test]
+    fn diff_inventory() {
+        let programs = set(&["trace_file_open", "trace_path_unlink"]);
+        let maps = set(&["rb", "metrics"]);
+
+        assert_eq!(
+            diff(
+                &programs,
+                &maps,
+                ["trace_path_unlink", "trace_file_open"].into_iter(),
+                ["metrics", "rb"].into_iter(),
+            ),
+            Ok(())
+        );
+
+        let err = diff(
+            &programs,
+            &maps,
+            ["trace_file_open", "trace_path_chmod"].into_iter(),
+            ["rb", "metrics"].into_iter(),
+        )
+        .unwrap_err();
+        assert_eq!(
+            err,
+            InventoryError {
+                missing_programs: vec![String::from("trace_path_unlink")],
+                unexpected_programs: vec![String::from("trace_path_chmod")],
+                ..Default::default()
+            }
+        );
+        assert_eq!(
+            err.to_string(),
+            "BPF object does not match fact: missing programs trace_path_unlink; \
+            unexpected programs trace_path_chmod"
+        );
+
+        let err = diff(
+            &programs,
+            &maps,
+            programs.iter().map(String::as_str),
+            ["rb"].into_iter(),
+        )
+        .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "BPF object does not match fact: missing maps metrics"
+        );
+    }
+
+    #[test]
+    fn expected_names() {
+        let programs = expected_programs();
+        assert_eq!(programs.len(), names::HOOKS.len());
+        assert!(programs.contains("trace_file_open"));
+        assert!(programs.contains("trace_path_link"));
+
+        let maps = set(names::maps::ALL);
+        assert_eq!(maps.len(), names::maps::ALL.len(), "duplicate map names");
+    }
+}
@@ -1,9 +1,18 @@
-use std::{io, path::PathBuf};
+use std::{
+    collections::HashSet,
+    io,
+    path::PathBuf,
+    sync::{
+        Arc,
+        atomic::{AtomicBool, Ordering},
+    },
+    time::{Duration, Instant},
+};
 
 use anyhow::{Context, bail};
 use aya::{
-    Btf, Ebpf,
-    maps::{HashMap, LpmTrie, MapData, PerCpuArray, RingBuf},
+    Btf, Ebpf, EbpfError,
+    maps::{Array, HashMap, LpmTrie, MapData, MapError, PerCpuArray, RingBuf},
     programs::{Program, lsm::LsmLink},
 };
 use checks::Checks;
@@ -16,13 +25,73 @@ use tokio::{
     task::JoinSet,
 };
 
-use crate::{config::BpfConfig, event::Event, host_info, metrics::EventCounter};
+use crate::{
+    config::{
+        BpfConfig, ProtectedPath, SamplingRule,
+        reloader::{ReloadStatus, Reloader},
+    },
+    event::{
+        Event, FilterState,
+        checkpoint_restore::SuppressionWindow,
+        lineage::{self, Lineages},
+        raw::{HostContext, parse_record},
+    },
+    filter::{self, Filter},
+    host_info,
+    metrics::{DropReason, EventCounter},
+    pause::PauseSwitch,
+    sampling::Sampler,
+};
 
-use fact_ebpf::{LPM_SIZE_MAX, event_t, inode_key_t, inode_value_t, metrics_t, path_prefix_t};
+use fact_ebpf::{
+    LPM_SIZE_MAX, inode_key_t, inode_value_t, lineage_key_t, metrics_t,
+    names::{self, globals, maps},
+    path_prefix_action_t, path_prefix_t,
+};
 
 mod checks;
+mod diagnostics;
+mod inventory;
+mod prefixes;
+
+pub use diagnostics::Diagnostics;
+
+/// Interval between logs of events that failed to parse.
+const PARSE_ERROR_LOG_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Sizes to create the ringbuffer with, in kilobytes: the requested
+/// size, then halved down to `min`.
+///
+/// Both sizes are powers of two, so every size tried is too.
+fn ringbuf_sizes(requested: u32, min: u32) -> impl Iterator<Item = u32> {
+    let min = min.min(requested);
+    std::iter::successors(Some(requested), move |size| {
+        Some(size / 2).filter(|next| *next >= min)
+    })
+}
+
+/// Whether loading failed because the kernel could not allocate the
+/// memory for the ringbuffer.
+///
+/// Kernels charging BPF maps to the locked memory limit fail with
+/// EPERM, the ones charging them to the memory cgroup with ENOMEM.
+fn is_ringbuf_alloc_error(err: &EbpfError) -> bool {
+    match err {
+        EbpfError::MapError(MapError::CreateError { name, io_error, .. }) => {
+            name == maps::RINGBUFFER
+                && matches!(io_error.raw_os_error(), Some(libc::ENOMEM | libc::EPERM))
+        }
+        _ => false,
+    }
+}
 
-const RINGBUFFER_NAME: &str = "rb";
+/// Check if BPF LSM programs can be attached, by loading and attaching
+/// a no-op program.
+pub fn probe_lsm() -> anyhow::Result<()> {
+    Bpf::bump_memlock_rlimit()?;
+    let btf = Btf::from_sys_fs()?;
+    Checks::probe_attach(&btf)
+}
 
 pub struct Bpf {
     obj: Ebpf,
@@ -30,47 +99,100 @@ pub struct Bpf {
 
     tx: mpsc::Sender<Event>,
 
-    paths: Vec<path_prefix_t>,
+    /// Prefixes loaded in the kernel, along with their action.
+    paths: Vec<prefixes::Prefix>,
     paths_config: watch::Receiver<Vec<PathBuf>>,
+    protected_paths_config: watch::Receiver<Vec<ProtectedPath>>,
+    /// Where paths the kernel rejects on reload are reported.
+    reload_status: watch::Sender<ReloadStatus>,
 
     paths_globset: GlobSet,
+    /// Whether the monitored paths are fully loaded in the kernel and
+    /// in `paths_globset`.
+    filter_state: FilterState,
+
+    checkpoint_restore_config: watch::Receiver<Duration>,
+    checkpoint_restore: SuppressionWindow,
+    /// Events that fail to parse are all counted, only logged once per
+    /// `PARSE_ERROR_LOG_INTERVAL`.
+    parse_errors: SuppressionWindow,
+
+    sampling_config: watch::Receiver<Vec<SamplingRule>>,
+    sampler: Sampler,
+
+    filters_config: watch::Receiver<Vec<Filter>>,
+    filters: Vec<Filter>,
 
     links: Vec<LsmLink>,
+    /// Whether `links` holds the attached programs, shared with
+    /// `Diagnostics`.
+    attached: Arc<AtomicBool>,
+    /// Size of the ringbuffer in bytes, it may be smaller than the
+    /// configured one, in `ringbuf_requested_size`.
+    ringbuf_size: u64,
+    ringbuf_requested_size: u64,
 
     running: watch::Receiver<bool>,
     metrics: EventCounter,
+    lineage_metrics: EventCounter,
+    /// Lineages of the events read so far, shared with `Diagnostics`.
+    lineages: Lineages,
 }
 
 impl Bpf {
     pub fn new(
-        paths_config: watch::Receiver<Vec<PathBuf>>,
-        bpf_config: &BpfConfig,
+        reloader: &Reloader,
         running: watch::Receiver<bool>,
         metrics: EventCounter,
+        lineage_metrics: EventCounter,
     ) -> anyhow::Result<(Self, mpsc::Receiver<Event>)> {
         Bpf::bump_memlock_rlimit()?;
 
+        let paths_config = reloader.paths();
+        let protected_paths_config = reloader.protected_paths();
+        let checkpoint_restore_config = reloader.checkpoint_restore_window();
+        let mut sampling_config = reloader.sampling();
+        let mut filters_config = reloader.filters();
+        let bpf_config = &reloader.config().bpf;
+
         let btf = Btf::from_sys_fs()?;
         let checks = Checks::new(&btf)?;
 
-        // Include the BPF object as raw bytes at compile-time and load it
-        // at runtime.
-        let obj = Bpf::load_ebpf(&checks, bpf_config)?;
+        let (obj, ringbuf_size) = Bpf::load_ebpf(&checks, bpf_config)?;
+        inventory::check(&obj)?;
 
         Bpf::validate_config(&obj, bpf_config);
 
-        let (tx, rx) = mpsc::channel(100);
+        let (tx, rx) = mpsc::channel(crate::EVENT_CHANNEL_CAPACITY);
         let paths = Vec::new();
+        let checkpoint_restore = SuppressionWindow::new(*checkpoint_restore_config.borrow());
+        let sampler = Sampler::new(&sampling_config.borrow_and_update());
+        let filters = filters_config.borrow_and_update().clone();
         let mut bpf = Bpf {
             obj,
             checks,
             tx,
             paths,
             paths_config,
+            protected_paths_config,
+            reload_status: reloader.status_reporter(),
             paths_globset: GlobSet::empty(),
+            filter_state: FilterState::Initializing,
+            checkpoint_restore_config,
+            checkpoint_restore,
+            parse_errors: SuppressionWindow::new(PARSE_ERROR_LOG_INTERVAL),
+            sampling_config,
+            sampler,
+            filters_config,
+            filters,
             links: Vec::new(),
+            attached: Arc::default(),
+            ringbuf_size: u64::from(ringbuf_size) * 1024,
+            ringbuf_requested_size: u64::from(bpf_config.ringbuf_size()) * 1024,
             running,
             metrics,
+            lineage_metrics,
+            lineages: Lineages::default(),
         };
 
         bpf.load_progs(&btf, bpf_config)?;
@@ -96,104 +218,232 @@ impl Bpf {
         Ok(())
     }
 
-    fn load_ebpf(checks: &Checks, bpf_config: &BpfConfig) -> anyhow::Result<Ebpf> {
+    /// Load the BPF object, returning it with the size of its
+    /// ringbuffer in kilobytes.
+    ///
+    /// Small nodes may not have the memory for the configured
+    /// ringbuffer, smaller ones are tried down to
+    /// `bpf.ringbuf_min_size` before giving up.
+    fn load_ebpf(checks: &Checks, bpf_config: &BpfConfig) -> anyhow::Result<(Ebpf, u32)> {
+        let requested = bpf_config.ringbuf_size();
+        let mut sizes = ringbuf_sizes(requested, bpf_config.ringbuf_min_size()).peekable();
+        while let Some(size) = sizes.next() {
+            let err = match Bpf::load_ebpf_with_ringbuf(checks, bpf_config, size) {
+                Ok(obj) if size == requested => {
+                    info!("Using a {size}KB ringbuffer");
+                    return Ok((obj, size));
+                }
+                Ok(obj) => {
+                    warn!("Using a {size}KB ringbuffer instead of the {requested}KB configured");
+                    return Ok((obj, size));
+                }
+                Err(e) if is_ringbuf_alloc_error(&e) => e,
+                Err(e) => return Err(e).context("failed to load eBPF object"),
+            };
+
+            let Some(next) = sizes.peek() else {
+                return Err(err).context(format!(
+                    "failed to create a ringbuffer of {size}KB, the kernel is out of memory for it. \
+                    Check the locked memory limit (ulimit -l) and the memory limit of the cgroup \
+                    fact runs in, or lower bpf.ringbuf_min_size"
+                ));
+            };
+            warn!("Failed to create a {size}KB ringbuffer, retrying with {next}KB: {err}");
+        }
+        unreachable!("the configured ringbuffer size is always tried")
+    }
+
+    fn load_ebpf_with_ringbuf(
+        checks: &Checks,
+        bpf_config: &BpfConfig,
+        ringbuf_size: u32,
+    ) -> Result<Ebpf, EbpfError> {
         // Include the BPF object as raw bytes at compile-time and load it
         // at runtime.
         aya::EbpfLoader::new()
-            .override_global("host_mount_ns", &host_info::get_host_mount_ns(), true)
             .override_global(
-                "path_hooks_support_bpf_d_path",
+                globals::HOST_MOUNT_NS,
+                &host_info::get_host_mount_ns(),
+                true,
+            )
+            .override_global(globals::FACT_TGID, &host_info::get_host_pid(), true)
+            .override_global(
+                globals::COLLECT_ARGS,
+                &(bpf_config.collect_args() as u8),
+                true,
+            )
+            .override_global(
+                globals::REPORT_SELF,
+                &(bpf_config.report_self() as u8),
+                true,
+            )
+            .override_global(
+                globals::PATH_HOOKS_SUPPORT_BPF_D_PATH,
                 &(checks.path_hooks_support_bpf_d_path as u8),
                 true,
             )
-            .map_max_entries(RINGBUFFER_NAME, bpf_config.ringbuf_size() * 1024)
-            .map_max_entries("inode_map", bpf_config.inodes_max())
+            .map_max_entries(maps::RINGBUFFER, ringbuf_size * 1024)
+            .map_max_entries(maps::INODE_MAP, bpf_config.inodes_max())
             .load(fact_ebpf::EBPF_OBJ)
-            .context("failed to load eBPF object")
+    }
+
+    /// Size of the ringbuffer in bytes.
+    pub fn ringbuf_size(&self) -> u64 {
+        self.ringbuf_size
+    }
+
+    /// Get a handle for taking snapshots of the programs and maps, must
+    /// be called before any map is taken.
+    pub fn diagnostics(&self) -> Diagnostics {
+        Diagnostics::new(
+            &self.obj,
+            self.ringbuf_size,
+            self.ringbuf_requested_size,
+            self.attached.clone(),
+            self.lineages.clone(),
+        )
     }
 
     pub fn take_inode_map(
         &mut self,
     ) -> anyhow::Result<HashMap<MapData, inode_key_t, inode_value_t>> {
-        let Some(inode_map) = self.obj.take_map("inode_map") else {
-            bail!("inode_map not found");
-        };
+        let inode_map = inventory::take_map(&mut self.obj, maps::INODE_MAP)?;
         Ok(inode_map.try_into()?)
     }
 
     pub fn take_metrics(&mut self) -> anyhow::Result<PerCpuArray<MapData, metrics_t>> {
-        let metrics = match self.obj.take_map("metrics") {
-            Some(m) => m,
-            None => bail!("metrics map not found"),
-        };
+        let metrics = inventory::take_map(&mut self.obj, maps::METRICS)?;
         Ok(PerCpuArray::try_from(metrics)?)
     }
 
+    pub fn take_pause_flag(&mut self) -> anyhow::Result<PauseFlag> {
+        let paused = inventory::take_map(&mut self.obj, maps::PAUSED)?;
+        Ok(PauseFlag(Array::try_from(paused)?))
+    }
+
+    fn take_lineage_cache(&mut self) -> anyhow::Result<HashMap<MapData, lineage_key_t, u32>> {
+        let lineage_cache = inventory::take_map(&mut self.obj, maps::LINEAGE_CACHE)?;
+        Ok(lineage_cache.try_into()?)
+    }
+
+    /// Count where the lineage of `event` came from. The kernel forgets
+    /// processes whose lineage is missing, so it is sent again with
+    /// their next event.
+    fn check_lineage(
+        &self,
+        event: &Event,
+        lineage_cache: &mut HashMap<MapData, lineage_key_t, u32>,
+    ) {
+        let process = event.get_process();
+        match process.lineage_source() {
+            lineage::Source::Kernel => self.lineage_metrics.added(),
+            lineage::Source::Cached => self.lineage_metrics.hit(),
+            lineage::Source::Missing => {
+                self.lineage_metrics.miss();
+                match lineage_cache.remove(&process.key().into()) {
+                    Ok(()) | Err(MapError::KeyNotFound) => {}
+                    Err(e) => warn!("Failed to remove process from lineage_cache: {e}"),
+                }
+            }
+        }
+    }
+
     fn take_ringbuffer(&mut self) -> anyhow::Result<RingBuf<MapData>> {
-        let ringbuf = match self.obj.take_map(RINGBUFFER_NAME) {
-            Some(r) => r,
-            None => bail!("Ring buffer not found"),
-        };
+        let ringbuf = inventory::take_map(&mut self.obj, maps::RINGBUFFER)?;
         Ok(RingBuf::try_from(ringbuf)?)
     }
 
+    /// Load the configured paths into the kernel and the userspace
+    /// globset.
+    ///
+    /// Programs are only attached once the paths are fully loaded, so
+    /// the kernel never sends events matched against a partial set of
+    /// paths. All the paths are checked before the kernel map is
+    /// updated, which is done all or nothing, see `prefixes::replace`.
     fn load_paths(&mut self) -> anyhow::Result<()> {
-        if self.paths_config.borrow().is_empty() {
+        self.filter_state = FilterState::Initializing;
+        let paths_config = self.paths_config.borrow();
+        let protected_paths_config = self.protected_paths_config.borrow();
+        if paths_config.is_empty() && protected_paths_config.is_empty() {
+            drop(paths_config);
+            drop(protected_paths_config);
             self.detach_progs();
             self.paths.clear();
             self.paths_globset = GlobSet::empty();
+            self.filter_state = FilterState::Ready;
             return Ok(());
         }
 
-        if self.links.is_empty() {
-            self.attach_progs()?;
-        }
-
-        let Some(path_prefix) = self.obj.map_mut("path_prefix") else {
-            bail!("path_prefix map not found");
-        };
-        let mut path_prefix: LpmTrie<&mut MapData, [c_char; LPM_SIZE_MAX as usize], c_char> =
-            LpmTrie::try_from(path_prefix)?;
-
-        // Add the new prefixes
-        let paths_config = self.paths_config.borrow();
-        let mut new_paths = Vec::with_capacity(paths_config.len());
+        // Protected paths are monitored too, they are added last so
+        // their action takes precedence on prefixes used for both.
+        let paths = paths_config
+            .iter()
+            .map(|p| (p, path_prefix_action_t::PATH_PREFIX_MONITOR))
+            .chain(protected_paths_config.iter().map(|p| {
+                let action = if p.enforce {
+                    warn!("Enforcing protected path {}", p.path.display());
+                    path_prefix_action_t::PATH_PREFIX_ENFORCE
+                } else {
+                    path_prefix_action_t::PATH_PREFIX_MONITOR
+                };
+                (&p.path, action)
+            }))
+            .collect::<Vec<_>>();
+
+        let mut new_paths: Vec<prefixes::Prefix> = Vec::with_capacity(paths.len());
         let mut builder = GlobSetBuilder::new();
-        for p in paths_config.iter() {
+        for (p, action) in paths {
             let Some(glob_str) = p.to_str() else {
                 bail!("failed to convert path {} to string", p.display());
             };
 
-            builder.add(
-                Glob::new(glob_str)
-                    .with_context(|| format!("invalid glob {}", glob_str))
-                    .unwrap(),
-            );
+            builder.add(Glob::new(glob_str).with_context(|| format!("invalid glob {glob_str}"))?);
 
             let prefix = path_prefix_t::try_from(p)?;
-            path_prefix.insert(&prefix.into(), 0, 0)?;
-            new_paths.push(prefix);
-        }
-        self.paths_globset = builder.build()?;
-
-        // Remove old prefixes
-        for p in self.paths.iter().filter(|p| !new_paths.contains(p)) {
-            if let Err(e) = path_prefix.remove(&(*p).into()) {
-                warn!("Failed to remove path prefix: {e:#?}");
+            let action = action.0 as c_char;
+            match new_paths.iter_mut().find(|(p, _)| *p == prefix) {
+                Some((_, a)) => *a = action,
+                None => new_paths.push((prefix, action)),
             }
         }
+        let paths_globset = builder.build()?;
+        drop(paths_config);
+        drop(protected_paths_config);
+
+        let path_prefix = inventory::map_mut(&mut self.obj, maps::PATH_PREFIX)?;
+        let mut path_prefix: LpmTrie<&mut MapData, [c_char; LPM_SIZE_MAX as usize], c_char> =
+            LpmTrie::try_from(path_prefix)?;
 
+        prefixes::replace(&mut path_prefix, &self.paths, &new_paths)?;
+        self.paths_globset = paths_globset;
         self.paths = new_paths;
 
+        if self.links.is_empty() {
+            self.attach_progs()?;
+        }
+        self.filter_state = FilterState::Ready;
+
         Ok(())
     }
 
+    /// Load the paths of a new configuration, the previous ones are
+    /// kept if they can't be.
+    fn reload_paths(&mut self) {
+        let filter_state = self.filter_state;
+        if let Err(e) = self.load_paths() {
+            error!("Failed to load the monitored paths, keeping the previous ones: {e:#}");
+            self.filter_state = filter_state;
+            self.reload_status
+                .send_modify(|status| status.failed(format!("{e:#}")));
+        }
+    }
+
     fn load_progs(&mut self, btf: &Btf, bpf_config: &BpfConfig) -> anyhow::Result<()> {
         for (name, prog) in self.obj.programs_mut() {
             // The format used for our hook names is `trace_<hook>`, so
             // we can just strip trace_ to get the hook name we need for
             // loading.
-            let Some(hook) = name.strip_prefix("trace_") else {
+            let Some(hook) = name.strip_prefix(names::PROGRAM_PREFIX) else {
                 bail!("Invalid hook name: {name}");
             };
 
@@ -216,6 +466,19 @@ impl Bpf {
         Ok(())
     }
 
+    /// Get the name of the hooks that were loaded into the kernel.
+    ///
+    /// Hooks disabled in the configuration or not supported by the
+    /// running kernel are not included.
+    pub fn loaded_hooks(&self) -> HashSet<String> {
+        self.obj
+            .programs()
+            .filter(|(_, prog)| matches!(prog, Program::Lsm(prog) if prog.fd().is_ok()))
+            .filter_map(|(name, _)| name.strip_prefix(names::PROGRAM_PREFIX))
+            .map(str::to_owned)
+            .collect()
+    }
+
     /// Attaches the supplied BPF program if it is loaded into the kernel.
     fn attach_prog(prog: &mut Program) -> Result<LsmLink, BpfAttachError> {
         match prog {
@@ -233,6 +496,9 @@ impl Bpf {
     ///
     /// If any attach fails, programs that were already attached during
     /// this call are dropped.
+    ///
+    /// Must only be called once the monitored paths are loaded, see
+    /// `Bpf::load_paths`.
     fn attach_progs(&mut self) -> anyhow::Result<()> {
         self.links = self
             .obj
@@ -243,6 +509,7 @@ impl Bpf {
                 Err(e) => Some(Err(e)),
             })
             .collect::<Result<Vec<_>, BpfAttachError>>()?;
+        self.attached.store(true, Ordering::Relaxed);
 
         Ok(())
     }
@@ -250,6 +517,7 @@ impl Bpf {
     /// Detaches all BPF programs by dropping owned links.
     fn detach_progs(&mut self) {
         self.links.clear();
+        self.attached.store(false, Ordering::Relaxed);
     }
 
     /// Verify the current configuration for errors.
@@ -259,7 +527,7 @@ impl Bpf {
         let mut is_valid = true;
 
         for name in bpf_config.programs.keys() {
-            let hook = "trace_".to_string() + name;
+            let hook = names::PROGRAM_PREFIX.to_string() + name;
             if obj.program(&hook).is_none() {
                 warn!("{name} is not a known program");
                 is_valid = false;
@@ -269,6 +537,23 @@ impl Bpf {
         is_valid
     }
 
+    /// Tag events received while the monitored paths are not fully
+    /// loaded.
+    ///
+    /// The ordering in `Bpf::load_paths` should make this impossible,
+    /// receiving such an event is a bug.
+    fn check_filter_state(&self, event: &mut Event) {
+        if self.filter_state != FilterState::Ready {
+            self.metrics.initializing();
+            event.set_filter_state(self.filter_state);
+        }
+        debug_assert_eq!(
+            self.filter_state,
+            FilterState::Ready,
+            "Event received before paths were loaded: {event:?}"
+        );
+    }
+
     // Gather events from the ring buffer and print them out.
     pub fn start(mut self, task_set: &mut JoinSet<anyhow::Result<()>>) {
         info!("Starting BPF worker...");
@@ -276,6 +561,8 @@ impl Bpf {
         task_set.spawn(async move {
             let rb = self.take_ringbuffer()?;
             let mut fd = AsyncFd::new(rb)?;
+            let mut lineage_cache = self.take_lineage_cache()?;
+            let host = HostContext::local();
 
             loop {
                 tokio::select! {
@@ -284,23 +571,41 @@ impl Bpf {
                             .context("ringbuffer guard held while runtime is stopping")?;
                         let ringbuf = guard.get_inner_mut();
                         while let Some(event) = ringbuf.next() {
-                            let event: &event_t = unsafe { &*(event.as_ptr() as *const _) };
-                            let event = match Event::try_from(event) {
-                                Ok(event) => {
+                            let event = match parse_record(&event, &host, &self.lineages) {
+                                Ok(mut event) => {
+                                    self.check_filter_state(&mut event);
+                                    self.check_lineage(&event, &mut lineage_cache);
                                     // If the event is monitored by parent, we need to check
                                     // its host path, but we don't have that context here,
                                     // so we let the event go into HostScanner and make the
                                     // decision there.
                                     if !event.is_monitored_by_parent() &&
                                             event.is_ignored(&self.paths_globset) {
-                                        self.metrics.dropped();
+                                        self.metrics.dropped(DropReason::Filtered);
+                                        continue;
+                                    }
+                                    if event.is_checkpoint_restore() &&
+                                            self.checkpoint_restore.suppress(Instant::now()) {
+                                        self.metrics.ignored();
+                                        continue;
+                                    }
+                                    if !filter::keep(&self.filters, &event) {
+                                        self.metrics.filtered();
+                                        continue;
+                                    }
+                                    if !self.sampler.is_empty() && !self.sampler.sample(&mut event) {
+                                        self.metrics.sampled();
                                         continue;
                                     }
                                     event
                                 },
                                 Err(e) => {
-                                    error!("Failed to parse event: '{e}'");
-                                    self.metrics.dropped();
+                                    if !self.parse_errors.suppress(Instant::now()) {
+                                        error!(
+                                            "Failed to parse event: '{e}', further errors in the next {PARSE_ERROR_LOG_INTERVAL:?} are only counted"
+                                        );
+                                    }
+                                    self.metrics.dropped(DropReason::ParseError);
                                     continue;
                                 }
                             };
@@ -313,8 +618,17 @@ impl Bpf {
                         }
                         guard.clear_ready();
                     },
-                    _ = self.paths_config.changed() => {
-                        self.load_paths().context("Failed to load paths")?;
+                    _ = self.paths_config.changed() => self.reload_paths(),
+                    _ = self.protected_paths_config.changed() => self.reload_paths(),
+                    _ = self.checkpoint_restore_config.changed() => {
+                        let window = *self.checkpoint_restore_config.borrow();
+                        self.checkpoint_restore.set_window(window);
+                    },
+                    _ = self.sampling_config.changed() => {
+                        self.sampler = Sampler::new(&self.sampling_config.borrow());
+                    },
+                    _ = self.filters_config.changed() => {
+                        self.filters = self.filters_config.borrow().clone();
                     },
                     _ = self.running.changed() => {
                         if !*self.running.borrow() {
@@ -330,6 +644,17 @@ impl Bpf {
     }
 }
 
+/// Kernel flag pausing the emission of events by the BPF programs.
+pub struct PauseFlag(Array<MapData, u32>);
+
+impl PauseSwitch for PauseFlag {
+    fn set_paused(&mut self, paused: bool) -> anyhow::Result<()> {
+        self.0
+            .set(0, paused as u32, 0)
+            .context("failed to update paused map")
+    }
+}
+
 #[derive(thiserror::Error, Debug)]
 enum BpfAttachError {
     #[error("attempted to attach unloaded program")]
@@ -338,11 +663,81 @@ enum BpfAttachError {
     ProgramError(#[from] aya::programs::ProgramError),
 }
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ringbuf_fallback() {
+        let tests: &[(u32, u32, &[u32], &str)] = &[
+            (8192, 8192, &[8192], "No fallback"),
+            (1024, 64, &[1024, 512, 256, 128, 64], "Down to the minimum"),
+            (
+                262144,
+                32768,
+                &[262144, 131072, 65536, 32768],
+                "Custom floor",
+            ),
+            (64, 64, &[64], "Already the minimum"),
+            (128, 1024, &[128], "Floor above the requested size"),
+        ];
+
+        for (requested, min, expected, description) in tests {
+            assert_eq!(
+                ringbuf_sizes(*requested, *min).collect::<Vec<_>>(),
+                *expected,
+                "Failed for {description}"
+            );
+        }
+    }
+
+    #[test]
+    fn ringbuf_alloc_errors() {
+        let create_error = |name: &str, errno| {
+            EbpfError::MapError(MapError::CreateError {
+                name: name.to_owned(),
+                io_error: io::Error::from_raw_os_error(errno),
+            })
+        };
+
+        assert!(is_ringbuf_alloc_error(&create_error(
+            maps::RINGBUFFER,
+            libc::ENOMEM
+        )));
+        assert!(is_ringbuf_alloc_error(&create_error(
+            maps::RINGBUFFER,
+            libc::EPERM
+        )));
+        assert!(!is_ringbuf_alloc_error(&create_error(
+            maps::RINGBUFFER,
+            libc::EINVAL
+        )));
+        assert!(!is_ringbuf_alloc_error(&create_error(
+            maps::INODE_MAP,
+            libc::ENOMEM
+        )));
+        assert!(!is_ringbuf_alloc_error(&EbpfError::MapError(
+            MapError::KeyNotFound
+        )));
+    }
+}
+
 #[cfg(all(test, feature = "bpf-test"))]
 mod bpf_tests {
-    use std::{collections, env, os::unix::fs::PermissionsExt, path::PathBuf, time::Duration};
+    use std::{
+        collections, env,
+        fs::OpenOptions,
+        io::Write,
+        os::unix::fs::PermissionsExt,
+        path::{Path, PathBuf},
+        sync::{
+            Arc,
+            atomic::{AtomicBool, AtomicU64, Ordering},
+        },
+        time::Duration,
+    };
 
-    use tempfile::NamedTempFile;
+    use tempfile::{NamedTempFile, TempDir};
     use tokio::{sync::watch, time::timeout};
 
     use crate::{
@@ -354,6 +749,43 @@ mod bpf_tests {
 
     use super::*;
 
+    /// Configuration monitoring `paths`. The tests cause the events they
+    /// wait for themselves, which are only reported along with the
+    /// activity of fact.
+    fn config_for(paths: Vec<PathBuf>) -> FactConfig {
+        let mut config = self_reporting();
+        config.set_paths(paths);
+        config
+    }
+
+    fn self_reporting() -> FactConfig {
+        FactConfig::try_from("bpf:\n  report_self: true").expect("Failed to parse config")
+    }
+
+    #[test]
+    fn test_inventory() {
+        Bpf::bump_memlock_rlimit().unwrap();
+        let obj = aya::Ebpf::load(fact_ebpf::EBPF_OBJ).expect("Failed to load BPF object");
+
+        let programs = obj
+            .programs()
+            .map(|(name, _)| name.to_owned())
+            .collect::<collections::BTreeSet<_>>();
+        assert_eq!(programs, inventory::expected_programs());
+
+        let mut maps = obj
+            .maps()
+            .map(|(name, _)| name)
+            .filter(|name| !name.starts_with('.'))
+            .collect::<Vec<_>>();
+        maps.sort();
+        let mut expected = names::maps::ALL.to_vec();
+        expected.sort();
+        assert_eq!(maps, expected);
+
+        inventory::check(&obj).unwrap();
+    }
+
     #[tokio::test]
     async fn test_basic() {
         if let Ok(value) = std::env::var("FACT_LOGLEVEL") {
@@ -366,16 +798,14 @@ mod bpf_tests {
         let monitored_path = env!("CARGO_MANIFEST_DIR");
         let monitored_path = PathBuf::from(monitored_path);
         let paths = vec![PathBuf::from(format!("{}/**/*", monitored_path.display()))];
-        let mut config = FactConfig::default();
-        config.set_paths(paths);
-        let reloader = Reloader::from(config);
+        let reloader = Reloader::from(config_for(paths));
         let metrics = Metrics::new();
         let (run_tx, run_rx) = watch::channel(true);
         let (bpf, mut rx) = Bpf::new(
-            reloader.paths(),
-            &reloader.config().bpf,
+            &reloader,
             run_rx,
             metrics.bpf_worker.clone(),
+            metrics.lineage_cache.clone(),
         )
         .expect("Failed to load BPF code");
         let mut task_set = JoinSet::new();
@@ -474,6 +904,597 @@ mod bpf_tests {
         run_tx.send(false).unwrap();
     }
 
+    #[tokio::test]
+    async fn test_disabled_program() {
+        let monitored_path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        let paths = vec![PathBuf::from(format!("{}/**/*", monitored_path.display()))];
+        let mut config = FactConfig::try_from(
+            "bpf:\n  report_self: true\n  programs:\n    file_open:\n      enabled: false",
+        )
+        .expect("Failed to parse config");
+        config.set_paths(paths);
+        let reloader = Reloader::from(config);
+        let metrics = Metrics::new();
+        let (run_tx, run_rx) = watch::channel(true);
+        let (bpf, mut rx) = Bpf::new(
+            &reloader,
+            run_rx,
+            metrics.bpf_worker.clone(),
+            metrics.lineage_cache.clone(),
+        )
+        .expect("Failed to load BPF code");
+
+        let loaded = bpf.loaded_hooks();
+        assert!(!loaded.contains("file_open"));
+        assert!(loaded.contains("path_unlink"));
+
+        let mut task_set = JoinSet::new();
+        bpf.start(&mut task_set);
+        tokio::time::sleep(Duration::from_millis(500)).await;
+
+        // Opening an existing file must not generate any events, the
+        // unlink done when closing the temporary file still does.
+        let file = NamedTempFile::new_in(&monitored_path).expect("Failed to create temporary file");
+        let file_path = file.path().to_path_buf();
+        std::fs::read(&file_path).expect("Failed to read file");
+        file.close().expect("Failed to close temp file");
+
+        let expected = Event::new(
+            EventTestData::Unlink,
+            host_info::get_hostname(),
+            file_path,
+            PathBuf::new(),
+            Process::current(),
+        )
+        .unwrap();
+
+        let wait = timeout(Duration::from_secs(1), async move {
+            while let Some(event) = rx.recv().await {
+                println!("{event:#?}");
+                assert!(!event.is_open(), "Unexpected open event: {event:#?}");
+                if event == expected {
+                    break;
+                }
+            }
+        });
+
+        tokio::select! {
+            res = wait => res.unwrap(),
+            res = task_set.join_next() => res.unwrap().unwrap().unwrap(),
+        }
+
+        run_tx.send(false).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_write_events() {
+        let monitored_path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        let paths = vec![PathBuf::from(format!("{}/**/*", monitored_path.display()))];
+        let mut config = FactConfig::try_from(
+            "bpf:\n  report_self: true\n  programs:\n    file_permission:\n      enabled: true",
+        )
+        .expect("Failed to parse config");
+        config.set_paths(paths);
+        let reloader = Reloader::from(config);
+        let metrics = Metrics::new();
+        let (run_tx, run_rx) = watch::channel(true);
+        let (bpf, mut rx) = Bpf::new(
+            &reloader,
+            run_rx,
+            metrics.bpf_worker.clone(),
+            metrics.lineage_cache.clone(),
+        )
+        .expect("Failed to load BPF code");
+        assert!(bpf.loaded_hooks().contains("file_permission"));
+
+        let mut task_set = JoinSet::new();
+        bpf.start(&mut task_set);
+        tokio::time::sleep(Duration::from_millis(500)).await;
+
+        let file = NamedTempFile::new_in(&monitored_path).expect("Failed to create temporary file");
+        let file_path = file.path().to_path_buf();
+
+        // Writes in quick succession are reported once
+        let mut f = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(&file_path)
+            .expect("Failed to open file");
+        f.write_all(b"first").expect("Failed to write");
+        f.write_all(b"second").expect("Failed to write");
+        drop(f);
+
+        // Opening for writing without writing is only an open
+        OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(&file_path)
+            .expect("Failed to open file");
+        file.close().expect("Failed to close temp file");
+
+        let wait = timeout(Duration::from_secs(1), async move {
+            let mut types = Vec::new();
+            while let Some(event) = rx.recv().await {
+                println!("{event:#?}");
+                if *event.get_filename() != file_path {
+                    continue;
+                }
+                types.push(event.event_type());
+                if event.is_deletion() {
+                    break;
+                }
+            }
+            types
+        });
+
+        let types = tokio::select! {
+            res = wait => res.unwrap(),
+            res = task_set.join_next() => panic!("BPF worker stopped: {res:?}"),
+        };
+        assert_eq!(types, ["creation", "open", "write", "open", "unlink"]);
+
+        run_tx.send(false).unwrap();
+    }
+
+    /// Keys reported by the kernel must match the ones built from
+    /// `stat`, point `FACT_TEST_INODE64_DIR` to a directory on XFS or
+    /// btrfs with inode numbers past 2^32 to cover those too.
+    #[tokio::test]
+    async fn test_inode_numbers() {
+        use std::os::unix::fs::MetadataExt;
+
+        let monitored_path = std::env::var_os("FACT_TEST_INODE64_DIR")
+            .map(PathBuf::from)
+            .unwrap_or_else(|| PathBuf::from(env!("CARGO_MANIFEST_DIR")));
+        let paths = vec![PathBuf::from(format!("{}/**/*", monitored_path.display()))];
+        let reloader = Reloader::from(config_for(paths));
+        let metrics = Metrics::new();
+        let (run_tx, run_rx) = watch::channel(true);
+        let (bpf, mut rx) = Bpf::new(
+            &reloader,
+            run_rx,
+            metrics.bpf_worker.clone(),
+            metrics.lineage_cache.clone(),
+        )
+        .expect("Failed to load BPF code");
+
+        let mut task_set = JoinSet::new();
+        bpf.start(&mut task_set);
+        tokio::time::sleep(Duration::from_millis(500)).await;
+
+        let file = NamedTempFile::new_in(&monitored_path).expect("Failed to create temporary file");
+        let file_path = file.path().to_path_buf();
+        let metadata = file.as_file().metadata().expect("Failed to stat file");
+        if metadata.ino() > u64::from(u32::MAX) {
+            println!("Inode {} is past 2^32", metadata.ino());
+        }
+
+        let wait = timeout(Duration::from_secs(1), async move {
+            while let Some(event) = rx.recv().await {
+                if *event.get_filename() == file_path {
+                    return *event.get_inode();
+                }
+            }
+            panic!("No event for {}", file_path.display());
+        });
+
+        let inode = tokio::select! {
+            res = wait => res.unwrap(),
+            res = task_set.join_next() => panic!("BPF worker stopped: {res:?}"),
+        };
+        assert_eq!(inode.inode, metadata.ino());
+        assert_eq!(inode.dev, metadata.dev());
+
+        run_tx.send(false).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_paused() {
+        let monitored_path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        let paths = vec![PathBuf::from(format!("{}/**/*", monitored_path.display()))];
+        let reloader = Reloader::from(config_for(paths));
+        let metrics = Metrics::new();
+        let (run_tx, run_rx) = watch::channel(true);
+        let (mut bpf, mut rx) = Bpf::new(
+            &reloader,
+            run_rx,
+            metrics.bpf_worker.clone(),
+            metrics.lineage_cache.clone(),
+        )
+        .expect("Failed to load BPF code");
+        let kernel_metrics = bpf.take_metrics().expect("Failed to get metrics");
+        let mut pause_flag = bpf.take_pause_flag().expect("Failed to get pause flag");
+        pause_flag.set_paused(true).expect("Failed to pause");
+
+        let mut task_set = JoinSet::new();
+        bpf.start(&mut task_set);
+        tokio::time::sleep(Duration::from_millis(500)).await;
+
+        // Events are counted but not sent while paused
+        let file = NamedTempFile::new_in(&monitored_path).expect("Failed to create temporary file");
+        let file_path = file.path().to_path_buf();
+        file.close().expect("Failed to close temp file");
+
+        let res = timeout(Duration::from_millis(500), async {
+            while let Some(event) = rx.recv().await {
+                if *event.get_filename() == file_path {
+                    return event;
+                }
+            }
+            unreachable!("BPF worker stopped");
+        })
+        .await;
+        assert!(res.is_err(), "Unexpected event while paused: {res:#?}");
+
+        let paused = kernel_metrics
+            .get(&0, 0)
+            .expect("Failed to read metrics")
+            .iter()
+            .fold(metrics_t::default(), |acc, x| acc.accumulate(x))
+            .path_unlink
+            .paused;
+        assert!(paused > 0);
+
+        // Events flow again once resumed
+        pause_flag.set_paused(false).expect("Failed to resume");
+        let file = NamedTempFile::new_in(&monitored_path).expect("Failed to create temporary file");
+        let file_path = file.path().to_path_buf();
+        file.close().expect("Failed to close temp file");
+        let expected = Event::new(
+            EventTestData::Unlink,
+            host_info::get_hostname(),
+            file_path,
+            PathBuf::new(),
+            Process::current(),
+        )
+        .unwrap();
+
+        let wait = timeout(Duration::from_secs(1), async move {
+            while let Some(event) = rx.recv().await {
+                if event == expected {
+                    break;
+                }
+            }
+        });
+
+        tokio::select! {
+            res = wait => res.unwrap(),
+            res = task_set.join_next() => res.unwrap().unwrap().unwrap(),
+        }
+
+        run_tx.send(false).unwrap();
+    }
+
+    /// fact skips its own activity, like the scans of the monitored
+    /// paths on startup, while other processes are still reported.
+    #[tokio::test]
+    async fn test_self_activity() {
+        let monitored_path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        let dir = TempDir::new_in(&monitored_path).expect("Failed to create directory");
+        let mut config = FactConfig::default();
+        config.set_paths(vec![PathBuf::from(format!(
+            "{}/**/*",
+            dir.path().display()
+        ))]);
+        let reloader = Reloader::from(config);
+        let metrics = Metrics::new();
+        let (run_tx, run_rx) = watch::channel(true);
+        let (mut bpf, mut rx) = Bpf::new(
+            &reloader,
+            run_rx,
+            metrics.bpf_worker.clone(),
+            metrics.lineage_cache.clone(),
+        )
+        .expect("Failed to load BPF code");
+        let kernel_metrics = bpf.take_metrics().expect("Failed to get metrics");
+        let mut task_set = JoinSet::new();
+        bpf.start(&mut task_set);
+        tokio::time::sleep(Duration::from_millis(500)).await;
+
+        // The tests run as fact, so files they touch are skipped
+        let own = dir.path().join("own");
+        std::fs::write(&own, b"").expect("Failed to create file");
+        std::fs::read(&own).expect("Failed to read file");
+        let touched = dir.path().join("touched");
+        let status = std::process::Command::new("touch")
+            .arg(&touched)
+            .status()
+            .expect("Failed to run touch");
+        assert!(status.success());
+
+        let event = timeout(Duration::from_secs(1), async {
+            loop {
+                let event = rx.recv().await.expect("BPF worker stopped");
+                assert_ne!(*event.get_filename(), own, "Unexpected event: {event:#?}");
+                if *event.get_filename() == touched {
+                    break event;
+                }
+            }
+        })
+        .await
+        .expect("Timed out waiting for the event of touch");
+        assert!(event.is_creation(), "{event:#?}");
+
+        let skipped = kernel_metrics
+            .get(&0, 0)
+            .expect("Failed to read metrics")
+            .iter()
+            .fold(metrics_t::default(), |acc, x| acc.accumulate(x))
+            .file_open
+            .self_activity;
+        assert!(skipped > 0);
+
+        run_tx.send(false).unwrap();
+    }
+
+    /// Sequence numbers of the files created by the writers in each
+    /// directory, in the order their events were received.
+    #[derive(Default)]
+    struct Seen {
+        a: Vec<u64>,
+        b: Vec<u64>,
+    }
+
+    /// Create and remove files named after a sequence number in `dir`
+    /// until `stop` is set. `created` holds the number of files
+    /// created so far.
+    fn spawn_writer(
+        dir: PathBuf,
+        stop: Arc<AtomicBool>,
+        created: Arc<AtomicU64>,
+    ) -> tokio::task::JoinHandle<()> {
+        tokio::task::spawn_blocking(move || {
+            let mut n = 0;
+            while !stop.load(Ordering::Relaxed) {
+                let path = dir.join(n.to_string());
+                std::fs::write(&path, b"").expect("Failed to create file");
+                std::fs::remove_file(&path).expect("Failed to remove file");
+                n += 1;
+                created.store(n, Ordering::Relaxed);
+                std::thread::sleep(Duration::from_millis(5));
+            }
+        })
+    }
+
+    /// Receive events until `done` returns true, recording the files
+    /// created by the writers.
+    async fn recv_until(
+        rx: &mut mpsc::Receiver<Event>,
+        dirs: (&Path, &Path),
+        seen: &mut Seen,
+        done: impl Fn(&Seen) -> bool,
+    ) {
+        let created_in = |event: &Event, dir: &Path| -> Option<u64> {
+            if !event.is_creation() {
+                return None;
+            }
+            event
+                .get_filename()
+                .strip_prefix(dir)
+                .ok()?
+                .to_str()?
+                .parse()
+                .ok()
+        };
+
+        timeout(Duration::from_secs(5), async {
+            while !done(seen) {
+                let event = rx.recv().await.expect("BPF worker stopped");
+                if let Some(n) = created_in(&event, dirs.0) {
+                    seen.a.push(n);
+                } else if let Some(n) = created_in(&event, dirs.1) {
+                    seen.b.push(n);
+                }
+            }
+        })
+        .await
+        .expect("Timed out waiting for events");
+    }
+
+    #[tokio::test]
+    async fn test_reload_paths() {
+        let monitored_path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        let dir_a = TempDir::new_in(&monitored_path).expect("Failed to create directory");
+        let dir_b = TempDir::new_in(&monitored_path).expect("Failed to create directory");
+        let dirs = (dir_a.path(), dir_b.path());
+        let config = |dirs: &[&Path]| {
+            config_for(
+                dirs.iter()
+                    .map(|d| PathBuf::from(format!("{}/**/*", d.display())))
+                    .collect(),
+            )
+        };
+
+        let mut reloader = Reloader::from(config(&[dirs.0]));
+        let metrics = Metrics::new();
+        let (run_tx, run_rx) = watch::channel(true);
+        let (bpf, mut rx) = Bpf::new(
+            &reloader,
+            run_rx,
+            metrics.bpf_worker.clone(),
+            metrics.lineage_cache.clone(),
+        )
+        .expect("Failed to load BPF code");
+        let mut task_set = JoinSet::new();
+        bpf.start(&mut task_set);
+        tokio::time::sleep(Duration::from_millis(500)).await;
+
+        let stop = Arc::new(AtomicBool::new(false));
+        let created_a = Arc::new(AtomicU64::new(0));
+        let writers = [
+            spawn_writer(dirs.0.to_path_buf(), stop.clone(), created_a.clone()),
+            spawn_writer(dirs.1.to_path_buf(), stop.clone(), Arc::default()),
+        ];
+
+        let mut seen = Seen::default();
+        recv_until(&mut rx, dirs, &mut seen, |s| s.a.len() >= 20).await;
+        assert!(seen.b.is_empty(), "Unexpected events: {:?}", seen.b);
+
+        // Adding B must not interrupt the events for A
+        reloader.apply(config(&[dirs.0, dirs.1]));
+        let a_before = seen.a.len();
+        recv_until(&mut rx, dirs, &mut seen, |s| {
+            s.b.len() >= 20 && s.a.len() >= a_before + 20
+        })
+        .await;
+        assert!(
+            seen.a.windows(2).all(|w| w[1] == w[0] + 1),
+            "Gap in events: {:?}",
+            seen.a
+        );
+
+        // Removing A stops its events, B keeps going. Events for A
+        // already in flight when the reload happens may still arrive,
+        // so only files created once the reload went through count.
+        reloader.apply(config(&[dirs.1]));
+        let b_before = seen.b.len();
+        recv_until(&mut rx, dirs, &mut seen, |s| s.b.len() >= b_before + 20).await;
+        let cutoff = created_a.load(Ordering::Relaxed);
+        let b_before = seen.b.len();
+        recv_until(&mut rx, dirs, &mut seen, |s| s.b.len() >= b_before + 20).await;
+        assert!(
+            seen.a.iter().all(|n| *n < cutoff),
+            "Events after removing {}: {:?}",
+            dirs.0.display(),
+            seen.a
+        );
+
+        stop.store(true, Ordering::Relaxed);
+        for writer in writers {
+            writer.await.unwrap();
+        }
+        run_tx.send(false).unwrap();
+        crate::join_all_tasks(task_set)
+            .await
+            .expect("BPF worker failed");
+    }
+
+    #[tokio::test]
+    async fn test_delayed_paths() {
+        let monitored_path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        let dir = TempDir::new_in(&monitored_path).expect("Failed to create directory");
+
+        // No programs are attached until paths are configured
+        let mut reloader = Reloader::from(self_reporting());
+        let metrics = Metrics::new();
+        let (run_tx, run_rx) = watch::channel(true);
+        let (bpf, mut rx) = Bpf::new(
+            &reloader,
+            run_rx,
+            metrics.bpf_worker.clone(),
+            metrics.lineage_cache.clone(),
+        )
+        .expect("Failed to load BPF code");
+        assert!(bpf.links.is_empty());
+        let mut task_set = JoinSet::new();
+        bpf.start(&mut task_set);
+
+        let stop = Arc::new(AtomicBool::new(false));
+        let writer = spawn_writer(dir.path().to_path_buf(), stop.clone(), Arc::default());
+        let res = timeout(Duration::from_millis(500), rx.recv()).await;
+        assert!(res.is_err(), "Unexpected event: {res:#?}");
+
+        reloader.apply(config_for(vec![PathBuf::from(format!(
+            "{}/**/*",
+            dir.path().display()
+        ))]));
+
+        // Every event after the load is matched against the full paths
+        timeout(Duration::from_secs(5), async {
+            let mut received = 0;
+            while received < 20 {
+                let event = rx.recv().await.expect("BPF worker stopped");
+                let value = serde_json::to_value(&event).unwrap();
+                assert!(
+                    value["filter_state"].is_null(),
+                    "Event while loading paths: {event:#?}"
+                );
+                if event.get_filename().starts_with(dir.path()) {
+                    received += 1;
+                }
+            }
+        })
+        .await
+        .expect("Timed out waiting for events");
+        assert_eq!(metrics.bpf_worker.initializing_count(), 0);
+
+        stop.store(true, Ordering::Relaxed);
+        writer.await.unwrap();
+        run_tx.send(false).unwrap();
+        crate::join_all_tasks(task_set)
+            .await
+            .expect("BPF worker failed");
+    }
+
+    #[tokio::test]
+    async fn test_diagnostics() {
+        // Without paths, programs are loaded but not attached
+        let reloader = Reloader::from(FactConfig::default());
+        let metrics = Metrics::new();
+        let (_run_tx, run_rx) = watch::channel(true);
+        let (bpf, _rx) = Bpf::new(
+            &reloader,
+            run_rx,
+            metrics.bpf_worker.clone(),
+            metrics.lineage_cache.clone(),
+        )
+        .expect("Failed to load BPF code");
+        let snapshot = serde_json::to_value(bpf.diagnostics().snapshot()).unwrap();
+
+        let programs = snapshot["programs"].as_array().unwrap();
+        let file_open = programs
+            .iter()
+            .find(|p| p["name"] == "file_open")
+            .expect("file_open not reported");
+        assert_eq!(file_open["loaded"], true);
+        assert_eq!(file_open["attached"], false);
+        assert!(file_open.get("run_count").is_some(), "{file_open}");
+
+        let maps = snapshot["maps"].as_array().unwrap();
+        let map = |name: &str| {
+            maps.iter()
+                .find(|m| m["name"] == name)
+                .unwrap_or_else(|| panic!("{name} not reported: {snapshot}"))
+        };
+        let bpf_config = &reloader.config().bpf;
+        assert_eq!(map("inode_map")["entries"], 0);
+        assert_eq!(map("inode_map")["max_entries"], bpf_config.inodes_max());
+        assert_eq!(map("path_prefix")["entries"], 0);
+        assert!(map("rb")["entries"].is_null());
+        assert!(
+            maps.iter()
+                .all(|m| !m["name"].as_str().unwrap().starts_with('.'))
+        );
+        assert_eq!(
+            snapshot["ringbuf_size"],
+            u64::from(bpf_config.ringbuf_size()) * 1024
+        );
+    }
+
+    #[tokio::test]
+    async fn test_ringbuf_fallback() {
+        // A 2GB ringbuffer is more than most test machines can spare,
+        // loading still succeeds with whatever size the kernel allows.
+        let config = FactConfig::try_from("bpf:\n  ringbuf_size: 2GB").unwrap();
+        let reloader = Reloader::from(config);
+        let metrics = Metrics::new();
+        let (_run_tx, run_rx) = watch::channel(true);
+        let (bpf, _rx) = Bpf::new(
+            &reloader,
+            run_rx,
+            metrics.bpf_worker.clone(),
+            metrics.lineage_cache.clone(),
+        )
+        .expect("Failed to load BPF code");
+
+        let size = bpf.ringbuf_size();
+        assert!(size.is_power_of_two(), "{size}");
+        assert!((64 * 1024..=2 << 30).contains(&size), "{size}");
+        let snapshot = serde_json::to_value(bpf.diagnostics().snapshot()).unwrap();
+        assert_eq!(snapshot["ringbuf_size"], size);
+        assert_eq!(snapshot["ringbuf_requested_size"], 2u64 << 30);
+    }
+
     #[test]
     fn test_validate_config() {
         let tests = [
@@ -526,7 +1547,7 @@ mod bpf_tests {
 
         let btf = Btf::from_sys_fs().expect("Failed to read BTF symbols");
         let checks = Checks::new(&btf).expect("Failed to create `checks`");
-        let obj =
+        let (obj, _) =
             Bpf::load_ebpf(&checks, &BpfConfig::default()).expect("Failed to load eBPF object");
 
         for (programs, expected) in tests {
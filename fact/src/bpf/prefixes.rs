@@ -0,0 +1,185 @@
+//! Updates of the path prefixes loaded in the kernel.
+//!
+//! The prefixes in the `path_prefix` LPM trie are replaced all or
+//! nothing: if the kernel rejects one of the new prefixes, the map is
+//! put back the way it was, so it keeps matching the previous
+//! configuration instead of a mix of both.
+
+use anyhow::Context;
+use aya::maps::{LpmTrie, MapData, MapError};
+use fact_ebpf::{LPM_SIZE_MAX, path_prefix_t};
+use libc::c_char;
+use log::warn;
+
+/// A prefix along with the action taken on the paths under it.
+pub type Prefix = (path_prefix_t, c_char);
+
+/// The operations on the `path_prefix` map, replaced in tests.
+pub trait PrefixMap {
+    fn insert(&mut self, prefix: &path_prefix_t, action: c_char) -> Result<(), MapError>;
+    fn remove(&mut self, prefix: &path_prefix_t) -> Result<(), MapError>;
+}
+
+impl PrefixMap for LpmTrie<&mut MapData, [c_char; LPM_SIZE_MAX as usize], c_char> {
+    fn insert(&mut self, prefix: &path_prefix_t, action: c_char) -> Result<(), MapError> {
+        LpmTrie::insert(self, &(*prefix).into(), action, 0)
+    }
+
+    fn remove(&mut self, prefix: &path_prefix_t) -> Result<(), MapError> {
+        LpmTrie::remove(self, &(*prefix).into())
+    }
+}
+
+/// Replace the prefixes in `map`, `old`, with `new`.
+///
+/// The new prefixes are inserted first, prefixes in both get their
+/// action updated in place so enforcement can be lifted without the
+/// path ever going unmonitored. Old prefixes are only removed once all
+/// the new ones are in. If an insert fails, the prefixes inserted so
+/// far are removed, or set back to their old action, and the error is
+/// returned.
+pub fn replace(map: &mut impl PrefixMap, old: &[Prefix], new: &[Prefix]) -> anyhow::Result<()> {
+    for (i, (prefix, action)) in new.iter().enumerate() {
+        if let Err(e) = map.insert(prefix, *action) {
+            rollback(map, old, &new[..i]);
+            return Err(e)
+                .with_context(|| format!("failed to add path prefix {} of {}", i + 1, new.len()));
+        }
+    }
+
+    for (prefix, _) in old.iter().filter(|(p, _)| !contains(new, p)) {
+        if let Err(e) = map.remove(prefix) {
+            warn!("Failed to remove path prefix: {e:#?}");
+        }
+    }
+    Ok(())
+}
+
+/// Undo the insertion of `inserted` on top of `old`.
+fn rollback(map: &mut impl PrefixMap, old: &[Prefix], inserted: &[Prefix]) {
+    for (prefix, _) in inserted {
+        let res = match old.iter().find(|(p, _)| p == prefix) {
+            Some((_, action)) => map.insert(prefix, *action),
+            None => map.remove(prefix),
+        };
+        if let Err(e) = res {
+            warn!("Failed to roll back path prefix: {e:#?}");
+        }
+    }
+}
+
+fn contains(prefixes: &[Prefix], prefix: &path_prefix_t) -> bool {
+    prefixes.iter().any(|(p, _)| p == prefix)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::BTreeMap, io, path::PathBuf};
+
+    use fact_ebpf::path_prefix_action_t;
+
+    use super::*;
+
+    const MONITOR: c_char = path_prefix_action_t::PATH_PREFIX_MONITOR.0 as c_char;
+    const ENFORCE: c_char = path_prefix_action_t::PATH_PREFIX_ENFORCE.0 as c_char;
+
+    /// A map holding its prefixes by path, failing the insert number
+    /// `fail_at`, counting from 0.
+    #[derive(Default)]
+    struct MockMap {
+        entries: BTreeMap<String, c_char>,
+        inserts: usize,
+        fail_at: Option<usize>,
+    }
+
+    fn key(prefix: &path_prefix_t) -> String {
+        let len = prefix.path.iter().position(|c| *c == 0).unwrap();
+        let bytes = prefix.path[..len].iter().map(|c| *c as u8).collect();
+        String::from_utf8(bytes).unwrap()
+    }
+
+    impl PrefixMap for MockMap {
+        fn insert(&mut self, prefix: &path_prefix_t, action: c_char) -> Result<(), MapError> {
+            self.inserts += 1;
+            if self.fail_at == Some(self.inserts - 1) {
+                return Err(MapError::SyscallError(aya::sys::SyscallError {
+                    call: "bpf_map_update_elem",
+                    io_error: io::Error::from_raw_os_error(libc::E2BIG),
+                }));
+            }
+            self.entries.insert(key(prefix), action);
+            Ok(())
+        }
+
+        fn remove(&mut self, prefix: &path_prefix_t) -> Result<(), MapError> {
+            self.entries.remove(&key(prefix));
+            Ok(())
+        }
+    }
+
+    fn prefixes(paths: &[(&str, c_char)]) -> Vec<Prefix> {
+        paths
+            .iter()
+            .map(|(path, action)| {
+                let prefix = path_prefix_t::try_from(&PathBuf::from(path)).unwrap();
+                (prefix, *action)
+            })
+            .collect()
+    }
+
+    fn entries(paths: &[(&str, c_char)]) -> BTreeMap<String, c_char> {
+        paths
+            .iter()
+            .map(|(path, action)| (path.to_string(), *action))
+            .collect()
+    }
+
+    #[test]
+    fn replaced() {
+        let old = [("/etc", MONITOR), ("/usr/bin", ENFORCE)];
+        let new = [
+            ("/etc", ENFORCE),
+            ("/usr/bin", MONITOR),
+            ("/var/lib", MONITOR),
+        ];
+        let mut map = MockMap::default();
+        replace(&mut map, &[], &prefixes(&old)).unwrap();
+        assert_eq!(map.entries, entries(&old));
+
+        replace(&mut map, &prefixes(&old), &prefixes(&new)).unwrap();
+        assert_eq!(map.entries, entries(&new));
+
+        replace(&mut map, &prefixes(&new), &prefixes(&[("/opt", MONITOR)])).unwrap();
+        assert_eq!(map.entries, entries(&[("/opt", MONITOR)]));
+    }
+
+    #[test]
+    fn rolled_back() {
+        let old = [("/etc", MONITOR), ("/usr/bin", ENFORCE), ("/opt", MONITOR)];
+        let new = [
+            ("/etc", ENFORCE),
+            ("/usr/bin", MONITOR),
+            ("/var/lib", MONITOR),
+            ("/srv", MONITOR),
+        ];
+
+        // Failing on any insert leaves the old prefixes, with their
+        // old actions
+        for fail_at in 0..new.len() {
+            let mut map = MockMap::default();
+            replace(&mut map, &[], &prefixes(&old)).unwrap();
+            map.fail_at = Some(map.inserts + fail_at);
+
+            let err = replace(&mut map, &prefixes(&old), &prefixes(&new)).unwrap_err();
+            assert_eq!(
+                err.to_string(),
+                format!("failed to add path prefix {} of 4", fail_at + 1)
+            );
+            assert_eq!(map.entries, entries(&old), "failed at {fail_at}");
+
+            // And the next update starts from them
+            replace(&mut map, &prefixes(&old), &prefixes(&new)).unwrap();
+            assert_eq!(map.entries, entries(&new), "failed at {fail_at}");
+        }
+    }
+}
@@ -0,0 +1,544 @@
+//! Changes between two configurations, for logging what a reload did.
+//!
+//! Fields are compared as configured, a field going from unset to its
+//! default value is a change. Values are described with their `Debug`
+//! output, which redacts the secrets the configuration holds, like the
+//! control token or proxy credentials.
+
+use std::{collections::BTreeSet, fmt};
+
+use super::{
+    BackoffConfig, BpfConfig, EndpointConfig, EnrichConfig, FactConfig, GrpcConfig,
+    MaintenanceConfig, OTelConfig, OutputConfig, ReadinessConfig, TracesConfig,
+};
+use crate::coverage::Support;
+
+/// A field that differs between two configurations.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ChangeDescription {
+    /// The value of the field changed, `None` when unset.
+    Value {
+        field: String,
+        old: Option<String>,
+        new: Option<String>,
+    },
+    /// Items were added to or removed from a list.
+    List {
+        field: String,
+        added: Vec<String>,
+        removed: Vec<String>,
+    },
+}
+
+impl ChangeDescription {
+    /// Name of the field, with the sections it is in, like
+    /// `endpoint.address`.
+    pub fn field(&self) -> &str {
+        match self {
+            ChangeDescription::Value { field, .. } | ChangeDescription::List { field, .. } => field,
+        }
+    }
+}
+
+impl fmt::Display for ChangeDescription {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ChangeDescription::Value { field, old, new } => {
+                let unset = || "unset".to_owned();
+                write!(
+                    f,
+                    "{field}: {} -> {}",
+                    old.clone().unwrap_or_else(unset),
+                    new.clone().unwrap_or_else(unset)
+                )
+            }
+            ChangeDescription::List {
+                field,
+                added,
+                removed,
+            } => {
+                write!(f, "{field}:")?;
+                if !added.is_empty() {
+                    write!(f, " added {}", added.join(", "))?;
+                }
+                if !removed.is_empty() {
+                    if !added.is_empty() {
+                        f.write_str(",")?;
+                    }
+                    write!(f, " removed {}", removed.join(", "))?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+/// Collects the changes of the fields it is given.
+struct Diff {
+    section: String,
+    changes: Vec<ChangeDescription>,
+}
+
+impl Diff {
+    fn name(&self, field: &str) -> String {
+        if self.section.is_empty() {
+            field.to_owned()
+        } else {
+            format!("{}.{field}", self.section)
+        }
+    }
+
+    fn value<T: fmt::Debug + PartialEq>(&mut self, field: &str, old: &Option<T>, new: &Option<T>) {
+        if old != new {
+            self.changes.push(ChangeDescription::Value {
+                field: self.name(field),
+                old: old.as_ref().map(|v| format!("{v:?}")),
+                new: new.as_ref().map(|v| format!("{v:?}")),
+            });
+        }
+    }
+
+    /// Compare lists by their items. Lists with the same items in a
+    /// different order are reported as a change of value, since the
+    /// order of filters and rules matters.
+    fn list<T: fmt::Debug + PartialEq>(
+        &mut self,
+        field: &str,
+        old: &Option<Vec<T>>,
+        new: &Option<Vec<T>>,
+    ) {
+        let (old_items, new_items) = (old.as_deref().unwrap_or(&[]), new.as_deref().unwrap_or(&[]));
+        let added = items_missing(new_items, old_items);
+        let removed = items_missing(old_items, new_items);
+        if !added.is_empty() || !removed.is_empty() {
+            self.changes.push(ChangeDescription::List {
+                field: self.name(field),
+                added,
+                removed,
+            });
+        } else if old != new {
+            self.value(field, old, new);
+        }
+    }
+
+    /// Compare the fields of a section, their names are prefixed by
+    /// the one of the section.
+    fn section(&mut self, name: &str, diff: impl FnOnce(&mut Diff)) {
+        let mut section = Diff {
+            section: self.name(name),
+            changes: Vec::new(),
+        };
+        diff(&mut section);
+        self.changes.append(&mut section.changes);
+    }
+}
+
+/// Items of `items` that `other` does not have.
+fn items_missing<T: fmt::Debug + PartialEq>(items: &[T], other: &[T]) -> Vec<String> {
+    items
+        .iter()
+        .filter(|item| !other.contains(item))
+        .map(|item| format!("{item:?}"))
+        .collect()
+}
+
+impl FactConfig {
+    /// Fields changed from `self` to `other`, in the order they are
+    /// declared.
+    pub fn diff(&self, other: &FactConfig) -> Vec<ChangeDescription> {
+        let mut diff = Diff {
+            section: String::new(),
+            changes: Vec::new(),
+        };
+        diff.list("paths", &self.paths, &other.paths);
+        diff.value("paths_file", &self.paths_file, &other.paths_file);
+        diff.list("paths_file.paths", &self.file_paths, &other.file_paths);
+        diff.section("grpc", |d| self.grpc.diff(&other.grpc, d));
+        diff.section("otel", |d| self.otel.diff(&other.otel, d));
+        diff.section("endpoint", |d| self.endpoint.diff(&other.endpoint, d));
+        diff.section("readiness", |d| self.readiness.diff(&other.readiness, d));
+        diff.section("maintenance", |d| {
+            self.maintenance.diff(&other.maintenance, d)
+        });
+        diff.section("enrich", |d| self.enrich.diff(&other.enrich, d));
+        diff.section("output", |d| self.output.diff(&other.output, d));
+        diff.section("bpf", |d| self.bpf.diff(&other.bpf, d));
+        diff.value(
+            "skip_pre_flight",
+            &self.skip_pre_flight,
+            &other.skip_pre_flight,
+        );
+        diff.value("json", &self.json, &other.json);
+        diff.value("hotreload", &self.hotreload, &other.hotreload);
+        diff.value("scan_interval", &self.scan_interval, &other.scan_interval);
+        diff.value("rate_limit", &self.rate_limit, &other.rate_limit);
+        diff.value("replay", &self.replay, &other.replay);
+        diff.section("replay_options", |d| {
+            let (old, new) = (&self.replay_options, &other.replay_options);
+            d.value("pace", &old.pace, &new.pace);
+            d.value(
+                "rewrite_timestamps",
+                &old.rewrite_timestamps,
+                &new.rewrite_timestamps,
+            );
+            d.value("hostname", &old.hostname, &new.hostname);
+        });
+        diff.value(
+            "checkpoint_restore_window",
+            &self.checkpoint_restore_window,
+            &other.checkpoint_restore_window,
+        );
+        diff.value(
+            "reorder_window",
+            &self.reorder_window,
+            &other.reorder_window,
+        );
+        diff.value(
+            "summary_interval",
+            &self.summary_interval,
+            &other.summary_interval,
+        );
+        diff.value("summary_only", &self.summary_only, &other.summary_only);
+        diff.value("inventory", &self.inventory, &other.inventory);
+        diff.value(
+            "inventory_limit",
+            &self.inventory_limit,
+            &other.inventory_limit,
+        );
+        diff.value("generate", &self.generate, &other.generate);
+        diff.section("generate_options", |d| {
+            let (old, new) = (&self.generate_options, &other.generate_options);
+            d.value("rate", &old.rate, &new.rate);
+            d.value("paths", &old.paths, &new.paths);
+            d.value("processes", &old.processes, &new.processes);
+            d.value("containers", &old.containers, &new.containers);
+            d.value("host_pct", &old.host_pct, &new.host_pct);
+            d.value("raw", &old.raw, &new.raw);
+        });
+        diff.value("limits", &self.limits, &other.limits);
+        diff.value("run_for", &self.run_for, &other.run_for);
+        diff.value("max_events", &self.max_events, &other.max_events);
+        diff.value(
+            "top_containers",
+            &self.top_containers,
+            &other.top_containers,
+        );
+        diff.list(
+            "protected_paths",
+            &self.protected_paths,
+            &other.protected_paths,
+        );
+        diff.value(
+            "enforcement_enabled",
+            &self.enforcement_enabled,
+            &other.enforcement_enabled,
+        );
+        diff.list("path_labels", &self.path_labels, &other.path_labels);
+        diff.list("sampling", &self.sampling, &other.sampling);
+        diff.list("filters", &self.filters, &other.filters);
+        diff.list("redact_args", &self.redact_args, &other.redact_args);
+        diff.value("lock_file", &self.lock_file, &other.lock_file);
+        diff.value(
+            "allow_multiple",
+            &self.allow_multiple,
+            &other.allow_multiple,
+        );
+        diff.value("force_lock", &self.force_lock, &other.force_lock);
+        diff.value("node_id_file", &self.node_id_file, &other.node_id_file);
+        diff.value("host_mount", &self.host_mount, &other.host_mount);
+        diff.value("strict_config", &self.strict_config, &other.strict_config);
+        diff.changes
+    }
+}
+
+impl GrpcConfig {
+    fn diff(&self, other: &GrpcConfig, diff: &mut Diff) {
+        diff.list("url", &self.url, &other.url);
+        diff.value("certs", &self.certs, &other.certs);
+        diff.value(
+            "key_passphrase_file",
+            &self.key_passphrase_file,
+            &other.key_passphrase_file,
+        );
+        diff.value("token_file", &self.token_file, &other.token_file);
+        diff.value("cluster_id", &self.cluster_id, &other.cluster_id);
+        diff.value("compression", &self.compression, &other.compression);
+        diff.value("proxy", &self.proxy, &other.proxy);
+        diff.value(
+            "cert_expiry_warning",
+            &self.cert_expiry_warning,
+            &other.cert_expiry_warning,
+        );
+        diff.section("backoff", |d| self.backoff.diff(&other.backoff, d));
+    }
+}
+
+impl BackoffConfig {
+    fn diff(&self, other: &BackoffConfig, diff: &mut Diff) {
+        diff.value("initial", &self.initial, &other.initial);
+        diff.value("max", &self.max, &other.max);
+        diff.value("jitter", &self.jitter, &other.jitter);
+        diff.value("multiplier", &self.multiplier, &other.multiplier);
+        diff.value("retries_max", &self.retries_max, &other.retries_max);
+    }
+}
+
+impl OTelConfig {
+    fn diff(&self, other: &OTelConfig, diff: &mut Diff) {
+        diff.value("endpoint", &self.endpoint, &other.endpoint);
+        diff.section("traces", |d| self.traces.diff(&other.traces, d));
+    }
+}
+
+impl TracesConfig {
+    fn diff(&self, other: &TracesConfig, diff: &mut Diff) {
+        diff.value("endpoint", &self.endpoint, &other.endpoint);
+        diff.value("sample_ratio", &self.sample_ratio, &other.sample_ratio);
+    }
+}
+
+impl EndpointConfig {
+    fn diff(&self, other: &EndpointConfig, diff: &mut Diff) {
+        diff.list("address", &self.address, &other.address);
+        diff.value(
+            "metrics_address",
+            &self.metrics_address,
+            &other.metrics_address,
+        );
+        diff.value(
+            "health_address",
+            &self.health_address,
+            &other.health_address,
+        );
+        diff.value(
+            "expose_metrics",
+            &self.expose_metrics,
+            &other.expose_metrics,
+        );
+        diff.value("health_check", &self.health_check, &other.health_check);
+        diff.value("control_token", &self.control_token, &other.control_token);
+    }
+}
+
+impl ReadinessConfig {
+    fn diff(&self, other: &ReadinessConfig, diff: &mut Diff) {
+        diff.value(
+            "drop_threshold",
+            &self.drop_threshold,
+            &other.drop_threshold,
+        );
+        diff.value("interval", &self.interval, &other.interval);
+        diff.value(
+            "degraded_after",
+            &self.degraded_after,
+            &other.degraded_after,
+        );
+        diff.value("recover_after", &self.recover_after, &other.recover_after);
+        diff.value(
+            "fail_on_degraded",
+            &self.fail_on_degraded,
+            &other.fail_on_degraded,
+        );
+        diff.value(
+            "fail_on_paused",
+            &self.fail_on_paused,
+            &other.fail_on_paused,
+        );
+    }
+}
+
+impl MaintenanceConfig {
+    fn diff(&self, other: &MaintenanceConfig, diff: &mut Diff) {
+        diff.value(
+            "cpu_budget_pct",
+            &self.cpu_budget_pct,
+            &other.cpu_budget_pct,
+        );
+    }
+}
+
+impl EnrichConfig {
+    fn diff(&self, other: &EnrichConfig, diff: &mut Diff) {
+        diff.value(
+            "existence_check",
+            &self.existence_check,
+            &other.existence_check,
+        );
+    }
+}
+
+impl OutputConfig {
+    fn diff(&self, other: &OutputConfig, diff: &mut Diff) {
+        diff.value("stdout", &self.stdout, &other.stdout);
+        diff.value("stdout_format", &self.stdout_format, &other.stdout_format);
+        diff.value(
+            "overflow_policy",
+            &self.overflow_policy,
+            &other.overflow_policy,
+        );
+        diff.value("shards", &self.shards, &other.shards);
+        diff.value("shard_depth", &self.shard_depth, &other.shard_depth);
+    }
+}
+
+impl BpfConfig {
+    fn diff(&self, other: &BpfConfig, diff: &mut Diff) {
+        diff.value("ringbuf_size", &self.ringbuf_size, &other.ringbuf_size);
+        diff.value(
+            "ringbuf_min_size",
+            &self.ringbuf_min_size,
+            &other.ringbuf_min_size,
+        );
+        diff.value("inodes_max", &self.inodes_max, &other.inodes_max);
+        diff.value("collect_args", &self.collect_args, &other.collect_args);
+        diff.value("report_self", &self.report_self, &other.report_self);
+
+        let programs = self
+            .programs
+            .keys()
+            .chain(other.programs.keys())
+            .collect::<BTreeSet<_>>();
+        for program in programs {
+            let old = self.programs.get(program).and_then(|p| p.enabled);
+            let new = other.programs.get(program).and_then(|p| p.enabled);
+            diff.value(&format!("programs.{program}.enabled"), &old, &new);
+        }
+
+        let filesystems = self
+            .filesystems
+            .keys()
+            .chain(other.filesystems.keys())
+            .collect::<BTreeSet<_>>();
+        for fs in filesystems {
+            let old = self.filesystems.get(fs).map(Support::as_str);
+            let new = other.filesystems.get(fs).map(Support::as_str);
+            diff.value(&format!("filesystems.{fs}"), &old, &new);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn diff(old: &str, new: &str) -> Vec<String> {
+        let old = FactConfig::try_from(old).unwrap();
+        let new = FactConfig::try_from(new).unwrap();
+        old.diff(&new).iter().map(|c| c.to_string()).collect()
+    }
+
+    #[test]
+    fn values() {
+        let tests: &[(&str, &str, &[&str], &str)] = &[
+            ("", "", &[], "Empty"),
+            (
+                "hotreload: false\nrate_limit: 10",
+                "hotreload: false\nrate_limit: 10",
+                &[],
+                "Unchanged",
+            ),
+            ("", "rate_limit: 10", &["rate_limit: unset -> 10"], "Set"),
+            ("rate_limit: 10", "", &["rate_limit: 10 -> unset"], "Unset"),
+            (
+                "scan_interval: 30",
+                "scan_interval: 1m",
+                &["scan_interval: 30s -> 60s"],
+                "Changed",
+            ),
+            (
+                "json: true\nhotreload: true",
+                "json: false\nhotreload: false",
+                &["json: true -> false", "hotreload: true -> false"],
+                "Several",
+            ),
+        ];
+
+        for (old, new, expected, description) in tests {
+            assert_eq!(diff(old, new), *expected, "Failed for {description}");
+        }
+    }
+
+    #[test]
+    fn sections() {
+        let tests: &[(&str, &str, &[&str], &str)] = &[
+            (
+                "endpoint:\n  address: 0.0.0.0:9000\n  expose_metrics: true",
+                "endpoint:\n  address: 127.0.0.1:9000\n  expose_metrics: true",
+                &["endpoint.address: added 127.0.0.1:9000, removed 0.0.0.0:9000"],
+                "Section",
+            ),
+            (
+                "grpc:\n  backoff:\n    initial: 1",
+                "grpc:\n  backoff:\n    initial: 2\n    jitter: false",
+                &[
+                    "grpc.backoff.initial: 1s -> 2s",
+                    "grpc.backoff.jitter: unset -> false",
+                ],
+                "Nested section",
+            ),
+            (
+                "bpf:\n  programs:\n    trace_file_open:\n      enabled: false",
+                "bpf:\n  programs:\n    trace_path_unlink:\n      enabled: false",
+                &[
+                    "bpf.programs.trace_file_open.enabled: false -> unset",
+                    "bpf.programs.trace_path_unlink.enabled: unset -> false",
+                ],
+                "Programs",
+            ),
+            (
+                "bpf:\n  filesystems:\n    nfs: partial\n    fuse: unsupported",
+                "bpf:\n  filesystems:\n    nfs: unsupported",
+                &[
+                    "bpf.filesystems.fuse: \"unsupported\" -> unset",
+                    "bpf.filesystems.nfs: \"partial\" -> \"unsupported\"",
+                ],
+                "Filesystems",
+            ),
+            (
+                "endpoint:\n  control_token: first-secret-token",
+                "endpoint:\n  control_token: second-secret-token",
+                &["endpoint.control_token: ControlToken(<redacted>) -> ControlToken(<redacted>)"],
+                "Secret",
+            ),
+        ];
+
+        for (old, new, expected, description) in tests {
+            assert_eq!(diff(old, new), *expected, "Failed for {description}");
+        }
+    }
+
+    #[test]
+    fn lists() {
+        let tests: &[(&str, &str, &[&str], &str)] = &[
+            (
+                "paths: [/etc, /usr/bin]",
+                "paths: [/etc, /usr/sbin]",
+                &[r#"paths: added "/usr/sbin", removed "/usr/bin""#],
+                "Added and removed",
+            ),
+            (
+                "",
+                "paths: [/etc]",
+                &[r#"paths: added "/etc""#],
+                "From unset",
+            ),
+            (
+                "grpc:\n  url: [https://a:443, https://b:443]",
+                "grpc:\n  url: [https://a:443]",
+                &[r#"grpc.url: removed "https://b:443""#],
+                "Removed",
+            ),
+            (
+                "paths: [/etc, /usr/bin]",
+                "paths: [/usr/bin, /etc]",
+                &[r#"paths: ["/etc", "/usr/bin"] -> ["/usr/bin", "/etc"]"#],
+                "Reordered",
+            ),
+            ("paths: []", "", &[r#"paths: [] -> unset"#], "Emptied"),
+        ];
+
+        for (old, new, expected, description) in tests {
+            assert_eq!(diff(old, new), *expected, "Failed for {description}");
+        }
+    }
+}
@@ -0,0 +1,243 @@
+//! Configuration from environment variables.
+//!
+//! Every field of the configuration files can be set with a variable
+//! named after its section and name, like `FACT_GRPC_URL` for `url` in
+//! the `grpc` section. Fields of the `bpf` section have no section in
+//! their name, like `FACT_RINGBUF_SIZE`. Values are parsed the way they
+//! would be in a configuration file, lists and structured fields take
+//! YAML flow syntax, e.g. `FACT_PROTECTED_PATHS='[{path: /etc/shadow}]'`.
+//!
+//! Settings are applied in this order, later ones taking precedence:
+//! configuration files, environment variables, command line arguments.
+//! Variables bound to a command line argument, like `FACT_URL`, are
+//! read along with the arguments, where an argument given on the
+//! command line still takes precedence over its variable.
+
+use anyhow::Context;
+use clap::CommandFactory;
+use yaml_rust2::{Yaml, YamlLoader, yaml};
+
+use super::{
+    FactCli, FactConfig,
+    schema::{FIELDS, Type},
+};
+
+/// Source of environment variables, replaced in tests.
+pub type Env<'a> = &'a dyn Fn(&str) -> Option<String>;
+
+pub fn system_env(name: &str) -> Option<String> {
+    std::env::var(name).ok()
+}
+
+/// How the value of a variable turns into YAML.
+#[derive(Debug, Clone, Copy)]
+enum Kind {
+    /// Always a string, even if it looks like a number or boolean.
+    Str,
+    /// A YAML scalar, like a boolean, a number or a duration.
+    Scalar,
+    /// A list of strings separated by the character, or in YAML flow
+    /// syntax.
+    List(char),
+    /// Any YAML value, for structured fields.
+    Yaml,
+}
+
+impl From<Type> for Kind {
+    fn from(ty: Type) -> Self {
+        match ty {
+            Type::Str | Type::Enum(_) => Kind::Str,
+            Type::Bool
+            | Type::Int { .. }
+            | Type::Number { .. }
+            | Type::Ratio
+            | Type::Duration { .. }
+            | Type::RingbufSize => Kind::Scalar,
+            Type::List(sep)
+            | Type::Structured {
+                separator: Some(sep),
+                ..
+            } => Kind::List(sep),
+            Type::Structured {
+                separator: None, ..
+            } => Kind::Yaml,
+        }
+    }
+}
+
+/// Name of the variable for the field at `path`.
+fn var_name(path: &[&str]) -> String {
+    let path = match path {
+        ["bpf", rest @ ..] => rest,
+        path => path,
+    };
+    format!("FACT_{}", path.join("_").to_uppercase())
+}
+
+/// Variables bound to command line arguments.
+pub fn cli_vars() -> Vec<String> {
+    FactCli::command()
+        .get_arguments()
+        .filter_map(|arg| arg.get_env())
+        .map(|name| name.to_string_lossy().into_owned())
+        .collect()
+}
+
+fn parse_value(value: &str, kind: Kind) -> anyhow::Result<Yaml> {
+    let parse = |value: &str| -> anyhow::Result<Yaml> {
+        let mut docs = YamlLoader::load_from_str(value)?;
+        Ok(docs.pop().unwrap_or(Yaml::Null))
+    };
+
+    let value = match kind {
+        Kind::Str => Yaml::String(value.to_owned()),
+        Kind::Scalar | Kind::Yaml => parse(value)?,
+        Kind::List(_) if value.trim_start().starts_with('[') => parse(value)?,
+        Kind::List(sep) => Yaml::Array(
+            value
+                .split(sep)
+                .filter(|v| !v.is_empty())
+                .map(|v| Yaml::String(v.to_owned()))
+                .collect(),
+        ),
+    };
+    Ok(value)
+}
+
+/// Nest `value` in the sections of `path`, the same as in a
+/// configuration file.
+fn nest(path: &[&str], value: Yaml) -> Yaml {
+    path.iter().rev().fold(value, |value, key| {
+        let mut hash = yaml::Hash::new();
+        hash.insert(Yaml::String(key.to_string()), value);
+        Yaml::Hash(hash)
+    })
+}
+
+/// Build the configuration set in the environment, skipping the
+/// variables in `skip`.
+pub fn from_env(env: Env, skip: &[String]) -> anyhow::Result<FactConfig> {
+    let mut config = FactConfig::default();
+    for field in FIELDS {
+        let (path, kind) = (field.path, Kind::from(field.ty));
+        let name = var_name(path);
+        if skip.contains(&name) {
+            continue;
+        }
+        let Some(value) = env(&name) else {
+            continue;
+        };
+
+        let value = parse_value(&value, kind).with_context(|| format!("invalid {name}"))?;
+        let fragment = FactConfig::try_from(vec![nest(path, value)])
+            .with_context(|| format!("invalid {name}"))?;
+        config.update(&fragment);
+    }
+    Ok(config)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::HashMap, path::PathBuf, time::Duration};
+
+    use super::*;
+    use crate::config::{ProtectedPath, StdoutMode};
+
+    fn env(vars: &[(&str, &str)]) -> HashMap<String, String> {
+        vars.iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect()
+    }
+
+    fn config(vars: &[(&str, &str)]) -> anyhow::Result<FactConfig> {
+        let vars = env(vars);
+        from_env(&|name| vars.get(name).cloned(), &[])
+    }
+
+    #[test]
+    fn names() {
+        assert_eq!(var_name(&["paths"]), "FACT_PATHS");
+        assert_eq!(var_name(&["grpc", "url"]), "FACT_GRPC_URL");
+        assert_eq!(
+            var_name(&["grpc", "backoff", "initial"]),
+            "FACT_GRPC_BACKOFF_INITIAL"
+        );
+        assert_eq!(var_name(&["bpf", "ringbuf_size"]), "FACT_RINGBUF_SIZE");
+    }
+
+    #[test]
+    fn nested_fields() {
+        let config = config(&[
+            ("FACT_GRPC_URL", "https://a:9090,https://b:9090"),
+            ("FACT_GRPC_BACKOFF_RETRIES", "5"),
+            ("FACT_ENDPOINT_EXPOSE_METRICS", "true"),
+            ("FACT_ENDPOINT_CONTROL_TOKEN", "1234"),
+            ("FACT_OUTPUT_STDOUT", "off"),
+            ("FACT_RINGBUF_SIZE", "16MB"),
+            ("FACT_READINESS_INTERVAL", "30s"),
+        ])
+        .unwrap();
+
+        assert_eq!(config.grpc.urls(), ["https://a:9090", "https://b:9090"]);
+        assert_eq!(config.grpc.backoff.retries(), 5);
+        assert!(config.endpoint.expose_metrics());
+        assert!(config.endpoint.control_token().is_some());
+        assert_eq!(config.stdout(), StdoutMode::Off);
+        assert_eq!(config.bpf.ringbuf_size(), 16 * 1024);
+        assert_eq!(config.readiness.interval(), Duration::from_secs(30));
+    }
+
+    #[test]
+    fn lists_and_structures() {
+        let config = config(&[
+            ("FACT_PATHS", "/etc:/usr/bin"),
+            (
+                "FACT_PROTECTED_PATHS",
+                "[{path: /etc/shadow, enforce: true}]",
+            ),
+            ("FACT_GRPC_URL", "[https://a:9090]"),
+        ])
+        .unwrap();
+
+        assert_eq!(
+            config.paths(),
+            [PathBuf::from("/etc"), PathBuf::from("/usr/bin")]
+        );
+        assert_eq!(
+            config.protected_paths(),
+            [ProtectedPath {
+                path: PathBuf::from("/etc/shadow"),
+                enforce: true,
+            }]
+        );
+        assert_eq!(config.grpc.urls(), ["https://a:9090"]);
+    }
+
+    #[test]
+    fn invalid_values() {
+        let tests = [
+            (
+                ("FACT_ENDPOINT_EXPOSE_METRICS", "yes"),
+                "invalid FACT_ENDPOINT_EXPOSE_METRICS: endpoint.expose_metrics field has incorrect type: String(\"yes\")",
+            ),
+            (
+                ("FACT_OUTPUT_STDOUT", "always"),
+                "invalid FACT_OUTPUT_STDOUT: invalid output.stdout: unknown mode \"always\", expected one of: auto, on, off",
+            ),
+        ];
+        for (var, expected) in tests {
+            let err = config(&[var]).unwrap_err();
+            assert_eq!(format!("{err:#}"), expected);
+        }
+    }
+
+    #[test]
+    fn skipped() {
+        let vars = env(&[("FACT_PATHS", "/etc"), ("FACT_GRPC_URL", "https://a:9090")]);
+        let config = from_env(&|name| vars.get(name).cloned(), &cli_vars()).unwrap();
+
+        // FACT_PATHS is read by clap
+        assert!(config.paths().is_empty());
+        assert_eq!(config.grpc.urls(), ["https://a:9090"]);
+    }
+}
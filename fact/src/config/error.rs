@@ -0,0 +1,267 @@
+use std::fmt;
+
+use serde::Serialize;
+use yaml_rust2::Yaml;
+
+/// An error in a configuration file.
+///
+/// The variants let tools tell the kind of error apart, they are
+/// serialized tagged with `kind`, along with the path of the field in
+/// the file. Values are kept as they were found in the YAML document.
+///
+/// Variants about a field keep the message shown to users apart from
+/// the structured fields, so the wording of each error stays the same
+/// regardless of how it is classified.
+#[derive(thiserror::Error, Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum ConfigError {
+    /// The file is not valid YAML.
+    #[error("{reason}")]
+    Syntax { reason: String },
+    #[error("YAML file contains multiple documents")]
+    MultipleDocuments,
+    /// The document is not a mapping.
+    #[error("Wrong configuration type")]
+    NotAMapping,
+    #[error("{message}")]
+    KeyNotString {
+        key: String,
+        #[serde(skip)]
+        message: String,
+    },
+    /// A field not known to this version of fact.
+    #[error("{message}")]
+    UnknownField {
+        path: String,
+        value: String,
+        #[serde(skip)]
+        message: String,
+    },
+    #[error("{message}")]
+    WrongType {
+        path: String,
+        expected: &'static str,
+        found: String,
+        #[serde(skip)]
+        message: String,
+    },
+    /// An entry of a list without one of its required fields.
+    #[error("{message}")]
+    MissingField {
+        path: String,
+        field: &'static str,
+        value: String,
+        #[serde(skip)]
+        message: String,
+    },
+    /// A field with the right type but a value fact can't use.
+    #[error("{message}")]
+    InvalidValue {
+        path: String,
+        reason: String,
+        #[serde(skip)]
+        message: String,
+    },
+}
+
+impl ConfigError {
+    pub(super) fn key_not_string(key: &Yaml) -> Self {
+        ConfigError::KeyNotString {
+            key: format!("{key:?}"),
+            message: format!("key is not string: {key:?}"),
+        }
+    }
+
+    pub(super) fn unknown_field(path: impl Into<String>, value: &Yaml) -> Self {
+        let path = path.into();
+        ConfigError::UnknownField {
+            message: format!("Invalid field '{path}' with value: {value:?}"),
+            path,
+            value: format!("{value:?}"),
+        }
+    }
+
+    /// `path` holds `found` instead of a value of the `expected` YAML
+    /// type, like `boolean` or `mapping`.
+    pub(super) fn wrong_type(
+        path: impl Into<String>,
+        expected: &'static str,
+        found: &Yaml,
+    ) -> Self {
+        let path = path.into();
+        ConfigError::WrongType {
+            message: format!("{path} field has incorrect type: {found:?}"),
+            path,
+            expected,
+            found: format!("{found:?}"),
+        }
+    }
+
+    pub(super) fn missing_field(
+        path: impl Into<String>,
+        field: &'static str,
+        entry: &impl fmt::Debug,
+    ) -> Self {
+        let path = path.into();
+        ConfigError::MissingField {
+            message: format!("{path} entry is missing the {field} field: {entry:?}"),
+            path,
+            field,
+            value: format!("{entry:?}"),
+        }
+    }
+
+    pub(super) fn invalid(path: impl Into<String>, reason: impl fmt::Display) -> Self {
+        let path = path.into();
+        ConfigError::InvalidValue {
+            message: format!("invalid {path}: {reason}"),
+            path,
+            reason: reason.to_string(),
+        }
+    }
+
+    /// Replace the message shown for the error, for fields whose errors
+    /// are worded differently from the rest.
+    pub(super) fn with_message(mut self, new: impl Into<String>) -> Self {
+        match &mut self {
+            ConfigError::KeyNotString { message, .. }
+            | ConfigError::UnknownField { message, .. }
+            | ConfigError::WrongType { message, .. }
+            | ConfigError::MissingField { message, .. }
+            | ConfigError::InvalidValue { message, .. } => *message = new.into(),
+            ConfigError::Syntax { .. }
+            | ConfigError::MultipleDocuments
+            | ConfigError::NotAMapping => {}
+        }
+        self
+    }
+
+    /// Make the path of the error relative to `section`, for errors
+    /// found by parsers that don't know where their values are.
+    pub(super) fn within(self, section: &str) -> Self {
+        let join = |path: String| format!("{section}.{path}");
+        let wrap = |message: String| format!("{section} parsing failed: {message}");
+        match self {
+            ConfigError::UnknownField {
+                path,
+                value,
+                message,
+            } => ConfigError::UnknownField {
+                path: join(path),
+                value,
+                message: wrap(message),
+            },
+            ConfigError::WrongType {
+                path,
+                expected,
+                found,
+                message,
+            } => ConfigError::WrongType {
+                path: join(path),
+                expected,
+                found,
+                message: wrap(message),
+            },
+            ConfigError::MissingField {
+                path,
+                field,
+                value,
+                message,
+            } => ConfigError::MissingField {
+                path: join(path),
+                field,
+                value,
+                message: wrap(message),
+            },
+            ConfigError::InvalidValue {
+                path,
+                reason,
+                message,
+            } => ConfigError::InvalidValue {
+                path: join(path),
+                reason,
+                message: wrap(message),
+            },
+            ConfigError::KeyNotString { key, message } => ConfigError::KeyNotString {
+                key,
+                message: wrap(message),
+            },
+            e => e,
+        }
+    }
+
+    /// Path of the field the error is about, if it is about one.
+    pub fn path(&self) -> Option<&str> {
+        match self {
+            ConfigError::Syntax { .. }
+            | ConfigError::MultipleDocuments
+            | ConfigError::NotAMapping
+            | ConfigError::KeyNotString { .. } => None,
+            ConfigError::UnknownField { path, .. }
+            | ConfigError::WrongType { path, .. }
+            | ConfigError::MissingField { path, .. }
+            | ConfigError::InvalidValue { path, .. } => Some(path),
+        }
+    }
+
+    /// Apply `f` to the text taken from the configuration, like values
+    /// and reasons, to remove secrets from it.
+    pub fn map_text(self, f: impl Fn(&str) -> String) -> Self {
+        match self {
+            ConfigError::Syntax { reason } => ConfigError::Syntax { reason: f(&reason) },
+            ConfigError::KeyNotString { key, message } => ConfigError::KeyNotString {
+                key,
+                message: f(&message),
+            },
+            ConfigError::UnknownField {
+                path,
+                value,
+                message,
+            } => ConfigError::UnknownField {
+                path,
+                value: f(&value),
+                message: f(&message),
+            },
+            ConfigError::WrongType {
+                path,
+                expected,
+                found,
+                message,
+            } => ConfigError::WrongType {
+                path,
+                expected,
+                found: f(&found),
+                message: f(&message),
+            },
+            ConfigError::MissingField {
+                path,
+                field,
+                value,
+                message,
+            } => ConfigError::MissingField {
+                path,
+                field,
+                value: f(&value),
+                message: f(&message),
+            },
+            ConfigError::InvalidValue {
+                path,
+                reason,
+                message,
+            } => ConfigError::InvalidValue {
+                path,
+                reason: f(&reason),
+                message: f(&message),
+            },
+            e @ (ConfigError::MultipleDocuments | ConfigError::NotAMapping) => e,
+        }
+    }
+}
+
+impl From<yaml_rust2::ScanError> for ConfigError {
+    fn from(value: yaml_rust2::ScanError) -> Self {
+        ConfigError::Syntax {
+            reason: value.to_string(),
+        }
+    }
+}
@@ -1,7 +1,11 @@
 use std::{
-    collections::HashMap,
-    fs::read_to_string,
-    net::SocketAddr,
+    cell::RefCell,
+    collections::{BTreeMap, HashMap},
+    fmt,
+    fs::{File, read_to_string},
+    hash::{DefaultHasher, Hash, Hasher},
+    io::ErrorKind,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
     path::{Path, PathBuf},
     str::FromStr,
     sync::LazyLock,
@@ -9,14 +13,37 @@ use std::{
 };
 
 use anyhow::{Context, bail};
-use clap::Parser;
-use log::info;
+use clap::{Args, Parser, Subcommand};
+use log::{info, warn};
+use prometheus_client::metrics::counter::Counter;
 use yaml_rust2::{Yaml, YamlLoader, yaml};
 
+use crate::{
+    coverage::Support,
+    event::{self, FileData, raw::HostContext},
+    filter::{Filter, FilterAction},
+    metrics::containers,
+    redact::RedactPattern,
+    secret::Source,
+    tls,
+};
+
+mod diff;
+mod env;
+mod error;
+pub(crate) mod paths;
 pub mod reloader;
+mod schema;
 #[cfg(test)]
 mod tests;
+mod units;
 
+pub use diff::ChangeDescription;
+pub use error::ConfigError;
+pub use units::{ByteSize, DurationValue};
+
+/// Configuration files in the order they are applied, settings in
+/// later files override the ones from earlier files.
 const CONFIG_FILES: [&str; 4] = [
     "/etc/stackrox/fact.yml",
     "/etc/stackrox/fact.yaml",
@@ -24,19 +51,257 @@ const CONFIG_FILES: [&str; 4] = [
     "fact.yaml",
 ];
 
-fn yaml_to_duration_secs(v: &Yaml) -> Option<Duration> {
-    v.as_f64()
-        .or_else(|| v.as_i64().map(|i| i as f64))
-        .filter(|s| s.is_finite() && *s >= 0.0)
-        .map(Duration::from_secs_f64)
+/// Unknown fields ignored in configuration files with `strict_config`
+/// disabled, exported as a metric.
+pub(crate) static UNKNOWN_FIELDS: LazyLock<Counter> = LazyLock::new(Counter::default);
+
+thread_local! {
+    /// Unknown fields found by the parser, only collected while
+    /// parsing with `parse_lenient`.
+    static FOUND_UNKNOWN_FIELDS: RefCell<Option<Vec<ConfigError>>> = const { RefCell::new(None) };
+}
+
+/// Handle a field the parser does not know about.
+///
+/// Unknown fields are an error, unless parsing with `parse_lenient`,
+/// which collects them instead.
+fn unknown_field(name: &str, v: &Yaml) -> Result<(), ConfigError> {
+    let field = ConfigError::unknown_field(name, v);
+    FOUND_UNKNOWN_FIELDS.with_borrow_mut(|found| match found {
+        Some(found) => {
+            // Fields are checked against the schema before they are
+            // parsed, the parser finds the same fields again
+            if !found.contains(&field) {
+                found.push(field);
+            }
+            Ok(())
+        }
+        None => Err(field),
+    })
+}
+
+/// Parse a configuration file, skipping the fields not known to this
+/// version of fact and returning them along with the configuration.
+///
+/// Other errors, like fields with the wrong type, still fail parsing.
+fn parse_lenient(content: &str) -> anyhow::Result<(FactConfig, Vec<ConfigError>)> {
+    FOUND_UNKNOWN_FIELDS.set(Some(Vec::new()));
+    let config = FactConfig::try_from(content);
+    let found = FOUND_UNKNOWN_FIELDS.take().unwrap_or_default();
+    Ok((config?, found))
+}
+
+/// Apply the `strict_config` policy to the unknown fields found in the
+/// configuration files.
+///
+/// With a strict policy the first unknown field is an error, otherwise
+/// each one is logged and counted.
+fn check_unknown_fields(strict: bool, unknown: Vec<(PathBuf, ConfigError)>) -> anyhow::Result<()> {
+    if strict {
+        let Some((file, field)) = unknown.into_iter().next() else {
+            return Ok(());
+        };
+        return Err(field)
+            .with_context(|| format!("parsing error while processing {}", file.display()));
+    }
+
+    for (file, field) in unknown {
+        warn!(
+            "Ignoring unknown field '{}' in {}, set strict_config to fail on unknown fields",
+            field.path().unwrap_or_default(),
+            file.display()
+        );
+        UNKNOWN_FIELDS.inc();
+    }
+    Ok(())
+}
+
+/// Directory with configuration snippets, applied after the files in
+/// `CONFIG_FILES`.
+const CONFIG_DIR: &str = "/etc/stackrox/fact.d";
+
+/// Configuration snippets in `dir`, in the order they are applied.
+///
+/// Files ending in .yml or .yaml are applied in lexical order, other
+/// files are ignored. A missing directory has no snippets.
+fn drop_in_files(dir: &Path) -> Vec<PathBuf> {
+    let entries = match dir.read_dir() {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Vec::new(),
+        Err(e) => {
+            warn!("Failed to read {}: {e}", dir.display());
+            return Vec::new();
+        }
+    };
+
+    let mut files = entries
+        .filter_map(|entry| entry.ok().map(|e| e.path()))
+        .filter(|p| {
+            let ext = p.extension().and_then(|e| e.to_str());
+            matches!(ext, Some("yml" | "yaml")) && p.is_file()
+        })
+        .collect::<Vec<_>>();
+    files.sort();
+    files
+}
+
+/// All the configuration files to apply, in order: the existing files
+/// in `candidates` followed by the snippets in `dir`.
+fn config_paths(candidates: &[&str], dir: &Path) -> Vec<PathBuf> {
+    applied_config_files(candidates)
+        .into_iter()
+        .map(PathBuf::from)
+        .chain(drop_in_files(dir))
+        .collect()
+}
+
+/// Parse and merge `files`, in order.
+///
+/// Unknown fields are returned along with the file they are in, they
+/// can only be checked once all settings are applied.
+fn load_files(files: &[PathBuf]) -> anyhow::Result<(FactConfig, Vec<(PathBuf, ConfigError)>)> {
+    let mut config = FactConfig::default();
+    let mut unknown = Vec::new();
+    for p in files {
+        let content =
+            read_to_string(p).with_context(|| format!("Failed to read {}", p.display()))?;
+        let (other, fields) = parse_lenient(&content)
+            .with_context(|| format!("parsing error while processing {}", p.display()))?;
+        config.update(&other);
+        unknown.extend(fields.into_iter().map(|f| (p.clone(), f)));
+    }
+    Ok((config, unknown))
+}
+
+/// Go through the configuration files in `candidates`, in order, along
+/// with whether they exist.
+fn config_files<'a>(candidates: &[&'a str]) -> impl Iterator<Item = (&'a str, bool)> {
+    candidates
+        .iter()
+        .map(|file| (*file, Path::new(file).exists()))
+}
+
+/// Find files with the same name but a different extension in the
+/// same directory, like fact.yml and fact.yaml.
+///
+/// Returns pairs of files with the one that takes precedence last.
+fn config_file_conflicts<'a>(files: &[&'a str]) -> Vec<(&'a str, &'a str)> {
+    let key = |file: &str| {
+        let p = Path::new(file);
+        (
+            p.parent().map(Path::to_path_buf),
+            p.file_stem().map(|s| s.to_owned()),
+        )
+    };
+
+    let mut conflicts = Vec::new();
+    for (i, first) in files.iter().enumerate() {
+        for second in &files[i + 1..] {
+            if key(first) == key(second) {
+                conflicts.push((*first, *second));
+            }
+        }
+    }
+    conflicts
+}
+
+/// Existing configuration files in `candidates`, in the order they
+/// are applied.
+///
+/// A warning is logged for files conflicting with each other, since it
+/// is easy to miss which one wins.
+fn applied_config_files<'a>(candidates: &[&'a str]) -> Vec<&'a str> {
+    let files = config_files(candidates)
+        .filter_map(|(file, exists)| exists.then_some(file))
+        .collect::<Vec<_>>();
+    for (overridden, winner) in config_file_conflicts(&files) {
+        warn!(
+            "Both {overridden} and {winner} exist, settings in {winner} take precedence over the ones in {overridden}"
+        );
+    }
+    files
+}
+
+/// JSON Schema of the configuration files, as printed by
+/// `fact config schema`.
+pub fn schema_json() -> anyhow::Result<String> {
+    serde_json::to_string_pretty(&schema::json_schema())
+        .context("Failed to serialize the configuration schema")
+}
+
+/// Describe the configuration files fact loads and the snippets
+/// applied after them, as printed by `--print-config-files`.
+pub fn describe_files() -> String {
+    describe_config_files(&CONFIG_FILES) + &describe_drop_in_files(Path::new(CONFIG_DIR))
+}
+
+/// Describe the search order for the configuration files in
+/// `candidates`.
+fn describe_config_files(candidates: &[&str]) -> String {
+    let mut out =
+        String::from("Configuration files, later files override settings from earlier ones:\n");
+    for (file, exists) in config_files(candidates) {
+        let status = if exists { "exists" } else { "missing" };
+        out.push_str(&format!("  {file} ({status})\n"));
+    }
+    out
+}
+
+/// Describe the snippets in `dir`.
+fn describe_drop_in_files(dir: &Path) -> String {
+    let mut out = format!(
+        "Snippets in {}, applied after the files above in lexical order:\n",
+        dir.display()
+    );
+    let files = drop_in_files(dir);
+    if files.is_empty() {
+        out.push_str("  (none)\n");
+    }
+    for file in files {
+        out.push_str(&format!("  {}\n", file.display()));
+    }
+    out
+}
+
+fn yaml_to_duration(name: &str, v: &Yaml) -> Result<Duration, ConfigError> {
+    match DurationValue::try_from(v) {
+        Ok(d) => Ok(d.into()),
+        Err(e) => Err(ConfigError::invalid(name, e)),
+    }
+}
+
+fn yaml_to_positive_duration(name: &str, v: &Yaml) -> Result<Duration, ConfigError> {
+    let d = yaml_to_duration(name, v)?;
+    if d.is_zero() {
+        return Err(ConfigError::invalid(
+            name,
+            format_args!("{v:?}, must be greater than zero"),
+        ));
+    }
+    Ok(d)
+}
+
+fn yaml_to_socket_addr(name: &str, v: &Yaml) -> Result<SocketAddr, ConfigError> {
+    let Some(addr) = v.as_str() else {
+        return Err(ConfigError::wrong_type(name, "string", v));
+    };
+    SocketAddr::from_str(addr).map_err(|e| ConfigError::invalid(name, e))
 }
 
 #[derive(Debug, Default, PartialEq, Clone)]
 pub struct FactConfig {
     paths: Option<Vec<PathBuf>>,
+    paths_file: Option<PathBuf>,
+    /// Paths read from `paths_file` by the last build, kept to reuse
+    /// them when the file can no longer be read.
+    file_paths: Option<Vec<PathBuf>>,
     pub grpc: GrpcConfig,
     pub otel: OTelConfig,
     pub endpoint: EndpointConfig,
+    pub readiness: ReadinessConfig,
+    pub maintenance: MaintenanceConfig,
+    pub enrich: EnrichConfig,
+    pub output: OutputConfig,
     pub bpf: BpfConfig,
     skip_pre_flight: Option<bool>,
     json: Option<bool>,
@@ -44,51 +309,96 @@ pub struct FactConfig {
     scan_interval: Option<Duration>,
     rate_limit: Option<u64>,
     replay: Option<PathBuf>,
+    replay_options: ReplayOptions,
+    checkpoint_restore_window: Option<Duration>,
+    reorder_window: Option<Duration>,
+    summary_interval: Option<Duration>,
+    summary_only: Option<bool>,
+    inventory: Option<bool>,
+    inventory_limit: Option<u64>,
+    generate: Option<bool>,
+    generate_options: GenerateOptions,
+    limits: Option<LimitsFormat>,
+    run_for: Option<Duration>,
+    max_events: Option<u64>,
+    top_containers: Option<u64>,
+    protected_paths: Option<Vec<ProtectedPath>>,
+    enforcement_enabled: Option<bool>,
+    path_labels: Option<Vec<PathLabels>>,
+    sampling: Option<Vec<SamplingRule>>,
+    filters: Option<Vec<Filter>>,
+    redact_args: Option<Vec<RedactPattern>>,
+    lock_file: Option<PathBuf>,
+    allow_multiple: Option<bool>,
+    force_lock: Option<bool>,
+    node_id_file: Option<PathBuf>,
+    host_mount: Option<PathBuf>,
+    strict_config: Option<bool>,
 }
 
 impl FactConfig {
     pub fn new() -> anyhow::Result<Self> {
-        let config = FactConfig::build()?;
+        let config = FactConfig::build(None)?;
         info!("{config:#?}");
         Ok(config)
     }
 
-    fn build() -> anyhow::Result<FactConfig> {
-        let mut config = CONFIG_FILES
-            .iter()
-            .filter_map(|p| {
-                let p = Path::new(p);
-                if p.exists() { Some(p) } else { None }
-            })
-            .map(|p| {
-                let content =
-                    read_to_string(p).with_context(|| format!("Failed to read {}", p.display()))?;
-                FactConfig::try_from(content.as_str())
-                    .with_context(|| format!("parsing error while processing {}", p.display()))
-            })
-            .try_fold(
-                FactConfig::default(),
-                |mut config: FactConfig, other: anyhow::Result<FactConfig>| {
-                    config.update(&other?);
-                    Ok::<FactConfig, anyhow::Error>(config)
-                },
-            )?;
-
-        // Once file configuration is handled, apply CLI arguments
+    /// Build the configuration from the files, the environment and the
+    /// command line.
+    ///
+    /// `previous` is the configuration currently in use, its paths read
+    /// from `paths_file` are kept if the file can't be read anymore.
+    fn build(previous: Option<&FactConfig>) -> anyhow::Result<FactConfig> {
+        let files = config_paths(&CONFIG_FILES, Path::new(CONFIG_DIR));
+        if files.is_empty() {
+            info!("No configuration files found");
+        } else {
+            let names = files.iter().map(|f| f.display().to_string());
+            info!(
+                "Applying configuration files in order: {}",
+                names.collect::<Vec<_>>().join(", ")
+            );
+        }
+        let (mut config, unknown) = load_files(&files)?;
+
+        // Once file configuration is handled, apply environment
+        // variables and then CLI arguments. Variables bound to CLI
+        // arguments are read by clap along with them, so each variable
+        // is only read once.
+        static ENV: LazyLock<anyhow::Result<FactConfig>> =
+            LazyLock::new(|| env::from_env(&env::system_env, &env::cli_vars()));
         static CLI_ARGS: LazyLock<FactConfig> = LazyLock::new(|| FactCli::parse().into_config());
+        match &*ENV {
+            Ok(env) => config.update(env),
+            Err(e) => bail!("{e:#}"),
+        }
         config.update(&CLI_ARGS);
+        check_unknown_fields(config.strict_config(), unknown)?;
+        config.grpc.validate()?;
+        config.load_paths_file(previous)?;
+        config.canonicalize_paths()?;
 
         Ok(config)
     }
 
     pub fn update(&mut self, from: &FactConfig) {
+        // Labels are set along with the paths they belong to
         if let Some(paths) = from.paths.as_deref() {
             self.paths = Some(paths.to_owned());
+            self.path_labels = from.path_labels.clone();
+        }
+
+        if let Some(paths_file) = from.paths_file.as_deref() {
+            self.paths_file = Some(paths_file.to_owned());
         }
 
         self.grpc.update(&from.grpc);
         self.otel.update(&from.otel);
         self.endpoint.update(&from.endpoint);
+        self.readiness.update(&from.readiness);
+        self.maintenance.update(&from.maintenance);
+        self.enrich.update(&from.enrich);
+        self.output.update(&from.output);
         self.bpf.update(&from.bpf);
 
         if let Some(skip_pre_flight) = from.skip_pre_flight {
@@ -114,18 +424,132 @@ impl FactConfig {
         if let Some(replay) = from.replay.as_deref() {
             self.replay = Some(replay.to_path_buf());
         }
+
+        self.replay_options.update(&from.replay_options);
+
+        if let Some(window) = from.checkpoint_restore_window {
+            self.checkpoint_restore_window = Some(window);
+        }
+
+        if let Some(window) = from.reorder_window {
+            self.reorder_window = Some(window);
+        }
+
+        if let Some(summary_interval) = from.summary_interval {
+            self.summary_interval = Some(summary_interval);
+        }
+
+        if let Some(summary_only) = from.summary_only {
+            self.summary_only = Some(summary_only);
+        }
+
+        if let Some(inventory) = from.inventory {
+            self.inventory = Some(inventory);
+        }
+
+        if let Some(limit) = from.inventory_limit {
+            self.inventory_limit = Some(limit);
+        }
+
+        if let Some(generate) = from.generate {
+            self.generate = Some(generate);
+        }
+
+        self.generate_options.update(&from.generate_options);
+
+        if let Some(limits) = from.limits {
+            self.limits = Some(limits);
+        }
+
+        if let Some(run_for) = from.run_for {
+            self.run_for = Some(run_for);
+        }
+
+        if let Some(max_events) = from.max_events {
+            self.max_events = Some(max_events);
+        }
+
+        if let Some(top_containers) = from.top_containers {
+            self.top_containers = Some(top_containers);
+        }
+
+        if let Some(protected_paths) = from.protected_paths.as_deref() {
+            self.protected_paths = Some(protected_paths.to_owned());
+        }
+
+        if let Some(enforcement_enabled) = from.enforcement_enabled {
+            self.enforcement_enabled = Some(enforcement_enabled);
+        }
+
+        if let Some(sampling) = from.sampling.as_deref() {
+            self.sampling = Some(sampling.to_owned());
+        }
+
+        if let Some(filters) = from.filters.as_deref() {
+            self.filters = Some(filters.to_owned());
+        }
+
+        if let Some(redact_args) = from.redact_args.as_deref() {
+            self.redact_args = Some(redact_args.to_owned());
+        }
+
+        if let Some(lock_file) = from.lock_file.as_deref() {
+            self.lock_file = Some(lock_file.to_owned());
+        }
+
+        if let Some(allow_multiple) = from.allow_multiple {
+            self.allow_multiple = Some(allow_multiple);
+        }
+
+        if let Some(node_id_file) = from.node_id_file.as_deref() {
+            self.node_id_file = Some(node_id_file.to_owned());
+        }
+
+        if let Some(host_mount) = from.host_mount.as_deref() {
+            self.host_mount = Some(host_mount.to_owned());
+        }
+
+        if let Some(force_lock) = from.force_lock {
+            self.force_lock = Some(force_lock);
+        }
+
+        if let Some(strict_config) = from.strict_config {
+            self.strict_config = Some(strict_config);
+        }
     }
 
     pub fn paths(&self) -> &[PathBuf] {
         self.paths.as_ref().map(|v| v.as_ref()).unwrap_or(&[])
     }
 
+    /// File with more paths to monitor, one per line.
+    pub fn paths_file(&self) -> Option<&Path> {
+        self.paths_file.as_deref()
+    }
+
+    /// Labels configured for the monitored paths.
+    ///
+    /// Empty if no path has labels, otherwise paths without labels are
+    /// included too, since they can be a longer match for a file than a
+    /// labeled path.
+    pub fn path_labels(&self) -> &[PathLabels] {
+        self.path_labels.as_deref().unwrap_or(&[])
+    }
+
     pub fn skip_pre_flight(&self) -> bool {
         self.skip_pre_flight.unwrap_or(false)
     }
 
-    pub fn json(&self) -> bool {
-        self.json.unwrap_or(false)
+    /// When to write events to stdout.
+    ///
+    /// `output.stdout` takes precedence over the deprecated `json`,
+    /// which is an alias for `on` when set to true.
+    pub fn stdout(&self) -> StdoutMode {
+        match (self.output.stdout, self.json) {
+            (Some(stdout), _) => stdout,
+            (None, Some(true)) => StdoutMode::On,
+            (None, _) => StdoutMode::Auto,
+        }
     }
 
     pub fn hotreload(&self) -> bool {
@@ -144,14 +568,234 @@ impl FactConfig {
         self.replay.as_deref()
     }
 
+    pub fn replay_options(&self) -> &ReplayOptions {
+        &self.replay_options
+    }
+
+    pub fn checkpoint_restore_window(&self) -> Duration {
+        self.checkpoint_restore_window.unwrap_or(Duration::ZERO)
+    }
+
+    pub fn reorder_window(&self) -> Duration {
+        self.reorder_window.unwrap_or(Duration::ZERO)
+    }
+
+    /// Settings for the periodic summaries of the events.
+    pub fn summary(&self) -> SummaryConfig {
+        SummaryConfig {
+            interval: self.summary_interval.unwrap_or(Duration::ZERO),
+            only: self.summary_only.unwrap_or(false),
+        }
+    }
+
+    /// Whether a one-shot inventory of the monitored paths was
+    /// requested with the `scan` subcommand.
+    pub fn inventory(&self) -> bool {
+        self.inventory.unwrap_or(false)
+    }
+
+    /// Maximum number of entries reported by the inventory scan.
+    pub fn inventory_limit(&self) -> Option<u64> {
+        self.inventory_limit
+    }
+
+    /// Whether synthetic events were requested with the `generate`
+    /// subcommand.
+    pub fn generate(&self) -> bool {
+        self.generate.unwrap_or(false)
+    }
+
+    pub fn generate_options(&self) -> &GenerateOptions {
+        &self.generate_options
+    }
+
+    /// Format to print limits in, if requested with the `limits`
+    /// subcommand.
+    pub fn limits(&self) -> Option<LimitsFormat> {
+        self.limits
+    }
+
+    /// Time after which fact shuts down on its own, `None` if it
+    /// should run until stopped.
+    pub fn run_for(&self) -> Option<Duration> {
+        self.run_for.filter(|d| !d.is_zero())
+    }
+
+    /// Number of events after which fact shuts down on its own, `None`
+    /// if there is no limit.
+    pub fn max_events(&self) -> Option<u64> {
+        self.max_events.filter(|n| *n != 0)
+    }
+
+    /// Number of containers whose events are counted, 0 disables the
+    /// counts. Only read at startup.
+    pub fn top_containers(&self) -> u64 {
+        self.top_containers
+            .unwrap_or(containers::DEFAULT_CAPACITY as u64)
+    }
+
+    pub fn protected_paths(&self) -> &[ProtectedPath] {
+        self.protected_paths.as_deref().unwrap_or(&[])
+    }
+
+    pub fn sampling(&self) -> &[SamplingRule] {
+        self.sampling.as_deref().unwrap_or(&[])
+    }
+
+    pub fn filters(&self) -> &[Filter] {
+        self.filters.as_deref().unwrap_or(&[])
+    }
+
+    /// Patterns replaced with `***` in the arguments of processes.
+    pub fn redact_args(&self) -> &[RedactPattern] {
+        self.redact_args.as_deref().unwrap_or(&[])
+    }
+
+    /// File locked by the instance of fact attached to the kernel, it
+    /// must be on a filesystem shared by all instances on the host.
+    pub fn lock_file(&self) -> &Path {
+        self.lock_file
+            .as_deref()
+            .unwrap_or(Path::new("/run/fact/fact.lock"))
+    }
+
+    /// Whether to keep running when another instance holds the lock,
+    /// tagging events with the ID of this instance.
+    pub fn allow_multiple(&self) -> bool {
+        self.allow_multiple.unwrap_or(false)
+    }
+
+    /// Whether to take the lock even if another instance holds it,
+    /// only set from the command line.
+    pub fn force_lock(&self) -> bool {
+        self.force_lock.unwrap_or(false)
+    }
+
+    /// File storing the ID of the node, kept across restarts. The path
+    /// is on the host, the host mount is prepended to it.
+    pub fn node_id_file(&self) -> &Path {
+        self.node_id_file
+            .as_deref()
+            .unwrap_or(Path::new("/var/lib/fact/node_id"))
+    }
+
+    /// Where the filesystem of the host is mounted in the container
+    /// fact runs in, the root when running on the host. Only read at
+    /// startup.
+    pub fn host_mount(&self) -> &Path {
+        self.host_mount.as_deref().unwrap_or(Path::new("/"))
+    }
+
+    /// Whether fields not known to this version of fact in the
+    /// configuration files are an error, instead of being ignored with
+    /// a warning.
+    pub fn strict_config(&self) -> bool {
+        self.strict_config.unwrap_or(true)
+    }
+
+    /// Short digest of the effective configuration, for telling apart
+    /// nodes running with different settings.
+    ///
+    /// It is only stable for a given build of fact.
+    pub fn digest(&self) -> String {
+        // BPF programs are kept in a HashMap, its order changes from
+        // one process to another
+        let mut config = self.clone();
+        let programs = std::mem::take(&mut config.bpf.programs)
+            .into_iter()
+            .collect::<BTreeMap<_, _>>();
+
+        let mut hasher = DefaultHasher::new();
+        format!("{config:?}{programs:?}").hash(&mut hasher);
+        format!("{:08x}", hasher.finish() >> 32)
+    }
+
+    /// Global switch for denying operations on protected paths, no
+    /// path is enforced unless this is set.
+    pub fn enforcement_enabled(&self) -> bool {
+        self.enforcement_enabled.unwrap_or(false)
+    }
+
+    /// The protected paths as they need to be applied to the kernel,
+    /// with `enforce` cleared on all of them if enforcement is not
+    /// globally enabled.
+    pub fn active_protected_paths(&self) -> Vec<ProtectedPath> {
+        let enforcement_enabled = self.enforcement_enabled();
+        self.protected_paths()
+            .iter()
+            .map(|p| ProtectedPath {
+                path: p.path.clone(),
+                enforce: p.enforce && enforcement_enabled,
+            })
+            .collect()
+    }
+
+    /// Read the paths in `paths_file`.
+    ///
+    /// If the file can't be read, the paths read from it by `previous`
+    /// are used with a warning, failing only if there are none.
+    fn load_paths_file(&mut self, previous: Option<&FactConfig>) -> anyhow::Result<()> {
+        let Some(file) = self.paths_file.as_deref() else {
+            return Ok(());
+        };
+
+        let e = match paths::read_file(file) {
+            Ok(paths) => {
+                self.file_paths = Some(paths);
+                return Ok(());
+            }
+            Err(e) => e,
+        };
+        let previous = previous
+            .filter(|p| p.paths_file() == Some(file))
+            .and_then(|p| p.file_paths.clone());
+        let Some(paths) = previous else {
+            return Err(e);
+        };
+        warn!("{e:#}, keeping the paths previously read from it");
+        self.file_paths = Some(paths);
+        Ok(())
+    }
+
+    /// Normalize the monitored paths along with the ones read from
+    /// `paths_file` and drop the redundant ones, see
+    /// [`paths::canonicalize`].
+    fn canonicalize_paths(&mut self) -> anyhow::Result<()> {
+        if self.paths.is_none() && self.file_paths.is_none() {
+            return Ok(());
+        }
+
+        let unlabeled = |path: &PathBuf| PathLabels {
+            path: path.clone(),
+            labels: BTreeMap::new(),
+        };
+        let mut paths = match self.path_labels.take() {
+            Some(path_labels) => path_labels,
+            None => self.paths().iter().map(unlabeled).collect(),
+        };
+        paths.extend(self.file_paths.iter().flatten().map(unlabeled));
+
+        let paths = paths::canonicalize(paths, self.host_mount())?;
+        self.paths = Some(paths.iter().map(|p| p.path.clone()).collect());
+        if paths.iter().any(|p| !p.labels.is_empty()) {
+            self.path_labels = Some(paths);
+        }
+        Ok(())
+    }
+
     #[cfg(test)]
     pub fn set_paths(&mut self, paths: Vec<PathBuf>) {
         self.paths = Some(paths);
     }
+
+    #[cfg(test)]
+    pub fn set_replay_options(&mut self, replay_options: ReplayOptions) {
+        self.replay_options = replay_options;
+    }
 }
 
 impl TryFrom<&str> for FactConfig {
-    type Error = anyhow::Error;
+    type Error = ConfigError;
 
     fn try_from(value: &str) -> Result<Self, Self::Error> {
         YamlLoader::load_from_str(value)?.try_into()
@@ -159,7 +803,7 @@ impl TryFrom<&str> for FactConfig {
 }
 
 impl TryFrom<Vec<Yaml>> for FactConfig {
-    type Error = anyhow::Error;
+    type Error = ConfigError;
 
     fn try_from(value: Vec<Yaml>) -> Result<Self, Self::Error> {
         if value.is_empty() {
@@ -168,7 +812,7 @@ impl TryFrom<Vec<Yaml>> for FactConfig {
         }
 
         if value.len() > 1 {
-            bail!("YAML file contains multiple documents");
+            return Err(ConfigError::MultipleDocuments);
         }
 
         let mut config = FactConfig::default();
@@ -178,28 +822,40 @@ impl TryFrom<Vec<Yaml>> for FactConfig {
         }
 
         let Some(value) = value.as_hash() else {
-            bail!("Wrong configuration type");
+            return Err(ConfigError::NotAMapping);
         };
+        schema::check_fields(&[], value)?;
 
         for (k, v) in value.iter() {
             let Some(k) = k.as_str() else {
-                bail!("key is not string: {k:?}")
+                return Err(ConfigError::key_not_string(k));
             };
 
             match k {
                 "paths" if v.is_array() => {
-                    let paths = v
-                        .as_vec()
-                        .unwrap()
-                        .iter()
-                        .map(|p| {
-                            let Some(p) = p.as_str() else {
-                                bail!("Path has invalid type: {p:?}");
-                            };
-                            Ok(PathBuf::from(p))
-                        })
-                        .collect::<anyhow::Result<_>>()?;
+                    let mut paths = Vec::new();
+                    let mut path_labels = Vec::new();
+                    for p in v.as_vec().unwrap() {
+                        if let Some(p) = p.as_str() {
+                            paths.push(PathBuf::from(p));
+                            path_labels.push(PathLabels {
+                                path: PathBuf::from(p),
+                                labels: BTreeMap::new(),
+                            });
+                            continue;
+                        }
+                        if !p.is_hash() {
+                            return Err(ConfigError::wrong_type("paths", "string or mapping", p)
+                                .with_message(format!("Path has invalid type: {p:?}")));
+                        }
+                        let p = PathLabels::try_from(p)?;
+                        paths.push(p.path.clone());
+                        path_labels.push(p);
+                    }
                     config.paths = Some(paths);
+                    if path_labels.iter().any(|p| !p.labels.is_empty()) {
+                        config.path_labels = Some(path_labels);
+                    }
                 }
                 "paths" if v.is_null() => {
                     config.paths = Some(Vec::new());
@@ -216,54 +872,216 @@ impl TryFrom<Vec<Yaml>> for FactConfig {
                     let endpoint = v.as_hash().unwrap();
                     config.endpoint = EndpointConfig::try_from(endpoint)?;
                 }
+                "readiness" if v.is_hash() => {
+                    let readiness = v.as_hash().unwrap();
+                    config.readiness = ReadinessConfig::try_from(readiness)?;
+                }
+                "maintenance" if v.is_hash() => {
+                    let maintenance = v.as_hash().unwrap();
+                    config.maintenance = MaintenanceConfig::try_from(maintenance)?;
+                }
+                "enrich" if v.is_hash() => {
+                    let enrich = v.as_hash().unwrap();
+                    config.enrich = EnrichConfig::try_from(enrich)?;
+                }
+                "output" if v.is_hash() => {
+                    let output = v.as_hash().unwrap();
+                    config.output = OutputConfig::try_from(output)?;
+                }
                 "skip_pre_flight" => {
                     let Some(spf) = v.as_bool() else {
-                        bail!("skip_pre_flight field has incorrect type: {v:?}");
+                        return Err(ConfigError::wrong_type("skip_pre_flight", "boolean", v));
                     };
                     config.skip_pre_flight = Some(spf);
                 }
                 "json" => {
                     let Some(json) = v.as_bool() else {
-                        bail!("json field has incorrect type: {v:?}");
+                        return Err(ConfigError::wrong_type("json", "boolean", v));
                     };
+                    warn!("json is deprecated, use output.stdout instead");
                     config.json = Some(json);
                 }
                 "bpf" => {
                     let Some(bpf) = v.as_hash() else {
-                        bail!("bpf section has incorrect type: {v:#?}");
+                        return Err(ConfigError::wrong_type("bpf", "mapping", v)
+                            .with_message(format!("bpf section has incorrect type: {v:#?}")));
                     };
                     config.bpf = BpfConfig::try_from(bpf)?;
                 }
                 "hotreload" => {
                     let Some(hotreload) = v.as_bool() else {
-                        bail!("hotreload field has incorrect type: {v:?}");
+                        return Err(ConfigError::wrong_type("hotreload", "boolean", v));
                     };
                     config.hotreload = Some(hotreload);
                 }
                 "scan_interval" => {
                     // scan_interval == 0 disables the scanner
-                    let Some(scan_interval) = yaml_to_duration_secs(v) else {
-                        bail!("invalid scan_interval: {v:?}");
-                    };
+                    let scan_interval = yaml_to_duration("scan_interval", v)?;
                     config.scan_interval = Some(scan_interval);
                 }
                 "rate_limit" => {
                     // rate_limit == 0 means unlimited (no throttling)
                     let Some(rate_limit) = v.as_i64() else {
-                        bail!("rate_limit field has incorrect type: {v:?}");
+                        return Err(ConfigError::wrong_type("rate_limit", "integer", v));
                     };
                     if rate_limit < 0 {
-                        bail!("invalid rate_limit: {rate_limit}");
+                        return Err(ConfigError::invalid("rate_limit", rate_limit));
                     }
                     config.rate_limit = Some(rate_limit as u64);
                 }
                 "replay" => {
                     let Some(replay) = v.as_str() else {
-                        bail!("replay field has incorrect type: {v:?}")
+                        return Err(ConfigError::wrong_type("replay", "string", v));
                     };
                     config.replay = Some(PathBuf::from(replay));
                 }
-                name => bail!("Invalid field '{name}' with value: {v:?}"),
+                "checkpoint_restore_window" => {
+                    // checkpoint_restore_window == 0 disables suppression
+                    let window = yaml_to_duration("checkpoint_restore_window", v)?;
+                    config.checkpoint_restore_window = Some(window);
+                }
+                "reorder_window" => {
+                    // reorder_window == 0 disables reordering
+                    let window = yaml_to_duration("reorder_window", v)?;
+                    config.reorder_window = Some(window);
+                }
+                "summary_interval" => {
+                    // summary_interval == 0 disables summaries
+                    let interval = yaml_to_duration("summary_interval", v)?;
+                    config.summary_interval = Some(interval);
+                }
+                "summary_only" => {
+                    let Some(summary_only) = v.as_bool() else {
+                        return Err(ConfigError::wrong_type("summary_only", "boolean", v));
+                    };
+                    config.summary_only = Some(summary_only);
+                }
+                "run_for" => {
+                    // run_for == 0 runs until stopped
+                    let run_for = yaml_to_duration("run_for", v)?;
+                    config.run_for = Some(run_for);
+                }
+                "max_events" => {
+                    // max_events == 0 means no limit
+                    let Some(max_events) = v.as_i64() else {
+                        return Err(ConfigError::wrong_type("max_events", "integer", v));
+                    };
+                    if max_events < 0 {
+                        return Err(ConfigError::invalid("max_events", max_events));
+                    }
+                    config.max_events = Some(max_events as u64);
+                }
+                "top_containers" => {
+                    // top_containers == 0 disables the counts
+                    let Some(top_containers) = v.as_i64() else {
+                        return Err(ConfigError::wrong_type("top_containers", "integer", v));
+                    };
+                    if top_containers < 0 {
+                        return Err(ConfigError::invalid("top_containers", top_containers));
+                    }
+                    config.top_containers = Some(top_containers as u64);
+                }
+                "protected_paths" if v.is_array() => {
+                    let protected_paths = v
+                        .as_vec()
+                        .unwrap()
+                        .iter()
+                        .map(ProtectedPath::try_from)
+                        .collect::<Result<_, ConfigError>>()?;
+                    config.protected_paths = Some(protected_paths);
+                }
+                "protected_paths" if v.is_null() => {
+                    config.protected_paths = Some(Vec::new());
+                }
+                "sampling" if v.is_array() => {
+                    let sampling = v
+                        .as_vec()
+                        .unwrap()
+                        .iter()
+                        .map(SamplingRule::try_from)
+                        .collect::<Result<_, ConfigError>>()?;
+                    config.sampling = Some(sampling);
+                }
+                "sampling" if v.is_null() => {
+                    config.sampling = Some(Vec::new());
+                }
+                "filters" if v.is_array() => {
+                    let filters = v
+                        .as_vec()
+                        .unwrap()
+                        .iter()
+                        .map(parse_filter)
+                        .collect::<Result<_, ConfigError>>()?;
+                    config.filters = Some(filters);
+                }
+                "filters" if v.is_null() => {
+                    config.filters = Some(Vec::new());
+                }
+                "redact_args" if v.is_array() => {
+                    let redact_args = v
+                        .as_vec()
+                        .unwrap()
+                        .iter()
+                        .map(parse_redact_pattern)
+                        .collect::<Result<_, ConfigError>>()?;
+                    config.redact_args = Some(redact_args);
+                }
+                "redact_args" if v.is_null() => {
+                    config.redact_args = Some(Vec::new());
+                }
+                "enforcement_enabled" => {
+                    let Some(enforcement_enabled) = v.as_bool() else {
+                        return Err(ConfigError::wrong_type("enforcement_enabled", "boolean", v));
+                    };
+                    config.enforcement_enabled = Some(enforcement_enabled);
+                }
+                "paths_file" => {
+                    let Some(paths_file) = v.as_str() else {
+                        return Err(ConfigError::wrong_type("paths_file", "string", v));
+                    };
+                    config.paths_file = Some(PathBuf::from(paths_file));
+                }
+                "lock_file" => {
+                    let Some(lock_file) = v.as_str() else {
+                        return Err(ConfigError::wrong_type("lock_file", "string", v));
+                    };
+                    config.lock_file = Some(PathBuf::from(lock_file));
+                }
+                "allow_multiple" => {
+                    let Some(allow_multiple) = v.as_bool() else {
+                        return Err(ConfigError::wrong_type("allow_multiple", "boolean", v));
+                    };
+                    config.allow_multiple = Some(allow_multiple);
+                }
+                "node_id_file" => {
+                    let Some(node_id_file) = v.as_str() else {
+                        return Err(ConfigError::wrong_type("node_id_file", "string", v));
+                    };
+                    config.node_id_file = Some(PathBuf::from(node_id_file));
+                }
+                "host_mount" => {
+                    let Some(host_mount) = v.as_str() else {
+                        return Err(ConfigError::wrong_type("host_mount", "string", v));
+                    };
+                    config.host_mount = Some(PathBuf::from(host_mount));
+                }
+                "strict_config" => {
+                    let Some(strict_config) = v.as_bool() else {
+                        return Err(ConfigError::wrong_type("strict_config", "boolean", v));
+                    };
+                    config.strict_config = Some(strict_config);
+                }
+                // Known fields with a type not matched above
+                "grpc" | "otel" | "endpoint" | "readiness" | "maintenance" | "enrich"
+                | "output" => {
+                    return Err(ConfigError::wrong_type(k, "mapping", v)
+                        .with_message(format!("Invalid field '{k}' with value: {v:?}")));
+                }
+                "paths" | "protected_paths" | "sampling" | "filters" | "redact_args" => {
+                    return Err(ConfigError::wrong_type(k, "sequence", v)
+                        .with_message(format!("Invalid field '{k}' with value: {v:?}")));
+                }
+                name => unknown_field(name, v)?,
             }
         }
 
@@ -271,17 +1089,45 @@ impl TryFrom<Vec<Yaml>> for FactConfig {
     }
 }
 
-#[derive(Debug, Default, PartialEq, Eq, Clone)]
-pub struct EndpointConfig {
-    address: Option<SocketAddr>,
-    expose_metrics: Option<bool>,
-    health_check: Option<bool>,
+/// Settings of the summary events, from `summary_interval` and
+/// `summary_only`.
+#[derive(Debug, Default, PartialEq, Eq, Clone, Copy)]
+pub struct SummaryConfig {
+    /// Period covered by each summary, no summaries are sent if zero.
+    pub interval: Duration,
+    /// Send the summaries only, dropping the events they count. Has no
+    /// effect while summaries are disabled.
+    pub only: bool,
 }
 
-impl EndpointConfig {
+impl SummaryConfig {
+    pub fn enabled(&self) -> bool {
+        !self.interval.is_zero()
+    }
+}
+
+#[derive(Debug, Default, PartialEq, Eq, Clone)]
+pub struct EndpointConfig {
+    address: Option<Vec<SocketAddr>>,
+    metrics_address: Option<SocketAddr>,
+    health_address: Option<SocketAddr>,
+    expose_metrics: Option<bool>,
+    health_check: Option<bool>,
+    control_token: Option<ControlToken>,
+}
+
+impl EndpointConfig {
     fn update(&mut self, from: &EndpointConfig) {
-        if let Some(address) = from.address {
-            self.address = Some(address);
+        if let Some(address) = from.address.as_deref() {
+            self.address = Some(address.to_owned());
+        }
+
+        if let Some(metrics_address) = from.metrics_address {
+            self.metrics_address = Some(metrics_address);
+        }
+
+        if let Some(health_address) = from.health_address {
+            self.health_address = Some(health_address);
         }
 
         if let Some(expose_metrics) = from.expose_metrics {
@@ -291,11 +1137,71 @@ impl EndpointConfig {
         if let Some(health_check) = from.health_check {
             self.health_check = Some(health_check);
         }
+
+        if let Some(control_token) = &from.control_token {
+            self.control_token = Some(control_token.clone());
+        }
+    }
+
+    /// Address used when none is configured, binds dual-stack when
+    /// IPv6 is available.
+    pub const DEFAULT_ADDRESS: SocketAddr =
+        SocketAddr::new(IpAddr::V6(Ipv6Addr::UNSPECIFIED), 9000);
+
+    /// Address used when IPv6 is not available on the system and no
+    /// address is configured.
+    pub const FALLBACK_ADDRESS: SocketAddr =
+        SocketAddr::new(IpAddr::V4(Ipv4Addr::UNSPECIFIED), 9000);
+
+    /// Addresses serving all the endpoints.
+    ///
+    /// The default address is only used when no address is configured,
+    /// including `metrics_address` and `health_address`.
+    pub fn addresses(&self) -> &[SocketAddr] {
+        match self.address.as_deref() {
+            Some(address) => address,
+            None if self.address_is_default() => &[EndpointConfig::DEFAULT_ADDRESS],
+            None => &[],
+        }
+    }
+
+    pub fn address_is_default(&self) -> bool {
+        self.address.is_none() && self.metrics_address.is_none() && self.health_address.is_none()
+    }
+
+    /// Address serving only the metrics and the status.
+    pub fn metrics_address(&self) -> Option<SocketAddr> {
+        self.metrics_address
+    }
+
+    /// Address serving only the health checks.
+    pub fn health_address(&self) -> Option<SocketAddr> {
+        self.health_address
     }
 
-    pub fn address(&self) -> SocketAddr {
-        self.address
-            .unwrap_or(SocketAddr::from(([0, 0, 0, 0], 9000)))
+    /// The addresses to listen on with the routes served on each.
+    ///
+    /// `metrics_address` and `health_address` are only listened on
+    /// while their endpoints are enabled. Routes of an address
+    /// configured several times are merged, so it is bound once.
+    pub fn listeners(&self) -> Vec<(SocketAddr, Routes)> {
+        let restricted = [
+            (self.metrics_address, self.expose_metrics(), Routes::METRICS),
+            (self.health_address, self.health_check(), Routes::HEALTH),
+        ];
+        let all = self.addresses().iter().map(|addr| (*addr, Routes::ALL));
+        let restricted = restricted
+            .into_iter()
+            .filter_map(|(addr, enabled, routes)| Some((addr.filter(|_| enabled)?, routes)));
+
+        let mut listeners: Vec<(SocketAddr, Routes)> = Vec::new();
+        for (addr, routes) in all.chain(restricted) {
+            match listeners.iter_mut().find(|(a, _)| *a == addr) {
+                Some((_, r)) => *r = r.union(routes),
+                None => listeners.push((addr, routes)),
+            }
+        }
+        listeners
     }
 
     pub fn expose_metrics(&self) -> bool {
@@ -305,42 +1211,181 @@ impl EndpointConfig {
     pub fn health_check(&self) -> bool {
         self.health_check.unwrap_or(false)
     }
+
+    /// Token required for the control endpoints, they are disabled
+    /// when no token is configured.
+    pub fn control_token(&self) -> Option<&ControlToken> {
+        self.control_token.as_ref()
+    }
+}
+
+/// The groups of endpoints served on an address.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Routes {
+    /// `/metrics` and `/status`.
+    pub metrics: bool,
+    /// `/health_check` and `/readyz`.
+    pub health: bool,
+    /// `/control/` and `/debug/`.
+    pub control: bool,
+}
+
+impl Routes {
+    pub const ALL: Routes = Routes {
+        metrics: true,
+        health: true,
+        control: true,
+    };
+
+    pub const METRICS: Routes = Routes {
+        metrics: true,
+        health: false,
+        control: false,
+    };
+
+    pub const HEALTH: Routes = Routes {
+        metrics: false,
+        health: true,
+        control: false,
+    };
+
+    fn union(self, other: Routes) -> Routes {
+        Routes {
+            metrics: self.metrics || other.metrics,
+            health: self.health || other.health,
+            control: self.control || other.control,
+        }
+    }
+
+    /// Whether the endpoint at `path` is served, paths that are not
+    /// endpoints belong with the control ones.
+    pub fn serves(&self, path: &str) -> bool {
+        match path {
+            "/metrics" | "/status" => self.metrics,
+            "/health_check" | "/readyz" => self.health,
+            _ => self.control,
+        }
+    }
+}
+
+impl fmt::Display for Routes {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let groups = [
+            ("metrics", self.metrics),
+            ("health", self.health),
+            ("control", self.control),
+        ];
+        let groups = groups
+            .iter()
+            .filter_map(|(name, enabled)| enabled.then_some(*name))
+            .collect::<Vec<_>>();
+        f.write_str(&groups.join(", "))
+    }
+}
+
+/// Bearer token authorizing requests to the control endpoints.
+///
+/// The token is never printed, so configurations can be logged safely.
+#[derive(Clone, PartialEq, Eq)]
+pub struct ControlToken(String);
+
+impl ControlToken {
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl fmt::Debug for ControlToken {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("ControlToken(<redacted>)")
+    }
+}
+
+impl FromStr for ControlToken {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.trim().is_empty() {
+            bail!("control token must not be empty");
+        }
+        Ok(ControlToken(s.to_owned()))
+    }
 }
 
 impl TryFrom<&yaml::Hash> for EndpointConfig {
-    type Error = anyhow::Error;
+    type Error = ConfigError;
 
     fn try_from(value: &yaml::Hash) -> Result<Self, Self::Error> {
         let mut endpoint = EndpointConfig::default();
         for (k, v) in value.iter() {
             let Some(k) = k.as_str() else {
-                bail!("key is not string: {k:?}");
+                return Err(ConfigError::key_not_string(k));
             };
 
             match k {
                 "address" => {
-                    let Some(addr) = v.as_str() else {
-                        bail!("endpoint.address field has incorrect type: {v:?}");
+                    let parse = |addr: &Yaml| {
+                        yaml_to_socket_addr("endpoint.address", addr).map_err(|e| match e {
+                            ConfigError::InvalidValue { ref reason, .. } => {
+                                let message = format!("Failed to parse endpoint.address: {reason}");
+                                e.with_message(message)
+                            }
+                            e => e,
+                        })
                     };
-                    let address = match SocketAddr::from_str(addr) {
-                        Ok(a) => a,
-                        Err(e) => bail!("Failed to parse endpoint.address: {e}"),
+                    let addresses = match v {
+                        Yaml::Array(addresses) => {
+                            addresses.iter().map(parse).collect::<Result<Vec<_>, _>>()?
+                        }
+                        v => vec![parse(v)?],
                     };
-                    endpoint.address = Some(address);
+                    if addresses.is_empty() {
+                        return Err(ConfigError::invalid("endpoint.address", "no addresses"));
+                    }
+                    endpoint.address = Some(addresses);
+                }
+                "metrics_address" => {
+                    let addr = yaml_to_socket_addr("endpoint.metrics_address", v)?;
+                    endpoint.metrics_address = Some(addr);
+                }
+                "health_address" => {
+                    let addr = yaml_to_socket_addr("endpoint.health_address", v)?;
+                    endpoint.health_address = Some(addr);
                 }
                 "expose_metrics" => {
                     let Some(em) = v.as_bool() else {
-                        bail!("endpoint.expose_metrics field has incorrect type: {v:?}");
+                        return Err(ConfigError::wrong_type(
+                            "endpoint.expose_metrics",
+                            "boolean",
+                            v,
+                        ));
                     };
                     endpoint.expose_metrics = Some(em);
                 }
                 "health_check" => {
                     let Some(hc) = v.as_bool() else {
-                        bail!("endpoint.health_check field has incorrect type: {v:?}");
+                        return Err(ConfigError::wrong_type(
+                            "endpoint.health_check",
+                            "boolean",
+                            v,
+                        ));
                     };
                     endpoint.health_check = Some(hc);
                 }
-                name => bail!("Invalid field 'endpoint.{name}' with value: {v:?}"),
+                "control_token" => {
+                    let Some(token) = v.as_str() else {
+                        return Err(ConfigError::wrong_type(
+                            "endpoint.control_token",
+                            "string",
+                            v,
+                        ));
+                    };
+                    match ControlToken::from_str(token) {
+                        Ok(token) => endpoint.control_token = Some(token),
+                        Err(e) => return Err(ConfigError::invalid("endpoint.control_token", e)),
+                    }
+                }
+                name => unknown_field(&format!("endpoint.{name}"), v)?,
             }
         }
 
@@ -348,6 +1393,460 @@ impl TryFrom<&yaml::Hash> for EndpointConfig {
     }
 }
 
+/// Policy for reporting degraded service on the readiness endpoint.
+///
+/// Output drops are sampled every `interval`, if more than
+/// `drop_threshold` events are dropped for `degraded_after` consecutive
+/// intervals, fact is considered degraded. It only goes back to ready
+/// after `recover_after` consecutive intervals under the threshold.
+#[derive(Debug, Default, PartialEq, Eq, Clone)]
+pub struct ReadinessConfig {
+    drop_threshold: Option<u64>,
+    interval: Option<Duration>,
+    degraded_after: Option<u64>,
+    recover_after: Option<u64>,
+    fail_on_degraded: Option<bool>,
+    fail_on_paused: Option<bool>,
+}
+
+impl ReadinessConfig {
+    fn update(&mut self, from: &ReadinessConfig) {
+        if let Some(drop_threshold) = from.drop_threshold {
+            self.drop_threshold = Some(drop_threshold);
+        }
+        if let Some(interval) = from.interval {
+            self.interval = Some(interval);
+        }
+        if let Some(degraded_after) = from.degraded_after {
+            self.degraded_after = Some(degraded_after);
+        }
+        if let Some(recover_after) = from.recover_after {
+            self.recover_after = Some(recover_after);
+        }
+        if let Some(fail_on_degraded) = from.fail_on_degraded {
+            self.fail_on_degraded = Some(fail_on_degraded);
+        }
+        if let Some(fail_on_paused) = from.fail_on_paused {
+            self.fail_on_paused = Some(fail_on_paused);
+        }
+    }
+
+    /// Maximum number of events dropped by the outputs in an interval
+    /// before it is considered degraded, 0 disables the policy.
+    pub fn drop_threshold(&self) -> u64 {
+        self.drop_threshold.unwrap_or(0)
+    }
+
+    pub fn interval(&self) -> Duration {
+        self.interval.unwrap_or(Duration::from_secs(10))
+    }
+
+    pub fn degraded_after(&self) -> u64 {
+        self.degraded_after.unwrap_or(3)
+    }
+
+    pub fn recover_after(&self) -> u64 {
+        self.recover_after.unwrap_or(3)
+    }
+
+    pub fn fail_on_degraded(&self) -> bool {
+        self.fail_on_degraded.unwrap_or(false)
+    }
+
+    /// Whether readiness fails while event collection is paused.
+    pub fn fail_on_paused(&self) -> bool {
+        self.fail_on_paused.unwrap_or(false)
+    }
+}
+
+impl TryFrom<&yaml::Hash> for ReadinessConfig {
+    type Error = ConfigError;
+
+    fn try_from(value: &yaml::Hash) -> Result<Self, Self::Error> {
+        let mut readiness = ReadinessConfig::default();
+        for (k, v) in value.iter() {
+            let Some(k) = k.as_str() else {
+                return Err(ConfigError::key_not_string(k));
+            };
+
+            match k {
+                "drop_threshold" => {
+                    let Some(drop_threshold) = v.as_i64() else {
+                        return Err(ConfigError::wrong_type(
+                            "readiness.drop_threshold",
+                            "integer",
+                            v,
+                        ));
+                    };
+                    if drop_threshold < 0 {
+                        return Err(ConfigError::invalid(
+                            "readiness.drop_threshold",
+                            drop_threshold,
+                        ));
+                    }
+                    readiness.drop_threshold = Some(drop_threshold as u64);
+                }
+                "interval" => {
+                    let interval = yaml_to_positive_duration("readiness.interval", v)?;
+                    readiness.interval = Some(interval);
+                }
+                "degraded_after" => {
+                    let Some(degraded_after) = v.as_i64() else {
+                        return Err(ConfigError::wrong_type(
+                            "readiness.degraded_after",
+                            "integer",
+                            v,
+                        ));
+                    };
+                    if degraded_after <= 0 {
+                        return Err(ConfigError::invalid(
+                            "readiness.degraded_after",
+                            degraded_after,
+                        ));
+                    }
+                    readiness.degraded_after = Some(degraded_after as u64);
+                }
+                "recover_after" => {
+                    let Some(recover_after) = v.as_i64() else {
+                        return Err(ConfigError::wrong_type(
+                            "readiness.recover_after",
+                            "integer",
+                            v,
+                        ));
+                    };
+                    if recover_after <= 0 {
+                        return Err(ConfigError::invalid(
+                            "readiness.recover_after",
+                            recover_after,
+                        ));
+                    }
+                    readiness.recover_after = Some(recover_after as u64);
+                }
+                "fail_on_degraded" => {
+                    let Some(fail_on_degraded) = v.as_bool() else {
+                        return Err(ConfigError::wrong_type(
+                            "readiness.fail_on_degraded",
+                            "boolean",
+                            v,
+                        ));
+                    };
+                    readiness.fail_on_degraded = Some(fail_on_degraded);
+                }
+                "fail_on_paused" => {
+                    let Some(fail_on_paused) = v.as_bool() else {
+                        return Err(ConfigError::wrong_type(
+                            "readiness.fail_on_paused",
+                            "boolean",
+                            v,
+                        ));
+                    };
+                    readiness.fail_on_paused = Some(fail_on_paused);
+                }
+                name => unknown_field(&format!("readiness.{name}"), v)?,
+            }
+        }
+
+        Ok(readiness)
+    }
+}
+
+/// Limits on the background maintenance work, like host rescans.
+///
+/// Maintenance loops share a budget of `cpu_budget_pct` percent of a
+/// core and sleep between batches of work when over it, see
+/// [`crate::pacer`].
+#[derive(Debug, Default, PartialEq, Eq, Clone)]
+pub struct MaintenanceConfig {
+    cpu_budget_pct: Option<u8>,
+}
+
+impl MaintenanceConfig {
+    fn update(&mut self, from: &MaintenanceConfig) {
+        if let Some(cpu_budget_pct) = from.cpu_budget_pct {
+            self.cpu_budget_pct = Some(cpu_budget_pct);
+        }
+    }
+
+    /// Percentage of a core maintenance work may use, 100 disables
+    /// pacing.
+    pub fn cpu_budget_pct(&self) -> u8 {
+        self.cpu_budget_pct.unwrap_or(100)
+    }
+}
+
+impl TryFrom<&yaml::Hash> for MaintenanceConfig {
+    type Error = ConfigError;
+
+    fn try_from(value: &yaml::Hash) -> Result<Self, Self::Error> {
+        let mut maintenance = MaintenanceConfig::default();
+        for (k, v) in value.iter() {
+            let Some(k) = k.as_str() else {
+                return Err(ConfigError::key_not_string(k));
+            };
+
+            match k {
+                "cpu_budget_pct" => {
+                    let Some(pct) = v.as_i64() else {
+                        return Err(ConfigError::wrong_type(
+                            "maintenance.cpu_budget_pct",
+                            "integer",
+                            v,
+                        ));
+                    };
+                    if !(1..=100).contains(&pct) {
+                        return Err(ConfigError::invalid(
+                            "maintenance.cpu_budget_pct",
+                            format_args!("{pct}, expected 1 to 100"),
+                        ));
+                    }
+                    maintenance.cpu_budget_pct = Some(pct as u8);
+                }
+                name => unknown_field(&format!("maintenance.{name}"), v)?,
+            }
+        }
+
+        Ok(maintenance)
+    }
+}
+
+/// Optional enrichment of events right before they are emitted.
+#[derive(Debug, Default, PartialEq, Eq, Clone)]
+pub struct EnrichConfig {
+    existence_check: Option<bool>,
+}
+
+impl EnrichConfig {
+    fn update(&mut self, from: &EnrichConfig) {
+        if let Some(existence_check) = from.existence_check {
+            self.existence_check = Some(existence_check);
+        }
+    }
+
+    /// Whether to check if the files of open and creation events still
+    /// exist when emitting them.
+    pub fn existence_check(&self) -> bool {
+        self.existence_check.unwrap_or(false)
+    }
+}
+
+impl TryFrom<&yaml::Hash> for EnrichConfig {
+    type Error = ConfigError;
+
+    fn try_from(value: &yaml::Hash) -> Result<Self, Self::Error> {
+        let mut enrich = EnrichConfig::default();
+        for (k, v) in value.iter() {
+            let Some(k) = k.as_str() else {
+                return Err(ConfigError::key_not_string(k));
+            };
+
+            match k {
+                "existence_check" => {
+                    let Some(existence_check) = v.as_bool() else {
+                        return Err(ConfigError::wrong_type(
+                            "enrich.existence_check",
+                            "boolean",
+                            v,
+                        ));
+                    };
+                    enrich.existence_check = Some(existence_check);
+                }
+                name => unknown_field(&format!("enrich.{name}"), v)?,
+            }
+        }
+
+        Ok(enrich)
+    }
+}
+
+/// When events are written to stdout.
+#[derive(Debug, Default, PartialEq, Eq, Clone, Copy)]
+pub enum StdoutMode {
+    /// Only when no other output is enabled at startup.
+    #[default]
+    Auto,
+    On,
+    Off,
+}
+
+impl FromStr for StdoutMode {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "auto" => Ok(StdoutMode::Auto),
+            "on" => Ok(StdoutMode::On),
+            "off" => Ok(StdoutMode::Off),
+            s => bail!("unknown mode {s:?}, expected one of: auto, on, off"),
+        }
+    }
+}
+
+/// How events are written to stdout.
+#[derive(Debug, Default, PartialEq, Eq, Clone, Copy)]
+pub enum StdoutFormat {
+    /// The serialized events, see `fact::events`.
+    #[default]
+    Fact,
+    /// Alerts in the JSON format of Falco, for pipelines parsing them.
+    Falco,
+}
+
+impl FromStr for StdoutFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "fact" => Ok(StdoutFormat::Fact),
+            "falco" => Ok(StdoutFormat::Falco),
+            s => bail!("unknown format {s:?}, expected one of: fact, falco"),
+        }
+    }
+}
+
+/// What the output component does when outputs can't keep up.
+#[derive(Debug, Default, PartialEq, Eq, Clone, Copy)]
+pub enum OverflowPolicy {
+    /// Outputs that fall behind miss events, the pipeline never slows
+    /// down.
+    #[default]
+    Drop,
+    /// Events wait for the slowest output. The pipeline stops reading
+    /// the ringbuffer, so events are lost in the kernel instead.
+    Block,
+}
+
+impl FromStr for OverflowPolicy {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "drop" => Ok(OverflowPolicy::Drop),
+            "block" => Ok(OverflowPolicy::Block),
+            s => bail!("unknown policy {s:?}, expected one of: drop, block"),
+        }
+    }
+}
+
+#[derive(Debug, Default, PartialEq, Eq, Clone)]
+pub struct OutputConfig {
+    stdout: Option<StdoutMode>,
+    stdout_format: Option<StdoutFormat>,
+    overflow_policy: Option<OverflowPolicy>,
+    shards: Option<u64>,
+    shard_depth: Option<u64>,
+}
+
+impl OutputConfig {
+    fn update(&mut self, from: &OutputConfig) {
+        if let Some(stdout) = from.stdout {
+            self.stdout = Some(stdout);
+        }
+        if let Some(stdout_format) = from.stdout_format {
+            self.stdout_format = Some(stdout_format);
+        }
+        if let Some(overflow_policy) = from.overflow_policy {
+            self.overflow_policy = Some(overflow_policy);
+        }
+        if let Some(shards) = from.shards {
+            self.shards = Some(shards);
+        }
+        if let Some(shard_depth) = from.shard_depth {
+            self.shard_depth = Some(shard_depth);
+        }
+    }
+
+    /// How events are written to stdout. Only read on startup.
+    pub fn stdout_format(&self) -> StdoutFormat {
+        self.stdout_format.unwrap_or_default()
+    }
+
+    /// What to do with events when outputs can't keep up. Only read on
+    /// startup.
+    pub fn overflow_policy(&self) -> OverflowPolicy {
+        self.overflow_policy.unwrap_or_default()
+    }
+
+    /// Channels events are spread over by container ID, 1 keeps a
+    /// single channel. Only read on startup.
+    pub fn shards(&self) -> usize {
+        self.shards.unwrap_or(1) as usize
+    }
+
+    /// Events each shard holds for the outputs. Only read on startup.
+    pub fn shard_depth(&self) -> usize {
+        self.shard_depth
+            .unwrap_or(crate::EVENT_CHANNEL_CAPACITY as u64) as usize
+    }
+}
+
+impl TryFrom<&yaml::Hash> for OutputConfig {
+    type Error = ConfigError;
+
+    fn try_from(value: &yaml::Hash) -> Result<Self, Self::Error> {
+        let mut output = OutputConfig::default();
+        for (k, v) in value.iter() {
+            let Some(k) = k.as_str() else {
+                return Err(ConfigError::key_not_string(k));
+            };
+
+            match k {
+                "stdout" => {
+                    let Some(stdout) = v.as_str() else {
+                        return Err(ConfigError::wrong_type("output.stdout", "string", v));
+                    };
+                    match StdoutMode::from_str(stdout) {
+                        Ok(stdout) => output.stdout = Some(stdout),
+                        Err(e) => return Err(ConfigError::invalid("output.stdout", e)),
+                    }
+                }
+                "stdout_format" => {
+                    let Some(format) = v.as_str() else {
+                        return Err(ConfigError::wrong_type("output.stdout_format", "string", v));
+                    };
+                    match StdoutFormat::from_str(format) {
+                        Ok(format) => output.stdout_format = Some(format),
+                        Err(e) => return Err(ConfigError::invalid("output.stdout_format", e)),
+                    }
+                }
+                "overflow_policy" => {
+                    let Some(policy) = v.as_str() else {
+                        return Err(ConfigError::wrong_type(
+                            "output.overflow_policy",
+                            "string",
+                            v,
+                        ));
+                    };
+                    match OverflowPolicy::from_str(policy) {
+                        Ok(policy) => output.overflow_policy = Some(policy),
+                        Err(e) => return Err(ConfigError::invalid("output.overflow_policy", e)),
+                    }
+                }
+                "shards" => {
+                    let Some(shards) = v.as_i64() else {
+                        return Err(ConfigError::wrong_type("output.shards", "integer", v));
+                    };
+                    if shards <= 0 {
+                        return Err(ConfigError::invalid("output.shards", shards));
+                    }
+                    output.shards = Some(shards as u64);
+                }
+                "shard_depth" => {
+                    let Some(shard_depth) = v.as_i64() else {
+                        return Err(ConfigError::wrong_type("output.shard_depth", "integer", v));
+                    };
+                    if shard_depth <= 0 {
+                        return Err(ConfigError::invalid("output.shard_depth", shard_depth));
+                    }
+                    output.shard_depth = Some(shard_depth as u64);
+                }
+                name => unknown_field(&format!("output.{name}"), v)?,
+            }
+        }
+
+        Ok(output)
+    }
+}
+
 #[derive(Debug, Default, PartialEq, Clone)]
 pub struct BackoffConfig {
     initial: Option<Duration>,
@@ -398,30 +1897,26 @@ impl BackoffConfig {
 }
 
 impl TryFrom<&yaml::Hash> for BackoffConfig {
-    type Error = anyhow::Error;
+    type Error = ConfigError;
 
     fn try_from(value: &yaml::Hash) -> Result<Self, Self::Error> {
         let mut backoff = BackoffConfig::default();
         for (k, v) in value.iter() {
             let Some(k) = k.as_str() else {
-                bail!("key is not string: {k:?}");
+                return Err(ConfigError::key_not_string(k));
             };
             match k {
                 "initial" => {
-                    let Some(initial) = yaml_to_duration_secs(v).filter(|d| !d.is_zero()) else {
-                        bail!("invalid grpc.backoff.initial: {v:?}");
-                    };
+                    let initial = yaml_to_positive_duration("grpc.backoff.initial", v)?;
                     backoff.initial = Some(initial);
                 }
                 "max" => {
-                    let Some(max) = yaml_to_duration_secs(v).filter(|d| !d.is_zero()) else {
-                        bail!("invalid grpc.backoff.max: {v:?}");
-                    };
+                    let max = yaml_to_positive_duration("grpc.backoff.max", v)?;
                     backoff.max = Some(max);
                 }
                 "jitter" => {
                     let Some(jitter) = v.as_bool() else {
-                        bail!("grpc.backoff.jitter field has incorrect type: {v:?}");
+                        return Err(ConfigError::wrong_type("grpc.backoff.jitter", "boolean", v));
                     };
                     backoff.jitter = Some(jitter);
                 }
@@ -431,82 +1926,363 @@ impl TryFrom<&yaml::Hash> for BackoffConfig {
                         .or_else(|| v.as_i64().map(|v| v as f64))
                         .filter(|mult| mult.is_finite() && *mult > 1.0)
                     else {
-                        bail!("invalid grpc.backoff.multiplier: {v:?}");
+                        return Err(ConfigError::invalid(
+                            "grpc.backoff.multiplier",
+                            format_args!("{v:?}"),
+                        ));
                     };
                     backoff.multiplier = Some(multiplier);
                 }
                 "retries" => {
                     let Some(retries) = v.as_i64().filter(|r| *r >= 0) else {
-                        bail!("invalid grpc.backoff.retries: {v:?}");
+                        return Err(ConfigError::invalid(
+                            "grpc.backoff.retries",
+                            format_args!("{v:?}"),
+                        ));
                     };
                     backoff.retries_max = Some(retries as u64);
                 }
-                name => bail!("Invalid field 'grpc.backoff.{name}' with value: {v:?}"),
+                name => unknown_field(&format!("grpc.backoff.{name}"), v)?,
             }
         }
         Ok(backoff)
     }
 }
 
-#[derive(Debug, Default, PartialEq, Clone)]
-pub struct GrpcConfig {
-    url: Option<String>,
-    certs: Option<PathBuf>,
-    pub backoff: BackoffConfig,
+/// Compression of the events sent over gRPC.
+#[derive(Debug, Default, PartialEq, Eq, Clone, Copy)]
+pub enum GrpcCompression {
+    /// Older Sensors do not support compression, so it is opt-in.
+    #[default]
+    None,
+    Gzip,
+    Zstd,
 }
 
-impl GrpcConfig {
-    fn update(&mut self, from: &GrpcConfig) {
-        if let Some(url) = from.url.as_deref() {
-            self.url = Some(url.to_owned());
-        }
+impl FromStr for GrpcCompression {
+    type Err = anyhow::Error;
 
-        if let Some(certs) = from.certs.as_deref() {
-            self.certs = Some(certs.to_owned());
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "none" => Ok(GrpcCompression::None),
+            "gzip" => Ok(GrpcCompression::Gzip),
+            "zstd" => Ok(GrpcCompression::Zstd),
+            s => bail!("unknown compression {s:?}, expected one of: none, gzip, zstd"),
         }
-
-        self.backoff.update(&from.backoff);
     }
+}
 
-    pub fn url(&self) -> Option<&str> {
-        self.url.as_deref()
+/// URL of the HTTP proxy the gRPC connection is tunneled through.
+///
+/// Credentials in the URL are never printed, so configurations can be
+/// logged safely.
+#[derive(Clone, PartialEq, Eq)]
+pub struct ProxyUrl(String);
+
+impl ProxyUrl {
+    pub fn as_str(&self) -> &str {
+        &self.0
     }
 
-    pub fn certs(&self) -> Option<&Path> {
-        self.certs.as_deref()
+    /// The URL with the credentials, if any, replaced.
+    pub fn redacted(&self) -> String {
+        redact_url(&self.0)
     }
 }
 
-impl TryFrom<&yaml::Hash> for GrpcConfig {
-    type Error = anyhow::Error;
+/// Replace the credentials in `url`, if any.
+pub fn redact_url(url: &str) -> String {
+    let (scheme, rest) = url.split_once("://").unwrap_or(("", url));
+    let authority = rest.split('/').next().unwrap_or_default();
+    let rest = match authority.rsplit_once('@') {
+        Some((_, host)) => format!("<redacted>@{host}{}", &rest[authority.len()..]),
+        None => rest.to_owned(),
+    };
+    if scheme.is_empty() {
+        rest
+    } else {
+        format!("{scheme}://{rest}")
+    }
+}
+
+impl fmt::Debug for ProxyUrl {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "ProxyUrl({:?})", self.redacted())
+    }
+}
+
+impl FromStr for ProxyUrl {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let url = ProxyUrl(s.to_owned());
+        let Ok(uri) = s.parse::<hyper::Uri>() else {
+            bail!("invalid proxy URL {:?}", url.redacted());
+        };
+        if !matches!(uri.scheme_str(), None | Some("http" | "https")) {
+            bail!(
+                "invalid proxy URL {:?}, only HTTP proxies are supported",
+                url.redacted()
+            );
+        }
+        if uri.host().is_none_or(str::is_empty) {
+            bail!("invalid proxy URL {:?}, no host", url.redacted());
+        }
+        Ok(url)
+    }
+}
+
+#[derive(Debug, Default, PartialEq, Clone)]
+pub struct GrpcConfig {
+    url: Option<Vec<String>>,
+    certs: Option<PathBuf>,
+    key_passphrase_file: Option<PathBuf>,
+    token_file: Option<PathBuf>,
+    cluster_id: Option<String>,
+    compression: Option<GrpcCompression>,
+    proxy: Option<ProxyUrl>,
+    cert_expiry_warning: Option<Duration>,
+    pub backoff: BackoffConfig,
+}
+
+impl GrpcConfig {
+    fn update(&mut self, from: &GrpcConfig) {
+        // Endpoints from a later layer replace the previous ones, they
+        // are not appended, so an override can drop a failed Sensor.
+        if let Some(url) = from.url.as_deref() {
+            self.url = Some(url.to_owned());
+        }
+
+        if let Some(certs) = from.certs.as_deref() {
+            self.certs = Some(certs.to_owned());
+        }
+
+        if let Some(key_passphrase_file) = from.key_passphrase_file.as_deref() {
+            self.key_passphrase_file = Some(key_passphrase_file.to_owned());
+        }
+
+        if let Some(token_file) = from.token_file.as_deref() {
+            self.token_file = Some(token_file.to_owned());
+        }
+
+        if let Some(cluster_id) = from.cluster_id.as_deref() {
+            self.cluster_id = Some(cluster_id.to_owned());
+        }
+
+        if let Some(compression) = from.compression {
+            self.compression = Some(compression);
+        }
+
+        if let Some(proxy) = &from.proxy {
+            self.proxy = Some(proxy.clone());
+        }
+
+        if let Some(cert_expiry_warning) = from.cert_expiry_warning {
+            self.cert_expiry_warning = Some(cert_expiry_warning);
+        }
+
+        self.backoff.update(&from.backoff);
+    }
+
+    /// Endpoints of the server, tried in order until one accepts the
+    /// connection.
+    pub fn urls(&self) -> &[String] {
+        self.url.as_deref().unwrap_or_default()
+    }
+
+    pub fn certs(&self) -> Option<&Path> {
+        self.certs.as_deref()
+    }
+
+    /// File holding the passphrase for an encrypted private key in the
+    /// certs directory.
+    pub fn key_passphrase_file(&self) -> Option<&Path> {
+        self.key_passphrase_file.as_deref()
+    }
+
+    /// File holding a bearer token sent to the server, read on every
+    /// connection attempt.
+    pub fn token_file(&self) -> Option<&Path> {
+        self.token_file.as_deref()
+    }
+
+    /// Cluster fact runs in, sent to the server along with the
+    /// hostname to identify the node.
+    pub fn cluster_id(&self) -> Option<&str> {
+        self.cluster_id.as_deref()
+    }
+
+    pub fn compression(&self) -> GrpcCompression {
+        self.compression.unwrap_or_default()
+    }
+
+    /// HTTP proxy for the connection to the server, the
+    /// `HTTPS_PROXY` environment variable is used when not set.
+    pub fn proxy(&self) -> Option<&ProxyUrl> {
+        self.proxy.as_ref()
+    }
+
+    /// A warning is logged when a certificate expires within this
+    /// window, 7 days by default.
+    pub fn cert_expiry_warning(&self) -> Duration {
+        self.cert_expiry_warning
+            .unwrap_or(Duration::from_secs(7 * 24 * 60 * 60))
+    }
+
+    /// Check the merged configuration, including the values from the
+    /// command line, which are not checked when parsed: the URLs must
+    /// be HTTP or HTTPS and the certs directory must hold readable
+    /// certificates.
+    fn validate(&self) -> Result<(), ConfigError> {
+        for url in self.urls() {
+            if let Err(e) = check_grpc_url(url) {
+                return Err(ConfigError::invalid("grpc.url", e));
+            }
+        }
+
+        if let Some(certs) = self.certs() {
+            let certs = match Source::new(certs) {
+                Ok(source) => source.path(),
+                Err(e) => return Err(ConfigError::invalid("grpc.certs", e)),
+            };
+            for file in [tls::CA_FILE, tls::CERT_FILE, tls::KEY_FILE] {
+                let path = certs.join(file);
+                if let Err(e) = File::open(&path) {
+                    return Err(ConfigError::invalid(
+                        "grpc.certs",
+                        format_args!("cannot read {}: {e}", path.display()),
+                    ));
+                }
+            }
+        }
+        for (name, value) in [
+            ("key_passphrase_file", self.key_passphrase_file()),
+            ("token_file", self.token_file()),
+        ] {
+            if let Some(Err(e)) = value.map(Source::new) {
+                return Err(ConfigError::invalid(format!("grpc.{name}"), e));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Check `url` is an HTTP or HTTPS URL, the ones the gRPC client can
+/// connect to.
+fn check_grpc_url(url: &str) -> anyhow::Result<()> {
+    let uri = match url.parse::<hyper::Uri>() {
+        Ok(uri) => uri,
+        Err(e) => bail!("{url:?} is not a URL: {e}"),
+    };
+    match uri.scheme_str() {
+        Some("http" | "https") => {}
+        Some(scheme) => bail!("unsupported scheme {scheme:?} in {url:?}, expected http or https"),
+        None => bail!("no scheme in {url:?}, expected http or https"),
+    }
+    if uri.host().is_none_or(str::is_empty) {
+        bail!("no host in {url:?}");
+    }
+    Ok(())
+}
+
+impl TryFrom<&yaml::Hash> for GrpcConfig {
+    type Error = ConfigError;
 
     fn try_from(value: &yaml::Hash) -> Result<Self, Self::Error> {
         let mut grpc = GrpcConfig::default();
         for (k, v) in value.iter() {
             let Some(k) = k.as_str() else {
-                bail!("key is not string: {k:?}");
+                return Err(ConfigError::key_not_string(k));
             };
 
             match k {
                 "url" => {
-                    let Some(url) = v.as_str() else {
-                        bail!("url field has incorrect type: {v:?}");
+                    let urls = match v {
+                        Yaml::String(url) => vec![url.to_owned()],
+                        Yaml::Array(urls) => urls
+                            .iter()
+                            .map(|url| match url.as_str() {
+                                Some(url) => Ok(url.to_owned()),
+                                None => Err(ConfigError::wrong_type("url", "string", url)),
+                            })
+                            .collect::<Result<_, ConfigError>>()?,
+                        v => return Err(ConfigError::wrong_type("url", "string or sequence", v)),
                     };
-                    grpc.url = Some(url.to_owned());
+                    if urls.is_empty() {
+                        return Err(ConfigError::invalid("grpc.url", "no endpoints")
+                            .with_message("url field has no endpoints"));
+                    }
+                    for url in &urls {
+                        if let Err(e) = check_grpc_url(url) {
+                            return Err(ConfigError::invalid("grpc.url", e));
+                        }
+                    }
+                    grpc.url = Some(urls);
                 }
                 "certs" => {
                     let Some(certs) = v.as_str() else {
-                        bail!("certs field has incorrect type: {v:?}");
+                        return Err(ConfigError::wrong_type("certs", "string", v));
                     };
                     grpc.certs = Some(PathBuf::from(certs));
                 }
+                "key_passphrase_file" => {
+                    let Some(file) = v.as_str() else {
+                        return Err(ConfigError::wrong_type("key_passphrase_file", "string", v));
+                    };
+                    grpc.key_passphrase_file = Some(PathBuf::from(file));
+                }
+                "token_file" => {
+                    let Some(file) = v.as_str() else {
+                        return Err(ConfigError::wrong_type("token_file", "string", v));
+                    };
+                    grpc.token_file = Some(PathBuf::from(file));
+                }
+                "cluster_id" => {
+                    let Some(cluster_id) = v.as_str() else {
+                        return Err(ConfigError::wrong_type("cluster_id", "string", v));
+                    };
+                    match parse_metadata_value(cluster_id) {
+                        Ok(cluster_id) => grpc.cluster_id = Some(cluster_id),
+                        Err(e) => {
+                            let message = e.to_string();
+                            return Err(
+                                ConfigError::invalid("grpc.cluster_id", e).with_message(message)
+                            );
+                        }
+                    }
+                }
+                "compression" => {
+                    let Some(compression) = v.as_str() else {
+                        return Err(ConfigError::wrong_type("compression", "string", v));
+                    };
+                    match GrpcCompression::from_str(compression) {
+                        Ok(compression) => grpc.compression = Some(compression),
+                        Err(e) => return Err(ConfigError::invalid("grpc.compression", e)),
+                    }
+                }
+                "proxy" => {
+                    let Some(proxy) = v.as_str() else {
+                        return Err(ConfigError::wrong_type("proxy", "string", v));
+                    };
+                    match ProxyUrl::from_str(proxy) {
+                        Ok(proxy) => grpc.proxy = Some(proxy),
+                        Err(e) => return Err(ConfigError::invalid("grpc.proxy", e)),
+                    }
+                }
+                "cert_expiry_warning" => {
+                    let window = yaml_to_duration("grpc.cert_expiry_warning", v)?;
+                    grpc.cert_expiry_warning = Some(window);
+                }
                 "backoff" => {
                     let Some(backoff) = v.as_hash() else {
-                        bail!("grpc.backoff section has incorrect type: {v:?}");
+                        return Err(ConfigError::wrong_type("grpc.backoff", "mapping", v)
+                            .with_message(format!(
+                                "grpc.backoff section has incorrect type: {v:?}"
+                            )));
                     };
                     grpc.backoff = BackoffConfig::try_from(backoff)?;
                 }
-                name => bail!("Invalid field 'grpc.{name}' with value: {v:?}"),
+                name => unknown_field(&format!("grpc.{name}"), v)?,
             }
         }
 
@@ -514,9 +2290,10 @@ impl TryFrom<&yaml::Hash> for GrpcConfig {
     }
 }
 
-#[derive(Debug, Default, PartialEq, Eq, Clone)]
+#[derive(Debug, Default, PartialEq, Clone)]
 pub struct OTelConfig {
     endpoint: Option<String>,
+    pub traces: TracesConfig,
 }
 
 impl OTelConfig {
@@ -524,6 +2301,7 @@ impl OTelConfig {
         if let Some(endpoint) = from.endpoint.as_deref() {
             self.endpoint = Some(endpoint.to_owned());
         }
+        self.traces.update(&from.traces);
     }
 
     pub fn endpoint(&self) -> Option<&str> {
@@ -532,23 +2310,32 @@ impl OTelConfig {
 }
 
 impl TryFrom<&yaml::Hash> for OTelConfig {
-    type Error = anyhow::Error;
+    type Error = ConfigError;
 
     fn try_from(value: &yaml::Hash) -> Result<Self, Self::Error> {
         let mut otel = OTelConfig::default();
         for (k, v) in value.iter() {
             let Some(k) = k.as_str() else {
-                bail!("key is not string: {k:?}");
+                return Err(ConfigError::key_not_string(k));
             };
 
             match k {
                 "endpoint" => {
                     let Some(endpoint) = v.as_str() else {
-                        bail!("otel.endpoint field has incorrect type: {v:?}")
+                        return Err(ConfigError::wrong_type("otel.endpoint", "string", v));
                     };
                     otel.endpoint = Some(endpoint.to_owned());
                 }
-                name => bail!("Invalid field 'otel.{name}' with value: {v:?}"),
+                "traces" => {
+                    let Some(traces) = v.as_hash() else {
+                        return Err(ConfigError::wrong_type("otel.traces", "mapping", v)
+                            .with_message(format!(
+                                "otel.traces section has incorrect type: {v:?}"
+                            )));
+                    };
+                    otel.traces = TracesConfig::try_from(traces)?;
+                }
+                name => unknown_field(&format!("otel.{name}"), v)?,
             }
         }
 
@@ -556,11 +2343,85 @@ impl TryFrom<&yaml::Hash> for OTelConfig {
     }
 }
 
+/// Export of the spans around the stages of the pipeline, only
+/// available with the `tracing` feature.
+#[derive(Debug, Default, PartialEq, Clone)]
+pub struct TracesConfig {
+    endpoint: Option<String>,
+    sample_ratio: Option<f64>,
+}
+
+impl TracesConfig {
+    fn update(&mut self, from: &TracesConfig) {
+        if let Some(endpoint) = from.endpoint.as_deref() {
+            self.endpoint = Some(endpoint.to_owned());
+        }
+        if let Some(sample_ratio) = from.sample_ratio {
+            self.sample_ratio = Some(sample_ratio);
+        }
+    }
+
+    /// OTLP endpoint spans are exported to, nothing is exported when
+    /// unset. Only read at startup.
+    pub fn endpoint(&self) -> Option<&str> {
+        self.endpoint.as_deref()
+    }
+
+    /// Share of the traces exported, from 0 to 1.
+    pub fn sample_ratio(&self) -> f64 {
+        self.sample_ratio.unwrap_or(0.1)
+    }
+}
+
+impl TryFrom<&yaml::Hash> for TracesConfig {
+    type Error = ConfigError;
+
+    fn try_from(value: &yaml::Hash) -> Result<Self, Self::Error> {
+        let mut traces = TracesConfig::default();
+        for (k, v) in value.iter() {
+            let Some(k) = k.as_str() else {
+                return Err(ConfigError::key_not_string(k));
+            };
+
+            match k {
+                "endpoint" => {
+                    let Some(endpoint) = v.as_str() else {
+                        return Err(ConfigError::wrong_type("otel.traces.endpoint", "string", v));
+                    };
+                    traces.endpoint = Some(endpoint.to_owned());
+                }
+                "sample_ratio" => {
+                    let Some(sample_ratio) = v
+                        .as_f64()
+                        .or_else(|| v.as_i64().map(|v| v as f64))
+                        .filter(|ratio| (0.0..=1.0).contains(ratio))
+                    else {
+                        return Err(ConfigError::invalid(
+                            "otel.traces.sample_ratio",
+                            format_args!("{v:?}"),
+                        ));
+                    };
+                    traces.sample_ratio = Some(sample_ratio);
+                }
+                name => unknown_field(&format!("otel.traces.{name}"), v)?,
+            }
+        }
+
+        Ok(traces)
+    }
+}
+
 #[derive(Debug, Default, PartialEq, Eq, Clone)]
 pub struct BpfConfig {
     ringbuf_size: Option<u32>,
+    ringbuf_min_size: Option<u32>,
     inodes_max: Option<u32>,
+    collect_args: Option<bool>,
+    report_self: Option<bool>,
     pub programs: HashMap<String, BpfProgConfig>,
+    /// Support of filesystem types by the hooks, overriding the one
+    /// built into fact.
+    pub filesystems: BTreeMap<String, Support>,
 }
 
 impl BpfConfig {
@@ -569,141 +2430,664 @@ impl BpfConfig {
             self.ringbuf_size = Some(ringbuf_size);
         }
 
+        if let Some(ringbuf_min_size) = from.ringbuf_min_size {
+            self.ringbuf_min_size = Some(ringbuf_min_size);
+        }
+
         if let Some(inodes_max) = from.inodes_max {
             self.inodes_max = Some(inodes_max);
         }
 
-        for (k, v) in &from.programs {
-            self.programs.entry(k.clone()).or_default().update(v);
+        if let Some(collect_args) = from.collect_args {
+            self.collect_args = Some(collect_args);
+        }
+
+        if let Some(report_self) = from.report_self {
+            self.report_self = Some(report_self);
+        }
+
+        for (k, v) in &from.programs {
+            self.programs.entry(k.clone()).or_default().update(v);
+        }
+
+        for (k, v) in &from.filesystems {
+            self.filesystems.insert(k.clone(), *v);
+        }
+    }
+
+    pub fn ringbuf_size(&self) -> u32 {
+        self.ringbuf_size.unwrap_or(8192)
+    }
+
+    /// Smallest size, in kilobytes, the ringbuffer is shrunk to when
+    /// the kernel fails to create it with `ringbuf_size`.
+    pub fn ringbuf_min_size(&self) -> u32 {
+        self.ringbuf_min_size.unwrap_or(64)
+    }
+
+    pub fn inodes_max(&self) -> u32 {
+        self.inodes_max.unwrap_or(65536)
+    }
+
+    /// Whether the arguments of processes are included in events.
+    pub fn collect_args(&self) -> bool {
+        self.collect_args.unwrap_or(true)
+    }
+
+    /// Whether events caused by fact itself, like its scans of the
+    /// monitored paths, are reported.
+    pub fn report_self(&self) -> bool {
+        self.report_self.unwrap_or(false)
+    }
+
+    /// Whether the program for the `name` hook is loaded.
+    ///
+    /// Programs in `OPTIONAL_PROGRAMS` need to be enabled explicitly,
+    /// the rest are loaded unless disabled.
+    pub fn program_is_enabled(&self, name: &str) -> bool {
+        self.programs
+            .get(name)
+            .and_then(|c| c.enabled)
+            .unwrap_or(!Self::OPTIONAL_PROGRAMS.contains(&name))
+    }
+
+    /// Programs that are not loaded by default.
+    ///
+    /// `file_permission` reports writes to monitored files, it runs on
+    /// every read and write on the host.
+    pub const OPTIONAL_PROGRAMS: [&'static str; 1] = ["file_permission"];
+}
+
+impl TryFrom<&yaml::Hash> for BpfConfig {
+    type Error = ConfigError;
+
+    fn try_from(value: &yaml::Hash) -> Result<Self, Self::Error> {
+        let mut bpf = BpfConfig::default();
+        for (k, v) in value {
+            let Some(k) = k.as_str() else {
+                return Err(ConfigError::key_not_string(k));
+            };
+
+            match k {
+                "ringbuf_size" => {
+                    let rb_size = match ByteSize::from_yaml(v, units::KB) {
+                        Ok(rb_size) => rb_size,
+                        Err(e) => return Err(ConfigError::invalid("ringbuf_size", e)),
+                    };
+                    bpf.ringbuf_size = Some(ringbuf_size_kb(k, rb_size)?);
+                }
+                "ringbuf_min_size" => {
+                    let rb_size = match ByteSize::from_yaml(v, units::KB) {
+                        Ok(rb_size) => rb_size,
+                        Err(e) => return Err(ConfigError::invalid("ringbuf_min_size", e)),
+                    };
+                    bpf.ringbuf_min_size = Some(ringbuf_size_kb(k, rb_size)?);
+                }
+                "inodes_max" => {
+                    let Some(inode_max) = v.as_i64() else {
+                        return Err(ConfigError::wrong_type("inodes_max", "integer", v));
+                    };
+                    bpf.inodes_max = Some(inode_max as u32);
+                }
+                "collect_args" => {
+                    let Some(collect_args) = v.as_bool() else {
+                        return Err(ConfigError::wrong_type("collect_args", "boolean", v));
+                    };
+                    bpf.collect_args = Some(collect_args);
+                }
+                "report_self" => {
+                    let Some(report_self) = v.as_bool() else {
+                        return Err(ConfigError::wrong_type("report_self", "boolean", v));
+                    };
+                    bpf.report_self = Some(report_self);
+                }
+                "programs" => {
+                    let Some(programs) = v.as_hash() else {
+                        return Err(ConfigError::wrong_type("bpf.programs", "mapping", v));
+                    };
+                    bpf.programs = programs
+                        .iter()
+                        .map(|(name, config)| {
+                            let Some(name) = name.as_str() else {
+                                return Err(ConfigError::key_not_string(name).with_message(
+                                    format!("bpf program name is not string: {name:?}"),
+                                ));
+                            };
+                            let section = format!("bpf.programs.{name}");
+                            let Some(config) = config.as_hash() else {
+                                let message = format!("{section} has wrong type: {config:?}");
+                                return Err(ConfigError::wrong_type(section, "mapping", config)
+                                    .with_message(message));
+                            };
+                            let config =
+                                BpfProgConfig::try_from(config).map_err(|e| e.within(&section))?;
+
+                            Ok((name.into(), config))
+                        })
+                        .collect::<Result<HashMap<_, _>, _>>()?;
+                }
+                "filesystems" => {
+                    let Some(filesystems) = v.as_hash() else {
+                        return Err(ConfigError::wrong_type("bpf.filesystems", "mapping", v));
+                    };
+                    bpf.filesystems = filesystems
+                        .iter()
+                        .map(|(name, support)| {
+                            let Some(name) = name.as_str() else {
+                                return Err(ConfigError::key_not_string(name));
+                            };
+                            let section = format!("bpf.filesystems.{name}");
+                            let Some(support) = support.as_str() else {
+                                return Err(ConfigError::wrong_type(section, "string", support));
+                            };
+                            let support = Support::from_str(support)
+                                .map_err(|e| ConfigError::invalid(section, e))?;
+
+                            Ok((name.into(), support))
+                        })
+                        .collect::<Result<BTreeMap<_, _>, _>>()?;
+                }
+                name => unknown_field(&format!("bpf.{name}"), v)?,
+            }
+        }
+        Ok(bpf)
+    }
+}
+
+/// Options for replaying events, these can only be set from the
+/// `replay` subcommand.
+#[derive(Debug, Default, PartialEq, Eq, Clone)]
+pub struct ReplayOptions {
+    pub pace: Option<bool>,
+    pub rewrite_timestamps: Option<bool>,
+    pub hostname: Option<String>,
+}
+
+impl ReplayOptions {
+    fn update(&mut self, from: &ReplayOptions) {
+        if let Some(pace) = from.pace {
+            self.pace = Some(pace);
+        }
+
+        if let Some(rewrite_timestamps) = from.rewrite_timestamps {
+            self.rewrite_timestamps = Some(rewrite_timestamps);
+        }
+
+        if let Some(hostname) = from.hostname.as_deref() {
+            self.hostname = Some(hostname.to_owned());
+        }
+    }
+
+    pub fn pace(&self) -> bool {
+        self.pace.unwrap_or(false)
+    }
+
+    pub fn rewrite_timestamps(&self) -> bool {
+        self.rewrite_timestamps.unwrap_or(false)
+    }
+
+    pub fn hostname(&self) -> Option<&str> {
+        self.hostname.as_deref()
+    }
+}
+
+/// Output format of the `limits` subcommand.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum LimitsFormat {
+    Table,
+    Json,
+}
+
+/// Options for generating synthetic events, these can only be set from
+/// the `generate` subcommand.
+#[derive(Debug, Default, PartialEq, Eq, Clone)]
+pub struct GenerateOptions {
+    pub rate: Option<u64>,
+    pub paths: Option<u32>,
+    pub processes: Option<u32>,
+    pub containers: Option<u32>,
+    pub host_pct: Option<u8>,
+    pub raw: Option<bool>,
+}
+
+impl GenerateOptions {
+    fn update(&mut self, from: &GenerateOptions) {
+        if let Some(rate) = from.rate {
+            self.rate = Some(rate);
+        }
+
+        if let Some(paths) = from.paths {
+            self.paths = Some(paths);
+        }
+
+        if let Some(processes) = from.processes {
+            self.processes = Some(processes);
+        }
+
+        if let Some(containers) = from.containers {
+            self.containers = Some(containers);
+        }
+
+        if let Some(host_pct) = from.host_pct {
+            self.host_pct = Some(host_pct);
+        }
+
+        if let Some(raw) = from.raw {
+            self.raw = Some(raw);
+        }
+    }
+
+    /// Events generated per second, 0 for as fast as possible.
+    pub fn rate(&self) -> u64 {
+        self.rate.unwrap_or(10_000)
+    }
+
+    /// Number of distinct file paths.
+    pub fn paths(&self) -> u32 {
+        self.paths.unwrap_or(1000).max(1)
+    }
+
+    /// Number of distinct processes.
+    pub fn processes(&self) -> u32 {
+        self.processes.unwrap_or(100).max(1)
+    }
+
+    /// Number of distinct containers processes run in.
+    pub fn containers(&self) -> u32 {
+        self.containers.unwrap_or(10)
+    }
+
+    /// Percentage of processes running on the host instead of in a
+    /// container.
+    pub fn host_pct(&self) -> u8 {
+        self.host_pct.unwrap_or(10).min(100)
+    }
+
+    /// Whether events are encoded in the ringbuffer format and parsed
+    /// back, instead of being built directly.
+    pub fn raw(&self) -> bool {
+        self.raw.unwrap_or(false)
+    }
+}
+
+#[derive(Debug, Default, PartialEq, Eq, Clone)]
+pub struct BpfProgConfig {
+    pub enabled: Option<bool>,
+}
+
+impl BpfProgConfig {
+    fn update(&mut self, from: &BpfProgConfig) {
+        if let Some(enabled) = from.enabled {
+            self.enabled = Some(enabled);
+        }
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.enabled.unwrap_or(true)
+    }
+}
+
+impl TryFrom<&yaml::Hash> for BpfProgConfig {
+    type Error = ConfigError;
+
+    fn try_from(value: &yaml::Hash) -> Result<Self, Self::Error> {
+        let mut bpf_prog_config = BpfProgConfig::default();
+        for (k, v) in value.iter() {
+            let Some(k) = k.as_str() else {
+                return Err(ConfigError::key_not_string(k));
+            };
+
+            match k {
+                "enabled" => {
+                    let Some(enabled) = v.as_bool() else {
+                        return Err(ConfigError::wrong_type("enabled", "boolean", v)
+                            .with_message(format!("enabled field has wrong type: {v:?}")));
+                    };
+                    bpf_prog_config.enabled = Some(enabled);
+                }
+                name => unknown_field(name, v)?,
+            }
+        }
+
+        Ok(bpf_prog_config)
+    }
+}
+
+/// A path on which fact can deny operations.
+///
+/// Protected paths are monitored like any other path. If `enforce` is
+/// set and enforcement is globally enabled, write opens and unlinks on
+/// files under the path prefix are denied with EPERM and the resulting
+/// events are flagged as blocked.
+#[derive(Debug, Default, PartialEq, Eq, Clone)]
+pub struct ProtectedPath {
+    pub path: PathBuf,
+    pub enforce: bool,
+}
+
+impl TryFrom<&Yaml> for ProtectedPath {
+    type Error = ConfigError;
+
+    fn try_from(value: &Yaml) -> Result<Self, Self::Error> {
+        let Some(value) = value.as_hash() else {
+            return Err(ConfigError::wrong_type("protected_paths", "mapping", value)
+                .with_message(format!("protected path has incorrect type: {value:?}")));
+        };
+
+        let mut path = None;
+        let mut enforce = false;
+        for (k, v) in value.iter() {
+            let Some(k) = k.as_str() else {
+                return Err(ConfigError::key_not_string(k));
+            };
+
+            match k {
+                "path" => {
+                    let Some(p) = v.as_str() else {
+                        return Err(ConfigError::wrong_type("protected_paths.path", "string", v));
+                    };
+                    path = Some(PathBuf::from(p));
+                }
+                "enforce" => {
+                    let Some(e) = v.as_bool() else {
+                        return Err(ConfigError::wrong_type(
+                            "protected_paths.enforce",
+                            "boolean",
+                            v,
+                        ));
+                    };
+                    enforce = e;
+                }
+                name => unknown_field(&format!("protected_paths.{name}"), v)?,
+            }
         }
-    }
-
-    pub fn ringbuf_size(&self) -> u32 {
-        self.ringbuf_size.unwrap_or(8192)
-    }
 
-    pub fn inodes_max(&self) -> u32 {
-        self.inodes_max.unwrap_or(65536)
+        let Some(path) = path else {
+            return Err(
+                ConfigError::missing_field("protected_paths", "path", value).with_message(format!(
+                    "protected path is missing the path field: {value:?}"
+                )),
+            );
+        };
+        Ok(ProtectedPath { path, enforce })
     }
+}
 
-    pub fn program_is_enabled(&self, name: &str) -> bool {
-        self.programs.get(name).map(|c| c.enabled()).unwrap_or(true)
-    }
+/// Sampling applied to events of a type, optionally restricted to
+/// files under a path prefix.
+///
+/// One out of every `rate` events is kept, configured as "1/N". When
+/// several rules match an event, the one with the longest path prefix
+/// wins, see [`crate::sampling`].
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct SamplingRule {
+    pub event: &'static str,
+    pub path: Option<PathBuf>,
+    pub rate: u32,
 }
 
-impl TryFrom<&yaml::Hash> for BpfConfig {
-    type Error = anyhow::Error;
+impl TryFrom<&Yaml> for SamplingRule {
+    type Error = ConfigError;
 
-    fn try_from(value: &yaml::Hash) -> Result<Self, Self::Error> {
-        let mut bpf = BpfConfig::default();
-        for (k, v) in value {
+    fn try_from(value: &Yaml) -> Result<Self, Self::Error> {
+        let Some(value) = value.as_hash() else {
+            return Err(ConfigError::wrong_type("sampling", "mapping", value)
+                .with_message(format!("sampling rule has incorrect type: {value:?}")));
+        };
+
+        let mut event = None;
+        let mut path = None;
+        let mut rate = None;
+        for (k, v) in value.iter() {
             let Some(k) = k.as_str() else {
-                bail!("key is not string: {k:?}");
+                return Err(ConfigError::key_not_string(k));
             };
 
             match k {
-                "ringbuf_size" => {
-                    let Some(rb_size) = v.as_i64() else {
-                        bail!("ringbuf_size field has incorrect type: {v:?}");
+                "event" => {
+                    let Some(e) = v.as_str() else {
+                        return Err(ConfigError::wrong_type("sampling.event", "string", v));
                     };
-                    if rb_size < 64 || rb_size > (u32::MAX / 1024) as i64 {
-                        bail!("ringbuf_size out of range: {rb_size}");
-                    }
-                    let rb_size = rb_size as u32;
-                    if rb_size.count_ones() != 1 {
-                        bail!("ringbuf_size is not a power of 2: {rb_size}");
-                    }
-                    bpf.ringbuf_size = Some(rb_size);
+                    let Some(e) = FileData::EVENT_TYPES.iter().find(|t| **t == e) else {
+                        return Err(ConfigError::invalid(
+                            "sampling.event",
+                            format_args!(
+                                "{e:?}, expected one of {}",
+                                FileData::EVENT_TYPES.join(", ")
+                            ),
+                        ));
+                    };
+                    event = Some(*e);
                 }
-                "inodes_max" => {
-                    let Some(inode_max) = v.as_i64() else {
-                        bail!("inodes_max field has incorrect type: {v:?}");
+                "path" => {
+                    let Some(p) = v.as_str() else {
+                        return Err(ConfigError::wrong_type("sampling.path", "string", v));
                     };
-                    bpf.inodes_max = Some(inode_max as u32);
+                    path = Some(PathBuf::from(p));
                 }
-                "programs" => {
-                    let Some(programs) = v.as_hash() else {
-                        bail!("bpf.programs field has incorrect type: {v:?}");
+                "rate" => {
+                    let Some(r) = v.as_str() else {
+                        return Err(ConfigError::wrong_type("sampling.rate", "string", v));
                     };
-                    bpf.programs = programs
-                        .iter()
-                        .map(|(name, config)| {
-                            let Some(name) = name.as_str() else {
-                                bail!("bpf program name is not string: {name:?}");
-                            };
-                            let Some(config) = config.as_hash() else {
-                                bail!("bpf.programs.{name} has wrong type: {config:?}");
-                            };
-                            let config = match BpfProgConfig::try_from(config) {
-                                Ok(c) => c,
-                                Err(e) => {
-                                    bail!("bpf.programs.{name} parsing failed: {e:?}");
-                                }
-                            };
-
-                            Ok((name.into(), config))
-                        })
-                        .collect::<Result<HashMap<_, _>, _>>()?;
+                    let Some(r) = r
+                        .trim()
+                        .strip_prefix("1/")
+                        .and_then(|n| n.trim().parse::<u32>().ok())
+                        .filter(|n| *n > 0)
+                    else {
+                        return Err(ConfigError::invalid(
+                            "sampling.rate",
+                            format_args!(r#"{r:?}, expected "1/N" with N > 0"#),
+                        ));
+                    };
+                    rate = Some(r);
                 }
-                name => bail!("Invalid field 'bpf.{name}' with value: {v:?}"),
+                name => unknown_field(&format!("sampling.{name}"), v)?,
             }
         }
-        Ok(bpf)
+
+        let Some(event) = event else {
+            return Err(
+                ConfigError::missing_field("sampling", "event", value).with_message(format!(
+                    "sampling rule is missing the event field: {value:?}"
+                )),
+            );
+        };
+        let Some(rate) = rate else {
+            return Err(
+                ConfigError::missing_field("sampling", "rate", value).with_message(format!(
+                    "sampling rule is missing the rate field: {value:?}"
+                )),
+            );
+        };
+        Ok(SamplingRule { event, path, rate })
     }
 }
 
-#[derive(Debug, Default, PartialEq, Eq, Clone)]
-pub struct BpfProgConfig {
-    pub enabled: Option<bool>,
+/// Parse a filter from a map with the `expr` to evaluate and the
+/// `action` to take on matching events, `drop` by default. See
+/// [`crate::filter`] for the syntax of expressions.
+fn parse_redact_pattern(value: &Yaml) -> Result<RedactPattern, ConfigError> {
+    let Some(pattern) = value.as_str() else {
+        return Err(ConfigError::wrong_type("redact_args", "string", value));
+    };
+    match pattern.parse() {
+        Ok(pattern) => Ok(pattern),
+        Err(e) => Err(ConfigError::invalid(
+            "redact_args",
+            format_args!("pattern {pattern:?}: {e}"),
+        )
+        .with_message(format!("invalid redact_args pattern {pattern:?}: {e}"))),
+    }
 }
 
-impl BpfProgConfig {
-    fn update(&mut self, from: &BpfProgConfig) {
-        if let Some(enabled) = from.enabled {
-            self.enabled = Some(enabled);
+fn parse_filter(value: &Yaml) -> Result<Filter, ConfigError> {
+    let Some(value) = value.as_hash() else {
+        return Err(ConfigError::wrong_type("filters", "mapping", value)
+            .with_message(format!("filter has incorrect type: {value:?}")));
+    };
+
+    let mut action = FilterAction::default();
+    let mut expr = None;
+    for (k, v) in value.iter() {
+        let Some(k) = k.as_str() else {
+            return Err(ConfigError::key_not_string(k));
+        };
+
+        match k {
+            "action" => {
+                let Some(a) = v.as_str() else {
+                    return Err(ConfigError::wrong_type("filters.action", "string", v));
+                };
+                action = a.parse().map_err(|e: anyhow::Error| {
+                    let message = e.to_string();
+                    ConfigError::invalid("filters.action", e).with_message(message)
+                })?;
+            }
+            "expr" => {
+                let Some(e) = v.as_str() else {
+                    return Err(ConfigError::wrong_type("filters.expr", "string", v));
+                };
+                expr = Some(e);
+            }
+            name => unknown_field(&format!("filters.{name}"), v)?,
         }
     }
 
-    pub fn enabled(&self) -> bool {
-        self.enabled.unwrap_or(true)
+    let Some(expr) = expr else {
+        return Err(ConfigError::missing_field("filters", "expr", value)
+            .with_message(format!("filter is missing the expr field: {value:?}")));
+    };
+    match Filter::new(action, expr) {
+        Ok(filter) => Ok(filter),
+        Err(e) => Err(
+            ConfigError::invalid("filters.expr", format_args!("{expr:?} at {e}"))
+                .with_message(format!("invalid filter expression {expr:?} at {e}")),
+        ),
     }
 }
 
-impl TryFrom<&yaml::Hash> for BpfProgConfig {
-    type Error = anyhow::Error;
+/// A monitored path along with the labels attached to events on it.
+///
+/// In the configuration file, monitored paths can either be a plain
+/// path or a map with `path` and `labels` keys. Labels are resolved
+/// with the same longest prefix match used by the kernel, see
+/// [`crate::labels`].
+#[derive(Debug, Default, PartialEq, Eq, Clone)]
+pub struct PathLabels {
+    pub path: PathBuf,
+    pub labels: BTreeMap<String, String>,
+}
 
-    fn try_from(value: &yaml::Hash) -> Result<Self, Self::Error> {
-        let mut bpf_prog_config = BpfProgConfig::default();
+impl TryFrom<&Yaml> for PathLabels {
+    type Error = ConfigError;
+
+    fn try_from(value: &Yaml) -> Result<Self, Self::Error> {
+        let Some(value) = value.as_hash() else {
+            return Err(ConfigError::wrong_type("paths", "string or mapping", value)
+                .with_message(format!("Path has invalid type: {value:?}")));
+        };
+
+        let mut path = None;
+        let mut labels = BTreeMap::new();
         for (k, v) in value.iter() {
             let Some(k) = k.as_str() else {
-                bail!("key is not string: {k:?}");
+                return Err(ConfigError::key_not_string(k));
             };
 
             match k {
-                "enabled" => {
-                    let Some(enabled) = v.as_bool() else {
-                        bail!("enabled field has wrong type: {v:?}");
+                "path" => {
+                    let Some(p) = v.as_str() else {
+                        return Err(ConfigError::wrong_type("paths.path", "string", v));
                     };
-                    bpf_prog_config.enabled = Some(enabled);
+                    path = Some(PathBuf::from(p));
+                }
+                "labels" if v.is_null() => {}
+                "labels" => {
+                    let Some(l) = v.as_hash() else {
+                        return Err(ConfigError::wrong_type("paths.labels", "mapping", v));
+                    };
+                    for (key, value) in l.iter() {
+                        let (Some(key), Some(value)) = (key.as_str(), value.as_str()) else {
+                            return Err(ConfigError::invalid(
+                                "paths.labels",
+                                format_args!("{key:?}: {value:?}, keys and values must be strings"),
+                            )
+                            .with_message(format!(
+                                "invalid label: {key:?}: {value:?}, keys and values must be strings"
+                            )));
+                        };
+                        labels.insert(key.to_string(), value.to_string());
+                    }
                 }
-                name => bail!("Invalid field '{name}' with value: {v:?}"),
+                name => unknown_field(&format!("paths.{name}"), v)?,
             }
         }
 
-        Ok(bpf_prog_config)
+        let Some(path) = path else {
+            return Err(ConfigError::missing_field("paths", "path", value)
+                .with_message(format!("path is missing the path field: {value:?}")));
+        };
+        Ok(PathLabels { path, labels })
     }
 }
 
-fn parse_duration_secs(s: &str) -> anyhow::Result<Duration> {
-    let f = s.parse::<f64>()?;
-    if !f.is_finite() || f < 0.0 {
-        bail!("value must be a non-negative finite number, got {f}");
+/// Whether `s` can be sent as gRPC metadata, which only allows
+/// visible ASCII characters and spaces.
+pub fn is_metadata_value(s: &str) -> bool {
+    !s.is_empty() && s.bytes().all(|b| b == b' ' || b.is_ascii_graphic())
+}
+
+fn parse_metadata_value(s: &str) -> anyhow::Result<String> {
+    if !is_metadata_value(s) {
+        bail!("invalid metadata value {s:?}, only printable ASCII characters are allowed");
+    }
+    Ok(s.to_owned())
+}
+
+/// Validate a size of the ringbuffer set in `field`, returning it in
+/// kilobytes.
+fn ringbuf_size_kb(field: &str, size: ByteSize) -> Result<u32, ConfigError> {
+    let bytes = size.as_bytes();
+    if !bytes.is_multiple_of(units::KB) {
+        return Err(ConfigError::invalid(
+            field,
+            format_args!("must be a multiple of 1KB: {bytes}B"),
+        )
+        .with_message(format!("{field} must be a multiple of 1KB: {bytes}B")));
+    }
+    let kb = bytes / units::KB;
+    if !(64..=(u32::MAX / 1024) as u64).contains(&kb) {
+        return Err(ConfigError::invalid(
+            field,
+            format_args!("out of range: {kb}KB, must be between 64KB and 2GB"),
+        )
+        .with_message(format!(
+            "{field} out of range: {kb}KB, must be between 64KB and 2GB"
+        )));
     }
-    Ok(Duration::from_secs_f64(f))
+    if kb.count_ones() != 1 {
+        return Err(
+            ConfigError::invalid(field, format_args!("not a power of 2: {kb}KB"))
+                .with_message(format!("{field} is not a power of 2: {kb}KB")),
+        );
+    }
+    Ok(kb as u32)
+}
+
+fn parse_ringbuf_size(s: &str) -> anyhow::Result<u32> {
+    Ok(ringbuf_size_kb(
+        "ringbuf_size",
+        ByteSize::parse(s, units::KB)?,
+    )?)
+}
+
+fn parse_duration(s: &str) -> anyhow::Result<Duration> {
+    Ok(s.parse::<DurationValue>()?.into())
 }
 
-fn parse_positive_duration_secs(s: &str) -> anyhow::Result<Duration> {
-    let d = parse_duration_secs(s)?;
+fn parse_positive_duration(s: &str) -> anyhow::Result<Duration> {
+    let d = parse_duration(s)?;
     if d.is_zero() {
         bail!("value must be greater than zero");
     }
@@ -718,6 +3102,14 @@ fn parse_multiplier(s: &str) -> anyhow::Result<f64> {
     Ok(mult)
 }
 
+fn parse_ratio(s: &str) -> anyhow::Result<f64> {
+    let ratio = s.parse::<f64>()?;
+    if !(0.0..=1.0).contains(&ratio) {
+        bail!("ratio must be between 0 and 1, got {ratio}");
+    }
+    Ok(ratio)
+}
+
 #[derive(Debug, Parser)]
 #[clap(version = crate::version::FACT_VERSION, about)]
 pub struct FactCli {
@@ -725,24 +3117,66 @@ pub struct FactCli {
     #[clap(short, long, num_args = 0..16, value_delimiter = ':', env = "FACT_PATHS")]
     paths: Option<Vec<PathBuf>>,
 
-    /// URL to forward the packages to
-    #[arg(env = "FACT_URL")]
-    url: Option<String>,
-
-    /// Directory holding the mTLS certificates and keys
+    /// File with more paths to monitor, one per line
+    ///
+    /// Empty lines and lines starting with # are ignored. The file is
+    /// reloaded along with the configuration files.
+    #[arg(long, env = "FACT_PATHS_FILE")]
+    paths_file: Option<PathBuf>,
+
+    /// URL to forward the packages to, several comma separated URLs
+    /// are tried in order until one accepts the connection
+    #[arg(env = "FACT_URL", num_args = 1, value_delimiter = ',')]
+    url: Option<Vec<String>>,
+
+    /// Directory holding the mTLS certificates and keys, fd:<n> for an
+    /// inherited descriptor or a name in $CREDENTIALS_DIRECTORY
     #[arg(short, long, env = "FACT_CERTS")]
     certs: Option<PathBuf>,
 
-    /// Initial backoff delay in seconds for gRPC reconnection
+    /// File holding the passphrase of the private key in the certs
+    /// directory, only needed if the key is encrypted
+    #[arg(long, env = "FACT_GRPC_KEY_PASSPHRASE_FILE")]
+    key_passphrase_file: Option<PathBuf>,
+
+    /// File holding a bearer token to authenticate to the gRPC server,
+    /// re-read on every reconnection
+    #[arg(long, env = "FACT_GRPC_TOKEN_FILE")]
+    token_file: Option<PathBuf>,
+
+    /// Cluster fact runs in, sent to the gRPC server to identify the
+    /// node along with the hostname
+    #[arg(long, env = "FACT_GRPC_CLUSTER_ID", value_parser = parse_metadata_value)]
+    cluster_id: Option<String>,
+
+    /// Compression of the events sent to the gRPC server: none, gzip
+    /// or zstd. Default value is none
+    #[arg(long, env = "FACT_GRPC_COMPRESSION")]
+    compression: Option<GrpcCompression>,
+
+    /// HTTP proxy to tunnel the gRPC connection through, defaults to
+    /// the HTTPS_PROXY environment variable. Hosts in NO_PROXY are
+    /// always reached directly
+    #[arg(long = "grpc-proxy", env = "FACT_GRPC_PROXY")]
+    proxy: Option<ProxyUrl>,
+
+    /// Warn when a certificate in the certs directory expires within
+    /// this duration. Default value is 7 days
+    #[arg(long, env = "FACT_GRPC_CERT_EXPIRY_WARNING", value_parser = parse_duration)]
+    cert_expiry_warning: Option<Duration>,
+
+    /// Initial backoff delay for gRPC reconnection
     ///
+    /// Accepts a number of seconds or a duration like "500ms" or "5s".
     /// Default value is 1 second
-    #[arg(long, env = "FACT_GRPC_BACKOFF_INITIAL_DURATION", value_parser = parse_positive_duration_secs)]
+    #[arg(long, env = "FACT_GRPC_BACKOFF_INITIAL_DURATION", value_parser = parse_positive_duration)]
     backoff_initial: Option<Duration>,
 
-    /// Maximum backoff delay in seconds for gRPC reconnection
+    /// Maximum backoff delay for gRPC reconnection
     ///
+    /// Accepts a number of seconds or a duration like "30s" or "1m".
     /// Default value is 60 seconds
-    #[arg(long, env = "FACT_GRPC_BACKOFF_MAX_DURATION", value_parser = parse_positive_duration_secs)]
+    #[arg(long, env = "FACT_GRPC_BACKOFF_MAX_DURATION", value_parser = parse_positive_duration)]
     backoff_max: Option<Duration>,
 
     /// Backoff multiplier for gRPC reconnection
@@ -768,9 +3202,41 @@ pub struct FactCli {
     #[arg(long, env = "FACT_OTEL_ENDPOINT")]
     otel_endpoint: Option<String>,
 
-    /// The port to bind for all exposed endpoints
-    #[arg(long, short, env = "FACT_ENDPOINT_ADDRESS")]
-    address: Option<SocketAddr>,
+    /// OpenTelemetry endpoint to push the spans of the pipeline into
+    ///
+    /// Only available when fact is built with the tracing feature.
+    #[arg(long, env = "FACT_OTEL_TRACES_ENDPOINT")]
+    otel_traces_endpoint: Option<String>,
+
+    /// Share of the traces pushed, from 0 to 1
+    ///
+    /// Default value is 0.1
+    #[arg(long, env = "FACT_OTEL_TRACES_SAMPLE_RATIO", value_parser = parse_ratio)]
+    otel_traces_sample_ratio: Option<f64>,
+
+    /// The addresses to bind for all exposed endpoints, several comma
+    /// separated addresses are listened on at once
+    ///
+    /// Default value is [::]:9000, accepting both IPv4 and IPv6
+    /// connections. On systems without IPv6 support 0.0.0.0:9000 is
+    /// used instead. No default is bound when --metrics-address or
+    /// --health-address are set.
+    #[arg(
+        long,
+        short,
+        env = "FACT_ENDPOINT_ADDRESS",
+        num_args = 1,
+        value_delimiter = ','
+    )]
+    address: Option<Vec<SocketAddr>>,
+
+    /// An address only serving the metrics and the status
+    #[arg(long, env = "FACT_ENDPOINT_METRICS_ADDRESS")]
+    metrics_address: Option<SocketAddr>,
+
+    /// An address only serving the health checks
+    #[arg(long, env = "FACT_ENDPOINT_HEALTH_ADDRESS")]
+    health_address: Option<SocketAddr>,
 
     /// Whether prometheus metrics should be collected and exposed
     #[arg(
@@ -792,6 +3258,69 @@ pub struct FactCli {
     #[arg(long, overrides_with = "health_check", hide(true))]
     no_health_check: bool,
 
+    /// Bearer token required to use the control endpoints
+    ///
+    /// The control endpoints, used for pausing and resuming event
+    /// collection, are disabled unless a token is set.
+    #[arg(long, env = "FACT_ENDPOINT_CONTROL_TOKEN", hide_env_values = true)]
+    control_token: Option<ControlToken>,
+
+    /// Number of events dropped by the outputs in an interval above
+    /// which readiness is considered degraded
+    ///
+    /// Default value is 0 (readiness is never degraded)
+    #[arg(long, env = "FACT_READINESS_DROP_THRESHOLD")]
+    readiness_drop_threshold: Option<u64>,
+
+    /// Interval at which output drops are checked for readiness
+    ///
+    /// Accepts a number of seconds or a duration like "10s".
+    /// Default value is 10 seconds
+    #[arg(long, env = "FACT_READINESS_INTERVAL", value_parser = parse_positive_duration)]
+    readiness_interval: Option<Duration>,
+
+    /// Consecutive intervals over the drop threshold before readiness
+    /// is degraded
+    ///
+    /// Default value is 3
+    #[arg(long, env = "FACT_READINESS_DEGRADED_AFTER", value_parser = clap::value_parser!(u64).range(1..))]
+    readiness_degraded_after: Option<u64>,
+
+    /// Consecutive intervals under the drop threshold before a
+    /// degraded readiness recovers
+    ///
+    /// Default value is 3
+    #[arg(long, env = "FACT_READINESS_RECOVER_AFTER", value_parser = clap::value_parser!(u64).range(1..))]
+    readiness_recover_after: Option<u64>,
+
+    /// Whether the readiness endpoint fails with 503 while degraded
+    /// instead of reporting it in the body of a 200 response
+    ///
+    /// Default value is false
+    #[arg(long, env = "FACT_READINESS_FAIL_ON_DEGRADED")]
+    readiness_fail_on_degraded: Option<bool>,
+
+    /// Whether the readiness endpoint fails with 503 while event
+    /// collection is paused
+    ///
+    /// Default value is false
+    #[arg(long, env = "FACT_READINESS_FAIL_ON_PAUSED")]
+    readiness_fail_on_paused: Option<bool>,
+
+    /// Percentage of a core background maintenance work, like host
+    /// rescans, may use
+    ///
+    /// Default value is 100 (no limit)
+    #[arg(long, env = "FACT_MAINTENANCE_CPU_BUDGET_PCT", value_parser = clap::value_parser!(u8).range(1..=100))]
+    maintenance_cpu_budget_pct: Option<u8>,
+
+    /// Whether to check if the files of open and creation events still
+    /// exist when emitting them
+    ///
+    /// Default value is false
+    #[arg(long, env = "FACT_ENRICH_EXISTENCE_CHECK")]
+    enrich_existence_check: Option<bool>,
+
     /// Whether to perform a pre flight check
     #[arg(
         long,
@@ -802,21 +3331,58 @@ pub struct FactCli {
     #[arg(long, overrides_with = "skip_pre_flight", hide(true))]
     no_skip_pre_flight: bool,
 
-    /// Force events to be output as JSON to stdout
+    /// When to write events as JSON to stdout: auto, on or off
+    ///
+    /// auto writes them only if no other output is enabled. Default
+    /// value is auto
+    #[arg(long, env = "FACT_STDOUT")]
+    stdout: Option<StdoutMode>,
+
+    /// Format of the events written to stdout: fact or falco
+    ///
+    /// falco writes them as Falco alerts, for pipelines parsing them.
+    /// Default value is fact
+    #[arg(long, env = "FACT_STDOUT_FORMAT")]
+    stdout_format: Option<StdoutFormat>,
+
+    /// What to do when outputs can't keep up: drop or block
+    ///
+    /// drop makes slow outputs miss events, block makes the pipeline
+    /// wait for them and lets the ringbuffer fill up instead. Default
+    /// value is drop
+    #[arg(long, env = "FACT_OVERFLOW_POLICY")]
+    overflow_policy: Option<OverflowPolicy>,
+
+    /// Channels events are spread over by container ID for the outputs
+    ///
+    /// A container flooding its shard only makes outputs miss events of
+    /// that shard. Default value is 1, a single channel
+    #[arg(long, env = "FACT_OUTPUT_SHARDS", value_parser = clap::value_parser!(u64).range(1..))]
+    output_shards: Option<u64>,
+
+    /// Events each output shard holds for outputs falling behind
+    ///
+    /// Default value is 100
+    #[arg(long, env = "FACT_OUTPUT_SHARD_DEPTH", value_parser = clap::value_parser!(u64).range(1..))]
+    output_shard_depth: Option<u64>,
+
+    /// Force events to be output as JSON to stdout, same as --stdout on
     #[arg(long, short, overrides_with = "no_json", env = "FACT_JSON")]
     json: bool,
     #[arg(long, short, overrides_with = "json", hide(true))]
     no_json: bool,
 
-    /// Sets the size of the ringbuffer to be used in kilobytes
+    /// Sets the size of the ringbuffer to be used
     ///
+    /// Accepts a size like "64KB" or "8MB", a bare integer is taken
+    /// as kilobytes.
     /// The size must be a power of 2, preferably a multiple of the page
     /// size on the running system (usually 4KB).
     /// The minimum allowed size is 64KB.
     /// There is no maximum size, but it is recommended to keep this
     /// at a reasonable value.
     /// Default value is 8MB.
-    #[arg(long, short, env = "FACT_RINGBUF_SIZE")]
+    #[arg(long, short, env = "FACT_RINGBUF_SIZE", value_parser = parse_ringbuf_size)]
     ringbuf_size: Option<u32>,
 
     /// Sets the maximum number of inodes that can be tracked
@@ -826,19 +3392,38 @@ pub struct FactCli {
     #[arg(long, short, env = "FACT_INODES_MAX")]
     inodes_max: Option<u32>,
 
+    /// Whether the arguments of processes are included in events
+    ///
+    /// When disabled, the kernel does not copy the arguments at all.
+    ///
+    /// Default value is true
+    #[arg(long, env = "FACT_COLLECT_ARGS")]
+    collect_args: Option<bool>,
+
+    /// Whether events caused by fact itself are reported
+    ///
+    /// They are skipped in the kernel unless enabled, for debugging.
+    ///
+    /// Default value is false
+    #[arg(long, env = "FACT_REPORT_SELF")]
+    report_self: Option<bool>,
+
     /// Whether configuration should be hotreloaded
+    ///
+    /// When disabled, changes are still applied on SIGHUP
     #[arg(long, overrides_with = "no_hotreload", env = "FACT_HOTRELOAD")]
     hotreload: bool,
     #[arg(long, overrides_with = "hotreload", hide(true))]
     no_hotreload: bool,
 
     /// Interval at which scanning of monitored directories should
-    /// happen in seconds.
+    /// happen.
     ///
-    /// The seconds can use a decimal point for fractions of seconds.
+    /// Accepts a number of seconds, which can use a decimal point for
+    /// fractions of seconds, or a duration like "500ms" or "5m".
     ///
     /// Default value is 30 seconds
-    #[arg(long, short, env = "FACT_SCAN_INTERVAL", value_parser = parse_duration_secs)]
+    #[arg(long, short, env = "FACT_SCAN_INTERVAL", value_parser = parse_duration)]
     scan_interval: Option<Duration>,
 
     /// Maximum number of file events to allow per second
@@ -856,15 +3441,378 @@ pub struct FactCli {
     /// events for profiling purposes (e.g. valgrind, DHAT).
     #[arg(long, env = "FACT_REPLAY")]
     replay: Option<PathBuf>,
+
+    /// Time window during which events caused by checkpoint/restore
+    /// (CRIU) operations are suppressed.
+    ///
+    /// Accepts a number of seconds or a duration like "500ms".
+    /// The first such event is always reported and opens the window,
+    /// any other checkpoint/restore event arriving before the window
+    /// elapses is ignored. A value of 0 disables suppression, events
+    /// are still tagged with `checkpoint_restore`.
+    ///
+    /// Default value is 0 (no suppression)
+    #[arg(long, env = "FACT_CHECKPOINT_RESTORE_WINDOW", value_parser = parse_duration)]
+    checkpoint_restore_window: Option<Duration>,
+
+    /// Time events are held for to be reordered by timestamp
+    ///
+    /// Events from different CPUs can be read out of order. They are
+    /// held for this long after being read and released sorted by
+    /// their kernel timestamp, adding as much latency. Accepts a number
+    /// of seconds or a duration like "50ms". A value of 0 disables it.
+    ///
+    /// Default value is 0 (no reordering)
+    #[arg(long, env = "FACT_REORDER_WINDOW", value_parser = parse_duration)]
+    reorder_window: Option<Duration>,
+
+    /// Interval at which summaries of the events are sent
+    ///
+    /// Summaries count the events of each monitored path by event type
+    /// and whether they came from a container. Accepts a number of
+    /// seconds or a duration like "5m". A value of 0 disables them.
+    ///
+    /// Default value is 0
+    #[arg(long, env = "FACT_SUMMARY_INTERVAL", value_parser = parse_duration)]
+    summary_interval: Option<Duration>,
+
+    /// Only send summaries, dropping the events they count
+    ///
+    /// Meant for nodes with too many events to send them all, it has no
+    /// effect unless summary_interval is set.
+    #[arg(long, overrides_with = "no_summary_only", env = "FACT_SUMMARY_ONLY")]
+    summary_only: bool,
+    #[arg(long, overrides_with = "summary_only", hide(true))]
+    no_summary_only: bool,
+
+    /// Shut down after running for this long
+    ///
+    /// Accepts a number of seconds or a duration like "10m" or "1h".
+    /// Shutting down this way goes through the same path as stopping
+    /// fact with a signal, all pending events are sent to the outputs.
+    /// A value of 0 runs until stopped.
+    ///
+    /// Default value is 0
+    #[arg(long, env = "FACT_RUN_FOR", value_parser = parse_duration)]
+    run_for: Option<Duration>,
+
+    /// Shut down after forwarding this many events to the outputs
+    ///
+    /// A value of 0 means no limit.
+    ///
+    /// Default value is 0
+    #[arg(long, env = "FACT_MAX_EVENTS")]
+    max_events: Option<u64>,
+
+    /// Number of containers whose events are counted
+    ///
+    /// The busiest containers get an events_by_container series and
+    /// are listed at /debug/top-containers. A value of 0 disables the
+    /// counts.
+    ///
+    /// Default value is 20
+    #[arg(long, env = "FACT_TOP_CONTAINERS")]
+    top_containers: Option<u64>,
+
+    /// Deny write opens and unlinks on protected paths with `enforce`
+    /// set
+    ///
+    /// Protected paths can only be set in the configuration file.
+    /// Without this switch, operations on them are only reported.
+    #[arg(
+        long,
+        overrides_with = "no_enforcement_enabled",
+        env = "FACT_ENFORCEMENT_ENABLED"
+    )]
+    enforcement_enabled: bool,
+    #[arg(long, overrides_with = "enforcement_enabled", hide(true))]
+    no_enforcement_enabled: bool,
+
+    /// File locked to detect other instances of fact on the host
+    ///
+    /// It must be on a filesystem shared by all instances, like a
+    /// directory mounted from the host.
+    ///
+    /// Default value is /run/fact/fact.lock
+    #[arg(long, env = "FACT_LOCK_FILE")]
+    lock_file: Option<PathBuf>,
+
+    /// Keep running when another instance of fact holds the lock
+    ///
+    /// Events are tagged with the ID of this instance, otherwise fact
+    /// exits with code 3.
+    #[arg(
+        long,
+        overrides_with = "no_allow_multiple",
+        env = "FACT_ALLOW_MULTIPLE"
+    )]
+    allow_multiple: bool,
+    #[arg(long, overrides_with = "allow_multiple", hide(true))]
+    no_allow_multiple: bool,
+
+    /// Take the lock even if another instance of fact holds it
+    ///
+    /// Meant for recovering from an instance that is stuck, both
+    /// instances report events until the other one is stopped.
+    #[arg(long = "force")]
+    force_lock: bool,
+
+    /// File storing the ID of the node, kept across restarts
+    ///
+    /// The path is on the host. If the file can't be written, a new ID
+    /// is used on every start.
+    ///
+    /// Default value is /var/lib/fact/node_id
+    #[arg(long, env = "FACT_NODE_ID_FILE")]
+    node_id_file: Option<PathBuf>,
+
+    /// Where the filesystem of the host is mounted
+    ///
+    /// Host paths are read under it, like the monitored paths on
+    /// startup and /etc/passwd. It must be an existing directory.
+    ///
+    /// Default value is /
+    #[arg(long, env = "FACT_HOST_MOUNT")]
+    host_mount: Option<PathBuf>,
+
+    /// Whether unknown fields in the configuration files are an error
+    ///
+    /// When disabled, unknown fields are logged and ignored, so a
+    /// configuration meant for a newer version of fact can be used.
+    /// Default value is true
+    #[arg(long, env = "FACT_STRICT_CONFIG")]
+    strict_config: Option<bool>,
+
+    /// Print the configuration files in the order they are applied,
+    /// noting which of them exist, and exit
+    #[arg(long)]
+    print_config_files: bool,
+
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Debug, Subcommand)]
+enum Command {
+    /// Send recorded events to the configured outputs.
+    ///
+    /// Events are read from a JSONL file as produced by the stdout
+    /// output, no BPF programs are loaded.
+    Replay(ReplayArgs),
+
+    /// Report the files found in the monitored paths and exit.
+    ///
+    /// The monitored paths are walked once and an inventory event is
+    /// sent to the configured outputs for each file and directory
+    /// found, no BPF programs are loaded.
+    Scan(ScanArgs),
+
+    /// Send synthetic events to the configured outputs, for load
+    /// testing.
+    ///
+    /// No BPF programs are loaded, a summary of the throughput is
+    /// logged on exit. Use --run-for or --max-events to bound the
+    /// run.
+    #[command(hide = true)]
+    Generate(GenerateArgs),
+
+    /// Print the limits on what fact can capture and exit.
+    ///
+    /// Lists the limits fixed when fact was built and the ones set by
+    /// the current configuration. No BPF programs are loaded and no
+    /// privileges are required.
+    Limits(LimitsArgs),
+
+    /// Describe the configuration.
+    Config(ConfigArgs),
+
+    /// Print the events in a capture of raw ringbuffer records and
+    /// exit.
+    ///
+    /// Records are read one after the other, each preceded by its
+    /// length as a 32 bit little endian integer, and printed as JSON
+    /// lines. Values taken from the host the records were captured on
+    /// are left out unless given, or taken from this host with
+    /// --local.
+    Decode(DecodeArgs),
+}
+
+#[derive(Debug, Args)]
+struct DecodeArgs {
+    /// File holding the raw records
+    file: PathBuf,
+
+    /// The records were captured on this host, take its hostname,
+    /// boot and user names
+    #[arg(long, conflicts_with_all = ["hostname", "boot_id", "boot_time"])]
+    local: bool,
+
+    /// Hostname of the host the records were captured on
+    #[arg(long)]
+    hostname: Option<String>,
+
+    /// Boot ID of the host the records were captured on
+    #[arg(long)]
+    boot_id: Option<String>,
+
+    /// Time the host the records were captured on booted, in
+    /// nanoseconds since the epoch
+    #[arg(long)]
+    boot_time: Option<u64>,
+}
+
+impl DecodeArgs {
+    fn host(&self) -> HostContext {
+        if self.local {
+            return HostContext::local();
+        }
+        HostContext {
+            hostname: self
+                .hostname
+                .as_deref()
+                .map(event::intern)
+                .unwrap_or_default(),
+            boot_id: self
+                .boot_id
+                .as_deref()
+                .map(event::intern)
+                .unwrap_or_default(),
+            boot_time: self.boot_time.unwrap_or_default(),
+            ..Default::default()
+        }
+    }
+}
+
+#[derive(Debug, Args)]
+struct ConfigArgs {
+    #[command(subcommand)]
+    command: ConfigCommand,
+}
+
+#[derive(Debug, Subcommand)]
+enum ConfigCommand {
+    /// Print a JSON Schema of the configuration files and exit.
+    ///
+    /// The schema lists every field with its type, constraints and
+    /// default value, for tools generating configuration files.
+    Schema,
+}
+
+#[derive(Debug, Args)]
+struct LimitsArgs {
+    /// Print the limits as JSON instead of a table
+    #[arg(long)]
+    json: bool,
+}
+
+#[derive(Debug, Args)]
+struct GenerateArgs {
+    /// Events generated per second, 0 for as fast as possible
+    #[arg(long, default_value_t = 10_000)]
+    rate: u64,
+
+    /// Number of distinct file paths
+    #[arg(long, default_value_t = 1000, value_parser = clap::value_parser!(u32).range(1..))]
+    paths: u32,
+
+    /// Number of distinct processes
+    #[arg(long, default_value_t = 100, value_parser = clap::value_parser!(u32).range(1..))]
+    processes: u32,
+
+    /// Number of distinct containers processes run in
+    #[arg(long, default_value_t = 10)]
+    containers: u32,
+
+    /// Percentage of processes running on the host instead of in a
+    /// container
+    #[arg(long, default_value_t = 10, value_parser = clap::value_parser!(u8).range(0..=100))]
+    host_pct: u8,
+
+    /// Encode events in the ringbuffer format and parse them back,
+    /// exercising the same parsing as events from the kernel
+    #[arg(long)]
+    raw: bool,
+}
+
+#[derive(Debug, Args)]
+struct ScanArgs {
+    /// Stop after reporting this many entries
+    #[arg(long)]
+    limit: Option<u64>,
+}
+
+#[derive(Debug, Args)]
+struct ReplayArgs {
+    /// JSONL file holding the events to replay
+    file: PathBuf,
+
+    /// Keep the original time between events instead of sending
+    /// them as fast as possible
+    #[arg(long)]
+    pace: bool,
+
+    /// Set the timestamp of events to the time they are sent
+    #[arg(long)]
+    rewrite_timestamps: bool,
+
+    /// Hostname to set on all replayed events
+    #[arg(long)]
+    hostname: Option<String>,
+}
+
+/// Tools run from the command line in place of fact.
+///
+/// They are dispatched before the configuration is loaded, so they
+/// keep working when a configuration file or variable is invalid.
+#[derive(Debug)]
+pub enum Offline {
+    /// Print the configuration files fact would load.
+    PrintConfigFiles,
+    /// Print the JSON Schema of the configuration files.
+    Schema,
+    /// Print the events in a capture of raw ringbuffer records, as
+    /// captured on `host`.
+    Decode { file: PathBuf, host: HostContext },
+}
+
+impl Offline {
+    /// The tool requested on the command line, if any.
+    pub fn from_cli() -> Option<Self> {
+        FactCli::parse().offline()
+    }
 }
 
 impl FactCli {
+    fn offline(&self) -> Option<Offline> {
+        if self.print_config_files {
+            return Some(Offline::PrintConfigFiles);
+        }
+        match &self.command {
+            Some(Command::Config(ConfigArgs {
+                command: ConfigCommand::Schema,
+            })) => Some(Offline::Schema),
+            Some(Command::Decode(args)) => Some(Offline::Decode {
+                file: args.file.clone(),
+                host: args.host(),
+            }),
+            _ => None,
+        }
+    }
+
     fn into_config(self) -> FactConfig {
-        FactConfig {
+        let mut config = FactConfig {
             paths: self.paths,
+            paths_file: self.paths_file,
+            file_paths: None,
             grpc: GrpcConfig {
                 url: self.url,
                 certs: self.certs,
+                key_passphrase_file: self.key_passphrase_file,
+                token_file: self.token_file,
+                cluster_id: self.cluster_id,
+                compression: self.compression,
+                proxy: self.proxy,
+                cert_expiry_warning: self.cert_expiry_warning,
                 backoff: BackoffConfig {
                     initial: self.backoff_initial,
                     max: self.backoff_max,
@@ -875,16 +3823,48 @@ impl FactCli {
             },
             otel: OTelConfig {
                 endpoint: self.otel_endpoint,
+                traces: TracesConfig {
+                    endpoint: self.otel_traces_endpoint,
+                    sample_ratio: self.otel_traces_sample_ratio,
+                },
             },
             endpoint: EndpointConfig {
                 address: self.address,
+                metrics_address: self.metrics_address,
+                health_address: self.health_address,
                 expose_metrics: resolve_bool_arg(self.expose_metrics, self.no_expose_metrics),
                 health_check: resolve_bool_arg(self.health_check, self.no_health_check),
+                control_token: self.control_token,
+            },
+            readiness: ReadinessConfig {
+                drop_threshold: self.readiness_drop_threshold,
+                interval: self.readiness_interval,
+                degraded_after: self.readiness_degraded_after,
+                recover_after: self.readiness_recover_after,
+                fail_on_degraded: self.readiness_fail_on_degraded,
+                fail_on_paused: self.readiness_fail_on_paused,
+            },
+            maintenance: MaintenanceConfig {
+                cpu_budget_pct: self.maintenance_cpu_budget_pct,
+            },
+            enrich: EnrichConfig {
+                existence_check: self.enrich_existence_check,
+            },
+            output: OutputConfig {
+                stdout: self.stdout,
+                stdout_format: self.stdout_format,
+                overflow_policy: self.overflow_policy,
+                shards: self.output_shards,
+                shard_depth: self.output_shard_depth,
             },
             bpf: BpfConfig {
                 ringbuf_size: self.ringbuf_size,
+                ringbuf_min_size: None,
                 inodes_max: self.inodes_max,
+                collect_args: self.collect_args,
+                report_self: self.report_self,
                 programs: HashMap::new(),
+                filesystems: BTreeMap::new(),
             },
             skip_pre_flight: resolve_bool_arg(self.skip_pre_flight, self.no_skip_pre_flight),
             json: resolve_bool_arg(self.json, self.no_json),
@@ -892,7 +3872,72 @@ impl FactCli {
             scan_interval: self.scan_interval,
             rate_limit: self.rate_limit,
             replay: self.replay.clone(),
+            replay_options: ReplayOptions::default(),
+            checkpoint_restore_window: self.checkpoint_restore_window,
+            reorder_window: self.reorder_window,
+            summary_interval: self.summary_interval,
+            summary_only: resolve_bool_arg(self.summary_only, self.no_summary_only),
+            inventory: None,
+            inventory_limit: None,
+            generate: None,
+            generate_options: GenerateOptions::default(),
+            limits: None,
+            run_for: self.run_for,
+            max_events: self.max_events,
+            top_containers: self.top_containers,
+            protected_paths: None,
+            enforcement_enabled: resolve_bool_arg(
+                self.enforcement_enabled,
+                self.no_enforcement_enabled,
+            ),
+            path_labels: None,
+            sampling: None,
+            filters: None,
+            redact_args: None,
+            lock_file: self.lock_file,
+            allow_multiple: resolve_bool_arg(self.allow_multiple, self.no_allow_multiple),
+            force_lock: self.force_lock.then_some(true),
+            node_id_file: self.node_id_file,
+            host_mount: self.host_mount,
+            strict_config: self.strict_config,
+        };
+
+        match self.command {
+            Some(Command::Replay(args)) => {
+                config.replay = Some(args.file);
+                config.replay_options = ReplayOptions {
+                    pace: Some(args.pace),
+                    rewrite_timestamps: Some(args.rewrite_timestamps),
+                    hostname: args.hostname,
+                };
+            }
+            Some(Command::Scan(args)) => {
+                config.inventory = Some(true);
+                config.inventory_limit = args.limit;
+            }
+            Some(Command::Generate(args)) => {
+                config.generate = Some(true);
+                config.generate_options = GenerateOptions {
+                    rate: Some(args.rate),
+                    paths: Some(args.paths),
+                    processes: Some(args.processes),
+                    containers: Some(args.containers),
+                    host_pct: Some(args.host_pct),
+                    raw: Some(args.raw),
+                };
+            }
+            Some(Command::Limits(args)) => {
+                config.limits = Some(if args.json {
+                    LimitsFormat::Json
+                } else {
+                    LimitsFormat::Table
+                });
+            }
+            // Offline tools, run instead of building the configuration
+            Some(Command::Config(_)) | Some(Command::Decode(_)) | None => {}
         }
+
+        config
     }
 }
 
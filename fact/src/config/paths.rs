@@ -0,0 +1,344 @@
+//! Canonicalization of the monitored paths.
+//!
+//! Paths are normalized lexically when the configuration is loaded,
+//! without resolving symbolic links, since they refer to the host and
+//! not to the filesystem fact runs in. Duplicates and paths covered by
+//! a recursive glob on one of their parents are dropped, they would
+//! only take space in the kernel map without matching anything more.
+//!
+//! Paths can also be listed in the file set in `paths_file`, one per
+//! line, they are added to the ones in the configuration.
+
+use std::{
+    ffi::OsStr,
+    fs::read_to_string,
+    os::unix::ffi::OsStrExt,
+    path::{Component, Path, PathBuf},
+};
+
+use anyhow::{Context, bail};
+use log::{info, warn};
+
+use super::PathLabels;
+use crate::host_info;
+
+/// Resolve `.` and `..` components and collapse repeated separators.
+///
+/// Fails for relative paths, the kernel matches absolute paths only.
+pub fn normalize(path: &Path) -> anyhow::Result<PathBuf> {
+    if !path.is_absolute() {
+        bail!(
+            "invalid path {:?}: monitored paths must be absolute",
+            path.display().to_string()
+        );
+    }
+
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::RootDir => normalized.push("/"),
+            Component::CurDir | Component::Prefix(_) => {}
+            Component::ParentDir => {
+                normalized.pop();
+            }
+            Component::Normal(c) => normalized.push(c),
+        }
+    }
+    Ok(normalized)
+}
+
+fn is_glob(component: &OsStr) -> bool {
+    component
+        .as_bytes()
+        .iter()
+        .any(|c| matches!(c, b'*' | b'?' | b'[' | b'{'))
+}
+
+/// The directory a recursive glob like `/etc/**` or `/etc/**/*`
+/// matches everything under, if `path` is one.
+fn recursive_dir(path: &Path) -> Option<&Path> {
+    let dir = match path.file_name()?.as_bytes() {
+        b"**" => path.parent()?,
+        b"*" if path.parent()?.file_name()? == "**" => path.parent()?.parent()?,
+        _ => return None,
+    };
+    if dir.iter().any(is_glob) {
+        return None;
+    }
+    Some(dir)
+}
+
+/// The part of `path` before its first glob component.
+pub(crate) fn static_prefix(path: &Path) -> PathBuf {
+    path.iter().take_while(|c| !is_glob(c)).collect()
+}
+
+fn covered_by<'a>(path: &PathLabels, paths: &'a [PathLabels]) -> Option<&'a PathLabels> {
+    paths.iter().find(|p| {
+        p.path != path.path
+            && p.labels == path.labels
+            && recursive_dir(&p.path)
+                .is_some_and(|dir| path.path != dir && path.path.starts_with(dir))
+    })
+}
+
+/// Normalize the monitored paths, dropping duplicates and paths
+/// covered by others, and warn about the ones missing under
+/// `host_mount`.
+///
+/// A path covered by another one is only dropped if both have the same
+/// labels, since the longest match decides the labels of an event.
+pub fn canonicalize(paths: Vec<PathLabels>, host_mount: &Path) -> anyhow::Result<Vec<PathLabels>> {
+    let mut normalized: Vec<PathLabels> = Vec::with_capacity(paths.len());
+    for p in paths {
+        let path = normalize(&p.path)?;
+        if path != p.path {
+            info!("Monitoring {} as {}", p.path.display(), path.display());
+        }
+
+        // The last labels configured for a path win
+        if let Some(existing) = normalized.iter_mut().find(|e| e.path == path) {
+            info!("Ignoring duplicate monitored path {}", path.display());
+            existing.labels = p.labels;
+            continue;
+        }
+        normalized.push(PathLabels {
+            path,
+            labels: p.labels,
+        });
+    }
+
+    let mut canonical = Vec::with_capacity(normalized.len());
+    for p in normalized.iter() {
+        if let Some(parent) = covered_by(p, &normalized) {
+            info!(
+                "Ignoring monitored path {}, covered by {}",
+                p.path.display(),
+                parent.path.display()
+            );
+            continue;
+        }
+        canonical.push(p.clone());
+    }
+
+    for p in canonical.iter() {
+        let prefix = static_prefix(&p.path);
+        if !host_info::prepend_mount(host_mount, &prefix).exists() {
+            warn!(
+                "Monitored path {} does not exist on the host",
+                prefix.display()
+            );
+        }
+    }
+    Ok(canonical)
+}
+
+/// Parse the content of a paths file, skipping empty lines and
+/// comments.
+fn parse_file(content: &str) -> anyhow::Result<Vec<PathBuf>> {
+    content
+        .lines()
+        .enumerate()
+        .map(|(i, line)| (i + 1, line.trim()))
+        .filter(|(_, line)| !line.is_empty() && !line.starts_with('#'))
+        .map(|(n, line)| normalize(Path::new(line)).with_context(|| format!("line {n}")))
+        .collect()
+}
+
+/// Read the paths listed in `file`.
+pub fn read_file(file: &Path) -> anyhow::Result<Vec<PathBuf>> {
+    let content = read_to_string(file)
+        .with_context(|| format!("Failed to read paths file {}", file.display()))?;
+    parse_file(&content).with_context(|| format!("invalid paths file {}", file.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use super::*;
+
+    fn canonicalize_plain(paths: &[PathBuf]) -> anyhow::Result<Vec<PathBuf>> {
+        let paths = paths
+            .iter()
+            .map(|path| PathLabels {
+                path: path.clone(),
+                labels: BTreeMap::new(),
+            })
+            .collect();
+        Ok(canonicalize(paths, Path::new("/"))?
+            .into_iter()
+            .map(|p| p.path)
+            .collect())
+    }
+
+    fn paths(paths: &[&str]) -> Vec<PathBuf> {
+        paths.iter().map(PathBuf::from).collect()
+    }
+
+    fn labeled(path: &str, labels: &[(&str, &str)]) -> PathLabels {
+        PathLabels {
+            path: PathBuf::from(path),
+            labels: labels
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn normalization() {
+        let tests = [
+            ("/etc", "/etc"),
+            ("/etc/", "/etc"),
+            ("//etc///ssh", "/etc/ssh"),
+            ("/etc/./ssh/.", "/etc/ssh"),
+            ("/etc/ssh/../passwd", "/etc/passwd"),
+            ("/../etc", "/etc"),
+            ("/", "/"),
+            ("/etc/**/../*", "/etc/*"),
+            ("/usr//bin/*.sh", "/usr/bin/*.sh"),
+        ];
+        for (path, expected) in tests {
+            assert_eq!(
+                normalize(Path::new(path)).unwrap(),
+                PathBuf::from(expected),
+                "Failed for {path}"
+            );
+        }
+    }
+
+    #[test]
+    fn relative_paths() {
+        for path in ["etc", "./etc", "../etc", "**/*.conf", ""] {
+            let err = normalize(Path::new(path)).unwrap_err();
+            assert_eq!(
+                err.to_string(),
+                format!("invalid path {path:?}: monitored paths must be absolute")
+            );
+        }
+        assert!(canonicalize_plain(&paths(&["/etc", "etc"])).is_err());
+    }
+
+    #[test]
+    fn duplicates() {
+        let canonical = canonicalize_plain(&paths(&["/etc", "/bin", "/etc/", "//etc"])).unwrap();
+        assert_eq!(canonical, paths(&["/etc", "/bin"]));
+
+        let canonical = canonicalize(
+            vec![
+                labeled("/etc", &[("tier", "1")]),
+                labeled("/etc/./", &[("tier", "2")]),
+            ],
+            Path::new("/"),
+        )
+        .unwrap();
+        assert_eq!(canonical, [labeled("/etc", &[("tier", "2")])]);
+    }
+
+    #[test]
+    fn nesting() {
+        let tests = [
+            (
+                &["/etc/**", "/etc/ssh", "/etc/ssh/*.conf", "/usr/bin"][..],
+                &["/etc/**", "/usr/bin"][..],
+                "Recursive glob",
+            ),
+            (
+                &["/etc/ssh/sshd_config", "/etc/**/*"],
+                &["/etc/**/*"],
+                "Recursive glob after",
+            ),
+            (
+                &["/etc", "/etc/ssh", "/etc/passwd"],
+                &["/etc", "/etc/ssh", "/etc/passwd"],
+                "Plain paths only match themselves",
+            ),
+            (
+                &["/etc/*.conf", "/etc/ssh/sshd_config"],
+                &["/etc/*.conf", "/etc/ssh/sshd_config"],
+                "Non recursive glob",
+            ),
+            (
+                &["/etc/**", "/etc"],
+                &["/etc/**", "/etc"],
+                "The directory itself",
+            ),
+            (
+                &["/etc/**", "/etcd/data"],
+                &["/etc/**", "/etcd/data"],
+                "Component-wise prefix",
+            ),
+            (
+                &["/*/**", "/etc/passwd"],
+                &["/*/**", "/etc/passwd"],
+                "Glob in the directory",
+            ),
+        ];
+        for (input, expected, description) in tests {
+            assert_eq!(
+                canonicalize_plain(&paths(input)).unwrap(),
+                paths(expected),
+                "Failed for {description}"
+            );
+        }
+    }
+
+    #[test]
+    fn nesting_with_labels() {
+        let canonical = canonicalize(
+            vec![
+                labeled("/etc/**", &[("tier", "1")]),
+                labeled("/etc/ssh/**", &[("tier", "2")]),
+                labeled("/etc/pki/**", &[("tier", "1")]),
+                labeled("/etc/hosts", &[]),
+            ],
+            Path::new("/"),
+        )
+        .unwrap();
+        assert_eq!(
+            canonical,
+            [
+                labeled("/etc/**", &[("tier", "1")]),
+                labeled("/etc/ssh/**", &[("tier", "2")]),
+                labeled("/etc/hosts", &[]),
+            ]
+        );
+    }
+
+    #[test]
+    fn paths_file() {
+        let content = "\
+# Generated from the inventory
+/etc/ssh
+
+   /usr/bin/\t
+/var/log//*.log
+   # indented comment
+/etc/ssh
+";
+        assert_eq!(
+            parse_file(content).unwrap(),
+            paths(&["/etc/ssh", "/usr/bin", "/var/log/*.log", "/etc/ssh"])
+        );
+        assert!(parse_file("").unwrap().is_empty());
+        assert!(parse_file("# nothing\n\n").unwrap().is_empty());
+
+        let err = parse_file("/etc\n\nvar/log\n").unwrap_err();
+        assert_eq!(
+            format!("{err:#}"),
+            "line 3: invalid path \"var/log\": monitored paths must be absolute"
+        );
+
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("paths");
+        let err = read_file(&file).unwrap_err();
+        assert!(
+            format!("{err:#}")
+                .starts_with(&format!("Failed to read paths file {}", file.display())),
+            "{err:#}"
+        );
+        std::fs::write(&file, "/etc\n#/bin\n").unwrap();
+        assert_eq!(read_file(&file).unwrap(), paths(&["/etc"]));
+    }
+}
@@ -1,26 +1,147 @@
 use std::{
-    collections::HashMap, os::unix::fs::MetadataExt, path::PathBuf, sync::Arc, time::Duration,
+    collections::HashMap,
+    hash::{DefaultHasher, Hash, Hasher},
+    os::unix::fs::MetadataExt,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::{Duration, SystemTime, UNIX_EPOCH},
 };
 
 use log::{debug, info, warn};
 use tokio::{
     sync::{Notify, watch},
+    task::JoinHandle,
     time::interval,
 };
 
-use crate::config::OTelConfig;
+use crate::{config::OTelConfig, filter::Filter, redact::RedactPattern};
 
-use super::{CONFIG_FILES, EndpointConfig, FactConfig, GrpcConfig};
+use super::{
+    CONFIG_DIR, CONFIG_FILES, ConfigError, EndpointConfig, EnrichConfig, FactConfig, GrpcConfig,
+    MaintenanceConfig, PathLabels, ProtectedPath, ReadinessConfig, SamplingRule, SummaryConfig,
+    config_files, drop_in_files,
+};
+
+/// How often configuration files are checked for changes.
+const POLL_INTERVAL: Duration = Duration::from_secs(10);
+
+/// The configuration files in use and how the last reload went.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ReloadStatus {
+    /// Files the configuration is read from, with their modification
+    /// time in seconds since the epoch, sorted by path.
+    pub files: Vec<(PathBuf, i64)>,
+    /// Unset until the configuration changes after startup.
+    pub last_reload: Option<ReloadOutcome>,
+    /// Reloads that failed since startup, the configuration files
+    /// could not be parsed or the kernel rejected the new paths.
+    pub failures: u64,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ReloadOutcome {
+    /// Unix time of the reload.
+    pub timestamp: u64,
+    /// Why the new configuration was rejected, the previous one is
+    /// kept running when set.
+    pub error: Option<String>,
+    /// The error found in the configuration files, when that is why
+    /// the reload failed.
+    pub config_error: Option<ConfigError>,
+}
+
+impl ReloadStatus {
+    fn new(files: &HashMap<PathBuf, FileState>) -> Self {
+        let mut status = ReloadStatus::default();
+        status.set_files(files);
+        status
+    }
+
+    fn set_files(&mut self, files: &HashMap<PathBuf, FileState>) {
+        self.files = files
+            .iter()
+            .map(|(f, state)| (f.clone(), state.mtime))
+            .collect();
+        self.files.sort();
+    }
+
+    fn reloaded(&mut self, error: Option<String>, config_error: Option<ConfigError>) {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        if error.is_some() {
+            self.failures += 1;
+        }
+        self.last_reload = Some(ReloadOutcome {
+            timestamp,
+            error,
+            config_error,
+        });
+    }
+
+    /// Record that the last reload failed while being applied, after
+    /// the configuration files were parsed.
+    pub fn failed(&mut self, error: String) {
+        self.reloaded(Some(error), None);
+    }
+}
+
+/// What a configuration file is compared by to notice it changed.
+///
+/// The modification time is not enough on its own, kubelet updates
+/// ConfigMaps by pointing a `..data` symlink to a directory with the
+/// new files, which can keep the modification time of the previous
+/// ones, and clocks can be set back. The file the path resolves to
+/// follows the `..data` symlink, so swapping it changes the target.
+#[derive(Debug, Clone, PartialEq)]
+struct FileState {
+    /// Seconds since the epoch.
+    mtime: i64,
+    /// The file the path resolves to through all symlinks.
+    target: PathBuf,
+    size: u64,
+    /// Hash of the content.
+    hash: u64,
+}
+
+impl FileState {
+    fn read(file: &Path) -> std::io::Result<Self> {
+        let target = file.canonicalize()?;
+        let metadata = target.metadata()?;
+        let mut hasher = DefaultHasher::new();
+        std::fs::read(&target)?.hash(&mut hasher);
+        Ok(FileState {
+            mtime: metadata.mtime(),
+            target,
+            size: metadata.len(),
+            hash: hasher.finish(),
+        })
+    }
+}
 
 pub struct Reloader {
     config: FactConfig,
     endpoint: watch::Sender<EndpointConfig>,
+    readiness: watch::Sender<ReadinessConfig>,
+    maintenance: watch::Sender<MaintenanceConfig>,
+    enrich: watch::Sender<EnrichConfig>,
     grpc: watch::Sender<GrpcConfig>,
     otel: watch::Sender<OTelConfig>,
     paths: watch::Sender<Vec<PathBuf>>,
-    files: HashMap<&'static str, i64>,
+    protected_paths: watch::Sender<Vec<ProtectedPath>>,
+    path_labels: watch::Sender<Vec<PathLabels>>,
+    sampling: watch::Sender<Vec<SamplingRule>>,
+    filters: watch::Sender<Vec<Filter>>,
+    redact_args: watch::Sender<Vec<RedactPattern>>,
+    files: HashMap<PathBuf, FileState>,
     scan_interval: watch::Sender<Duration>,
     rate_limit: watch::Sender<u64>,
+    checkpoint_restore_window: watch::Sender<Duration>,
+    reorder_window: watch::Sender<Duration>,
+    summary: watch::Sender<SummaryConfig>,
+    digest: watch::Sender<String>,
+    status: watch::Sender<ReloadStatus>,
     trigger: Arc<Notify>,
 }
 
@@ -31,29 +152,43 @@ impl Reloader {
     /// forwarding the changes to any parts of the program that might
     /// need to take action accordingly.
     ///
-    /// If hotreload is disabled on startup the task will not be
-    /// spawned.
-    pub fn start(mut self, mut running: watch::Receiver<bool>) {
-        if !self.config.hotreload() {
-            info!("Configuration hotreload is disabled, changes will require a restart.");
-            return;
-        }
+    /// If hotreload is disabled on startup the configuration files are
+    /// not polled, the configuration is only reloaded when triggered.
+    pub fn start(mut self, running: watch::Receiver<bool>) -> JoinHandle<()> {
+        let poll_interval = if self.config.hotreload() {
+            Some(POLL_INTERVAL)
+        } else {
+            info!("Configuration hotreload is disabled, changes will only be applied on SIGHUP.");
+            None
+        };
+
+        tokio::spawn(async move { self.run(poll_interval, running, Self::reload).await })
+    }
 
-        tokio::spawn(async move {
-            let mut ticker = interval(Duration::from_secs(10));
-            loop {
-                tokio::select! {
-                    _ = ticker.tick() => self.reload(),
-                    _ = self.trigger.notified() => self.reload(),
-                    _ = running.changed() => {
-                        if !*running.borrow() {
-                            info!("Stopping config reloader...");
-                            return;
-                        }
+    /// Call `reload` every `poll_interval`, if set, and whenever the
+    /// trigger is notified, until `running` turns false.
+    async fn run(
+        &mut self,
+        poll_interval: Option<Duration>,
+        mut running: watch::Receiver<bool>,
+        mut reload: impl FnMut(&mut Self),
+    ) {
+        let mut ticker = interval(poll_interval.unwrap_or(POLL_INTERVAL));
+        loop {
+            tokio::select! {
+                _ = ticker.tick(), if poll_interval.is_some() => reload(self),
+                _ = self.trigger.notified() => {
+                    info!("Configuration reload requested");
+                    reload(self);
+                }
+                _ = running.changed() => {
+                    if !*running.borrow() {
+                        info!("Stopping config reloader...");
+                        return;
                     }
                 }
             }
-        });
+        }
     }
 
     pub fn config(&self) -> &FactConfig {
@@ -66,6 +201,24 @@ impl Reloader {
         self.endpoint.subscribe()
     }
 
+    /// Subscribe to get notifications when readiness configuration is
+    /// changed.
+    pub fn readiness(&self) -> watch::Receiver<ReadinessConfig> {
+        self.readiness.subscribe()
+    }
+
+    /// Subscribe to get notifications when maintenance configuration
+    /// is changed.
+    pub fn maintenance(&self) -> watch::Receiver<MaintenanceConfig> {
+        self.maintenance.subscribe()
+    }
+
+    /// Subscribe to get notifications when enrichment configuration is
+    /// changed.
+    pub fn enrich(&self) -> watch::Receiver<EnrichConfig> {
+        self.enrich.subscribe()
+    }
+
     /// Subscribe to get notifications when grpc configuration is
     /// changed.
     pub fn grpc(&self) -> watch::Receiver<GrpcConfig> {
@@ -84,6 +237,39 @@ impl Reloader {
         self.paths.subscribe()
     }
 
+    /// Subscribe to get notifications when the protected paths or the
+    /// enforcement switch are changed.
+    ///
+    /// The paths received already take the enforcement switch into
+    /// account.
+    pub fn protected_paths(&self) -> watch::Receiver<Vec<ProtectedPath>> {
+        self.protected_paths.subscribe()
+    }
+
+    /// Subscribe to get notifications when the labels for monitored
+    /// paths are changed.
+    pub fn path_labels(&self) -> watch::Receiver<Vec<PathLabels>> {
+        self.path_labels.subscribe()
+    }
+
+    /// Subscribe to get notifications when the sampling rules are
+    /// changed.
+    pub fn sampling(&self) -> watch::Receiver<Vec<SamplingRule>> {
+        self.sampling.subscribe()
+    }
+
+    /// Subscribe to get notifications when the event filters are
+    /// changed.
+    pub fn filters(&self) -> watch::Receiver<Vec<Filter>> {
+        self.filters.subscribe()
+    }
+
+    /// Subscribe to get notifications when the patterns redacted from
+    /// process arguments are changed.
+    pub fn redact_args(&self) -> watch::Receiver<Vec<RedactPattern>> {
+        self.redact_args.subscribe()
+    }
+
     /// Subscribe to get notifications when scan_interval configuration
     /// is changed.
     pub fn scan_interval(&self) -> watch::Receiver<Duration> {
@@ -96,6 +282,42 @@ impl Reloader {
         self.rate_limit.subscribe()
     }
 
+    /// Subscribe to get notifications when checkpoint_restore_window
+    /// configuration is changed.
+    pub fn checkpoint_restore_window(&self) -> watch::Receiver<Duration> {
+        self.checkpoint_restore_window.subscribe()
+    }
+
+    /// Subscribe to get notifications when reorder_window configuration
+    /// is changed.
+    pub fn reorder_window(&self) -> watch::Receiver<Duration> {
+        self.reorder_window.subscribe()
+    }
+
+    /// Subscribe to get notifications when summary_interval or
+    /// summary_only are changed.
+    pub fn summary(&self) -> watch::Receiver<SummaryConfig> {
+        self.summary.subscribe()
+    }
+
+    /// Subscribe to get notifications when the digest of the effective
+    /// configuration changes, which is the case for any change.
+    pub fn digest(&self) -> watch::Receiver<String> {
+        self.digest.subscribe()
+    }
+
+    /// Subscribe to the configuration files in use and the outcome of
+    /// the last reload.
+    pub fn status(&self) -> watch::Receiver<ReloadStatus> {
+        self.status.subscribe()
+    }
+
+    /// For the components applying the configuration to report the
+    /// reloads they fail to apply.
+    pub fn status_reporter(&self) -> watch::Sender<ReloadStatus> {
+        self.status.clone()
+    }
+
     /// Get a reference to the internal trigger for manual reloading of
     /// configuration.
     ///
@@ -105,43 +327,21 @@ impl Reloader {
         self.trigger.clone()
     }
 
-    /// Go through the configuration files and reload the modification
-    /// time for each of them.
+    /// Go through the configuration files, including the paths file,
+    /// and reload the state of each of them.
     ///
-    /// Returns true if any file has been modified.
+    /// Returns true if any file has been added, modified or removed.
     fn update_cache(&mut self) -> bool {
-        let mut res = false;
-
-        for file in CONFIG_FILES {
-            let path = PathBuf::from(file);
-            if path.exists() {
-                let mtime = match path.metadata() {
-                    Ok(m) => m.mtime(),
-                    Err(e) => {
-                        warn!("Failed to stat {file}: {e}");
-                        warn!("Configuration reloading may not work");
-                        continue;
-                    }
-                };
-                match self.files.get_mut(&file) {
-                    Some(old) if *old == mtime => {}
-                    Some(old) => {
-                        debug!("Updating '{file}'");
-                        res = true;
-                        *old = mtime;
-                    }
-                    None => {
-                        debug!("New configuration file '{file}'");
-                        res = true;
-                        self.files.insert(file, mtime);
-                    }
-                }
-            } else if self.files.contains_key(&file) {
-                debug!("'{file}' no longer exists, removing from cache");
-                res = true;
-                self.files.remove(&file);
-            }
+        let files = file_states(
+            &CONFIG_FILES,
+            Path::new(CONFIG_DIR),
+            self.config.paths_file(),
+        );
+        let res = files_changed(&self.files, &files);
+        if res {
+            self.status.send_modify(|status| status.set_files(&files));
         }
+        self.files = files;
         res
     }
 
@@ -152,14 +352,50 @@ impl Reloader {
             return;
         }
 
-        let new = match FactConfig::build() {
-            Ok(config) => config,
+        let (error, config_error) = match FactConfig::build(Some(&self.config)) {
+            Ok(config) => {
+                self.apply(config);
+                (None, None)
+            }
             Err(e) => {
                 warn!("Configuration reloading failed: {e}");
-                return;
+                (
+                    Some(format!("{e:#}")),
+                    e.downcast_ref::<ConfigError>().cloned(),
+                )
             }
         };
-        info!("Updated configuration: {new:#?}");
+        self.status
+            .send_modify(|status| status.reloaded(error, config_error));
+    }
+
+    /// Replace the current configuration with `new`, notifying
+    /// subscribers of the sections that changed.
+    ///
+    /// This is what a reload does once the configuration files have
+    /// been parsed, tests use it to switch configurations at a known
+    /// point in time.
+    pub fn apply(&mut self, new: FactConfig) {
+        let mut files = self
+            .files
+            .keys()
+            .map(|f| f.display().to_string())
+            .collect::<Vec<_>>();
+        files.sort();
+        let changes = self.config.diff(&new);
+        if changes.is_empty() {
+            info!(
+                "Configuration reloaded from [{}], nothing changed",
+                files.join(", ")
+            );
+        } else {
+            let changes = changes.iter().map(|c| format!("\n  {c}"));
+            info!(
+                "Configuration reloaded from [{}], changes:{}",
+                files.join(", "),
+                changes.collect::<String>()
+            );
+        }
 
         self.endpoint.send_if_modified(|old| {
             if *old != new.endpoint {
@@ -171,6 +407,36 @@ impl Reloader {
             }
         });
 
+        self.readiness.send_if_modified(|old| {
+            if *old != new.readiness {
+                debug!("Sending new readiness configuration...");
+                *old = new.readiness.clone();
+                true
+            } else {
+                false
+            }
+        });
+
+        self.maintenance.send_if_modified(|old| {
+            if *old != new.maintenance {
+                debug!("Sending new maintenance configuration...");
+                *old = new.maintenance.clone();
+                true
+            } else {
+                false
+            }
+        });
+
+        self.enrich.send_if_modified(|old| {
+            if *old != new.enrich {
+                debug!("Sending new enrichment configuration...");
+                *old = new.enrich.clone();
+                true
+            } else {
+                false
+            }
+        });
+
         self.grpc.send_if_modified(|old| {
             if *old != new.grpc {
                 debug!("Sending new gRPC configuration...");
@@ -202,6 +468,61 @@ impl Reloader {
             }
         });
 
+        self.protected_paths.send_if_modified(|old| {
+            let new = new.active_protected_paths();
+            if *old != new {
+                debug!("Sending new protected paths configuration...");
+                *old = new;
+                true
+            } else {
+                false
+            }
+        });
+
+        self.path_labels.send_if_modified(|old| {
+            let new = new.path_labels();
+            if *old != new {
+                debug!("Sending new path labels configuration...");
+                *old = new.to_vec();
+                true
+            } else {
+                false
+            }
+        });
+
+        self.sampling.send_if_modified(|old| {
+            let new = new.sampling();
+            if *old != new {
+                debug!("Sending new sampling configuration...");
+                *old = new.to_vec();
+                true
+            } else {
+                false
+            }
+        });
+
+        self.filters.send_if_modified(|old| {
+            let new = new.filters();
+            if *old != new {
+                debug!("Sending new filters configuration...");
+                *old = new.to_vec();
+                true
+            } else {
+                false
+            }
+        });
+
+        self.redact_args.send_if_modified(|old| {
+            let new = new.redact_args();
+            if *old != new {
+                debug!("Sending new redact_args configuration...");
+                *old = new.to_vec();
+                true
+            } else {
+                false
+            }
+        });
+
         self.scan_interval.send_if_modified(|old| {
             let new = new.scan_interval();
             if *old != new {
@@ -224,53 +545,385 @@ impl Reloader {
             }
         });
 
+        self.checkpoint_restore_window.send_if_modified(|old| {
+            let new = new.checkpoint_restore_window();
+            if *old != new {
+                debug!("Sending new checkpoint/restore window configuration...");
+                *old = new;
+                true
+            } else {
+                false
+            }
+        });
+
+        self.reorder_window.send_if_modified(|old| {
+            let new = new.reorder_window();
+            if *old != new {
+                debug!("Sending new reorder window configuration...");
+                *old = new;
+                true
+            } else {
+                false
+            }
+        });
+
+        self.summary.send_if_modified(|old| {
+            let new = new.summary();
+            if *old != new {
+                debug!("Sending new summary configuration...");
+                *old = new;
+                true
+            } else {
+                false
+            }
+        });
+
+        self.digest.send_if_modified(|old| {
+            let new = new.digest();
+            if *old != new {
+                debug!("Sending new configuration digest...");
+                *old = new;
+                true
+            } else {
+                false
+            }
+        });
+
         if self.config.hotreload() != new.hotreload() {
             warn!("Changes to the hotreload field only take effect on startup");
         }
 
+        if self.config.bpf != new.bpf {
+            warn!(
+                "Changes to the bpf section, including enabled programs, only take effect on startup"
+            );
+        }
+
         self.config = new;
     }
 }
 
+/// Whether any file was added, removed or changed between `old` and
+/// `new`.
+fn files_changed(old: &HashMap<PathBuf, FileState>, new: &HashMap<PathBuf, FileState>) -> bool {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs() as i64;
+    let mut res = false;
+    for (file, state) in new {
+        match old.get(file) {
+            Some(old) if old == state => continue,
+            Some(old) if old.target != state.target => {
+                debug!(
+                    "'{}' now points to '{}'",
+                    file.display(),
+                    state.target.display()
+                );
+            }
+            Some(old) if old.mtime == state.mtime => {
+                debug!("'{}' changed without its modification time", file.display());
+            }
+            Some(_) => debug!("Updating '{}'", file.display()),
+            None => debug!("New configuration file '{}'", file.display()),
+        }
+        if state.mtime > now {
+            warn!(
+                "Modification time of '{}' is {}s in the future, check the clock of the host",
+                file.display(),
+                state.mtime - now
+            );
+        }
+        res = true;
+    }
+    for file in old.keys().filter(|f| !new.contains_key(*f)) {
+        debug!("'{}' no longer exists, removing from cache", file.display());
+        res = true;
+    }
+    res
+}
+
+/// State of the existing configuration files in `candidates`, of the
+/// snippets in `dir` and of `paths_file`.
+fn file_states(
+    candidates: &[&str],
+    dir: &Path,
+    paths_file: Option<&Path>,
+) -> HashMap<PathBuf, FileState> {
+    config_files(candidates)
+        .filter(|(_, exists)| *exists)
+        .map(|(file, _)| PathBuf::from(file))
+        .chain(drop_in_files(dir))
+        .chain(paths_file.filter(|f| f.exists()).map(Path::to_path_buf))
+        .filter_map(|file| match FileState::read(&file) {
+            Ok(state) => Some((file, state)),
+            Err(e) => {
+                warn!("Failed to read {}: {e}", file.display());
+                warn!("Configuration reloading may not work");
+                None
+            }
+        })
+        .collect()
+}
+
 impl From<FactConfig> for Reloader {
     fn from(config: FactConfig) -> Self {
-        let files = CONFIG_FILES
-            .iter()
-            .filter_map(|path| {
-                let p = PathBuf::from(path);
-                if p.exists() {
-                    let mtime = match p.metadata() {
-                        Ok(m) => m.mtime(),
-                        Err(e) => {
-                            warn!("Failed to stat {path}: {e}");
-                            warn!("Configuration reloading may not work");
-                            return None;
-                        }
-                    };
-                    Some((*path, mtime))
-                } else {
-                    None
-                }
-            })
-            .collect();
+        let files = file_states(&CONFIG_FILES, Path::new(CONFIG_DIR), config.paths_file());
         let (endpoint, _) = watch::channel(config.endpoint.clone());
+        let (readiness, _) = watch::channel(config.readiness.clone());
+        let (maintenance, _) = watch::channel(config.maintenance.clone());
+        let (enrich, _) = watch::channel(config.enrich.clone());
         let (grpc, _) = watch::channel(config.grpc.clone());
         let (otel, _) = watch::channel(config.otel.clone());
         let (paths, _) = watch::channel(config.paths().to_vec());
+        let (protected_paths, _) = watch::channel(config.active_protected_paths());
+        let (path_labels, _) = watch::channel(config.path_labels().to_vec());
+        let (sampling, _) = watch::channel(config.sampling().to_vec());
+        let (filters, _) = watch::channel(config.filters().to_vec());
+        let (redact_args, _) = watch::channel(config.redact_args().to_vec());
         let (scan_interval, _) = watch::channel(config.scan_interval());
         let (rate_limit, _) = watch::channel(config.rate_limit());
+        let (checkpoint_restore_window, _) = watch::channel(config.checkpoint_restore_window());
+        let (reorder_window, _) = watch::channel(config.reorder_window());
+        let (summary, _) = watch::channel(config.summary());
+        let (digest, _) = watch::channel(config.digest());
+        let (status, _) = watch::channel(ReloadStatus::new(&files));
         let trigger = Arc::new(Notify::new());
 
         Reloader {
             config,
             endpoint,
+            readiness,
+            maintenance,
+            enrich,
             grpc,
             otel,
             paths,
+            protected_paths,
+            path_labels,
+            sampling,
+            filters,
+            redact_args,
             scan_interval,
             rate_limit,
+            checkpoint_restore_window,
+            reorder_window,
+            summary,
+            digest,
+            status,
             files,
             trigger,
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        fs::{File, write},
+        os::unix::fs::symlink,
+        time::SystemTime,
+    };
+
+    use tokio::{sync::mpsc, time::timeout};
+
+    use super::*;
+
+    fn touch(path: &Path, secs: u64) {
+        let mtime = SystemTime::UNIX_EPOCH + Duration::from_secs(secs);
+        File::options()
+            .write(true)
+            .open(path)
+            .unwrap()
+            .set_modified(mtime)
+            .unwrap();
+    }
+
+    fn mtimes(files: HashMap<PathBuf, FileState>) -> HashMap<PathBuf, i64> {
+        files
+            .into_iter()
+            .map(|(file, state)| (file, state.mtime))
+            .collect()
+    }
+
+    #[test]
+    fn drop_in_changes() {
+        let dir = tempfile::tempdir().unwrap();
+        let main = dir.path().join("fact.yml");
+        let snippets = dir.path().join("fact.d");
+        std::fs::create_dir(&snippets).unwrap();
+        let candidates = [main.to_str().unwrap()];
+        let mtimes = || mtimes(file_states(&candidates, &snippets, None));
+
+        write(&main, "paths: [/etc]").unwrap();
+        touch(&main, 1000);
+        assert_eq!(mtimes(), HashMap::from([(main.clone(), 1000)]));
+
+        // Snippets added, edited and removed are all noticed
+        let paths = snippets.join("10-paths.yml");
+        let grpc = snippets.join("20-grpc.yml");
+        write(&paths, "paths: [/usr/bin]").unwrap();
+        write(&grpc, "grpc:\n  url: https://svc:9090").unwrap();
+        write(snippets.join("README"), "not a snippet").unwrap();
+        touch(&paths, 1000);
+        touch(&grpc, 1000);
+        let added = mtimes();
+        assert_eq!(
+            added,
+            HashMap::from([
+                (main.clone(), 1000),
+                (paths.clone(), 1000),
+                (grpc.clone(), 1000),
+            ])
+        );
+
+        touch(&grpc, 2000);
+        let edited = mtimes();
+        assert_ne!(edited, added);
+        assert_eq!(edited[&grpc], 2000);
+
+        std::fs::remove_file(&paths).unwrap();
+        let removed = mtimes();
+        assert_eq!(
+            removed,
+            HashMap::from([(main.clone(), 1000), (grpc.clone(), 2000)])
+        );
+
+        // A missing directory has no snippets
+        std::fs::remove_dir_all(&snippets).unwrap();
+        assert_eq!(mtimes(), HashMap::from([(main, 1000)]));
+    }
+
+    #[test]
+    fn paths_file_changes() {
+        let dir = tempfile::tempdir().unwrap();
+        let snippets = dir.path().join("fact.d");
+        let paths_file = dir.path().join("paths");
+        let mtimes = || mtimes(file_states(&[], &snippets, Some(&paths_file)));
+
+        // A missing paths file is not tracked until it is created
+        assert!(mtimes().is_empty());
+        write(&paths_file, "/etc").unwrap();
+        touch(&paths_file, 1000);
+        assert_eq!(mtimes(), HashMap::from([(paths_file.clone(), 1000)]));
+
+        touch(&paths_file, 2000);
+        assert_eq!(mtimes(), HashMap::from([(paths_file.clone(), 2000)]));
+    }
+
+    /// kubelet updates a ConfigMap by writing the new files to a new
+    /// directory and swapping the `..data` symlink over to it, files
+    /// keeping their modification time are still noticed.
+    #[test]
+    fn configmap_swap() {
+        let dir = tempfile::tempdir().unwrap();
+        let mount = dir.path();
+        let snippets = mount.join("fact.d");
+        let main = mount.join("fact.yml");
+        let states = || file_states(&[main.to_str().unwrap()], &snippets, None);
+
+        let write_version = |version: &str, content: &str| {
+            let data = mount.join(version);
+            std::fs::create_dir(&data).unwrap();
+            let file = data.join("fact.yml");
+            write(&file, content).unwrap();
+            touch(&file, 1000);
+            let tmp = mount.join("..data_tmp");
+            symlink(version, &tmp).unwrap();
+            std::fs::rename(&tmp, mount.join("..data")).unwrap();
+        };
+        write_version("..2024_01_01", "paths: [/etc]");
+        symlink("..data/fact.yml", &main).unwrap();
+        let before = states();
+        assert_eq!(
+            mtimes(before.clone()),
+            HashMap::from([(main.clone(), 1000)])
+        );
+        assert!(!files_changed(&before, &states()));
+
+        // Same size and modification time, only the target changes
+        write_version("..2024_01_02", "paths: [/usr]");
+        std::fs::remove_dir_all(mount.join("..2024_01_01")).unwrap();
+        let after = states();
+        assert_eq!(mtimes(after.clone()), HashMap::from([(main.clone(), 1000)]));
+        assert!(files_changed(&before, &after));
+
+        // Content rewritten in place with the modification time kept
+        let file = mount.join("..2024_01_02/fact.yml");
+        write(&file, "paths: [/var]").unwrap();
+        touch(&file, 1000);
+        let rewritten = states();
+        assert_eq!(rewritten[&main].target, after[&main].target);
+        assert!(files_changed(&after, &rewritten));
+
+        // Files added to and removed from the ConfigMap
+        std::fs::create_dir(&snippets).unwrap();
+        let snippet = snippets.join("10-grpc.yml");
+        write(&snippet, "grpc:\n  url: https://svc:9090").unwrap();
+        let added = states();
+        assert!(files_changed(&rewritten, &added));
+        std::fs::remove_file(&snippet).unwrap();
+        assert!(files_changed(&added, &states()));
+    }
+
+    /// Run a reloader, reporting each reload on the returned channel.
+    fn run(
+        poll_interval: Option<Duration>,
+    ) -> (
+        Arc<Notify>,
+        watch::Sender<bool>,
+        mpsc::UnboundedReceiver<()>,
+        JoinHandle<()>,
+    ) {
+        let mut reloader = Reloader::from(FactConfig::default());
+        let trigger = reloader.get_trigger();
+        let (running_tx, running) = watch::channel(true);
+        let (reload_tx, reloads) = mpsc::unbounded_channel();
+        let handle = tokio::spawn(async move {
+            reloader
+                .run(poll_interval, running, |_| reload_tx.send(()).unwrap())
+                .await
+        });
+        (trigger, running_tx, reloads, handle)
+    }
+
+    async fn reloaded(reloads: &mut mpsc::UnboundedReceiver<()>) -> bool {
+        timeout(Duration::from_millis(100), reloads.recv())
+            .await
+            .is_ok()
+    }
+
+    #[tokio::test]
+    async fn trigger_with_hotreload() {
+        let (trigger, running, mut reloads, handle) = run(Some(Duration::from_millis(10)));
+
+        // Files are polled
+        assert!(reloaded(&mut reloads).await);
+        assert!(reloaded(&mut reloads).await);
+
+        // And SIGHUP reloads them right away too
+        trigger.notify_one();
+        assert!(reloaded(&mut reloads).await);
+
+        running.send(false).unwrap();
+        handle.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn trigger_without_hotreload() {
+        let (trigger, running, mut reloads, handle) = run(None);
+
+        // Files are not polled
+        assert!(!reloaded(&mut reloads).await);
+
+        // SIGHUP still reloads them, once per signal
+        trigger.notify_one();
+        assert!(reloaded(&mut reloads).await);
+        assert!(!reloaded(&mut reloads).await);
+        trigger.notify_one();
+        assert!(reloaded(&mut reloads).await);
+
+        running.send(false).unwrap();
+        handle.await.unwrap();
+    }
+}
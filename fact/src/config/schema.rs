@@ -0,0 +1,879 @@
+//! The fields of the configuration files.
+//!
+//! `FIELDS` lists every field the configuration files accept, along
+//! with its type and default value. Fields missing from it are unknown
+//! to the parser, the environment layer reads a variable for each of
+//! them, and `fact config schema` prints them as a JSON Schema for the
+//! tools generating configuration files.
+
+use std::{fmt, time::Duration};
+
+use serde_json::{Map, Value, json};
+use yaml_rust2::yaml;
+
+use super::{ConfigError, FactConfig, unknown_field};
+use crate::event::FileData;
+
+/// Type of the value of a field.
+#[derive(Debug, Clone, Copy)]
+pub enum Type {
+    Bool,
+    /// An integer between `min` and `max`, both included.
+    Int {
+        min: i64,
+        max: i64,
+    },
+    /// A number greater than `exclusive_min`.
+    Number {
+        exclusive_min: f64,
+    },
+    /// A number between 0 and 1, both included.
+    Ratio,
+    Str,
+    /// One of the strings listed.
+    Enum(&'static [&'static str]),
+    /// A number of seconds or a number with a ms, s, m or h suffix.
+    Duration {
+        positive: bool,
+    },
+    /// The size of the ringbuffer, a number of KB or a number with a
+    /// unit suffix, which must be a power of two.
+    RingbufSize,
+    /// A string or a list of strings, separated by the character in
+    /// environment variables.
+    List(char),
+    /// A structured value, described by the JSON Schema `schema`
+    /// returns. With a separator, it can be set as a list of strings
+    /// separated by it in environment variables.
+    Structured {
+        schema: fn() -> Value,
+        separator: Option<char>,
+    },
+}
+
+/// A field of the configuration files.
+pub struct Field {
+    /// Sections the field is in, followed by its name.
+    pub path: &'static [&'static str],
+    pub ty: Type,
+    /// Value used when the field is not set, taken from the default
+    /// configuration. `Null` if unset fields have no value.
+    pub default: fn(&FactConfig) -> Value,
+    pub description: &'static str,
+}
+
+fn no_default(_: &FactConfig) -> Value {
+    Value::Null
+}
+
+/// Durations are printed with the largest unit they are a whole
+/// number of.
+fn duration(d: Duration) -> Value {
+    let ms = d.as_millis();
+    let value = match ms {
+        0 => "0s".to_owned(),
+        ms if ms % 3_600_000 == 0 => format!("{}h", ms / 3_600_000),
+        ms if ms % 60_000 == 0 => format!("{}m", ms / 60_000),
+        ms if ms % 1000 == 0 => format!("{}s", ms / 1000),
+        ms => format!("{ms}ms"),
+    };
+    Value::String(value)
+}
+
+fn size_kb(kb: u32) -> Value {
+    let value = match kb {
+        kb if kb % (1024 * 1024) == 0 => format!("{}GB", kb / (1024 * 1024)),
+        kb if kb % 1024 == 0 => format!("{}MB", kb / 1024),
+        kb => format!("{kb}KB"),
+    };
+    Value::String(value)
+}
+
+/// Enumerations are configured with the lowercase name of their
+/// variants.
+fn variant(v: impl fmt::Debug) -> Value {
+    Value::String(format!("{v:?}").to_lowercase())
+}
+
+fn paths_schema() -> Value {
+    json!({
+        "type": ["array", "null"],
+        "items": {
+            "oneOf": [
+                {"type": "string"},
+                {
+                    "type": "object",
+                    "properties": {
+                        "path": {"type": "string"},
+                        "labels": {
+                            "type": ["object", "null"],
+                            "additionalProperties": {"type": "string"},
+                        },
+                    },
+                    "required": ["path"],
+                    "additionalProperties": false,
+                },
+            ],
+        },
+    })
+}
+
+fn protected_paths_schema() -> Value {
+    json!({
+        "type": ["array", "null"],
+        "items": {
+            "type": "object",
+            "properties": {
+                "path": {"type": "string"},
+                "enforce": {"type": "boolean", "default": false},
+            },
+            "required": ["path"],
+            "additionalProperties": false,
+        },
+    })
+}
+
+fn sampling_schema() -> Value {
+    json!({
+        "type": ["array", "null"],
+        "items": {
+            "type": "object",
+            "properties": {
+                "event": {"enum": FileData::EVENT_TYPES},
+                "path": {"type": "string"},
+                "rate": {"type": "string", "pattern": "^1/[0-9]+$"},
+            },
+            "required": ["event", "rate"],
+            "additionalProperties": false,
+        },
+    })
+}
+
+fn filters_schema() -> Value {
+    json!({
+        "type": ["array", "null"],
+        "items": {
+            "type": "object",
+            "properties": {
+                "action": {"enum": ["drop", "keep"], "default": "drop"},
+                "expr": {"type": "string"},
+            },
+            "required": ["expr"],
+            "additionalProperties": false,
+        },
+    })
+}
+
+fn redact_args_schema() -> Value {
+    json!({
+        "type": ["array", "null"],
+        "items": {"type": "string", "format": "regex"},
+    })
+}
+
+fn programs_schema() -> Value {
+    json!({
+        "type": "object",
+        "additionalProperties": {
+            "type": "object",
+            "properties": {
+                "enabled": {"type": "boolean"},
+            },
+            "additionalProperties": false,
+        },
+    })
+}
+
+fn filesystems_schema() -> Value {
+    json!({
+        "type": "object",
+        "additionalProperties": {
+            "enum": ["supported", "partial", "unsupported"],
+        },
+    })
+}
+
+pub const FIELDS: &[Field] = &[
+    Field {
+        path: &["paths"],
+        ty: Type::Structured {
+            schema: paths_schema,
+            separator: Some(':'),
+        },
+        default: no_default,
+        description: "Paths to monitor, as a path or glob, or a map with the path and the labels added to its events",
+    },
+    Field {
+        path: &["paths_file"],
+        ty: Type::Str,
+        default: no_default,
+        description: "File with more paths to monitor, one per line",
+    },
+    Field {
+        path: &["grpc", "url"],
+        ty: Type::List(','),
+        default: no_default,
+        description: "Endpoints of the gRPC server, tried in order until one accepts the connection",
+    },
+    Field {
+        path: &["grpc", "certs"],
+        ty: Type::Str,
+        default: no_default,
+        description: "Directory holding the mTLS certificates and keys, fd:<n> for an inherited descriptor or a name in $CREDENTIALS_DIRECTORY",
+    },
+    Field {
+        path: &["grpc", "key_passphrase_file"],
+        ty: Type::Str,
+        default: no_default,
+        description: "File holding the passphrase of an encrypted private key",
+    },
+    Field {
+        path: &["grpc", "token_file"],
+        ty: Type::Str,
+        default: no_default,
+        description: "File holding a bearer token sent to the server",
+    },
+    Field {
+        path: &["grpc", "cluster_id"],
+        ty: Type::Str,
+        default: no_default,
+        description: "Cluster fact runs in, printable ASCII characters only",
+    },
+    Field {
+        path: &["grpc", "compression"],
+        ty: Type::Enum(&["none", "gzip", "zstd"]),
+        default: |c| variant(c.grpc.compression()),
+        description: "Compression of the events sent to the server",
+    },
+    Field {
+        path: &["grpc", "proxy"],
+        ty: Type::Str,
+        default: no_default,
+        description: "HTTP proxy for the connection to the server, HTTPS_PROXY is used when not set",
+    },
+    Field {
+        path: &["grpc", "cert_expiry_warning"],
+        ty: Type::Duration { positive: false },
+        default: |c| duration(c.grpc.cert_expiry_warning()),
+        description: "Warn when a certificate expires within this window",
+    },
+    Field {
+        path: &["grpc", "backoff", "initial"],
+        ty: Type::Duration { positive: true },
+        default: |c| duration(c.grpc.backoff.initial()),
+        description: "Delay before the first reconnection attempt",
+    },
+    Field {
+        path: &["grpc", "backoff", "max"],
+        ty: Type::Duration { positive: true },
+        default: |c| duration(c.grpc.backoff.max()),
+        description: "Longest delay between reconnection attempts",
+    },
+    Field {
+        path: &["grpc", "backoff", "jitter"],
+        ty: Type::Bool,
+        default: |c| json!(c.grpc.backoff.jitter()),
+        description: "Randomize the delay between reconnection attempts",
+    },
+    Field {
+        path: &["grpc", "backoff", "multiplier"],
+        ty: Type::Number { exclusive_min: 1.0 },
+        default: |c| json!(c.grpc.backoff.multiplier()),
+        description: "Factor the delay grows by after each failed attempt",
+    },
+    Field {
+        path: &["grpc", "backoff", "retries"],
+        ty: Type::Int {
+            min: 0,
+            max: i64::MAX,
+        },
+        default: |c| json!(c.grpc.backoff.retries()),
+        description: "Failed attempts before moving to the next endpoint",
+    },
+    Field {
+        path: &["otel", "endpoint"],
+        ty: Type::Str,
+        default: no_default,
+        description: "OTLP endpoint events are exported to",
+    },
+    Field {
+        path: &["otel", "traces", "endpoint"],
+        ty: Type::Str,
+        default: no_default,
+        description: "OTLP endpoint the spans of the pipeline are exported to, with the tracing feature",
+    },
+    Field {
+        path: &["otel", "traces", "sample_ratio"],
+        ty: Type::Ratio,
+        default: |c| json!(c.otel.traces.sample_ratio()),
+        description: "Share of the traces exported",
+    },
+    Field {
+        path: &["endpoint", "address"],
+        ty: Type::List(','),
+        default: |c| {
+            let addresses = c.endpoint.addresses().iter();
+            json!(addresses.map(|a| a.to_string()).collect::<Vec<_>>())
+        },
+        description: "Addresses the HTTP endpoint listens on, serving all the endpoints",
+    },
+    Field {
+        path: &["endpoint", "metrics_address"],
+        ty: Type::Str,
+        default: no_default,
+        description: "Address only serving the metrics and the status, no default address is listened on when set",
+    },
+    Field {
+        path: &["endpoint", "health_address"],
+        ty: Type::Str,
+        default: no_default,
+        description: "Address only serving the health checks, no default address is listened on when set",
+    },
+    Field {
+        path: &["endpoint", "expose_metrics"],
+        ty: Type::Bool,
+        default: |c| json!(c.endpoint.expose_metrics()),
+        description: "Serve the Prometheus metrics",
+    },
+    Field {
+        path: &["endpoint", "health_check"],
+        ty: Type::Bool,
+        default: |c| json!(c.endpoint.health_check()),
+        description: "Serve the health check endpoints",
+    },
+    Field {
+        path: &["endpoint", "control_token"],
+        ty: Type::Str,
+        default: no_default,
+        description: "Bearer token required by the control endpoints, disabled when not set",
+    },
+    Field {
+        path: &["readiness", "drop_threshold"],
+        ty: Type::Int {
+            min: 0,
+            max: i64::MAX,
+        },
+        default: |c| json!(c.readiness.drop_threshold()),
+        description: "Events dropped in an interval before it is degraded, 0 disables the policy",
+    },
+    Field {
+        path: &["readiness", "interval"],
+        ty: Type::Duration { positive: true },
+        default: |c| duration(c.readiness.interval()),
+        description: "Period drops are sampled over",
+    },
+    Field {
+        path: &["readiness", "degraded_after"],
+        ty: Type::Int {
+            min: 1,
+            max: i64::MAX,
+        },
+        default: |c| json!(c.readiness.degraded_after()),
+        description: "Degraded intervals in a row before fact is reported degraded",
+    },
+    Field {
+        path: &["readiness", "recover_after"],
+        ty: Type::Int {
+            min: 1,
+            max: i64::MAX,
+        },
+        default: |c| json!(c.readiness.recover_after()),
+        description: "Healthy intervals in a row before fact is reported ready again",
+    },
+    Field {
+        path: &["readiness", "fail_on_degraded"],
+        ty: Type::Bool,
+        default: |c| json!(c.readiness.fail_on_degraded()),
+        description: "Fail readiness while degraded",
+    },
+    Field {
+        path: &["readiness", "fail_on_paused"],
+        ty: Type::Bool,
+        default: |c| json!(c.readiness.fail_on_paused()),
+        description: "Fail readiness while event collection is paused",
+    },
+    Field {
+        path: &["maintenance", "cpu_budget_pct"],
+        ty: Type::Int { min: 1, max: 100 },
+        default: |c| json!(c.maintenance.cpu_budget_pct()),
+        description: "Percentage of a core maintenance work may use, 100 disables pacing",
+    },
+    Field {
+        path: &["enrich", "existence_check"],
+        ty: Type::Bool,
+        default: |c| json!(c.enrich.existence_check()),
+        description: "Check if the files of open and creation events still exist",
+    },
+    Field {
+        path: &["output", "stdout"],
+        ty: Type::Enum(&["auto", "on", "off"]),
+        default: |c| variant(c.stdout()),
+        description: "Write events to stdout, auto does it when no other output is configured",
+    },
+    Field {
+        path: &["output", "stdout_format"],
+        ty: Type::Enum(&["fact", "falco"]),
+        default: |c| variant(c.output.stdout_format()),
+        description: "Format of the events written to stdout, falco writes them as Falco alerts",
+    },
+    Field {
+        path: &["output", "overflow_policy"],
+        ty: Type::Enum(&["drop", "block"]),
+        default: |c| variant(c.output.overflow_policy()),
+        description: "Drop events for outputs that can't keep up, or block the pipeline until they do",
+    },
+    Field {
+        path: &["output", "shards"],
+        ty: Type::Int {
+            min: 1,
+            max: i64::MAX,
+        },
+        default: |c| json!(c.output.shards()),
+        description: "Channels events are spread over by container ID, so a flood in one only drops its events",
+    },
+    Field {
+        path: &["output", "shard_depth"],
+        ty: Type::Int {
+            min: 1,
+            max: i64::MAX,
+        },
+        default: |c| json!(c.output.shard_depth()),
+        description: "Events each output shard holds for outputs falling behind",
+    },
+    Field {
+        path: &["skip_pre_flight"],
+        ty: Type::Bool,
+        default: |c| json!(c.skip_pre_flight()),
+        description: "Skip the checks of the host run on startup",
+    },
+    Field {
+        path: &["json"],
+        ty: Type::Bool,
+        default: no_default,
+        description: "Deprecated, use output.stdout instead",
+    },
+    Field {
+        path: &["bpf", "ringbuf_size"],
+        ty: Type::RingbufSize,
+        default: |c| size_kb(c.bpf.ringbuf_size()),
+        description: "Size of the ringbuffer events are read from, a power of two",
+    },
+    Field {
+        path: &["bpf", "ringbuf_min_size"],
+        ty: Type::RingbufSize,
+        default: |c| size_kb(c.bpf.ringbuf_min_size()),
+        description: "Smallest size the ringbuffer is shrunk to when the kernel is low on memory",
+    },
+    Field {
+        path: &["bpf", "inodes_max"],
+        ty: Type::Int {
+            min: 0,
+            max: u32::MAX as i64,
+        },
+        default: |c| json!(c.bpf.inodes_max()),
+        description: "Most inodes tracked in the kernel",
+    },
+    Field {
+        path: &["bpf", "collect_args"],
+        ty: Type::Bool,
+        default: |c| json!(c.bpf.collect_args()),
+        description: "Include the arguments of processes in events",
+    },
+    Field {
+        path: &["bpf", "report_self"],
+        ty: Type::Bool,
+        default: |c| json!(c.bpf.report_self()),
+        description: "Report events caused by fact itself",
+    },
+    Field {
+        path: &["bpf", "programs"],
+        ty: Type::Structured {
+            schema: programs_schema,
+            separator: None,
+        },
+        default: no_default,
+        description: "BPF programs to enable or disable, by hook name",
+    },
+    Field {
+        path: &["bpf", "filesystems"],
+        ty: Type::Structured {
+            schema: filesystems_schema,
+            separator: None,
+        },
+        default: no_default,
+        description: "Support of filesystem types by the hooks, by the type in the mount table",
+    },
+    Field {
+        path: &["hotreload"],
+        ty: Type::Bool,
+        default: |c| json!(c.hotreload()),
+        description: "Reload the configuration when it changes, it is always reloaded on SIGHUP",
+    },
+    Field {
+        path: &["scan_interval"],
+        ty: Type::Duration { positive: false },
+        default: |c| duration(c.scan_interval()),
+        description: "Period of the scans of the monitored paths, 0 disables them",
+    },
+    Field {
+        path: &["rate_limit"],
+        ty: Type::Int {
+            min: 0,
+            max: i64::MAX,
+        },
+        default: |c| json!(c.rate_limit()),
+        description: "Most events sent per second, 0 for no limit",
+    },
+    Field {
+        path: &["replay"],
+        ty: Type::Str,
+        default: no_default,
+        description: "JSONL file with the events to send instead of reading them from the kernel",
+    },
+    Field {
+        path: &["checkpoint_restore_window"],
+        ty: Type::Duration { positive: false },
+        default: |c| duration(c.checkpoint_restore_window()),
+        description: "Window events of checkpoint/restore tools are suppressed in, 0 disables it",
+    },
+    Field {
+        path: &["reorder_window"],
+        ty: Type::Duration { positive: false },
+        default: |c| duration(c.reorder_window()),
+        description: "Time events are held for to be released sorted by kernel timestamp, 0 disables it",
+    },
+    Field {
+        path: &["summary_interval"],
+        ty: Type::Duration { positive: false },
+        default: |c| duration(c.summary().interval),
+        description: "Period of the summaries of the events, 0 disables them",
+    },
+    Field {
+        path: &["summary_only"],
+        ty: Type::Bool,
+        default: |c| json!(c.summary().only),
+        description: "Send the summaries instead of the events they count",
+    },
+    Field {
+        path: &["run_for"],
+        ty: Type::Duration { positive: false },
+        default: |c| duration(c.run_for().unwrap_or_default()),
+        description: "Time after which fact stops, 0 runs until stopped",
+    },
+    Field {
+        path: &["max_events"],
+        ty: Type::Int {
+            min: 0,
+            max: i64::MAX,
+        },
+        default: |c| json!(c.max_events().unwrap_or_default()),
+        description: "Events after which fact stops, 0 for no limit",
+    },
+    Field {
+        path: &["top_containers"],
+        ty: Type::Int {
+            min: 0,
+            max: i64::MAX,
+        },
+        default: |c| json!(c.top_containers()),
+        description: "Busiest containers whose events are counted, 0 disables the counts",
+    },
+    Field {
+        path: &["protected_paths"],
+        ty: Type::Structured {
+            schema: protected_paths_schema,
+            separator: None,
+        },
+        default: no_default,
+        description: "Paths operations are denied on, when enforced and enforcement_enabled is set",
+    },
+    Field {
+        path: &["sampling"],
+        ty: Type::Structured {
+            schema: sampling_schema,
+            separator: None,
+        },
+        default: no_default,
+        description: "Rules keeping a fraction of the events of a type",
+    },
+    Field {
+        path: &["filters"],
+        ty: Type::Structured {
+            schema: filters_schema,
+            separator: None,
+        },
+        default: no_default,
+        description: "Expressions selecting the events to drop or keep",
+    },
+    Field {
+        path: &["redact_args"],
+        ty: Type::Structured {
+            schema: redact_args_schema,
+            separator: None,
+        },
+        default: no_default,
+        description: "Regular expressions replaced with *** in the arguments of processes",
+    },
+    Field {
+        path: &["enforcement_enabled"],
+        ty: Type::Bool,
+        default: |c| json!(c.enforcement_enabled()),
+        description: "Deny the operations on the enforced protected paths",
+    },
+    Field {
+        path: &["lock_file"],
+        ty: Type::Str,
+        default: |c| json!(c.lock_file()),
+        description: "File locked to detect other instances of fact on the host",
+    },
+    Field {
+        path: &["allow_multiple"],
+        ty: Type::Bool,
+        default: |c| json!(c.allow_multiple()),
+        description: "Keep running when another instance holds the lock",
+    },
+    Field {
+        path: &["node_id_file"],
+        ty: Type::Str,
+        default: |c| json!(c.node_id_file()),
+        description: "File storing the ID of the node, kept across restarts",
+    },
+    Field {
+        path: &["host_mount"],
+        ty: Type::Str,
+        default: |c| json!(c.host_mount()),
+        description: "Where the filesystem of the host is mounted, an existing directory",
+    },
+    Field {
+        path: &["strict_config"],
+        ty: Type::Bool,
+        default: |c| json!(c.strict_config()),
+        description: "Fail on unknown fields in the configuration files instead of ignoring them",
+    },
+];
+
+/// Report the fields in `value` that are not in `FIELDS` as unknown.
+///
+/// `section` is the path to `value` in the configuration. Fields known
+/// to be there are left to the parser, even if their type is wrong.
+pub fn check_fields(section: &[&str], value: &yaml::Hash) -> Result<(), ConfigError> {
+    for (k, v) in value {
+        // Keys that are not strings are reported by the parser
+        let Some(k) = k.as_str() else {
+            continue;
+        };
+        let path = [section, &[k]].concat();
+
+        if FIELDS.iter().any(|f| f.path == path) {
+            continue;
+        }
+        if FIELDS.iter().any(|f| f.path.starts_with(&path)) {
+            if let Some(v) = v.as_hash() {
+                check_fields(&path, v)?;
+            }
+            continue;
+        }
+        unknown_field(&path.join("."), v)?;
+    }
+    Ok(())
+}
+
+impl Type {
+    fn schema(self) -> Value {
+        const DURATION: &str = "^[0-9]+(\\.[0-9]+)? *([mM][sS]|[sSmMhH])?$";
+        const SIZE: &str = "^[0-9]+ *([bB]|[kKmMgG]([iI]?[bB])?)?$";
+
+        match self {
+            Type::Bool => json!({"type": "boolean"}),
+            Type::Int { min, max } => json!({"type": "integer", "minimum": min, "maximum": max}),
+            Type::Number { exclusive_min } => {
+                json!({"type": "number", "exclusiveMinimum": exclusive_min})
+            }
+            Type::Ratio => json!({"type": "number", "minimum": 0, "maximum": 1}),
+            Type::Str => json!({"type": "string"}),
+            Type::Enum(values) => json!({"enum": values}),
+            Type::Duration { positive } => json!({
+                "oneOf": [
+                    {"type": "number", "minimum": 0},
+                    {"type": "string", "pattern": DURATION},
+                ],
+                "x-fact-positive": positive,
+            }),
+            Type::RingbufSize => json!({
+                "oneOf": [
+                    {"type": "integer", "minimum": 1},
+                    {"type": "string", "pattern": SIZE},
+                ],
+                "x-fact-power-of-two": true,
+            }),
+            Type::List(_) => json!({
+                "oneOf": [
+                    {"type": "string"},
+                    {"type": "array", "items": {"type": "string"}, "minItems": 1},
+                ],
+            }),
+            Type::Structured { schema, .. } => schema(),
+        }
+    }
+}
+
+/// JSON Schema of the configuration files.
+///
+/// Constraints JSON Schema can't express are added as `x-fact-*`
+/// keywords: durations that must not be zero, and sizes that must be a
+/// power of two.
+pub fn json_schema() -> Value {
+    let defaults = FactConfig::default();
+    let mut root = Map::new();
+    for field in FIELDS {
+        let mut schema = field.ty.schema();
+        let object = schema.as_object_mut().unwrap();
+        object.insert("description".into(), field.description.into());
+        let default = (field.default)(&defaults);
+        if !default.is_null() {
+            object.insert("default".into(), default);
+        }
+
+        let (name, sections) = field.path.split_last().unwrap();
+        let mut properties = &mut root;
+        for section in sections {
+            let section = properties.entry(*section).or_insert_with(
+                || json!({"type": "object", "additionalProperties": false, "properties": {}}),
+            );
+            properties = section["properties"].as_object_mut().unwrap();
+        }
+        properties.insert(name.to_string(), schema);
+    }
+
+    json!({
+        "$schema": "https://json-schema.org/draft/2020-12/schema",
+        "title": "fact configuration",
+        "type": ["object", "null"],
+        "additionalProperties": false,
+        "properties": root,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use yaml_rust2::Yaml;
+
+    use super::*;
+
+    /// Nest `value` in the sections of `path`.
+    fn nest(path: &[&str], value: Yaml) -> Yaml {
+        path.iter().rev().fold(value, |value, key| {
+            let mut hash = yaml::Hash::new();
+            hash.insert(Yaml::String(key.to_string()), value);
+            Yaml::Hash(hash)
+        })
+    }
+
+    #[test]
+    fn known_fields() {
+        // Every field in the schema must be parsed, a field the parser
+        // knows but the schema doesn't is rejected as unknown, which
+        // the parsing tests would notice
+        for field in FIELDS {
+            if let Err(e) = FactConfig::try_from(vec![nest(field.path, Yaml::Null)]) {
+                assert!(
+                    !format!("{e:#}").contains("Invalid field"),
+                    "{:?}: {e:#}",
+                    field.path
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn unknown_fields() {
+        let sections = FIELDS
+            .iter()
+            .map(|f| &f.path[..f.path.len() - 1])
+            .collect::<Vec<_>>();
+        for section in sections {
+            let path = [section, &["unknown"]].concat();
+            let err = FactConfig::try_from(vec![nest(&path, Yaml::Integer(1))]).unwrap_err();
+            assert_eq!(
+                err.to_string(),
+                format!("Invalid field '{}' with value: Integer(1)", path.join("."))
+            );
+        }
+
+        // Fields of structured values are checked by their parsers
+        let err = FactConfig::try_from("paths: [{path: /etc, unknown: 1}]").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Invalid field 'paths.unknown' with value: Integer(1)"
+        );
+    }
+
+    #[test]
+    fn schema() {
+        let schema = json_schema();
+        let properties = &schema["properties"];
+
+        // Every field is in the schema, and nothing else
+        fn count(properties: &Value) -> usize {
+            properties
+                .as_object()
+                .unwrap()
+                .values()
+                .map(|p| match p.get("description") {
+                    Some(_) => 1,
+                    None => count(&p["properties"]),
+                })
+                .sum()
+        }
+        assert_eq!(count(properties), FIELDS.len());
+
+        // Defaults come from the configuration
+        let defaults = [
+            (&properties["hotreload"], json!(true)),
+            (&properties["scan_interval"], json!("30s")),
+            (
+                &properties["bpf"]["properties"]["ringbuf_size"],
+                json!("8MB"),
+            ),
+            (
+                &properties["grpc"]["properties"]["compression"],
+                json!("none"),
+            ),
+            (
+                &properties["grpc"]["properties"]["cert_expiry_warning"],
+                json!("168h"),
+            ),
+            (
+                &properties["grpc"]["properties"]["backoff"]["properties"]["multiplier"],
+                json!(1.5),
+            ),
+            (&properties["output"]["properties"]["stdout"], json!("auto")),
+            (&properties["lock_file"], json!("/run/fact/fact.lock")),
+            (
+                &properties["endpoint"]["properties"]["address"],
+                json!(["[::]:9000"]),
+            ),
+        ];
+        for (property, default) in defaults {
+            assert_eq!(property["default"], default, "{property}");
+        }
+        assert!(properties["paths_file"].get("default").is_none());
+
+        // Constraints
+        let cpu = &properties["maintenance"]["properties"]["cpu_budget_pct"];
+        assert_eq!(cpu["minimum"], json!(1));
+        assert_eq!(cpu["maximum"], json!(100));
+        assert_eq!(
+            properties["bpf"]["properties"]["ringbuf_size"]["x-fact-power-of-two"],
+            json!(true)
+        );
+        assert_eq!(
+            properties["readiness"]["properties"]["interval"]["x-fact-positive"],
+            json!(true)
+        );
+        assert_eq!(
+            properties["sampling"]["items"]["properties"]["event"]["enum"],
+            json!(FileData::EVENT_TYPES)
+        );
+    }
+}
@@ -0,0 +1,316 @@
+//! Typed configuration values with human friendly units.
+//!
+//! Sizes and durations can be written with a unit suffix, like "8MB"
+//! or "500ms". Bare numbers keep the meaning they had before units
+//! were supported, seconds for durations and a per field unit for
+//! sizes, so existing configurations keep working.
+
+use std::{fmt::Display, str::FromStr, time::Duration};
+
+use anyhow::bail;
+use yaml_rust2::Yaml;
+
+const DURATION_FORMS: &str = r#"expected a number of seconds or a number with a ms, s, m or h suffix, e.g. "500ms", "10s", "5m""#;
+const SIZE_FORMS: &str =
+    r#"expected an integer or an integer with a B, KB, MB or GB suffix, e.g. "64KB", "8MB""#;
+
+/// Number of bytes in a kilobyte.
+pub const KB: u64 = 1024;
+
+/// Split a value into its numeric part and its unit suffix.
+fn split_unit(s: &str) -> (&str, &str) {
+    let s = s.trim();
+    let idx = s.find(|c: char| c.is_ascii_alphabetic()).unwrap_or(s.len());
+    let (value, unit) = s.split_at(idx);
+    (value.trim_end(), unit)
+}
+
+/// A duration, parsed from a number of seconds or a number followed
+/// by one of the ms, s, m or h units.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct DurationValue(Duration);
+
+impl DurationValue {
+    fn parse(s: &str, display: impl Display) -> anyhow::Result<Self> {
+        let (value, unit) = split_unit(s);
+        let scale = match unit.to_ascii_lowercase().as_str() {
+            "ms" => 0.001,
+            "" | "s" => 1.0,
+            "m" => 60.0,
+            "h" => 3600.0,
+            _ => bail!("{display} is not a valid duration, {DURATION_FORMS}"),
+        };
+        let Ok(value) = value.parse::<f64>() else {
+            bail!("{display} is not a valid duration, {DURATION_FORMS}");
+        };
+        if !value.is_finite() {
+            bail!("{display} is not a valid duration, {DURATION_FORMS}");
+        }
+        if value < 0.0 {
+            bail!("{display} is negative, {DURATION_FORMS}");
+        }
+        let Ok(duration) = Duration::try_from_secs_f64(value * scale) else {
+            bail!("{display} is too large for a duration");
+        };
+        Ok(DurationValue(duration))
+    }
+}
+
+impl FromStr for DurationValue {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        DurationValue::parse(s, format_args!("{s:?}"))
+    }
+}
+
+impl TryFrom<&Yaml> for DurationValue {
+    type Error = anyhow::Error;
+
+    fn try_from(value: &Yaml) -> Result<Self, Self::Error> {
+        match value {
+            Yaml::Integer(i) => DurationValue::parse(&i.to_string(), i),
+            Yaml::Real(s) => DurationValue::parse(s, s),
+            Yaml::String(s) => s.parse(),
+            v => bail!("{v:?} is not a valid duration, {DURATION_FORMS}"),
+        }
+    }
+}
+
+impl From<DurationValue> for Duration {
+    fn from(value: DurationValue) -> Self {
+        value.0
+    }
+}
+
+/// A size in bytes, parsed from an integer or an integer followed by
+/// one of the B, KB, MB or GB units.
+///
+/// Units are powers of 1024, KiB, MiB and GiB are accepted as aliases.
+/// Since bare integers mean different units for different fields, the
+/// unit used for them needs to be provided when parsing.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct ByteSize(u64);
+
+impl ByteSize {
+    pub fn as_bytes(&self) -> u64 {
+        self.0
+    }
+
+    /// Parse a size, using `bare_unit` bytes as the unit for values
+    /// without a suffix.
+    pub fn parse(s: &str, bare_unit: u64) -> anyhow::Result<Self> {
+        ByteSize::parse_inner(s, bare_unit, format_args!("{s:?}"))
+    }
+
+    /// Parse a size from YAML, using `bare_unit` bytes as the unit for
+    /// integers and strings without a suffix.
+    pub fn from_yaml(value: &Yaml, bare_unit: u64) -> anyhow::Result<Self> {
+        match value {
+            Yaml::Integer(i) => ByteSize::parse_inner(&i.to_string(), bare_unit, i),
+            Yaml::String(s) => ByteSize::parse(s, bare_unit),
+            v => bail!("{v:?} is not a valid size, {SIZE_FORMS}"),
+        }
+    }
+
+    fn parse_inner(s: &str, bare_unit: u64, display: impl Display) -> anyhow::Result<Self> {
+        let (value, unit) = split_unit(s);
+        let scale = match unit.to_ascii_lowercase().as_str() {
+            "" => bare_unit,
+            "b" => 1,
+            "k" | "kb" | "kib" => KB,
+            "m" | "mb" | "mib" => KB * KB,
+            "g" | "gb" | "gib" => KB * KB * KB,
+            _ => bail!("{display} is not a valid size, {SIZE_FORMS}"),
+        };
+        if value.starts_with('-') && value[1..].chars().all(|c| c.is_ascii_digit()) {
+            bail!("{display} is negative, {SIZE_FORMS}");
+        }
+        let Ok(value) = value.parse::<u64>() else {
+            bail!("{display} is not a valid size, {SIZE_FORMS}");
+        };
+        let Some(bytes) = value.checked_mul(scale) else {
+            bail!("{display} is too large for a size");
+        };
+        Ok(ByteSize(bytes))
+    }
+}
+
+impl FromStr for ByteSize {
+    type Err = anyhow::Error;
+
+    /// Parse a size, bare integers are taken as bytes.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        ByteSize::parse(s, 1)
+    }
+}
+
+impl TryFrom<&Yaml> for ByteSize {
+    type Error = anyhow::Error;
+
+    /// Parse a size from YAML, bare integers are taken as bytes.
+    fn try_from(value: &Yaml) -> Result<Self, Self::Error> {
+        ByteSize::from_yaml(value, 1)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn durations() {
+        let tests = [
+            ("0", Duration::ZERO),
+            ("10", Duration::from_secs(10)),
+            ("1.5", Duration::from_millis(1500)),
+            ("500ms", Duration::from_millis(500)),
+            ("0.5ms", Duration::from_micros(500)),
+            ("10s", Duration::from_secs(10)),
+            ("10S", Duration::from_secs(10)),
+            ("5m", Duration::from_secs(300)),
+            ("1.5m", Duration::from_secs(90)),
+            ("2h", Duration::from_secs(7200)),
+            (" 10 s ", Duration::from_secs(10)),
+        ];
+        for (input, expected) in tests {
+            let parsed: DurationValue = input.parse().expect(input);
+            assert_eq!(Duration::from(parsed), expected, "{input}");
+        }
+    }
+
+    #[test]
+    fn duration_errors() {
+        let tests = [
+            (
+                "",
+                format!(r#""" is not a valid duration, {DURATION_FORMS}"#),
+            ),
+            (
+                "ms",
+                format!(r#""ms" is not a valid duration, {DURATION_FORMS}"#),
+            ),
+            (
+                "10x",
+                format!(r#""10x" is not a valid duration, {DURATION_FORMS}"#),
+            ),
+            (
+                "10 sec",
+                format!(r#""10 sec" is not a valid duration, {DURATION_FORMS}"#),
+            ),
+            (
+                "1d",
+                format!(r#""1d" is not a valid duration, {DURATION_FORMS}"#),
+            ),
+            (
+                "inf",
+                format!(r#""inf" is not a valid duration, {DURATION_FORMS}"#),
+            ),
+            ("-1", format!(r#""-1" is negative, {DURATION_FORMS}"#)),
+            ("-5m", format!(r#""-5m" is negative, {DURATION_FORMS}"#)),
+            (
+                "99999999999999999999h",
+                r#""99999999999999999999h" is too large for a duration"#.to_string(),
+            ),
+        ];
+        for (input, expected) in tests {
+            let err = input.parse::<DurationValue>().unwrap_err();
+            assert_eq!(err.to_string(), expected);
+        }
+    }
+
+    #[test]
+    fn sizes() {
+        let tests = [
+            ("0", 1, 0),
+            ("64", 1, 64),
+            ("64", KB, 64 * KB),
+            ("64B", KB, 64),
+            ("64KB", 1, 64 * KB),
+            ("64kb", 1, 64 * KB),
+            ("64K", 1, 64 * KB),
+            ("64KiB", 1, 64 * KB),
+            ("8MB", 1, 8 * KB * KB),
+            ("8 MiB", 1, 8 * KB * KB),
+            ("2GB", 1, 2 * KB * KB * KB),
+        ];
+        for (input, bare_unit, expected) in tests {
+            let parsed = ByteSize::parse(input, bare_unit).expect(input);
+            assert_eq!(parsed.as_bytes(), expected, "{input}");
+        }
+    }
+
+    #[test]
+    fn size_errors() {
+        let tests = [
+            ("", format!(r#""" is not a valid size, {SIZE_FORMS}"#)),
+            (
+                "1.5MB",
+                format!(r#""1.5MB" is not a valid size, {SIZE_FORMS}"#),
+            ),
+            ("8TB", format!(r#""8TB" is not a valid size, {SIZE_FORMS}"#)),
+            (
+                "8 megs",
+                format!(r#""8 megs" is not a valid size, {SIZE_FORMS}"#),
+            ),
+            ("-64KB", format!(r#""-64KB" is negative, {SIZE_FORMS}"#)),
+            (
+                "18446744073709551616",
+                format!(r#""18446744073709551616" is not a valid size, {SIZE_FORMS}"#),
+            ),
+            (
+                "18446744073709551615KB",
+                r#""18446744073709551615KB" is too large for a size"#.to_string(),
+            ),
+        ];
+        for (input, expected) in tests {
+            let err = input.parse::<ByteSize>().unwrap_err();
+            assert_eq!(err.to_string(), expected);
+        }
+    }
+
+    #[test]
+    fn yaml_values() {
+        let tests = [
+            (Yaml::Integer(10), Ok(Duration::from_secs(10))),
+            (Yaml::Real("0.25".into()), Ok(Duration::from_millis(250))),
+            (Yaml::String("250ms".into()), Ok(Duration::from_millis(250))),
+            (
+                Yaml::Integer(-1),
+                Err(format!("-1 is negative, {DURATION_FORMS}")),
+            ),
+            (
+                Yaml::Boolean(true),
+                Err(format!(
+                    "Boolean(true) is not a valid duration, {DURATION_FORMS}"
+                )),
+            ),
+        ];
+        for (input, expected) in tests {
+            let parsed = DurationValue::try_from(&input)
+                .map(Duration::from)
+                .map_err(|e| e.to_string());
+            assert_eq!(parsed, expected, "{input:?}");
+        }
+
+        let tests = [
+            (Yaml::Integer(64), Ok(64 * KB)),
+            (Yaml::String("64".into()), Ok(64 * KB)),
+            (Yaml::String("1MB".into()), Ok(KB * KB)),
+            (
+                Yaml::Integer(-1),
+                Err(format!("-1 is negative, {SIZE_FORMS}")),
+            ),
+            (
+                Yaml::Real("1.5".into()),
+                Err(format!(r#"Real("1.5") is not a valid size, {SIZE_FORMS}"#)),
+            ),
+        ];
+        for (input, expected) in tests {
+            let parsed = ByteSize::from_yaml(&input, KB)
+                .map(|s| s.as_bytes())
+                .map_err(|e| e.to_string());
+            assert_eq!(parsed, expected, "{input:?}");
+        }
+    }
+}
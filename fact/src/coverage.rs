@@ -0,0 +1,484 @@
+//! Coverage of the monitored paths by the BPF programs.
+//!
+//! The hooks fact attaches to do not see everything on every
+//! filesystem: changes made to a network filesystem from other hosts
+//! never go through the kernel of this one, and files on FUSE
+//! filesystems may get events without a path. The filesystem of each
+//! monitored prefix is looked up on startup and whenever the paths are
+//! reloaded, the ones not fully supported are warned about and all of
+//! them are exported as `path_coverage`.
+//!
+//! The type is read with statfs(2) on the prefix under the host mount,
+//! the mount table then tells apart types sharing a magic number, like
+//! the subtypes of FUSE. `bpf.filesystems` overrides `SUPPORT`.
+
+use std::{
+    collections::BTreeMap,
+    ffi::CString,
+    io, mem,
+    os::unix::ffi::OsStrExt,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+use anyhow::bail;
+use log::{debug, info, warn};
+use tokio::{sync::watch, task::JoinHandle};
+
+use crate::{
+    config::paths::static_prefix,
+    host_info,
+    metrics::config::ConfigMetrics,
+    mount_info::{MountEntry, MountInfo},
+};
+
+/// How well the hooks see the changes to files on a filesystem.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Support {
+    Supported,
+    /// Changes made from other hosts are not seen.
+    Partial,
+    /// Events may be missed or have no path.
+    Unsupported,
+    /// The filesystem is not in `SUPPORT`.
+    Unknown,
+}
+
+impl Support {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Support::Supported => "supported",
+            Support::Partial => "partial",
+            Support::Unsupported => "unsupported",
+            Support::Unknown => "unknown",
+        }
+    }
+}
+
+impl FromStr for Support {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "supported" => Ok(Support::Supported),
+            "partial" => Ok(Support::Partial),
+            "unsupported" => Ok(Support::Unsupported),
+            s => bail!("unknown support {s:?}, expected one of: supported, partial, unsupported"),
+        }
+    }
+}
+
+/// Filesystem types by the magic number statfs(2) reports, with the
+/// names the mount table gives them. The first name is used for mounts
+/// missing from the table.
+const MAGICS: &[(u32, &[&str])] = &[
+    (0xEF53, &["ext4", "ext3", "ext2"]),
+    (0x5846_5342, &["xfs"]),
+    (0x9123_683E, &["btrfs"]),
+    (0x0102_1994, &["tmpfs", "devtmpfs"]),
+    (0x794C_7630, &["overlay"]),
+    (0x2FC1_2FC1, &["zfs"]),
+    (0xF2F5_2010, &["f2fs"]),
+    (0x8584_58F6, &["ramfs"]),
+    (0x7371_7368, &["squashfs"]),
+    (0x6969, &["nfs", "nfs4"]),
+    (0xFF53_4D42, &["cifs", "smb3"]),
+    (0xFE53_4D42, &["smb3", "cifs"]),
+    (0x00C3_6400, &["ceph"]),
+    (0x0102_1997, &["9p"]),
+    (0x6573_5546, &["fuse", "fuseblk", "virtiofs"]),
+    (0x9FA0, &["proc"]),
+    (0x6265_6572, &["sysfs"]),
+];
+
+/// Support of the hooks by filesystem type, subtypes like `fuse.sshfs`
+/// get the one of their type unless listed.
+const SUPPORT: &[(&str, Support)] = &[
+    ("ext2", Support::Supported),
+    ("ext3", Support::Supported),
+    ("ext4", Support::Supported),
+    ("xfs", Support::Supported),
+    ("btrfs", Support::Supported),
+    ("tmpfs", Support::Supported),
+    ("overlay", Support::Supported),
+    ("zfs", Support::Supported),
+    ("f2fs", Support::Supported),
+    ("ramfs", Support::Supported),
+    ("squashfs", Support::Supported),
+    ("nfs", Support::Partial),
+    ("nfs4", Support::Partial),
+    ("cifs", Support::Partial),
+    ("smb3", Support::Partial),
+    ("ceph", Support::Partial),
+    ("9p", Support::Partial),
+    ("virtiofs", Support::Partial),
+    // bpf_d_path fails on files of some FUSE daemons, and changes made
+    // behind the daemon are not seen
+    ("fuse", Support::Unsupported),
+    ("fuseblk", Support::Unsupported),
+];
+
+/// Access to the filesystem for the checks, so tests can simulate it.
+pub trait FsStat {
+    /// Magic number of the filesystem `path` is on.
+    fn magic(&self, path: &Path) -> io::Result<u32>;
+    fn mounts(&self) -> io::Result<MountInfo>;
+}
+
+pub struct SystemFsStat;
+
+impl FsStat for SystemFsStat {
+    fn magic(&self, path: &Path) -> io::Result<u32> {
+        let path = CString::new(path.as_os_str().as_bytes())?;
+        let mut stat: libc::statfs = unsafe { mem::zeroed() };
+        if unsafe { libc::statfs(path.as_ptr(), &mut stat) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        // Magic numbers fit in 32 bits, f_type is signed on some
+        // architectures
+        Ok(stat.f_type as u32)
+    }
+
+    fn mounts(&self) -> io::Result<MountInfo> {
+        MountInfo::read()
+    }
+}
+
+/// Filesystem of a monitored path.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PathCoverage {
+    /// The monitored path, as configured.
+    pub path: PathBuf,
+    pub fs_type: String,
+    pub support: Support,
+}
+
+/// Whether the mount table name `fs_type` is one of `names`, or a
+/// subtype of one.
+fn is_named(names: &[&str], fs_type: &str) -> bool {
+    names.iter().any(|name| {
+        fs_type
+            .strip_prefix(name)
+            .is_some_and(|rest| rest.is_empty() || rest.starts_with('.'))
+    })
+}
+
+/// Name of the filesystem with `magic`, from the mount table when it
+/// agrees with statfs.
+fn fs_type(magic: u32, mount: Option<&MountEntry>) -> String {
+    let mount_type = mount.map(|m| m.fs_type.as_str());
+    match MAGICS.iter().find(|(m, _)| *m == magic) {
+        Some((_, names)) => match mount_type {
+            Some(t) if is_named(names, t) => t.to_owned(),
+            _ => names[0].to_owned(),
+        },
+        // A known type in the table belongs to a parent mount, the
+        // mount of the path is missing from it
+        None => match mount_type {
+            Some(t) if !MAGICS.iter().any(|(_, names)| is_named(names, t)) => t.to_owned(),
+            _ => format!("0x{magic:x}"),
+        },
+    }
+}
+
+fn support(fs_type: &str, overrides: &BTreeMap<String, Support>) -> Support {
+    let base = fs_type.split_once('.').map_or(fs_type, |(base, _)| base);
+    [fs_type, base]
+        .into_iter()
+        .find_map(|t| {
+            overrides.get(t).copied().or_else(|| {
+                SUPPORT
+                    .iter()
+                    .find(|(name, _)| *name == t)
+                    .map(|(_, support)| *support)
+            })
+        })
+        .unwrap_or(Support::Unknown)
+}
+
+/// Look up the filesystem of each path in `paths`, under `host_mount`.
+///
+/// Paths that don't exist yet are on the filesystem of their closest
+/// existing parent. Paths without any are left out.
+pub fn check(
+    paths: &[PathBuf],
+    host_mount: &Path,
+    overrides: &BTreeMap<String, Support>,
+    stat: &impl FsStat,
+) -> Vec<PathCoverage> {
+    let mounts = stat.mounts().unwrap_or_else(|e| {
+        warn!("Failed to read the mount table: {e}");
+        MountInfo::default()
+    });
+
+    let mut coverage = Vec::with_capacity(paths.len());
+    for path in paths {
+        let prefix = host_info::prepend_mount(host_mount, &static_prefix(path));
+        let Some((dir, magic)) = prefix
+            .ancestors()
+            .find_map(|p| Some((p, stat.magic(p).ok()?)))
+        else {
+            warn!("Failed to find the filesystem of {}", path.display());
+            continue;
+        };
+        let fs_type = fs_type(magic, mounts.find(dir));
+        coverage.push(PathCoverage {
+            path: path.clone(),
+            support: support(&fs_type, overrides),
+            fs_type,
+        });
+    }
+    coverage
+}
+
+fn report(coverage: &[PathCoverage], metrics: &ConfigMetrics) {
+    for c in coverage {
+        let path = c.path.display();
+        let fs_type = &c.fs_type;
+        match c.support {
+            Support::Supported => debug!("Monitored path {path} is on {fs_type}"),
+            Support::Partial => warn!(
+                "Monitored path {path} is on {fs_type}, changes made to it from other hosts are not reported"
+            ),
+            Support::Unsupported => warn!(
+                "Monitored path {path} is on {fs_type}, events for it may be missed or have no path"
+            ),
+            Support::Unknown => info!(
+                "Monitored path {path} is on {fs_type}, which fact does not know the support of"
+            ),
+        }
+    }
+    metrics.set_coverage(coverage);
+}
+
+/// Check the coverage of the monitored paths now and every time they
+/// change, until `running` turns false.
+///
+/// `overrides` are only read on startup.
+pub fn start(
+    mut paths: watch::Receiver<Vec<PathBuf>>,
+    overrides: BTreeMap<String, Support>,
+    metrics: ConfigMetrics,
+    mut running: watch::Receiver<bool>,
+) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        loop {
+            let current = paths.borrow_and_update().clone();
+            let overrides = overrides.clone();
+            // statfs on a network filesystem blocks for as long as the
+            // server takes to answer
+            let coverage = tokio::task::spawn_blocking(move || {
+                check(
+                    &current,
+                    host_info::get_host_mount(),
+                    &overrides,
+                    &SystemFsStat,
+                )
+            });
+            match coverage.await {
+                Ok(coverage) => report(&coverage, &metrics),
+                Err(e) => warn!("Failed to check the coverage of the monitored paths: {e}"),
+            }
+
+            tokio::select! {
+                Ok(_) = paths.changed() => {}
+                _ = running.changed() => {
+                    if !*running.borrow() {
+                        info!("Stopping path coverage checks...");
+                        return;
+                    }
+                }
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+
+    const EXT4: u32 = 0xEF53;
+    const NFS: u32 = 0x6969;
+    const FUSE: u32 = 0x6573_5546;
+    const CIFS: u32 = 0xFF53_4D42;
+    const PROC: u32 = 0x9FA0;
+
+    /// Filesystems by mount point, paths missing from it fail statfs.
+    struct MockFsStat {
+        magics: HashMap<PathBuf, u32>,
+        mounts: &'static str,
+    }
+
+    impl FsStat for MockFsStat {
+        fn magic(&self, path: &Path) -> io::Result<u32> {
+            self.magics
+                .get(path)
+                .copied()
+                .ok_or_else(|| io::Error::from(io::ErrorKind::NotFound))
+        }
+
+        fn mounts(&self) -> io::Result<MountInfo> {
+            Ok(MountInfo::parse(self.mounts))
+        }
+    }
+
+    fn stat() -> MockFsStat {
+        let magics = [
+            ("/host", EXT4),
+            ("/host/etc", EXT4),
+            ("/host/mnt/nfs", NFS),
+            ("/host/mnt/nfs/share", NFS),
+            ("/host/mnt/sshfs", FUSE),
+            ("/host/mnt/unlisted", FUSE),
+            ("/host/mnt/smb", CIFS),
+            ("/host/proc", PROC),
+            ("/host/mnt/odd", 0x1234),
+        ];
+        MockFsStat {
+            magics: magics
+                .into_iter()
+                .map(|(path, magic)| (PathBuf::from(path), magic))
+                .collect(),
+            mounts: "22 1 8:1 / /host rw - ext4 /dev/sda1 rw\n\
+                     40 22 0:35 / /host/mnt/nfs rw - nfs4 server:/export rw\n\
+                     41 22 0:36 / /host/mnt/sshfs rw - fuse.sshfs user@server: rw\n\
+                     42 22 0:37 / /host/mnt/smb rw - smb3 //server/share rw\n\
+                     43 22 0:38 / /host/proc rw - proc proc rw\n\
+                     44 22 0:39 / /host/mnt/odd rw - odd odd rw\n",
+        }
+    }
+
+    fn classify(paths: &[&str], overrides: &[(&str, Support)]) -> Vec<(String, Support)> {
+        let paths = paths.iter().map(PathBuf::from).collect::<Vec<_>>();
+        let overrides = overrides
+            .iter()
+            .map(|(name, support)| (name.to_string(), *support))
+            .collect();
+        check(&paths, Path::new("/host"), &overrides, &stat())
+            .into_iter()
+            .map(|c| (c.fs_type, c.support))
+            .collect()
+    }
+
+    #[test]
+    fn fs_types() {
+        let tests = [
+            ("/etc", "ext4", Support::Supported, "Local filesystem"),
+            ("/etc/**/*.conf", "ext4", Support::Supported, "Glob"),
+            (
+                "/etc/missing/file",
+                "ext4",
+                Support::Supported,
+                "Missing path",
+            ),
+            ("/mnt/nfs/share/*", "nfs4", Support::Partial, "NFS"),
+            ("/mnt/smb", "smb3", Support::Partial, "SMB"),
+            (
+                "/mnt/sshfs",
+                "fuse.sshfs",
+                Support::Unsupported,
+                "FUSE subtype",
+            ),
+            // statfs says FUSE, the mount table has the parent mount
+            (
+                "/mnt/unlisted",
+                "fuse",
+                Support::Unsupported,
+                "Not in table",
+            ),
+            ("/proc", "proc", Support::Unknown, "Pseudo filesystem"),
+            ("/mnt/odd", "odd", Support::Unknown, "Unknown magic"),
+        ];
+        for (path, fs_type, support, description) in tests {
+            assert_eq!(
+                classify(&[path], &[]),
+                [(fs_type.to_owned(), support)],
+                "Failed for {description}"
+            );
+        }
+    }
+
+    #[test]
+    fn overrides() {
+        let tests: &[(&str, &[(&str, Support)], Support, &str)] = &[
+            (
+                "/mnt/sshfs",
+                &[("fuse.sshfs", Support::Supported)],
+                Support::Supported,
+                "Subtype",
+            ),
+            (
+                "/mnt/sshfs",
+                &[("fuse", Support::Partial)],
+                Support::Partial,
+                "Type of a subtype",
+            ),
+            (
+                "/mnt/sshfs",
+                &[
+                    ("fuse", Support::Partial),
+                    ("fuse.sshfs", Support::Supported),
+                ],
+                Support::Supported,
+                "Subtype over type",
+            ),
+            (
+                "/mnt/nfs",
+                &[("nfs4", Support::Unsupported)],
+                Support::Unsupported,
+                "Known type",
+            ),
+            (
+                "/proc",
+                &[("proc", Support::Unsupported)],
+                Support::Unsupported,
+                "Unknown type",
+            ),
+            (
+                "/etc",
+                &[("xfs", Support::Unsupported)],
+                Support::Supported,
+                "Other type",
+            ),
+        ];
+        for (path, overrides, support, description) in tests {
+            let coverage = classify(&[path], overrides);
+            assert_eq!(coverage[0].1, *support, "Failed for {description}");
+        }
+    }
+
+    #[test]
+    fn no_filesystem() {
+        // Paths are only looked up under the host mount
+        let coverage = check(
+            &[PathBuf::from("/etc")],
+            Path::new("/missing"),
+            &BTreeMap::new(),
+            &stat(),
+        );
+        assert!(coverage.is_empty());
+
+        let stat = MockFsStat {
+            magics: HashMap::from([(PathBuf::from("/host/mnt/nfs"), NFS)]),
+            mounts: "",
+        };
+        let coverage = check(
+            &[PathBuf::from("/mnt/nfs")],
+            Path::new("/host"),
+            &BTreeMap::new(),
+            &stat,
+        );
+        assert_eq!(coverage[0].fs_type, "nfs");
+    }
+
+    #[test]
+    fn parse() {
+        assert_eq!("partial".parse::<Support>().unwrap(), Support::Partial);
+        assert_eq!(
+            "unknown".parse::<Support>().unwrap_err().to_string(),
+            "unknown support \"unknown\", expected one of: supported, partial, unsupported"
+        );
+    }
+}
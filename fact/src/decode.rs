@@ -0,0 +1,37 @@
+//! `fact decode`, printing the events in a capture of raw ringbuffer
+//! records as JSON.
+//!
+//! Captures hold records one after the other, each preceded by its
+//! length as a 32 bit little endian integer. Events are printed one per
+//! line like the stdout output does, so they can be replayed with
+//! `fact replay`.
+
+use std::{
+    io::{self, Write},
+    path::Path,
+};
+
+use anyhow::Context;
+
+use crate::event::{
+    lineage::Lineages,
+    raw::{HostContext, Records, parse_record},
+};
+
+/// Print the events in the capture at `path`, as captured on `host`.
+///
+/// Records that don't parse are reported and skipped, the capture is
+/// only rejected if it is cut in the middle of a record. Lineages left
+/// out of records are filled in from the earlier ones.
+pub fn run(path: &Path, host: &HostContext) -> anyhow::Result<()> {
+    let data = std::fs::read(path).with_context(|| format!("Failed to read {}", path.display()))?;
+    let mut stdout = io::stdout().lock();
+    let lineages = Lineages::default();
+    for (i, record) in Records::new(&data).enumerate() {
+        match parse_record(record?, host, &lineages) {
+            Ok(event) => writeln!(stdout, "{}", serde_json::to_string(&event)?)?,
+            Err(e) => eprintln!("Failed to parse record {i}: {e:#}"),
+        }
+    }
+    Ok(())
+}
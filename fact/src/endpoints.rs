@@ -1,35 +1,68 @@
-use std::{future::Future, pin::Pin};
+use std::{
+    future::Future, io, net::SocketAddr, os::fd::AsRawFd, pin::Pin, sync::Arc, time::Duration,
+};
 
+use fact_ebpf::inode_key_t;
 use http_body_util::Full;
 use hyper::{
-    Method, Request, Response, StatusCode,
+    HeaderMap, Method, Request, Response, StatusCode,
     body::{Bytes, Incoming},
     server::conn::http1,
     service::Service,
 };
 use hyper_util::rt::TokioIo;
 use log::{info, warn};
-use tokio::{net::TcpListener, sync::watch, task::JoinHandle};
+use tokio::{
+    net::{TcpListener, TcpSocket, TcpStream},
+    sync::{mpsc, watch},
+    task::{JoinHandle, JoinSet},
+    time::timeout,
+};
 
-use crate::{config::EndpointConfig, metrics::exporter::Exporter};
+use crate::{
+    config::{DurationValue, EndpointConfig, Routes},
+    health::HealthState,
+    inode_map::QueryHandle,
+    limits::Limit,
+    metrics::containers::MAX_AGE,
+    pause::{PauseError, PauseHandle, PauseState},
+    status::Collector,
+};
 
 #[derive(Clone)]
 pub struct Server {
-    metrics: Exporter,
+    status: Collector,
+    limits: Arc<[Limit]>,
+    inodes: Option<QueryHandle>,
     config: watch::Receiver<EndpointConfig>,
+    health: watch::Receiver<HealthState>,
+    pause: PauseHandle,
     running: watch::Receiver<bool>,
+    drain_timeout: Duration,
+    /// Endpoints served on the connection being handled.
+    routes: Routes,
 }
 
 impl Server {
     pub fn new(
-        metrics: Exporter,
+        status: Collector,
+        limits: Vec<Limit>,
+        inodes: Option<QueryHandle>,
         config: watch::Receiver<EndpointConfig>,
+        health: watch::Receiver<HealthState>,
+        pause: PauseHandle,
         running: watch::Receiver<bool>,
     ) -> Self {
         Server {
-            metrics,
+            status,
+            limits: limits.into(),
+            inodes,
             config,
+            health,
+            pause,
             running,
+            drain_timeout: crate::SHUTDOWN_TIMEOUT,
+            routes: Routes::ALL,
         }
     }
 
@@ -69,33 +102,136 @@ impl Server {
 
     /// Serve requests on the configured endpoints.
     ///
-    /// If a configuration change is detected, returning from this
-    /// method will handle reloading it.
+    /// Each configured address is accepted on by its own task, the
+    /// connections are all served here. If a configuration change is
+    /// detected, returning from this method will handle reloading it.
+    /// Open connections are left to finish with the previous
+    /// configuration.
+    ///
+    /// When fact stops, no new connections are accepted and open ones
+    /// are drained, see `Server::drain`.
     async fn serve(&mut self) -> anyhow::Result<bool> {
-        let addr = self.config.borrow().address();
-        let listener = TcpListener::bind(addr).await?;
+        let (tx, mut accepted) = mpsc::channel(64);
+        let mut listeners = JoinSet::new();
+        for (listener, routes) in self.listen()? {
+            listeners.spawn(accept(listener, routes, tx.clone()));
+        }
+        drop(tx);
+        let mut connections = JoinSet::new();
 
         loop {
             tokio::select! {
-                Ok((stream, _)) = listener.accept() => {
+                Some((stream, routes)) = accepted.recv() => {
                     let io = TokioIo::new(stream);
-                    let s = self.clone();
-                    tokio::spawn(async move {
-                        if let Err(e) = http1::Builder::new().serve_connection(io, s).await {
+                    let mut s = self.clone();
+                    s.routes = routes;
+                    let mut running = self.running.clone();
+                    connections.spawn(async move {
+                        let conn = http1::Builder::new().serve_connection(io, s);
+                        tokio::pin!(conn);
+                        let res = tokio::select! {
+                            res = conn.as_mut() => res,
+                            _ = async { running.wait_for(|r| !*r).await.map(|_| ()) } => {
+                                conn.as_mut().graceful_shutdown();
+                                conn.await
+                            }
+                        };
+                        if let Err(e) = res {
                             warn!("Error serving connection: {e:?}");
                         }
                     });
                 },
-                _ = self.config.changed() => return Ok(true),
-                _ = self.running.changed() => return Ok(*self.running.borrow()),
+                // Reap finished connections
+                Some(_) = connections.join_next() => {},
+                _ = self.config.changed() => {
+                    // Release the addresses before they are bound again
+                    listeners.shutdown().await;
+                    connections.detach_all();
+                    return Ok(true);
+                },
+                _ = self.running.changed() => {
+                    listeners.shutdown().await;
+                    if *self.running.borrow() {
+                        connections.detach_all();
+                        return Ok(true);
+                    }
+                    break;
+                },
             }
         }
+
+        self.drain(connections).await;
+        Ok(false)
+    }
+
+    /// Wait for open connections to finish once fact is stopping.
+    ///
+    /// Connections are asked to shut down gracefully: idle ones are
+    /// closed right away, requests in flight get their response.
+    /// Connections still open after the drain timeout are dropped.
+    async fn drain(&self, mut connections: JoinSet<()>) {
+        if connections.is_empty() {
+            return;
+        }
+
+        info!("Draining {} endpoint connections...", connections.len());
+        let drained = timeout(self.drain_timeout, async {
+            while connections.join_next().await.is_some() {}
+        })
+        .await;
+        if drained.is_err() {
+            warn!(
+                "{} endpoint connections still open after {:?}, closing them",
+                connections.len(),
+                self.drain_timeout
+            );
+        }
+    }
+
+    /// Create the listeners for the configured addresses, along with
+    /// the endpoints served on each.
+    ///
+    /// If no address is configured and IPv6 is not available on the
+    /// system, binding to the default dual-stack address will fail and
+    /// the IPv4 fallback address is used instead.
+    fn listen(&self) -> anyhow::Result<Vec<(TcpListener, Routes)>> {
+        let (listeners, is_default) = {
+            let config = self.config.borrow();
+            (config.listeners(), config.address_is_default())
+        };
+
+        let mut bound = Vec::with_capacity(listeners.len());
+        for (addr, routes) in listeners {
+            let listener = match bind(addr) {
+                Ok(listener) => listener,
+                Err(e) if is_default && ipv6_unavailable(&e) => {
+                    let fallback = EndpointConfig::FALLBACK_ADDRESS;
+                    warn!("Failed to bind {addr}, IPv6 may be unavailable: {e}");
+                    warn!("Falling back to {fallback}");
+                    bind(fallback)?
+                }
+                Err(e) => return Err(anyhow::anyhow!("Failed to bind {addr}: {e}")),
+            };
+
+            let default = if is_default { " (default)" } else { "" };
+            let only = if routes == Routes::ALL {
+                String::new()
+            } else {
+                format!(", {routes} only")
+            };
+            info!(
+                "Serving endpoints on {}{default}{only}",
+                listener.local_addr()?
+            );
+            bound.push((listener, routes));
+        }
+        Ok(bound)
     }
 
     /// Check if there are active endpoints to serve.
     fn is_active(&self) -> bool {
         let config = self.config.borrow();
-        config.health_check() || config.expose_metrics()
+        config.health_check() || config.expose_metrics() || config.control_token().is_some()
     }
 
     fn health_check_is_active(&self) -> bool {
@@ -121,7 +257,7 @@ impl Server {
             return Server::make_response(StatusCode::SERVICE_UNAVAILABLE, String::new());
         }
 
-        self.metrics.encode().map(|buf| {
+        self.status.exporter().encode().map(|buf| {
             let body = Full::new(Bytes::from(buf));
             Response::builder()
                 .header(
@@ -133,6 +269,19 @@ impl Server {
         })?
     }
 
+    /// Report the state of fact as a whole, served along with the
+    /// metrics and redacted the same way.
+    fn handle_status(&self) -> Result<Response<Full<Bytes>>, anyhow::Error> {
+        if !self.metrics_is_active() {
+            return Server::make_response(StatusCode::SERVICE_UNAVAILABLE, String::new());
+        }
+
+        let token = self.config.borrow().control_token().cloned();
+        let secrets = token.iter().map(|t| t.as_str()).collect::<Vec<_>>();
+        let status = self.status.collect(&secrets);
+        Server::make_json_response(StatusCode::OK, serde_json::to_value(status)?)
+    }
+
     fn handle_health_check(&self) -> Result<Response<Full<Bytes>>, anyhow::Error> {
         let res = if self.health_check_is_active() {
             StatusCode::OK
@@ -141,6 +290,222 @@ impl Server {
         };
         Server::make_response(res, String::new())
     }
+
+    /// Report if fact is ready, which is the case unless outputs are
+    /// persistently dropping events.
+    ///
+    /// While degraded, a 503 is returned if configured to fail on
+    /// degraded service, otherwise a 200 with the state in the body.
+    fn handle_readyz(&self) -> Result<Response<Full<Bytes>>, anyhow::Error> {
+        if !self.health_check_is_active() {
+            return Server::make_response(StatusCode::SERVICE_UNAVAILABLE, String::new());
+        }
+
+        let health = *self.health.borrow();
+        if health.paused {
+            let res = if health.fail_on_paused {
+                StatusCode::SERVICE_UNAVAILABLE
+            } else {
+                StatusCode::OK
+            };
+            let body = match self.pause.state().paused_until_secs() {
+                Some(until) => format!("paused: event collection resumes at {until}"),
+                None => "paused".to_string(),
+            };
+            return Server::make_response(res, body);
+        }
+
+        if !health.degraded {
+            return Server::make_response(StatusCode::OK, "ready".to_string());
+        }
+
+        let res = if health.fail_on_degraded {
+            StatusCode::SERVICE_UNAVAILABLE
+        } else {
+            StatusCode::OK
+        };
+        let body = format!(
+            "degraded: {} events dropped in the last interval",
+            health.dropped
+        );
+        Server::make_response(res, body)
+    }
+
+    /// Check the bearer token sent to the control endpoints.
+    ///
+    /// The control endpoints don't exist unless a token is configured.
+    fn authorize(&self, headers: &HeaderMap) -> Result<(), StatusCode> {
+        let config = self.config.borrow();
+        let Some(token) = config.control_token() else {
+            return Err(StatusCode::NOT_FOUND);
+        };
+        let provided = headers
+            .get(hyper::header::AUTHORIZATION)
+            .and_then(|h| h.to_str().ok())
+            .and_then(|h| h.strip_prefix("Bearer "));
+        match provided {
+            Some(provided) if constant_time_eq(provided.as_bytes(), token.as_str().as_bytes()) => {
+                Ok(())
+            }
+            _ => Err(StatusCode::UNAUTHORIZED),
+        }
+    }
+
+    fn make_json_response(
+        res: StatusCode,
+        body: serde_json::Value,
+    ) -> Result<Response<Full<Bytes>>, anyhow::Error> {
+        Ok(Response::builder()
+            .status(res)
+            .header(hyper::header::CONTENT_TYPE, "application/json")
+            .body(Full::new(Bytes::from(body.to_string())))
+            .unwrap())
+    }
+
+    fn make_pause_response(
+        res: Result<PauseState, PauseError>,
+    ) -> Result<Response<Full<Bytes>>, anyhow::Error> {
+        match res {
+            Ok(state) => Server::make_json_response(
+                StatusCode::OK,
+                serde_json::json!({
+                    "paused": state.is_paused(),
+                    "paused_until": state.paused_until_secs(),
+                }),
+            ),
+            Err(e) => {
+                let res = match e {
+                    PauseError::ZeroDuration | PauseError::TooLong(_) => StatusCode::BAD_REQUEST,
+                    PauseError::Unsupported => StatusCode::CONFLICT,
+                    PauseError::Switch(_) => StatusCode::INTERNAL_SERVER_ERROR,
+                    PauseError::Stopped => StatusCode::SERVICE_UNAVAILABLE,
+                };
+                Server::make_response(res, e.to_string())
+            }
+        }
+    }
+
+    /// Pause event collection for the duration in the query, e.g.
+    /// `?duration=600` or `?duration=10m`.
+    async fn handle_pause(
+        &self,
+        query: Option<&str>,
+    ) -> Result<Response<Full<Bytes>>, anyhow::Error> {
+        let duration = query
+            .unwrap_or_default()
+            .split('&')
+            .find_map(|param| param.strip_prefix("duration="));
+        let Some(duration) = duration else {
+            return Server::make_response(
+                StatusCode::BAD_REQUEST,
+                "missing duration parameter".to_string(),
+            );
+        };
+        let duration = match duration.parse::<DurationValue>() {
+            Ok(duration) => Duration::from(duration),
+            Err(e) => return Server::make_response(StatusCode::BAD_REQUEST, e.to_string()),
+        };
+        Server::make_pause_response(self.pause.pause(duration).await)
+    }
+
+    async fn handle_resume(&self) -> Result<Response<Full<Bytes>>, anyhow::Error> {
+        Server::make_pause_response(self.pause.resume().await)
+    }
+
+    fn handle_debug_state(&self) -> Result<Response<Full<Bytes>>, anyhow::Error> {
+        let pause = self.pause.state();
+        let health = *self.health.borrow();
+        Server::make_json_response(
+            StatusCode::OK,
+            serde_json::json!({
+                "paused": pause.is_paused(),
+                "paused_until": pause.paused_until_secs(),
+                "degraded": health.degraded,
+                "dropped": health.dropped,
+            }),
+        )
+    }
+
+    fn handle_debug_limits(&self) -> Result<Response<Full<Bytes>>, anyhow::Error> {
+        Server::make_json_response(StatusCode::OK, serde_json::to_value(&*self.limits)?)
+    }
+
+    /// Report the state of the BPF programs and maps, only available
+    /// when events are read from the kernel.
+    fn handle_debug_bpf(&self) -> Result<Response<Full<Bytes>>, anyhow::Error> {
+        let Some(bpf) = self.status.bpf() else {
+            return Server::make_response(
+                StatusCode::NOT_FOUND,
+                "BPF programs are not loaded".to_string(),
+            );
+        };
+        Server::make_json_response(StatusCode::OK, serde_json::to_value(bpf.snapshot())?)
+    }
+
+    /// Report the busiest containers, with their events by type.
+    fn handle_debug_top_containers(&self) -> Result<Response<Full<Bytes>>, anyhow::Error> {
+        let containers = self.status.containers();
+        Server::make_json_response(
+            StatusCode::OK,
+            serde_json::json!({
+                "capacity": containers.capacity(),
+                "max_age_seconds": MAX_AGE.as_secs(),
+                "containers": containers.top(),
+            }),
+        )
+    }
+
+    /// Look up an inode in the map of the host scanner, for example
+    /// `?inode=1234&dev=2049`.
+    async fn handle_debug_inode(
+        &self,
+        query: Option<&str>,
+    ) -> Result<Response<Full<Bytes>>, anyhow::Error> {
+        let Some(inodes) = &self.inodes else {
+            return Server::make_response(
+                StatusCode::NOT_FOUND,
+                "inodes are not tracked".to_string(),
+            );
+        };
+        let param = |name: &str| {
+            query
+                .unwrap_or_default()
+                .split('&')
+                .find_map(|param| param.strip_prefix(name)?.strip_prefix('='))
+                .map(str::parse::<u64>)
+        };
+        let (inode, dev) = match (param("inode"), param("dev")) {
+            (Some(Ok(inode)), Some(Ok(dev))) => (inode, dev),
+            _ => {
+                return Server::make_response(
+                    StatusCode::BAD_REQUEST,
+                    "inode and dev must be set to numbers".to_string(),
+                );
+            }
+        };
+        match inodes.inode(inode_key_t { inode, dev }).await {
+            Ok(info) => Server::make_json_response(StatusCode::OK, serde_json::to_value(info)?),
+            Err(e) => Server::make_response(StatusCode::SERVICE_UNAVAILABLE, e.to_string()),
+        }
+    }
+
+    async fn handle_debug_inode_stats(&self) -> Result<Response<Full<Bytes>>, anyhow::Error> {
+        let Some(inodes) = &self.inodes else {
+            return Server::make_response(
+                StatusCode::NOT_FOUND,
+                "inodes are not tracked".to_string(),
+            );
+        };
+        match inodes.stats().await {
+            Ok(stats) => Server::make_json_response(StatusCode::OK, serde_json::to_value(stats)?),
+            Err(e) => Server::make_response(StatusCode::SERVICE_UNAVAILABLE, e.to_string()),
+        }
+    }
+}
+
+/// Compare two byte strings in time independent of where they differ.
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
 }
 
 impl Service<Request<Incoming>> for Server {
@@ -150,12 +515,697 @@ impl Service<Request<Incoming>> for Server {
 
     fn call(&self, req: Request<Incoming>) -> Self::Future {
         let s = self.clone();
+        let (parts, _) = req.into_parts();
         Box::pin(async move {
-            match (req.method(), req.uri().path()) {
+            let path = parts.uri.path();
+            if !s.routes.serves(path) {
+                return Server::make_response(StatusCode::NOT_FOUND, String::new());
+            }
+            if (path.starts_with("/control/") || path.starts_with("/debug/"))
+                && let Err(res) = s.authorize(&parts.headers)
+            {
+                return Server::make_response(res, String::new());
+            }
+
+            match (&parts.method, path) {
                 (&Method::GET, "/metrics") => s.handle_metrics(),
+                (&Method::GET, "/status") => s.handle_status(),
                 (&Method::GET, "/health_check") => s.handle_health_check(),
+                (&Method::GET, "/readyz") => s.handle_readyz(),
+                (&Method::POST, "/control/pause") => s.handle_pause(parts.uri.query()).await,
+                (&Method::POST, "/control/resume") => s.handle_resume().await,
+                (&Method::GET, "/debug/state") => s.handle_debug_state(),
+                (&Method::GET, "/debug/limits") => s.handle_debug_limits(),
+                (&Method::GET, "/debug/bpf") => s.handle_debug_bpf(),
+                (&Method::GET, "/debug/top-containers") => s.handle_debug_top_containers(),
+                (&Method::GET, "/debug/inode") => s.handle_debug_inode(parts.uri.query()).await,
+                (&Method::GET, "/debug/inode/stats") => s.handle_debug_inode_stats().await,
                 _ => Server::make_response(StatusCode::NOT_FOUND, String::new()),
             }
         })
     }
 }
+
+/// Accept connections on `listener`, passing them on along with the
+/// endpoints served on it until the receiver is gone.
+async fn accept(
+    listener: TcpListener,
+    routes: Routes,
+    accepted: mpsc::Sender<(TcpStream, Routes)>,
+) {
+    loop {
+        let Ok((stream, _)) = listener.accept().await else {
+            continue;
+        };
+        if accepted.send((stream, routes)).await.is_err() {
+            break;
+        }
+    }
+}
+
+/// Bind a listener to the provided address.
+///
+/// Listeners on the IPv6 unspecified address are set to dual-stack, so
+/// IPv4 connections are accepted regardless of the system default for
+/// IPV6_V6ONLY.
+fn bind(addr: SocketAddr) -> io::Result<TcpListener> {
+    let socket = match addr {
+        SocketAddr::V4(_) => TcpSocket::new_v4()?,
+        SocketAddr::V6(_) => TcpSocket::new_v6()?,
+    };
+    if addr.is_ipv6() && addr.ip().is_unspecified() {
+        set_ipv6_only(&socket, false)?;
+    }
+    socket.set_reuseaddr(true)?;
+    socket.bind(addr)?;
+    socket.listen(1024)
+}
+
+fn set_ipv6_only(socket: &TcpSocket, only_v6: bool) -> io::Result<()> {
+    let value = libc::c_int::from(only_v6);
+    let res = unsafe {
+        libc::setsockopt(
+            socket.as_raw_fd(),
+            libc::IPPROTO_IPV6,
+            libc::IPV6_V6ONLY,
+            (&raw const value).cast(),
+            size_of::<libc::c_int>() as libc::socklen_t,
+        )
+    };
+    if res != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// Check if the error is caused by IPv6 not being available.
+fn ipv6_unavailable(e: &io::Error) -> bool {
+    matches!(
+        e.raw_os_error(),
+        Some(libc::EAFNOSUPPORT | libc::EADDRNOTAVAIL | libc::EPROTONOSUPPORT)
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        net::{Ipv4Addr, Ipv6Addr},
+        path::{Path, PathBuf},
+        time::Instant,
+    };
+
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::TcpStream,
+    };
+
+    use super::*;
+    use crate::{
+        config::FactConfig,
+        inode_map::{InodeMap, Source},
+        metrics::Metrics,
+        pause::{PauseController, PauseSwitch},
+    };
+
+    const CONFIG: &str = "endpoint:\n  health_check: true\n  expose_metrics: true";
+
+    struct NoopSwitch;
+
+    impl PauseSwitch for NoopSwitch {
+        fn set_paused(&mut self, _: bool) -> anyhow::Result<()> {
+            Ok(())
+        }
+    }
+
+    async fn get(addr: SocketAddr, path: &str) -> String {
+        request(addr, "GET", path, None).await
+    }
+
+    async fn request(addr: SocketAddr, method: &str, path: &str, token: Option<&str>) -> String {
+        let mut stream = TcpStream::connect(addr).await.expect("Failed to connect");
+        let auth = token
+            .map(|t| format!("Authorization: Bearer {t}\r\n"))
+            .unwrap_or_default();
+        let req = format!(
+            "{method} {path} HTTP/1.1\r\nHost: fact\r\n{auth}Content-Length: 0\r\nConnection: close\r\n\r\n"
+        );
+        stream
+            .write_all(req.as_bytes())
+            .await
+            .expect("Failed to send request");
+        let mut res = String::new();
+        stream
+            .read_to_string(&mut res)
+            .await
+            .expect("Failed to read response");
+        res
+    }
+
+    /// Serve the endpoints on the provided listener with the provided
+    /// configuration.
+    fn spawn_server(
+        listener: TcpListener,
+        config: &str,
+        health: watch::Receiver<HealthState>,
+    ) -> PauseHandle {
+        spawn_server_with_inodes(listener, config, health, None)
+    }
+
+    fn spawn_server_with_inodes(
+        listener: TcpListener,
+        config: &str,
+        health: watch::Receiver<HealthState>,
+        inodes: Option<QueryHandle>,
+    ) -> PauseHandle {
+        let config = FactConfig::try_from(config).expect("Failed to parse config");
+        let limits = crate::limits::limits(&config);
+        let (config_tx, config_rx) = watch::channel(config.endpoint);
+        let (running_tx, running_rx) = watch::channel(true);
+        let metrics = Metrics::new();
+        let (controller, pause) = PauseController::new(
+            Some(Box::new(NoopSwitch)),
+            metrics.collection_paused.clone(),
+            running_rx.clone(),
+            mpsc::channel(10).0,
+        );
+        controller.start();
+        let server = Server::new(
+            Collector::for_tests(&metrics),
+            limits,
+            inodes,
+            config_rx,
+            health,
+            pause.clone(),
+            running_rx,
+        );
+        tokio::spawn(async move {
+            // Keep the senders alive for as long as the server runs
+            let _senders = (config_tx, running_tx);
+            while let Ok((stream, _)) = listener.accept().await {
+                let s = server.clone();
+                tokio::spawn(http1::Builder::new().serve_connection(TokioIo::new(stream), s));
+            }
+        });
+        pause
+    }
+
+    #[tokio::test]
+    async fn dual_stack() {
+        let listener = match bind(SocketAddr::from((Ipv6Addr::UNSPECIFIED, 0))) {
+            Ok(listener) => listener,
+            Err(e) if ipv6_unavailable(&e) => {
+                eprintln!("Skipping test, IPv6 is not available: {e}");
+                return;
+            }
+            Err(e) => panic!("Failed to bind: {e}"),
+        };
+        let port = listener.local_addr().unwrap().port();
+        let (_health_tx, health_rx) = watch::channel(HealthState::default());
+        spawn_server(listener, CONFIG, health_rx);
+
+        let addrs = [
+            SocketAddr::from((Ipv4Addr::LOCALHOST, port)),
+            SocketAddr::from((Ipv6Addr::LOCALHOST, port)),
+        ];
+        for addr in addrs {
+            for path in ["/health_check", "/readyz", "/metrics"] {
+                let res = get(addr, path).await;
+                assert!(
+                    res.starts_with("HTTP/1.1 200 OK"),
+                    "Failed for {addr}{path}: {res}"
+                );
+            }
+            let res = get(addr, "/unknown").await;
+            assert!(
+                res.starts_with("HTTP/1.1 404 Not Found"),
+                "Failed for {addr}: {res}"
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn readyz() {
+        let listener = bind(SocketAddr::from((Ipv4Addr::LOCALHOST, 0))).expect("Failed to bind");
+        let addr = listener.local_addr().unwrap();
+        let (health_tx, health_rx) = watch::channel(HealthState::default());
+        spawn_server(listener, CONFIG, health_rx);
+
+        let tests = [
+            (false, false, "HTTP/1.1 200 OK", "ready"),
+            (true, false, "HTTP/1.1 200 OK", "degraded: 42 events"),
+            (
+                true,
+                true,
+                "HTTP/1.1 503 Service Unavailable",
+                "degraded: 42 events",
+            ),
+            (false, true, "HTTP/1.1 200 OK", "ready"),
+        ];
+
+        for (degraded, fail_on_degraded, status, body) in tests {
+            health_tx.send_replace(HealthState {
+                degraded,
+                fail_on_degraded,
+                dropped: 42,
+                ..Default::default()
+            });
+            let res = get(addr, "/readyz").await;
+            assert!(
+                res.starts_with(status),
+                "Failed for degraded={degraded} fail_on_degraded={fail_on_degraded}: {res}"
+            );
+            assert!(
+                res.contains(body),
+                "Failed for degraded={degraded} fail_on_degraded={fail_on_degraded}: {res}"
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn readyz_paused() {
+        let listener = bind(SocketAddr::from((Ipv4Addr::LOCALHOST, 0))).expect("Failed to bind");
+        let addr = listener.local_addr().unwrap();
+        let (health_tx, health_rx) = watch::channel(HealthState::default());
+        spawn_server(listener, CONFIG, health_rx);
+
+        for (fail_on_paused, status) in [
+            (false, "HTTP/1.1 200 OK"),
+            (true, "HTTP/1.1 503 Service Unavailable"),
+        ] {
+            health_tx.send_replace(HealthState {
+                paused: true,
+                fail_on_paused,
+                ..Default::default()
+            });
+            let res = get(addr, "/readyz").await;
+            assert!(res.starts_with(status), "{res}");
+            assert!(res.contains("paused"), "{res}");
+        }
+    }
+
+    #[tokio::test]
+    async fn control() {
+        let listener = bind(SocketAddr::from((Ipv4Addr::LOCALHOST, 0))).expect("Failed to bind");
+        let addr = listener.local_addr().unwrap();
+        let (_health_tx, health_rx) = watch::channel(HealthState::default());
+        let pause = spawn_server(listener, "endpoint:\n  control_token: secret", health_rx);
+
+        let tests = [
+            (
+                "POST",
+                "/control/pause?duration=600",
+                None,
+                "401 Unauthorized",
+            ),
+            (
+                "POST",
+                "/control/pause?duration=600",
+                Some("wrong"),
+                "401 Unauthorized",
+            ),
+            ("GET", "/debug/state", None, "401 Unauthorized"),
+            ("GET", "/debug/limits", None, "401 Unauthorized"),
+            ("GET", "/debug/bpf", None, "401 Unauthorized"),
+            ("GET", "/control/pause", Some("secret"), "404 Not Found"),
+            // No BPF programs are loaded in this test
+            ("GET", "/debug/bpf", Some("secret"), "404 Not Found"),
+            ("POST", "/control/pause", Some("secret"), "400 Bad Request"),
+            (
+                "POST",
+                "/control/pause?duration=soon",
+                Some("secret"),
+                "400 Bad Request",
+            ),
+            (
+                "POST",
+                "/control/pause?duration=0",
+                Some("secret"),
+                "400 Bad Request",
+            ),
+            (
+                "POST",
+                "/control/pause?duration=25h",
+                Some("secret"),
+                "400 Bad Request",
+            ),
+            // Other endpoints are disabled in this configuration
+            ("GET", "/readyz", None, "503 Service Unavailable"),
+        ];
+        for (method, path, token, status) in tests {
+            let res = request(addr, method, path, token).await;
+            assert!(
+                res.starts_with(&format!("HTTP/1.1 {status}")),
+                "Failed for {method} {path} {token:?}: {res}"
+            );
+        }
+        assert!(!pause.state().is_paused());
+
+        let res = request(addr, "POST", "/control/pause?duration=10m", Some("secret")).await;
+        assert!(res.starts_with("HTTP/1.1 200 OK"), "{res}");
+        assert!(res.contains(r#""paused":true"#), "{res}");
+        assert!(pause.state().is_paused());
+
+        let res = request(addr, "GET", "/debug/state", Some("secret")).await;
+        assert!(res.starts_with("HTTP/1.1 200 OK"), "{res}");
+        assert!(res.contains(r#""paused":true"#), "{res}");
+        let until = pause.state().paused_until_secs().unwrap();
+        assert!(res.contains(&format!(r#""paused_until":{until}"#)), "{res}");
+
+        let res = request(addr, "GET", "/debug/limits", Some("secret")).await;
+        assert!(res.starts_with("HTTP/1.1 200 OK"), "{res}");
+        assert!(
+            res.contains(r#"{"description":"Longest path captured for files and executables","kind":"compile","name":"PATH_MAX","unit":"bytes","value":4096}"#),
+            "{res}"
+        );
+
+        let res = request(addr, "GET", "/debug/top-containers", Some("secret")).await;
+        assert!(res.starts_with("HTTP/1.1 200 OK"), "{res}");
+        assert!(
+            res.contains(r#"{"capacity":20,"containers":[],"max_age_seconds":600}"#),
+            "{res}"
+        );
+
+        let res = request(addr, "POST", "/control/resume", Some("secret")).await;
+        assert!(res.starts_with("HTTP/1.1 200 OK"), "{res}");
+        assert!(res.contains(r#""paused":false"#), "{res}");
+        assert!(!pause.state().is_paused());
+    }
+
+    #[tokio::test]
+    async fn control_disabled() {
+        let listener = bind(SocketAddr::from((Ipv4Addr::LOCALHOST, 0))).expect("Failed to bind");
+        let addr = listener.local_addr().unwrap();
+        let (_health_tx, health_rx) = watch::channel(HealthState::default());
+        let pause = spawn_server(listener, CONFIG, health_rx);
+
+        for (method, path) in [
+            ("POST", "/control/pause?duration=600"),
+            ("POST", "/control/resume"),
+            ("GET", "/debug/state"),
+            ("GET", "/debug/limits"),
+            ("GET", "/debug/bpf"),
+            ("GET", "/debug/inode?inode=1&dev=1"),
+            ("GET", "/debug/inode/stats"),
+            ("GET", "/debug/top-containers"),
+        ] {
+            let res = request(addr, method, path, Some("secret")).await;
+            assert!(res.starts_with("HTTP/1.1 404 Not Found"), "{res}");
+        }
+        assert!(!pause.state().is_paused());
+    }
+
+    #[tokio::test]
+    async fn debug_inode() {
+        let mut map = InodeMap::new();
+        let inode = inode_key_t {
+            inode: 42,
+            dev: 2049,
+        };
+        map.insert(inode, Path::new("/etc/hosts"), Source::StartupScan);
+        let (handle, mut queries) = QueryHandle::new();
+        tokio::spawn(async move {
+            while let Some(query) = queries.recv().await {
+                query.answer(&map, |_| true, &[PathBuf::from("/etc")]);
+            }
+        });
+
+        let listener = bind(SocketAddr::from((Ipv4Addr::LOCALHOST, 0))).expect("Failed to bind");
+        let addr = listener.local_addr().unwrap();
+        let (_health_tx, health_rx) = watch::channel(HealthState::default());
+        let config = format!("{CONFIG}\n  control_token: secret");
+        spawn_server_with_inodes(listener, &config, health_rx, Some(handle));
+
+        let res = request(
+            addr,
+            "GET",
+            "/debug/inode?inode=42&dev=2049",
+            Some("secret"),
+        )
+        .await;
+        assert!(res.starts_with("HTTP/1.1 200 OK"), "{res}");
+        assert!(res.contains(r#""path":"/etc/hosts""#), "{res}");
+        assert!(res.contains(r#""source":"startup_scan""#), "{res}");
+        assert!(res.contains(r#""in_kernel":true"#), "{res}");
+
+        let res = request(
+            addr,
+            "GET",
+            "/debug/inode?inode=43&dev=2049",
+            Some("secret"),
+        )
+        .await;
+        assert!(res.contains(r#""entry":null"#), "{res}");
+
+        for query in ["", "?inode=42", "?inode=42&dev=sda", "?inodes=42&dev=2049"] {
+            let res = request(addr, "GET", &format!("/debug/inode{query}"), Some("secret")).await;
+            assert!(
+                res.starts_with("HTTP/1.1 400 Bad Request"),
+                "{query}: {res}"
+            );
+        }
+
+        let res = request(addr, "GET", "/debug/inode/stats", Some("secret")).await;
+        assert!(res.starts_with("HTTP/1.1 200 OK"), "{res}");
+        assert!(res.contains(r#""prefixes":{"/etc":1}"#), "{res}");
+
+        // Only with the token
+        let res = get(addr, "/debug/inode/stats").await;
+        assert!(res.starts_with("HTTP/1.1 401 Unauthorized"), "{res}");
+    }
+
+    #[tokio::test]
+    async fn status() {
+        let listener = bind(SocketAddr::from((Ipv4Addr::LOCALHOST, 0))).expect("Failed to bind");
+        let addr = listener.local_addr().unwrap();
+        let (_health_tx, health_rx) = watch::channel(HealthState::default());
+        let config = format!("{CONFIG}\n  control_token: status-secret");
+        spawn_server(listener, &config, health_rx);
+
+        let res = get(addr, "/status").await;
+        assert!(res.starts_with("HTTP/1.1 200 OK"), "{res}");
+        assert!(res.contains("content-type: application/json"), "{res}");
+        let (_, body) = res.split_once("\r\n\r\n").unwrap();
+        let status: serde_json::Value = serde_json::from_str(body).unwrap();
+        assert_eq!(status["version"], crate::status::STATUS_VERSION);
+        assert!(!body.contains("status-secret"), "{body}");
+
+        // Served along with the metrics
+        let listener = bind(SocketAddr::from((Ipv4Addr::LOCALHOST, 0))).expect("Failed to bind");
+        let addr = listener.local_addr().unwrap();
+        let (_health_tx, health_rx) = watch::channel(HealthState::default());
+        spawn_server(listener, "endpoint:\n  health_check: true", health_rx);
+        let res = get(addr, "/status").await;
+        assert!(res.starts_with("HTTP/1.1 503 Service Unavailable"), "{res}");
+    }
+
+    /// Pick a free port for the server to bind.
+    fn free_addr() -> SocketAddr {
+        bind(SocketAddr::from((Ipv4Addr::LOCALHOST, 0)))
+            .and_then(|l| l.local_addr())
+            .expect("Failed to bind")
+    }
+
+    async fn connect(addr: SocketAddr) -> TcpStream {
+        loop {
+            match TcpStream::connect(addr).await {
+                Ok(stream) => return stream,
+                Err(_) => tokio::time::sleep(Duration::from_millis(10)).await,
+            }
+        }
+    }
+
+    /// Start a server with `config`, returning the senders for
+    /// configuration changes and for stopping it.
+    fn start_server(config: &str) -> (watch::Sender<EndpointConfig>, watch::Sender<bool>) {
+        let config = FactConfig::try_from(config).expect("Failed to parse config");
+        let (config_tx, config_rx) = watch::channel(config.endpoint);
+        let (_health_tx, health_rx) = watch::channel(HealthState::default());
+        let (running_tx, running_rx) = watch::channel(true);
+        let metrics = Metrics::new();
+        let (controller, pause) = PauseController::new(
+            Some(Box::new(NoopSwitch)),
+            metrics.collection_paused.clone(),
+            running_rx.clone(),
+            mpsc::channel(10).0,
+        );
+        controller.start();
+        Server::new(
+            Collector::for_tests(&metrics),
+            Vec::new(),
+            None,
+            config_rx,
+            health_rx,
+            pause,
+            running_rx,
+        )
+        .start();
+        (config_tx, running_tx)
+    }
+
+    #[tokio::test]
+    async fn multiple_addresses() {
+        let (first, second) = (free_addr(), free_addr());
+        let config = format!(
+            "endpoint:\n  address: [{first}, {second}]\n  expose_metrics: true\n  health_check: true"
+        );
+        let (config_tx, _running_tx) = start_server(&config);
+
+        for addr in [first, second] {
+            connect(addr).await;
+            for path in ["/metrics", "/readyz"] {
+                let res = get(addr, path).await;
+                assert!(res.starts_with("HTTP/1.1 200 OK"), "{addr}{path}: {res}");
+            }
+        }
+
+        // Reloading binds the new addresses and releases the old ones
+        let third = free_addr();
+        let config = format!("endpoint:\n  address: {third}\n  expose_metrics: true");
+        let config = FactConfig::try_from(config.as_str()).unwrap();
+        config_tx.send_replace(config.endpoint);
+        connect(third).await;
+        let res = get(third, "/metrics").await;
+        assert!(res.starts_with("HTTP/1.1 200 OK"), "{res}");
+        for addr in [first, second] {
+            assert!(
+                TcpStream::connect(addr).await.is_err(),
+                "{addr} still bound"
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn restricted_addresses() {
+        let (all, metrics, health) = (free_addr(), free_addr(), free_addr());
+        let config = format!(
+            "endpoint:
+  address: {all}
+  metrics_address: {metrics}
+  health_address: {health}
+  expose_metrics: true
+  health_check: true
+  control_token: secret"
+        );
+        let _senders = start_server(&config);
+
+        let tests = [
+            (all, "/metrics", "200 OK"),
+            (all, "/readyz", "200 OK"),
+            (all, "/debug/state", "200 OK"),
+            (metrics, "/metrics", "200 OK"),
+            (metrics, "/status", "200 OK"),
+            (metrics, "/readyz", "404 Not Found"),
+            (metrics, "/debug/state", "404 Not Found"),
+            (health, "/health_check", "200 OK"),
+            (health, "/readyz", "200 OK"),
+            (health, "/metrics", "404 Not Found"),
+            (health, "/control/resume", "404 Not Found"),
+        ];
+        for (addr, path, status) in tests {
+            connect(addr).await;
+            let method = if path.starts_with("/control/") {
+                "POST"
+            } else {
+                "GET"
+            };
+            let res = request(addr, method, path, Some("secret")).await;
+            assert!(
+                res.starts_with(&format!("HTTP/1.1 {status}")),
+                "{addr}{path}: {res}"
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn drain() {
+        let addr = free_addr();
+        let config = format!(
+            "endpoint:\n  address: {addr}\n  expose_metrics: true\n  control_token: secret"
+        );
+        let config = FactConfig::try_from(config.as_str()).expect("Failed to parse config");
+        let (_config_tx, config_rx) = watch::channel(config.endpoint);
+        let (_health_tx, health_rx) = watch::channel(HealthState::default());
+        let (running_tx, running_rx) = watch::channel(true);
+        let metrics = Metrics::new();
+        // The controller is never started, pause requests hang until
+        // the connection is closed.
+        let (_controller, pause) = PauseController::new(
+            Some(Box::new(NoopSwitch)),
+            metrics.collection_paused.clone(),
+            running_rx.clone(),
+            mpsc::channel(10).0,
+        );
+        let mut server = Server::new(
+            Collector::for_tests(&metrics),
+            Vec::new(),
+            None,
+            config_rx,
+            health_rx,
+            pause,
+            running_rx,
+        );
+        server.drain_timeout = Duration::from_millis(500);
+        let server = server.start();
+
+        // A keep-alive connection, idle after its first response
+        let mut idle = connect(addr).await;
+        idle.write_all(b"GET /metrics HTTP/1.1\r\nHost: fact\r\n\r\n")
+            .await
+            .unwrap();
+        let mut res = Vec::new();
+        let mut buf = [0; 4096];
+        while !res.ends_with(b"# EOF\n") {
+            let n = idle.read(&mut buf).await.unwrap();
+            assert_ne!(n, 0, "Connection closed early");
+            res.extend_from_slice(&buf[..n]);
+        }
+        assert!(res.starts_with(b"HTTP/1.1 200 OK"));
+
+        // A connection with a request in flight
+        let mut busy = connect(addr).await;
+        busy.write_all(
+            b"POST /control/pause?duration=10m HTTP/1.1\r\nHost: fact\r\nAuthorization: Bearer secret\r\nContent-Length: 0\r\n\r\n",
+        )
+        .await
+        .unwrap();
+        tokio::time::sleep(Duration::from_millis(50)).await;
+
+        let start = Instant::now();
+        running_tx.send(false).unwrap();
+
+        // The idle connection is closed right away
+        let n = timeout(Duration::from_millis(250), idle.read(&mut buf))
+            .await
+            .expect("Idle connection was not closed")
+            .unwrap();
+        assert_eq!(n, 0);
+
+        // The request in flight holds the server until the deadline
+        timeout(Duration::from_secs(2), server)
+            .await
+            .expect("Server did not stop")
+            .unwrap();
+        assert!(start.elapsed() >= Duration::from_millis(500));
+        let n = busy.read(&mut buf).await.unwrap_or(0);
+        assert_eq!(n, 0);
+
+        // No new connections are accepted
+        assert!(TcpStream::connect(addr).await.is_err());
+    }
+
+    #[test]
+    fn ipv6_errors() {
+        let tests = [
+            (libc::EAFNOSUPPORT, true),
+            (libc::EADDRNOTAVAIL, true),
+            (libc::EPROTONOSUPPORT, true),
+            (libc::EADDRINUSE, false),
+            (libc::EACCES, false),
+        ];
+
+        for (errno, expected) in tests {
+            let e = io::Error::from_raw_os_error(errno);
+            assert_eq!(ipv6_unavailable(&e), expected, "Failed for {e}");
+        }
+    }
+}
@@ -0,0 +1,400 @@
+//! Enrichment of events right before they are emitted.
+//!
+//! With `enrich.existence_check` enabled, open and creation events get
+//! `exists_at_emit` set to whether their file still exists on the host,
+//! useful to tell apart files that were deleted right after being
+//! accessed. Files are checked in parallel, up to a limit and with a
+//! short timeout, while events keep the order they came in. Files on
+//! network filesystems are never checked, a stat on them can block for
+//! as long as the server takes to answer.
+
+use std::{
+    collections::VecDeque,
+    io,
+    path::Path,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use log::{debug, info, warn};
+use tokio::{
+    sync::{Semaphore, mpsc, watch},
+    task::{JoinHandle, JoinSet},
+    time::timeout,
+};
+
+use crate::{
+    config::EnrichConfig,
+    event::{Event, Existence},
+    host_info,
+    metrics::EventCounter,
+    mount_info::MountInfo,
+};
+
+/// Maximum time a single existence check is allowed to take.
+pub const STAT_TIMEOUT: Duration = Duration::from_millis(100);
+
+/// Maximum number of files being checked at the same time.
+const MAX_CONCURRENT_STATS: usize = 16;
+
+/// Maximum number of events held while waiting on checks.
+const MAX_PENDING: usize = 256;
+
+/// Time the mount table is used before being read again.
+const MOUNTS_TTL: Duration = Duration::from_secs(30);
+
+/// Access to the filesystem for the checks, so tests can simulate it.
+pub trait Stat: Send + Sync {
+    /// Whether `path` exists, without following symlinks.
+    fn exists(&self, path: &Path) -> io::Result<bool>;
+    fn mounts(&self) -> io::Result<MountInfo>;
+}
+
+pub struct SystemStat;
+
+impl Stat for SystemStat {
+    fn exists(&self, path: &Path) -> io::Result<bool> {
+        match path.symlink_metadata() {
+            Ok(_) => Ok(true),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(false),
+            // A component of the path is no longer a directory
+            Err(e) if e.raw_os_error() == Some(libc::ENOTDIR) => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+
+    fn mounts(&self) -> io::Result<MountInfo> {
+        MountInfo::read()
+    }
+}
+
+pub struct ExistenceChecker {
+    stat: Arc<dyn Stat>,
+    permits: Arc<Semaphore>,
+    timeout: Duration,
+    mounts: MountInfo,
+    mounts_read_at: Option<Instant>,
+    metrics: EventCounter,
+}
+
+impl ExistenceChecker {
+    pub fn new(stat: impl Stat + 'static, timeout: Duration, metrics: EventCounter) -> Self {
+        ExistenceChecker {
+            stat: Arc::new(stat),
+            permits: Arc::new(Semaphore::new(MAX_CONCURRENT_STATS)),
+            timeout,
+            mounts: MountInfo::default(),
+            mounts_read_at: None,
+            metrics,
+        }
+    }
+
+    /// Start checking whether the file of `event` exists.
+    ///
+    /// Returns `None` if the check is skipped.
+    fn check(&mut self, event: &Event) -> Option<JoinHandle<Existence>> {
+        let host_path = event.get_host_path();
+        if host_path.as_os_str().is_empty() {
+            self.metrics.ignored();
+            return None;
+        }
+        let path = host_info::prepend_host_mount(host_path);
+        if self.is_remote(&path) {
+            self.metrics.ignored();
+            return None;
+        }
+
+        let stat = self.stat.clone();
+        let permits = self.permits.clone();
+        let metrics = self.metrics.clone();
+        let limit = self.timeout;
+        Some(tokio::spawn(async move {
+            // The permit is held until the stat returns, even if it
+            // outlives the timeout, so stuck checks count against the
+            // limit.
+            let res = timeout(limit, async {
+                let permit = permits.acquire_owned().await.unwrap();
+                let path = path.clone();
+                tokio::task::spawn_blocking(move || {
+                    let _permit = permit;
+                    stat.exists(&path)
+                })
+                .await
+            })
+            .await;
+
+            match res {
+                Ok(Ok(Ok(true))) => {
+                    metrics.added();
+                    Existence::True
+                }
+                Ok(Ok(Ok(false))) => {
+                    metrics.added();
+                    Existence::False
+                }
+                Ok(Ok(Err(e))) => {
+                    debug!("Failed to check {}: {e}", path.display());
+                    metrics.errored();
+                    Existence::Unknown
+                }
+                Ok(Err(e)) => {
+                    warn!("Existence check for {} failed: {e}", path.display());
+                    metrics.errored();
+                    Existence::Unknown
+                }
+                Err(_) => {
+                    debug!("Existence check for {} timed out", path.display());
+                    metrics.timed_out();
+                    Existence::Unknown
+                }
+            }
+        }))
+    }
+
+    fn is_remote(&mut self, path: &Path) -> bool {
+        if self
+            .mounts_read_at
+            .is_none_or(|read_at| read_at.elapsed() >= MOUNTS_TTL)
+        {
+            match self.stat.mounts() {
+                Ok(mounts) => self.mounts = mounts,
+                Err(e) => warn!("Failed to read the mount table: {e}"),
+            }
+            self.mounts_read_at = Some(Instant::now());
+        }
+        self.mounts.is_remote(path)
+    }
+}
+
+/// Wait for the check of the first pending event.
+async fn front_check(pending: &mut VecDeque<(Event, Option<JoinHandle<Existence>>)>) -> Existence {
+    match pending.front_mut() {
+        Some((_, Some(check))) => check.await.unwrap_or(Existence::Unknown),
+        _ => std::future::pending().await,
+    }
+}
+
+/// Start a task enriching the events going through it.
+pub fn start(
+    task_set: &mut JoinSet<anyhow::Result<()>>,
+    mut rx: mpsc::Receiver<Event>,
+    mut config: watch::Receiver<EnrichConfig>,
+    mut checker: ExistenceChecker,
+) -> mpsc::Receiver<Event> {
+    let (tx, output) = mpsc::channel(crate::EVENT_CHANNEL_CAPACITY);
+    let mut existence_check = config.borrow_and_update().existence_check();
+    task_set.spawn(async move {
+        debug!("Starting enrichment...");
+        let mut pending = VecDeque::new();
+        let mut closed = false;
+        loop {
+            // Forward events in order as soon as they are ready
+            while let Some((_, None)) = pending.front() {
+                let (event, _) = pending.pop_front().unwrap();
+                if tx.send(event).await.is_err() {
+                    info!("No enrichment consumers left, stopping...");
+                    return Ok(());
+                }
+            }
+            if closed && pending.is_empty() {
+                info!("Stopping enrichment...");
+                return Ok(());
+            }
+
+            tokio::select! {
+                event = rx.recv(), if !closed && pending.len() < MAX_PENDING => {
+                    let Some(mut event) = event else {
+                        closed = true;
+                        continue;
+                    };
+                    let mut check = None;
+                    if existence_check && (event.is_open() || event.is_creation()) {
+                        check = checker.check(&event);
+                        if check.is_none() {
+                            event.set_exists_at_emit(Existence::Unknown);
+                        }
+                    }
+                    pending.push_back((event, check));
+                }
+                exists = front_check(&mut pending), if !pending.is_empty() => {
+                    let (mut event, _) = pending.pop_front().unwrap();
+                    event.set_exists_at_emit(exists);
+                    pending.push_front((event, None));
+                }
+                Ok(_) = config.changed() => {
+                    existence_check = config.borrow_and_update().existence_check();
+                }
+            }
+        }
+    });
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        path::PathBuf,
+        sync::atomic::{AtomicUsize, Ordering},
+    };
+
+    use fact_ebpf::{PATH_MAX, event_t, file_activity_type_t};
+
+    use super::*;
+    use crate::{config::FactConfig, event::test_utils::string_to_c_char_array, metrics::Metrics};
+
+    /// Paths under `/missing` don't exist, `/error` fail, `/slow` take
+    /// longer than the timeout and everything else exists.
+    #[derive(Clone, Default)]
+    struct MockStat(Arc<AtomicUsize>);
+
+    impl MockStat {
+        fn calls(&self) -> usize {
+            self.0.load(Ordering::Relaxed)
+        }
+    }
+
+    impl Stat for MockStat {
+        fn exists(&self, path: &Path) -> io::Result<bool> {
+            self.0.fetch_add(1, Ordering::Relaxed);
+            if path.starts_with("/missing") {
+                Ok(false)
+            } else if path.starts_with("/error") {
+                Err(io::Error::from_raw_os_error(libc::EACCES))
+            } else if path.starts_with("/slow") {
+                std::thread::sleep(Duration::from_millis(500));
+                Ok(true)
+            } else {
+                Ok(true)
+            }
+        }
+
+        fn mounts(&self) -> io::Result<MountInfo> {
+            Ok(MountInfo::parse(
+                "22 1 253:0 / / rw - xfs /dev/root rw\n\
+                 40 22 0:35 / /nfs rw - nfs4 server:/export rw\n",
+            ))
+        }
+    }
+
+    fn event(type_: file_activity_type_t, path: &str) -> Event {
+        let raw = event_t {
+            type_,
+            filename: string_to_c_char_array::<{ PATH_MAX as usize }>(path),
+            ..Default::default()
+        };
+        let mut event = Event::try_from(&raw).unwrap();
+        event.set_host_path(PathBuf::from(path));
+        event
+    }
+
+    fn open(path: &str) -> Event {
+        event(file_activity_type_t::FILE_ACTIVITY_OPEN, path)
+    }
+
+    fn config(existence_check: bool) -> EnrichConfig {
+        let yaml = format!("enrich:\n  existence_check: {existence_check}");
+        FactConfig::try_from(yaml.as_str()).unwrap().enrich
+    }
+
+    async fn run(
+        stat: &MockStat,
+        existence_check: bool,
+        events: Vec<Event>,
+    ) -> (Vec<serde_json::Value>, EventCounter) {
+        let metrics = Metrics::new().existence_check;
+        let checker = ExistenceChecker::new(stat.clone(), STAT_TIMEOUT, metrics.clone());
+        let (_config_tx, config_rx) = watch::channel(config(existence_check));
+        let (tx, rx) = mpsc::channel(100);
+        let mut task_set = JoinSet::new();
+        let mut rx = start(&mut task_set, rx, config_rx, checker);
+
+        for event in events {
+            tx.send(event).await.unwrap();
+        }
+        drop(tx);
+
+        let mut output = Vec::new();
+        while let Some(event) = rx.recv().await {
+            output.push(serde_json::to_value(&event).unwrap());
+        }
+        crate::join_all_tasks(task_set).await.unwrap();
+        (output, metrics)
+    }
+
+    #[tokio::test]
+    async fn outcomes() {
+        let stat = MockStat::default();
+        let events = vec![
+            open("/etc/passwd"),
+            event(
+                file_activity_type_t::FILE_ACTIVITY_CREATION,
+                "/missing/file",
+            ),
+            open("/error/file"),
+            open("/slow/file"),
+            open("/nfs/file"),
+            open(""),
+            open("/missing/again"),
+        ];
+        let start = Instant::now();
+        let (output, metrics) = run(&stat, true, events).await;
+        assert!(start.elapsed() < Duration::from_millis(400));
+
+        let exists: Vec<_> = output.iter().map(|e| e["exists_at_emit"].clone()).collect();
+        assert_eq!(
+            exists,
+            [
+                "true", "false", "unknown", "unknown", "unknown", "unknown", "false"
+            ]
+        );
+
+        // Remote and unresolved files are never checked
+        assert_eq!(stat.calls(), 5);
+        assert_eq!(metrics.added_count(), 3);
+        assert_eq!(metrics.ignored_count(), 2);
+        assert_eq!(metrics.errored_count(), 1);
+        assert_eq!(metrics.timed_out_count(), 1);
+    }
+
+    #[tokio::test]
+    async fn order_and_types() {
+        // Fast checks wait for slow ones before them
+        let stat = MockStat::default();
+        let events = vec![
+            open("/slow/file"),
+            event(file_activity_type_t::FILE_ACTIVITY_UNLINK, "/missing/file"),
+            open("/etc/passwd"),
+        ];
+        let (output, _) = run(&stat, true, events).await;
+        let files: Vec<_> = output
+            .iter()
+            .map(|e| {
+                let (kind, data) = e["file"].as_object().unwrap().iter().next().unwrap();
+                (kind.clone(), data["filename"].clone())
+            })
+            .collect();
+        assert_eq!(
+            files,
+            [
+                ("Open".to_string(), "/slow/file".into()),
+                ("Unlink".to_string(), "/missing/file".into()),
+                ("Open".to_string(), "/etc/passwd".into()),
+            ]
+        );
+
+        // Only open and creation events are checked
+        assert_eq!(output[0]["exists_at_emit"], "unknown");
+        assert!(output[1].get("exists_at_emit").is_none());
+        assert_eq!(output[2]["exists_at_emit"], "true");
+        assert_eq!(stat.calls(), 2);
+    }
+
+    #[tokio::test]
+    async fn disabled() {
+        let stat = MockStat::default();
+        let (output, _) = run(&stat, false, vec![open("/missing/file")]).await;
+        assert_eq!(output.len(), 1);
+        assert!(output[0].get("exists_at_emit").is_none());
+        assert_eq!(stat.calls(), 0);
+    }
+}
@@ -0,0 +1,168 @@
+//! Attribute flags of a file, as set by `chattr`.
+//!
+//! The kernel reports the flags with the bits of `FS_IOC_GETFLAGS` in
+//! `linux/fs.h`, only the ones fact watches for are kept: immutable,
+//! append-only and fs-verity. Events show their names instead, bits
+//! not in this table are shown as `flag_<bit>`.
+
+use std::{borrow::Cow, fmt};
+
+use serde::{
+    Deserialize, Deserializer, Serialize, Serializer,
+    de::{self, SeqAccess, Visitor},
+    ser::SerializeSeq,
+};
+
+/// Names of the flags, with their bit in the bitmask.
+const NAMES: &[(u32, &str)] = &[
+    (fact_ebpf::FACT_FS_IMMUTABLE_FL, "immutable"),
+    (fact_ebpf::FACT_FS_APPEND_FL, "append"),
+    (fact_ebpf::FACT_FS_VERITY_FL, "verity"),
+];
+
+fn name(bit: u32) -> Cow<'static, str> {
+    match NAMES.iter().find(|(flag, _)| *flag == 1 << bit) {
+        Some((_, name)) => Cow::Borrowed(name),
+        None => Cow::Owned(format!("flag_{bit}")),
+    }
+}
+
+fn bit(name: &str) -> Option<u32> {
+    if let Some((flag, _)) = NAMES.iter().find(|(_, n)| *n == name) {
+        return Some(flag.trailing_zeros());
+    }
+    name.strip_prefix("flag_")?
+        .parse()
+        .ok()
+        .filter(|bit| *bit < u32::BITS)
+}
+
+/// A set of attribute flags, as the bitmask read from the kernel.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct FileFlags(u32);
+
+impl FileFlags {
+    pub fn bits(&self) -> u32 {
+        self.0
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0 == 0
+    }
+
+    /// Whether the set has the flag called `name`.
+    pub fn contains(&self, name: &str) -> bool {
+        bit(name).is_some_and(|bit| self.0 & (1 << bit) != 0)
+    }
+
+    /// Names of the flags in the set, by increasing bit.
+    pub fn names(&self) -> impl Iterator<Item = Cow<'static, str>> {
+        let bits = self.0;
+        (0..u32::BITS)
+            .filter(move |bit| bits & (1 << bit) != 0)
+            .map(name)
+    }
+
+    /// Flags in this set and not in `other`.
+    pub fn difference(&self, other: FileFlags) -> FileFlags {
+        FileFlags(self.0 & !other.0)
+    }
+}
+
+impl From<u32> for FileFlags {
+    fn from(bits: u32) -> Self {
+        FileFlags(bits)
+    }
+}
+
+impl Serialize for FileFlags {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut seq = serializer.serialize_seq(Some(self.0.count_ones() as usize))?;
+        for name in self.names() {
+            seq.serialize_element(&name)?;
+        }
+        seq.end()
+    }
+}
+
+impl<'de> Deserialize<'de> for FileFlags {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct NamesVisitor;
+
+        impl<'de> Visitor<'de> for NamesVisitor {
+            type Value = FileFlags;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.write_str("a list of attribute flag names")
+            }
+
+            fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Self::Value, A::Error> {
+                let mut bits = 0;
+                while let Some(name) = seq.next_element::<Cow<str>>()? {
+                    let Some(bit) = bit(&name) else {
+                        return Err(de::Error::custom(format!("unknown flag {name:?}")));
+                    };
+                    bits |= 1 << bit;
+                }
+                Ok(FileFlags(bits))
+            }
+        }
+
+        deserializer.deserialize_seq(NamesVisitor)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn names() {
+        let tests: &[(u32, &[&str], &str)] = &[
+            (0, &[], "Empty"),
+            (0x10, &["immutable"], "Immutable"),
+            (0x20 | 0x10, &["immutable", "append"], "Several"),
+            (0x0010_0000, &["verity"], "Verity"),
+            (0x80, &["flag_7"], "Unknown"),
+            (1 << 31, &["flag_31"], "Last bit"),
+        ];
+
+        for (bits, expected, description) in tests {
+            let flags = FileFlags::from(*bits);
+            assert_eq!(
+                flags.names().collect::<Vec<_>>(),
+                *expected,
+                "Failed for {description}"
+            );
+            for name in *expected {
+                assert!(flags.contains(name), "Failed for {description}");
+            }
+        }
+
+        let flags = FileFlags::from(0x10 | 0x20);
+        assert_eq!(
+            flags.difference(FileFlags::from(0x20)),
+            FileFlags::from(0x10)
+        );
+        assert!(!flags.contains("verity"));
+        assert!(!flags.contains("flag_32"));
+        assert!(!flags.contains("nodump"));
+    }
+
+    #[test]
+    fn serde() {
+        let flags = FileFlags::from(0x10 | 0x0010_0000 | 0x40);
+        let value = serde_json::to_value(flags).unwrap();
+        assert_eq!(value, serde_json::json!(["immutable", "flag_6", "verity"]));
+        let parsed: FileFlags = serde_json::from_value(value).unwrap();
+        assert_eq!(parsed, flags);
+
+        for (names, expected) in [
+            (serde_json::json!(["nodump"]), "unknown flag \"nodump\""),
+            (serde_json::json!(["flag_32"]), "unknown flag \"flag_32\""),
+        ] {
+            let err = serde_json::from_value::<FileFlags>(names).unwrap_err();
+            assert_eq!(err.to_string(), expected);
+        }
+    }
+}
@@ -0,0 +1,210 @@
+//! Effective capabilities of the process behind an event.
+//!
+//! The kernel reports capabilities as a bitmask, with the bit of each
+//! capability set to its number in `linux/capability.h`. Events show
+//! the names instead, like `CAP_DAC_OVERRIDE`. Capabilities added to
+//! the kernel after this table are shown as `CAP_<number>`.
+
+use std::{borrow::Cow, fmt};
+
+use serde::{
+    Deserialize, Deserializer, Serialize, Serializer,
+    de::{self, SeqAccess, Visitor},
+    ser::SerializeSeq,
+};
+
+/// Names of the capabilities, indexed by their number.
+const NAMES: &[&str] = &[
+    "CAP_CHOWN",
+    "CAP_DAC_OVERRIDE",
+    "CAP_DAC_READ_SEARCH",
+    "CAP_FOWNER",
+    "CAP_FSETID",
+    "CAP_KILL",
+    "CAP_SETGID",
+    "CAP_SETUID",
+    "CAP_SETPCAP",
+    "CAP_LINUX_IMMUTABLE",
+    "CAP_NET_BIND_SERVICE",
+    "CAP_NET_BROADCAST",
+    "CAP_NET_ADMIN",
+    "CAP_NET_RAW",
+    "CAP_IPC_LOCK",
+    "CAP_IPC_OWNER",
+    "CAP_SYS_MODULE",
+    "CAP_SYS_RAWIO",
+    "CAP_SYS_CHROOT",
+    "CAP_SYS_PTRACE",
+    "CAP_SYS_PACCT",
+    "CAP_SYS_ADMIN",
+    "CAP_SYS_BOOT",
+    "CAP_SYS_NICE",
+    "CAP_SYS_RESOURCE",
+    "CAP_SYS_TIME",
+    "CAP_SYS_TTY_CONFIG",
+    "CAP_MKNOD",
+    "CAP_LEASE",
+    "CAP_AUDIT_WRITE",
+    "CAP_AUDIT_CONTROL",
+    "CAP_SETFCAP",
+    "CAP_MAC_OVERRIDE",
+    "CAP_MAC_ADMIN",
+    "CAP_SYSLOG",
+    "CAP_WAKE_ALARM",
+    "CAP_BLOCK_SUSPEND",
+    "CAP_AUDIT_READ",
+    "CAP_PERFMON",
+    "CAP_BPF",
+    "CAP_CHECKPOINT_RESTORE",
+];
+
+fn name(bit: u32) -> Cow<'static, str> {
+    match NAMES.get(bit as usize) {
+        Some(name) => Cow::Borrowed(name),
+        None => Cow::Owned(format!("CAP_{bit}")),
+    }
+}
+
+fn bit(name: &str) -> Option<u32> {
+    if let Some(bit) = NAMES.iter().position(|n| *n == name) {
+        return Some(bit as u32);
+    }
+    name.strip_prefix("CAP_")?
+        .parse()
+        .ok()
+        .filter(|bit| *bit < u64::BITS)
+}
+
+/// A set of capabilities, as the bitmask read from the kernel.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Capabilities(u64);
+
+impl Capabilities {
+    pub fn bits(&self) -> u64 {
+        self.0
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0 == 0
+    }
+
+    /// Whether the set has the capability called `name`.
+    pub fn contains(&self, name: &str) -> bool {
+        bit(name).is_some_and(|bit| self.0 & (1 << bit) != 0)
+    }
+
+    /// Names of the capabilities in the set, by increasing number.
+    pub fn names(&self) -> impl Iterator<Item = Cow<'static, str>> {
+        let bits = self.0;
+        (0..u64::BITS)
+            .filter(move |bit| bits & (1 << bit) != 0)
+            .map(name)
+    }
+}
+
+impl From<u64> for Capabilities {
+    fn from(bits: u64) -> Self {
+        Capabilities(bits)
+    }
+}
+
+impl Serialize for Capabilities {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut seq = serializer.serialize_seq(Some(self.0.count_ones() as usize))?;
+        for name in self.names() {
+            seq.serialize_element(&name)?;
+        }
+        seq.end()
+    }
+}
+
+impl<'de> Deserialize<'de> for Capabilities {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct NamesVisitor;
+
+        impl<'de> Visitor<'de> for NamesVisitor {
+            type Value = Capabilities;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.write_str("a list of capability names")
+            }
+
+            fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Self::Value, A::Error> {
+                let mut bits = 0;
+                while let Some(name) = seq.next_element::<Cow<str>>()? {
+                    let Some(bit) = bit(&name) else {
+                        return Err(de::Error::custom(format!("unknown capability {name:?}")));
+                    };
+                    bits |= 1 << bit;
+                }
+                Ok(Capabilities(bits))
+            }
+        }
+
+        deserializer.deserialize_seq(NamesVisitor)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn names() {
+        let tests: &[(u64, &[&str], &str)] = &[
+            (0, &[], "Empty"),
+            (1 << 1, &["CAP_DAC_OVERRIDE"], "Single"),
+            (
+                (1 << 0) | (1 << 21) | (1 << 40),
+                &["CAP_CHOWN", "CAP_SYS_ADMIN", "CAP_CHECKPOINT_RESTORE"],
+                "Several",
+            ),
+            (1 << 41, &["CAP_41"], "Unknown"),
+            (1 << 63, &["CAP_63"], "Last bit"),
+        ];
+
+        for (bits, expected, description) in tests {
+            let caps = Capabilities::from(*bits);
+            assert_eq!(
+                caps.names().collect::<Vec<_>>(),
+                *expected,
+                "Failed for {description}"
+            );
+            for name in *expected {
+                assert!(caps.contains(name), "Failed for {description}");
+            }
+        }
+
+        // Every capability known to the kernel fact is built against
+        let all = Capabilities::from((1 << NAMES.len()) - 1);
+        assert_eq!(all.names().collect::<Vec<_>>(), NAMES);
+        assert!(!all.contains("CAP_41"));
+        assert!(!all.contains("CAP_UNKNOWN"));
+    }
+
+    #[test]
+    fn serde() {
+        let caps = Capabilities::from((1 << 1) | (1 << 12) | (1 << 45));
+        let value = serde_json::to_value(caps).unwrap();
+        assert_eq!(
+            value,
+            serde_json::json!(["CAP_DAC_OVERRIDE", "CAP_NET_ADMIN", "CAP_45"])
+        );
+        let parsed: Capabilities = serde_json::from_value(value).unwrap();
+        assert_eq!(parsed, caps);
+
+        for (names, expected) in [
+            (
+                serde_json::json!(["CAP_FAKE"]),
+                "unknown capability \"CAP_FAKE\"",
+            ),
+            (
+                serde_json::json!(["CAP_64"]),
+                "unknown capability \"CAP_64\"",
+            ),
+        ] {
+            let err = serde_json::from_value::<Capabilities>(names).unwrap_err();
+            assert_eq!(err.to_string(), expected);
+        }
+    }
+}
@@ -0,0 +1,175 @@
+//! Detection and suppression of events caused by checkpoint/restore
+//! (CRIU) operations.
+//!
+//! Restoring a checkpointed container touches a large number of files
+//! from unusual process contexts, which can flood the output with
+//! confusing events during live migrations. Events triggered by CRIU
+//! are tagged so consumers can tell them apart and can optionally be
+//! suppressed for a bounded window of time.
+
+use std::{
+    path::Path,
+    time::{Duration, Instant},
+};
+
+const CRIU_EXE: &str = "criu";
+
+/// Check if the process information points to a checkpoint/restore
+/// operation.
+///
+/// A process is considered to be doing a checkpoint/restore if the
+/// acting executable is criu or if criu shows up anywhere in its
+/// lineage, since restored tasks are forked from the criu process.
+pub(super) fn is_checkpoint_restore<'a>(
+    comm: &str,
+    exe_path: &Path,
+    lineage: impl IntoIterator<Item = &'a Path>,
+) -> bool {
+    comm == CRIU_EXE || is_criu_exe(exe_path) || lineage.into_iter().any(is_criu_exe)
+}
+
+fn is_criu_exe(exe_path: &Path) -> bool {
+    exe_path.file_name().is_some_and(|name| name == CRIU_EXE)
+}
+
+/// Bounded window during which checkpoint/restore events are
+/// suppressed.
+///
+/// The first checkpoint/restore event is always let through and opens
+/// the window, any subsequent event arriving before the window elapses
+/// is suppressed. Once the window is over, the next checkpoint/restore
+/// event is let through and opens a new window. The time of each event
+/// is passed in by the caller, no clock is kept.
+#[derive(Debug)]
+pub struct SuppressionWindow {
+    window: Duration,
+    /// When the current window was opened. The end of the window is not
+    /// stored, a window too long to be added to an instant lasts
+    /// forever instead of overflowing.
+    opened: Option<Instant>,
+}
+
+impl SuppressionWindow {
+    pub fn new(window: Duration) -> Self {
+        SuppressionWindow {
+            window,
+            opened: None,
+        }
+    }
+
+    /// Change the length of the window, any window currently open is
+    /// closed.
+    pub fn set_window(&mut self, window: Duration) {
+        self.window = window;
+        self.opened = None;
+    }
+
+    /// Check if a checkpoint/restore event seen at `now` needs to be
+    /// suppressed.
+    pub fn suppress(&mut self, now: Instant) -> bool {
+        if self.window.is_zero() {
+            return false;
+        }
+
+        match self.opened {
+            Some(opened) if now.saturating_duration_since(opened) < self.window => true,
+            _ => {
+                self.opened = Some(now);
+                false
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::*;
+
+    #[test]
+    fn detection() {
+        let tests: &[(&str, &str, &[&str], bool, &str)] = &[
+            ("cat", "/usr/bin/cat", &[], false, "Regular process"),
+            ("criu", "/usr/bin/cat", &[], true, "criu comm"),
+            ("cat", "/usr/sbin/criu", &[], true, "criu exe"),
+            (
+                "cat",
+                "/usr/bin/cat",
+                &["/usr/bin/bash", "/usr/sbin/criu"],
+                true,
+                "criu in lineage",
+            ),
+            (
+                "cat",
+                "/usr/bin/cat",
+                &["/usr/bin/runc", "/usr/bin/conmon"],
+                false,
+                "Regular lineage",
+            ),
+            ("cat", "/opt/criu/bin/cat", &[], false, "criu directory"),
+            ("cat", "/usr/bin/criu-ns", &[], false, "criu prefix"),
+            ("cat", "", &[], false, "Empty exe_path"),
+        ];
+
+        for (comm, exe_path, lineage, expected, description) in tests {
+            let lineage = lineage.iter().map(PathBuf::from).collect::<Vec<_>>();
+            let res = is_checkpoint_restore(
+                comm,
+                Path::new(exe_path),
+                lineage.iter().map(PathBuf::as_path),
+            );
+            assert_eq!(res, *expected, "Failed for {description}");
+        }
+    }
+
+    #[test]
+    fn suppression_window() {
+        let start = Instant::now();
+        let mut window = SuppressionWindow::new(Duration::from_secs(10));
+
+        assert!(!window.suppress(start));
+        assert!(window.suppress(start + Duration::from_secs(1)));
+        assert!(window.suppress(start + Duration::from_secs(9)));
+        assert!(!window.suppress(start + Duration::from_secs(10)));
+        assert!(window.suppress(start + Duration::from_secs(15)));
+        assert!(!window.suppress(start + Duration::from_secs(25)));
+    }
+
+    #[test]
+    fn suppression_window_forever() {
+        let start = Instant::now();
+        let mut window = SuppressionWindow::new(Duration::MAX);
+
+        assert!(!window.suppress(start));
+        assert!(window.suppress(start + Duration::from_secs(1)));
+        assert!(window.suppress(start + Duration::from_secs(86400 * 365)));
+    }
+
+    #[test]
+    fn suppression_window_disabled() {
+        let start = Instant::now();
+        let mut window = SuppressionWindow::new(Duration::ZERO);
+
+        for i in 0..5 {
+            assert!(!window.suppress(start + Duration::from_secs(i)));
+        }
+    }
+
+    #[test]
+    fn suppression_window_update() {
+        let start = Instant::now();
+        let mut window = SuppressionWindow::new(Duration::from_secs(10));
+
+        assert!(!window.suppress(start));
+        assert!(window.suppress(start + Duration::from_secs(1)));
+
+        window.set_window(Duration::from_secs(2));
+        assert!(!window.suppress(start + Duration::from_secs(2)));
+        assert!(window.suppress(start + Duration::from_secs(3)));
+        assert!(!window.suppress(start + Duration::from_secs(4)));
+
+        window.set_window(Duration::ZERO);
+        assert!(!window.suppress(start + Duration::from_secs(5)));
+    }
+}
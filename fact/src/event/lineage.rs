@@ -0,0 +1,367 @@
+//! Ancestors of the process behind an event.
+//!
+//! Events from the same process tree repeat the same lineage, every
+//! command run from a shell has bash, tmux and systemd as ancestors.
+//! Lineages are cached by the bytes read from the kernel and shared
+//! between events, so each distinct chain of ancestors is only parsed
+//! and allocated once. An ancestor exec'ing into another binary changes
+//! the bytes, events after it get a lineage of their own.
+//!
+//! The kernel only walks the ancestors of a process for its first
+//! event, or once it is reparented, and leaves the lineage out of the
+//! later ones. Lineages are kept by process for filling them back in,
+//! the processes started first are forgotten once the cache is full.
+//! An event whose lineage is neither sent nor cached has none, the
+//! process is then forgotten by the kernel so its next event carries
+//! the lineage again.
+//!
+//! Both caches live in `Lineages`, owned by whatever reads the events
+//! of a kernel, so pipelines embedded in the same process do not fill
+//! in lineages of each other's events.
+
+#[cfg(feature = "otel")]
+use std::collections::HashMap;
+use std::{
+    collections::BTreeSet,
+    ffi::CStr,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+
+use fact_ebpf::{LINEAGE_CACHE_MAX, lineage_key_t, lineage_t};
+#[cfg(feature = "otel")]
+use opentelemetry::logs::AnyValue;
+use rustc_hash::FxHashMap;
+use serde::{Deserialize, Serialize};
+
+use super::{c_char_to_bytes, sanitize_d_path, slice_to_cstr};
+
+/// Distinct lineages kept, the cache is emptied once it holds more.
+const CACHE_MAX: usize = 1024;
+
+/// Parsed lineages, by the bytes they were read from. Paths can be up
+/// to PATH_MAX long, the hasher has to be cheaper than parsing them.
+type Cache = FxHashMap<Vec<u8>, Arc<[Lineage]>>;
+
+/// Processes kept, twice as many as the kernel remembers so entries it
+/// still holds are rarely forgotten here first.
+const PROCESSES_MAX: usize = 2 * LINEAGE_CACHE_MAX as usize;
+
+/// Where the lineage of an event came from.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Source {
+    /// Sent by the kernel, or the event was not read from it.
+    #[default]
+    Kernel,
+    /// Left out by the kernel and found in the cache.
+    Cached,
+    /// Left out by the kernel and missing from the cache, the event has
+    /// no lineage.
+    Missing,
+}
+
+/// A process, by its ID and start time in nanoseconds since boot.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct ProcessKey {
+    start_time: u64,
+    pid: u32,
+}
+
+impl ProcessKey {
+    pub fn new(pid: u32, start_time: u64) -> Self {
+        ProcessKey { start_time, pid }
+    }
+}
+
+impl From<ProcessKey> for lineage_key_t {
+    fn from(value: ProcessKey) -> Self {
+        lineage_key_t {
+            start_time: value.start_time,
+            pid: value.pid,
+            reserved: 0,
+        }
+    }
+}
+
+/// Lineages of processes, along with the processes ordered by start
+/// time for forgetting the oldest ones first.
+#[derive(Debug, Default)]
+struct Processes {
+    lineages: FxHashMap<ProcessKey, Arc<[Lineage]>>,
+    by_start: BTreeSet<ProcessKey>,
+}
+
+impl Processes {
+    fn insert(&mut self, key: ProcessKey, lineage: Arc<[Lineage]>) {
+        if self.lineages.insert(key, lineage).is_some() {
+            return;
+        }
+        self.by_start.insert(key);
+        while self.by_start.len() > PROCESSES_MAX {
+            let Some(oldest) = self.by_start.pop_first() else {
+                break;
+            };
+            self.lineages.remove(&oldest);
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Lineage {
+    uid: u32,
+    exe_path: PathBuf,
+}
+
+impl Lineage {
+    #[cfg(test)]
+    pub fn new(uid: u32, exe_path: impl Into<PathBuf>) -> Self {
+        Lineage {
+            uid,
+            exe_path: exe_path.into(),
+        }
+    }
+
+    pub fn uid(&self) -> u32 {
+        self.uid
+    }
+
+    pub fn exe_path(&self) -> &Path {
+        &self.exe_path
+    }
+}
+
+impl TryFrom<&lineage_t> for Lineage {
+    type Error = anyhow::Error;
+
+    fn try_from(value: &lineage_t) -> Result<Self, Self::Error> {
+        let lineage_t { uid, exe_path } = value;
+        let exe_path = sanitize_d_path(exe_path)?;
+
+        Ok(Lineage {
+            uid: *uid,
+            exe_path,
+        })
+    }
+}
+
+impl From<&Lineage> for fact_api::process_signal::LineageInfo {
+    fn from(value: &Lineage) -> Self {
+        Self {
+            parent_uid: value.uid,
+            parent_exec_file_path: value.exe_path.to_string_lossy().to_string(),
+        }
+    }
+}
+
+#[cfg(feature = "otel")]
+impl From<&Lineage> for AnyValue {
+    fn from(value: &Lineage) -> Self {
+        AnyValue::Map(Box::new(HashMap::from([
+            ("uid".into(), value.uid.into()),
+            (
+                "exec_path".into(),
+                value.exe_path.to_string_lossy().to_string().into(),
+            ),
+        ])))
+    }
+}
+
+/// The lineages seen in the events of a kernel, shared by the clones
+/// of the handle.
+#[derive(Debug, Clone, Default)]
+pub struct Lineages(Arc<Mutex<Inner>>);
+
+#[derive(Debug, Default)]
+struct Inner {
+    parsed: Cache,
+    processes: Processes,
+}
+
+impl Lineages {
+    /// Parse the lineage read from the kernel, sharing it with the
+    /// earlier events that had the same one.
+    pub fn parse(&self, raw: &[lineage_t]) -> anyhow::Result<Arc<[Lineage]>> {
+        let key = key(raw);
+        if let Some(lineage) = self.0.lock().unwrap().parsed.get(&key) {
+            return Ok(lineage.clone());
+        }
+
+        let lineage = parse_uncached(raw)?;
+        let parsed = &mut self.0.lock().unwrap().parsed;
+        if parsed.len() >= CACHE_MAX {
+            parsed.clear();
+        }
+        parsed.insert(key, lineage.clone());
+        Ok(lineage)
+    }
+
+    /// Number of distinct lineages cached.
+    pub fn cache_len(&self) -> usize {
+        self.0.lock().unwrap().parsed.len()
+    }
+
+    /// Keep the lineage the kernel sent for a process, for its later
+    /// events.
+    pub fn remember(&self, key: ProcessKey, lineage: Arc<[Lineage]>) {
+        self.0.lock().unwrap().processes.insert(key, lineage);
+    }
+
+    /// The lineage of a process the kernel left out of an event.
+    pub fn recall(&self, key: ProcessKey) -> Option<Arc<[Lineage]>> {
+        self.0.lock().unwrap().processes.lineages.get(&key).cloned()
+    }
+
+    /// Number of processes whose lineage is kept.
+    pub fn processes_len(&self) -> usize {
+        self.0.lock().unwrap().processes.lineages.len()
+    }
+}
+
+/// Parse the lineage read from the kernel into new allocations.
+pub fn parse_uncached(raw: &[lineage_t]) -> anyhow::Result<Arc<[Lineage]>> {
+    raw.iter().map(Lineage::try_from).collect()
+}
+
+/// The bytes identifying a lineage: the UID and path of every ancestor,
+/// up to the end of the path.
+fn key(raw: &[lineage_t]) -> Vec<u8> {
+    // Paths without an end are an error once parsed, they are not cached
+    // anyway
+    let paths = raw.iter().map(|lineage| {
+        slice_to_cstr(&lineage.exe_path)
+            .map(CStr::to_bytes_with_nul)
+            .unwrap_or_else(|| c_char_to_bytes(&lineage.exe_path))
+    });
+    let len = paths.clone().map(|path| path.len() + 4).sum();
+
+    let mut key = Vec::with_capacity(len);
+    for (lineage, path) in raw.iter().zip(paths) {
+        key.extend_from_slice(&lineage.uid.to_ne_bytes());
+        key.extend_from_slice(path);
+    }
+    key
+}
+
+#[cfg(test)]
+mod tests {
+    use fact_ebpf::PATH_MAX;
+
+    use super::*;
+    use crate::event::test_utils::string_to_c_char_array;
+
+    fn raw(ancestors: &[(u32, &str)]) -> Vec<lineage_t> {
+        ancestors
+            .iter()
+            .map(|(uid, path)| lineage_t {
+                uid: *uid,
+                exe_path: string_to_c_char_array::<{ PATH_MAX as usize }>(path),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn shared() {
+        let shell = raw(&[
+            (1000, "/usr/bin/bash"),
+            (1000, "/usr/bin/tmux"),
+            (0, "/usr/lib/systemd/systemd"),
+        ]);
+        let lineages = Lineages::default();
+        let first = lineages.parse(&shell).unwrap();
+        let second = lineages.parse(&shell).unwrap();
+        assert!(Arc::ptr_eq(&first, &second));
+        assert_eq!(
+            *first,
+            [
+                Lineage::new(1000, "/usr/bin/bash"),
+                Lineage::new(1000, "/usr/bin/tmux"),
+                Lineage::new(0, "/usr/lib/systemd/systemd"),
+            ]
+        );
+        assert_eq!(first, parse_uncached(&shell).unwrap());
+
+        // Nothing is shared with other pipelines
+        let other = Lineages::default().parse(&shell).unwrap();
+        assert!(!Arc::ptr_eq(&first, &other));
+        assert_eq!(lineages.cache_len(), 1);
+    }
+
+    #[test]
+    fn parent_exec() {
+        let lineages = Lineages::default();
+        let before = raw(&[(1000, "/usr/bin/bash"), (0, "/usr/lib/systemd/systemd")]);
+        let before = lineages.parse(&before).unwrap();
+
+        // The parent execs into another binary
+        let after = raw(&[(1000, "/usr/bin/python3"), (0, "/usr/lib/systemd/systemd")]);
+        let after = lineages.parse(&after).unwrap();
+        assert!(!Arc::ptr_eq(&before, &after));
+        assert_eq!(after[0], Lineage::new(1000, "/usr/bin/python3"));
+        assert_eq!(before[0], Lineage::new(1000, "/usr/bin/bash"));
+
+        // Or changes its user
+        let setuid = raw(&[(0, "/usr/bin/bash"), (0, "/usr/lib/systemd/systemd")]);
+        assert_eq!(
+            lineages.parse(&setuid).unwrap()[0],
+            Lineage::new(0, "/usr/bin/bash")
+        );
+
+        // Leftovers past the end of a shorter path are ignored
+        let mut long = raw(&[(1000, "/usr/bin/bash-static")]);
+        long[0].exe_path["/usr/bin/bash".len()] = 0;
+        assert_eq!(key(&long), key(&raw(&[(1000, "/usr/bin/bash")])));
+        assert_eq!(
+            *lineages.parse(&long).unwrap(),
+            [Lineage::new(1000, "/usr/bin/bash")]
+        );
+    }
+
+    #[test]
+    fn processes() {
+        let lineages = Lineages::default();
+        let shell = lineages.parse(&raw(&[(1000, "/usr/bin/bash")])).unwrap();
+        let key = ProcessKey::new(4242, 1_000);
+        assert_eq!(lineages.recall(key), None);
+        lineages.remember(key, shell.clone());
+        assert!(Arc::ptr_eq(&lineages.recall(key).unwrap(), &shell));
+        assert_eq!(lineages.processes_len(), 1);
+
+        // The ID of an exited process is reused
+        assert_eq!(lineages.recall(ProcessKey::new(4242, 2_000)), None);
+
+        // Clones of the handle share the processes, other pipelines do not
+        assert!(lineages.clone().recall(key).is_some());
+        assert_eq!(Lineages::default().recall(key), None);
+    }
+
+    #[test]
+    fn processes_oldest_forgotten() {
+        let mut processes = Processes::default();
+        let lineage: Arc<[Lineage]> = Arc::from([Lineage::new(0, "/usr/bin/bash")]);
+        // Inserted out of order, like events read from different CPUs
+        let keys = (0..=PROCESSES_MAX as u64)
+            .rev()
+            .map(|start| ProcessKey::new(1, start + 1));
+        for key in keys {
+            processes.insert(key, lineage.clone());
+        }
+        assert_eq!(processes.lineages.len(), PROCESSES_MAX);
+        assert_eq!(processes.by_start.len(), PROCESSES_MAX);
+        assert!(!processes.lineages.contains_key(&ProcessKey::new(1, 1)));
+        assert!(processes.lineages.contains_key(&ProcessKey::new(1, 2)));
+
+        // Inserting a process again does not forget another one
+        processes.insert(ProcessKey::new(1, 2), lineage.clone());
+        assert_eq!(processes.lineages.len(), PROCESSES_MAX);
+    }
+
+    #[test]
+    fn errors_not_cached() {
+        let mut unterminated = raw(&[(1000, "/usr/bin/bash")]);
+        unterminated[0].exe_path.fill(b'a' as _);
+        let lineages = Lineages::default();
+        assert!(lineages.parse(&unterminated).is_err());
+        assert!(lineages.parse(&unterminated).is_err());
+        assert_eq!(lineages.cache_len(), 0);
+    }
+}
@@ -1,11 +1,16 @@
 #[cfg(feature = "otel")]
 use std::collections::HashMap;
-#[cfg(all(test, feature = "bpf-test"))]
-use std::time::{SystemTime, UNIX_EPOCH};
 use std::{
+    borrow::Cow,
+    collections::{BTreeMap, HashSet},
     ffi::{CStr, OsStr},
-    os::{raw::c_char, unix::ffi::OsStrExt},
+    fs::Metadata,
+    mem::offset_of,
+    os::{linux::fs::MetadataExt, raw::c_char, unix::ffi::OsStrExt},
     path::{Path, PathBuf},
+    ptr,
+    sync::{LazyLock, Mutex},
+    time::{SystemTime, UNIX_EPOCH},
 };
 
 use globset::GlobSet;
@@ -14,16 +19,106 @@ use opentelemetry::logs::AnyValue;
 use serde::{Deserialize, Serialize};
 
 use fact_ebpf::{
-    PATH_MAX, XATTR_NAME_MAX_LEN, event_t, file_activity_type_t, inode_key_t, monitored_t,
+    LINEAGE_MAX, PATH_MAX, XATTR_NAME_MAX_LEN, event_t, file_activity_type_t, inode_key_t,
+    monitored_t, process_t, tmpfile_t,
 };
 
-use crate::host_info;
+use crate::{host_info, node_id, trace};
+use attributes::FileFlags;
+use lineage::Lineages;
 use process::Process;
+use raw::HostContext;
+pub(crate) use raw::parse_raw;
 
+pub(crate) mod attributes;
+pub(crate) mod capabilities;
+pub(crate) mod checkpoint_restore;
+pub(crate) mod lineage;
 pub(crate) mod process;
+pub(crate) mod raw;
+
+/// Size of the records of events whose lineage the kernel left out,
+/// they end right before it.
+const NO_LINEAGE_SIZE: usize = offset_of!(event_t, process) + offset_of!(process_t, lineage);
+
+/// Maximum length of the arguments buffer sent by the kernel.
+const ARGS_MAX: usize = fact_ebpf::ARGS_MAX as usize;
+
+/// Errors in the data sent by the kernel.
+///
+/// Lengths and buffers in events are populated kernel side, a bogus
+/// value in them should result in the event being dropped, never in
+/// a panic or an out of bounds read.
+#[derive(thiserror::Error, Debug, PartialEq)]
+pub enum ParseError {
+    #[error("lineage length {0} exceeds maximum of {LINEAGE_MAX}")]
+    LineageTooLong(u32),
+    #[error("args length {0} exceeds maximum of {ARGS_MAX}")]
+    ArgsTooLong(u32),
+    #[error("filename exceeds maximum length of {PATH_MAX}")]
+    FilenameTooLong,
+    #[error("string is not nul terminated")]
+    Unterminated,
+    #[error("unknown event type: {0}")]
+    UnknownEventType(i64),
+    #[error("record of {0} bytes is shorter than an event of {size} bytes", size = size_of::<event_t>())]
+    Truncated(usize),
+}
+
+/// Get the event at the start of a record read from the ringbuffer,
+/// checking the record is large enough to hold one.
+///
+/// Records in the ringbuffer are aligned for `event_t` and borrowed,
+/// unaligned buffers are copied. Records the kernel left the lineage
+/// out of are copied into an event with an empty lineage.
+fn event_from_bytes(data: &[u8]) -> Result<Cow<'_, event_t>, ParseError> {
+    let Some(data) = data.get(..size_of::<event_t>()) else {
+        return event_without_lineage(data).map(Cow::Owned);
+    };
+    let event = data.as_ptr().cast::<event_t>();
+    // SAFETY: event_t is plain old data, any bit pattern is valid, and
+    // the buffer was checked to hold one.
+    if event.is_aligned() {
+        Ok(Cow::Borrowed(unsafe { &*event }))
+    } else {
+        Ok(Cow::Owned(unsafe { event.read_unaligned() }))
+    }
+}
+
+fn event_without_lineage(data: &[u8]) -> Result<event_t, ParseError> {
+    let Some(head) = data.get(..NO_LINEAGE_SIZE) else {
+        return Err(ParseError::Truncated(data.len()));
+    };
+    let mut event = event_t::default();
+    // SAFETY: event_t is plain old data, any bit pattern is valid, and
+    // it is larger than the bytes copied.
+    unsafe {
+        ptr::copy_nonoverlapping(
+            head.as_ptr(),
+            ptr::from_mut(&mut event).cast::<u8>(),
+            NO_LINEAGE_SIZE,
+        );
+    }
+    if event.process.lineage_cached == 0 {
+        return Err(ParseError::Truncated(data.len()));
+    }
+    Ok(event)
+}
+
+fn c_char_to_bytes(s: &[c_char]) -> &[u8] {
+    // SAFETY: c_char and u8 have the same size and alignment.
+    unsafe { std::slice::from_raw_parts(s.as_ptr().cast(), s.len()) }
+}
+
+/// Get the nul terminated string at the start of the buffer, without
+/// going past its end.
+fn slice_to_cstr(s: &[c_char]) -> Option<&CStr> {
+    CStr::from_bytes_until_nul(c_char_to_bytes(s)).ok()
+}
 
 fn slice_to_string(s: &[c_char]) -> anyhow::Result<String> {
-    Ok(unsafe { CStr::from_ptr(s.as_ptr()) }.to_str()?.to_owned())
+    let s = slice_to_cstr(s).ok_or(ParseError::Unterminated)?;
+    Ok(s.to_str()?.to_owned())
 }
 
 /// Sanitize a buffer obtained from calling d_path kernel side.
@@ -43,8 +138,8 @@ fn slice_to_string(s: &[c_char]) -> anyhow::Result<String> {
 /// However, we believe this would be a _very_ special case with a low
 /// chance that we will stumble upon it, so we purposely decide to
 /// ignore it.
-fn sanitize_d_path(s: &[c_char]) -> PathBuf {
-    let s = unsafe { CStr::from_ptr(s.as_ptr()) };
+fn sanitize_d_path(s: &[c_char]) -> Result<PathBuf, ParseError> {
+    let s = slice_to_cstr(s).ok_or(ParseError::FilenameTooLong)?;
     let p = Path::new(OsStr::from_bytes(s.to_bytes()));
 
     // Take the file name of the path and remove the " (deleted)" suffix
@@ -53,10 +148,48 @@ fn sanitize_d_path(s: &[c_char]) -> PathBuf {
         && let Some(file_name) = file_name.to_string_lossy().strip_suffix(" (deleted)")
     {
         // The file name needed to be sanitized
-        return p.parent().map(|p| p.join(file_name)).unwrap_or_default();
+        return Ok(p.parent().map(|p| p.join(file_name)).unwrap_or_default());
     }
 
-    p.to_path_buf()
+    Ok(p.to_path_buf())
+}
+
+/// Get a string like a hostname or username with a static lifetime.
+///
+/// Strings are interned, so only one allocation is leaked for each
+/// distinct value.
+pub(crate) fn intern(s: &str) -> &'static str {
+    static INTERNED: LazyLock<Mutex<HashSet<&'static str>>> = LazyLock::new(Mutex::default);
+
+    let mut interned = INTERNED.lock().unwrap();
+    if let Some(s) = interned.get(s) {
+        return s;
+    }
+    let s: &'static str = s.to_owned().leak();
+    interned.insert(s);
+    s
+}
+
+/// A string returned by `intern`.
+///
+/// Spelled as an alias so serde does not try to borrow it from the
+/// input when deserializing.
+type Interned = &'static str;
+
+fn deserialize_interned<'de, D>(deserializer: D) -> Result<Interned, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    let s = String::deserialize(deserializer)?;
+    Ok(intern(&s))
+}
+
+/// Current time in nanoseconds since the epoch.
+pub(crate) fn now_ns() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_nanos() as _
 }
 
 fn timestamp_to_proto(ts: u64) -> prost_types::Timestamp {
@@ -65,6 +198,13 @@ fn timestamp_to_proto(ts: u64) -> prost_types::Timestamp {
     prost_types::Timestamp { seconds, nanos }
 }
 
+/// Version of the JSON representation of events.
+///
+/// Bumped whenever a change prevents output from a previous version
+/// from being parsed, like removing or renaming a field. Adding
+/// optional fields does not change it.
+pub const SCHEMA_VERSION: u32 = 1;
+
 #[cfg(all(test, feature = "bpf-test"))]
 #[derive(Debug)]
 pub(crate) enum EventTestData {
@@ -74,13 +214,117 @@ pub(crate) enum EventTestData {
     Rename(PathBuf),
 }
 
+/// Whether the file of an event existed when the event was emitted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Existence {
+    True,
+    False,
+    /// The check was skipped, failed or timed out.
+    Unknown,
+}
+
+impl Existence {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Existence::True => "true",
+            Existence::False => "false",
+            Existence::Unknown => "unknown",
+        }
+    }
+}
+
+/// How a file opened with O_TMPFILE came to be reported. Such files
+/// are created without a name and may be given one later with a link.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TmpFile {
+    /// Created without a name in the directory of the event.
+    Unnamed,
+    /// Given its first name, the path of the event.
+    Linked,
+}
+
+impl TmpFile {
+    fn from_kernel(value: tmpfile_t) -> Option<Self> {
+        match value {
+            tmpfile_t::TMPFILE_UNNAMED => Some(TmpFile::Unnamed),
+            tmpfile_t::TMPFILE_LINKED => Some(TmpFile::Linked),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            TmpFile::Unnamed => "unnamed",
+            TmpFile::Linked => "linked",
+        }
+    }
+}
+
+/// State of the kernel side filters when an event was received.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FilterState {
+    /// Monitored paths were not fully loaded, the event may have been
+    /// matched against incomplete or stale paths.
+    Initializing,
+    Ready,
+}
+
+impl FilterState {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            FilterState::Initializing => "initializing",
+            FilterState::Ready => "ready",
+        }
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Event {
+    /// Wall clock time of the event, in nanoseconds since the epoch.
     timestamp: u64,
-    #[serde(skip_deserializing)]
-    hostname: &'static str,
+    /// Time of the event in nanoseconds since boot, as read by the
+    /// kernel. It is not affected by changes to the wall clock, events
+    /// with the same `boot_id` can be ordered by it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    timestamp_boot_ns: Option<u64>,
+    /// Boot of the host `timestamp_boot_ns` counts from.
+    #[serde(
+        default,
+        deserialize_with = "deserialize_interned",
+        skip_serializing_if = "str::is_empty"
+    )]
+    boot_id: Interned,
+    #[serde(default, deserialize_with = "deserialize_interned")]
+    hostname: Interned,
+    /// Stable ID of the node, kept across restarts of fact.
+    #[serde(
+        default,
+        deserialize_with = "deserialize_interned",
+        skip_serializing_if = "str::is_empty"
+    )]
+    node_id: Interned,
     process: Process,
     file: FileData,
+    /// Labels of the monitored path the file matched.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    labels: BTreeMap<String, String>,
+    /// Set on sampled events, one out of every `sample_rate` events
+    /// like this one was kept.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    sample_rate: Option<u32>,
+    /// Set when `enrich.existence_check` is enabled.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    exists_at_emit: Option<Existence>,
+    /// Set on events received while filters were not fully loaded.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    filter_state: Option<FilterState>,
+    /// Host PID of the instance of fact that reported the event, set
+    /// when other instances run on the same host.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    instance_id: Option<u32>,
 }
 
 impl Event {
@@ -92,16 +336,15 @@ impl Event {
         host_file: PathBuf,
         process: Process,
     ) -> anyhow::Result<Self> {
-        let timestamp = SystemTime::now()
-            .duration_since(UNIX_EPOCH)
-            .unwrap()
-            .as_nanos() as _;
+        let timestamp = now_ns();
         let inner = BaseFileData {
             filename,
             host_file,
             inode: Default::default(),
             parent_inode: Default::default(),
             monitored: Default::default(),
+            blocked: false,
+            tmpfile: None,
         };
         let file = match data {
             EventTestData::Creation => FileData::Creation(inner),
@@ -121,6 +364,7 @@ impl Event {
                         filename: old_path,
                         ..Default::default()
                     },
+                    overwrote_existing: false,
                 };
                 FileData::Rename(data)
             }
@@ -128,12 +372,273 @@ impl Event {
 
         Ok(Event {
             timestamp,
+            timestamp_boot_ns: None,
+            boot_id: "",
             hostname,
+            node_id: node_id::get(),
             process,
             file,
+            labels: BTreeMap::new(),
+            sample_rate: None,
+            exists_at_emit: None,
+            filter_state: None,
+            instance_id: None,
         })
     }
 
+    /// Create an inventory event for a file found by `fact scan`.
+    ///
+    /// `path` is expected to include the host mount, inventory events
+    /// are not caused by any process so an empty one is used.
+    pub fn inventory(path: &Path, metadata: &Metadata) -> Self {
+        let host_file = host_info::remove_host_mount(path);
+        let inner = BaseFileData {
+            filename: host_file.clone(),
+            host_file,
+            inode: inode_key_t {
+                inode: metadata.st_ino(),
+                dev: metadata.st_dev(),
+            },
+            parent_inode: Default::default(),
+            monitored: Default::default(),
+            blocked: false,
+            tmpfile: None,
+        };
+        let file = FileData::Inventory(InventoryFileData {
+            inner,
+            mode: metadata.st_mode(),
+            uid: metadata.st_uid(),
+            gid: metadata.st_gid(),
+            file_owner_name: host_info::get_username(metadata.st_uid()),
+            file_group_name: host_info::get_groupname(metadata.st_gid()),
+            size: metadata.st_size(),
+        });
+
+        Event {
+            timestamp: now_ns(),
+            timestamp_boot_ns: None,
+            boot_id: "",
+            hostname: host_info::get_hostname(),
+            node_id: node_id::get(),
+            process: Process::default(),
+            file,
+            labels: BTreeMap::new(),
+            sample_rate: None,
+            exists_at_emit: None,
+            filter_state: None,
+            instance_id: None,
+        }
+    }
+
+    /// Create a summary of the events from `start` until now.
+    ///
+    /// Summaries are not caused by any process, an empty one is used.
+    pub(crate) fn summary(start: u64, entries: Vec<SummaryEntry>) -> Self {
+        let file = FileData::Summary(SummaryFileData {
+            inner: BaseFileData::default(),
+            start,
+            entries,
+        });
+        Event::from_parts(now_ns(), Process::default(), file)
+    }
+
+    /// Whether the event is a summary of other events.
+    pub fn is_summary(&self) -> bool {
+        matches!(self.file, FileData::Summary(_))
+    }
+
+    /// Create a record of collection being paused until `paused_until`,
+    /// in seconds since the epoch, or resumed if it is `None`.
+    ///
+    /// Pauses are not caused by any process, an empty one is used.
+    pub(crate) fn pause(paused_until: Option<u64>) -> Self {
+        let file = FileData::Pause(PauseFileData {
+            inner: BaseFileData::default(),
+            paused: paused_until.is_some(),
+            paused_until,
+        });
+        Event::from_parts(now_ns(), Process::default(), file)
+    }
+
+    /// Whether the event is a record of collection being paused or
+    /// resumed.
+    pub fn is_pause(&self) -> bool {
+        matches!(self.file, FileData::Pause(_))
+    }
+
+    /// Build an event from already parsed parts, skipping the
+    /// conversion from the kernel format.
+    pub(crate) fn from_parts(timestamp: u64, process: Process, file: FileData) -> Self {
+        Event {
+            timestamp,
+            timestamp_boot_ns: None,
+            boot_id: "",
+            hostname: host_info::get_hostname(),
+            node_id: node_id::get(),
+            process,
+            file,
+            labels: BTreeMap::new(),
+            sample_rate: None,
+            exists_at_emit: None,
+            filter_state: None,
+            instance_id: None,
+        }
+    }
+
+    /// Build an event from parts read from the kernel of this host,
+    /// `boot_ns` being the time of the event in nanoseconds since boot.
+    pub(crate) fn from_kernel_parts(boot_ns: u64, process: Process, file: FileData) -> Self {
+        Event::from_host_parts(boot_ns, process, file, &HostContext::local())
+    }
+
+    /// Build an event from parts read from the kernel of `host`.
+    fn from_host_parts(
+        boot_ns: u64,
+        process: Process,
+        mut file: FileData,
+        host: &HostContext,
+    ) -> Self {
+        file.resolve_names(host);
+        Event {
+            timestamp: host.boot_time.saturating_add(boot_ns),
+            timestamp_boot_ns: Some(boot_ns),
+            boot_id: host.boot_id,
+            hostname: host.hostname,
+            node_id: host.node_id,
+            ..Event::from_parts(0, process, file)
+        }
+    }
+
+    /// Parse an event sent by the kernel of `host`, see
+    /// `Process::from_kernel` for `lineages`.
+    fn from_kernel(
+        value: &event_t,
+        host: &HostContext,
+        lineages: &Lineages,
+    ) -> anyhow::Result<Self> {
+        let _span = trace::stage!("parse");
+        let process = Process::from_kernel(value.process, host, lineages)?;
+        let mut file = FileData::new(
+            value.type_,
+            value.filename,
+            value.inode,
+            value.parent_inode,
+            value.monitored,
+            value.blocked != 0,
+            value.__bindgen_anon_1,
+        )?;
+        if let FileData::Creation(data) = &mut file {
+            data.tmpfile = TmpFile::from_kernel(value.tmpfile);
+        }
+
+        Ok(Event::from_host_parts(value.timestamp, process, file, host))
+    }
+
+    pub fn get_timestamp(&self) -> u64 {
+        self.timestamp
+    }
+
+    pub fn set_timestamp(&mut self, timestamp: u64) {
+        self.timestamp = timestamp;
+    }
+
+    pub fn get_timestamp_boot_ns(&self) -> Option<u64> {
+        self.timestamp_boot_ns
+    }
+
+    pub fn get_boot_id(&self) -> &str {
+        self.boot_id
+    }
+
+    pub fn get_hostname(&self) -> &str {
+        self.hostname
+    }
+
+    pub fn get_node_id(&self) -> &str {
+        self.node_id
+    }
+
+    pub fn get_process(&self) -> &Process {
+        &self.process
+    }
+
+    pub(crate) fn args_mut(&mut self) -> &mut [String] {
+        self.process.args_mut()
+    }
+
+    pub fn get_file(&self) -> &FileData {
+        &self.file
+    }
+
+    pub fn get_labels(&self) -> &BTreeMap<String, String> {
+        &self.labels
+    }
+
+    pub fn get_sample_rate(&self) -> Option<u32> {
+        self.sample_rate
+    }
+
+    pub fn get_exists_at_emit(&self) -> Option<Existence> {
+        self.exists_at_emit
+    }
+
+    pub fn get_filter_state(&self) -> Option<FilterState> {
+        self.filter_state
+    }
+
+    pub fn get_instance_id(&self) -> Option<u32> {
+        self.instance_id
+    }
+
+    pub fn set_hostname(&mut self, hostname: &'static str) {
+        self.hostname = hostname;
+    }
+
+    pub fn set_labels(&mut self, labels: BTreeMap<String, String>) {
+        self.labels = labels;
+    }
+
+    pub fn set_sample_rate(&mut self, sample_rate: u32) {
+        self.sample_rate = Some(sample_rate);
+    }
+
+    pub fn set_exists_at_emit(&mut self, exists: Existence) {
+        self.exists_at_emit = Some(exists);
+    }
+
+    pub fn set_filter_state(&mut self, state: FilterState) {
+        self.filter_state = Some(state);
+    }
+
+    pub fn set_instance_id(&mut self, id: u32) {
+        self.instance_id = Some(id);
+    }
+
+    pub fn get_pid(&self) -> u32 {
+        self.process.pid()
+    }
+
+    pub fn get_uid(&self) -> u32 {
+        self.process.uid()
+    }
+
+    pub fn get_exe_path(&self) -> &Path {
+        self.process.exe_path()
+    }
+
+    pub fn get_container_id(&self) -> Option<&str> {
+        self.process.container_id()
+    }
+
+    /// Whether the event was caused by a checkpoint/restore operation.
+    pub fn is_checkpoint_restore(&self) -> bool {
+        self.process.is_checkpoint_restore()
+    }
+
+    pub fn is_open(&self) -> bool {
+        matches!(self.file, FileData::Open(_))
+    }
+
     pub fn is_creation(&self) -> bool {
         matches!(self.file, FileData::Creation(_) | FileData::MkDir(_))
     }
@@ -142,6 +647,16 @@ impl Event {
         matches!(self.file, FileData::SetXattr(_) | FileData::RemoveXattr(_))
     }
 
+    /// Whether the event is a change of the attribute flags of a
+    /// file.
+    pub fn is_attributes(&self) -> bool {
+        matches!(self.file, FileData::Attributes(_))
+    }
+
+    pub fn is_write(&self) -> bool {
+        matches!(self.file, FileData::Write(_))
+    }
+
     pub fn is_mkdir(&self) -> bool {
         matches!(self.file, FileData::MkDir(_))
     }
@@ -154,10 +669,37 @@ impl Event {
         matches!(self.file, FileData::Unlink(_) | FileData::RmDir(_))
     }
 
+    /// Whether the operation was denied because it happened on an
+    /// enforced protected path.
+    pub fn is_blocked(&self) -> bool {
+        match &self.file {
+            FileData::Open(data) | FileData::Creation(data) | FileData::Unlink(data) => {
+                data.blocked
+            }
+            _ => false,
+        }
+    }
+
     pub fn is_rename(&self) -> bool {
         matches!(self.file, FileData::Rename(_))
     }
 
+    /// Whether the event is a rename replacing an existing file.
+    pub fn overwrote_existing(&self) -> bool {
+        match &self.file {
+            FileData::Rename(data) => data.overwrote_existing,
+            _ => false,
+        }
+    }
+
+    /// How the file was created, if it was opened with O_TMPFILE.
+    pub fn tmpfile(&self) -> Option<TmpFile> {
+        match &self.file {
+            FileData::Creation(data) => data.tmpfile,
+            _ => None,
+        }
+    }
+
     /// Unwrap the inner FileData and return the inode that triggered
     /// the event.
     ///
@@ -166,6 +708,7 @@ impl Event {
     pub fn get_inode(&self) -> &inode_key_t {
         match &self.file {
             FileData::Open(data) => &data.inode,
+            FileData::Write(data) => &data.inode,
             FileData::Creation(data) => &data.inode,
             FileData::MkDir(data) => &data.inode,
             FileData::RmDir(data) => &data.inode,
@@ -176,6 +719,10 @@ impl Event {
             FileData::SetXattr(data) => &data.inner.inode,
             FileData::RemoveXattr(data) => &data.inner.inode,
             FileData::AclSet(data) => &data.inner.inode,
+            FileData::Attributes(data) => &data.inner.inode,
+            FileData::Inventory(data) => &data.inner.inode,
+            FileData::Summary(data) => &data.inner.inode,
+            FileData::Pause(data) => &data.inner.inode,
         }
     }
 
@@ -183,6 +730,7 @@ impl Event {
     pub fn get_parent_inode(&self) -> &inode_key_t {
         match &self.file {
             FileData::Open(data) => &data.parent_inode,
+            FileData::Write(data) => &data.parent_inode,
             FileData::Creation(data) => &data.parent_inode,
             FileData::MkDir(data) => &data.parent_inode,
             FileData::RmDir(data) => &data.parent_inode,
@@ -193,6 +741,10 @@ impl Event {
             FileData::SetXattr(data) => &data.inner.parent_inode,
             FileData::RemoveXattr(data) => &data.inner.parent_inode,
             FileData::AclSet(data) => &data.inner.parent_inode,
+            FileData::Attributes(data) => &data.inner.parent_inode,
+            FileData::Inventory(data) => &data.inner.parent_inode,
+            FileData::Summary(data) => &data.inner.parent_inode,
+            FileData::Pause(data) => &data.inner.parent_inode,
         }
     }
 
@@ -209,6 +761,7 @@ impl Event {
     pub fn get_filename(&self) -> &PathBuf {
         match &self.file {
             FileData::Open(data) => &data.filename,
+            FileData::Write(data) => &data.filename,
             FileData::Creation(data) => &data.filename,
             FileData::MkDir(data) => &data.filename,
             FileData::RmDir(data) => &data.filename,
@@ -219,6 +772,10 @@ impl Event {
             FileData::SetXattr(data) => &data.inner.filename,
             FileData::RemoveXattr(data) => &data.inner.filename,
             FileData::AclSet(data) => &data.inner.filename,
+            FileData::Attributes(data) => &data.inner.filename,
+            FileData::Inventory(data) => &data.inner.filename,
+            FileData::Summary(data) => &data.inner.filename,
+            FileData::Pause(data) => &data.inner.filename,
         }
     }
 
@@ -232,6 +789,7 @@ impl Event {
     pub fn get_host_path(&self) -> &PathBuf {
         match &self.file {
             FileData::Open(data) => &data.host_file,
+            FileData::Write(data) => &data.host_file,
             FileData::Creation(data) => &data.host_file,
             FileData::MkDir(data) => &data.host_file,
             FileData::RmDir(data) => &data.host_file,
@@ -242,6 +800,10 @@ impl Event {
             FileData::SetXattr(data) => &data.inner.host_file,
             FileData::RemoveXattr(data) => &data.inner.host_file,
             FileData::AclSet(data) => &data.inner.host_file,
+            FileData::Attributes(data) => &data.inner.host_file,
+            FileData::Inventory(data) => &data.inner.host_file,
+            FileData::Summary(data) => &data.inner.host_file,
+            FileData::Pause(data) => &data.inner.host_file,
         }
     }
 
@@ -259,6 +821,7 @@ impl Event {
     pub fn set_host_path(&mut self, host_path: PathBuf) {
         match &mut self.file {
             FileData::Open(data) => data.host_file = host_path,
+            FileData::Write(data) => data.host_file = host_path,
             FileData::Creation(data) => data.host_file = host_path,
             FileData::MkDir(data) => data.host_file = host_path,
             FileData::RmDir(data) => data.host_file = host_path,
@@ -269,6 +832,10 @@ impl Event {
             FileData::SetXattr(data) => data.inner.host_file = host_path,
             FileData::RemoveXattr(data) => data.inner.host_file = host_path,
             FileData::AclSet(data) => data.inner.host_file = host_path,
+            FileData::Attributes(data) => data.inner.host_file = host_path,
+            FileData::Inventory(data) => data.inner.host_file = host_path,
+            FileData::Summary(data) => data.inner.host_file = host_path,
+            FileData::Pause(data) => data.inner.host_file = host_path,
         }
     }
 
@@ -283,6 +850,7 @@ impl Event {
     pub fn get_monitored(&self) -> monitored_t {
         match &self.file {
             FileData::Open(data) => data.monitored,
+            FileData::Write(data) => data.monitored,
             FileData::Creation(data) => data.monitored,
             FileData::MkDir(data) => data.monitored,
             FileData::RmDir(data) => data.monitored,
@@ -293,6 +861,10 @@ impl Event {
             FileData::SetXattr(data) => data.inner.monitored,
             FileData::RemoveXattr(data) => data.inner.monitored,
             FileData::AclSet(data) => data.inner.monitored,
+            FileData::Attributes(data) => data.inner.monitored,
+            FileData::Inventory(data) => data.inner.monitored,
+            FileData::Summary(data) => data.inner.monitored,
+            FileData::Pause(data) => data.inner.monitored,
         }
     }
 
@@ -328,8 +900,8 @@ impl Event {
         self.get_monitored() == monitored_t::MONITORED_BY_PARENT
     }
 
-    #[cfg(feature = "otel")]
-    pub(crate) fn event_type(&self) -> &'static str {
+    /// Type of the event, one of `FileData::EVENT_TYPES`.
+    pub fn event_type(&self) -> &'static str {
         self.file.event_type()
     }
 }
@@ -338,63 +910,88 @@ impl TryFrom<&event_t> for Event {
     type Error = anyhow::Error;
 
     fn try_from(value: &event_t) -> Result<Self, Self::Error> {
-        let process = Process::try_from(value.process)?;
-        let timestamp = host_info::get_boot_time() + value.timestamp;
-        let file = FileData::new(
-            value.type_,
-            value.filename,
-            value.inode,
-            value.parent_inode,
-            value.monitored,
-            value.__bindgen_anon_1,
-        )?;
-
-        Ok(Event {
-            timestamp,
-            hostname: host_info::get_hostname(),
-            process,
-            file,
-        })
+        Event::from_kernel(value, &HostContext::local(), &Lineages::default())
     }
 }
 
-impl From<Event> for fact_api::FileActivity {
-    fn from(value: Event) -> Self {
-        let file = fact_api::file_activity::File::from(value.file);
+impl TryFrom<Event> for fact_api::FileActivity {
+    type Error = anyhow::Error;
+
+    fn try_from(value: Event) -> Result<Self, Self::Error> {
+        let _span = trace::stage!("serialize");
+        let file = fact_api::file_activity::File::try_from(value.file)?;
         let timestamp = timestamp_to_proto(value.timestamp);
         let process = fact_api::ProcessSignal::from(value.process);
 
-        Self {
+        Ok(Self {
             file: Some(file),
             timestamp: Some(timestamp),
             process: Some(process),
             hostname: value.hostname.to_string(),
-        }
+        })
     }
 }
 
 #[cfg(feature = "otel")]
 impl From<Event> for opentelemetry::logs::AnyValue {
     fn from(value: Event) -> Self {
-        AnyValue::Map(Box::new(HashMap::from([
+        let _span = trace::stage!("serialize");
+        let mut map = HashMap::from([
             ("file".into(), value.file.into()),
             ("timestamp".into(), AnyValue::Int(value.timestamp as i64)),
             ("process".into(), value.process.into()),
             ("hostname".into(), value.hostname.into()),
-        ])))
+        ]);
+        if let Some(boot_ns) = value.timestamp_boot_ns {
+            map.insert("timestamp_boot_ns".into(), AnyValue::Int(boot_ns as i64));
+            map.insert("boot_id".into(), value.boot_id.into());
+        }
+        if !value.node_id.is_empty() {
+            map.insert("node_id".into(), value.node_id.into());
+        }
+        if !value.labels.is_empty() {
+            let labels = value
+                .labels
+                .into_iter()
+                .map(|(k, v)| (k.into(), v.into()))
+                .collect();
+            map.insert("labels".into(), AnyValue::Map(Box::new(labels)));
+        }
+        if let Some(sample_rate) = value.sample_rate {
+            map.insert("sample_rate".into(), AnyValue::Int(sample_rate.into()));
+        }
+        if let Some(exists) = value.exists_at_emit {
+            map.insert("exists_at_emit".into(), exists.as_str().into());
+        }
+        if let Some(state) = value.filter_state {
+            map.insert("filter_state".into(), state.as_str().into());
+        }
+        if let Some(id) = value.instance_id {
+            map.insert("instance_id".into(), AnyValue::Int(id.into()));
+        }
+        AnyValue::Map(Box::new(map))
     }
 }
 
 #[cfg(test)]
 impl PartialEq for Event {
     fn eq(&self, other: &Self) -> bool {
-        self.hostname == other.hostname && self.process == other.process && self.file == other.file
+        self.hostname == other.hostname
+            && self.node_id == other.node_id
+            && self.process == other.process
+            && self.file == other.file
+            && self.labels == other.labels
+            && self.sample_rate == other.sample_rate
+            && self.exists_at_emit == other.exists_at_emit
+            && self.filter_state == other.filter_state
+            && self.instance_id == other.instance_id
     }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum FileData {
     Open(BaseFileData),
+    Write(BaseFileData),
     Creation(BaseFileData),
     MkDir(BaseFileData),
     RmDir(BaseFileData),
@@ -405,6 +1002,10 @@ pub enum FileData {
     SetXattr(XattrFileData),
     RemoveXattr(XattrFileData),
     AclSet(AclSetFileData),
+    Attributes(AttributesFileData),
+    Inventory(InventoryFileData),
+    Summary(SummaryFileData),
+    Pause(PauseFileData),
 }
 
 impl FileData {
@@ -414,11 +1015,14 @@ impl FileData {
         inode: inode_key_t,
         parent_inode: inode_key_t,
         monitored: monitored_t,
+        blocked: bool,
         extra_data: fact_ebpf::event_t__bindgen_ty_1,
     ) -> anyhow::Result<Self> {
-        let inner = BaseFileData::new(filename, inode, parent_inode, monitored)?;
+        let mut inner = BaseFileData::new(filename, inode, parent_inode, monitored)?;
+        inner.blocked = blocked;
         let file = match event_type {
             file_activity_type_t::FILE_ACTIVITY_OPEN => FileData::Open(inner),
+            file_activity_type_t::FILE_ACTIVITY_WRITE => FileData::Write(inner),
             file_activity_type_t::FILE_ACTIVITY_CREATION => FileData::Creation(inner),
             file_activity_type_t::DIR_ACTIVITY_CREATION => FileData::MkDir(inner),
             file_activity_type_t::DIR_ACTIVITY_UNLINK => FileData::RmDir(inner),
@@ -432,12 +1036,17 @@ impl FileData {
                 FileData::Chmod(data)
             }
             file_activity_type_t::FILE_ACTIVITY_CHOWN => {
+                let new_uid = unsafe { extra_data.chown.new.uid };
+                let new_gid = unsafe { extra_data.chown.new.gid };
                 let data = ChownFileData {
                     inner,
-                    new_uid: unsafe { extra_data.chown.new.uid },
-                    new_gid: unsafe { extra_data.chown.new.gid },
+                    new_uid,
+                    new_gid,
                     old_uid: unsafe { extra_data.chown.old.uid },
                     old_gid: unsafe { extra_data.chown.old.gid },
+                    // Set by `resolve_names`
+                    file_owner_name: "",
+                    file_group_name: "",
                 };
                 FileData::Chown(data)
             }
@@ -453,6 +1062,7 @@ impl FileData {
                         Default::default(),
                         old_monitored,
                     )?,
+                    overwrote_existing: unsafe { extra_data.rename.overwrote_existing } != 0,
                 };
                 FileData::Rename(data)
             }
@@ -483,16 +1093,74 @@ impl FileData {
                     entries,
                 })
             }
-            invalid => unreachable!("Invalid event type: {invalid:?}"),
+            file_activity_type_t::FILE_ACTIVITY_ATTRIBUTES => {
+                let data = AttributesFileData {
+                    inner,
+                    old_flags: unsafe { extra_data.attributes.old }.into(),
+                    new_flags: unsafe { extra_data.attributes.new }.into(),
+                };
+                FileData::Attributes(data)
+            }
+            invalid => return Err(ParseError::UnknownEventType(i64::from(invalid.0)).into()),
         };
 
         Ok(file)
     }
 
-    #[cfg(feature = "otel")]
-    fn event_type(&self) -> &'static str {
+    /// Look up the names of the owner set by ownership changes on
+    /// `host`.
+    fn resolve_names(&mut self, host: &HostContext) {
+        if let FileData::Chown(data) = self {
+            data.file_owner_name = host.username(data.new_uid);
+            data.file_group_name = host.groupname(data.new_gid);
+        }
+    }
+
+    /// Names of all event types, as returned by `event_type`.
+    pub const EVENT_TYPES: [&'static str; 16] = [
+        "open",
+        "creation",
+        "mkdir",
+        "rmdir",
+        "unlink",
+        "permission",
+        "ownership",
+        "rename",
+        "xattr_set",
+        "xattr_remove",
+        "acl",
+        "write",
+        "attributes",
+        "inventory",
+        "summary",
+        "pause",
+    ];
+
+    /// File the event is about, the new one for renames.
+    pub fn base(&self) -> &BaseFileData {
+        match self {
+            FileData::Open(data)
+            | FileData::Write(data)
+            | FileData::Creation(data)
+            | FileData::MkDir(data)
+            | FileData::RmDir(data)
+            | FileData::Unlink(data) => data,
+            FileData::Chmod(data) => &data.inner,
+            FileData::Chown(data) => &data.inner,
+            FileData::Rename(data) => &data.new,
+            FileData::SetXattr(data) | FileData::RemoveXattr(data) => &data.inner,
+            FileData::AclSet(data) => &data.inner,
+            FileData::Attributes(data) => &data.inner,
+            FileData::Inventory(data) => &data.inner,
+            FileData::Summary(data) => &data.inner,
+            FileData::Pause(data) => &data.inner,
+        }
+    }
+
+    pub fn event_type(&self) -> &'static str {
         match self {
             FileData::Open(_) => "open",
+            FileData::Write(_) => "write",
             FileData::Creation(_) => "creation",
             FileData::MkDir(_) => "mkdir",
             FileData::RmDir(_) => "rmdir",
@@ -503,13 +1171,20 @@ impl FileData {
             FileData::SetXattr(_) => "xattr_set",
             FileData::RemoveXattr(_) => "xattr_remove",
             FileData::AclSet(_) => "acl",
+            FileData::Attributes(_) => "attributes",
+            FileData::Inventory(_) => "inventory",
+            FileData::Summary(_) => "summary",
+            FileData::Pause(_) => "pause",
         }
     }
 }
 
-impl From<FileData> for fact_api::file_activity::File {
-    fn from(event: FileData) -> Self {
-        match event {
+impl TryFrom<FileData> for fact_api::file_activity::File {
+    type Error = anyhow::Error;
+
+    /// Fails for the kinds the Sensor API has no message for.
+    fn try_from(event: FileData) -> Result<Self, Self::Error> {
+        let file = match event {
             FileData::Open(event) => {
                 let activity = Some(fact_api::FileActivityBase::from(event));
                 let f_act = fact_api::FileOpen { activity };
@@ -520,12 +1195,6 @@ impl From<FileData> for fact_api::file_activity::File {
                 let f_act = fact_api::FileCreation { activity };
                 fact_api::file_activity::File::Creation(f_act)
             }
-            FileData::MkDir(_) => {
-                unreachable!("MkDir event reached protobuf conversion");
-            }
-            FileData::RmDir(_) => {
-                unreachable!("RmDir event reached protobuf conversion");
-            }
             FileData::SetXattr(event) => {
                 let f_act = fact_api::FileXattrChange::from(event);
                 fact_api::file_activity::File::XattrSet(f_act)
@@ -555,7 +1224,23 @@ impl From<FileData> for fact_api::file_activity::File {
                 let f_act = fact_api::FileAclChange::from(event);
                 fact_api::file_activity::File::Acl(f_act)
             }
-        }
+            FileData::Inventory(event) => {
+                // The API has no dedicated message for inventory, files
+                // found by a scan are reported as created.
+                let activity = Some(fact_api::FileActivityBase::from(event.inner));
+                let f_act = fact_api::FileCreation { activity };
+                fact_api::file_activity::File::Creation(f_act)
+            }
+            FileData::Write(_)
+            | FileData::MkDir(_)
+            | FileData::RmDir(_)
+            | FileData::Attributes(_)
+            | FileData::Summary(_)
+            | FileData::Pause(_) => {
+                anyhow::bail!("{} events have no Sensor API message", event.event_type())
+            }
+        };
+        Ok(file)
     }
 }
 
@@ -565,6 +1250,7 @@ impl From<FileData> for opentelemetry::logs::AnyValue {
         let event_type = value.event_type();
         let AnyValue::Map(mut map) = (match value {
             FileData::Open(data)
+            | FileData::Write(data)
             | FileData::Creation(data)
             | FileData::MkDir(data)
             | FileData::RmDir(data)
@@ -574,6 +1260,10 @@ impl From<FileData> for opentelemetry::logs::AnyValue {
             FileData::Rename(data) => AnyValue::from(data),
             FileData::SetXattr(data) | FileData::RemoveXattr(data) => AnyValue::from(data),
             FileData::AclSet(data) => AnyValue::from(data),
+            FileData::Attributes(data) => AnyValue::from(data),
+            FileData::Inventory(data) => AnyValue::from(data),
+            FileData::Summary(data) => AnyValue::from(data),
+            FileData::Pause(data) => AnyValue::from(data),
         }) else {
             unreachable!("event data did not serialize to map");
         };
@@ -589,6 +1279,7 @@ impl PartialEq for FileData {
     fn eq(&self, other: &Self) -> bool {
         match (self, other) {
             (FileData::Open(this), FileData::Open(other)) => this == other,
+            (FileData::Write(this), FileData::Write(other)) => this == other,
             (FileData::Creation(this), FileData::Creation(other)) => this == other,
             (FileData::MkDir(this), FileData::MkDir(other)) => this == other,
             (FileData::RmDir(this), FileData::RmDir(other)) => this == other,
@@ -603,6 +1294,10 @@ impl PartialEq for FileData {
                     && this.acl_type == other.acl_type
                     && this.entries == other.entries
             }
+            (FileData::Attributes(this), FileData::Attributes(other)) => this == other,
+            (FileData::Inventory(this), FileData::Inventory(other)) => this == other,
+            (FileData::Summary(this), FileData::Summary(other)) => this == other,
+            (FileData::Pause(this), FileData::Pause(other)) => this == other,
             _ => false,
         }
     }
@@ -615,6 +1310,13 @@ pub struct BaseFileData {
     inode: inode_key_t,
     parent_inode: inode_key_t,
     monitored: monitored_t,
+    /// The operation was denied by fact, only set for write opens and
+    /// unlinks on enforced protected paths.
+    #[serde(default)]
+    blocked: bool,
+    /// Only set on creations of files opened with O_TMPFILE.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    tmpfile: Option<TmpFile>,
 }
 
 impl BaseFileData {
@@ -625,19 +1327,48 @@ impl BaseFileData {
         monitored: monitored_t,
     ) -> anyhow::Result<Self> {
         Ok(BaseFileData {
-            filename: sanitize_d_path(&filename),
+            filename: sanitize_d_path(&filename)?,
             host_file: PathBuf::new(), // this field is set by HostScanner
             inode,
             parent_inode,
             monitored,
+            blocked: false,
+            tmpfile: None,
         })
     }
+
+    pub fn filename(&self) -> &Path {
+        &self.filename
+    }
+
+    /// Path of the file on the host, empty until resolved.
+    pub fn host_file(&self) -> &Path {
+        &self.host_file
+    }
+
+    pub fn inode(&self) -> &inode_key_t {
+        &self.inode
+    }
+
+    pub fn parent_inode(&self) -> &inode_key_t {
+        &self.parent_inode
+    }
+
+    pub fn monitored(&self) -> monitored_t {
+        self.monitored
+    }
+
+    pub fn is_blocked(&self) -> bool {
+        self.blocked
+    }
 }
 
 #[cfg(test)]
 impl PartialEq for BaseFileData {
     fn eq(&self, other: &Self) -> bool {
-        self.filename == other.filename && self.host_file == other.host_file
+        self.filename == other.filename
+            && self.host_file == other.host_file
+            && self.blocked == other.blocked
     }
 }
 
@@ -653,7 +1384,7 @@ impl From<BaseFileData> for fact_api::FileActivityBase {
 #[cfg(feature = "otel")]
 impl From<BaseFileData> for opentelemetry::logs::AnyValue {
     fn from(value: BaseFileData) -> Self {
-        AnyValue::Map(Box::new(HashMap::from([
+        let mut map = HashMap::from([
             (
                 "filename".into(),
                 value.filename.to_string_lossy().to_string().into(),
@@ -662,7 +1393,12 @@ impl From<BaseFileData> for opentelemetry::logs::AnyValue {
                 "host_path".into(),
                 value.host_file.to_string_lossy().to_string().into(),
             ),
-        ])))
+            ("blocked".into(), value.blocked.into()),
+        ]);
+        if let Some(tmpfile) = value.tmpfile {
+            map.insert("tmpfile".into(), tmpfile.as_str().into());
+        }
+        AnyValue::Map(Box::new(map))
     }
 }
 
@@ -673,6 +1409,20 @@ pub struct ChmodFileData {
     old_mode: u16,
 }
 
+impl ChmodFileData {
+    pub fn base(&self) -> &BaseFileData {
+        &self.inner
+    }
+
+    pub fn new_mode(&self) -> u16 {
+        self.new_mode
+    }
+
+    pub fn old_mode(&self) -> u16 {
+        self.old_mode
+    }
+}
+
 impl From<ChmodFileData> for fact_api::FilePermissionChange {
     fn from(value: ChmodFileData) -> Self {
         let ChmodFileData {
@@ -712,6 +1462,206 @@ impl PartialEq for ChmodFileData {
     }
 }
 
+/// Metadata of an existing file, as found by `fact scan`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct InventoryFileData {
+    inner: BaseFileData,
+    mode: u32,
+    uid: u32,
+    gid: u32,
+    /// Names of the user and group owning the file.
+    #[serde(default, deserialize_with = "deserialize_interned")]
+    file_owner_name: Interned,
+    #[serde(default, deserialize_with = "deserialize_interned")]
+    file_group_name: Interned,
+    size: u64,
+}
+
+impl InventoryFileData {
+    pub fn base(&self) -> &BaseFileData {
+        &self.inner
+    }
+
+    pub fn mode(&self) -> u32 {
+        self.mode
+    }
+
+    pub fn uid(&self) -> u32 {
+        self.uid
+    }
+
+    pub fn gid(&self) -> u32 {
+        self.gid
+    }
+
+    pub fn file_owner_name(&self) -> &str {
+        self.file_owner_name
+    }
+
+    pub fn file_group_name(&self) -> &str {
+        self.file_group_name
+    }
+
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+}
+
+#[cfg(feature = "otel")]
+impl From<InventoryFileData> for opentelemetry::logs::AnyValue {
+    fn from(value: InventoryFileData) -> Self {
+        let AnyValue::Map(mut map) = value.inner.into() else {
+            unreachable!("inner value did not serialize to map");
+        };
+        map.extend([
+            ("mode".into(), value.mode.into()),
+            ("uid".into(), value.uid.into()),
+            ("gid".into(), value.gid.into()),
+            ("file_owner_name".into(), value.file_owner_name.into()),
+            ("file_group_name".into(), value.file_group_name.into()),
+            ("size".into(), AnyValue::Int(value.size as i64)),
+        ]);
+
+        AnyValue::Map(map)
+    }
+}
+
+#[cfg(test)]
+impl PartialEq for InventoryFileData {
+    fn eq(&self, other: &Self) -> bool {
+        self.mode == other.mode
+            && self.uid == other.uid
+            && self.gid == other.gid
+            && self.file_owner_name == other.file_owner_name
+            && self.file_group_name == other.file_group_name
+            && self.size == other.size
+            && self.inner == other.inner
+    }
+}
+
+/// Number of events of each type on the monitored paths over a period
+/// of time, sent every `summary_interval`.
+///
+/// Summaries are not about any file, the inner data is always empty.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SummaryFileData {
+    inner: BaseFileData,
+    /// Start of the period, the end is the timestamp of the event.
+    start: u64,
+    entries: Vec<SummaryEntry>,
+}
+
+impl SummaryFileData {
+    pub fn base(&self) -> &BaseFileData {
+        &self.inner
+    }
+
+    pub fn start(&self) -> u64 {
+        self.start
+    }
+
+    pub fn entries(&self) -> &[SummaryEntry] {
+        &self.entries
+    }
+}
+
+#[cfg(feature = "otel")]
+impl From<SummaryFileData> for opentelemetry::logs::AnyValue {
+    fn from(value: SummaryFileData) -> Self {
+        let entries = value
+            .entries
+            .into_iter()
+            .map(AnyValue::from)
+            .collect::<Vec<_>>();
+        let map = HashMap::from([
+            ("start".into(), AnyValue::Int(value.start as i64)),
+            ("entries".into(), AnyValue::ListAny(Box::new(entries))),
+        ]);
+
+        AnyValue::Map(Box::new(map))
+    }
+}
+
+#[cfg(test)]
+impl PartialEq for SummaryFileData {
+    fn eq(&self, other: &Self) -> bool {
+        self.start == other.start && self.entries == other.entries
+    }
+}
+
+/// Collection being paused or resumed through the control endpoints,
+/// sent so consumers can account for the gap in the events.
+///
+/// Pauses are not about any file, the inner data is always empty.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PauseFileData {
+    inner: BaseFileData,
+    /// Whether collection was paused, false once it resumed.
+    paused: bool,
+    /// Seconds since the epoch at which collection resumes on its own,
+    /// only set while paused.
+    paused_until: Option<u64>,
+}
+
+impl PauseFileData {
+    pub fn base(&self) -> &BaseFileData {
+        &self.inner
+    }
+
+    pub fn paused(&self) -> bool {
+        self.paused
+    }
+
+    pub fn paused_until(&self) -> Option<u64> {
+        self.paused_until
+    }
+}
+
+#[cfg(feature = "otel")]
+impl From<PauseFileData> for opentelemetry::logs::AnyValue {
+    fn from(value: PauseFileData) -> Self {
+        let mut map = HashMap::from([("paused".into(), value.paused.into())]);
+        if let Some(until) = value.paused_until {
+            map.insert("paused_until".into(), AnyValue::Int(until as i64));
+        }
+
+        AnyValue::Map(Box::new(map))
+    }
+}
+
+#[cfg(test)]
+impl PartialEq for PauseFileData {
+    fn eq(&self, other: &Self) -> bool {
+        self.paused == other.paused && self.paused_until == other.paused_until
+    }
+}
+
+/// Events counted in a summary for a monitored path, event type and
+/// whether they came from a container.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct SummaryEntry {
+    /// Monitored path the files matched, as configured. Empty for
+    /// files outside of all of them.
+    pub path: String,
+    pub event_type: String,
+    pub container: bool,
+    pub count: u64,
+}
+
+#[cfg(feature = "otel")]
+impl From<SummaryEntry> for opentelemetry::logs::AnyValue {
+    fn from(value: SummaryEntry) -> Self {
+        let map = HashMap::from([
+            ("path".into(), value.path.into()),
+            ("event_type".into(), value.event_type.into()),
+            ("container".into(), value.container.into()),
+            ("count".into(), AnyValue::Int(value.count as i64)),
+        ]);
+
+        AnyValue::Map(Box::new(map))
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ChownFileData {
     inner: BaseFileData,
@@ -719,6 +1669,41 @@ pub struct ChownFileData {
     new_gid: u32,
     old_uid: u32,
     old_gid: u32,
+    /// Names of the user and group owning the file after the change.
+    #[serde(default, deserialize_with = "deserialize_interned")]
+    file_owner_name: Interned,
+    #[serde(default, deserialize_with = "deserialize_interned")]
+    file_group_name: Interned,
+}
+
+impl ChownFileData {
+    pub fn base(&self) -> &BaseFileData {
+        &self.inner
+    }
+
+    pub fn new_uid(&self) -> u32 {
+        self.new_uid
+    }
+
+    pub fn new_gid(&self) -> u32 {
+        self.new_gid
+    }
+
+    pub fn old_uid(&self) -> u32 {
+        self.old_uid
+    }
+
+    pub fn old_gid(&self) -> u32 {
+        self.old_gid
+    }
+
+    pub fn file_owner_name(&self) -> &str {
+        self.file_owner_name
+    }
+
+    pub fn file_group_name(&self) -> &str {
+        self.file_group_name
+    }
 }
 
 #[cfg(test)]
@@ -728,6 +1713,8 @@ impl PartialEq for ChownFileData {
             && self.new_gid == other.new_gid
             && self.old_uid == other.old_uid
             && self.old_gid == other.old_gid
+            && self.file_owner_name == other.file_owner_name
+            && self.file_group_name == other.file_group_name
             && self.inner == other.inner
     }
 }
@@ -738,6 +1725,8 @@ impl From<ChownFileData> for fact_api::FileOwnershipChange {
             inner: file,
             new_uid,
             new_gid,
+            file_owner_name,
+            file_group_name,
             ..
         } = value;
         let activity = fact_api::FileActivityBase::from(file);
@@ -745,8 +1734,8 @@ impl From<ChownFileData> for fact_api::FileOwnershipChange {
             activity: Some(activity),
             uid: new_uid,
             gid: new_gid,
-            username: "".to_string(),
-            group: "".to_string(),
+            username: file_owner_name.to_owned(),
+            group: file_group_name.to_owned(),
         }
     }
 }
@@ -762,6 +1751,8 @@ impl From<ChownFileData> for opentelemetry::logs::AnyValue {
             ("old_uid".into(), value.old_uid.into()),
             ("new_gid".into(), value.new_gid.into()),
             ("old_gid".into(), value.old_gid.into()),
+            ("file_owner_name".into(), value.file_owner_name.into()),
+            ("file_group_name".into(), value.file_group_name.into()),
         ]);
 
         AnyValue::Map(map)
@@ -772,10 +1763,28 @@ impl From<ChownFileData> for opentelemetry::logs::AnyValue {
 pub struct RenameFileData {
     new: BaseFileData,
     old: BaseFileData,
+    /// The rename replaced an existing file at the new path. The inode
+    /// and host path in `new` are the ones of the replaced file.
+    #[serde(default)]
+    overwrote_existing: bool,
+}
+
+impl RenameFileData {
+    pub fn new_file(&self) -> &BaseFileData {
+        &self.new
+    }
+
+    pub fn old_file(&self) -> &BaseFileData {
+        &self.old
+    }
+
+    pub fn overwrote_existing(&self) -> bool {
+        self.overwrote_existing
+    }
 }
 
 impl From<RenameFileData> for fact_api::FileRename {
-    fn from(RenameFileData { new, old }: RenameFileData) -> Self {
+    fn from(RenameFileData { new, old, .. }: RenameFileData) -> Self {
         let new = fact_api::FileActivityBase::from(new);
         let old = fact_api::FileActivityBase::from(old);
         fact_api::FileRename {
@@ -792,6 +1801,7 @@ impl From<RenameFileData> for opentelemetry::logs::AnyValue {
             unreachable!("new value did not serialize to map");
         };
         map.insert("old".into(), value.old.into());
+        map.insert("overwrote_existing".into(), value.overwrote_existing.into());
         AnyValue::Map(map)
     }
 }
@@ -851,6 +1861,19 @@ pub struct AclEntry {
 }
 
 impl AclEntry {
+    pub fn tag(&self) -> AclTag {
+        self.tag
+    }
+
+    pub fn perm(&self) -> u16 {
+        self.perm
+    }
+
+    /// User or group the entry is for, only set for named entries.
+    pub fn id(&self) -> Option<u32> {
+        self.id
+    }
+
     fn new(entry: &fact_ebpf::acl_entry_t) -> Self {
         let tag = AclTag::from(entry.e_tag);
         let id = tag.has_qualifier().then_some(entry.e_id);
@@ -900,6 +1923,20 @@ pub struct AclSetFileData {
     entries: Vec<AclEntry>,
 }
 
+impl AclSetFileData {
+    pub fn base(&self) -> &BaseFileData {
+        &self.inner
+    }
+
+    pub fn acl_type(&self) -> AclType {
+        self.acl_type
+    }
+
+    pub fn entries(&self) -> &[AclEntry] {
+        &self.entries
+    }
+}
+
 impl From<AclTag> for i32 {
     fn from(tag: AclTag) -> Self {
         match tag {
@@ -965,7 +2002,9 @@ impl From<AclSetFileData> for opentelemetry::logs::AnyValue {
 #[cfg(test)]
 impl PartialEq for RenameFileData {
     fn eq(&self, other: &Self) -> bool {
-        self.new == other.new && self.old == other.old
+        self.new == other.new
+            && self.old == other.old
+            && self.overwrote_existing == other.overwrote_existing
     }
 }
 
@@ -975,6 +2014,16 @@ pub struct XattrFileData {
     xattr_name: String,
 }
 
+impl XattrFileData {
+    pub fn base(&self) -> &BaseFileData {
+        &self.inner
+    }
+
+    pub fn xattr_name(&self) -> &str {
+        &self.xattr_name
+    }
+}
+
 impl From<XattrFileData> for fact_api::FileXattrChange {
     fn from(value: XattrFileData) -> Self {
         let activity = fact_api::FileActivityBase::from(value.inner);
@@ -1004,8 +2053,61 @@ impl PartialEq for XattrFileData {
     }
 }
 
+/// Change of the attribute flags of a file, only the immutable,
+/// append-only and fs-verity flags are reported.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AttributesFileData {
+    inner: BaseFileData,
+    old_flags: FileFlags,
+    new_flags: FileFlags,
+}
+
+impl AttributesFileData {
+    pub fn base(&self) -> &BaseFileData {
+        &self.inner
+    }
+
+    pub fn old_flags(&self) -> FileFlags {
+        self.old_flags
+    }
+
+    pub fn new_flags(&self) -> FileFlags {
+        self.new_flags
+    }
+}
+
+#[cfg(feature = "otel")]
+impl From<AttributesFileData> for opentelemetry::logs::AnyValue {
+    fn from(value: AttributesFileData) -> Self {
+        let AnyValue::Map(mut map) = value.inner.into() else {
+            unreachable!("inner value did not serialize to map");
+        };
+        for (key, flags) in [
+            ("old_flags", value.old_flags),
+            ("new_flags", value.new_flags),
+        ] {
+            let names = flags
+                .names()
+                .map(|name| AnyValue::from(name.into_owned()))
+                .collect();
+            map.insert(key.into(), AnyValue::ListAny(Box::new(names)));
+        }
+
+        AnyValue::Map(map)
+    }
+}
+
+#[cfg(test)]
+impl PartialEq for AttributesFileData {
+    fn eq(&self, other: &Self) -> bool {
+        self.old_flags == other.old_flags
+            && self.new_flags == other.new_flags
+            && self.inner == other.inner
+    }
+}
+
 #[cfg(test)]
-mod test_utils {
+pub(crate) mod test_utils {
     use std::os::raw::c_char;
 
     /// Helper function to convert raw bytes to a c_char array for testing
@@ -1026,9 +2128,415 @@ mod test_utils {
 
 #[cfg(test)]
 mod tests {
+    use proptest::{collection::vec, prelude::*};
+
     use super::test_utils::*;
     use super::*;
 
+    /// Parsing either succeeds or fails with an error for bad data
+    /// coming from the kernel, anything else is a bug.
+    fn assert_clean_parse(res: anyhow::Result<Event>) {
+        if let Err(e) = res {
+            assert!(
+                e.downcast_ref::<ParseError>().is_some()
+                    || e.downcast_ref::<std::str::Utf8Error>().is_some(),
+                "Unexpected error: {e:?}"
+            );
+        }
+    }
+
+    proptest! {
+        #[test]
+        fn event_parsing_random_bytes(data in vec(any::<u8>(), 0..=size_of::<event_t>())) {
+            assert_clean_parse(raw::parse_raw(&data));
+            let mut padded = data;
+            padded.resize(size_of::<event_t>(), 0);
+            assert_clean_parse(raw::parse_raw(&padded));
+        }
+
+        #[test]
+        fn event_parsing_mutated_fields(
+            event_type in -2..16i32,
+            lineage_len in prop_oneof![0..=LINEAGE_MAX, any::<u32>()],
+            args_len in prop_oneof![0..=ARGS_MAX as u32, any::<u32>()],
+            args in vec(any::<u8>(), 0..=ARGS_MAX),
+            filename in vec(1..=u8::MAX, 0..=PATH_MAX as usize),
+            exe_path in vec(1..=u8::MAX, 0..=PATH_MAX as usize),
+        ) {
+            let mut event = event_t {
+                type_: file_activity_type_t(event_type),
+                ..Default::default()
+            };
+            event.process.lineage_len = lineage_len;
+            event.process.args_len = args_len;
+            for (dst, src) in event.process.args.iter_mut().zip(&args) {
+                *dst = *src as c_char;
+            }
+            for (dst, src) in event.filename.iter_mut().zip(&filename) {
+                *dst = *src as c_char;
+            }
+            for (dst, src) in event.process.exe_path.iter_mut().zip(&exe_path) {
+                *dst = *src as c_char;
+            }
+
+            assert_clean_parse(Event::try_from(&event));
+        }
+    }
+
+    #[test]
+    fn event_parsing_errors() {
+        let mut unterminated = event_t::default();
+        unterminated.filename.fill(b'a' as c_char);
+
+        let tests = [
+            (
+                event_t {
+                    type_: file_activity_type_t(42),
+                    ..Default::default()
+                },
+                ParseError::UnknownEventType(42),
+                "Unknown event type",
+            ),
+            (
+                unterminated,
+                ParseError::FilenameTooLong,
+                "Unterminated filename",
+            ),
+        ];
+
+        for (event, expected, description) in tests {
+            let Err(err) = Event::try_from(&event) else {
+                panic!("Expected error for {description}");
+            };
+            assert_eq!(
+                err.downcast_ref::<ParseError>(),
+                Some(&expected),
+                "Failed for {description}"
+            );
+        }
+    }
+
+    #[test]
+    fn event_parsing_truncated() {
+        let event = event_t {
+            type_: file_activity_type_t::FILE_ACTIVITY_OPEN,
+            ..Default::default()
+        };
+        let data = crate::generate::as_bytes(&event);
+        for len in 0..data.len() {
+            assert_eq!(
+                event_from_bytes(&data[..len]).err(),
+                Some(ParseError::Truncated(len)),
+                "Failed for {len} bytes"
+            );
+        }
+        let err = raw::parse_raw(&data[..1]).unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<ParseError>(),
+            Some(&ParseError::Truncated(1))
+        );
+
+        let expected = Event::try_from(&event).unwrap();
+        assert_eq!(raw::parse_raw(data).unwrap(), expected);
+        assert!(matches!(event_from_bytes(data), Ok(Cow::Borrowed(_))));
+
+        // Unaligned buffers are copied, extra bytes are ignored
+        let mut unaligned = vec![0; data.len() + 2];
+        unaligned[1..=data.len()].copy_from_slice(data);
+        assert!(matches!(
+            event_from_bytes(&unaligned[1..]),
+            Ok(Cow::Owned(_))
+        ));
+        assert_eq!(raw::parse_raw(&unaligned[1..]).unwrap(), expected);
+    }
+
+    #[test]
+    fn event_parsing_lineage_left_out() {
+        let mut event = event_t {
+            type_: file_activity_type_t::FILE_ACTIVITY_OPEN,
+            ..Default::default()
+        };
+        event.process.pid = 4242;
+        event.process.lineage_len = 1;
+        event.process.lineage[0].exe_path[0] = b'/' as c_char;
+        let lineages = Lineages::default();
+        let host = HostContext::default();
+        let full = raw::parse_record(crate::generate::as_bytes(&event), &host, &lineages).unwrap();
+        assert_eq!(full.process.lineage().len(), 1);
+
+        // Later events of the process end before the lineage
+        event.process.lineage_len = 0;
+        event.process.lineage_cached = 1;
+        let data = &crate::generate::as_bytes(&event)[..NO_LINEAGE_SIZE];
+        let short = raw::parse_record(data, &host, &lineages).unwrap();
+        assert_eq!(short.process.lineage_source(), lineage::Source::Cached);
+        assert!(ptr::eq(
+            full.process.lineage().as_ptr(),
+            short.process.lineage().as_ptr()
+        ));
+        assert_eq!(
+            event_from_bytes(&data[..NO_LINEAGE_SIZE - 1]).err(),
+            Some(ParseError::Truncated(NO_LINEAGE_SIZE - 1))
+        );
+
+        // Only those of processes whose lineage was already sent
+        event.process.lineage_cached = 0;
+        let data = &crate::generate::as_bytes(&event)[..NO_LINEAGE_SIZE];
+        assert_eq!(
+            event_from_bytes(data).err(),
+            Some(ParseError::Truncated(NO_LINEAGE_SIZE))
+        );
+    }
+
+    #[test]
+    fn large_inodes() {
+        let inode = inode_key_t {
+            inode: (1 << 40) + 7,
+            dev: (1 << 32) + 0x803,
+        };
+        let parent_inode = inode_key_t {
+            inode: u64::MAX,
+            dev: (1 << 32) + 0x803,
+        };
+        let event = event_t {
+            type_: file_activity_type_t::FILE_ACTIVITY_OPEN,
+            inode,
+            parent_inode,
+            ..Default::default()
+        };
+        let parsed = raw::parse_raw(crate::generate::as_bytes(&event)).unwrap();
+        assert_eq!(parsed.get_inode(), &inode);
+        assert_eq!(parsed.get_parent_inode(), &parent_inode);
+
+        let value = serde_json::to_value(&parsed).unwrap();
+        let parsed: Event = serde_json::from_value(value).unwrap();
+        assert_eq!(parsed.get_inode(), &inode);
+        assert_eq!(parsed.get_parent_inode(), &parent_inode);
+    }
+
+    #[test]
+    fn filter_state() {
+        let mut event = Event::try_from(&event_t {
+            type_: file_activity_type_t::FILE_ACTIVITY_OPEN,
+            ..Default::default()
+        })
+        .unwrap();
+        let value = serde_json::to_value(&event).unwrap();
+        assert!(value.get("filter_state").is_none());
+
+        event.set_filter_state(FilterState::Initializing);
+        let value = serde_json::to_value(&event).unwrap();
+        assert_eq!(value["filter_state"], "initializing");
+    }
+
+    #[test]
+    fn tmpfile() {
+        let creation = |tmpfile| {
+            Event::try_from(&event_t {
+                type_: file_activity_type_t::FILE_ACTIVITY_CREATION,
+                tmpfile,
+                ..Default::default()
+            })
+            .unwrap()
+        };
+        let event = creation(tmpfile_t::NOT_TMPFILE);
+        assert_eq!(event.tmpfile(), None);
+        let value = serde_json::to_value(&event).unwrap();
+        assert!(value["file"]["Creation"].get("tmpfile").is_none());
+
+        for (tmpfile, expected) in [
+            (tmpfile_t::TMPFILE_UNNAMED, TmpFile::Unnamed),
+            (tmpfile_t::TMPFILE_LINKED, TmpFile::Linked),
+        ] {
+            let event = creation(tmpfile);
+            assert_eq!(event.tmpfile(), Some(expected));
+            let value = serde_json::to_value(&event).unwrap();
+            assert_eq!(value["file"]["Creation"]["tmpfile"], expected.as_str());
+            let parsed: Event = serde_json::from_value(value).unwrap();
+            assert_eq!(parsed.tmpfile(), Some(expected));
+        }
+    }
+
+    #[test]
+    fn rename_overwrite() {
+        for overwrote_existing in [false, true] {
+            let mut raw = event_t {
+                type_: file_activity_type_t::FILE_ACTIVITY_RENAME,
+                ..Default::default()
+            };
+            raw.__bindgen_anon_1.rename.overwrote_existing = overwrote_existing as c_char;
+            let event = Event::try_from(&raw).unwrap();
+            assert_eq!(event.overwrote_existing(), overwrote_existing);
+
+            let value = serde_json::to_value(&event).unwrap();
+            assert_eq!(
+                value["file"]["Rename"]["overwrote_existing"],
+                overwrote_existing
+            );
+            let parsed: Event = serde_json::from_value(value).unwrap();
+            assert_eq!(parsed, event);
+        }
+
+        // Events serialized before the field existed did not overwrite
+        let mut value = serde_json::to_value(
+            Event::try_from(&event_t {
+                type_: file_activity_type_t::FILE_ACTIVITY_RENAME,
+                ..Default::default()
+            })
+            .unwrap(),
+        )
+        .unwrap();
+        value["file"]["Rename"]
+            .as_object_mut()
+            .unwrap()
+            .remove("overwrote_existing");
+        let parsed: Event = serde_json::from_value(value).unwrap();
+        assert!(!parsed.overwrote_existing());
+    }
+
+    #[test]
+    fn attributes() {
+        let mut raw = event_t {
+            type_: file_activity_type_t::FILE_ACTIVITY_ATTRIBUTES,
+            ..Default::default()
+        };
+        raw.__bindgen_anon_1.attributes.old = fact_ebpf::FACT_FS_IMMUTABLE_FL;
+        raw.__bindgen_anon_1.attributes.new = fact_ebpf::FACT_FS_APPEND_FL;
+        let event = Event::try_from(&raw).unwrap();
+        assert!(event.is_attributes());
+        assert_eq!(event.event_type(), "attributes");
+        let FileData::Attributes(data) = &event.file else {
+            panic!("not an attributes event: {event:?}");
+        };
+        assert!(data.old_flags().contains("immutable"));
+        assert!(data.new_flags().contains("append"));
+
+        let value = serde_json::to_value(&event).unwrap();
+        assert_eq!(
+            value["file"]["Attributes"]["old_flags"],
+            serde_json::json!(["immutable"])
+        );
+        assert_eq!(
+            value["file"]["Attributes"]["new_flags"],
+            serde_json::json!(["append"])
+        );
+        let parsed: Event = serde_json::from_value(value).unwrap();
+        assert_eq!(parsed, event);
+    }
+
+    #[test]
+    fn owner_names() {
+        let mut event = event_t {
+            type_: file_activity_type_t::FILE_ACTIVITY_CHOWN,
+            ..Default::default()
+        };
+        event.__bindgen_anon_1.chown.new.uid = 0;
+        event.__bindgen_anon_1.chown.new.gid = 0;
+        event.__bindgen_anon_1.chown.old.uid = 1000;
+        let event = Event::try_from(&event).unwrap();
+        let FileData::Chown(chown) = &event.file else {
+            panic!("not a chown event: {event:?}");
+        };
+        let (owner, group) = (host_info::get_username(0), host_info::get_groupname(0));
+        assert_eq!(chown.file_owner_name(), owner);
+        assert_eq!(chown.file_group_name(), group);
+
+        let value = serde_json::to_value(&event).unwrap();
+        assert_eq!(value["file"]["Chown"]["file_owner_name"], owner);
+        assert_eq!(value["file"]["Chown"]["file_group_name"], group);
+        let parsed: Event = serde_json::from_value(value).unwrap();
+        assert_eq!(parsed, event);
+
+        let Some(fact_api::file_activity::File::Ownership(ownership)) =
+            fact_api::FileActivity::try_from(event).unwrap().file
+        else {
+            panic!("not an ownership change");
+        };
+        assert_eq!((ownership.uid, ownership.gid), (0, 0));
+        assert_eq!(ownership.username, owner);
+        assert_eq!(ownership.group, group);
+    }
+
+    #[test]
+    fn boot_timestamps() {
+        let event = Event::try_from(&event_t {
+            type_: file_activity_type_t::FILE_ACTIVITY_OPEN,
+            timestamp: 1_500_000_000,
+            ..Default::default()
+        })
+        .unwrap();
+        assert_eq!(event.get_timestamp_boot_ns(), Some(1_500_000_000));
+        assert_eq!(event.get_boot_id(), host_info::get_boot_id());
+        assert_eq!(
+            event.get_timestamp(),
+            host_info::get_boot_time() + 1_500_000_000
+        );
+
+        let value = serde_json::to_value(&event).unwrap();
+        assert_eq!(value["timestamp_boot_ns"], 1_500_000_000);
+        assert_eq!(value["boot_id"], host_info::get_boot_id());
+        let parsed: Event = serde_json::from_value(value).unwrap();
+        assert_eq!(parsed.get_timestamp_boot_ns(), Some(1_500_000_000));
+        assert_eq!(parsed.get_boot_id(), host_info::get_boot_id());
+
+        // Events not read from the kernel have no boot time
+        let summary = Event::summary(0, vec![]);
+        let value = serde_json::to_value(&summary).unwrap();
+        assert!(value.get("timestamp_boot_ns").is_none());
+        assert!(value.get("boot_id").is_none());
+    }
+
+    #[test]
+    fn boot_ordering() {
+        let mut events = (1..=5u64)
+            .map(|i| {
+                Event::try_from(&event_t {
+                    type_: file_activity_type_t::FILE_ACTIVITY_OPEN,
+                    timestamp: i * 1000,
+                    filename: string_to_c_char_array::<{ PATH_MAX as usize }>(&format!("/etc/{i}")),
+                    ..Default::default()
+                })
+                .unwrap()
+            })
+            .collect::<Vec<_>>();
+
+        // The wall clock is set back an hour after the second event
+        for event in &mut events[2..] {
+            event.set_timestamp(event.get_timestamp() - 3_600_000_000_000);
+        }
+        events.reverse();
+
+        events.sort_by_key(|e| e.get_timestamp());
+        assert_ne!(events[0].get_filename(), Path::new("/etc/1"));
+
+        events.sort_by_key(|e| (e.get_boot_id().to_owned(), e.get_timestamp_boot_ns()));
+        let files = events
+            .iter()
+            .map(|e| e.get_filename().clone())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            files,
+            (1..=5)
+                .map(|i| PathBuf::from(format!("/etc/{i}")))
+                .collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn instance_id() {
+        let mut event = Event::try_from(&event_t {
+            type_: file_activity_type_t::FILE_ACTIVITY_OPEN,
+            ..Default::default()
+        })
+        .unwrap();
+        let value = serde_json::to_value(&event).unwrap();
+        assert!(value.get("instance_id").is_none());
+
+        event.set_instance_id(4242);
+        let value = serde_json::to_value(&event).unwrap();
+        assert_eq!(value["instance_id"], 4242);
+    }
+
     #[test]
     fn slice_to_string_valid_utf8() {
         let tests = [
@@ -1098,7 +2606,7 @@ mod tests {
         for (input, expected, description) in tests {
             let arr = string_to_c_char_array::<{ PATH_MAX as usize }>(input);
             assert_eq!(
-                sanitize_d_path(&arr),
+                sanitize_d_path(&arr).unwrap(),
                 PathBuf::from(expected),
                 "Failed for {}",
                 description
@@ -1130,7 +2638,7 @@ mod tests {
         for (input, expected, description) in tests {
             let arr = string_to_c_char_array::<{ PATH_MAX as usize }>(input);
             assert_eq!(
-                sanitize_d_path(&arr),
+                sanitize_d_path(&arr).unwrap(),
                 PathBuf::from(expected),
                 "Failed for {}",
                 description
@@ -1167,7 +2675,7 @@ mod tests {
 
         for (bytes, pattern, description) in tests {
             let arr = bytes_to_c_char_array::<{ PATH_MAX as usize }>(bytes);
-            let result = sanitize_d_path(&arr);
+            let result = sanitize_d_path(&arr).unwrap();
             let result_str = result.to_string_lossy();
 
             let re = Regex::new(pattern).expect("Invalid regex pattern");
@@ -1185,7 +2693,7 @@ mod tests {
     fn sanitize_d_path_invalid_utf8_with_deleted_suffix() {
         let invalid_with_deleted =
             bytes_to_c_char_array::<{ PATH_MAX as usize }>(b"/tmp/\xFF\xFE (deleted)");
-        let result = sanitize_d_path(&invalid_with_deleted);
+        let result = sanitize_d_path(&invalid_with_deleted).unwrap();
         let result_str = result.to_string_lossy();
 
         assert!(result_str.contains("/tmp/"));
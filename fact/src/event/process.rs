@@ -1,74 +1,123 @@
 #[cfg(feature = "otel")]
 use std::collections::HashMap;
-use std::{ffi::CStr, path::PathBuf};
+use std::{
+    path::{Path, PathBuf},
+    sync::{Arc, LazyLock},
+};
 
-use fact_ebpf::{lineage_t, process_t};
+use fact_ebpf::{LINEAGE_MAX, process_t};
+use log::warn;
 #[cfg(feature = "otel")]
 use opentelemetry::logs::AnyValue;
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 
-use crate::host_info;
+use crate::{host_info, metrics::ContainerIdMetrics, trace};
 
-use super::{sanitize_d_path, slice_to_string};
+use super::{
+    ARGS_MAX, ParseError, c_char_to_bytes,
+    capabilities::Capabilities,
+    checkpoint_restore,
+    lineage::{self, Lineage, Lineages, ProcessKey},
+    raw::HostContext,
+    sanitize_d_path, slice_to_string,
+};
 
-#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
-pub struct Lineage {
-    uid: u32,
-    exe_path: PathBuf,
+/// Value of the login UID and session ID of processes that are not
+/// part of a login.
+const AUDIT_UNSET: u32 = u32::MAX;
+
+fn audit_id(id: u32) -> Option<u32> {
+    (id != AUDIT_UNSET).then_some(id)
 }
 
-impl TryFrom<&lineage_t> for Lineage {
-    type Error = anyhow::Error;
+/// Read a login UID or session ID, events recorded before they could
+/// be unset hold the raw value.
+fn deserialize_audit_id<'de, D>(deserializer: D) -> Result<Option<u32>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    Ok(Option::<u32>::deserialize(deserializer)?.and_then(audit_id))
+}
 
-    fn try_from(value: &lineage_t) -> Result<Self, Self::Error> {
-        let lineage_t { uid, exe_path } = value;
-        let exe_path = sanitize_d_path(exe_path);
+/// Processes read from the kernel by how their container ID was
+/// resolved, exported as `container_id_resolution`.
+pub(crate) static CONTAINER_ID_RESOLUTION: LazyLock<ContainerIdMetrics> =
+    LazyLock::new(ContainerIdMetrics::default);
 
-        Ok(Lineage {
-            uid: *uid,
-            exe_path,
-        })
-    }
+/// How the container ID of a process was resolved.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum ContainerIdResolution {
+    /// In the host mount namespace, no container ID is expected.
+    Host,
+    Resolved,
+    /// Outside of the host mount namespace, but no container ID was
+    /// found in its memory cgroup.
+    Unresolved,
 }
 
-impl From<Lineage> for fact_api::process_signal::LineageInfo {
-    fn from(value: Lineage) -> Self {
-        let Lineage { uid, exe_path } = value;
-        Self {
-            parent_uid: uid,
-            parent_exec_file_path: exe_path.to_string_lossy().to_string(),
+impl ContainerIdResolution {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ContainerIdResolution::Host => "host",
+            ContainerIdResolution::Resolved => "resolved",
+            ContainerIdResolution::Unresolved => "unresolved",
         }
     }
 }
 
-#[cfg(feature = "otel")]
-impl From<Lineage> for opentelemetry::logs::AnyValue {
-    fn from(value: Lineage) -> Self {
-        AnyValue::Map(Box::new(HashMap::from([
-            ("uid".into(), value.uid.into()),
-            (
-                "exec_path".into(),
-                value.exe_path.to_string_lossy().to_string().into(),
-            ),
-        ])))
-    }
-}
-
 #[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct Process {
+    /// Identifies the process across events, see `Process::make_id`.
+    #[serde(default)]
+    id: Uuid,
     comm: String,
     args: Vec<String>,
+    /// The arguments were cut short by the kernel.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    args_truncated: bool,
     exe_path: PathBuf,
     container_id: Option<String>,
     uid: u32,
-    #[serde(skip_deserializing)]
-    username: &'static str,
+    #[serde(default, deserialize_with = "super::deserialize_interned")]
+    username: super::Interned,
     gid: u32,
-    login_uid: u32,
+    /// UID the user logged in with, kept across `su` and `sudo`. Unset
+    /// for processes not started from a login, like services.
+    #[serde(deserialize_with = "deserialize_audit_id")]
+    login_uid: Option<u32>,
+    /// Name of the user with `login_uid`.
+    #[serde(
+        default,
+        deserialize_with = "super::deserialize_interned",
+        skip_serializing_if = "str::is_empty"
+    )]
+    login_username: super::Interned,
+    /// Audit session of the login the process was started from.
+    #[serde(
+        default,
+        deserialize_with = "deserialize_audit_id",
+        skip_serializing_if = "Option::is_none"
+    )]
+    session_id: Option<u32>,
+    /// Effective capabilities of the process.
+    #[serde(default, skip_serializing_if = "Capabilities::is_empty")]
+    capabilities: Capabilities,
     pid: u32,
     in_root_mount_ns: bool,
-    lineage: Vec<Lineage>,
+    /// Shared with the other events of processes with the same
+    /// ancestors.
+    lineage: Arc<[Lineage]>,
+    /// Whether the kernel sent the lineage or left it out, only known
+    /// for events read from it.
+    #[serde(skip)]
+    lineage_source: lineage::Source,
+    /// Start of the process in nanoseconds since boot, identifies it
+    /// along with the PID.
+    #[serde(skip)]
+    start_time: u64,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    checkpoint_restore: bool,
 }
 
 impl Process {
@@ -85,28 +134,174 @@ impl Process {
         let uid = unsafe { libc::getuid() };
         let gid = unsafe { libc::getgid() };
         let pid = std::process::id();
-        let login_uid = std::fs::read_to_string("/proc/self/loginuid")
-            .expect("Failed to read loginuid")
-            .parse()
-            .expect("Failed to parse login_uid");
+        let read_id = |name| {
+            let id = std::fs::read_to_string(format!("/proc/self/{name}"))
+                .unwrap_or_else(|e| panic!("Failed to read {name}: {e}"));
+            audit_id(
+                id.parse()
+                    .unwrap_or_else(|e| panic!("Failed to parse {name}: {e}")),
+            )
+        };
+        let login_uid = read_id("loginuid");
+        let session_id = read_id("sessionid");
+        let status = std::fs::read_to_string("/proc/self/status").expect("Failed to read status");
+        let capabilities = status
+            .lines()
+            .find_map(|line| line.strip_prefix("CapEff:"))
+            .map(|caps| u64::from_str_radix(caps.trim(), 16).expect("Failed to parse CapEff"))
+            .expect("Missing CapEff")
+            .into();
 
         let in_root_mount_ns = get_host_mount_ns() == get_mount_ns(&pid.to_string(), false);
 
         Self {
+            id: Process::make_id(pid, 0, host_info::get_boot_id()),
             comm: "".to_string(),
             args,
+            args_truncated: false,
             exe_path,
             container_id,
             uid,
             username: "",
             gid,
             login_uid,
+            login_username: "",
+            session_id,
+            capabilities,
             pid,
             in_root_mount_ns,
-            lineage: vec![],
+            lineage: Arc::from([]),
+            lineage_source: lineage::Source::Kernel,
+            start_time: 0,
+            checkpoint_restore: false,
         }
     }
 
+    /// ID of the process for a given PID and start time on the boot
+    /// `boot_id`.
+    ///
+    /// The ID is the same for all events of a process, the boot ID of
+    /// the host is part of it so it is not reused after a reboot. If
+    /// the boot ID of this host can't be read, IDs are only stable for
+    /// as long as fact runs. Events of other hosts with an unknown
+    /// boot ID get IDs that are only unique within that boot.
+    fn make_id(pid: u32, start_time: u64, boot_id: &str) -> Uuid {
+        static NAMESPACE: LazyLock<Uuid> =
+            LazyLock::new(|| match Uuid::parse_str(host_info::get_boot_id()) {
+                Ok(boot_id) => boot_id,
+                Err(e) => {
+                    warn!("Invalid boot ID, process IDs will change with restarts: {e}");
+                    Uuid::new_v4()
+                }
+            });
+
+        let namespace = match boot_id == host_info::get_boot_id() {
+            true => *NAMESPACE,
+            false => Uuid::parse_str(boot_id).unwrap_or_default(),
+        };
+        let mut name = [0; 12];
+        name[..4].copy_from_slice(&pid.to_le_bytes());
+        name[4..].copy_from_slice(&start_time.to_le_bytes());
+        Uuid::new_v5(&namespace, &name)
+    }
+
+    pub fn id(&self) -> Uuid {
+        self.id
+    }
+
+    pub fn is_checkpoint_restore(&self) -> bool {
+        self.checkpoint_restore
+    }
+
+    pub fn pid(&self) -> u32 {
+        self.pid
+    }
+
+    pub fn uid(&self) -> u32 {
+        self.uid
+    }
+
+    pub fn exe_path(&self) -> &Path {
+        &self.exe_path
+    }
+
+    pub fn container_id(&self) -> Option<&str> {
+        self.container_id.as_deref()
+    }
+
+    pub fn comm(&self) -> &str {
+        &self.comm
+    }
+
+    pub fn args(&self) -> &[String] {
+        &self.args
+    }
+
+    pub(crate) fn args_mut(&mut self) -> &mut [String] {
+        &mut self.args
+    }
+
+    /// Whether the arguments are incomplete, the last one may be cut
+    /// short too.
+    pub fn args_truncated(&self) -> bool {
+        self.args_truncated
+    }
+
+    pub fn username(&self) -> &str {
+        self.username
+    }
+
+    pub fn gid(&self) -> u32 {
+        self.gid
+    }
+
+    pub fn login_uid(&self) -> Option<u32> {
+        self.login_uid
+    }
+
+    pub fn login_username(&self) -> &str {
+        self.login_username
+    }
+
+    pub fn session_id(&self) -> Option<u32> {
+        self.session_id
+    }
+
+    pub fn capabilities(&self) -> Capabilities {
+        self.capabilities
+    }
+
+    pub fn in_root_mount_ns(&self) -> bool {
+        self.in_root_mount_ns
+    }
+
+    /// Tell processes on the host, which have no container ID, from
+    /// those whose container ID is missing.
+    pub fn container_id_resolution(&self) -> ContainerIdResolution {
+        if self.in_root_mount_ns {
+            ContainerIdResolution::Host
+        } else if self.container_id.is_some() {
+            ContainerIdResolution::Resolved
+        } else {
+            ContainerIdResolution::Unresolved
+        }
+    }
+
+    /// Ancestors of the process, starting with its parent.
+    pub fn lineage(&self) -> &[Lineage] {
+        &self.lineage
+    }
+
+    /// Where the lineage came from, see `lineage::Source`.
+    pub fn lineage_source(&self) -> lineage::Source {
+        self.lineage_source
+    }
+
+    /// Identifies the process in the lineage caches.
+    pub fn key(&self) -> ProcessKey {
+        ProcessKey::new(self.pid, self.start_time)
+    }
+
     fn extract_container_id(cgroup: &str) -> Option<String> {
         let cgroup = if let Some(i) = cgroup.rfind(".scope") {
             cgroup.split_at(i).0
@@ -137,11 +332,15 @@ impl PartialEq for Process {
     fn eq(&self, other: &Self) -> bool {
         self.uid == other.uid
             && self.login_uid == other.login_uid
+            && self.session_id == other.session_id
+            && self.capabilities == other.capabilities
             && self.gid == other.gid
             && self.exe_path == other.exe_path
             && self.args == other.args
+            && self.args_truncated == other.args_truncated
             && self.container_id == other.container_id
             && self.in_root_mount_ns == other.in_root_mount_ns
+            && self.checkpoint_restore == other.checkpoint_restore
     }
 }
 
@@ -149,63 +348,121 @@ impl TryFrom<process_t> for Process {
     type Error = anyhow::Error;
 
     fn try_from(value: process_t) -> Result<Self, Self::Error> {
+        Process::from_kernel(value, &HostContext::local(), &Lineages::default())
+    }
+}
+
+impl Process {
+    /// Parse a process sent by the kernel of `host`, filling in the
+    /// lineage left out of it from the earlier events in `lineages`.
+    pub(super) fn from_kernel(
+        value: process_t,
+        host: &HostContext,
+        lineages: &Lineages,
+    ) -> anyhow::Result<Self> {
         let comm = slice_to_string(value.comm.as_slice())?;
-        let exe_path = sanitize_d_path(value.exe_path.as_slice());
-        let memory_cgroup = unsafe { CStr::from_ptr(value.memory_cgroup.as_ptr()) }.to_str()?;
-        let container_id = Process::extract_container_id(memory_cgroup);
+        let exe_path = sanitize_d_path(value.exe_path.as_slice())?;
+        let memory_cgroup = slice_to_string(value.memory_cgroup.as_slice())?;
+        let container_id = {
+            let _span = trace::stage!("container_id");
+            Process::extract_container_id(&memory_cgroup)
+        };
         let in_root_mount_ns = value.in_root_mount_ns != 0;
 
-        let lineage = value.lineage[..value.lineage_len as usize]
-            .iter()
-            .map(Lineage::try_from)
-            .collect::<Result<Vec<_>, _>>()?;
+        if value.lineage_len > LINEAGE_MAX {
+            return Err(ParseError::LineageTooLong(value.lineage_len).into());
+        }
+        let key = ProcessKey::new(value.pid, value.start_time);
+        let (lineage, lineage_source) = if value.lineage_cached != 0 {
+            match lineages.recall(key) {
+                Some(lineage) => (lineage, lineage::Source::Cached),
+                None => (Arc::from([]), lineage::Source::Missing),
+            }
+        } else {
+            let lineage = lineages.parse(&value.lineage[..value.lineage_len as usize])?;
+            lineages.remember(key, lineage.clone());
+            (lineage, lineage::Source::Kernel)
+        };
 
-        let mut converted_args = Vec::new();
         let args_len = value.args_len as usize;
-        let mut offset = 0;
-        while offset < args_len {
-            let arg = unsafe { CStr::from_ptr(value.args.as_ptr().add(offset)) }
-                .to_str()?
-                .to_owned();
+        if args_len > ARGS_MAX {
+            return Err(ParseError::ArgsTooLong(value.args_len).into());
+        }
+        let args_truncated = value.args_truncated != 0;
+        let mut args = c_char_to_bytes(&value.args[..args_len]);
+        if args_truncated
+            && let Err(e) = std::str::from_utf8(args)
+            && e.error_len().is_none()
+        {
+            // The last argument was cut in the middle of a character
+            args = &args[..e.valid_up_to()];
+        }
+        let mut converted_args = Vec::new();
+        for arg in args.split(|b| *b == 0) {
             if arg.is_empty() {
                 break;
             }
-            offset += arg.len() + 1;
-            converted_args.push(arg);
+            converted_args.push(std::str::from_utf8(arg)?.to_owned());
         }
 
-        let username = host_info::get_username(value.uid);
+        let checkpoint_restore = checkpoint_restore::is_checkpoint_restore(
+            &comm,
+            &exe_path,
+            lineage.iter().map(Lineage::exe_path),
+        );
+
+        let username = host.username(value.uid);
+        let login_uid = audit_id(value.login_uid);
+        let login_username = login_uid.map(|uid| host.username(uid)).unwrap_or_default();
 
-        Ok(Process {
+        let process = Process {
+            id: Process::make_id(value.pid, value.start_time, host.boot_id),
             comm,
             args: converted_args,
+            args_truncated,
             exe_path,
             container_id,
             uid: value.uid,
             username,
             gid: value.gid,
-            login_uid: value.login_uid,
+            login_uid,
+            login_username,
+            session_id: audit_id(value.session_id),
+            capabilities: value.cap_effective.into(),
             pid: value.pid,
             in_root_mount_ns,
             lineage,
-        })
+            lineage_source,
+            start_time: value.start_time,
+            checkpoint_restore,
+        };
+        CONTAINER_ID_RESOLUTION.inc(process.container_id_resolution());
+        Ok(process)
     }
 }
 
 impl From<Process> for fact_api::ProcessSignal {
     fn from(value: Process) -> Self {
         let Process {
+            id,
             comm,
             args,
+            args_truncated: _,
             exe_path,
             container_id,
             uid,
             username,
             gid,
             login_uid,
+            login_username: _,
+            session_id: _,
+            capabilities: _,
             pid,
             in_root_mount_ns,
             lineage,
+            lineage_source: _,
+            start_time: _,
+            checkpoint_restore: _,
         } = value;
 
         let container_id = container_id.unwrap_or("".to_string());
@@ -217,7 +474,7 @@ impl From<Process> for fact_api::ProcessSignal {
         };
 
         Self {
-            id: Uuid::new_v4().to_string(),
+            id: id.to_string(),
             container_id,
             creation_time: None,
             name: comm,
@@ -228,10 +485,11 @@ impl From<Process> for fact_api::ProcessSignal {
             gid,
             scraped: false,
             lineage_info: lineage
-                .into_iter()
+                .iter()
                 .map(fact_api::process_signal::LineageInfo::from)
                 .collect(),
-            login_uid,
+            // The message can't tell an unset login UID from root
+            login_uid: login_uid.unwrap_or_default(),
             username: username.to_owned(),
             in_root_mount_ns,
         }
@@ -247,13 +505,10 @@ impl From<Process> for opentelemetry::logs::AnyValue {
             .map(AnyValue::from)
             .collect::<Vec<_>>();
 
-        let lineage = value
-            .lineage
-            .into_iter()
-            .map(AnyValue::from)
-            .collect::<Vec<_>>();
+        let lineage = value.lineage.iter().map(AnyValue::from).collect::<Vec<_>>();
 
         let mut map = HashMap::from([
+            ("id".into(), value.id.to_string().into()),
             ("comm".into(), value.comm.into()),
             ("args".into(), AnyValue::ListAny(Box::new(args))),
             (
@@ -263,7 +518,6 @@ impl From<Process> for opentelemetry::logs::AnyValue {
             ("pid".into(), value.pid.into()),
             ("uid".into(), value.uid.into()),
             ("gid".into(), value.gid.into()),
-            ("login_uid".into(), value.login_uid.into()),
             ("username".into(), value.username.into()),
             ("in_root_mount_ns".into(), value.in_root_mount_ns.into()),
             ("lineage".into(), AnyValue::ListAny(Box::new(lineage))),
@@ -273,6 +527,35 @@ impl From<Process> for opentelemetry::logs::AnyValue {
             map.insert("container_id".into(), container_id.into());
         }
 
+        if let Some(login_uid) = value.login_uid {
+            map.insert("login_uid".into(), login_uid.into());
+            map.insert("login_username".into(), value.login_username.into());
+        }
+
+        if let Some(session_id) = value.session_id {
+            map.insert("session_id".into(), session_id.into());
+        }
+
+        if !value.capabilities.is_empty() {
+            let capabilities = value
+                .capabilities
+                .names()
+                .map(|name| AnyValue::from(name.into_owned()))
+                .collect();
+            map.insert(
+                "capabilities".into(),
+                AnyValue::ListAny(Box::new(capabilities)),
+            );
+        }
+
+        if value.args_truncated {
+            map.insert("args_truncated".into(), true.into());
+        }
+
+        if value.checkpoint_restore {
+            map.insert("checkpoint_restore".into(), true.into());
+        }
+
         AnyValue::Map(Box::new(map))
     }
 }
@@ -281,7 +564,18 @@ impl From<Process> for opentelemetry::logs::AnyValue {
 mod tests {
     use super::*;
     use crate::event::test_utils::*;
-    use fact_ebpf::PATH_MAX;
+    use fact_ebpf::{PATH_MAX, lineage_t};
+    use std::os::raw::c_char;
+
+    /// A process not started from a login, the same as
+    /// `Process::default()`.
+    fn no_login() -> process_t {
+        process_t {
+            login_uid: u32::MAX,
+            session_id: u32::MAX,
+            ..Default::default()
+        }
+    }
 
     #[test]
     fn extract_container_id() {
@@ -320,6 +614,125 @@ mod tests {
         }
     }
 
+    #[test]
+    fn container_id_resolution() {
+        let container = "/kubepods/burstable/pod7cd3dba6-e475-11e9-8f99-42010a8a00d2/2bc55a8cae1704a733ba5d785d146bbed9610483380507cbf00c96b32bb637e1";
+        let tests = [
+            (1, "/user.slice", ContainerIdResolution::Host),
+            // Containers sharing the mount namespace of the host
+            (1, container, ContainerIdResolution::Host),
+            (0, container, ContainerIdResolution::Resolved),
+            (
+                0,
+                "/system.slice/crio.service",
+                ContainerIdResolution::Unresolved,
+            ),
+            (0, "", ContainerIdResolution::Unresolved),
+        ];
+
+        for (in_root_mount_ns, cgroup, expected) in tests {
+            let before = CONTAINER_ID_RESOLUTION.get(expected);
+            let proc = process_t {
+                in_root_mount_ns,
+                memory_cgroup: string_to_c_char_array::<{ PATH_MAX as usize }>(cgroup),
+                ..no_login()
+            };
+            let result = Process::try_from(proc).expect("Failed to parse process");
+            assert_eq!(result.container_id_resolution(), expected, "{cgroup}");
+            // Other tests parse processes too
+            assert!(CONTAINER_ID_RESOLUTION.get(expected) > before, "{cgroup}");
+        }
+    }
+
+    #[test]
+    fn login_session() {
+        let tests = [
+            (u32::MAX, u32::MAX, None, None, "Unset"),
+            (0, 3, Some(0), Some(3), "Root login"),
+            (1000, 42, Some(1000), Some(42), "User login"),
+        ];
+
+        for (login_uid, session_id, expected_uid, expected_session, description) in tests {
+            let proc = process_t {
+                uid: 0,
+                login_uid,
+                session_id,
+                ..no_login()
+            };
+            let result = Process::try_from(proc).expect("Failed to parse process");
+            assert_eq!(result.login_uid(), expected_uid, "Failed for {description}");
+            assert_eq!(
+                result.session_id(),
+                expected_session,
+                "Failed for {description}"
+            );
+            // The login user is resolved apart from the effective one
+            match expected_uid {
+                Some(uid) => assert_eq!(
+                    result.login_username(),
+                    host_info::get_username(uid),
+                    "Failed for {description}"
+                ),
+                None => assert_eq!(result.login_username(), "", "Failed for {description}"),
+            }
+
+            let value = serde_json::to_value(&result).unwrap();
+            assert_eq!(
+                value["login_uid"],
+                serde_json::json!(expected_uid),
+                "Failed for {description}"
+            );
+            assert_eq!(
+                value.get("session_id").and_then(|v| v.as_u64()),
+                expected_session.map(u64::from),
+                "Failed for {description}"
+            );
+            let parsed: Process = serde_json::from_value(value).unwrap();
+            assert_eq!(parsed, result, "Failed for {description}");
+
+            let signal = fact_api::ProcessSignal::from(result);
+            assert_eq!(
+                signal.login_uid,
+                expected_uid.unwrap_or(0),
+                "Failed for {description}"
+            );
+        }
+    }
+
+    #[test]
+    fn capabilities() {
+        let proc = process_t {
+            cap_effective: (1 << 1) | (1 << 21),
+            ..no_login()
+        };
+        let result = Process::try_from(proc).expect("Failed to parse process");
+        assert!(result.capabilities().contains("CAP_DAC_OVERRIDE"));
+        assert!(result.capabilities().contains("CAP_SYS_ADMIN"));
+        assert!(!result.capabilities().contains("CAP_CHOWN"));
+
+        let value = serde_json::to_value(&result).unwrap();
+        assert_eq!(
+            value["capabilities"],
+            serde_json::json!(["CAP_DAC_OVERRIDE", "CAP_SYS_ADMIN"])
+        );
+        let parsed: Process = serde_json::from_value(value).unwrap();
+        assert_eq!(parsed, result);
+
+        // Processes without capabilities leave the field out
+        let value = serde_json::to_value(Process::default()).unwrap();
+        assert!(value.get("capabilities").is_none());
+    }
+
+    #[test]
+    fn login_uid_recorded_raw() {
+        // Events recorded before the login UID could be unset
+        let mut value = serde_json::to_value(Process::default()).unwrap();
+        value["login_uid"] = serde_json::json!(u32::MAX);
+        let parsed: Process = serde_json::from_value(value).unwrap();
+        assert_eq!(parsed.login_uid(), None);
+        assert_eq!(parsed.session_id(), None);
+    }
+
     #[test]
     fn process_conversion_valid_utf8_comm() {
         let tests = [
@@ -332,7 +745,7 @@ mod tests {
         for (comm, description) in tests {
             let proc = process_t {
                 comm: string_to_c_char_array::<16>(comm),
-                ..Default::default()
+                ..no_login()
             };
             let result = Process::try_from(proc).expect("Failed to parse process");
             let expected = Process {
@@ -353,7 +766,7 @@ mod tests {
         for (bytes, description) in tests {
             let proc = process_t {
                 comm: bytes_to_c_char_array::<16>(bytes),
-                ..Default::default()
+                ..no_login()
             };
             let result = Process::try_from(proc);
             assert!(result.is_err(), "Should fail for {}", description);
@@ -373,7 +786,7 @@ mod tests {
         for (path, description) in tests {
             let proc = process_t {
                 exe_path: string_to_c_char_array::<{ PATH_MAX as usize }>(path),
-                ..Default::default()
+                ..no_login()
             };
             let result = Process::try_from(proc).expect("Failed to parse process");
             let expected = Process {
@@ -390,7 +803,7 @@ mod tests {
 
         let proc = process_t {
             exe_path: bytes_to_c_char_array::<{ PATH_MAX as usize }>(b"/usr/bin/\xFF\xFE"),
-            ..Default::default()
+            ..no_login()
         };
         let result = Process::try_from(proc).expect("Failed to parse process");
         let exe_path_str = result.exe_path.to_string_lossy();
@@ -429,7 +842,7 @@ mod tests {
             let proc = process_t {
                 args: string_to_c_char_array::<{ PATH_MAX as usize }>(args_str),
                 args_len: args_str.len() as u32,
-                ..Default::default()
+                ..no_login()
             };
             let result = Process::try_from(proc).expect("Failed to parse process");
             let expected_process = Process {
@@ -451,7 +864,7 @@ mod tests {
             let proc = process_t {
                 args: bytes_to_c_char_array::<{ PATH_MAX as usize }>(bytes),
                 args_len: *args_len,
-                ..Default::default()
+                ..no_login()
             };
             let result = Process::try_from(proc);
             assert!(result.is_err(), "Should fail for {}", description);
@@ -472,7 +885,7 @@ mod tests {
         for (cgroup, expected_id, description) in tests {
             let proc = process_t {
                 memory_cgroup: string_to_c_char_array::<{ PATH_MAX as usize }>(cgroup),
-                ..Default::default()
+                ..no_login()
             };
             let result = Process::try_from(proc).expect("Failed to parse process");
             let expected_process = Process {
@@ -487,7 +900,7 @@ mod tests {
     fn process_conversion_invalid_utf8_memory_cgroup() {
         let proc = process_t {
             memory_cgroup: bytes_to_c_char_array::<{ PATH_MAX as usize }>(b"/docker/\xFF\xFE"),
-            ..Default::default()
+            ..no_login()
         };
         let result = Process::try_from(proc);
         assert!(result.is_err());
@@ -511,14 +924,11 @@ mod tests {
                     Default::default(),
                 ],
                 lineage_len: 1,
-                ..Default::default()
+                ..no_login()
             };
             let result = Process::try_from(proc).expect("Failed to parse process");
             let expected_process = Process {
-                lineage: vec![Lineage {
-                    uid: 1000,
-                    exe_path: PathBuf::from(path),
-                }],
+                lineage: Arc::from([Lineage::new(1000, path)]),
                 ..Default::default()
             };
             assert_eq!(result, expected_process, "Failed for {}", description);
@@ -538,12 +948,12 @@ mod tests {
                 Default::default(),
             ],
             lineage_len: 1,
-            ..Default::default()
+            ..no_login()
         };
         let result = Process::try_from(proc);
         assert!(result.is_ok());
         let lineage = result.unwrap().lineage;
-        let lineage_path_str = lineage[0].exe_path.to_string_lossy();
+        let lineage_path_str = lineage[0].exe_path().to_string_lossy();
 
         let re = Regex::new(r"^/bin/\u{FFFD}+$").expect("Invalid regex pattern");
         assert!(
@@ -552,4 +962,233 @@ mod tests {
             lineage_path_str
         );
     }
+
+    #[test]
+    fn lineage_cached() {
+        let bash = lineage_t {
+            uid: 1000,
+            exe_path: string_to_c_char_array::<{ PATH_MAX as usize }>("/bin/bash"),
+        };
+        let full = process_t {
+            pid: 31337,
+            start_time: 42,
+            lineage: [bash, Default::default()],
+            lineage_len: 1,
+            ..no_login()
+        };
+        let left_out = process_t {
+            lineage: Default::default(),
+            lineage_len: 0,
+            lineage_cached: 1,
+            ..full
+        };
+
+        // The lineage is filled back in from the first event
+        let host = HostContext::default();
+        let lineages = Lineages::default();
+        let first = Process::from_kernel(full, &host, &lineages).unwrap();
+        assert_eq!(first.lineage_source(), lineage::Source::Kernel);
+        let second = Process::from_kernel(left_out, &host, &lineages).unwrap();
+        assert_eq!(second.lineage_source(), lineage::Source::Cached);
+        assert!(Arc::ptr_eq(&first.lineage, &second.lineage));
+        assert_eq!(second.key(), ProcessKey::new(31337, 42));
+
+        // A process whose first event was never seen has no lineage
+        let unknown = process_t {
+            start_time: 43,
+            ..left_out
+        };
+        let unknown = Process::from_kernel(unknown, &host, &lineages).unwrap();
+        assert_eq!(unknown.lineage_source(), lineage::Source::Missing);
+        assert!(unknown.lineage().is_empty());
+
+        // Nor does one whose first event was read by another pipeline
+        let other = Process::from_kernel(left_out, &host, &Lineages::default()).unwrap();
+        assert_eq!(other.lineage_source(), lineage::Source::Missing);
+    }
+
+    #[test]
+    fn process_conversion_checkpoint_restore() {
+        let tests = [
+            ("bash", "/usr/bin/bash", "/usr/bin/bash", false, "Regular"),
+            ("criu", "/usr/bin/bash", "/usr/bin/bash", true, "criu comm"),
+            ("bash", "/usr/sbin/criu", "/usr/bin/bash", true, "criu exe"),
+            (
+                "bash",
+                "/usr/bin/bash",
+                "/usr/sbin/criu",
+                true,
+                "criu parent",
+            ),
+        ];
+
+        for (comm, exe_path, parent, expected, description) in tests {
+            let proc = process_t {
+                comm: string_to_c_char_array::<16>(comm),
+                exe_path: string_to_c_char_array::<{ PATH_MAX as usize }>(exe_path),
+                lineage: [
+                    lineage_t {
+                        uid: 0,
+                        exe_path: string_to_c_char_array::<{ PATH_MAX as usize }>(parent),
+                    },
+                    Default::default(),
+                ],
+                lineage_len: 1,
+                ..no_login()
+            };
+            let result = Process::try_from(proc).expect("Failed to parse process");
+            assert_eq!(
+                result.is_checkpoint_restore(),
+                expected,
+                "Failed for {description}"
+            );
+        }
+    }
+
+    #[test]
+    fn process_conversion_length_limits() {
+        let tests = [
+            (
+                process_t {
+                    lineage_len: LINEAGE_MAX + 1,
+                    ..no_login()
+                },
+                ParseError::LineageTooLong(LINEAGE_MAX + 1),
+                "Lineage too long",
+            ),
+            (
+                process_t {
+                    lineage_len: u32::MAX,
+                    ..no_login()
+                },
+                ParseError::LineageTooLong(u32::MAX),
+                "Hostile lineage length",
+            ),
+            (
+                process_t {
+                    args_len: ARGS_MAX as u32 + 1,
+                    ..no_login()
+                },
+                ParseError::ArgsTooLong(ARGS_MAX as u32 + 1),
+                "Args too long",
+            ),
+            (
+                process_t {
+                    exe_path: [b'a' as c_char; PATH_MAX as usize],
+                    ..no_login()
+                },
+                ParseError::FilenameTooLong,
+                "Unterminated exe_path",
+            ),
+            (
+                process_t {
+                    comm: [b'a' as c_char; 16],
+                    ..no_login()
+                },
+                ParseError::Unterminated,
+                "Unterminated comm",
+            ),
+        ];
+
+        for (proc, expected, description) in tests {
+            let Err(err) = Process::try_from(proc) else {
+                panic!("Expected error for {description}");
+            };
+            assert_eq!(
+                err.downcast_ref::<ParseError>(),
+                Some(&expected),
+                "Failed for {description}"
+            );
+        }
+    }
+
+    #[test]
+    fn process_conversion_truncated_args() {
+        let tests: &[(&[u8], bool, Vec<&str>, &str)] = &[
+            (b"ls\0-l\0", false, vec!["ls", "-l"], "Complete"),
+            (
+                b"cat\0/etc/pass",
+                true,
+                vec!["cat", "/etc/pass"],
+                "Truncated",
+            ),
+            (
+                "echo\0тест".as_bytes().split_last().unwrap().1,
+                true,
+                vec!["echo", "тес"],
+                "Truncated in a character",
+            ),
+        ];
+
+        for (bytes, truncated, expected, description) in tests {
+            let proc = process_t {
+                args: bytes_to_c_char_array::<ARGS_MAX>(bytes),
+                args_len: bytes.len() as u32,
+                args_truncated: *truncated as c_char,
+                ..no_login()
+            };
+            let result = Process::try_from(proc).expect("Failed to parse process");
+            let expected_process = Process {
+                args: expected.iter().map(|s| s.to_string()).collect(),
+                args_truncated: *truncated,
+                ..Default::default()
+            };
+            assert_eq!(result, expected_process, "Failed for {description}");
+        }
+
+        // A cut character is only expected at the end of truncated args
+        let bytes = "echo\0тест".as_bytes().split_last().unwrap().1;
+        let proc = process_t {
+            args: bytes_to_c_char_array::<ARGS_MAX>(bytes),
+            args_len: bytes.len() as u32,
+            ..no_login()
+        };
+        assert!(Process::try_from(proc).is_err());
+    }
+
+    #[test]
+    fn process_conversion_unterminated_args() {
+        let proc = process_t {
+            args: [b'a' as c_char; ARGS_MAX],
+            args_len: ARGS_MAX as u32,
+            ..no_login()
+        };
+        let result = Process::try_from(proc).expect("Failed to parse process");
+        let expected = Process {
+            args: vec!["a".repeat(ARGS_MAX)],
+            ..Default::default()
+        };
+        assert_eq!(result, expected);
+    }
+
+    #[test]
+    fn process_id() {
+        let process = |pid, start_time, comm: &str| {
+            let proc = process_t {
+                comm: string_to_c_char_array::<16>(comm),
+                pid,
+                start_time,
+                ..no_login()
+            };
+            Process::try_from(proc).expect("Failed to parse process")
+        };
+
+        // Events from the same process, even after an exec
+        let first = process(1234, 5_000_000, "bash");
+        let second = process(1234, 5_000_000, "cat");
+        assert_eq!(first.id(), second.id());
+        assert_ne!(first.id(), Uuid::nil());
+
+        // A reused PID or another process started at the same time
+        assert_ne!(first.id(), process(1234, 9_000_000, "bash").id());
+        assert_ne!(first.id(), process(1235, 5_000_000, "bash").id());
+
+        let signal = fact_api::ProcessSignal::from(first.clone());
+        assert_eq!(signal.id, first.id().to_string());
+
+        let json = serde_json::to_value(&first).expect("Failed to serialize process");
+        assert_eq!(json["id"], first.id().to_string());
+        let parsed: Process = serde_json::from_value(json).expect("Failed to parse process");
+        assert_eq!(parsed.id(), first.id());
+    }
 }
@@ -0,0 +1,219 @@
+//! Parsing of events as read from the ringbuffer.
+//!
+//! Parsing takes a few values from the host the event was captured
+//! on: its hostname, the time it booted and the names of its users.
+//! Events read by fact take them from the host it runs on, captures
+//! decoded elsewhere pass a `HostContext` describing the host they come
+//! from, or leave them out.
+
+use super::{Event, Interned, event_from_bytes, lineage::Lineages};
+use crate::{host_info, node_id};
+
+/// What parsing an event takes from the host it was captured on.
+///
+/// The default context knows nothing about the host: hostnames and
+/// boot IDs are left empty, timestamps count from the boot of the host
+/// and names of users and groups are not looked up.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct HostContext {
+    pub hostname: Interned,
+    pub boot_id: Interned,
+    /// Time of the boot of the host, in nanoseconds since the epoch.
+    pub boot_time: u64,
+    pub node_id: Interned,
+    /// Look up the names of users and groups in the files of the host
+    /// fact runs on.
+    pub resolve_names: bool,
+}
+
+impl HostContext {
+    /// The host fact runs on.
+    pub fn local() -> Self {
+        HostContext {
+            hostname: host_info::get_hostname(),
+            boot_id: host_info::get_boot_id(),
+            boot_time: host_info::get_boot_time(),
+            node_id: node_id::get(),
+            resolve_names: true,
+        }
+    }
+
+    pub(super) fn username(&self, uid: u32) -> Interned {
+        match self.resolve_names {
+            true => host_info::get_username(uid),
+            false => "",
+        }
+    }
+
+    pub(super) fn groupname(&self, gid: u32) -> Interned {
+        match self.resolve_names {
+            true => host_info::get_groupname(gid),
+            false => "",
+        }
+    }
+}
+
+/// Parse an event from the bytes of a ringbuffer record, captured on
+/// the host fact runs on.
+///
+/// Records shorter than an event are rejected, unless the kernel left
+/// the lineage out of them, extra bytes are ignored. The lineage left
+/// out of a record is not filled in, parsing does not know about the
+/// records before it.
+pub fn parse_raw(data: &[u8]) -> anyhow::Result<Event> {
+    parse_raw_on(data, &HostContext::local())
+}
+
+/// Parse an event from the bytes of a ringbuffer record, captured on
+/// the host described by `host`.
+pub fn parse_raw_on(data: &[u8], host: &HostContext) -> anyhow::Result<Event> {
+    parse_record(data, host, &Lineages::default())
+}
+
+/// Parse an event from the bytes of a ringbuffer record, filling in
+/// its lineage from the records before it in `lineages`.
+pub(crate) fn parse_record(
+    data: &[u8],
+    host: &HostContext,
+    lineages: &Lineages,
+) -> anyhow::Result<Event> {
+    Event::from_kernel(&*event_from_bytes(data)?, host, lineages)
+}
+
+/// Iterate over the records of a capture, each one preceded by its
+/// length as a 32 bit little endian integer.
+///
+/// Iteration stops on the first length going past the end of the
+/// capture, which is returned as an error.
+pub struct Records<'a> {
+    data: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> Records<'a> {
+    pub fn new(data: &'a [u8]) -> Self {
+        Records { data, offset: 0 }
+    }
+}
+
+impl<'a> Iterator for Records<'a> {
+    type Item = anyhow::Result<&'a [u8]>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let rest = &self.data[self.offset..];
+        if rest.is_empty() {
+            return None;
+        }
+        let record = rest
+            .split_first_chunk::<4>()
+            .map(|(len, rest)| (u32::from_le_bytes(*len) as usize, rest))
+            .and_then(|(len, rest)| rest.get(..len));
+        let Some(record) = record else {
+            let offset = self.offset;
+            self.offset = self.data.len();
+            return Some(Err(anyhow::anyhow!(
+                "record at offset {offset} goes past the end of the capture"
+            )));
+        };
+        self.offset += 4 + record.len();
+        Some(Ok(record))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    /// The host the fixtures were captured on.
+    const HOST: HostContext = HostContext {
+        hostname: "node-1",
+        boot_id: "9c1a4d1e-5b7e-4a38-9a5c-2f0e6f0e3b10",
+        boot_time: 1_700_000_000_000_000_000,
+        node_id: "",
+        resolve_names: false,
+    };
+
+    /// An open and an ownership change of `/etc/motd` by a process in
+    /// a container, as read from the ringbuffer. The bytes must only
+    /// change along with the layout of `event_t`.
+    const OPEN_CHOWN: &[u8] = include_bytes!("testdata/open-chown.bin");
+
+    fn parse(data: &[u8], host: &HostContext) -> Vec<serde_json::Value> {
+        Records::new(data)
+            .map(|record| parse_raw_on(record.unwrap(), host).unwrap())
+            .map(|event| serde_json::to_value(event).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn golden() {
+        let events = parse(OPEN_CHOWN, &HOST);
+        assert_eq!(events.len(), 2);
+
+        let process = json!({
+            "id": "26c81232-a54d-524e-be60-13bdcbcb9eb8",
+            "comm": "touch",
+            "args": ["touch", "/etc/motd"],
+            "exe_path": "/usr/bin/touch",
+            "container_id": "0123456789ab",
+            "uid": 1000,
+            "username": "",
+            "gid": 1000,
+            "login_uid": 1000,
+            "session_id": 3,
+            "pid": 4242,
+            "in_root_mount_ns": false,
+            "lineage": [{"uid": 0, "exe_path": "/usr/bin/bash"}],
+        });
+        assert_eq!(
+            events[0],
+            json!({
+                "timestamp": 1_700_000_001_500_000_000u64,
+                "timestamp_boot_ns": 1_500_000_000u64,
+                "boot_id": HOST.boot_id,
+                "hostname": "node-1",
+                "process": process,
+                "file": {"Open": {
+                    "filename": "/etc/motd",
+                    "host_file": "",
+                    "inode": {"inode": 1234, "dev": 2049},
+                    "parent_inode": {"inode": 12, "dev": 2049},
+                    "monitored": "by inode",
+                    "blocked": false,
+                }},
+            })
+        );
+        assert_eq!(events[1]["file"]["Chown"]["new_uid"], 0);
+        assert_eq!(events[1]["file"]["Chown"]["old_uid"], 1000);
+        assert_eq!(events[1]["process"], process);
+    }
+
+    #[test]
+    fn unknown_host() {
+        // Nothing is taken from the host running the tests
+        for event in parse(OPEN_CHOWN, &HostContext::default()) {
+            assert_eq!(event["hostname"], "");
+            assert_eq!(event.get("boot_id"), None);
+            assert_eq!(event["timestamp"], event["timestamp_boot_ns"]);
+            assert_eq!(event["process"]["username"], "");
+        }
+    }
+
+    #[test]
+    fn records() {
+        let data = [2, 0, 0, 0, b'a', b'b', 0, 0, 0, 0, 3, 0, 0, 0, b'c'];
+        let mut records = Records::new(&data);
+        assert_eq!(records.next().unwrap().unwrap(), b"ab");
+        assert_eq!(records.next().unwrap().unwrap(), b"");
+        assert_eq!(
+            records.next().unwrap().unwrap_err().to_string(),
+            "record at offset 10 goes past the end of the capture"
+        );
+        assert!(records.next().is_none());
+
+        assert!(Records::new(&[]).next().is_none());
+        assert!(Records::new(&[1, 0]).next().unwrap().is_err());
+    }
+}
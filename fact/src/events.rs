@@ -0,0 +1,223 @@
+//! Types for tools consuming the events fact outputs.
+//!
+//! The JSON written by the stdout output is the serialized form of the
+//! types re-exported here, fact uses them for its own outputs and for
+//! `fact replay`, so parsing with them cannot drift from what is
+//! written.
+//!
+//! The format of the output is versioned by `SCHEMA_VERSION`. Output
+//! written by any fact release with the same schema version can be
+//! parsed with these types, a new schema version always comes with a
+//! new major version of fact.
+//!
+//! ```no_run
+//! use std::{fs::File, io::BufReader};
+//!
+//! use fact::events::JsonLines;
+//!
+//! let file = File::open("events.jsonl")?;
+//! for event in JsonLines::new(BufReader::new(file)) {
+//!     let event = event?;
+//!     println!("{} {}", event.event_type(), event.get_filename().display());
+//! }
+//! # Ok::<(), Box<dyn std::error::Error>>(())
+//! ```
+
+use std::io::{self, BufRead};
+
+use thiserror::Error;
+
+pub use crate::event::{
+    AclEntry, AclSetFileData, AclTag, AclType, AttributesFileData, BaseFileData, ChmodFileData,
+    ChownFileData, Event, Existence, FileData, FilterState, InventoryFileData, PauseFileData,
+    RenameFileData, SCHEMA_VERSION, SummaryEntry, SummaryFileData, XattrFileData,
+    attributes::FileFlags,
+    capabilities::Capabilities,
+    lineage::Lineage,
+    process::Process,
+    raw::{HostContext, Records, parse_raw, parse_raw_on},
+};
+pub use fact_ebpf::{inode_key_t, monitored_t};
+
+#[derive(Debug, Error)]
+pub enum ReadError {
+    #[error("failed to read events: {0}")]
+    Io(#[from] io::Error),
+    #[error("invalid event on line {line}: {source}")]
+    Json {
+        line: usize,
+        source: serde_json::Error,
+    },
+}
+
+/// Parse a single event serialized as JSON.
+pub fn parse_line(line: &str) -> serde_json::Result<Event> {
+    serde_json::from_str(line)
+}
+
+/// Read events written one JSON object per line, like the stdout
+/// output does.
+///
+/// Lines are parsed once their newline is read, empty lines are
+/// skipped. An invalid line is reported as an error without stopping
+/// the iteration, so callers can decide to skip it.
+///
+/// Once the end of the input is reached `None` is returned, reading
+/// again picks up lines appended since, which allows following a file
+/// being written to.
+pub struct JsonLines<R> {
+    reader: R,
+    line: usize,
+    buf: String,
+}
+
+impl<R: BufRead> JsonLines<R> {
+    pub fn new(reader: R) -> Self {
+        JsonLines {
+            reader,
+            line: 0,
+            buf: String::new(),
+        }
+    }
+}
+
+impl<R: BufRead> Iterator for JsonLines<R> {
+    type Item = Result<Event, ReadError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            // Partial lines are kept in the buffer until the rest of
+            // the line is written.
+            match self.reader.read_line(&mut self.buf) {
+                Ok(0) => return None,
+                Ok(_) if !self.buf.ends_with('\n') => return None,
+                Ok(_) => {}
+                Err(e) => return Some(Err(e.into())),
+            }
+            self.line += 1;
+            let line = std::mem::take(&mut self.buf);
+            if line.trim().is_empty() {
+                continue;
+            }
+            return Some(parse_line(&line).map_err(|source| ReadError::Json {
+                line: self.line,
+                source,
+            }));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Cursor, Write};
+
+    use fact_ebpf::{PATH_MAX, event_t, file_activity_type_t};
+
+    use super::*;
+    use crate::event::test_utils::string_to_c_char_array;
+
+    /// One event of every type the kernel reports, plus an inventory,
+    /// a summary and a pause one.
+    fn events() -> Vec<Event> {
+        let types = [
+            file_activity_type_t::FILE_ACTIVITY_OPEN,
+            file_activity_type_t::FILE_ACTIVITY_CREATION,
+            file_activity_type_t::DIR_ACTIVITY_CREATION,
+            file_activity_type_t::DIR_ACTIVITY_UNLINK,
+            file_activity_type_t::FILE_ACTIVITY_UNLINK,
+            file_activity_type_t::FILE_ACTIVITY_CHMOD,
+            file_activity_type_t::FILE_ACTIVITY_CHOWN,
+            file_activity_type_t::FILE_ACTIVITY_RENAME,
+            file_activity_type_t::FILE_ACTIVITY_SETXATTR,
+            file_activity_type_t::FILE_ACTIVITY_REMOVEXATTR,
+            file_activity_type_t::FILE_ACTIVITY_ACL_SET,
+            file_activity_type_t::FILE_ACTIVITY_WRITE,
+            file_activity_type_t::FILE_ACTIVITY_ATTRIBUTES,
+        ];
+        let mut events = types
+            .into_iter()
+            .map(|type_| {
+                let mut raw = event_t {
+                    type_,
+                    filename: string_to_c_char_array::<{ PATH_MAX as usize }>("/etc/passwd"),
+                    ..Default::default()
+                };
+                raw.process.comm = string_to_c_char_array("cat");
+                Event::try_from(&raw).expect("Failed to parse event")
+            })
+            .collect::<Vec<_>>();
+
+        let file = tempfile::NamedTempFile::new().unwrap();
+        events.push(Event::inventory(
+            file.path(),
+            &file.path().metadata().unwrap(),
+        ));
+        events.push(Event::summary(
+            1,
+            vec![SummaryEntry {
+                path: "/etc".to_owned(),
+                event_type: "open".to_owned(),
+                container: false,
+                count: 12,
+            }],
+        ));
+        events.push(Event::pause(Some(1700000600)));
+        events
+    }
+
+    #[test]
+    fn round_trip() {
+        let events = events();
+        let mut output = Vec::new();
+        for event in &events {
+            serde_json::to_writer(&mut output, event).unwrap();
+            output.push(b'\n');
+        }
+
+        let read = JsonLines::new(Cursor::new(output))
+            .collect::<Result<Vec<_>, _>>()
+            .expect("Failed to read events");
+        assert_eq!(read, events);
+        for (read, event) in read.iter().zip(&events) {
+            assert_eq!(read.get_timestamp(), event.get_timestamp());
+        }
+
+        let types = read.iter().map(Event::event_type).collect::<Vec<_>>();
+        assert_eq!(types, FileData::EVENT_TYPES);
+    }
+
+    #[test]
+    fn invalid_lines() {
+        let event = serde_json::to_string(&events()[0]).unwrap();
+        let input = format!("{event}\n\nnot json\n{{\"timestamp\": 1}}\n{event}\n");
+
+        let read = JsonLines::new(Cursor::new(input)).collect::<Vec<_>>();
+        assert_eq!(read.len(), 4);
+        assert!(read[0].is_ok());
+        for (res, line) in read[1..3].iter().zip([3, 4]) {
+            let Err(ReadError::Json { line: l, .. }) = res else {
+                panic!("Expected an error on line {line}: {res:?}");
+            };
+            assert_eq!(*l, line);
+        }
+        assert!(read[3].is_ok());
+    }
+
+    #[test]
+    fn follow() {
+        let event = serde_json::to_string(&events()[0]).unwrap();
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        let reader = file.reopen().unwrap();
+        let mut events = JsonLines::new(io::BufReader::new(reader));
+
+        // A partial line is not parsed until it is complete
+        let (start, end) = event.split_at(10);
+        write!(file, "{event}\n{start}").unwrap();
+        assert!(events.next().unwrap().is_ok());
+        assert!(events.next().is_none());
+
+        writeln!(file, "{end}").unwrap();
+        assert!(events.next().unwrap().is_ok());
+        assert!(events.next().is_none());
+    }
+}
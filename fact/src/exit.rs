@@ -0,0 +1,177 @@
+//! How fact reports the errors it stops on.
+//!
+//! Failures with a dedicated exit code are tagged with `Fatal`, added
+//! as context to the error they cause, so service managers can tell a
+//! broken configuration from a host fact cannot run on. Whatever the
+//! failure, a last line summarizes it, enough for triage on its own.
+
+use std::time::Duration;
+
+use log::error;
+use serde::Serialize;
+
+use crate::{instance::LockError, metrics::Metrics, version::FACT_VERSION};
+
+/// Exit code for errors without a dedicated one.
+pub const EXIT_FAILURE: i32 = 1;
+
+/// Exit code used when the configuration is invalid.
+pub const EXIT_CONFIG: i32 = 2;
+
+/// Exit code used when another instance of fact already runs on the
+/// host.
+pub const EXIT_ALREADY_RUNNING: i32 = 3;
+
+/// Exit code used when the host fails the pre-flight checks.
+pub const EXIT_PRE_FLIGHT: i32 = 4;
+
+/// Exit code used when the BPF programs cannot be loaded.
+pub const EXIT_BPF_LOAD: i32 = 5;
+
+/// Classes of failures fact exits with a dedicated code for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, thiserror::Error)]
+pub enum Fatal {
+    #[error("Invalid configuration")]
+    Config,
+    #[error("Pre-flight checks failed")]
+    PreFlight,
+    #[error("Failed to load BPF programs")]
+    BpfLoad,
+}
+
+impl Fatal {
+    pub fn exit_code(self) -> i32 {
+        match self {
+            Fatal::Config => EXIT_CONFIG,
+            Fatal::PreFlight => EXIT_PRE_FLIGHT,
+            Fatal::BpfLoad => EXIT_BPF_LOAD,
+        }
+    }
+}
+
+/// Exit code for fact stopping on `e`.
+pub fn code(e: &anyhow::Error) -> i32 {
+    if let Some(LockError::Held { .. }) = e.downcast_ref() {
+        return EXIT_ALREADY_RUNNING;
+    }
+    e.downcast_ref::<Fatal>()
+        .map_or(EXIT_FAILURE, |fatal| fatal.exit_code())
+}
+
+/// Events counted up to the failure, zero if fact stopped before
+/// reading any.
+#[derive(Debug, Default, PartialEq, Serialize)]
+pub struct Counters {
+    pub kernel_events: u64,
+    pub kernel_dropped: u64,
+    pub rate_limited: u64,
+    pub sent: u64,
+    pub output_dropped: u64,
+}
+
+impl From<&Metrics> for Counters {
+    fn from(metrics: &Metrics) -> Self {
+        let outputs = [
+            &metrics.output.stdout,
+            &metrics.output.grpc,
+            &metrics.output.otel,
+            &metrics.output.sinks,
+        ];
+        Counters {
+            kernel_events: metrics.bpf_worker.added_count(),
+            kernel_dropped: metrics.bpf_worker.dropped_count(),
+            rate_limited: metrics.rate_limiter.dropped_count(),
+            sent: outputs.iter().map(|c| c.added_count()).sum(),
+            output_dropped: outputs.iter().map(|c| c.dropped_count()).sum(),
+        }
+    }
+}
+
+/// Last line logged by fact stopping on an error.
+#[derive(Debug, Serialize)]
+pub struct Summary {
+    pub version: &'static str,
+    pub uptime_secs: u64,
+    pub exit_code: i32,
+    /// Chain of the error, outermost first.
+    pub errors: Vec<String>,
+    pub counters: Counters,
+}
+
+impl Summary {
+    pub fn new(e: &anyhow::Error, uptime: Duration, counters: Counters) -> Self {
+        Summary {
+            version: FACT_VERSION,
+            uptime_secs: uptime.as_secs(),
+            exit_code: code(e),
+            errors: e.chain().map(|e| e.to_string()).collect(),
+            counters,
+        }
+    }
+
+    pub fn log(&self) {
+        match serde_json::to_string(self) {
+            Ok(summary) => error!("Exiting on error: {summary}"),
+            Err(_) => error!("Exiting on error: {}", self.errors.join(": ")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use anyhow::{Context, anyhow};
+
+    use super::*;
+
+    #[test]
+    fn codes() {
+        let tagged = |fatal: Fatal| {
+            Err::<(), _>(anyhow!("root cause"))
+                .context(fatal)
+                .context("Outer context")
+                .unwrap_err()
+        };
+        assert_eq!(code(&tagged(Fatal::Config)), EXIT_CONFIG);
+        assert_eq!(code(&tagged(Fatal::PreFlight)), EXIT_PRE_FLIGHT);
+        assert_eq!(code(&tagged(Fatal::BpfLoad)), EXIT_BPF_LOAD);
+        assert_eq!(code(&anyhow!("Something else")), EXIT_FAILURE);
+
+        let held = anyhow::Error::from(LockError::Held {
+            path: PathBuf::from("/run/fact.lock"),
+            pid: Some(42),
+        });
+        assert_eq!(code(&held), EXIT_ALREADY_RUNNING);
+    }
+
+    #[test]
+    fn summary() {
+        let e = Err::<(), _>(anyhow!("BPF LSM is not enabled"))
+            .context(Fatal::PreFlight)
+            .unwrap_err();
+        let counters = Counters {
+            kernel_events: 10,
+            sent: 8,
+            ..Default::default()
+        };
+        let summary = serde_json::to_value(Summary::new(&e, Duration::from_secs(90), counters))
+            .expect("Failed to serialize summary");
+        assert_eq!(
+            summary,
+            serde_json::json!({
+                "version": FACT_VERSION,
+                "uptime_secs": 90,
+                "exit_code": EXIT_PRE_FLIGHT,
+                "errors": ["Pre-flight checks failed", "BPF LSM is not enabled"],
+                "counters": {
+                    "kernel_events": 10,
+                    "kernel_dropped": 0,
+                    "rate_limited": 0,
+                    "sent": 8,
+                    "output_dropped": 0,
+                },
+            })
+        );
+    }
+}
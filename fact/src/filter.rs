@@ -0,0 +1,709 @@
+//! Filter expressions evaluated on events in userspace.
+//!
+//! Monitored and ignored paths decide what the kernel reports, but some
+//! noise can only be described by combining several properties of an
+//! event, like unlinks done by the package manager in its cache
+//! directory. Filters are small boolean expressions over the fields of
+//! an event:
+//!
+//! ```text
+//! event == "unlink" and exe_path == "/usr/bin/dnf" and path starts_with "/var/cache"
+//! ```
+//!
+//! The supported fields are:
+//!
+//! * `event`: the type of the event, compared with `==` and `!=`.
+//! * `path`, `exe_path`: compared with `==`, `!=` and `starts_with`.
+//! * `uid`: compared with `==`, `!=`, `<`, `<=`, `>` and `>=`.
+//! * `container_id`: compared with `==` and `!=`, `has container_id`
+//!   is true for events coming from a container.
+//!
+//! Conditions are combined with `not`, `and` and `or`, in decreasing
+//! order of precedence, and grouped with parentheses. Strings are
+//! double or single quoted, with `\` escaping the quote and itself.
+//!
+//! Filters are evaluated in the order they are configured, the action
+//! of the first one matching an event decides if it is kept or
+//! dropped. Events not matched by any filter are kept.
+
+use std::{fmt, os::unix::ffi::OsStrExt, str::FromStr};
+
+use crate::event::{Event, FileData};
+
+/// What happens to an event matched by a filter.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum FilterAction {
+    #[default]
+    Drop,
+    Keep,
+}
+
+impl FromStr for FilterAction {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "drop" => Ok(FilterAction::Drop),
+            "keep" => Ok(FilterAction::Keep),
+            s => anyhow::bail!("invalid filter action: {s:?}, expected drop or keep"),
+        }
+    }
+}
+
+/// A filter expression along with the action taken on matching events.
+#[derive(Clone)]
+pub struct Filter {
+    pub action: FilterAction,
+    expr: Expr,
+    source: String,
+}
+
+impl Filter {
+    pub fn new(action: FilterAction, source: &str) -> Result<Self, ParseError> {
+        let expr = source.parse()?;
+        Ok(Filter {
+            action,
+            expr,
+            source: source.to_owned(),
+        })
+    }
+
+    pub fn matches(&self, event: &Event) -> bool {
+        self.expr.eval(&Fields::from(event))
+    }
+}
+
+// The expression is derived from the source, printing and comparing
+// the source is enough and a lot more readable.
+impl fmt::Debug for Filter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Filter")
+            .field("action", &self.action)
+            .field("expr", &self.source)
+            .finish()
+    }
+}
+
+impl PartialEq for Filter {
+    fn eq(&self, other: &Self) -> bool {
+        self.action == other.action && self.source == other.source
+    }
+}
+
+impl Eq for Filter {}
+
+/// Decide whether `event` is kept according to `filters`.
+pub fn keep(filters: &[Filter], event: &Event) -> bool {
+    decide(filters, &Fields::from(event))
+}
+
+fn decide(filters: &[Filter], fields: &Fields) -> bool {
+    filters
+        .iter()
+        .find(|f| f.expr.eval(fields))
+        .is_none_or(|f| f.action == FilterAction::Keep)
+}
+
+/// The values of an event filters can look at.
+struct Fields<'a> {
+    event: &'a str,
+    path: &'a [u8],
+    exe_path: &'a [u8],
+    uid: u32,
+    container_id: Option<&'a str>,
+}
+
+impl<'a> From<&'a Event> for Fields<'a> {
+    fn from(event: &'a Event) -> Self {
+        Fields {
+            event: event.event_type(),
+            path: event.get_filename().as_os_str().as_bytes(),
+            exe_path: event.get_exe_path().as_os_str().as_bytes(),
+            uid: event.get_uid(),
+            container_id: event.get_container_id(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Field {
+    Event,
+    Path,
+    ExePath,
+    Uid,
+    ContainerId,
+}
+
+impl Field {
+    fn parse(name: &str) -> Option<Self> {
+        let field = match name {
+            "event" => Field::Event,
+            "path" => Field::Path,
+            "exe_path" => Field::ExePath,
+            "uid" => Field::Uid,
+            "container_id" => Field::ContainerId,
+            _ => return None,
+        };
+        Some(field)
+    }
+
+    fn allows(&self, op: Op) -> bool {
+        match self {
+            Field::Event | Field::ContainerId => matches!(op, Op::Eq | Op::Ne),
+            Field::Path | Field::ExePath => matches!(op, Op::Eq | Op::Ne | Op::StartsWith),
+            Field::Uid => op != Op::StartsWith,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Op {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    StartsWith,
+}
+
+impl fmt::Display for Op {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let op = match self {
+            Op::Eq => "==",
+            Op::Ne => "!=",
+            Op::Lt => "<",
+            Op::Le => "<=",
+            Op::Gt => ">",
+            Op::Ge => ">=",
+            Op::StartsWith => "starts_with",
+        };
+        f.write_str(op)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Value {
+    Str(String),
+    Int(u32),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Expr {
+    Compare(Field, Op, Value),
+    HasContainerId,
+    Not(Box<Expr>),
+    And(Box<Expr>, Box<Expr>),
+    Or(Box<Expr>, Box<Expr>),
+}
+
+impl Expr {
+    fn eval(&self, fields: &Fields) -> bool {
+        match self {
+            Expr::Compare(field, op, value) => compare(fields, *field, *op, value),
+            Expr::HasContainerId => fields.container_id.is_some(),
+            Expr::Not(e) => !e.eval(fields),
+            Expr::And(l, r) => l.eval(fields) && r.eval(fields),
+            Expr::Or(l, r) => l.eval(fields) || r.eval(fields),
+        }
+    }
+}
+
+fn compare(fields: &Fields, field: Field, op: Op, value: &Value) -> bool {
+    match (field, value) {
+        (Field::Uid, Value::Int(v)) => {
+            let uid = fields.uid;
+            match op {
+                Op::Eq => uid == *v,
+                Op::Ne => uid != *v,
+                Op::Lt => uid < *v,
+                Op::Le => uid <= *v,
+                Op::Gt => uid > *v,
+                Op::Ge => uid >= *v,
+                Op::StartsWith => unreachable!("rejected by the parser"),
+            }
+        }
+        (_, Value::Str(v)) => {
+            let actual = match field {
+                Field::Event => Some(fields.event.as_bytes()),
+                Field::Path => Some(fields.path),
+                Field::ExePath => Some(fields.exe_path),
+                Field::ContainerId => fields.container_id.map(str::as_bytes),
+                Field::Uid => unreachable!("rejected by the parser"),
+            };
+            let v = v.as_bytes();
+            match op {
+                Op::Eq => actual == Some(v),
+                Op::Ne => actual != Some(v),
+                Op::StartsWith => actual.is_some_and(|a| a.starts_with(v)),
+                _ => unreachable!("rejected by the parser"),
+            }
+        }
+        (_, Value::Int(_)) => unreachable!("rejected by the parser"),
+    }
+}
+
+/// Error parsing a filter expression, with the 1-based position where
+/// it was found.
+#[derive(thiserror::Error, Debug, PartialEq, Eq)]
+#[error("line {line}, column {column}: {message}")]
+pub struct ParseError {
+    pub line: usize,
+    pub column: usize,
+    pub message: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Token {
+    Ident(String),
+    Str(String),
+    Int(u32),
+    Op(Op),
+    LParen,
+    RParen,
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Token::Ident(i) => write!(f, "'{i}'"),
+            Token::Str(s) => write!(f, "string {s:?}"),
+            Token::Int(i) => write!(f, "number {i}"),
+            Token::Op(op) => write!(f, "'{op}'"),
+            Token::LParen => f.write_str("'('"),
+            Token::RParen => f.write_str("')'"),
+        }
+    }
+}
+
+fn tokenize(src: &str) -> Result<Vec<(usize, Token)>, (usize, String)> {
+    let mut tokens = Vec::new();
+    let mut chars = src.char_indices().peekable();
+    while let Some((pos, c)) = chars.next() {
+        let token = match c {
+            c if c.is_whitespace() => continue,
+            '(' => Token::LParen,
+            ')' => Token::RParen,
+            '=' | '!' | '<' | '>' => {
+                let eq = chars.next_if(|(_, c)| *c == '=').is_some();
+                let op = match (c, eq) {
+                    ('=', true) => Op::Eq,
+                    ('!', true) => Op::Ne,
+                    ('<', false) => Op::Lt,
+                    ('<', true) => Op::Le,
+                    ('>', false) => Op::Gt,
+                    ('>', true) => Op::Ge,
+                    _ => return Err((pos, format!("unexpected character {c:?}"))),
+                };
+                Token::Op(op)
+            }
+            '"' | '\'' => {
+                let mut s = String::new();
+                loop {
+                    match chars.next() {
+                        Some((_, '\\')) => match chars.next() {
+                            Some((_, e)) if e == c || e == '\\' => s.push(e),
+                            Some((p, e)) => return Err((p, format!("invalid escape {e:?}"))),
+                            None => return Err((pos, "unterminated string".to_owned())),
+                        },
+                        Some((_, e)) if e == c => break,
+                        Some((_, e)) => s.push(e),
+                        None => return Err((pos, "unterminated string".to_owned())),
+                    }
+                }
+                Token::Str(s)
+            }
+            c if c.is_ascii_digit() => {
+                let mut end = pos + c.len_utf8();
+                while let Some((p, _)) = chars.next_if(|(_, c)| c.is_ascii_alphanumeric()) {
+                    end = p + 1;
+                }
+                let n = &src[pos..end];
+                let Ok(n) = n.parse() else {
+                    return Err((pos, format!("invalid number {n:?}")));
+                };
+                Token::Int(n)
+            }
+            c if c.is_ascii_alphabetic() || c == '_' => {
+                let mut end = pos + 1;
+                while let Some((p, _)) =
+                    chars.next_if(|(_, c)| c.is_ascii_alphanumeric() || *c == '_')
+                {
+                    end = p + 1;
+                }
+                match &src[pos..end] {
+                    "starts_with" => Token::Op(Op::StartsWith),
+                    ident => Token::Ident(ident.to_owned()),
+                }
+            }
+            c => return Err((pos, format!("unexpected character {c:?}"))),
+        };
+        tokens.push((pos, token));
+    }
+    Ok(tokens)
+}
+
+/// Recursive descent parser over the tokens of an expression.
+///
+/// ```text
+/// expr    := and ("or" and)*
+/// and     := unary ("and" unary)*
+/// unary   := "not" unary | primary
+/// primary := "(" expr ")" | "has" "container_id" | field op value
+/// ```
+struct Parser {
+    tokens: Vec<(usize, Token)>,
+    next: usize,
+    /// Offset reported for errors at the end of the expression.
+    end: usize,
+}
+
+type ParseResult<T> = Result<T, (usize, String)>;
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.next).map(|(_, t)| t)
+    }
+
+    fn pos(&self) -> usize {
+        self.tokens.get(self.next).map_or(self.end, |(p, _)| *p)
+    }
+
+    fn bump(&mut self) -> ParseResult<(usize, Token)> {
+        let Some(token) = self.tokens.get(self.next).cloned() else {
+            return Err((self.end, "unexpected end of expression".to_owned()));
+        };
+        self.next += 1;
+        Ok(token)
+    }
+
+    fn eat_keyword(&mut self, keyword: &str) -> bool {
+        if matches!(self.peek(), Some(Token::Ident(i)) if i == keyword) {
+            self.next += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expr(&mut self) -> ParseResult<Expr> {
+        let mut lhs = self.and()?;
+        while self.eat_keyword("or") {
+            let rhs = self.and()?;
+            lhs = Expr::Or(Box::new(lhs), Box::new(rhs));
+        }
+        Ok(lhs)
+    }
+
+    fn and(&mut self) -> ParseResult<Expr> {
+        let mut lhs = self.unary()?;
+        while self.eat_keyword("and") {
+            let rhs = self.unary()?;
+            lhs = Expr::And(Box::new(lhs), Box::new(rhs));
+        }
+        Ok(lhs)
+    }
+
+    fn unary(&mut self) -> ParseResult<Expr> {
+        if self.eat_keyword("not") {
+            return Ok(Expr::Not(Box::new(self.unary()?)));
+        }
+        self.primary()
+    }
+
+    fn primary(&mut self) -> ParseResult<Expr> {
+        let (pos, token) = self.bump()?;
+        match token {
+            Token::LParen => {
+                let expr = self.expr()?;
+                match self.bump()? {
+                    (_, Token::RParen) => Ok(expr),
+                    (pos, t) => Err((pos, format!("expected ')', found {t}"))),
+                }
+            }
+            Token::Ident(i) if i == "has" => match self.bump()? {
+                (_, Token::Ident(f)) if f == "container_id" => Ok(Expr::HasContainerId),
+                (pos, t) => Err((
+                    pos,
+                    format!("expected 'container_id' after 'has', found {t}"),
+                )),
+            },
+            Token::Ident(name) => {
+                let Some(field) = Field::parse(&name) else {
+                    return Err((pos, format!("unknown field '{name}'")));
+                };
+                let (op_pos, op) = match self.bump()? {
+                    (p, Token::Op(op)) => (p, op),
+                    (p, t) => return Err((p, format!("expected an operator, found {t}"))),
+                };
+                if !field.allows(op) {
+                    return Err((
+                        op_pos,
+                        format!("operator '{op}' is not supported on '{name}'"),
+                    ));
+                }
+                let (value_pos, value) = self.bump()?;
+                let value = match (field, value) {
+                    (Field::Uid, Token::Int(n)) => Value::Int(n),
+                    (Field::Uid, t) => {
+                        return Err((value_pos, format!("expected a number, found {t}")));
+                    }
+                    (Field::Event, Token::Str(s)) => {
+                        if !FileData::EVENT_TYPES.contains(&s.as_str()) {
+                            return Err((
+                                value_pos,
+                                format!(
+                                    "unknown event type {s:?}, expected one of {}",
+                                    FileData::EVENT_TYPES.join(", ")
+                                ),
+                            ));
+                        }
+                        Value::Str(s)
+                    }
+                    (_, Token::Str(s)) => Value::Str(s),
+                    (_, t) => return Err((value_pos, format!("expected a string, found {t}"))),
+                };
+                Ok(Expr::Compare(field, op, value))
+            }
+            t => Err((pos, format!("expected a condition, found {t}"))),
+        }
+    }
+}
+
+fn position(src: &str, offset: usize) -> (usize, usize) {
+    let before = &src[..offset];
+    let line = before.matches('\n').count() + 1;
+    let column = before
+        .rfind('\n')
+        .map_or(before, |i| &before[i + 1..])
+        .chars()
+        .count()
+        + 1;
+    (line, column)
+}
+
+impl FromStr for Expr {
+    type Err = ParseError;
+
+    fn from_str(src: &str) -> Result<Self, Self::Err> {
+        let to_error = |(offset, message)| {
+            let (line, column) = position(src, offset);
+            ParseError {
+                line,
+                column,
+                message,
+            }
+        };
+
+        let tokens = tokenize(src).map_err(to_error)?;
+        let mut parser = Parser {
+            tokens,
+            next: 0,
+            end: src.len(),
+        };
+        let expr = parser.expr().map_err(to_error)?;
+        if let Some(t) = parser.peek() {
+            let message = format!("unexpected {t} after the end of the expression");
+            return Err(to_error((parser.pos(), message)));
+        }
+        Ok(expr)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fields() -> Fields<'static> {
+        Fields {
+            event: "unlink",
+            path: b"/var/cache/dnf/metadata",
+            exe_path: b"/usr/bin/dnf",
+            uid: 0,
+            container_id: None,
+        }
+    }
+
+    fn eval(src: &str, fields: &Fields) -> bool {
+        src.parse::<Expr>()
+            .unwrap_or_else(|e| panic!("{src}: {e}"))
+            .eval(fields)
+    }
+
+    #[test]
+    fn comparisons() {
+        let host = fields();
+        let container = Fields {
+            event: "open",
+            path: b"/etc/passwd",
+            exe_path: b"/bin/cat",
+            uid: 1000,
+            container_id: Some("0123456789ab"),
+        };
+
+        let tests = [
+            (r#"event == "unlink""#, true, false),
+            (r#"event != "unlink""#, false, true),
+            (r#"path == "/etc/passwd""#, false, true),
+            (r#"path starts_with "/var/cache""#, true, false),
+            (r#"path starts_with '/etc/'"#, false, true),
+            (r#"exe_path == "/usr/bin/dnf""#, true, false),
+            (r#"exe_path starts_with "/usr/""#, true, false),
+            ("uid == 0", true, false),
+            ("uid != 0", false, true),
+            ("uid < 1000", true, false),
+            ("uid <= 1000", true, true),
+            ("uid > 0", false, true),
+            ("uid >= 0", true, true),
+            ("has container_id", false, true),
+            (r#"container_id == "0123456789ab""#, false, true),
+            (r#"container_id != "0123456789ab""#, true, false),
+            // Events outside of containers have no container ID to compare
+            (r#"container_id == """#, false, false),
+        ];
+        for (src, on_host, on_container) in tests {
+            assert_eq!(eval(src, &host), on_host, "{src} on host");
+            assert_eq!(eval(src, &container), on_container, "{src} in container");
+        }
+    }
+
+    #[test]
+    fn precedence() {
+        let f = fields();
+        // and binds tighter than or
+        assert!(eval(r#"uid == 1 and uid == 2 or event == "unlink""#, &f));
+        assert!(eval(r#"event == "unlink" or uid == 1 and uid == 2"#, &f));
+        assert!(!eval(r#"(event == "unlink" or uid == 1) and uid == 2"#, &f));
+        // not binds tighter than and
+        assert!(!eval(r#"not event == "unlink" and uid == 0"#, &f));
+        assert!(eval(r#"not (event == "unlink" and uid == 1)"#, &f));
+        assert!(eval(r#"not not event == "unlink""#, &f));
+
+        assert_eq!(
+            r#"uid == 1 or uid == 2 and not has container_id"#.parse::<Expr>().unwrap(),
+            Expr::Or(
+                Box::new(Expr::Compare(Field::Uid, Op::Eq, Value::Int(1))),
+                Box::new(Expr::And(
+                    Box::new(Expr::Compare(Field::Uid, Op::Eq, Value::Int(2))),
+                    Box::new(Expr::Not(Box::new(Expr::HasContainerId))),
+                )),
+            )
+        );
+    }
+
+    #[test]
+    fn strings() {
+        let f = Fields {
+            path: br#"/tmp/it's "quoted"\"#,
+            ..fields()
+        };
+        assert!(eval(r#"path == "/tmp/it's \"quoted\"\\""#, &f));
+        assert!(eval(r#"path == '/tmp/it\'s "quoted"\\'"#, &f));
+    }
+
+    #[test]
+    fn invalid() {
+        let tests = [
+            ("", 1, 1, "unexpected end of expression"),
+            ("uid", 1, 4, "unexpected end of expression"),
+            ("uid ==", 1, 7, "unexpected end of expression"),
+            ("user == 0", 1, 1, "unknown field 'user'"),
+            (
+                r#"uid == "0""#,
+                1,
+                8,
+                r#"expected a number, found string "0""#,
+            ),
+            ("path == 0", 1, 9, "expected a string, found number 0"),
+            (
+                r#"uid starts_with "0""#,
+                1,
+                5,
+                "operator 'starts_with' is not supported on 'uid'",
+            ),
+            (
+                r#"event < "open""#,
+                1,
+                7,
+                "operator '<' is not supported on 'event'",
+            ),
+            (
+                r#"event == "read""#,
+                1,
+                10,
+                r#"unknown event type "read", expected one of "#,
+            ),
+            ("uid = 0", 1, 5, "unexpected character '='"),
+            ("uid == 0 &&", 1, 10, "unexpected character '&'"),
+            (r#"path == "/etc"#, 1, 9, "unterminated string"),
+            (r#"path == "\n""#, 1, 11, "invalid escape 'n'"),
+            ("uid == 99999999999", 1, 8, "invalid number"),
+            ("(uid == 0", 1, 10, "unexpected end of expression"),
+            (
+                "uid == 0)",
+                1,
+                9,
+                "unexpected ')' after the end of the expression",
+            ),
+            ("uid == 0 uid == 1", 1, 10, "unexpected 'uid' after the end"),
+            ("has path", 1, 5, "expected 'container_id' after 'has'"),
+            ("not", 1, 4, "unexpected end of expression"),
+            ("uid == 0 and", 1, 13, "unexpected end of expression"),
+            ("== 0", 1, 1, "expected a condition, found '=='"),
+            (
+                "uid == 0 or\n  (path starts_with \"/tmp\" and\n   exe == \"/bin/sh\")",
+                3,
+                4,
+                "unknown field 'exe'",
+            ),
+        ];
+        for (src, line, column, message) in tests {
+            let err = src.parse::<Expr>().unwrap_err();
+            assert_eq!((err.line, err.column), (line, column), "{src}: {err}");
+            assert!(err.message.starts_with(message), "{src}: {err}");
+        }
+    }
+
+    #[test]
+    fn actions() {
+        let drop = |src| Filter::new(FilterAction::Drop, src).unwrap();
+        let keep = |src| Filter::new(FilterAction::Keep, src).unwrap();
+        let f = fields();
+
+        // Nothing matches, the event is kept
+        assert!(decide(&[], &f));
+        assert!(decide(&[drop("uid == 1")], &f));
+
+        assert!(!decide(
+            &[drop(
+                r#"event == "unlink" and exe_path == "/usr/bin/dnf" and path starts_with "/var/cache""#
+            )],
+            &f
+        ));
+
+        // The first matching filter wins
+        assert!(decide(&[keep("uid == 0"), drop("uid == 0")], &f));
+        assert!(!decide(&[drop("uid == 0"), keep("uid == 0")], &f));
+        assert!(!decide(&[keep("uid == 1"), drop("uid == 0")], &f));
+
+        assert_eq!("drop".parse::<FilterAction>().unwrap(), FilterAction::Drop);
+        assert_eq!("keep".parse::<FilterAction>().unwrap(), FilterAction::Keep);
+        assert!("ignore".parse::<FilterAction>().is_err());
+    }
+
+    #[test]
+    fn events() {
+        let dir = tempfile::tempdir().expect("Failed to create directory");
+        let path = dir.path().join("file");
+        std::fs::write(&path, "").unwrap();
+        let metadata = path.metadata().unwrap();
+        let event = Event::inventory(&path, &metadata);
+
+        let drop_inventory = Filter::new(FilterAction::Drop, r#"event == "inventory""#).unwrap();
+        assert!(drop_inventory.matches(&event));
+        assert!(!keep(&[drop_inventory], &event));
+
+        let drop_container = Filter::new(FilterAction::Drop, "has container_id").unwrap();
+        assert!(keep(&[drop_container], &event));
+    }
+}
@@ -0,0 +1,92 @@
+//! Traversal of the file system entries matching the monitored paths.
+//!
+//! The walker only resolves the configured glob patterns and
+//! classifies the entries found, what is done with each entry is up to
+//! the caller. This allows the same traversal to be used for
+//! populating the BPF maps in the `HostScanner` and for the one-shot
+//! inventory done by `fact scan`, which does not load BPF at all.
+
+use std::{
+    fs::FileType,
+    path::{Path, PathBuf},
+};
+
+use anyhow::bail;
+
+use crate::{host_fs, host_info};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum EntryKind {
+    File,
+    Directory,
+    /// Anything that is not a regular file or a directory, like
+    /// sockets, devices or symlinks.
+    Other,
+}
+
+impl From<FileType> for EntryKind {
+    fn from(file_type: FileType) -> Self {
+        if file_type.is_file() {
+            EntryKind::File
+        } else if file_type.is_dir() {
+            EntryKind::Directory
+        } else {
+            EntryKind::Other
+        }
+    }
+}
+
+/// Walk the entries matching a monitored path pattern.
+///
+/// The pattern is resolved under the host mount, so the returned paths
+/// include it. Symlinks are not followed, entries reached through a
+/// symlink leading out of the host mount are classified as `Other`.
+pub(crate) fn walk(
+    pattern: &Path,
+) -> anyhow::Result<impl Iterator<Item = anyhow::Result<(PathBuf, EntryKind)>>> {
+    let path = host_info::prepend_host_mount(pattern);
+    let Some(glob_str) = path.to_str() else {
+        bail!("invalid path {}", path.display());
+    };
+
+    let entries = glob::glob(glob_str)?.map(|entry| {
+        let path = entry?;
+        let kind = host_fs::symlink_metadata(host_info::get_host_mount(), &path)
+            .map_or(EntryKind::Other, |metadata| metadata.file_type().into());
+        Ok((path, kind))
+    });
+    Ok(entries)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, os::unix::fs::symlink};
+
+    use super::*;
+
+    #[test]
+    fn walk_tree() {
+        let root = tempfile::tempdir().expect("Failed to create root");
+        let root = root.path();
+        fs::create_dir_all(root.join("etc/ssh")).unwrap();
+        fs::write(root.join("etc/passwd"), "root:x:0:0").unwrap();
+        fs::write(root.join("etc/ssh/sshd_config"), "").unwrap();
+        symlink(root.join("missing"), root.join("etc/dangling")).unwrap();
+        symlink(root.join("etc/passwd"), root.join("etc/link")).unwrap();
+
+        let mut entries = walk(&root.join("etc/**/*"))
+            .expect("Failed to walk")
+            .collect::<anyhow::Result<Vec<_>>>()
+            .expect("Failed to read entry");
+        entries.sort_by(|(a, _), (b, _)| a.cmp(b));
+
+        let expected = vec![
+            (root.join("etc/dangling"), EntryKind::Other),
+            (root.join("etc/link"), EntryKind::Other),
+            (root.join("etc/passwd"), EntryKind::File),
+            (root.join("etc/ssh"), EntryKind::Directory),
+            (root.join("etc/ssh/sshd_config"), EntryKind::File),
+        ];
+        assert_eq!(entries, expected);
+    }
+}
@@ -0,0 +1,416 @@
+//! Synthetic events for load and soak testing.
+//!
+//! `fact generate` fabricates events and feeds them to the userspace
+//! pipeline instead of loading the BPF programs, so performance of the
+//! pipeline and outputs can be measured without a real workload. Paths,
+//! processes and containers are drawn from pools of configurable size,
+//! a fraction of the processes runs on the host.
+//!
+//! By default events are built from pre-parsed processes. With `--raw`
+//! every event is encoded in the format the kernel writes to the
+//! ringbuffer and parsed back, exercising the same code as events from
+//! the BPF programs.
+
+use std::{
+    os::raw::c_char,
+    time::{Duration, Instant},
+};
+
+use fact_ebpf::{event_t, file_activity_type_t, inode_key_t, monitored_t, process_t};
+use log::info;
+use tokio::{
+    sync::{mpsc, watch},
+    task::JoinSet,
+    time::{MissedTickBehavior, interval},
+};
+
+use crate::{
+    config::GenerateOptions,
+    event::{self, Event, FileData, now_ns, process::Process},
+    host_info,
+    metrics::{EventCounter, Metrics, OutputMetrics},
+};
+
+/// Number of directories generated paths are spread across.
+const DIRS: u32 = 32;
+
+/// Time between batches of events when generating at a fixed rate.
+const TICK: Duration = Duration::from_millis(10);
+
+/// Number of events between checks for stopping when unthrottled.
+const UNTHROTTLED_BATCH: u64 = 128;
+
+pub struct Generator {
+    options: GenerateOptions,
+    processes: Vec<process_t>,
+    /// `processes` already parsed, used when not in raw mode.
+    parsed: Vec<Process>,
+}
+
+impl Generator {
+    pub fn new(options: &GenerateOptions) -> anyhow::Result<Self> {
+        let processes = (0..options.processes())
+            .map(|i| process(i, options))
+            .collect::<Vec<_>>();
+        let parsed = if options.raw() {
+            Vec::new()
+        } else {
+            processes
+                .iter()
+                .map(|p| Process::try_from(*p))
+                .collect::<anyhow::Result<_>>()?
+        };
+        Ok(Generator {
+            options: options.clone(),
+            processes,
+            parsed,
+        })
+    }
+
+    /// Fabricate a random event in the format read from the
+    /// ringbuffer.
+    pub fn next_raw(&self) -> Box<event_t> {
+        // SAFETY: all zeroes is a valid event_t, starting from a zeroed
+        // allocation also leaves no padding bytes uninitialized.
+        let mut event: Box<event_t> = unsafe { Box::new_zeroed().assume_init() };
+        let process = rand::random_range(0..self.processes.len());
+        event.process = self.processes[process];
+        self.fill_file(&mut event);
+        event
+    }
+
+    pub fn next(&self) -> anyhow::Result<Event> {
+        if self.options.raw() {
+            let event = self.next_raw();
+            return event::parse_raw(as_bytes(&event));
+        }
+
+        let mut event = Box::<event_t>::default();
+        self.fill_file(&mut event);
+        let process = rand::random_range(0..self.parsed.len());
+        let file = FileData::new(
+            event.type_,
+            event.filename,
+            event.inode,
+            event.parent_inode,
+            event.monitored,
+            false,
+            event.__bindgen_anon_1,
+        )?;
+        Ok(Event::from_kernel_parts(
+            event.timestamp,
+            self.parsed[process].clone(),
+            file,
+        ))
+    }
+
+    fn fill_file(&self, event: &mut event_t) {
+        let file = rand::random_range(0..self.options.paths());
+        let dir = file % DIRS;
+        copy_str(
+            &mut event.filename,
+            &format!("/generated/dir_{dir}/file_{file}"),
+        );
+        event.timestamp = now_ns().saturating_sub(host_info::get_boot_time());
+        event.inode = inode_key_t {
+            inode: u64::from(DIRS + file),
+            dev: 1,
+        };
+        event.parent_inode = inode_key_t {
+            inode: u64::from(dir),
+            dev: 1,
+        };
+        event.monitored = monitored_t::MONITORED_BY_PATH;
+
+        // Mostly opens, like on a real host
+        event.type_ = match rand::random_range(0..10) {
+            0 => file_activity_type_t::FILE_ACTIVITY_CREATION,
+            1 => file_activity_type_t::FILE_ACTIVITY_UNLINK,
+            2 => {
+                let chmod = unsafe { &mut event.__bindgen_anon_1.chmod };
+                chmod.old = 0o100644;
+                chmod.new = 0o100600;
+                file_activity_type_t::FILE_ACTIVITY_CHMOD
+            }
+            _ => file_activity_type_t::FILE_ACTIVITY_OPEN,
+        };
+    }
+}
+
+/// Fabricate the `i`th process of the pool.
+///
+/// The first `host_pct` percent of processes run on the host, the rest
+/// are spread across the containers.
+fn process(i: u32, options: &GenerateOptions) -> process_t {
+    let mut process = process_t {
+        uid: 1000 + i % 10,
+        gid: 1000 + i % 10,
+        login_uid: u32::MAX,
+        session_id: u32::MAX,
+        pid: 10_000 + i,
+        ..Default::default()
+    };
+    copy_str(&mut process.comm, &format!("gen-{i}"));
+    copy_str(
+        &mut process.exe_path,
+        &format!("/usr/bin/generated-{}", i % 16),
+    );
+
+    let args = format!("generated\0--id\0{i}\0");
+    copy_str(&mut process.args, &args);
+    process.args_len = args.len() as u32;
+
+    let on_host =
+        u64::from(i) * 100 < u64::from(options.processes()) * u64::from(options.host_pct());
+    if !on_host && options.containers() > 0 {
+        let container = i % options.containers();
+        let id = format!(
+            "{:016x}",
+            u64::from(container).wrapping_mul(0x9e3779b97f4a7c15)
+        )
+        .repeat(4);
+        copy_str(
+            &mut process.memory_cgroup,
+            &format!("/kubepods/besteffort/pod-generated/{id}"),
+        );
+    } else {
+        process.in_root_mount_ns = 1;
+    }
+    process
+}
+
+/// Copy `s` into a C string buffer, truncating it if needed.
+pub(crate) fn copy_str(buf: &mut [c_char], s: &str) {
+    let len = s.len().min(buf.len() - 1);
+    for (dst, src) in buf.iter_mut().zip(&s.as_bytes()[..len]) {
+        *dst = *src as c_char;
+    }
+    buf[len] = 0;
+}
+
+/// View an event as the bytes written to the ringbuffer.
+pub(crate) fn as_bytes(event: &event_t) -> &[u8] {
+    // SAFETY: event_t is plain old data, generated events are built
+    // from zeroed memory.
+    unsafe {
+        std::slice::from_raw_parts(
+            (event as *const event_t).cast::<u8>(),
+            std::mem::size_of::<event_t>(),
+        )
+    }
+}
+
+/// Start a task sending generated events until stopped.
+pub fn start(
+    task_set: &mut JoinSet<anyhow::Result<()>>,
+    options: &GenerateOptions,
+    metrics: EventCounter,
+    mut running: watch::Receiver<bool>,
+) -> anyhow::Result<mpsc::Receiver<Event>> {
+    let generator = Generator::new(options)?;
+    let rate = options.rate();
+    let (tx, rx) = mpsc::channel(crate::EVENT_CHANNEL_CAPACITY);
+
+    task_set.spawn(async move {
+        let speed = match rate {
+            0 => "as fast as possible".to_string(),
+            rate => format!("at {rate} events/s"),
+        };
+        info!(
+            "Generating events {speed} with {} paths, {} processes and {} containers",
+            generator.options.paths(),
+            generator.options.processes(),
+            generator.options.containers(),
+        );
+
+        let mut ticker = interval(TICK);
+        ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        let mut carry = 0;
+        loop {
+            if rate > 0 {
+                tokio::select! {
+                    _ = ticker.tick() => {}
+                    Ok(_) = running.changed() => {}
+                }
+            }
+            if !*running.borrow() {
+                return Ok(());
+            }
+
+            // Rates that are not a multiple of the tick frequency are
+            // evened out across ticks.
+            let batch = if rate == 0 {
+                UNTHROTTLED_BATCH
+            } else {
+                carry += rate * TICK.as_millis() as u64;
+                let batch = carry / 1000;
+                carry %= 1000;
+                batch
+            };
+            for _ in 0..batch {
+                let event = generator.next()?;
+                if tx.send(event).await.is_err() {
+                    return Ok(());
+                }
+                metrics.added();
+            }
+        }
+    });
+    Ok(rx)
+}
+
+/// Report of a generator run, built from the metrics.
+pub struct Summary {
+    start: Instant,
+    generated: EventCounter,
+    rate_limiter: EventCounter,
+    output: OutputMetrics,
+}
+
+impl Summary {
+    pub fn new(metrics: &Metrics) -> Self {
+        Summary {
+            start: Instant::now(),
+            generated: metrics.generator.clone(),
+            rate_limiter: metrics.rate_limiter.clone(),
+            output: metrics.output.clone(),
+        }
+    }
+
+    pub fn report(&self) {
+        let elapsed = self.start.elapsed();
+        let generated = self.generated.added_count();
+        info!(
+            "Generated {generated} events in {elapsed:.1?} ({:.0} events/s)",
+            generated as f64 / elapsed.as_secs_f64()
+        );
+        info!(
+            "Rate limiter dropped {} events",
+            self.rate_limiter.dropped_count()
+        );
+        for (name, counter) in [
+            ("stdout", &self.output.stdout),
+            ("grpc", &self.output.grpc),
+            ("otel", &self.output.otel),
+        ] {
+            let (added, dropped) = (counter.added_count(), counter.dropped_count());
+            if added + dropped > 0 {
+                info!(
+                    "Output {name}: {added} events sent ({:.0} events/s), {dropped} dropped",
+                    added as f64 / elapsed.as_secs_f64()
+                );
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn options(raw: bool) -> GenerateOptions {
+        GenerateOptions {
+            paths: Some(50),
+            processes: Some(20),
+            containers: Some(3),
+            host_pct: Some(25),
+            raw: Some(raw),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn raw_round_trip() {
+        let generator = Generator::new(&options(true)).unwrap();
+        for _ in 0..1000 {
+            let raw = generator.next_raw();
+            let event = event::parse_raw(as_bytes(&raw)).expect("Failed to parse event");
+            assert_eq!(event, Event::try_from(&*raw).unwrap());
+
+            let path = event.get_filename().to_str().unwrap();
+            assert!(path.starts_with("/generated/dir_"), "{path}");
+            assert_eq!(event.get_inode(), &raw.inode);
+            assert!(event.get_pid() >= 10_000);
+        }
+    }
+
+    #[test]
+    fn distribution() {
+        let generator = Generator::new(&options(false)).unwrap();
+        let mut paths = std::collections::HashSet::new();
+        let mut containers = std::collections::HashSet::new();
+        let mut on_host = 0;
+        for _ in 0..5000 {
+            let event = generator.next().unwrap();
+            paths.insert(event.get_filename().clone());
+            match event.get_container_id() {
+                Some(id) => {
+                    assert_eq!(id.len(), 12);
+                    containers.insert(id.to_string());
+                }
+                None => on_host += 1,
+            }
+        }
+        assert_eq!(paths.len(), 50);
+        assert_eq!(containers.len(), 3);
+        // 5 out of 20 processes run on the host
+        assert!((1000..1500).contains(&on_host), "{on_host}");
+    }
+
+    #[test]
+    fn modes_match() {
+        // Both modes produce the same events out of the same raw data
+        let generator = Generator::new(&options(false)).unwrap();
+        let raw = generator.next_raw();
+        let parsed = Event::try_from(&*raw).unwrap();
+        let rebuilt = Event::from_kernel_parts(
+            raw.timestamp,
+            Process::try_from(raw.process).unwrap(),
+            FileData::new(
+                raw.type_,
+                raw.filename,
+                raw.inode,
+                raw.parent_inode,
+                raw.monitored,
+                false,
+                raw.__bindgen_anon_1,
+            )
+            .unwrap(),
+        );
+        assert_eq!(parsed, rebuilt);
+        assert_eq!(parsed.get_timestamp(), rebuilt.get_timestamp());
+        assert_eq!(
+            parsed.get_timestamp_boot_ns(),
+            rebuilt.get_timestamp_boot_ns()
+        );
+    }
+
+    #[tokio::test]
+    async fn rate() {
+        let mut options = options(false);
+        options.rate = Some(1000);
+        let metrics = Metrics::new();
+        let (running_tx, running) = watch::channel(true);
+        let mut task_set = JoinSet::new();
+        let mut rx = start(&mut task_set, &options, metrics.generator.clone(), running).unwrap();
+
+        let start = Instant::now();
+        let mut received = 0;
+        while start.elapsed() < Duration::from_millis(500) {
+            if tokio::time::timeout(Duration::from_millis(50), rx.recv())
+                .await
+                .is_ok()
+            {
+                received += 1;
+            }
+        }
+        running_tx.send(false).unwrap();
+        crate::join_all_tasks(task_set).await.unwrap();
+        assert!((300..700).contains(&received), "{received}");
+
+        while rx.try_recv().is_ok() {
+            received += 1;
+        }
+        assert_eq!(metrics.generator.added_count(), received);
+    }
+}
@@ -0,0 +1,312 @@
+//! Tracking of the service health reported by the readiness endpoint.
+//!
+//! When outputs are not able to keep up with the amount of events
+//! generated, events are dropped. If that happens persistently, fact is
+//! reported as degraded so orchestration can react to it, e.g. by
+//! alerting or avoiding to add more workload to the node.
+//!
+//! Event collection being paused is tracked here as well, so readiness
+//! can optionally fail for as long as events are not being collected.
+
+use log::{info, warn};
+use tokio::{sync::watch, task::JoinHandle, time::interval};
+
+use crate::{config::ReadinessConfig, metrics::OutputMetrics, pause::PauseState};
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct HealthState {
+    pub degraded: bool,
+    pub fail_on_degraded: bool,
+    /// Events dropped by the outputs in the last interval.
+    pub dropped: u64,
+    pub paused: bool,
+    pub fail_on_paused: bool,
+}
+
+/// Hysteresis for switching between ready and degraded.
+///
+/// Readiness is only degraded after `degraded_after` consecutive
+/// intervals over the drop threshold and only recovers after
+/// `recover_after` consecutive intervals under it.
+#[derive(Debug, Default)]
+struct DropPolicy {
+    last_total: Option<u64>,
+    over: u64,
+    under: u64,
+    degraded: bool,
+}
+
+impl DropPolicy {
+    /// Update the policy with a snapshot of the total number of events
+    /// dropped by the outputs.
+    ///
+    /// Returns the number of events dropped since the last snapshot.
+    fn update(&mut self, config: &ReadinessConfig, total: u64) -> u64 {
+        let dropped = total.saturating_sub(self.last_total.unwrap_or(total));
+        self.last_total = Some(total);
+
+        let threshold = config.drop_threshold();
+        if threshold == 0 {
+            self.degraded = false;
+            return dropped;
+        }
+
+        if dropped > threshold {
+            self.over += 1;
+            self.under = 0;
+        } else {
+            self.under += 1;
+            self.over = 0;
+        }
+
+        if !self.degraded && self.over >= config.degraded_after() {
+            self.degraded = true;
+        } else if self.degraded && self.under >= config.recover_after() {
+            self.degraded = false;
+        }
+        dropped
+    }
+
+    /// Forget about previous intervals, keeping the last snapshot.
+    fn reset(&mut self) {
+        self.over = 0;
+        self.under = 0;
+        self.degraded = false;
+    }
+}
+
+pub struct HealthMonitor {
+    metrics: OutputMetrics,
+    config: watch::Receiver<ReadinessConfig>,
+    state: watch::Sender<HealthState>,
+    pause: watch::Receiver<PauseState>,
+    running: watch::Receiver<bool>,
+    policy: DropPolicy,
+}
+
+impl HealthMonitor {
+    pub fn new(
+        metrics: OutputMetrics,
+        config: watch::Receiver<ReadinessConfig>,
+        pause: watch::Receiver<PauseState>,
+        running: watch::Receiver<bool>,
+    ) -> Self {
+        let (state, _) = watch::channel(HealthState {
+            fail_on_degraded: config.borrow().fail_on_degraded(),
+            paused: pause.borrow().is_paused(),
+            fail_on_paused: config.borrow().fail_on_paused(),
+            ..Default::default()
+        });
+        HealthMonitor {
+            metrics,
+            config,
+            state,
+            pause,
+            running,
+            policy: DropPolicy::default(),
+        }
+    }
+
+    pub fn subscribe(&self) -> watch::Receiver<HealthState> {
+        self.state.subscribe()
+    }
+
+    /// Consume the monitor into a task that periodically checks the
+    /// events dropped by the outputs.
+    pub fn start(mut self) -> JoinHandle<()> {
+        tokio::spawn(async move {
+            loop {
+                let mut ticker = interval(self.config.borrow().interval());
+                loop {
+                    tokio::select! {
+                        _ = ticker.tick() => self.check(),
+                        Ok(_) = self.config.changed() => break,
+                        Ok(_) = self.pause.changed() => {
+                            let paused = self.pause.borrow_and_update().is_paused();
+                            self.state.send_if_modified(|state| {
+                                let modified = state.paused != paused;
+                                state.paused = paused;
+                                modified
+                            });
+                        }
+                        _ = self.running.changed() => {
+                            if !*self.running.borrow() {
+                                info!("Stopping health monitor...");
+                                return;
+                            }
+                        }
+                    }
+                }
+
+                info!("Reloading health monitor...");
+                self.policy.reset();
+                let (fail_on_degraded, fail_on_paused) = {
+                    let config = self.config.borrow();
+                    (config.fail_on_degraded(), config.fail_on_paused())
+                };
+                self.state.send_modify(|state| {
+                    state.degraded = false;
+                    state.fail_on_degraded = fail_on_degraded;
+                    state.fail_on_paused = fail_on_paused;
+                });
+            }
+        })
+    }
+
+    fn check(&mut self) {
+        let dropped = self
+            .policy
+            .update(&self.config.borrow(), self.metrics.dropped_total());
+        let degraded = self.policy.degraded;
+
+        self.state.send_if_modified(|state| {
+            if state.degraded != degraded {
+                if degraded {
+                    warn!("Outputs are persistently dropping events, readiness is degraded");
+                } else {
+                    info!("Outputs recovered, readiness is no longer degraded");
+                }
+            }
+
+            let modified = state.degraded != degraded || state.dropped != dropped;
+            state.degraded = degraded;
+            state.dropped = dropped;
+            modified
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+    use crate::{
+        config::FactConfig,
+        metrics::{DropReason, Metrics},
+    };
+
+    fn config(yaml: &str) -> ReadinessConfig {
+        FactConfig::try_from(yaml)
+            .expect("Failed to parse config")
+            .readiness
+    }
+
+    #[test]
+    fn sustained_drops() {
+        let config =
+            config("readiness:\n  drop_threshold: 10\n  degraded_after: 3\n  recover_after: 2");
+        let mut policy = DropPolicy::default();
+        let mut total = 0;
+
+        // The first snapshot is only used as a reference
+        assert_eq!(policy.update(&config, 1000), 0);
+        total += 1000;
+
+        // (dropped in interval, expected degraded)
+        let steps = [
+            (100, false),
+            (100, false),
+            (5, false),
+            (100, false),
+            (100, false),
+            (100, true),
+            (11, true),
+            (10, true),
+            (100, true),
+            (0, true),
+            (0, false),
+            (100, false),
+        ];
+        for (i, (dropped, degraded)) in steps.into_iter().enumerate() {
+            total += dropped;
+            assert_eq!(policy.update(&config, total), dropped, "Step {i}");
+            assert_eq!(policy.degraded, degraded, "Step {i}");
+        }
+    }
+
+    #[test]
+    fn disabled() {
+        let config = ReadinessConfig::default();
+        let mut policy = DropPolicy::default();
+
+        for total in (0..10).map(|i| i * 1000) {
+            policy.update(&config, total);
+            assert!(!policy.degraded);
+        }
+    }
+
+    #[tokio::test]
+    async fn monitor() {
+        let metrics = Metrics::new().output;
+        let (config_tx, config_rx) = watch::channel(config(
+            "readiness:\n  drop_threshold: 1\n  degraded_after: 2\n  recover_after: 2\n  fail_on_degraded: true",
+        ));
+        let (_running_tx, running_rx) = watch::channel(true);
+        let (_pause_tx, pause_rx) = watch::channel(PauseState::default());
+        let mut monitor = HealthMonitor::new(metrics.clone(), config_rx, pause_rx, running_rx);
+        let state = monitor.subscribe();
+        assert!(state.borrow().fail_on_degraded);
+
+        monitor.check();
+        for _ in 0..2 {
+            metrics.grpc.dropped_n(DropReason::Lagged, 5);
+            monitor.check();
+        }
+        assert_eq!(
+            *state.borrow(),
+            HealthState {
+                degraded: true,
+                fail_on_degraded: true,
+                dropped: 5,
+                ..Default::default()
+            }
+        );
+
+        for _ in 0..2 {
+            monitor.check();
+        }
+        assert!(!state.borrow().degraded);
+
+        // Configuration changes are picked up by the running task
+        let mut state = monitor.subscribe();
+        let handle = monitor.start();
+        config_tx.send_modify(|c| *c = config("readiness:\n  interval: 0.01"));
+        tokio::time::timeout(
+            Duration::from_secs(1),
+            state.wait_for(|s| !s.fail_on_degraded),
+        )
+        .await
+        .expect("Configuration change not picked up")
+        .unwrap();
+        handle.abort();
+    }
+
+    #[tokio::test]
+    async fn paused() {
+        let metrics = Metrics::new().output;
+        let (_config_tx, config_rx) = watch::channel(config("readiness:\n  fail_on_paused: true"));
+        let (_running_tx, running_rx) = watch::channel(true);
+        let (pause_tx, pause_rx) = watch::channel(PauseState::default());
+        let monitor = HealthMonitor::new(metrics, config_rx, pause_rx, running_rx);
+        let mut state = monitor.subscribe();
+        assert!(state.borrow().fail_on_paused);
+        assert!(!state.borrow().paused);
+
+        let handle = monitor.start();
+        for paused in [true, false] {
+            pause_tx.send_replace(PauseState {
+                paused_until: paused
+                    .then(|| std::time::SystemTime::now() + Duration::from_secs(60)),
+            });
+            tokio::time::timeout(
+                Duration::from_secs(1),
+                state.wait_for(|s| s.paused == paused),
+            )
+            .await
+            .expect("Pause state not picked up")
+            .unwrap();
+        }
+        handle.abort();
+    }
+}
@@ -0,0 +1,274 @@
+//! Access to files under the host mount.
+//!
+//! The paths fact reads for enrichment are in directories the monitored
+//! workloads can write to, a symlink planted there must not make fact
+//! read files it was not asked to, like /proc/kcore, or leave the host
+//! mount. Files are opened relative to the mount with openat2(2) and
+//! `RESOLVE_BENEATH`, which fails with `EXDEV` on any symlink or `..`
+//! leading out of it, absolute symlinks included. The last component
+//! is opened with `O_NOFOLLOW`, so it is never a symlink.
+//!
+//! Kernels before 5.6 have no openat2, the parent directory of the file
+//! is canonicalized and checked to be under the mount instead. That
+//! check can be raced by renaming a directory before the open.
+//!
+//! Only regular files of up to `MAX_READ_SIZE` bytes are read.
+
+use std::{
+    ffi::CString,
+    fs::{File, Metadata, OpenOptions},
+    io::{self, Read},
+    mem,
+    os::{
+        fd::{AsRawFd, FromRawFd},
+        unix::{ffi::OsStrExt, fs::OpenOptionsExt},
+    },
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+use libc::c_int;
+use log::debug;
+
+/// Largest file read from the host, bigger ones are rejected.
+pub(crate) const MAX_READ_SIZE: u64 = 16 * 1024 * 1024;
+
+/// Cleared once openat2 is found to be missing.
+static OPENAT2: AtomicBool = AtomicBool::new(true);
+
+/// Open the regular file at `path` for reading, `path` including
+/// `mount`.
+pub(crate) fn open(mount: &Path, path: &Path) -> io::Result<File> {
+    let file = open_beneath(mount, path, libc::O_RDONLY | libc::O_NONBLOCK)?;
+    let metadata = file.metadata()?;
+    if !metadata.is_file() {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "not a regular file",
+        ));
+    }
+    if metadata.len() > MAX_READ_SIZE {
+        return Err(too_large());
+    }
+    Ok(file)
+}
+
+/// Read the regular file at `path`, `path` including `mount`.
+pub(crate) fn read_to_string(mount: &Path, path: &Path) -> io::Result<String> {
+    // Files in procfs have a size of 0, the read itself is limited too
+    let mut content = String::new();
+    open(mount, path)?
+        .take(MAX_READ_SIZE + 1)
+        .read_to_string(&mut content)?;
+    if content.len() as u64 > MAX_READ_SIZE {
+        return Err(too_large());
+    }
+    Ok(content)
+}
+
+/// Metadata of the entry at `path` without following it if it is a
+/// symlink, `path` including `mount`.
+pub(crate) fn symlink_metadata(mount: &Path, path: &Path) -> io::Result<Metadata> {
+    open_beneath(mount, path, libc::O_PATH)?.metadata()
+}
+
+fn too_large() -> io::Error {
+    io::Error::new(
+        io::ErrorKind::FileTooLarge,
+        format!("larger than {MAX_READ_SIZE} bytes"),
+    )
+}
+
+fn escaped() -> io::Error {
+    io::Error::from_raw_os_error(libc::EXDEV)
+}
+
+fn open_beneath(mount: &Path, path: &Path, flags: c_int) -> io::Result<File> {
+    let Ok(relative) = path.strip_prefix(mount) else {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("not under {}", mount.display()),
+        ));
+    };
+    let flags = flags | libc::O_NOFOLLOW | libc::O_CLOEXEC;
+
+    if OPENAT2.load(Ordering::Relaxed) {
+        match openat2(mount, relative, flags) {
+            Err(e) if e.raw_os_error() == Some(libc::ENOSYS) => {
+                debug!("openat2 is not supported, checking paths under the host mount instead");
+                OPENAT2.store(false, Ordering::Relaxed);
+            }
+            res => return res,
+        }
+    }
+    open_checked(mount, relative, flags)
+}
+
+fn openat2(mount: &Path, relative: &Path, flags: c_int) -> io::Result<File> {
+    let dir = OpenOptions::new()
+        .read(true)
+        .custom_flags(libc::O_PATH | libc::O_DIRECTORY)
+        .open(mount)?;
+    let relative = match relative.as_os_str().is_empty() {
+        true => Path::new("."),
+        false => relative,
+    };
+    let relative = CString::new(relative.as_os_str().as_bytes())?;
+
+    let mut how: libc::open_how = unsafe { mem::zeroed() };
+    how.flags = flags as u64;
+    how.resolve = libc::RESOLVE_BENEATH | libc::RESOLVE_NO_MAGICLINKS;
+    let fd = unsafe {
+        libc::syscall(
+            libc::SYS_openat2,
+            dir.as_raw_fd(),
+            relative.as_ptr(),
+            &how as *const libc::open_how,
+            mem::size_of::<libc::open_how>(),
+        )
+    };
+    if fd < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(unsafe { File::from_raw_fd(fd as c_int) })
+}
+
+/// Open `relative` under `mount` after checking the directory it is in
+/// resolves to somewhere under the mount.
+fn open_checked(mount: &Path, relative: &Path, flags: c_int) -> io::Result<File> {
+    let mount = mount.canonicalize()?;
+    let path = match (relative.parent(), relative.file_name()) {
+        (Some(parent), Some(name)) => check_beneath(&mount, mount.join(parent))?.join(name),
+        // The mount itself, or a path ending in `..`
+        _ => check_beneath(&mount, mount.join(relative))?,
+    };
+    OpenOptions::new().read(true).custom_flags(flags).open(path)
+}
+
+fn check_beneath(mount: &Path, path: PathBuf) -> io::Result<PathBuf> {
+    let path = path.canonicalize()?;
+    if !path.starts_with(mount) {
+        return Err(escaped());
+    }
+    Ok(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, os::unix::fs::symlink};
+
+    use super::*;
+
+    type OpenFn = fn(&Path, &Path, c_int) -> io::Result<File>;
+
+    /// Ways of opening files beneath the mount, openat2 is left out on
+    /// kernels without it.
+    fn openers() -> Vec<(&'static str, OpenFn)> {
+        let mut openers: Vec<(&str, OpenFn)> = vec![("checked", open_checked)];
+        match openat2(Path::new("/"), Path::new(""), libc::O_PATH) {
+            Err(e) if e.raw_os_error() == Some(libc::ENOSYS) => {}
+            _ => openers.push(("openat2", openat2)),
+        }
+        openers
+    }
+
+    /// A host mount with symlinks pointing out of it, and a directory
+    /// next to it standing for the rest of the filesystem.
+    fn hostile_tree(root: &Path) -> PathBuf {
+        let mount = root.join("host");
+        let outside = root.join("outside");
+        fs::create_dir_all(mount.join("etc")).unwrap();
+        fs::create_dir_all(mount.join("usr/lib")).unwrap();
+        fs::create_dir(&outside).unwrap();
+        fs::write(outside.join("secret"), "secret").unwrap();
+        fs::write(mount.join("etc/passwd"), "root:x:0:0").unwrap();
+        fs::write(mount.join("usr/lib/os-release"), "ID=fact").unwrap();
+
+        symlink(&outside, mount.join("etc/absolute")).unwrap();
+        symlink("../../outside", mount.join("etc/relative")).unwrap();
+        symlink(outside.join("secret"), mount.join("etc/shadow")).unwrap();
+        symlink("../usr/lib/os-release", mount.join("etc/os-release")).unwrap();
+        symlink("../usr", mount.join("etc/usr")).unwrap();
+        mount
+    }
+
+    #[test]
+    fn symlinks() {
+        let root = tempfile::tempdir().unwrap();
+        let mount = hostile_tree(root.path());
+
+        let tests = [
+            ("etc/passwd", None, "Regular file"),
+            ("usr/lib/os-release", None, "Nested file"),
+            (
+                "etc/usr/lib/os-release",
+                None,
+                "Directory symlink in the mount",
+            ),
+            ("etc/absolute/secret", Some(libc::EXDEV), "Absolute symlink"),
+            ("etc/relative/secret", Some(libc::EXDEV), "Relative symlink"),
+            ("etc/../../outside/secret", Some(libc::EXDEV), "Dot dot"),
+            ("etc/shadow", Some(libc::ELOOP), "Last component symlink"),
+            ("etc/os-release", Some(libc::ELOOP), "Symlink in the mount"),
+        ];
+        for (opener, open_fn) in openers() {
+            for (path, expected, description) in tests {
+                let res = open_fn(&mount, Path::new(path), libc::O_RDONLY | libc::O_NOFOLLOW);
+                assert_eq!(
+                    res.err().and_then(|e| e.raw_os_error()),
+                    expected,
+                    "Failed for {description} with {opener}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn read() {
+        let root = tempfile::tempdir().unwrap();
+        let mount = hostile_tree(root.path());
+
+        let content = read_to_string(&mount, &mount.join("etc/passwd")).unwrap();
+        assert_eq!(content, "root:x:0:0");
+        let err = read_to_string(&mount, &mount.join("etc/shadow")).unwrap_err();
+        assert_eq!(err.raw_os_error(), Some(libc::ELOOP));
+        let err = read_to_string(&mount, &root.path().join("outside/secret")).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+
+        let err = read_to_string(&mount, &mount.join("etc")).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+
+        // Opening a FIFO must not wait for a writer
+        let fifo = mount.join("etc/fifo");
+        let c_fifo = CString::new(fifo.as_os_str().as_bytes()).unwrap();
+        assert_eq!(unsafe { libc::mkfifo(c_fifo.as_ptr(), 0o600) }, 0);
+        let err = read_to_string(&mount, &fifo).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+
+        let large = mount.join("etc/large");
+        File::create(&large)
+            .unwrap()
+            .set_len(MAX_READ_SIZE + 1)
+            .unwrap();
+        let err = read_to_string(&mount, &large).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::FileTooLarge);
+    }
+
+    #[test]
+    fn metadata() {
+        let root = tempfile::tempdir().unwrap();
+        let mount = hostile_tree(root.path());
+
+        let link = symlink_metadata(&mount, &mount.join("etc/shadow")).unwrap();
+        assert!(link.file_type().is_symlink());
+        let dir = symlink_metadata(&mount, &mount).unwrap();
+        assert!(dir.is_dir());
+        let file = symlink_metadata(&mount, &mount.join("etc/usr/lib/os-release")).unwrap();
+        assert!(file.is_file());
+
+        let err = symlink_metadata(&mount, &mount.join("etc/absolute/secret")).unwrap_err();
+        assert_eq!(err.raw_os_error(), Some(libc::EXDEV));
+        let err = symlink_metadata(&mount, &mount.join("etc/missing")).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::NotFound);
+    }
+}
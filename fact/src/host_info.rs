@@ -4,11 +4,11 @@ use std::{
     collections::HashMap,
     env,
     ffi::{CStr, CString, c_char},
-    fs::{File, read_to_string},
-    io::{BufRead, BufReader},
+    fs::read_to_string,
+    io::{self, BufRead, BufReader},
     mem,
     path::{Path, PathBuf},
-    sync::LazyLock,
+    sync::{LazyLock, OnceLock},
 };
 
 use libc::{
@@ -16,19 +16,52 @@ use libc::{
     clockid_t, statx, timespec, uname,
 };
 
+use crate::host_fs;
+
+static HOST_MOUNT: OnceLock<PathBuf> = OnceLock::new();
+
+/// Set where the filesystem of the host is mounted, from the
+/// configuration.
+///
+/// Must be called before anything reads from the host, the mount can't
+/// change once it has been used.
+pub fn init_host_mount(host_mount: &Path) -> anyhow::Result<()> {
+    check_host_mount(host_mount)?;
+    if HOST_MOUNT.set(host_mount.to_owned()).is_err() && get_host_mount() != host_mount {
+        bail!(
+            "host mount is already set to {}",
+            get_host_mount().display()
+        );
+    }
+    Ok(())
+}
+
+fn check_host_mount(host_mount: &Path) -> anyhow::Result<()> {
+    match host_mount.metadata() {
+        Ok(metadata) if metadata.is_dir() => Ok(()),
+        Ok(_) => bail!("host mount {} is not a directory", host_mount.display()),
+        Err(e) => bail!("invalid host mount {}: {e}", host_mount.display()),
+    }
+}
+
+/// Where the filesystem of the host is mounted, `FACT_HOST_MOUNT` or
+/// the root if `init_host_mount` was not called.
 pub fn get_host_mount() -> &'static PathBuf {
-    static HOST_MOUNT: LazyLock<PathBuf> =
-        LazyLock::new(|| env::var("FACT_HOST_MOUNT").unwrap_or("/".into()).into());
-    &HOST_MOUNT
+    HOST_MOUNT.get_or_init(|| env::var("FACT_HOST_MOUNT").unwrap_or("/".into()).into())
 }
 
-pub fn prepend_host_mount(path: &Path) -> PathBuf {
+/// Turn the host path `path` into the path it has under `mount`.
+pub fn prepend_mount(mount: &Path, path: &Path) -> PathBuf {
     let path = if path.has_root() {
         path.strip_prefix(Path::new("/")).unwrap()
     } else {
         path
     };
-    get_host_mount().join(path)
+    mount.join(path)
+}
+
+pub fn prepend_host_mount(path: &Path) -> PathBuf {
+    prepend_mount(get_host_mount(), path)
 }
 
 pub fn remove_host_mount(path: &Path) -> PathBuf {
@@ -66,11 +99,10 @@ pub fn get_hostname() -> &'static str {
         let hostname_paths = ["etc/hostname", "proc/sys/kernel/hostname"];
         for p in hostname_paths {
             let p = get_host_mount().join(p);
-            if p.exists() {
-                match read_to_string(&p) {
-                    Ok(hostname) => return hostname.trim().to_owned(),
-                    Err(e) => warn!("Failed to read {}: {e}", p.display()),
-                }
+            match host_fs::read_to_string(get_host_mount(), &p) {
+                Ok(hostname) => return hostname.trim().to_owned(),
+                Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+                Err(e) => warn!("Failed to read {}: {e}", p.display()),
             }
         }
         String::new()
@@ -79,26 +111,79 @@ pub fn get_hostname() -> &'static str {
     &HOSTNAME
 }
 
-pub fn get_username(uid: u32) -> &'static str {
-    static USER_MAP: LazyLock<HashMap<u32, String>> = LazyLock::new(|| {
-        let passwd_file = get_host_mount().join("etc/passwd");
-        let passwd = read_to_string(passwd_file).unwrap_or_default();
-        passwd
-            .lines()
-            .map(|line| {
-                let mut parts = line.split(":");
-                let name = parts.next().unwrap_or_default().to_owned();
-                let uid = parts.nth(1).unwrap_or_default();
-                let uid = uid.parse::<u32>().unwrap_or_default();
-
-                (uid, name)
-            })
-            .collect()
+/// ID of the current boot of the host, empty if it can't be read.
+pub fn get_boot_id() -> &'static str {
+    static BOOT_ID: LazyLock<String> = LazyLock::new(|| {
+        // The boot ID is the same in every namespace, the proc of fact
+        // is used when the one of the host is not mounted.
+        let host_path = get_host_mount().join("proc/sys/kernel/random/boot_id");
+        let (path, id) = match host_path.exists() {
+            true => (
+                host_path.as_path(),
+                host_fs::read_to_string(get_host_mount(), &host_path),
+            ),
+            false => {
+                let path = Path::new("/proc/sys/kernel/random/boot_id");
+                (path, read_to_string(path))
+            }
+        };
+        match id {
+            Ok(id) => id.trim().to_owned(),
+            Err(e) => {
+                warn!("Failed to read the boot ID from {}: {e}", path.display());
+                String::new()
+            }
+        }
     });
-    match USER_MAP.get(&uid) {
-        Some(u) => u.as_str(),
-        None => "",
+    &BOOT_ID
+}
+
+/// Name of the user with `uid` on the host, empty if unknown.
+pub fn get_username(uid: u32) -> &'static str {
+    static USERS: LazyLock<HashMap<u32, String>> = LazyLock::new(|| read_id_names("etc/passwd"));
+    USERS.get(&uid).map_or("", String::as_str)
+}
+
+/// Name of the group with `gid` on the host, empty if unknown.
+pub fn get_groupname(gid: u32) -> &'static str {
+    static GROUPS: LazyLock<HashMap<u32, String>> = LazyLock::new(|| read_id_names("etc/group"));
+    GROUPS.get(&gid).map_or("", String::as_str)
+}
+
+/// Read the names by ID in `file` under the host mount, read once and
+/// kept for the lifetime of fact.
+fn read_id_names(file: &str) -> HashMap<u32, String> {
+    let path = get_host_mount().join(file);
+    match host_fs::read_to_string(get_host_mount(), &path) {
+        Ok(content) => parse_id_names(&content),
+        Err(e) => {
+            warn!("Failed to read {}: {e}", path.display());
+            HashMap::new()
+        }
+    }
+}
+
+/// Parse the names by ID in the content of passwd(5) or group(5), both
+/// have the name first and the ID third.
+///
+/// Lines without a name or a valid ID are skipped, the first name of an
+/// ID is kept, like getpwuid(3) does.
+fn parse_id_names(content: &str) -> HashMap<u32, String> {
+    let mut names = HashMap::new();
+    for line in content.lines() {
+        let mut parts = line.split(':');
+        let (Some(name), Some(id)) = (parts.next(), parts.nth(1)) else {
+            continue;
+        };
+        if name.is_empty() || name.starts_with('#') {
+            continue;
+        }
+        let Ok(id) = id.parse::<u32>() else {
+            continue;
+        };
+        names.entry(id).or_insert_with(|| name.to_owned());
     }
+    names
 }
 
 /// get_mount_ns
@@ -148,6 +233,23 @@ pub fn get_host_mount_ns() -> u64 {
     get_mount_ns("1", true)
 }
 
+/// Get the PID of the running process in the host PID namespace.
+///
+/// When running in a container, the PID namespace is usually not the
+/// one from the host. The host procfs resolves its `self` link to the
+/// PID of the process reading it as seen from the host, falling back
+/// to our own PID if that procfs is not available.
+pub fn get_host_pid() -> u32 {
+    let path = get_host_mount().join("proc/self");
+    match path.read_link().map(|pid| pid.to_str().map(str::parse)) {
+        Ok(Some(Ok(pid))) => pid,
+        _ => {
+            warn!("Failed to read host PID from {}", path.display());
+            std::process::id()
+        }
+    }
+}
+
 /// Get the pretty printed OS distribution name
 ///
 /// This value is retrieved from the os-release file on the running
@@ -161,9 +263,14 @@ pub fn get_distro() -> String {
     let paths = ["etc/os-release", "usr/lib/os-release"];
     for p in paths {
         let p = get_host_mount().join(p);
-        let Ok(file) = File::open(&p) else {
-            debug!("Failed to open {}", p.display());
-            continue;
+        // os-release is usually a symlink to usr/lib/os-release, which
+        // is not followed but read next
+        let file = match host_fs::open(get_host_mount(), &p) {
+            Ok(file) => file,
+            Err(e) => {
+                debug!("Failed to open {}: {e}", p.display());
+                continue;
+            }
         };
         for line in BufReader::new(file).lines() {
             let line = match line {
@@ -212,3 +319,90 @@ impl SystemInfo {
         Ok(SystemInfo { kernel, arch })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn host_mount_checked() {
+        let dir = tempfile::tempdir().unwrap();
+        check_host_mount(dir.path()).unwrap();
+
+        let missing = dir.path().join("missing");
+        let err = check_host_mount(&missing).unwrap_err().to_string();
+        assert!(
+            err.starts_with(&format!("invalid host mount {}: ", missing.display())),
+            "{err}"
+        );
+
+        let file = dir.path().join("file");
+        std::fs::write(&file, "").unwrap();
+        assert_eq!(
+            check_host_mount(&file).unwrap_err().to_string(),
+            format!("host mount {} is not a directory", file.display())
+        );
+    }
+
+    #[test]
+    fn passwd() {
+        let names = parse_id_names(
+            "root:x:0:0:root:/root:/bin/bash\n\
+             daemon:x:1:1:daemon:/usr/sbin:/usr/sbin/nologin\n\
+             \n\
+             # comment:x:2:2\n\
+             truncated:x\n\
+             baduid:x:abc:3::/:/bin/sh\n\
+             negative:x:-1:3::/:/bin/sh\n\
+             :x:4:4::/:/bin/sh\n\
+             toor:x:0:0:second root:/root:/bin/sh\n\
+             nobody:x:65534:65534:nobody:/nonexistent:/usr/sbin/nologin",
+        );
+        assert_eq!(
+            names,
+            HashMap::from([
+                (0, "root".to_string()),
+                (1, "daemon".to_string()),
+                (65534, "nobody".to_string()),
+            ])
+        );
+    }
+
+    #[test]
+    fn group() {
+        let names = parse_id_names(
+            "root:x:0:\n\
+             wheel:x:10:alice,bob\n\
+             docker:x:999\n\
+             broken\n\
+             big:x:4294967296:\n\
+             users::100:",
+        );
+        assert_eq!(
+            names,
+            HashMap::from([
+                (0, "root".to_string()),
+                (10, "wheel".to_string()),
+                (999, "docker".to_string()),
+                (100, "users".to_string()),
+            ])
+        );
+    }
+
+    #[test]
+    fn prepend() {
+        let mount = Path::new("/host");
+        assert_eq!(
+            prepend_mount(mount, Path::new("/etc/passwd")),
+            Path::new("/host/etc/passwd")
+        );
+        assert_eq!(
+            prepend_mount(mount, Path::new("etc/passwd")),
+            Path::new("/host/etc/passwd")
+        );
+        assert_eq!(
+            prepend_mount(Path::new("/"), Path::new("/etc")),
+            Path::new("/etc")
+        );
+    }
+}
@@ -5,7 +5,7 @@
 //!
 //! An initial scan of the filesystem is triggered when a `HostScanner`
 //! object is first created. The scan will populate two maps:
-//! * A HashMap that holds an inode to path translation.
+//! * An `InodeMap` that holds an inode to path translation.
 //! * An eBPF HashMap that will let the eBPF programs know if a given
 //!   file is being monitored, regardless of the path being used to
 //!   access it.
@@ -42,14 +42,20 @@ use tokio::{
 
 use crate::{
     bpf::Bpf,
-    event::Event,
-    host_info,
-    metrics::host_scanner::{HostScannerMetrics, ScanLabels},
+    event::{Event, TmpFile},
+    fs_walker::{self, EntryKind},
+    host_fs, host_info,
+    inode_map::{InodeMap, Query, QueryHandle, Source},
+    metrics::{
+        DropReason,
+        host_scanner::{HostScannerMetrics, ScanLabels},
+    },
+    pacer::Pacer,
 };
 
 pub struct HostScanner {
     kernel_inode_map: RefCell<aya::maps::HashMap<MapData, inode_key_t, inode_value_t>>,
-    inode_map: RefCell<std::collections::HashMap<inode_key_t, PathBuf>>,
+    inode_map: RefCell<InodeMap>,
 
     paths: watch::Receiver<Vec<PathBuf>>,
     scan_interval: watch::Receiver<Duration>,
@@ -57,7 +63,11 @@ pub struct HostScanner {
     rx: mpsc::Receiver<Event>,
     tx: mpsc::Sender<Event>,
 
+    queries: mpsc::Receiver<Query>,
+    query_handle: QueryHandle,
+
     metrics: HostScannerMetrics,
+    pacer: RefCell<Pacer>,
 
     paths_globset: GlobSet,
 }
@@ -69,11 +79,13 @@ impl HostScanner {
         paths: watch::Receiver<Vec<PathBuf>>,
         scan_interval: watch::Receiver<Duration>,
         metrics: HostScannerMetrics,
+        pacer: Pacer,
     ) -> anyhow::Result<(Self, mpsc::Receiver<Event>)> {
         let kernel_inode_map = RefCell::new(bpf.take_inode_map()?);
-        let inode_map = RefCell::new(std::collections::HashMap::new());
-        let (tx, output) = mpsc::channel(100);
+        let inode_map = RefCell::new(InodeMap::new());
+        let (tx, output) = mpsc::channel(crate::EVENT_CHANNEL_CAPACITY);
         let paths_globset = HostScanner::build_globset(paths.borrow().as_slice())?;
+        let (query_handle, queries) = QueryHandle::new();
 
         let host_scanner = HostScanner {
             kernel_inode_map,
@@ -82,12 +94,15 @@ impl HostScanner {
             scan_interval,
             rx,
             tx,
+            queries,
+            query_handle,
             metrics,
+            pacer: RefCell::new(pacer),
             paths_globset,
         };
 
         // Run an initial scan to fill in the inode map
-        host_scanner.scan()?;
+        host_scanner.scan(Source::StartupScan)?;
 
         Ok((host_scanner, output))
     }
@@ -108,9 +123,16 @@ impl HostScanner {
         Ok(builder.build()?)
     }
 
-    fn scan(&self) -> anyhow::Result<()> {
+    /// Handle for looking up the inode map from other tasks, queries
+    /// are answered while the host scanner runs.
+    pub fn query_handle(&self) -> QueryHandle {
+        self.query_handle.clone()
+    }
+
+    fn scan(&self, source: Source) -> anyhow::Result<()> {
         debug!("Host scan started");
         self.metrics.scan_inc(ScanLabels::Scans);
+        self.pacer.borrow_mut().start();
         let config = self.paths.borrow();
 
         // Cleanup any items that are either:
@@ -118,8 +140,13 @@ impl HostScanner {
         // * Are configured to be monitored but no longer are found in
         //   the file system.
         self.inode_map.borrow_mut().retain(|inode, path| {
+            self.pacer.borrow_mut().tick();
             if config.iter().any(|prefix| path.starts_with(prefix))
-                && host_info::prepend_host_mount(path).exists()
+                && host_fs::symlink_metadata(
+                    host_info::get_host_mount(),
+                    &host_info::prepend_host_mount(path),
+                )
+                .is_ok()
             {
                 true
             } else {
@@ -130,60 +157,63 @@ impl HostScanner {
         });
 
         for pattern in self.paths.borrow().iter() {
-            let path = host_info::prepend_host_mount(pattern);
-            self.scan_inner(&path)?;
+            self.scan_inner(pattern, source)?;
         }
+        self.pacer.borrow_mut().pace();
         debug!("Host scan done");
 
         Ok(())
     }
 
-    fn scan_inner(&self, path: &Path) -> anyhow::Result<()> {
+    fn scan_inner(&self, pattern: &Path, source: Source) -> anyhow::Result<()> {
         self.metrics.scan_inc(ScanLabels::ElementsScanned);
 
-        let Some(glob_str) = path.to_str() else {
-            bail!("invalid path {}", path.display());
-        };
-
-        for entry in glob::glob(glob_str)? {
-            let path = entry?;
-            if path.is_file() {
-                self.metrics.scan_inc(ScanLabels::FileScanned);
-                self.update_entry(path.as_path())
-                    .with_context(|| format!("Failed to update entry for {}", path.display()))?;
-            } else if path.is_dir() {
-                self.metrics.scan_inc(ScanLabels::DirectoryScanned);
-                self.update_entry(path.as_path())
-                    .with_context(|| format!("Failed to update entry for {}", path.display()))?;
-            } else {
-                self.metrics.scan_inc(ScanLabels::FsItemIgnored);
+        for entry in fs_walker::walk(pattern)? {
+            self.pacer.borrow_mut().tick();
+            let (path, kind) = entry?;
+            match kind {
+                EntryKind::File => self.metrics.scan_inc(ScanLabels::FileScanned),
+                EntryKind::Directory => self.metrics.scan_inc(ScanLabels::DirectoryScanned),
+                EntryKind::Other => {
+                    self.metrics.scan_inc(ScanLabels::FsItemIgnored);
+                    continue;
+                }
             }
+            self.update_entry(path.as_path(), source)
+                .with_context(|| format!("Failed to update entry for {}", path.display()))?;
         }
         Ok(())
     }
 
-    fn update_entry(&self, path: &Path) -> anyhow::Result<()> {
-        if !path.exists() {
-            // If path does not exist, we don't have anything to update
-            self.metrics.scan_inc(ScanLabels::FileRemoved);
-            return Ok(());
-        }
-
-        let metadata = path.metadata()?;
+    fn update_entry(&self, path: &Path, source: Source) -> anyhow::Result<()> {
+        let metadata = match host_fs::symlink_metadata(host_info::get_host_mount(), path) {
+            Ok(metadata) => metadata,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {
+                // If path does not exist, we don't have anything to update
+                self.metrics.scan_inc(ScanLabels::FileRemoved);
+                return Ok(());
+            }
+            Err(e) => return Err(e.into()),
+        };
         let inode = inode_key_t {
             inode: metadata.st_ino(),
             dev: metadata.st_dev(),
         };
 
         let host_path = host_info::remove_host_mount(path);
-        self.update_entry_with_inode(inode, host_path)?;
+        self.update_entry_with_inode(inode, host_path, source)?;
 
         debug!("Added entry for {}: {inode:?}", path.display());
         Ok(())
     }
 
     /// Similar to update_entry except we are are directly using the inode instead of the path.
-    fn update_entry_with_inode(&self, inode: inode_key_t, path: PathBuf) -> anyhow::Result<()> {
+    fn update_entry_with_inode(
+        &self,
+        inode: inode_key_t,
+        path: PathBuf,
+        source: Source,
+    ) -> anyhow::Result<()> {
         match self.kernel_inode_map.borrow_mut().insert(inode, 0, 0) {
             Ok(_) => {}
             Err(MapError::SyscallError(SyscallError { io_error, .. }))
@@ -204,9 +234,7 @@ You can increase this limit with:
             }
         }
 
-        let mut inode_map = self.inode_map.borrow_mut();
-        let entry = inode_map.entry(inode).or_default();
-        *entry = path;
+        self.inode_map.borrow_mut().insert(inode, &path, source);
 
         self.metrics.scan_inc(ScanLabels::FileUpdated);
 
@@ -216,7 +244,15 @@ You can increase this limit with:
     fn get_host_path(&self, inode: Option<&inode_key_t>) -> Option<PathBuf> {
         // The path here needs to be cloned because we won't keep the
         // inode_map borrow long enough.
-        self.inode_map.borrow().get(inode?).cloned()
+        self.inode_map.borrow().get(inode?)
+    }
+
+    fn update_map_metrics(&self) {
+        let inode_map = self.inode_map.borrow();
+        self.metrics.inode_map_entries.set(inode_map.len() as i64);
+        self.metrics
+            .inode_map_bytes
+            .set(inode_map.memory_usage() as i64);
     }
 
     /// Handle file creation events by adding new inodes to the map.
@@ -224,10 +260,10 @@ You can increase this limit with:
     /// We use the parent inode provided by the eBPF code
     /// to look up the parent directory's host path, then construct the full
     /// path by appending the new file's name.
-    fn handle_creation_event(&self, event: &Event) -> anyhow::Result<()> {
+    fn handle_creation_event(&self, event: &mut Event) -> anyhow::Result<()> {
         let inode = event.get_inode();
         let parent_inode = event.get_parent_inode();
-        if self.get_host_path(Some(inode)).is_some() || parent_inode.empty() {
+        if self.inode_map.borrow().contains_key(inode) || parent_inode.empty() {
             return Ok(());
         }
 
@@ -235,7 +271,13 @@ You can increase this limit with:
             && let Some(parent_host_path) = self.get_host_path(Some(parent_inode))
         {
             let host_path = parent_host_path.join(filename);
-            self.update_entry_with_inode(*inode, host_path)
+            // Files opened with O_TMPFILE are only tracked once linked,
+            // their temporary name is gone by then.
+            if event.tmpfile() == Some(TmpFile::Unnamed) {
+                event.set_host_path(host_path);
+                return Ok(());
+            }
+            self.update_entry_with_inode(*inode, host_path, Source::Event)
                 .with_context(|| {
                     format!(
                         "Failed to add creation event entry for {}",
@@ -275,7 +317,7 @@ You can increase this limit with:
                 let Some(old_inode) = event.get_old_inode() else {
                     unreachable!("old inode not found for rename event");
                 };
-                inode_map.insert(*old_inode, path);
+                inode_map.insert(*old_inode, &path, Source::Event);
             }
             monitored_t::NOT_MONITORED
                 if event.get_old_monitored() == Some(monitored_t::MONITORED_BY_INODE) =>
@@ -286,14 +328,11 @@ You can increase this limit with:
                     warn!("Rename event did not have old host path for inode tracked item");
                     return;
                 };
-                self.inode_map.borrow_mut().retain(|inode, path| {
-                    if !path.starts_with(old_host_path) {
-                        return true;
-                    }
-
-                    let _ = self.kernel_inode_map.borrow_mut().remove(inode);
-                    false
-                });
+                self.inode_map
+                    .borrow_mut()
+                    .remove_prefix(old_host_path, |inode| {
+                        let _ = self.kernel_inode_map.borrow_mut().remove(inode);
+                    });
             }
             monitored_t::NOT_MONITORED => {
                 // The new path is not monitored and the old path is most likely
@@ -302,11 +341,23 @@ You can increase this limit with:
             monitored_t::MONITORED_BY_PARENT if !event.get_inode().empty() => {
                 // The parent for the target is monitored, but the file itself
                 // is not. Remove the entry for the old file from the map.
-                self.inode_map.borrow_mut().remove(
+                let mut inode_map = self.inode_map.borrow_mut();
+                inode_map.remove(
                     event
                         .get_old_inode()
                         .expect("rename event did not have old inode"),
                 );
+
+                // The replaced file was not tracked either, its host path
+                // comes from the parent.
+                if event.overwrote_existing()
+                    && event.get_host_path().as_os_str().is_empty()
+                    && let Some(parent_host_path) = inode_map.get(event.get_parent_inode())
+                    && let Some(filename) = event.get_filename().file_name()
+                {
+                    let host_path = parent_host_path.join(filename);
+                    event.set_host_path(host_path);
+                }
             }
             monitored_t::MONITORED_BY_PARENT
                 if event.get_old_monitored() == Some(monitored_t::MONITORED_BY_INODE) =>
@@ -331,28 +382,19 @@ You can increase this limit with:
                 if self.paths_globset.is_match(&new_host_path) {
                     // New path needs to be tracked.
                     // Move all entries for the old host path to the new one
-                    for path in inode_map.values_mut() {
-                        if let Ok(suffix) = path.strip_prefix(old_host_path) {
-                            if suffix == Path::new("") {
-                                *path = new_host_path.clone();
-                            } else {
-                                *path = new_host_path.join(suffix);
-                            }
-                        }
-                    }
+                    let old_inode = event
+                        .get_old_inode()
+                        .expect("rename event did not have old inode");
+                    inode_map.rename(old_inode, old_host_path, &new_host_path);
 
                     // Add the new host path to the event
                     event.set_host_path(new_host_path);
                 } else {
                     // New path is not tracked, remove old entries
-                    inode_map.retain(|inode, path| {
-                        if !path.starts_with(old_host_path) {
-                            return true;
-                        }
+                    inode_map.remove_prefix(old_host_path, |inode| {
                         if let Err(e) = self.kernel_inode_map.borrow_mut().remove(inode) {
                             warn!("Failed to remove inode kernel entry: {e:?}");
                         }
-                        false
                     });
                 }
             }
@@ -361,7 +403,7 @@ You can increase this limit with:
                 // don't have any information of the host path for the old path,
                 // best we can do is attempt to scan the file system and fix the
                 // inode maps that way.
-                if let Err(e) = self.scan() {
+                if let Err(e) = self.scan(Source::Rescan) {
                     warn!("Scan failed: {e:?}");
                 }
 
@@ -369,7 +411,7 @@ You can increase this limit with:
                 if let Some(old_inode) = event.get_old_inode()
                     && let Some(path) = self.inode_map.borrow().get(old_inode)
                 {
-                    event.set_host_path(path.clone());
+                    event.set_host_path(path);
                 }
             }
             monitored_t::MONITORED_BY_PATH => {
@@ -420,6 +462,9 @@ You can increase this limit with:
             info!("Starting host scanner...");
 
             loop {
+                // Changes to the map are exported while waiting for
+                // the next event or scan.
+                self.update_map_metrics();
                 tokio::select! {
                     event = self.rx.recv() => {
                         let Some(mut event) = event else {
@@ -430,7 +475,7 @@ You can increase this limit with:
 
                         // Handle file and directory creation events by adding new inodes to the map
                         if event.is_creation() &&
-                            let Err(e) = self.handle_creation_event(&event) {
+                            let Err(e) = self.handle_creation_event(&mut event) {
                                 warn!("Failed to handle creation event: {e}");
                             }
 
@@ -444,8 +489,9 @@ You can increase this limit with:
                             event.set_old_host_path(host_path);
                         }
 
-                        // Remove inode from the map
-                        if event.is_deletion() {
+                        // Remove inode from the map, blocked unlinks
+                        // leave the file in place.
+                        if event.is_deletion() && !event.is_blocked() {
                             self.handle_unlink_event(&event);
                         }
 
@@ -469,14 +515,22 @@ You can increase this limit with:
                         }
 
                         if let Err(e) = self.tx.send(event).await {
-                            self.metrics.events.dropped();
+                            self.metrics.events.dropped(DropReason::StreamClosed);
                             warn!("Failed to send event: {e}");
                         }
                     },
-                    _ = scan_trigger.notified() => self.scan()?,
+                    _ = scan_trigger.notified() => self.scan(Source::Rescan)?,
+                    Some(query) = self.queries.recv() => {
+                        let kernel_inode_map = self.kernel_inode_map.borrow();
+                        query.answer(
+                            &self.inode_map.borrow(),
+                            |inode| kernel_inode_map.get(inode, 0).is_ok(),
+                            &self.paths.borrow(),
+                        );
+                    }
                     _ = self.paths.changed() => {
                             self.paths_globset = HostScanner::build_globset(self.paths.borrow().as_slice())?;
-                            self.scan()?;
+                            self.scan(Source::Rescan)?;
                         }
                 }
             }
@@ -0,0 +1,817 @@
+//! Host paths of the inodes tracked by the host scanner.
+//!
+//! Monitoring a container image store means tracking millions of files
+//! under a few deep directories, storing the full path of every file
+//! repeats those directories over and over. Instead, directories are
+//! interned once with a link to their parent and entries only hold the
+//! ID of their directory and their own name, full paths are put back
+//! together on lookup. That makes lookups a few times slower, in the
+//! order of 100ns for deep trees, for less than half the memory.
+//!
+//! Paths are split on their raw bytes rather than their components, so
+//! they come back exactly as inserted, repeated or trailing separators
+//! included.
+//!
+//! Renaming a directory re-parents its interned node, the entries under
+//! it resolve through it and are not touched.
+//!
+//! Entries remember when and how they were added, the map can be
+//! queried through a `QueryHandle` for `/debug/inode`.
+
+use std::{
+    collections::BTreeMap,
+    ffi::{OsStr, OsString},
+    mem::size_of,
+    os::unix::ffi::{OsStrExt, OsStringExt},
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use anyhow::Context;
+use fact_ebpf::inode_key_t;
+use rustc_hash::FxHashMap;
+use serde::Serialize;
+use tokio::sync::{mpsc, oneshot};
+
+use crate::trace;
+
+type DirId = u32;
+
+/// Names of the children of a directory, or of the directories without
+/// a parent.
+type Children = FxHashMap<Arc<[u8]>, DirId>;
+
+/// Bytes taken by a slot of a hash table, including its control byte.
+const fn slot_size<K, V>() -> usize {
+    size_of::<(K, V)>() + 1
+}
+
+/// Heap bytes of an `Arc<[u8]>` other than the bytes it holds, the
+/// strong and weak counts.
+const ARC_HEADER: usize = 2 * size_of::<usize>();
+
+#[derive(Debug, Default)]
+struct Dir {
+    parent: Option<DirId>,
+    name: Arc<[u8]>,
+    children: Children,
+    /// Entries and child directories in this directory, the directory
+    /// is removed when none are left.
+    refs: u32,
+}
+
+/// How an entry was added to the map.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Source {
+    /// The scan of the monitored paths when fact starts.
+    StartupScan,
+    /// An event creating or moving the file.
+    Event,
+    /// A later scan, periodic or after a configuration change.
+    Rescan,
+}
+
+#[derive(Debug)]
+struct Entry {
+    dir: Option<DirId>,
+    /// Unix time the entry was added at.
+    added: u32,
+    source: Source,
+    name: Box<[u8]>,
+}
+
+/// Map of inodes to host paths, sharing the directories they are in.
+#[derive(Debug, Default)]
+pub struct InodeMap {
+    entries: FxHashMap<inode_key_t, Entry>,
+    dirs: Vec<Dir>,
+    /// Slots in `dirs` of removed directories, reused first.
+    free: Vec<DirId>,
+    roots: Children,
+    /// Bytes of the names of entries.
+    entry_names: usize,
+    /// Bytes of the interned directories, names and slot in the
+    /// children of their parent included.
+    dir_bytes: usize,
+}
+
+/// Split `path` on its last separator, `None` if it has none.
+fn split(path: &[u8]) -> Option<(&[u8], &[u8])> {
+    let pos = path.iter().rposition(|b| *b == b'/')?;
+    Some((&path[..pos], &path[pos + 1..]))
+}
+
+/// A path as found in the map, for matching entries at or under it
+/// without putting their paths back together.
+struct Prefix<'a> {
+    /// Directory the path is in.
+    parent: Option<DirId>,
+    name: &'a [u8],
+    /// The path itself, if entries are under it.
+    dir: Option<DirId>,
+}
+
+impl Prefix<'_> {
+    fn matches(&self, dirs: &[Dir], entry: &Entry) -> bool {
+        if entry.dir == self.parent && *entry.name == *self.name {
+            return true;
+        }
+        self.dir.is_some_and(|dir| is_under(dirs, entry, dir))
+    }
+}
+
+/// Whether `entry` is in directory `id` or in one under it.
+fn is_under(dirs: &[Dir], entry: &Entry, id: DirId) -> bool {
+    std::iter::successors(entry.dir, |dir| dirs[*dir as usize].parent).any(|dir| dir == id)
+}
+
+impl InodeMap {
+    pub fn new() -> Self {
+        InodeMap::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Number of distinct directories the paths are in.
+    pub fn dirs(&self) -> usize {
+        self.dirs.len() - self.free.len()
+    }
+
+    pub fn contains_key(&self, inode: &inode_key_t) -> bool {
+        self.entries.contains_key(inode)
+    }
+
+    /// Estimate of the heap bytes used by the map.
+    pub fn memory_usage(&self) -> usize {
+        self.entries.capacity() * slot_size::<inode_key_t, Entry>()
+            + self.entry_names
+            + self.dirs.capacity() * size_of::<Dir>()
+            + self.dir_bytes
+    }
+
+    /// Map `inode` to `path`, replacing its previous path.
+    pub fn insert(&mut self, inode: inode_key_t, path: &Path, source: Source) {
+        let (dir, name) = match split(path.as_os_str().as_bytes()) {
+            Some((dir, name)) => (Some(self.intern(dir)), name),
+            None => (None, path.as_os_str().as_bytes()),
+        };
+        self.entry_names += name.len();
+        let added = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        let entry = Entry {
+            dir,
+            added: added as u32,
+            source,
+            name: name.into(),
+        };
+        if let Some(old) = self.entries.insert(inode, entry) {
+            self.forget(old);
+        }
+    }
+
+    pub fn get(&self, inode: &inode_key_t) -> Option<PathBuf> {
+        let _span = trace::stage!("host_path");
+        self.entries.get(inode).map(|entry| self.path(entry))
+    }
+
+    /// The path of `inode` along with how it was added.
+    pub fn entry(&self, inode: &inode_key_t) -> Option<EntryInfo> {
+        self.entries.get(inode).map(|entry| EntryInfo {
+            path: self.path(entry).to_string_lossy().into_owned(),
+            added: entry.added.into(),
+            source: entry.source,
+        })
+    }
+
+    pub fn paths(&self) -> impl Iterator<Item = PathBuf> {
+        self.entries.values().map(|entry| self.path(entry))
+    }
+
+    pub fn remove(&mut self, inode: &inode_key_t) -> Option<PathBuf> {
+        let entry = self.entries.remove(inode)?;
+        let path = self.path(&entry);
+        self.forget(entry);
+        Some(path)
+    }
+
+    /// Keep only the entries `f` returns true for.
+    pub fn retain(&mut self, mut f: impl FnMut(&inode_key_t, &Path) -> bool) {
+        self.retain_entries(|dirs, inode, entry| f(inode, &path(dirs, entry)));
+    }
+
+    /// Remove the entries at or under `path`, calling `f` with each
+    /// removed inode.
+    pub fn remove_prefix(&mut self, path: &Path, mut f: impl FnMut(&inode_key_t)) {
+        let Some(prefix) = self.prefix(path.as_os_str().as_bytes()) else {
+            return;
+        };
+        self.retain_entries(|dirs, inode, entry| {
+            if !prefix.matches(dirs, entry) {
+                return true;
+            }
+            f(inode);
+            false
+        });
+    }
+
+    /// Move `inode` from `from` to `to` along with the entries under it,
+    /// like a rename of `from` does. `inode` counts as added by the
+    /// rename, the entries under it keep when and how they were added.
+    pub fn rename(&mut self, inode: &inode_key_t, from: &Path, to: &Path) {
+        let (from, to) = (from.as_os_str().as_bytes(), to.as_os_str().as_bytes());
+        if from == to {
+            return;
+        }
+        if let Some(id) = self.lookup(from)
+            && !self.move_dir(id, to)
+        {
+            self.move_entries(id, from, to);
+        }
+        if self.entries.contains_key(inode) {
+            self.insert(*inode, Path::new(OsStr::from_bytes(to)), Source::Event);
+        }
+    }
+
+    fn retain_entries(&mut self, mut f: impl FnMut(&[Dir], &inode_key_t, &Entry) -> bool) {
+        let mut removed = Vec::new();
+        let InodeMap { entries, dirs, .. } = self;
+        entries.retain(|inode, entry| {
+            let keep = f(dirs, inode, entry);
+            if !keep {
+                removed.push(Entry {
+                    name: std::mem::take(&mut entry.name),
+                    ..*entry
+                });
+            }
+            keep
+        });
+        for entry in removed {
+            self.forget(entry);
+        }
+    }
+
+    fn path(&self, entry: &Entry) -> PathBuf {
+        path(&self.dirs, entry)
+    }
+
+    fn children(&self, parent: Option<DirId>) -> &Children {
+        match parent {
+            Some(parent) => &self.dirs[parent as usize].children,
+            None => &self.roots,
+        }
+    }
+
+    fn children_mut(&mut self, parent: Option<DirId>) -> &mut Children {
+        match parent {
+            Some(parent) => &mut self.dirs[parent as usize].children,
+            None => &mut self.roots,
+        }
+    }
+
+    /// Get the ID of directory `path`, if it is interned.
+    fn lookup(&self, path: &[u8]) -> Option<DirId> {
+        let (parent, name) = match split(path) {
+            Some((parent, name)) => (Some(self.lookup(parent)?), name),
+            None => (None, path),
+        };
+        self.children(parent).get(name).copied()
+    }
+
+    /// Find `path` in the map, `None` if no entry can be at or under it.
+    fn prefix<'a>(&self, path: &'a [u8]) -> Option<Prefix<'a>> {
+        let (parent, name) = match split(path) {
+            Some((parent, name)) => (Some(self.lookup(parent)?), name),
+            None => (None, path),
+        };
+        Some(Prefix {
+            parent,
+            name,
+            dir: self.children(parent).get(name).copied(),
+        })
+    }
+
+    /// Re-parent directory `id` to path `to`, the directories and entries
+    /// under it follow without being touched. Returns false if `to` is
+    /// already interned or under `id`, the directories would need to be
+    /// merged.
+    fn move_dir(&mut self, id: DirId, to: &[u8]) -> bool {
+        if self.lookup(to).is_some() {
+            return false;
+        }
+        let (parent, name) = match split(to) {
+            Some((parent, name)) => (Some(self.intern(parent)), name),
+            None => (None, to),
+        };
+        if let Some(parent) = parent
+            && std::iter::successors(Some(parent), |id| self.dirs[*id as usize].parent)
+                .any(|ancestor| ancestor == id)
+        {
+            self.release(parent);
+            return false;
+        }
+
+        // The reference taken on the new parent is the one of the moved
+        // directory, it drops the one on its old parent
+        let name = Arc::<[u8]>::from(name);
+        let dir = &mut self.dirs[id as usize];
+        let old_parent = std::mem::replace(&mut dir.parent, parent);
+        let old_name = std::mem::replace(&mut dir.name, name.clone());
+        self.dir_bytes = self.dir_bytes + name.len() - old_name.len();
+        self.children_mut(old_parent).remove(&old_name);
+        self.children_mut(parent).insert(name, id);
+        if let Some(old_parent) = old_parent {
+            self.release(old_parent);
+        }
+        true
+    }
+
+    /// Move the entries under directory `id`, at `from`, to `to` one by
+    /// one, for when the directory itself can't be moved.
+    fn move_entries(&mut self, id: DirId, from: &[u8], to: &[u8]) {
+        let moved = self
+            .entries
+            .iter()
+            .filter(|(_, entry)| is_under(&self.dirs, entry, id))
+            .map(|(inode, entry)| {
+                let path = self.path(entry).into_os_string().into_vec();
+                let mut moved = to.to_vec();
+                moved.extend_from_slice(&path[from.len()..]);
+                (*inode, PathBuf::from(OsString::from_vec(moved)))
+            })
+            .collect::<Vec<_>>();
+        for (inode, path) in moved {
+            self.insert(inode, &path, Source::Event);
+        }
+    }
+
+    /// Get the ID of directory `path`, adding it if needed, and take a
+    /// reference on it.
+    fn intern(&mut self, path: &[u8]) -> DirId {
+        let (parent, name) = match split(path) {
+            Some((parent, name)) => (Some(self.intern(parent)), name),
+            None => (None, path),
+        };
+        if let Some(id) = self.children(parent).get(name).copied() {
+            // The directory already holds a reference on its parent
+            if let Some(parent) = parent {
+                self.dirs[parent as usize].refs -= 1;
+            }
+            self.dirs[id as usize].refs += 1;
+            return id;
+        }
+
+        let name = Arc::<[u8]>::from(name);
+        let dir = Dir {
+            parent,
+            name: name.clone(),
+            children: Children::default(),
+            refs: 1,
+        };
+        let id = match self.free.pop() {
+            Some(id) => {
+                self.dirs[id as usize] = dir;
+                id
+            }
+            None => {
+                self.dirs.push(dir);
+                (self.dirs.len() - 1) as DirId
+            }
+        };
+        self.dir_bytes += name.len() + ARC_HEADER + slot_size::<Arc<[u8]>, DirId>();
+        self.children_mut(parent).insert(name, id);
+        id
+    }
+
+    /// Drop the references held by a removed entry.
+    fn forget(&mut self, entry: Entry) {
+        self.entry_names -= entry.name.len();
+        if let Some(dir) = entry.dir {
+            self.release(dir);
+        }
+    }
+
+    /// Drop a reference on directory `id`, removing it when it was the
+    /// last one.
+    fn release(&mut self, mut id: DirId) {
+        loop {
+            let dir = &mut self.dirs[id as usize];
+            dir.refs -= 1;
+            if dir.refs > 0 {
+                return;
+            }
+
+            let Dir { parent, name, .. } = std::mem::take(dir);
+            self.dir_bytes -= name.len() + ARC_HEADER + slot_size::<Arc<[u8]>, DirId>();
+            self.free.push(id);
+            self.children_mut(parent).remove(&name);
+            match parent {
+                Some(parent) => id = parent,
+                None => return,
+            }
+        }
+    }
+}
+
+/// An entry of the map, as reported by `/debug/inode`.
+#[derive(Debug, PartialEq, Serialize)]
+pub struct EntryInfo {
+    pub path: String,
+    pub added: u64,
+    pub source: Source,
+}
+
+#[derive(Debug, PartialEq, Serialize)]
+pub struct InodeInfo {
+    pub inode: u64,
+    pub dev: u64,
+    /// Unset if the inode was never indexed, or removed since.
+    pub entry: Option<EntryInfo>,
+    /// Whether the BPF programs track the inode.
+    pub in_kernel: bool,
+}
+
+#[derive(Debug, PartialEq, Serialize)]
+pub struct InodeStats {
+    pub entries: usize,
+    pub dirs: usize,
+    pub memory_bytes: usize,
+    /// Entries under each monitored path.
+    pub prefixes: BTreeMap<String, usize>,
+    /// Entries under none of the monitored paths, left over until the
+    /// next scan.
+    pub unmatched: usize,
+}
+
+/// A question about the map, answered by the task that owns it.
+pub enum Query {
+    Inode(inode_key_t, oneshot::Sender<InodeInfo>),
+    Stats(oneshot::Sender<InodeStats>),
+}
+
+impl Query {
+    /// Answer the query from `map`, `in_kernel` telling whether the
+    /// BPF programs track an inode and `prefixes` being the monitored
+    /// paths.
+    pub fn answer(
+        self,
+        map: &InodeMap,
+        in_kernel: impl Fn(&inode_key_t) -> bool,
+        prefixes: &[PathBuf],
+    ) {
+        match self {
+            Query::Inode(inode, tx) => {
+                let _ = tx.send(InodeInfo {
+                    inode: inode.inode,
+                    dev: inode.dev,
+                    entry: map.entry(&inode),
+                    in_kernel: in_kernel(&inode),
+                });
+            }
+            Query::Stats(tx) => {
+                let mut counts = vec![0; prefixes.len()];
+                let mut unmatched = 0;
+                for path in map.paths() {
+                    match prefixes.iter().position(|p| path.starts_with(p)) {
+                        Some(i) => counts[i] += 1,
+                        None => unmatched += 1,
+                    }
+                }
+                let _ = tx.send(InodeStats {
+                    entries: map.len(),
+                    dirs: map.dirs(),
+                    memory_bytes: map.memory_usage(),
+                    prefixes: prefixes
+                        .iter()
+                        .map(|p| p.display().to_string())
+                        .zip(counts)
+                        .collect(),
+                    unmatched,
+                });
+            }
+        }
+    }
+}
+
+/// Handle for querying the map from other tasks.
+#[derive(Debug, Clone)]
+pub struct QueryHandle(mpsc::Sender<Query>);
+
+impl QueryHandle {
+    /// Create a handle along with the receiving end the owner of the
+    /// map answers queries from.
+    pub fn new() -> (Self, mpsc::Receiver<Query>) {
+        let (tx, rx) = mpsc::channel(8);
+        (QueryHandle(tx), rx)
+    }
+
+    pub async fn inode(&self, inode: inode_key_t) -> anyhow::Result<InodeInfo> {
+        let (tx, rx) = oneshot::channel();
+        self.0
+            .send(Query::Inode(inode, tx))
+            .await
+            .ok()
+            .context("host scanner is not running")?;
+        rx.await.context("host scanner dropped the query")
+    }
+
+    pub async fn stats(&self) -> anyhow::Result<InodeStats> {
+        let (tx, rx) = oneshot::channel();
+        self.0
+            .send(Query::Stats(tx))
+            .await
+            .ok()
+            .context("host scanner is not running")?;
+        rx.await.context("host scanner dropped the query")
+    }
+}
+
+/// Put the path of `entry` back together from its directories.
+///
+/// The directories are walked twice, once for the length and once to
+/// fill the path from its end, so it takes a single allocation.
+fn path(dirs: &[Dir], entry: &Entry) -> PathBuf {
+    let ancestors = || {
+        std::iter::successors(entry.dir, |id| dirs[*id as usize].parent)
+            .map(|id| &*dirs[id as usize].name)
+    };
+    let len = entry.name.len() + ancestors().map(|name| name.len() + 1).sum::<usize>();
+
+    let mut path = vec![0; len];
+    let mut end = len;
+    for name in std::iter::once(&*entry.name).chain(ancestors()) {
+        path[end - name.len()..end].copy_from_slice(name);
+        end -= name.len();
+        if end > 0 {
+            end -= 1;
+            path[end] = b'/';
+        }
+    }
+    PathBuf::from(OsString::from_vec(path))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn inode(inode: u64) -> inode_key_t {
+        inode_key_t { inode, dev: 1 }
+    }
+
+    #[test]
+    fn exact_paths() {
+        let paths: &[&[u8]] = &[
+            b"/etc/passwd",
+            b"/etc/ssh/sshd_config",
+            b"/",
+            b"/etc",
+            b"/etc/",
+            b"relative/path",
+            b"file",
+            b"",
+            b"//etc//double",
+            b"/etc/./dot/../parent",
+            b"/var/lib/\xff\xfe-not-utf8/\xc3\x28",
+            b"/usr/lib/\xe4\xb8\xad\xe6\x96\x87",
+        ];
+        let mut map = InodeMap::new();
+        for (i, path) in paths.iter().enumerate() {
+            map.insert(
+                inode(i as u64),
+                Path::new(OsStr::from_bytes(path)),
+                Source::StartupScan,
+            );
+        }
+        assert_eq!(map.len(), paths.len());
+        for (i, path) in paths.iter().enumerate() {
+            let found = map.get(&inode(i as u64)).unwrap();
+            assert_eq!(found.as_os_str().as_bytes(), *path);
+        }
+    }
+
+    #[test]
+    fn shared_dirs() {
+        let mut map = InodeMap::new();
+        for i in 0..100 {
+            map.insert(
+                inode(i),
+                Path::new(&format!("/var/lib/containers/{i}")),
+                Source::StartupScan,
+            );
+        }
+        // The root, var, lib and containers
+        assert_eq!(map.dirs(), 4);
+        let dir_bytes = map.dir_bytes;
+
+        // Directories are released with the last entry in them
+        map.insert(
+            inode(100),
+            Path::new("/var/log/messages"),
+            Source::StartupScan,
+        );
+        assert_eq!(map.dirs(), 5);
+        assert_eq!(
+            map.remove(&inode(100)),
+            Some(PathBuf::from("/var/log/messages"))
+        );
+        assert_eq!(map.dirs(), 4);
+        assert_eq!(map.dir_bytes, dir_bytes);
+
+        for i in 0..100 {
+            assert!(map.remove(&inode(i)).is_some());
+        }
+        assert_eq!(map.remove(&inode(0)), None);
+        assert_eq!(map.dirs(), 0);
+        assert_eq!(map.entry_names, 0);
+        assert_eq!(map.dir_bytes, 0);
+
+        // Removed slots are reused
+        map.insert(inode(0), Path::new("/etc/hosts"), Source::StartupScan);
+        assert_eq!(map.dirs.len(), 5);
+        assert_eq!(map.get(&inode(0)), Some(PathBuf::from("/etc/hosts")));
+    }
+
+    #[test]
+    fn replace() {
+        let mut map = InodeMap::new();
+        map.insert(inode(1), Path::new("/etc/hosts"), Source::StartupScan);
+        map.insert(inode(1), Path::new("/tmp/hosts"), Source::StartupScan);
+        assert_eq!(map.len(), 1);
+        assert_eq!(map.get(&inode(1)), Some(PathBuf::from("/tmp/hosts")));
+        assert_eq!(map.dirs(), 2);
+
+        // Same directory, the reference is moved over
+        map.insert(inode(1), Path::new("/tmp/other"), Source::StartupScan);
+        assert_eq!(map.dirs(), 2);
+        map.remove(&inode(1));
+        assert_eq!(map.dirs(), 0);
+    }
+
+    #[test]
+    fn retain_and_rename() {
+        let mut map = InodeMap::new();
+        map.insert(inode(1), Path::new("/etc/app"), Source::StartupScan);
+        map.insert(
+            inode(2),
+            Path::new("/etc/app/config.yml"),
+            Source::StartupScan,
+        );
+        map.insert(
+            inode(3),
+            Path::new("/etc/app/conf.d/10-log.yml"),
+            Source::StartupScan,
+        );
+        map.insert(inode(4), Path::new("/etc/application"), Source::StartupScan);
+        let dirs = map.dirs.len();
+
+        map.rename(&inode(1), Path::new("/etc/app"), Path::new("/opt/app"));
+        assert_eq!(map.get(&inode(1)), Some(PathBuf::from("/opt/app")));
+        assert_eq!(
+            map.get(&inode(2)),
+            Some(PathBuf::from("/opt/app/config.yml"))
+        );
+        assert_eq!(
+            map.get(&inode(3)),
+            Some(PathBuf::from("/opt/app/conf.d/10-log.yml"))
+        );
+        assert_eq!(map.get(&inode(4)), Some(PathBuf::from("/etc/application")));
+        assert_eq!(map.entry(&inode(1)).unwrap().source, Source::Event);
+        assert_eq!(map.entry(&inode(3)).unwrap().source, Source::StartupScan);
+        // The directory is moved rather than interned again, only opt
+        // is added
+        assert_eq!(map.dirs(), 5);
+        assert_eq!(map.dirs.len(), dirs + 1);
+
+        map.retain(|_, path| !path.starts_with("/opt/app"));
+        assert_eq!(map.len(), 1);
+        assert_eq!(map.get(&inode(4)), Some(PathBuf::from("/etc/application")));
+        // The root and etc
+        assert_eq!(map.dirs(), 2);
+    }
+
+    #[test]
+    fn rename_into_existing() {
+        let mut map = InodeMap::new();
+        map.insert(inode(1), Path::new("/etc/app"), Source::StartupScan);
+        map.insert(inode(2), Path::new("/etc/app/a"), Source::StartupScan);
+        map.insert(inode(3), Path::new("/opt/app/b"), Source::StartupScan);
+
+        // Left over entries of the target, the directories are merged
+        map.rename(&inode(1), Path::new("/etc/app"), Path::new("/opt/app"));
+        assert_eq!(map.get(&inode(1)), Some(PathBuf::from("/opt/app")));
+        assert_eq!(map.get(&inode(2)), Some(PathBuf::from("/opt/app/a")));
+        assert_eq!(map.get(&inode(3)), Some(PathBuf::from("/opt/app/b")));
+        // The root, opt and app
+        assert_eq!(map.dirs(), 3);
+
+        // Renaming a file leaves the directories alone
+        map.rename(&inode(3), Path::new("/opt/app/b"), Path::new("/opt/app/c"));
+        assert_eq!(map.get(&inode(3)), Some(PathBuf::from("/opt/app/c")));
+        assert_eq!(map.dirs(), 3);
+    }
+
+    #[test]
+    fn remove_prefix() {
+        let mut map = InodeMap::new();
+        map.insert(inode(1), Path::new("/etc/app"), Source::StartupScan);
+        map.insert(inode(2), Path::new("/etc/app/a"), Source::StartupScan);
+        map.insert(inode(3), Path::new("/etc/app/sub/b"), Source::StartupScan);
+        map.insert(inode(4), Path::new("/etc/application"), Source::StartupScan);
+
+        let mut removed = Vec::new();
+        map.remove_prefix(Path::new("/etc/app"), |inode| removed.push(inode.inode));
+        removed.sort();
+        assert_eq!(removed, [1, 2, 3]);
+        assert_eq!(map.len(), 1);
+        assert_eq!(map.get(&inode(4)), Some(PathBuf::from("/etc/application")));
+        assert_eq!(map.dirs(), 2);
+
+        // Nothing was ever under it
+        map.remove_prefix(Path::new("/var/lib"), |_| panic!("nothing to remove"));
+        assert_eq!(map.len(), 1);
+    }
+
+    #[test]
+    fn large_keys() {
+        // XFS and btrfs inode numbers past 2^32, keys only differing in
+        // their upper bits must not collide
+        let low = inode_key_t { inode: 7, dev: 1 };
+        let high = inode_key_t {
+            inode: (1 << 40) + 7,
+            dev: 1,
+        };
+        let high_dev = inode_key_t {
+            inode: 7,
+            dev: (1 << 32) + 1,
+        };
+        let mut map = InodeMap::new();
+        map.insert(low, Path::new("/etc/low"), Source::StartupScan);
+        map.insert(high, Path::new("/etc/high"), Source::StartupScan);
+        map.insert(high_dev, Path::new("/etc/high_dev"), Source::Event);
+        assert_eq!(map.len(), 3);
+        assert_eq!(map.get(&low), Some(PathBuf::from("/etc/low")));
+        assert_eq!(map.get(&high), Some(PathBuf::from("/etc/high")));
+        assert_eq!(map.get(&high_dev), Some(PathBuf::from("/etc/high_dev")));
+    }
+
+    #[tokio::test]
+    async fn queries() {
+        let mut map = InodeMap::new();
+        map.insert(inode(1), Path::new("/etc/hosts"), Source::StartupScan);
+        map.insert(inode(2), Path::new("/etc/ssh/sshd_config"), Source::Rescan);
+        map.insert(inode(3), Path::new("/usr/bin/\u{e9}t\u{e9}"), Source::Event);
+        map.insert(inode(4), Path::new("/tmp/leftover"), Source::Rescan);
+        let kernel = [inode(1), inode(3)];
+        let prefixes = [PathBuf::from("/etc"), PathBuf::from("/usr/bin")];
+
+        let (handle, mut queries) = QueryHandle::new();
+        let owner = tokio::spawn(async move {
+            while let Some(query) = queries.recv().await {
+                query.answer(&map, |inode| kernel.contains(inode), &prefixes);
+            }
+        });
+
+        let info = handle.inode(inode(2)).await.unwrap();
+        let entry = info.entry.unwrap();
+        assert_eq!(entry.path, "/etc/ssh/sshd_config");
+        assert_eq!(entry.source, Source::Rescan);
+        assert!(entry.added > 0);
+        assert!(!info.in_kernel);
+
+        let info = handle.inode(inode(3)).await.unwrap();
+        assert_eq!(info.entry.unwrap().source, Source::Event);
+        assert!(info.in_kernel);
+
+        // Never indexed
+        let info = handle.inode(inode(42)).await.unwrap();
+        assert_eq!(
+            info,
+            InodeInfo {
+                inode: 42,
+                dev: 1,
+                entry: None,
+                in_kernel: false,
+            }
+        );
+
+        let stats = handle.stats().await.unwrap();
+        assert_eq!(stats.entries, 4);
+        assert_eq!(
+            stats.prefixes,
+            BTreeMap::from([("/etc".to_string(), 2), ("/usr/bin".to_string(), 1)])
+        );
+        assert_eq!(stats.unmatched, 1);
+        let json = serde_json::to_value(&stats).unwrap();
+        assert_eq!(json["prefixes"]["/etc"], 2);
+
+        // Queries fail once the owner is gone
+        owner.abort();
+        let _ = owner.await;
+        assert!(handle.stats().await.is_err());
+    }
+}
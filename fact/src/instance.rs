@@ -0,0 +1,386 @@
+//! Detection of other instances of fact running on the same host.
+//!
+//! Two instances attach two sets of BPF programs and every event ends
+//! up reported twice. The instance reading events from the kernel
+//! holds an exclusive `flock` on a lock file for as long as it runs,
+//! the kernel releases it when the process exits, even if it crashes.
+//!
+//! The lock file holds the host PID of its holder. It is emptied on a
+//! clean shutdown, a PID found in a lock that is not held comes from an
+//! instance that crashed. A held lock whose PID is not running anymore
+//! was leaked to another process and is replaced, `--force` replaces it
+//! even if its holder still runs.
+
+use std::{
+    fs::{self, File, OpenOptions},
+    io::{self, Read, Seek, Write},
+    os::{fd::AsRawFd, unix::fs::MetadataExt},
+    path::{Path, PathBuf},
+};
+
+use log::{info, warn};
+use thiserror::Error;
+
+use crate::host_info;
+
+#[derive(Debug, Error)]
+pub enum LockError {
+    #[error("another instance of fact{} holds {}", holder(*.pid), .path.display())]
+    Held { path: PathBuf, pid: Option<u32> },
+    #[error("failed to lock {}: {source}", .path.display())]
+    Io { path: PathBuf, source: io::Error },
+}
+
+fn holder(pid: Option<u32>) -> String {
+    pid.map(|pid| format!(" (PID {pid})")).unwrap_or_default()
+}
+
+/// Exclusive lock on the lock file, released when dropped.
+#[derive(Debug)]
+pub struct InstanceLock {
+    file: File,
+}
+
+impl InstanceLock {
+    /// Take the lock at `path` for the instance with host PID `pid`.
+    ///
+    /// With `force`, a lock held by another instance is replaced.
+    pub fn acquire(path: &Path, pid: u32, force: bool) -> Result<Self, LockError> {
+        InstanceLock::acquire_with(path, pid, force, is_running)
+    }
+
+    fn acquire_with(
+        path: &Path,
+        pid: u32,
+        force: bool,
+        is_running: impl Fn(u32) -> bool,
+    ) -> Result<Self, LockError> {
+        let io_error = |source| LockError::Io {
+            path: path.to_owned(),
+            source,
+        };
+
+        // A second attempt is needed when the lock file is replaced,
+        // by this instance or a concurrent one.
+        let mut attempts = 3;
+        loop {
+            attempts -= 1;
+            let mut file = OpenOptions::new()
+                .read(true)
+                .write(true)
+                .create(true)
+                .truncate(false)
+                .open(path)
+                .map_err(io_error)?;
+            let holder = read_pid(&mut file).map_err(io_error)?;
+
+            if let Err(e) = flock(&file) {
+                if e.kind() != io::ErrorKind::WouldBlock {
+                    return Err(io_error(e));
+                }
+                let replace = match holder {
+                    _ if attempts == 0 => false,
+                    Some(holder) if !is_running(holder) => {
+                        warn!(
+                            "{} is held by PID {holder} which is not running, replacing it",
+                            path.display()
+                        );
+                        true
+                    }
+                    _ if force => {
+                        let e = LockError::Held {
+                            path: path.to_owned(),
+                            pid: holder,
+                        };
+                        warn!("{e}, replacing it as requested");
+                        true
+                    }
+                    _ => false,
+                };
+                if !replace {
+                    return Err(LockError::Held {
+                        path: path.to_owned(),
+                        pid: holder,
+                    });
+                }
+                fs::remove_file(path).map_err(io_error)?;
+                continue;
+            }
+
+            // The file may have been replaced between opening and
+            // locking it, the lock is only valid on the current one.
+            let current = fs::metadata(path).map(|m| (m.dev(), m.ino()));
+            let locked = file.metadata().map(|m| (m.dev(), m.ino()));
+            match (current, locked) {
+                (Ok(current), Ok(locked)) if current == locked => {}
+                _ if attempts > 0 => continue,
+                (_, Err(e)) | (Err(e), _) => return Err(io_error(e)),
+                _ => {
+                    return Err(io_error(io::Error::other("lock file keeps being replaced")));
+                }
+            }
+
+            // Holders are re-read now that the lock is taken, the
+            // previous holder may have exited in between.
+            if let Some(holder) = read_pid(&mut file).map_err(io_error)? {
+                info!("Previous instance of fact (PID {holder}) did not shut down cleanly");
+            }
+            write_pid(&mut file, Some(pid)).map_err(io_error)?;
+            return Ok(InstanceLock { file });
+        }
+    }
+}
+
+impl Drop for InstanceLock {
+    fn drop(&mut self) {
+        // Closing the file releases the lock, the PID is removed to
+        // tell a clean shutdown apart from a crash.
+        if let Err(e) = write_pid(&mut self.file, None) {
+            warn!("Failed to clear the instance lock: {e}");
+        }
+    }
+}
+
+fn flock(file: &File) -> io::Result<()> {
+    // SAFETY: the file descriptor is valid for as long as `file` lives.
+    let res = unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX | libc::LOCK_NB) };
+    if res != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+fn read_pid(file: &mut File) -> io::Result<Option<u32>> {
+    let mut content = String::new();
+    file.rewind()?;
+    file.read_to_string(&mut content)?;
+    Ok(content.trim().parse().ok())
+}
+
+fn write_pid(file: &mut File, pid: Option<u32>) -> io::Result<()> {
+    file.set_len(0)?;
+    file.rewind()?;
+    if let Some(pid) = pid {
+        writeln!(file, "{pid}")?;
+    }
+    Ok(())
+}
+
+/// Whether a process with host PID `pid` exists.
+fn is_running(pid: u32) -> bool {
+    host_info::get_host_mount()
+        .join("proc")
+        .join(pid.to_string())
+        .exists()
+}
+
+/// How this instance shares the host with other instances of fact.
+#[derive(Debug)]
+pub enum Instance {
+    /// No other instance runs, the lock is held until this is dropped.
+    Only { _lock: InstanceLock },
+    /// The lock file cannot be used, other instances are not detected.
+    Unknown,
+    /// Other instances run, events are tagged with the ID of this one.
+    Shared { id: u32 },
+}
+
+impl Instance {
+    /// Make sure no other instance reads events from the kernel on the
+    /// host, unless `allow_multiple` is set. With `force` the lock is
+    /// taken from any other instance.
+    pub fn lock(path: &Path, allow_multiple: bool, force: bool) -> Result<Self, LockError> {
+        let pid = host_info::get_host_pid();
+        match InstanceLock::acquire(path, pid, force) {
+            Ok(lock) => Ok(Instance::Only { _lock: lock }),
+            Err(e @ LockError::Held { .. }) if allow_multiple => {
+                warn!("{e}, events will be tagged with instance ID {pid}");
+                Ok(Instance::Shared { id: pid })
+            }
+            Err(e @ LockError::Held { .. }) => Err(e),
+            Err(e @ LockError::Io { .. }) => {
+                warn!("{e}, other instances of fact will not be detected");
+                Ok(Instance::Unknown)
+            }
+        }
+    }
+
+    /// ID events are tagged with, only set when sharing the host.
+    pub fn id(&self) -> Option<u32> {
+        match self {
+            Instance::Shared { id } => Some(*id),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::{BufRead, BufReader},
+        process::{Child, Command, Stdio},
+    };
+
+    use tempfile::TempDir;
+
+    use super::*;
+
+    /// Variable with the lock file `lock_holder` takes.
+    const HOLDER_PATH: &str = "FACT_TEST_LOCK_HOLDER";
+
+    fn content(path: &Path) -> String {
+        fs::read_to_string(path).unwrap()
+    }
+
+    fn running(pid: u32) -> bool {
+        Path::new("/proc").join(pid.to_string()).exists()
+    }
+
+    /// Helper for `processes`, holds the lock until its stdin is closed
+    /// when run with `FACT_TEST_LOCK_HOLDER` set.
+    #[test]
+    fn lock_holder() {
+        let Some(path) = std::env::var_os(HOLDER_PATH) else {
+            return;
+        };
+        let _lock =
+            InstanceLock::acquire_with(Path::new(&path), std::process::id(), false, running)
+                .unwrap();
+        println!("locked");
+        let _ = io::stdin().read_to_end(&mut Vec::new());
+    }
+
+    /// Run `lock_holder` in another process, returns once it holds the
+    /// lock at `path`.
+    fn spawn_holder(path: &Path) -> Child {
+        let mut child = Command::new(std::env::current_exe().unwrap())
+            .args(["--exact", "instance::tests::lock_holder", "--nocapture"])
+            .env(HOLDER_PATH, path)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .unwrap();
+        let mut stdout = BufReader::new(child.stdout.take().unwrap());
+        let mut line = String::new();
+        // The test harness prints the name of the test on the same line
+        while !line.trim_end().ends_with("locked") {
+            line.clear();
+            if stdout.read_line(&mut line).unwrap() == 0 {
+                panic!("Lock holder exited");
+            }
+        }
+        child.stdout = Some(stdout.into_inner());
+        child
+    }
+
+    #[test]
+    fn processes() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("fact.lock");
+
+        let mut holder = spawn_holder(&path);
+        assert!(matches!(
+            InstanceLock::acquire_with(&path, 200, false, running),
+            Err(LockError::Held { pid: Some(pid), .. }) if pid == holder.id()
+        ));
+
+        // A clean shutdown clears the PID
+        drop(holder.stdin.take());
+        assert!(holder.wait().unwrap().success());
+        assert_eq!(content(&path), "");
+
+        // A killed holder leaves its PID behind, the kernel releases
+        // the lock
+        let mut holder = spawn_holder(&path);
+        holder.kill().unwrap();
+        holder.wait().unwrap();
+        assert_eq!(content(&path), format!("{}\n", holder.id()));
+        drop(InstanceLock::acquire_with(&path, 200, false, running).unwrap());
+
+        // The lock can be forcefully taken from a running holder
+        let mut holder = spawn_holder(&path);
+        let lock = InstanceLock::acquire_with(&path, 200, true, running).unwrap();
+        assert_eq!(content(&path), "200\n");
+        drop(holder.stdin.take());
+        assert!(holder.wait().unwrap().success());
+        assert_eq!(content(&path), "200\n");
+        drop(lock);
+    }
+
+    #[test]
+    fn contention() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("fact.lock");
+
+        let first = InstanceLock::acquire_with(&path, 100, false, |_| true).unwrap();
+        assert_eq!(content(&path), "100\n");
+
+        let Err(LockError::Held { pid, .. }) =
+            InstanceLock::acquire_with(&path, 200, false, |_| true)
+        else {
+            panic!("Lock acquired twice");
+        };
+        assert_eq!(pid, Some(100));
+
+        // A clean shutdown releases the lock and clears the PID
+        drop(first);
+        assert_eq!(content(&path), "");
+        let _second = InstanceLock::acquire_with(&path, 200, false, |_| true).unwrap();
+        assert_eq!(content(&path), "200\n");
+    }
+
+    #[test]
+    fn crashed() {
+        // The kernel released the lock, only the PID was left behind
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("fact.lock");
+        fs::write(&path, "100\n").unwrap();
+
+        let _lock = InstanceLock::acquire_with(&path, 200, false, |_| panic!("Not held")).unwrap();
+        assert_eq!(content(&path), "200\n");
+    }
+
+    #[test]
+    fn stale() {
+        // The lock is held, but its PID is gone
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("fact.lock");
+        let leaked = InstanceLock::acquire_with(&path, 100, false, |_| true).unwrap();
+
+        let lock = InstanceLock::acquire_with(&path, 200, false, |pid| pid != 100).unwrap();
+        assert_eq!(content(&path), "200\n");
+
+        // The new lock is not affected by the leaked one going away
+        drop(leaked);
+        assert_eq!(content(&path), "200\n");
+        assert!(matches!(
+            InstanceLock::acquire_with(&path, 300, false, |_| true),
+            Err(LockError::Held { pid: Some(200), .. })
+        ));
+        drop(lock);
+    }
+
+    #[test]
+    fn allow_multiple() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("fact.lock");
+
+        let first = Instance::lock(&path, false, false).unwrap();
+        assert!(matches!(first, Instance::Only { .. }));
+        assert_eq!(first.id(), None);
+
+        // The holder is this process, which is running
+        assert!(matches!(
+            Instance::lock(&path, false, false),
+            Err(LockError::Held { .. })
+        ));
+        let second = Instance::lock(&path, true, false).unwrap();
+        assert_eq!(second.id(), Some(host_info::get_host_pid()));
+
+        let missing = dir.path().join("missing/fact.lock");
+        assert!(matches!(
+            Instance::lock(&missing, false, false),
+            Ok(Instance::Unknown)
+        ));
+    }
+}
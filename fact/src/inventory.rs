@@ -0,0 +1,195 @@
+//! One-shot inventory of the monitored paths, as requested with the
+//! `fact scan` subcommand.
+//!
+//! The monitored paths are walked once and an inventory event is sent
+//! down the pipeline for each file and directory found. Since no BPF
+//! programs are involved, this can run on hosts where fact is not able
+//! to monitor file activity.
+
+use std::path::PathBuf;
+
+use log::{info, warn};
+use tokio::{
+    sync::{mpsc, watch},
+    task::JoinSet,
+};
+
+use crate::{
+    event::Event,
+    fs_walker::{self, EntryKind},
+    host_fs, host_info,
+};
+
+/// Number of entries between progress messages.
+const PROGRESS_INTERVAL: u64 = 10_000;
+
+pub fn start(
+    task_set: &mut JoinSet<anyhow::Result<()>>,
+    paths: Vec<PathBuf>,
+    limit: Option<u64>,
+    running: watch::Receiver<bool>,
+) -> mpsc::Receiver<Event> {
+    let (tx, rx) = mpsc::channel(crate::EVENT_CHANNEL_CAPACITY);
+    task_set.spawn_blocking(move || scan(&paths, limit, &running, &tx));
+    rx
+}
+
+fn scan(
+    paths: &[PathBuf],
+    limit: Option<u64>,
+    running: &watch::Receiver<bool>,
+    tx: &mpsc::Sender<Event>,
+) -> anyhow::Result<()> {
+    info!("Starting inventory of {} monitored paths", paths.len());
+    let mut reported = 0;
+
+    'patterns: for pattern in paths {
+        for entry in fs_walker::walk(pattern)? {
+            if !*running.borrow() {
+                info!("Inventory interrupted");
+                break 'patterns;
+            }
+
+            let (path, kind) = match entry {
+                Ok(entry) => entry,
+                Err(e) => {
+                    warn!("Failed to read entry: {e}");
+                    continue;
+                }
+            };
+            if kind == EntryKind::Other {
+                continue;
+            }
+
+            if let Some(limit) = limit
+                && reported >= limit
+            {
+                warn!("Inventory limit of {limit} entries reached, stopping");
+                break 'patterns;
+            }
+
+            let metadata = match host_fs::symlink_metadata(host_info::get_host_mount(), &path) {
+                Ok(metadata) => metadata,
+                Err(e) => {
+                    // The file might have been removed since it was found
+                    warn!("Failed to read metadata for {}: {e}", path.display());
+                    continue;
+                }
+            };
+
+            if tx
+                .blocking_send(Event::inventory(&path, &metadata))
+                .is_err()
+            {
+                break 'patterns;
+            }
+
+            reported += 1;
+            if reported % PROGRESS_INTERVAL == 0 {
+                info!("Inventory in progress, {reported} entries reported");
+            }
+        }
+    }
+
+    info!("Inventory done, {reported} entries reported");
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        fs,
+        os::unix::fs::{MetadataExt, PermissionsExt},
+        path::Path,
+    };
+
+    use serde_json::Value;
+
+    use super::*;
+
+    async fn inventory(paths: Vec<PathBuf>, limit: Option<u64>) -> Vec<Value> {
+        let mut task_set = JoinSet::new();
+        let (_running_tx, running_rx) = watch::channel(true);
+        let mut rx = start(&mut task_set, paths, limit, running_rx);
+
+        let mut events = Vec::new();
+        while let Some(event) = rx.recv().await {
+            events.push(serde_json::to_value(event).expect("Failed to serialize event"));
+        }
+        task_set
+            .join_next()
+            .await
+            .expect("Inventory task missing")
+            .expect("Inventory task panicked")
+            .expect("Inventory task failed");
+
+        events.sort_by_key(|event| event["file"]["Inventory"]["inner"]["filename"].to_string());
+        events
+    }
+
+    fn create_tree(root: &Path) {
+        fs::create_dir_all(root.join("etc/ssh")).unwrap();
+        fs::write(root.join("etc/passwd"), "root:x:0:0").unwrap();
+        fs::set_permissions(root.join("etc/passwd"), fs::Permissions::from_mode(0o644)).unwrap();
+        fs::write(root.join("etc/ssh/sshd_config"), "").unwrap();
+        fs::set_permissions(
+            root.join("etc/ssh/sshd_config"),
+            fs::Permissions::from_mode(0o600),
+        )
+        .unwrap();
+    }
+
+    #[tokio::test]
+    async fn scan_tree() {
+        let root = tempfile::tempdir().expect("Failed to create root");
+        let root = root.path();
+        create_tree(root);
+
+        let events = inventory(vec![root.join("etc/**/*")], None).await;
+
+        let expected = [
+            ("etc/passwd", libc::S_IFREG | 0o644, 10),
+            ("etc/ssh", libc::S_IFDIR, 0),
+            ("etc/ssh/sshd_config", libc::S_IFREG | 0o600, 0),
+        ];
+        assert_eq!(events.len(), expected.len());
+        for (event, (path, mode, size)) in events.iter().zip(expected) {
+            let path = root.join(path);
+            let metadata = fs::metadata(&path).unwrap();
+            let file = &event["file"]["Inventory"];
+
+            assert_eq!(file["inner"]["filename"], path.to_str().unwrap());
+            assert_eq!(file["inner"]["host_file"], path.to_str().unwrap());
+            assert_eq!(file["uid"], metadata.uid());
+            assert_eq!(file["gid"], metadata.gid());
+            if mode & libc::S_IFMT == libc::S_IFREG {
+                assert_eq!(file["mode"], mode);
+                assert_eq!(file["size"], size);
+            } else {
+                assert_eq!(file["mode"].as_u64().unwrap() as u32 & libc::S_IFMT, mode);
+            }
+            assert!(event["timestamp"].as_u64().unwrap() > 0);
+        }
+    }
+
+    #[tokio::test]
+    async fn scan_limit() {
+        let root = tempfile::tempdir().expect("Failed to create root");
+        let root = root.path();
+        create_tree(root);
+
+        let events = inventory(vec![root.join("etc/**/*")], Some(2)).await;
+        assert_eq!(events.len(), 2);
+
+        let events = inventory(vec![root.join("etc/**/*")], Some(0)).await;
+        assert!(events.is_empty());
+    }
+
+    #[tokio::test]
+    async fn scan_missing_path() {
+        let root = tempfile::tempdir().expect("Failed to create root");
+
+        let events = inventory(vec![root.path().join("missing")], None).await;
+        assert!(events.is_empty());
+    }
+}
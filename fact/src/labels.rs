@@ -0,0 +1,229 @@
+//! Tagging of events with the labels configured for monitored paths.
+//!
+//! Labels are resolved with a longest prefix match of the file name in
+//! the event against the prefixes of the labeled paths, the same way
+//! the kernel matches files against the path prefix map. This way, the
+//! labels on an event always come from the path that made the kernel
+//! report it.
+
+use std::{cmp::Reverse, collections::BTreeMap, os::unix::ffi::OsStrExt, path::Path};
+
+use fact_ebpf::glob_prefix;
+use log::{debug, info};
+use tokio::{
+    sync::{mpsc, watch},
+    task::JoinSet,
+};
+
+use crate::{config::PathLabels, event::Event};
+
+/// Lookup table from path prefixes to labels.
+#[derive(Debug, Default)]
+pub struct PathLabeler {
+    /// Prefixes with their labels, longest prefixes first.
+    prefixes: Vec<(Vec<u8>, BTreeMap<String, String>)>,
+}
+
+impl PathLabeler {
+    pub fn new(paths: &[PathLabels]) -> Self {
+        let mut prefixes: Vec<(Vec<u8>, BTreeMap<String, String>)> = Vec::new();
+        for p in paths {
+            let prefix = glob_prefix(&p.path.to_string_lossy()).to_vec();
+
+            // Paths with the same prefix are a single entry in the
+            // kernel, the last one configured wins.
+            match prefixes.iter_mut().find(|(p, _)| *p == prefix) {
+                Some((_, labels)) => *labels = p.labels.clone(),
+                None => prefixes.push((prefix, p.labels.clone())),
+            }
+        }
+        prefixes.sort_by_key(|(prefix, _)| Reverse(prefix.len()));
+        PathLabeler { prefixes }
+    }
+
+    /// Whether no path has any labels.
+    pub fn is_empty(&self) -> bool {
+        self.prefixes.iter().all(|(_, labels)| labels.is_empty())
+    }
+
+    /// Get the labels for the longest prefix matching `path`.
+    ///
+    /// Returns `None` if no prefix matches or the matching one has no
+    /// labels.
+    pub fn resolve(&self, path: &Path) -> Option<&BTreeMap<String, String>> {
+        let path = path.as_os_str().as_bytes();
+        self.prefixes
+            .iter()
+            .find(|(prefix, _)| path.starts_with(prefix))
+            .map(|(_, labels)| labels)
+            .filter(|labels| !labels.is_empty())
+    }
+}
+
+/// Start a task setting the labels on the events going through it.
+pub fn start(
+    task_set: &mut JoinSet<anyhow::Result<()>>,
+    mut rx: mpsc::Receiver<Event>,
+    mut config: watch::Receiver<Vec<PathLabels>>,
+) -> mpsc::Receiver<Event> {
+    let (tx, output) = mpsc::channel(crate::EVENT_CHANNEL_CAPACITY);
+    let mut labeler = PathLabeler::new(&config.borrow_and_update());
+    task_set.spawn(async move {
+        debug!("Starting path labeler...");
+        loop {
+            tokio::select! {
+                event = rx.recv() => {
+                    let Some(mut event) = event else {
+                        info!("Stopping path labeler...");
+                        return Ok(());
+                    };
+                    if !labeler.is_empty()
+                        && let Some(labels) = labeler.resolve(event.get_filename())
+                    {
+                        event.set_labels(labels.clone());
+                    }
+                    if tx.send(event).await.is_err() {
+                        info!("No path labeler consumers left, stopping...");
+                        return Ok(());
+                    }
+                }
+                Ok(_) = config.changed() => {
+                    labeler = PathLabeler::new(&config.borrow_and_update());
+                }
+            }
+        }
+    });
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::*;
+
+    fn path_labels(path: &str, labels: &[(&str, &str)]) -> PathLabels {
+        PathLabels {
+            path: PathBuf::from(path),
+            labels: labels
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn resolve() {
+        let labeler = PathLabeler::new(&[
+            path_labels("/etc/**/*", &[("criticality", "medium")]),
+            path_labels("/etc/kubernetes/**/*", &[("criticality", "high")]),
+            path_labels("/etc/kubernetes/manifests", &[("kind", "manifests")]),
+            path_labels("/etc/kubernetes/pki/**/*", &[]),
+            path_labels("/home", &[("criticality", "low")]),
+            path_labels("/var/log/*.log", &[("kind", "logs")]),
+        ]);
+
+        let tests = [
+            ("/etc/passwd", Some(vec![("criticality", "medium")])),
+            ("/etc/kubernetes", Some(vec![("criticality", "medium")])),
+            (
+                "/etc/kubernetes/kubelet.conf",
+                Some(vec![("criticality", "high")]),
+            ),
+            (
+                "/etc/kubernetes/manifests/etcd.yaml",
+                Some(vec![("kind", "manifests")]),
+            ),
+            // The longest matching path has no labels
+            ("/etc/kubernetes/pki/ca.crt", None),
+            // Prefixes are matched byte by byte, like in the kernel
+            ("/etc/kubernetes-old", Some(vec![("criticality", "medium")])),
+            ("/home/user/.bashrc", Some(vec![("criticality", "low")])),
+            ("/homework", Some(vec![("criticality", "low")])),
+            ("/var/log/syslog", Some(vec![("kind", "logs")])),
+            ("/et", None),
+            ("/usr/bin/ls", None),
+        ];
+        for (path, expected) in tests {
+            let expected = expected.map(|labels| {
+                labels
+                    .into_iter()
+                    .map(|(k, v)| (k.to_string(), v.to_string()))
+                    .collect::<BTreeMap<_, _>>()
+            });
+            assert_eq!(
+                labeler.resolve(Path::new(path)),
+                expected.as_ref(),
+                "{path}"
+            );
+        }
+    }
+
+    #[test]
+    fn resolve_same_prefix() {
+        let labeler = PathLabeler::new(&[
+            path_labels("/etc/**/*.conf", &[("criticality", "high")]),
+            path_labels("/etc/**/*", &[("criticality", "low")]),
+        ]);
+        let labels = labeler.resolve(Path::new("/etc/app.conf")).unwrap();
+        assert_eq!(labels["criticality"], "low");
+    }
+
+    #[test]
+    fn resolve_long_prefix() {
+        // Prefixes are truncated to the size of the kernel map keys
+        let long = format!("/{}", "a".repeat(300));
+        let labeler = PathLabeler::new(&[path_labels(&long, &[("kind", "long")])]);
+        let other = format!("/{}b", "a".repeat(255));
+        assert!(labeler.resolve(Path::new(&other)).is_some());
+        assert!(labeler.resolve(Path::new("/a")).is_none());
+    }
+
+    #[tokio::test]
+    async fn label_events() {
+        let dir = tempfile::tempdir().expect("Failed to create directory");
+        let labeled = dir.path().join("labeled");
+        let unlabeled = dir.path().join("unlabeled");
+        std::fs::write(&labeled, "").unwrap();
+        std::fs::write(&unlabeled, "").unwrap();
+
+        let mut task_set = JoinSet::new();
+        let (config_tx, config_rx) = watch::channel(vec![path_labels(
+            labeled.to_str().unwrap(),
+            &[("criticality", "high")],
+        )]);
+        let (tx, rx) = mpsc::channel(10);
+        let mut rx = start(&mut task_set, rx, config_rx);
+
+        for path in [&labeled, &unlabeled] {
+            let event = Event::inventory(path, &path.metadata().unwrap());
+            tx.send(event).await.unwrap();
+        }
+        let event = serde_json::to_value(rx.recv().await.unwrap()).unwrap();
+        assert_eq!(event["labels"], serde_json::json!({"criticality": "high"}));
+        let event = serde_json::to_value(rx.recv().await.unwrap()).unwrap();
+        assert!(event.get("labels").is_none());
+
+        // Labels are updated on reload
+        config_tx.send_replace(vec![path_labels(
+            unlabeled.to_str().unwrap(),
+            &[("criticality", "low")],
+        )]);
+        tokio::task::yield_now().await;
+        let event = Event::inventory(&unlabeled, &unlabeled.metadata().unwrap());
+        tx.send(event).await.unwrap();
+        let event = serde_json::to_value(rx.recv().await.unwrap()).unwrap();
+        assert_eq!(event["labels"], serde_json::json!({"criticality": "low"}));
+    }
+
+    #[test]
+    fn empty() {
+        let labeler = PathLabeler::new(&[]);
+        assert!(labeler.is_empty());
+        assert!(labeler.resolve(Path::new("/etc/passwd")).is_none());
+
+        let labeler = PathLabeler::new(&[path_labels("/etc", &[])]);
+        assert!(labeler.is_empty());
+        assert!(labeler.resolve(Path::new("/etc/passwd")).is_none());
+    }
+}
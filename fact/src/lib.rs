@@ -1,39 +1,110 @@
-use std::{io::Write, str::FromStr, time::Duration};
+use std::{
+    io::Write,
+    str::FromStr,
+    sync::Arc,
+    time::{Duration, Instant},
+};
 
 use anyhow::{Context, Result};
 use bpf::Bpf;
+use enrich::{ExistenceChecker, SystemStat};
+use exit::Fatal;
+use health::HealthMonitor;
 use host_info::{SystemInfo, get_distro, get_hostname};
 use host_scanner::HostScanner;
+use instance::Instance;
 use log::{LevelFilter, debug, info, warn};
 use metrics::exporter::Exporter;
+use output::Outputs;
+use pacer::{Budget, SystemClock};
+use pause::{PauseController, PauseSwitch};
+use pipeline::{EventSink, Pipeline};
 use rate_limiter::RateLimiter;
+use supervisor::Supervisor;
 use tokio::{
     signal::unix::{SignalKind, signal},
-    sync::{mpsc, watch},
+    sync::{broadcast, mpsc, watch},
     task::JoinSet,
-    time::timeout,
+    time::{sleep, timeout},
 };
 
+#[cfg(feature = "bench")]
+#[doc(hidden)]
+pub mod bench;
 mod bpf;
 pub mod config;
+mod coverage;
+pub mod decode;
 mod endpoints;
+mod enrich;
 mod event;
+pub mod events;
+pub mod exit;
+mod filter;
+mod fs_walker;
+mod generate;
+mod health;
+mod host_fs;
 mod host_info;
 mod host_scanner;
+mod inode_map;
+mod instance;
+mod inventory;
+mod labels;
+mod limits;
 mod metrics;
+mod mount_info;
+mod node_id;
 mod output;
+mod pacer;
+mod pause;
+pub mod pipeline;
 mod pre_flight;
 mod rate_limiter;
+mod redact;
+mod reorder;
 mod replay;
+mod sampling;
+mod secret;
+mod state;
+mod status;
+mod summary;
+mod supervisor;
+mod tls;
+mod trace;
 
-use config::FactConfig;
+use config::{FactConfig, LimitsFormat};
 use pre_flight::pre_flight;
 
 use crate::{
     event::Event,
-    metrics::{Metrics, kernel_metrics::KernelMetrics},
+    metrics::{MaintenanceTask, Metrics, kernel_metrics::KernelMetrics},
 };
 
+/// Capacity of the channels connecting the stages of the event
+/// pipeline.
+const EVENT_CHANNEL_CAPACITY: usize = 100;
+
+/// Time given to components to finish their work once fact is
+/// stopping.
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Times a minute the endpoints server is restarted before fact gives
+/// up and stops.
+const ENDPOINTS_MAX_RESTARTS: usize = 3;
+
+/// Entry point for fuzzing the parsing of events read from the
+/// ringbuffer, see the targets under `fact/fuzz`.
+#[cfg(fuzzing)]
+pub fn fuzz_event_parser(data: &[u8]) {
+    let _ = event::parse_raw(data);
+    // Inputs too short for an event are rejected above, padded they get
+    // to its fields
+    let mut padded = data.to_vec();
+    padded.resize(data.len().max(size_of::<fact_ebpf::event_t>()), 0);
+    let _ = event::parse_raw(&padded);
+}
+
 pub fn init_log() -> anyhow::Result<()> {
     let log_level = std::env::var("FACT_LOGLEVEL").unwrap_or("info".to_owned());
     let log_level = LevelFilter::from_str(&log_level)?;
@@ -82,6 +153,15 @@ fn flatten_task_result(
     }
 }
 
+fn print_limits(config: &FactConfig, format: LimitsFormat) -> anyhow::Result<()> {
+    let limits = limits::limits(config);
+    match format {
+        LimitsFormat::Table => print!("{}", limits::table(&limits)),
+        LimitsFormat::Json => println!("{}", serde_json::to_string_pretty(&limits)?),
+    }
+    Ok(())
+}
+
 async fn join_all_tasks(mut task_set: JoinSet<anyhow::Result<()>>) -> anyhow::Result<()> {
     while let Some(task_res) = task_set.join_next().await {
         flatten_task_result(task_res)?;
@@ -89,7 +169,43 @@ async fn join_all_tasks(mut task_set: JoinSet<anyhow::Result<()>>) -> anyhow::Re
     Ok(())
 }
 
+/// Run fact with the outputs in `config`, see `pipeline::Pipeline`
+/// for adding outputs of your own.
 pub async fn run(config: FactConfig) -> anyhow::Result<()> {
+    Pipeline::new(config).run().await
+}
+
+/// Run the pipeline, logging a summary of the error it stops on.
+async fn run_pipeline(
+    config: FactConfig,
+    events: broadcast::Sender<Arc<Event>>,
+    sinks: Vec<Box<dyn EventSink>>,
+) -> anyhow::Result<()> {
+    let start = Instant::now();
+    let metrics = Metrics::new();
+    let res = run_until_stopped(config, events, sinks, &metrics).await;
+    if let Err(e) = &res {
+        exit::Summary::new(e, start.elapsed(), (&metrics).into()).log();
+    }
+    res
+}
+
+async fn run_until_stopped(
+    config: FactConfig,
+    events: broadcast::Sender<Arc<Event>>,
+    sinks: Vec<Box<dyn EventSink>>,
+    metrics_userspace: &Metrics,
+) -> anyhow::Result<()> {
+    if let Some(format) = config.limits() {
+        return print_limits(&config, format);
+    }
+
+    // Everything reading from the host goes through the host mount,
+    // starting with the system information
+    host_info::init_host_mount(config.host_mount()).context(Fatal::Config)?;
+    // Spans left to export are flushed when fact stops
+    let _tracing = trace::init(&config.otel.traces).context(Fatal::Config)?;
+
     // Log system information as early as possible so we have it
     // available in case of a crash
     log_system_information();
@@ -97,41 +213,181 @@ pub async fn run(config: FactConfig) -> anyhow::Result<()> {
     let (running_helpers, _) = watch::channel(true);
     let reloader = config::reloader::Reloader::from(config);
     let config_trigger = reloader.get_trigger();
+    let run_for = reloader.config().run_for();
     let mut task_set = JoinSet::new();
-    let metrics_userspace = Metrics::new();
+    metrics_userspace
+        .output
+        .containers
+        .set_capacity(reloader.config().top_containers() as usize);
+    let generate_summary = reloader
+        .config()
+        .generate()
+        .then(|| generate::Summary::new(metrics_userspace));
 
-    let (metrics_kernelspace, rx) = setup_input(
+    // Only one instance may attach the BPF programs, the lock is held
+    // until fact exits.
+    let instance = match reads_kernel_events(reloader.config()) {
+        true => Instance::lock(
+            reloader.config().lock_file(),
+            reloader.config().allow_multiple(),
+            reloader.config().force_lock(),
+        )?,
+        false => Instance::Unknown,
+    };
+    // Events read from the kernel are tagged with the ID of the node,
+    // replayed ones keep the ID they were recorded with.
+    if reads_kernel_events(reloader.config()) {
+        node_id::init(
+            &host_info::prepend_host_mount(reloader.config().node_id_file()),
+            &metrics_userspace.state,
+        );
+    }
+    let Input {
+        rx,
+        metrics_kernelspace,
+        pause_flag,
+        bpf,
+        inodes,
+    } = setup_input(
         &mut task_set,
         &reloader,
-        &metrics_userspace,
+        metrics_userspace,
         running_pipeline_rx,
     )?;
+    let rx = match instance.id() {
+        Some(id) => tag_instance(&mut task_set, rx, id),
+        None => rx,
+    };
+    let rx = reorder::start(
+        &mut task_set,
+        rx,
+        reloader.reorder_window(),
+        metrics_userspace.reorder.clone(),
+    );
+    let (pause_records, pause_records_rx) = mpsc::channel(10);
+    let (pause_controller, pause) = PauseController::new(
+        pause_flag,
+        metrics_userspace.collection_paused.clone(),
+        running_helpers.subscribe(),
+        pause_records,
+    );
+    let rx = redact::start(
+        &mut task_set,
+        rx,
+        reloader.redact_args(),
+        metrics_userspace.redactions.clone(),
+    );
+    let rx = labels::start(&mut task_set, rx, reloader.path_labels());
     let (rate_limiter, rx) = RateLimiter::new(
         rx,
         reloader.rate_limit(),
         metrics_userspace.rate_limiter.clone(),
     )?;
+    let rx = match reloader.config().max_events() {
+        Some(max_events) => limit_events(&mut task_set, rx, max_events),
+        None => rx,
+    };
+    let existence_checker = ExistenceChecker::new(
+        SystemStat,
+        enrich::STAT_TIMEOUT,
+        metrics_userspace.existence_check.clone(),
+    );
+    let rx = enrich::start(&mut task_set, rx, reloader.enrich(), existence_checker);
+    let rx = summary::start(&mut task_set, rx, reloader.summary(), reloader.paths());
+    let rx = pause::records(&mut task_set, rx, pause_records_rx);
 
     output::start(
         &mut task_set,
         rx,
         metrics_userspace.output.clone(),
-        reloader.grpc(),
-        reloader.otel(),
-        reloader.config().json(),
+        Outputs {
+            grpc: reloader.grpc(),
+            otel: reloader.otel(),
+            stdout: reloader.config().stdout(),
+            stdout_format: reloader.config().output.stdout_format(),
+            overflow_policy: reloader.config().output.overflow_policy(),
+            events,
+            shards: reloader.config().output.shards(),
+            shard_depth: reloader.config().output.shard_depth(),
+            sinks,
+        },
     );
 
     rate_limiter.start(&mut task_set);
-    let exporter = Exporter::new(&metrics_userspace, metrics_kernelspace);
-    endpoints::Server::new(exporter, reloader.endpoint(), running_helpers.subscribe()).start();
-    reloader.start(running_helpers.subscribe());
+    let exporter = Exporter::new(metrics_userspace, metrics_kernelspace);
+    let health = HealthMonitor::new(
+        metrics_userspace.output.clone(),
+        reloader.readiness(),
+        pause.subscribe(),
+        running_helpers.subscribe(),
+    );
+    let status = status::Collector::new(
+        metrics_userspace,
+        exporter,
+        bpf,
+        reloader.status(),
+        reloader.digest(),
+    );
+    let endpoints = endpoints::Server::new(
+        status,
+        limits::limits(reloader.config()),
+        inodes,
+        reloader.endpoint(),
+        health.subscribe(),
+        pause,
+        running_helpers.subscribe(),
+    );
+
+    // Helper tasks only stop with fact, they are supervised apart from
+    // the pipeline.
+    let mut supervisor = Supervisor::new();
+    supervisor.restart("endpoints", ENDPOINTS_MAX_RESTARTS, move || {
+        endpoints.clone().start()
+    });
+    supervisor.watch("pause controller", pause_controller.start());
+    supervisor.watch("health monitor", health.start());
+    supervisor.watch(
+        "config metrics",
+        metrics_userspace.config.clone().start(
+            reloader.paths(),
+            reloader.digest(),
+            reloader.status(),
+            running_helpers.subscribe(),
+        ),
+    );
+    if reads_kernel_events(reloader.config()) {
+        supervisor.watch(
+            "path coverage",
+            coverage::start(
+                reloader.paths(),
+                reloader.config().bpf.filesystems.clone(),
+                metrics_userspace.config.clone(),
+                running_helpers.subscribe(),
+            ),
+        );
+    }
+    supervisor.watch(
+        "config reloader",
+        reloader.start(running_helpers.subscribe()),
+    );
 
     let mut sigterm = signal(SignalKind::terminate())?;
     let mut sighup = signal(SignalKind::hangup())?;
+    let deadline = async move {
+        match run_for {
+            Some(run_for) => {
+                sleep(run_for).await;
+                info!("Ran for {run_for:?}, stopping...");
+            }
+            None => std::future::pending().await,
+        }
+    };
+    tokio::pin!(deadline);
     let mut res = loop {
         tokio::select! {
             _ = tokio::signal::ctrl_c() => break Ok(()),
             _ = sigterm.recv() => break Ok(()),
+            _ = &mut deadline => break Ok(()),
             _ = sighup.recv() => config_trigger.notify_one(),
             task_res = task_set.join_next() => {
                 let Some(task_res) = task_res else {
@@ -139,6 +395,7 @@ pub async fn run(config: FactConfig) -> anyhow::Result<()> {
                 };
                 break flatten_task_result(task_res);
             }
+            e = supervisor.failed() => break Err(e),
         }
     };
 
@@ -148,30 +405,138 @@ pub async fn run(config: FactConfig) -> anyhow::Result<()> {
     // receivers.
     let _ = running_pipeline_tx.send(false);
     if res.is_ok() {
-        let join_res = timeout(Duration::from_secs(5), join_all_tasks(task_set)).await;
+        let join_res = timeout(SHUTDOWN_TIMEOUT, join_all_tasks(task_set)).await;
         res = flatten_task_result(join_res);
     }
     let _ = running_helpers.send(false);
+    // Let the endpoints finish serving open connections
+    supervisor.shutdown(SHUTDOWN_TIMEOUT).await;
 
+    if let Some(summary) = generate_summary {
+        summary.report();
+    }
+    drop(instance);
     info!("Exiting...");
     res
 }
 
+/// Whether events are read from the BPF programs, instead of being
+/// generated, scanned or replayed.
+fn reads_kernel_events(config: &FactConfig) -> bool {
+    !config.generate() && !config.inventory() && config.replay().is_none()
+}
+
+/// Tag events with the ID of this instance, for telling them apart
+/// from the ones reported by other instances on the host.
+fn tag_instance(
+    task_set: &mut JoinSet<anyhow::Result<()>>,
+    mut rx: mpsc::Receiver<Event>,
+    id: u32,
+) -> mpsc::Receiver<Event> {
+    let (tx, output) = mpsc::channel(EVENT_CHANNEL_CAPACITY);
+    task_set.spawn(async move {
+        while let Some(mut event) = rx.recv().await {
+            event.set_instance_id(id);
+            if tx.send(event).await.is_err() {
+                break;
+            }
+        }
+        Ok(())
+    });
+    output
+}
+
+/// Forward up to `max_events` events down the pipeline.
+///
+/// The task exits once the limit is reached, which shuts fact down the
+/// same way a signal would, letting outputs send pending events.
+fn limit_events(
+    task_set: &mut JoinSet<anyhow::Result<()>>,
+    mut rx: mpsc::Receiver<Event>,
+    max_events: u64,
+) -> mpsc::Receiver<Event> {
+    let (tx, output) = mpsc::channel(EVENT_CHANNEL_CAPACITY);
+    task_set.spawn(async move {
+        for _ in 0..max_events {
+            let Some(event) = rx.recv().await else {
+                return Ok(());
+            };
+            if tx.send(event).await.is_err() {
+                return Ok(());
+            }
+        }
+        info!("Forwarded {max_events} events, stopping...");
+        Ok(())
+    });
+    output
+}
+
+/// Events coming into the pipeline and the handles on their source.
+struct Input {
+    rx: mpsc::Receiver<Event>,
+    /// Metrics from the BPF programs, if events come from them.
+    metrics_kernelspace: Option<KernelMetrics>,
+    /// Switch for pausing the emission of events.
+    pause_flag: Option<Box<dyn PauseSwitch>>,
+    /// Handle on the BPF programs and maps, for `/debug/bpf`.
+    bpf: Option<bpf::Diagnostics>,
+    /// Lookups in the inode map of the host scanner, for
+    /// `/debug/inode`.
+    inodes: Option<inode_map::QueryHandle>,
+}
+
+impl From<mpsc::Receiver<Event>> for Input {
+    fn from(rx: mpsc::Receiver<Event>) -> Self {
+        Input {
+            rx,
+            metrics_kernelspace: None,
+            pause_flag: None,
+            bpf: None,
+            inodes: None,
+        }
+    }
+}
+
 fn setup_input(
     task_set: &mut JoinSet<anyhow::Result<()>>,
     reloader: &config::reloader::Reloader,
     metrics: &Metrics,
     running: watch::Receiver<bool>,
-) -> anyhow::Result<(Option<KernelMetrics>, mpsc::Receiver<Event>)> {
+) -> anyhow::Result<Input> {
+    if reloader.config().generate() {
+        let rx = generate::start(
+            task_set,
+            reloader.config().generate_options(),
+            metrics.generator.clone(),
+            running,
+        )?;
+        return Ok(Input::from(rx));
+    }
+
+    if reloader.config().inventory() {
+        let rx = inventory::start(
+            task_set,
+            reloader.config().paths().to_vec(),
+            reloader.config().inventory_limit(),
+            running,
+        );
+        return Ok(Input::from(rx));
+    }
+
     match reloader.config().replay() {
         Some(replay_file) => {
-            let rx = replay::start(task_set, replay_file, running)?;
-            Ok((None, rx))
+            let rx = replay::start(
+                task_set,
+                replay_file,
+                reloader.config().replay_options(),
+                running,
+            )?;
+            Ok(Input::from(rx))
         }
         None => {
             if !reloader.config().skip_pre_flight() {
                 debug!("Performing pre-flight checks");
-                pre_flight().context("Pre-flight checks failed")?;
+                pre_flight().context(Fatal::PreFlight)?;
             } else {
                 debug!("Skipping pre-flight checks");
             }
@@ -186,24 +551,125 @@ fn bpf_input(
     reloader: &config::reloader::Reloader,
     running: watch::Receiver<bool>,
     metrics_userspace: &Metrics,
-) -> anyhow::Result<(Option<KernelMetrics>, mpsc::Receiver<Event>)> {
+) -> anyhow::Result<Input> {
     let (mut bpf, rx) = Bpf::new(
-        reloader.paths(),
-        &reloader.config().bpf,
+        reloader,
         running.clone(),
         metrics_userspace.bpf_worker.clone(),
-    )?;
-    let metrics_kernelspace = KernelMetrics::new(bpf.take_metrics()?);
+        metrics_userspace.lineage_cache.clone(),
+    )
+    .context(Fatal::BpfLoad)?;
+    let diagnostics = bpf.diagnostics();
+    let metrics_kernelspace =
+        KernelMetrics::new(bpf.take_metrics()?, &bpf.loaded_hooks(), bpf.ringbuf_size());
 
+    let budget = Budget::new(
+        reloader.maintenance(),
+        Arc::new(SystemClock),
+        metrics_userspace.maintenance.clone(),
+    );
     let (host_scanner, rx) = HostScanner::new(
         &mut bpf,
         rx,
         reloader.paths(),
         reloader.scan_interval(),
         metrics_userspace.host_scanner.clone(),
+        budget.pacer(MaintenanceTask::HostScanner),
     )?;
 
+    let pause_flag = bpf.take_pause_flag()?;
+
     bpf.start(task_set);
+    let inodes = host_scanner.query_handle();
     host_scanner.start(task_set);
-    Ok((Some(metrics_kernelspace), rx))
+    Ok(Input {
+        rx,
+        metrics_kernelspace: Some(metrics_kernelspace),
+        pause_flag: Some(Box::new(pause_flag)),
+        bpf: Some(diagnostics),
+        inodes: Some(inodes),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{io::Write, time::Instant};
+
+    use fact_ebpf::{PATH_MAX, event_t};
+
+    use super::*;
+    use crate::{config::ReplayOptions, event::test_utils::string_to_c_char_array};
+
+    fn events(n: u64, step: Duration) -> Vec<Event> {
+        (0..n)
+            .map(|i| {
+                let event = event_t {
+                    timestamp: i * step.as_nanos() as u64,
+                    filename: string_to_c_char_array::<{ PATH_MAX as usize }>("/etc/passwd"),
+                    ..Default::default()
+                };
+                Event::try_from(&event).expect("Failed to parse event")
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn fatal_config() {
+        let config =
+            FactConfig::try_from("host_mount: /nonexistent/fact").expect("Failed to parse config");
+        let e = run(config)
+            .await
+            .expect_err("fact ran without its host mount");
+        assert_eq!(exit::code(&e), exit::EXIT_CONFIG);
+        assert_eq!(
+            format!("{e:#}"),
+            "Invalid configuration: invalid host mount /nonexistent/fact: No such file or directory (os error 2)"
+        );
+    }
+
+    #[tokio::test]
+    async fn max_events() {
+        let mut task_set = JoinSet::new();
+        let (tx, rx) = mpsc::channel(100);
+        let mut rx = limit_events(&mut task_set, rx, 3);
+
+        for event in events(10, Duration::ZERO) {
+            tx.send(event).await.expect("Failed to send event");
+        }
+
+        let mut received = 0;
+        while rx.recv().await.is_some() {
+            received += 1;
+        }
+        assert_eq!(received, 3);
+        join_all_tasks(task_set).await.expect("Limit task failed");
+    }
+
+    #[tokio::test]
+    async fn run_for() {
+        // Paced replay of events one hour apart, only the deadline can
+        // stop fact in a reasonable time.
+        let mut file = tempfile::NamedTempFile::new().expect("Failed to create temp file");
+        for event in events(2, Duration::from_secs(3600)) {
+            let line = serde_json::to_string(&event).expect("Failed to serialize event");
+            writeln!(file, "{line}").expect("Failed to write event");
+        }
+
+        let yaml = format!(
+            "replay: {}\nrun_for: 0.2\njson: true\nhotreload: false",
+            file.path().display()
+        );
+        let mut config = FactConfig::try_from(yaml.as_str()).expect("Failed to parse config");
+        config.set_replay_options(ReplayOptions {
+            pace: Some(true),
+            ..Default::default()
+        });
+
+        let start = Instant::now();
+        timeout(Duration::from_secs(5), run(config))
+            .await
+            .expect("fact did not stop")
+            .expect("fact failed");
+        assert!(start.elapsed() >= Duration::from_millis(200));
+    }
 }
@@ -0,0 +1,187 @@
+//! Limits on what fact can capture and hold.
+//!
+//! Compile time limits come from the constants shared with the BPF
+//! programs, runtime ones from the configuration. Both are listed by
+//! `fact limits` and `GET /debug/limits`.
+
+use std::fmt::Write;
+
+use serde::Serialize;
+
+use crate::config::FactConfig;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LimitKind {
+    /// Fixed when fact is built.
+    Compile,
+    /// Set through the configuration, changes take effect on restart.
+    Runtime,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Limit {
+    pub name: &'static str,
+    pub kind: LimitKind,
+    pub value: u64,
+    pub unit: &'static str,
+    pub description: &'static str,
+}
+
+/// A limit taken from a constant shared with the BPF programs.
+macro_rules! kernel_constant {
+    ($name:ident, $unit:literal, $description:literal) => {
+        Limit {
+            name: stringify!($name),
+            kind: LimitKind::Compile,
+            value: fact_ebpf::$name as u64,
+            unit: $unit,
+            description: $description,
+        }
+    };
+}
+
+const COMPILE_TIME: &[Limit] = &[
+    kernel_constant!(
+        PATH_MAX,
+        "bytes",
+        "Longest path captured for files and executables"
+    ),
+    kernel_constant!(TASK_COMM_LEN, "bytes", "Longest process name captured"),
+    kernel_constant!(
+        ARGS_MAX,
+        "bytes",
+        "Most bytes of process arguments captured"
+    ),
+    kernel_constant!(LINEAGE_MAX, "processes", "Ancestors captured for a process"),
+    kernel_constant!(
+        XATTR_NAME_MAX_LEN,
+        "bytes",
+        "Longest extended attribute name captured"
+    ),
+    kernel_constant!(
+        LPM_SIZE_MAX,
+        "bytes",
+        "Longest prefix of a monitored path matched in the kernel"
+    ),
+    kernel_constant!(
+        PATH_PREFIX_MAX_ENTRIES,
+        "entries",
+        "Distinct prefixes of monitored paths"
+    ),
+    kernel_constant!(
+        FACT_MAX_ACL_ENTRIES,
+        "entries",
+        "ACL entries captured for a change"
+    ),
+    Limit {
+        name: "EVENT_CHANNEL_CAPACITY",
+        kind: LimitKind::Compile,
+        value: crate::EVENT_CHANNEL_CAPACITY as u64,
+        unit: "events",
+        description: "Events buffered between stages of the pipeline",
+    },
+];
+
+/// All limits, with the runtime ones taken from `config`.
+pub fn limits(config: &FactConfig) -> Vec<Limit> {
+    let runtime = [
+        Limit {
+            name: "bpf.ringbuf_size",
+            kind: LimitKind::Runtime,
+            value: u64::from(config.bpf.ringbuf_size()) * 1024,
+            unit: "bytes",
+            description: "Size of the ringbuffer events are sent from the kernel through",
+        },
+        Limit {
+            name: "bpf.inodes_max",
+            kind: LimitKind::Runtime,
+            value: config.bpf.inodes_max().into(),
+            unit: "entries",
+            description: "Inodes of monitored files tracked by the kernel",
+        },
+    ];
+    COMPILE_TIME.iter().cloned().chain(runtime).collect()
+}
+
+/// Render limits as an aligned, human readable table.
+pub fn table(limits: &[Limit]) -> String {
+    let name_width = limits.iter().map(|l| l.name.len()).max().unwrap_or(0);
+    let value_width = limits
+        .iter()
+        .map(|l| l.value.to_string().len())
+        .max()
+        .unwrap_or(0);
+
+    let mut out = String::new();
+    for limit in limits {
+        let kind = match limit.kind {
+            LimitKind::Compile => "compile",
+            LimitKind::Runtime => "runtime",
+        };
+        let _ = writeln!(
+            out,
+            "{:name_width$}  {:<7}  {:>value_width$} {:<9}  {}",
+            limit.name, kind, limit.value, limit.unit, limit.description
+        );
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Constants in the shared header that are not limits.
+    const NOT_LIMITS: &[&str] = &["ACL_UNDEFINED_ID"];
+
+    #[test]
+    fn all_constants_listed() {
+        // Any constant added to the header must either be reported or
+        // explicitly ignored.
+        let header = include_str!("../../fact-ebpf/src/bpf/types.h");
+        let mut defined = header
+            .lines()
+            .filter_map(|l| l.strip_prefix("#define "))
+            .filter_map(|l| l.split_whitespace().next())
+            .filter(|name| !NOT_LIMITS.contains(name))
+            .collect::<Vec<_>>();
+        defined.sort();
+
+        let mut listed = COMPILE_TIME
+            .iter()
+            .map(|l| l.name)
+            .filter(|name| *name != "EVENT_CHANNEL_CAPACITY")
+            .collect::<Vec<_>>();
+        listed.sort();
+        assert_eq!(defined, listed);
+    }
+
+    #[test]
+    fn runtime() {
+        let config = FactConfig::try_from("bpf:\n  ringbuf_size: 16384\n  inodes_max: 1000")
+            .expect("Failed to parse config");
+        let limits = limits(&config);
+        let value = |name| limits.iter().find(|l| l.name == name).unwrap().value;
+        assert_eq!(value("bpf.ringbuf_size"), 16 * 1024 * 1024);
+        assert_eq!(value("bpf.inodes_max"), 1000);
+        assert_eq!(value("PATH_MAX"), 4096);
+    }
+
+    #[test]
+    fn render() {
+        let limits = limits(&FactConfig::default());
+        let table = table(&limits);
+        assert_eq!(table.lines().count(), limits.len());
+        assert!(
+            table
+                .lines()
+                .any(|l| l.starts_with("PATH_MAX ") && l.contains(" 4096 bytes "))
+        );
+
+        let json = serde_json::to_value(&limits).unwrap();
+        assert_eq!(json[0]["name"], "PATH_MAX");
+        assert_eq!(json[0]["kind"], "compile");
+        assert_eq!(json[0]["value"], 4096);
+    }
+}
@@ -1,9 +1,45 @@
-use fact::config::FactConfig;
+use std::time::Duration;
+
+use anyhow::Context;
+use fact::{
+    config::{self, FactConfig, Offline},
+    exit::{self, Counters, Fatal, Summary},
+};
 
 #[tokio::main]
-async fn main() -> anyhow::Result<()> {
-    fact::init_log()?;
-    let config = FactConfig::new()?;
+async fn main() {
+    if let Err(e) = fact::init_log() {
+        eprintln!("Failed to initialize logging: {e:#}");
+        std::process::exit(exit::EXIT_FAILURE);
+    }
+    // Offline tools must work when the configuration is broken, they
+    // run before it is loaded
+    if let Some(tool) = Offline::from_cli() {
+        let res = match tool {
+            Offline::PrintConfigFiles => {
+                print!("{}", config::describe_files());
+                Ok(())
+            }
+            Offline::Schema => config::schema_json().map(|schema| println!("{schema}")),
+            Offline::Decode { file, host } => fact::decode::run(&file, &host),
+        };
+        if let Err(e) = res {
+            eprintln!("{e:#}");
+            std::process::exit(exit::EXIT_FAILURE);
+        }
+        return;
+    }
+    let config = match FactConfig::new().context(Fatal::Config) {
+        Ok(config) => config,
+        Err(e) => {
+            Summary::new(&e, Duration::ZERO, Counters::default()).log();
+            std::process::exit(exit::code(&e));
+        }
+    };
 
-    fact::run(config).await
+    // Errors while running are summarized by fact along with its
+    // counters
+    if let Err(e) = fact::run(config).await {
+        std::process::exit(exit::code(&e));
+    }
 }
@@ -0,0 +1,227 @@
+use std::path::PathBuf;
+
+use log::info;
+use prometheus_client::{
+    encoding::{EncodeLabelSet, EncodeLabelValue, LabelValueEncoder},
+    metrics::{counter::Counter, family::Family, gauge::Gauge},
+    registry::Registry,
+};
+use tokio::{sync::watch, task::JoinHandle};
+
+use crate::{
+    config::reloader::ReloadStatus,
+    coverage::{PathCoverage, Support},
+};
+
+#[derive(Clone, Hash, Eq, Debug, PartialEq, EncodeLabelSet)]
+struct PathLabel {
+    path: String,
+}
+
+impl EncodeLabelValue for Support {
+    fn encode(&self, encoder: &mut LabelValueEncoder) -> Result<(), std::fmt::Error> {
+        encoder.write_str(self.as_str())
+    }
+}
+
+#[derive(Clone, Hash, Eq, Debug, PartialEq, EncodeLabelSet)]
+struct CoverageLabels {
+    path: String,
+    fs: String,
+    status: Support,
+}
+
+#[derive(Clone, Hash, Eq, Debug, PartialEq, EncodeLabelSet)]
+struct DigestLabel {
+    digest: String,
+}
+
+#[derive(Debug, Clone, Default)]
+/// The configuration fact is running with, exported as info-style
+/// metrics so it can be looked up after the fact.
+pub struct ConfigMetrics {
+    monitored_paths: Family<PathLabel, Gauge>,
+    path_coverage: Family<CoverageLabels, Gauge>,
+    digest: Family<DigestLabel, Gauge>,
+    reload_failures: Counter,
+}
+
+impl ConfigMetrics {
+    pub(super) fn register(&self, reg: &mut Registry) {
+        reg.register(
+            "monitored_paths",
+            "Path prefixes currently monitored, one series set to 1 per prefix",
+            self.monitored_paths.clone(),
+        );
+        reg.register(
+            "path_coverage",
+            "Filesystem of the monitored paths and how well it is supported, one series set to 1 per path",
+            self.path_coverage.clone(),
+        );
+        reg.register(
+            "config_hash",
+            "Digest of the effective configuration, as the label of the only series",
+            self.digest.clone(),
+        );
+        reg.register(
+            "config_unknown_fields",
+            "Unknown fields ignored in the configuration files with strict_config disabled",
+            crate::config::UNKNOWN_FIELDS.clone(),
+        );
+        reg.register(
+            "config_reload_failures",
+            "Configuration reloads that failed, the previous configuration is kept running",
+            self.reload_failures.clone(),
+        );
+    }
+
+    fn set_paths(&self, paths: &[PathBuf]) {
+        self.monitored_paths.clear();
+        for path in paths {
+            self.monitored_paths
+                .get_or_create(&PathLabel {
+                    path: path.display().to_string(),
+                })
+                .set(1);
+        }
+    }
+
+    /// Export the filesystems the monitored paths are on, replacing
+    /// the previous ones.
+    pub fn set_coverage(&self, coverage: &[PathCoverage]) {
+        self.path_coverage.clear();
+        for c in coverage {
+            self.path_coverage
+                .get_or_create(&CoverageLabels {
+                    path: c.path.display().to_string(),
+                    fs: c.fs_type.clone(),
+                    status: c.support,
+                })
+                .set(1);
+        }
+    }
+
+    fn set_reload_failures(&self, failures: u64) {
+        self.reload_failures
+            .inc_by(failures.saturating_sub(self.reload_failures.get()));
+    }
+
+    fn set_digest(&self, digest: &str) {
+        self.digest.clear();
+        self.digest
+            .get_or_create(&DigestLabel {
+                digest: digest.to_owned(),
+            })
+            .set(1);
+    }
+
+    /// Keep the metrics in sync with the configuration published by
+    /// the reloader.
+    ///
+    /// The current values are exported before returning.
+    pub fn start(
+        self,
+        mut paths: watch::Receiver<Vec<PathBuf>>,
+        mut digest: watch::Receiver<String>,
+        mut status: watch::Receiver<ReloadStatus>,
+        mut running: watch::Receiver<bool>,
+    ) -> JoinHandle<()> {
+        self.set_paths(&paths.borrow_and_update());
+        self.set_digest(&digest.borrow_and_update());
+        self.set_reload_failures(status.borrow_and_update().failures);
+
+        tokio::spawn(async move {
+            loop {
+                tokio::select! {
+                    Ok(_) = paths.changed() => self.set_paths(&paths.borrow_and_update()),
+                    Ok(_) = digest.changed() => self.set_digest(&digest.borrow_and_update()),
+                    Ok(_) = status.changed() => {
+                        self.set_reload_failures(status.borrow_and_update().failures);
+                    }
+                    _ = running.changed() => {
+                        if !*running.borrow() {
+                            info!("Stopping config metrics...");
+                            return;
+                        }
+                    }
+                }
+            }
+        })
+    }
+
+    #[cfg(test)]
+    fn paths(&self) -> Vec<String> {
+        let mut paths = Vec::new();
+        for path in ["/etc", "/usr/bin", "/var/lib"] {
+            let label = PathLabel {
+                path: path.to_owned(),
+            };
+            if self.monitored_paths.get(&label).is_some() {
+                paths.push(path.to_owned());
+            }
+        }
+        paths
+    }
+
+    #[cfg(test)]
+    fn has_digest(&self, digest: &str) -> bool {
+        self.digest
+            .get(&DigestLabel {
+                digest: digest.to_owned(),
+            })
+            .is_some()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use tokio::time::timeout;
+
+    use super::*;
+    use crate::config::{FactConfig, reloader::Reloader};
+
+    #[tokio::test]
+    async fn reload() {
+        let config = |yaml: &str| FactConfig::try_from(yaml).unwrap();
+        let mut reloader = Reloader::from(config("paths: [/etc, /usr/bin]"));
+        let (running, running_rx) = watch::channel(true);
+        let metrics = ConfigMetrics::default();
+        let handle = metrics.clone().start(
+            reloader.paths(),
+            reloader.digest(),
+            reloader.status(),
+            running_rx,
+        );
+        assert_eq!(metrics.paths(), ["/etc", "/usr/bin"]);
+        assert!(metrics.has_digest(&reloader.config().digest()));
+
+        let new = config("paths: [/etc, /var/lib]");
+        let digest = new.digest();
+        reloader.apply(new);
+        timeout(Duration::from_secs(10), async {
+            while !metrics.has_digest(&digest) || metrics.paths() != ["/etc", "/var/lib"] {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .expect("Timed out waiting for the reload");
+        assert!(!metrics.has_digest(&config("paths: [/etc, /usr/bin]").digest()));
+
+        // Failures reported while applying the configuration
+        reloader
+            .status_reporter()
+            .send_modify(|status| status.failed("rejected".to_string()));
+        timeout(Duration::from_secs(10), async {
+            while metrics.reload_failures.get() != 1 {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .expect("Timed out waiting for the failure");
+
+        running.send_replace(false);
+        handle.await.unwrap();
+    }
+}
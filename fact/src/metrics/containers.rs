@@ -0,0 +1,349 @@
+//! Events by container, for telling which workload a spike of events
+//! comes from.
+//!
+//! Only the busiest containers are tracked, with the space-saving
+//! algorithm: once `capacity` containers are tracked, a new one takes
+//! the place of the one with the fewest events, starting from its
+//! count. The count of a container is never below its actual number of
+//! events and at most `error` above it, containers busier than the
+//! capacity-th one are always tracked. Containers without events for
+//! `MAX_AGE` are forgotten, along with their series.
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::{Arc, Mutex, PoisonError},
+    time::{Duration, Instant},
+};
+
+use prometheus_client::{
+    encoding::EncodeLabelSet,
+    metrics::{counter::Counter, family::Family},
+    registry::Registry,
+};
+use serde::Serialize;
+
+use crate::event::Event;
+
+/// Containers tracked unless configured otherwise.
+pub const DEFAULT_CAPACITY: usize = 20;
+
+/// How long a container is tracked after its last event.
+pub const MAX_AGE: Duration = Duration::from_secs(600);
+
+/// How often containers past `MAX_AGE` are looked for.
+const EXPIRY_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Container ID reported for processes running on the host.
+const HOST: &str = "host";
+
+#[derive(Clone, Hash, Eq, Debug, PartialEq, EncodeLabelSet)]
+struct ContainerLabel {
+    container_id: String,
+}
+
+#[derive(Debug)]
+struct Entry {
+    /// Shared with the series of the container.
+    events: Counter,
+    /// Events counted before the container was tracked.
+    error: u64,
+    /// Events by type since the container is tracked.
+    by_type: BTreeMap<&'static str, u64>,
+    last_seen: Instant,
+}
+
+#[derive(Debug)]
+struct TopK {
+    capacity: usize,
+    entries: HashMap<String, Entry>,
+    last_expiry: Instant,
+}
+
+/// Events of a tracked container, as served by the debug endpoint.
+#[derive(Debug, PartialEq, Serialize)]
+pub struct ContainerCount {
+    pub container_id: String,
+    pub events: u64,
+    /// How much `events` may be above the actual count.
+    pub error: u64,
+    pub by_type: BTreeMap<&'static str, u64>,
+    pub last_seen_seconds: u64,
+}
+
+/// Counts of the busiest containers, exported as
+/// `events_by_container`, one series per tracked container.
+#[derive(Debug, Clone)]
+pub struct ContainerCounts {
+    top: Arc<Mutex<TopK>>,
+    family: Family<ContainerLabel, Counter>,
+}
+
+impl Default for ContainerCounts {
+    fn default() -> Self {
+        ContainerCounts {
+            top: Arc::new(Mutex::new(TopK {
+                capacity: DEFAULT_CAPACITY,
+                entries: HashMap::new(),
+                last_expiry: Instant::now(),
+            })),
+            family: Family::default(),
+        }
+    }
+}
+
+impl ContainerCounts {
+    pub(super) fn register(&self, reg: &mut Registry) {
+        reg.register(
+            "events_by_container",
+            "Events sent to the outputs by the busiest containers, processes on the host are counted as the host container",
+            self.family.clone(),
+        );
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, TopK> {
+        // Every update leaves the counts consistent
+        self.top.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Track up to `capacity` containers, 0 disables the counts.
+    pub fn set_capacity(&self, capacity: usize) {
+        let mut top = self.lock();
+        top.capacity = capacity;
+        while top.entries.len() > capacity {
+            self.evict(&mut top);
+        }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.lock().capacity
+    }
+
+    /// Count `event` for its container.
+    pub fn record(&self, event: &Event) {
+        self.record_at(event.get_container_id(), event.event_type(), Instant::now());
+    }
+
+    fn record_at(&self, container_id: Option<&str>, event_type: &'static str, now: Instant) {
+        let mut top = self.lock();
+        if top.capacity == 0 {
+            return;
+        }
+        if now.duration_since(top.last_expiry) >= EXPIRY_INTERVAL {
+            self.expire(&mut top, now);
+        }
+
+        let container_id = container_id.unwrap_or(HOST);
+        if let Some(entry) = top.entries.get_mut(container_id) {
+            entry.events.inc();
+            *entry.by_type.entry(event_type).or_default() += 1;
+            entry.last_seen = now;
+            return;
+        }
+
+        let error = match top.entries.len() >= top.capacity {
+            true => self.evict(&mut top),
+            false => 0,
+        };
+        let events = self
+            .family
+            .get_or_create(&ContainerLabel {
+                container_id: container_id.to_owned(),
+            })
+            .clone();
+        events.inc_by(error + 1);
+        top.entries.insert(
+            container_id.to_owned(),
+            Entry {
+                events,
+                error,
+                by_type: BTreeMap::from([(event_type, 1)]),
+                last_seen: now,
+            },
+        );
+    }
+
+    /// Stop tracking the container with the fewest events, returning
+    /// its count.
+    fn evict(&self, top: &mut TopK) -> u64 {
+        let Some((container_id, events)) = top
+            .entries
+            .iter()
+            .map(|(id, entry)| (id, entry.events.get()))
+            .min_by_key(|(_, events)| *events)
+        else {
+            return 0;
+        };
+        let container_id = container_id.clone();
+        self.remove(top, &container_id);
+        events
+    }
+
+    fn expire(&self, top: &mut TopK, now: Instant) {
+        let expired = top
+            .entries
+            .iter()
+            .filter(|(_, entry)| now.duration_since(entry.last_seen) >= MAX_AGE)
+            .map(|(id, _)| id.clone())
+            .collect::<Vec<_>>();
+        for container_id in expired {
+            self.remove(top, &container_id);
+        }
+        top.last_expiry = now;
+    }
+
+    fn remove(&self, top: &mut TopK, container_id: &str) {
+        top.entries.remove(container_id);
+        self.family.remove(&ContainerLabel {
+            container_id: container_id.to_owned(),
+        });
+    }
+
+    /// The tracked containers, busiest first.
+    pub fn top(&self) -> Vec<ContainerCount> {
+        self.top_at(Instant::now())
+    }
+
+    fn top_at(&self, now: Instant) -> Vec<ContainerCount> {
+        let mut top = self.lock();
+        self.expire(&mut top, now);
+        let mut counts = top
+            .entries
+            .iter()
+            .map(|(container_id, entry)| ContainerCount {
+                container_id: container_id.clone(),
+                events: entry.events.get(),
+                error: entry.error,
+                by_type: entry.by_type.clone(),
+                last_seen_seconds: now.duration_since(entry.last_seen).as_secs(),
+            })
+            .collect::<Vec<_>>();
+        counts.sort_by(|a, b| {
+            b.events
+                .cmp(&a.events)
+                .then_with(|| a.container_id.cmp(&b.container_id))
+        });
+        counts
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use prometheus_client::encoding::text::encode;
+
+    use super::*;
+
+    fn events(counts: &[ContainerCount]) -> Vec<(&str, u64)> {
+        counts
+            .iter()
+            .map(|c| (c.container_id.as_str(), c.events))
+            .collect()
+    }
+
+    #[test]
+    fn counts() {
+        let counts = ContainerCounts::default();
+        let now = Instant::now();
+        for _ in 0..3 {
+            counts.record_at(Some("aaaaaaaaaaaa"), "open", now);
+        }
+        counts.record_at(Some("aaaaaaaaaaaa"), "creation", now);
+        counts.record_at(None, "open", now);
+
+        let top = counts.top_at(now);
+        assert_eq!(
+            top[0],
+            ContainerCount {
+                container_id: "aaaaaaaaaaaa".to_string(),
+                events: 4,
+                error: 0,
+                by_type: BTreeMap::from([("creation", 1), ("open", 3)]),
+                last_seen_seconds: 0,
+            }
+        );
+        assert_eq!(events(&top), [("aaaaaaaaaaaa", 4), (HOST, 1)]);
+    }
+
+    #[test]
+    fn churn() {
+        let counts = ContainerCounts::default();
+        counts.set_capacity(10);
+        let now = Instant::now();
+
+        // Two busy containers among many short lived ones
+        for i in 0..1000 {
+            counts.record_at(Some("busy1"), "open", now);
+            if i % 2 == 0 {
+                counts.record_at(Some("busy2"), "open", now);
+            }
+            if i % 4 == 0 {
+                counts.record_at(Some(&format!("short{}", i / 4)), "open", now);
+            }
+        }
+
+        let top = counts.top_at(now);
+        assert_eq!(top.len(), 10);
+        assert_eq!(events(&top[..2]), [("busy1", 1000), ("busy2", 500)]);
+        assert_eq!((top[0].error, top[1].error), (0, 0));
+        // The last one took the place of the least busy one
+        let last = top.iter().find(|c| c.container_id == "short249").unwrap();
+        assert_eq!(last.events, last.error + 1);
+        assert!(top[2..].iter().all(|c| c.events < 500));
+    }
+
+    #[test]
+    fn cardinality() {
+        let counts = ContainerCounts::default();
+        counts.set_capacity(5);
+        let mut registry = Registry::default();
+        counts.register(&mut registry);
+        let now = Instant::now();
+        for i in 0..100 {
+            counts.record_at(Some(&format!("container{i}")), "open", now);
+        }
+
+        let mut scrape = String::new();
+        encode(&mut scrape, &registry).unwrap();
+        let series = scrape
+            .lines()
+            .filter(|l| l.starts_with("events_by_container_total{"))
+            .collect::<Vec<_>>();
+        assert_eq!(series.len(), 5, "{scrape}");
+        for c in counts.top_at(now) {
+            let line = format!(
+                "events_by_container_total{{container_id=\"{}\"}} {}",
+                c.container_id, c.events
+            );
+            assert!(series.contains(&line.as_str()), "{line} not in {scrape}");
+        }
+
+        // Shrinking drops the extra series
+        counts.set_capacity(2);
+        let mut scrape = String::new();
+        encode(&mut scrape, &registry).unwrap();
+        assert_eq!(scrape.matches("events_by_container_total{").count(), 2);
+
+        // And disabling all of them
+        counts.set_capacity(0);
+        counts.record_at(Some("container0"), "open", now);
+        assert!(counts.top_at(now).is_empty());
+    }
+
+    #[test]
+    fn aging() {
+        let counts = ContainerCounts::default();
+        let now = Instant::now();
+        counts.record_at(Some("old"), "open", now);
+        counts.record_at(Some("recent"), "open", now + MAX_AGE / 2);
+
+        let later = now + MAX_AGE + EXPIRY_INTERVAL;
+        assert_eq!(events(&counts.top_at(later)), [("recent", 1)]);
+
+        // Counted from scratch if seen again
+        counts.record_at(Some("old"), "open", later);
+        assert_eq!(events(&counts.top_at(later)), [("old", 1), ("recent", 1)]);
+
+        // Expired on new events too
+        counts.record_at(Some("other"), "open", later + MAX_AGE);
+        assert_eq!(events(&counts.top_at(later + MAX_AGE)), [("other", 1)]);
+    }
+}
@@ -1,14 +1,21 @@
-use std::sync::Arc;
+use std::sync::{Arc, Mutex, PoisonError};
 
+use fact_ebpf::metrics_by_hook_t;
 use log::warn;
 use prometheus_client::{encoding::text::encode, registry::Registry};
 
 use super::{Metrics, kernel_metrics::KernelMetrics};
 
+/// Encodes all metrics in the Prometheus text format.
+///
+/// The exporter is cheap to clone and safe to use from concurrent
+/// scrapes: kernel metrics are refreshed right before encoding and a
+/// lock is held across both steps, so a scrape never sees counters
+/// another scrape is in the middle of refreshing.
 #[derive(Clone)]
 pub struct Exporter {
     registry: Arc<Registry>,
-    kernel_metrics: Option<Arc<KernelMetrics>>,
+    kernel_metrics: Option<Arc<Mutex<KernelMetrics>>>,
 }
 
 impl Exporter {
@@ -18,7 +25,7 @@ impl Exporter {
         if let Some(metrics_kernel) = &metrics_kernel {
             metrics_kernel.register(&mut registry);
         }
-        let kernel_metrics = metrics_kernel.map(Arc::new);
+        let kernel_metrics = metrics_kernel.map(|km| Arc::new(Mutex::new(km)));
         let registry = Arc::new(registry);
         Exporter {
             registry,
@@ -26,14 +33,148 @@ impl Exporter {
         }
     }
 
+    /// Build an exporter with its own registry, reading kernel metrics
+    /// from `source` with every hook enabled.
+    #[cfg(test)]
+    pub fn for_tests(
+        metrics_user: &Metrics,
+        source: impl super::kernel_metrics::KernelMetricsSource + 'static,
+    ) -> Self {
+        let metrics_kernel = KernelMetrics::new(source, &KernelMetrics::hooks(), 8 << 20);
+        Exporter::new(metrics_user, Some(metrics_kernel))
+    }
+
     pub fn encode(&self) -> anyhow::Result<String> {
+        let _guard = self.kernel_metrics.as_ref().map(|km| {
+            // Collection never leaves the counters in a state worse
+            // than the next collection can fix, ignore poisoning.
+            let km = km.lock().unwrap_or_else(PoisonError::into_inner);
+            if let Err(e) = km.collect() {
+                warn!("Failed to collect kernel metrics: {e}");
+            }
+            km
+        });
+
         let mut buf = String::new();
-        if let Some(ref km) = self.kernel_metrics
-            && let Err(e) = km.collect()
-        {
-            warn!("Failed to collect kernel metrics: {e}");
-        }
         encode(&mut buf, &self.registry)?;
         Ok(buf)
     }
+
+    /// Read the counters of the enabled hooks, if events come from the
+    /// BPF programs.
+    pub fn hook_counters(&self) -> Option<anyhow::Result<Vec<(&'static str, metrics_by_hook_t)>>> {
+        self.kernel_metrics.as_ref().map(|km| {
+            km.lock()
+                .unwrap_or_else(PoisonError::into_inner)
+                .hook_counters()
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::HashMap,
+        sync::atomic::{AtomicU64, Ordering},
+        thread,
+    };
+
+    use fact_ebpf::metrics_t;
+
+    use crate::metrics::{DropReason, kernel_metrics::KernelMetricsSource};
+
+    use super::*;
+
+    /// Reports every counter of file_open as the number of times it
+    /// was read so far.
+    #[derive(Default)]
+    struct CountingSource(AtomicU64);
+
+    impl KernelMetricsSource for CountingSource {
+        fn read(&self) -> anyhow::Result<metrics_t> {
+            let n = self.0.fetch_add(1, Ordering::Relaxed) + 1;
+            let mut metrics = metrics_t::default();
+            let hook = &mut metrics.file_open;
+            hook.total = n;
+            hook.added = n;
+            hook.error = n;
+            hook.ignored = n;
+            hook.ringbuffer_full = n;
+            hook.blocked = n;
+            hook.paused = n;
+            hook.self_activity = n;
+            Ok(metrics)
+        }
+    }
+
+    /// Values of the file_open counters in an encoded scrape, by label.
+    fn file_open_counters(scrape: &str) -> HashMap<&str, u64> {
+        scrape
+            .lines()
+            .filter_map(|l| l.strip_prefix("stackrox_fact_kernel_file_open_events_total{label=\""))
+            .map(|l| {
+                let (label, value) = l.split_once("\"} ").unwrap();
+                (label, value.parse().unwrap())
+            })
+            .collect()
+    }
+
+    #[test]
+    fn kernel_metrics() {
+        let exporter = Exporter::for_tests(&Metrics::new(), CountingSource::default());
+        let scrape = exporter.encode().unwrap();
+        let counters = file_open_counters(&scrape);
+        assert_eq!(counters.len(), 8, "{scrape}");
+        assert!(counters.values().all(|v| *v == 1), "{counters:?}");
+        assert!(scrape.contains("stackrox_fact_kernel_hook_enabled{hook=\"path_chmod\"} 1"));
+        assert!(scrape.contains("stackrox_fact_kernel_ringbuf_size_bytes 8388608"));
+
+        let scrape = exporter.encode().unwrap();
+        let counters = file_open_counters(&scrape);
+        assert!(counters.values().all(|v| *v == 2), "{counters:?}");
+    }
+
+    #[test]
+    fn dropped_reasons() {
+        let metrics = Metrics::new();
+        metrics.bpf_worker.dropped(DropReason::ParseError);
+        metrics.bpf_worker.dropped(DropReason::Filtered);
+        metrics.bpf_worker.dropped(DropReason::Filtered);
+        metrics.output.grpc.dropped_n(DropReason::StreamClosed, 3);
+        let exporter = Exporter::for_tests(&metrics, CountingSource::default());
+        let scrape = exporter.encode().unwrap();
+
+        // The total is kept along with the counts by reason
+        for line in [
+            "stackrox_fact_bpf_events_total{label=\"Dropped\"} 3",
+            "stackrox_fact_bpf_events_dropped_total{reason=\"parse_error\"} 1",
+            "stackrox_fact_bpf_events_dropped_total{reason=\"filtered\"} 2",
+            "stackrox_fact_output_grpc_events_total{label=\"Dropped\"} 3",
+            "stackrox_fact_output_grpc_events_dropped_total{reason=\"stream_closed\"} 3",
+        ] {
+            assert!(scrape.lines().any(|l| l == line), "{line} not in {scrape}");
+        }
+        // Counters that never drop have no reasons
+        assert!(!scrape.contains("generator_events_dropped"), "{scrape}");
+    }
+
+    #[test]
+    fn concurrent_scrapes() {
+        let exporter = Exporter::for_tests(&Metrics::new(), CountingSource::default());
+        thread::scope(|s| {
+            for _ in 0..8 {
+                s.spawn(|| {
+                    for _ in 0..200 {
+                        let scrape = exporter.encode().unwrap();
+                        let counters = file_open_counters(&scrape);
+                        // A scrape racing with a collection would see
+                        // missing labels or values of different reads.
+                        assert_eq!(counters.len(), 8, "{scrape}");
+                        let first = counters["Total"];
+                        assert!(counters.values().all(|v| *v == first), "{counters:?}");
+                    }
+                });
+            }
+        });
+    }
 }
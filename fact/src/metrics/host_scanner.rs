@@ -1,6 +1,6 @@
 use prometheus_client::{
     encoding::{EncodeLabelSet, EncodeLabelValue},
-    metrics::{counter::Counter, family::Family},
+    metrics::{counter::Counter, family::Family, gauge::Gauge},
     registry::Registry,
 };
 
@@ -29,6 +29,8 @@ pub struct ScanEvents {
 pub struct HostScannerMetrics {
     pub events: EventCounter,
     pub scan: Family<ScanEvents, Counter<u64>>,
+    pub inode_map_entries: Gauge,
+    pub inode_map_bytes: Gauge,
 }
 
 impl HostScannerMetrics {
@@ -60,7 +62,12 @@ impl HostScannerMetrics {
             let _ = scan.get_or_create(&ScanEvents { label });
         }
 
-        HostScannerMetrics { events, scan }
+        HostScannerMetrics {
+            events,
+            scan,
+            inode_map_entries: Gauge::default(),
+            inode_map_bytes: Gauge::default(),
+        }
     }
 
     pub(super) fn register(&self, reg: &mut Registry) {
@@ -70,6 +77,16 @@ impl HostScannerMetrics {
             "Counter of events by scans from the host scanner component",
             self.scan.clone(),
         );
+        reg.register(
+            "host_scanner_inode_map_entries",
+            "Inodes the host scanner holds the host path of",
+            self.inode_map_entries.clone(),
+        );
+        reg.register(
+            "host_scanner_inode_map_bytes",
+            "Estimate of the memory used by the host paths of the inodes tracked",
+            self.inode_map_bytes.clone(),
+        );
     }
 
     pub fn scan_inc(&self, label: ScanLabels) {
@@ -1,5 +1,11 @@
+use std::collections::HashSet;
+
 use aya::maps::{MapData, PerCpuArray};
-use prometheus_client::registry::Registry;
+use prometheus_client::{
+    encoding::EncodeLabelSet,
+    metrics::{family::Family, gauge::Gauge},
+    registry::Registry,
+};
 
 use fact_ebpf::{metrics_by_hook_t, metrics_t};
 
@@ -7,15 +13,47 @@ use crate::metrics::MetricEvents;
 
 use super::{EventCounter, LabelValues};
 
+#[derive(Clone, Hash, Eq, Debug, PartialEq, EncodeLabelSet)]
+struct HookLabels {
+    hook: &'static str,
+}
+
+/// Where the counters maintained by the BPF programs are read from.
+pub trait KernelMetricsSource: Send + Sync {
+    /// Read the counters, summed across CPUs.
+    fn read(&self) -> anyhow::Result<metrics_t>;
+}
+
+impl KernelMetricsSource for PerCpuArray<MapData, metrics_t> {
+    fn read(&self) -> anyhow::Result<metrics_t> {
+        Ok(self
+            .get(&0, 0)?
+            .iter()
+            .fold(metrics_t::default(), |acc, x| acc.accumulate(x)))
+    }
+}
+
 macro_rules! define_kernel_metrics {
     ($($hook:ident),+ $(,)?) => {
         pub struct KernelMetrics {
             $($hook: EventCounter,)+
-            map: PerCpuArray<MapData, metrics_t>,
+            hooks_enabled: Family<HookLabels, Gauge>,
+            ringbuf_size: Gauge,
+            source: Box<dyn KernelMetricsSource>,
         }
 
         impl KernelMetrics {
-            pub fn new(kernel_metrics: PerCpuArray<MapData, metrics_t>) -> Self {
+            /// Create the kernel metrics.
+            ///
+            /// `enabled_hooks` holds the hooks that were loaded, any
+            /// other hook is reported as disabled and its counters are
+            /// left empty. `ringbuf_size` is the size in bytes of the
+            /// ringbuffer that was created.
+            pub fn new(
+                source: impl KernelMetricsSource + 'static,
+                enabled_hooks: &HashSet<String>,
+                ringbuf_size: u64,
+            ) -> Self {
                 $(
                     let $hook = EventCounter::new(
                         concat!("kernel_", stringify!($hook), "_events"),
@@ -24,28 +62,80 @@ macro_rules! define_kernel_metrics {
                     );
                 )+
 
+                let hooks_enabled: Family<HookLabels, Gauge> = Default::default();
+                $(
+                    let hook = stringify!($hook);
+                    hooks_enabled
+                        .get_or_create(&HookLabels { hook })
+                        .set(enabled_hooks.contains(hook) as i64);
+                )+
+
+                let ringbuf_size_gauge = Gauge::default();
+                ringbuf_size_gauge.set(ringbuf_size as i64);
+
                 KernelMetrics {
                     $($hook,)+
-                    map: kernel_metrics,
+                    hooks_enabled,
+                    ringbuf_size: ringbuf_size_gauge,
+                    source: Box::new(source),
                 }
             }
 
             pub fn register(&self, reg: &mut Registry) {
                 $(self.$hook.register(reg);)+
+                reg.register(
+                    "kernel_hook_enabled",
+                    "Whether the LSM hook is loaded (1) or disabled (0)",
+                    self.hooks_enabled.clone(),
+                );
+                reg.register(
+                    "kernel_ringbuf_size_bytes",
+                    "Size of the ringbuffer, smaller than configured if the kernel could not allocate it",
+                    self.ringbuf_size.clone(),
+                );
             }
 
+            /// Names of all the hooks metrics are kept for.
+            #[cfg(test)]
+            pub fn hooks() -> HashSet<String> {
+                HashSet::from([$(stringify!($hook).to_string(),)+])
+            }
+
+            fn is_enabled(&self, hook: &'static str) -> bool {
+                self.hooks_enabled
+                    .get(&HookLabels { hook })
+                    .is_some_and(|g| g.get() != 0)
+            }
+
+            /// Refresh the counters from the BPF programs.
+            ///
+            /// Counters are cleared and filled again, so this must not
+            /// run while the registry is being encoded. `Exporter`
+            /// takes care of that.
             pub fn collect(&self) -> anyhow::Result<()> {
-                let metrics = self
-                    .map
-                    .get(&0, 0)?
-                    .iter()
-                    .fold(metrics_t::default(), |acc, x| acc.accumulate(x));
+                let metrics = self.source.read()?;
 
-                $(Self::refresh_labels(&self.$hook, &metrics.$hook);)+
+                $(
+                    if self.is_enabled(stringify!($hook)) {
+                        Self::refresh_labels(&self.$hook, &metrics.$hook);
+                    }
+                )+
 
                 Ok(())
             }
 
+            /// Read the counters of the enabled hooks.
+            pub fn hook_counters(&self) -> anyhow::Result<Vec<(&'static str, metrics_by_hook_t)>> {
+                let metrics = self.source.read()?;
+                let mut hooks = Vec::new();
+                $(
+                    if self.is_enabled(stringify!($hook)) {
+                        hooks.push((stringify!($hook), metrics.$hook));
+                    }
+                )+
+                Ok(hooks)
+            }
+
             fn refresh_labels(ec: &EventCounter, m: &metrics_by_hook_t) {
                 ec.counter.clear();
                 for (label, value) in [
@@ -54,6 +144,9 @@ macro_rules! define_kernel_metrics {
                     (LabelValues::Error, m.error),
                     (LabelValues::Ignored, m.ignored),
                     (LabelValues::RingbufferFull, m.ringbuffer_full),
+                    (LabelValues::Blocked, m.blocked),
+                    (LabelValues::Paused, m.paused),
+                    (LabelValues::SelfActivity, m.self_activity),
                 ] {
                     ec.counter
                         .get_or_create(&MetricEvents { label })
@@ -76,4 +169,7 @@ define_kernel_metrics!(
     inode_setxattr,
     inode_removexattr,
     inode_set_acl,
+    file_permission,
+    path_link,
+    file_ioctl,
 );
@@ -1,11 +1,27 @@
+use std::{
+    collections::HashMap,
+    fmt::Write,
+    sync::{Arc, Mutex, atomic::AtomicU64},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
 use prometheus_client::{
-    encoding::{EncodeLabelSet, EncodeLabelValue},
-    metrics::{counter::Counter, family::Family},
+    encoding::{EncodeLabelSet, EncodeLabelValue, LabelValueEncoder},
+    metrics::{counter::Counter, family::Family, gauge::Gauge},
     registry::Registry,
 };
 
+use config::ConfigMetrics;
+use containers::ContainerCounts;
 use host_scanner::HostScannerMetrics;
 
+use crate::{
+    event::process::ContainerIdResolution,
+    tls::{CertKind, Expiry},
+};
+
+pub mod config;
+pub mod containers;
 pub mod exporter;
 pub mod host_scanner;
 pub mod kernel_metrics;
@@ -18,6 +34,16 @@ enum LabelValues {
     Ignored,
     Error,
     RingbufferFull,
+    Blocked,
+    Timeout,
+    Paused,
+    SelfActivity,
+    Sampled,
+    Filter,
+    Reset,
+    Initializing,
+    Hit,
+    Miss,
 }
 
 #[derive(Clone, Hash, Eq, Debug, PartialEq, EncodeLabelSet)]
@@ -25,11 +51,57 @@ struct MetricEvents {
     label: LabelValues,
 }
 
+/// Why an event was dropped in userspace.
+#[derive(Clone, Hash, Eq, Debug, PartialEq, Copy)]
+pub enum DropReason {
+    /// The event read from the kernel could not be parsed.
+    ParseError,
+    /// A consumer fell behind and the channel overwrote the event.
+    Lagged,
+    /// The event could not be serialized or handled by its output.
+    SerializeError,
+    /// The stream or channel the event was sent through closed.
+    StreamClosed,
+    /// The event is not for a monitored path.
+    Filtered,
+    /// The rate limit was exceeded.
+    RateLimited,
+}
+
+impl DropReason {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DropReason::ParseError => "parse_error",
+            DropReason::Lagged => "lagged",
+            DropReason::SerializeError => "serialize_error",
+            DropReason::StreamClosed => "stream_closed",
+            DropReason::Filtered => "filtered",
+            DropReason::RateLimited => "rate_limited",
+        }
+    }
+}
+
+impl EncodeLabelValue for DropReason {
+    fn encode(&self, encoder: &mut LabelValueEncoder) -> Result<(), std::fmt::Error> {
+        encoder.write_str(self.as_str())
+    }
+}
+
+#[derive(Clone, Hash, Eq, Debug, PartialEq, EncodeLabelSet)]
+struct DropLabels {
+    reason: DropReason,
+}
+
 #[derive(Debug, Clone)]
 /// An abstraction over Family<MetricEvents, Counter<u64>> for easily
 /// creating event counters.
+///
+/// Dropped events are also counted by reason, in a family of its own
+/// named after the counter with a `_dropped` suffix. The Dropped series
+/// keeps the total.
 pub struct EventCounter {
     counter: Family<MetricEvents, Counter<u64>>,
+    drops: Family<DropLabels, Counter<u64>>,
     name: &'static str,
     help: &'static str,
 }
@@ -52,6 +124,7 @@ impl EventCounter {
 
         EventCounter {
             counter,
+            drops: Default::default(),
             name,
             help,
         }
@@ -59,6 +132,17 @@ impl EventCounter {
 
     fn register(&self, reg: &mut Registry) {
         reg.register(self.name, self.help, self.counter.clone());
+        // Only counters that drop events count them by reason
+        let dropped = MetricEvents {
+            label: LabelValues::Dropped,
+        };
+        if self.counter.get(&dropped).is_some() {
+            reg.register(
+                format!("{}_dropped", self.name),
+                format!("{}, dropped by reason", self.help),
+                self.drops.clone(),
+            );
+        }
     }
 
     fn inc_label(&self, label: LabelValues) {
@@ -79,12 +163,13 @@ impl EventCounter {
         self.inc_label(LabelValues::Added);
     }
 
-    pub fn dropped(&self) {
-        self.inc_label(LabelValues::Dropped);
+    pub fn dropped(&self, reason: DropReason) {
+        self.dropped_n(reason, 1);
     }
 
-    pub fn dropped_n(&self, n: u64) {
+    pub fn dropped_n(&self, reason: DropReason, n: u64) {
         self.inc_label_by(LabelValues::Dropped, n);
+        self.drops.get_or_create(&DropLabels { reason }).inc_by(n);
     }
 
     pub fn ignored(&self) {
@@ -94,6 +179,268 @@ impl EventCounter {
     pub fn errored(&self) {
         self.inc_label(LabelValues::Error);
     }
+
+    pub fn timed_out(&self) {
+        self.inc_label(LabelValues::Timeout);
+    }
+
+    pub fn sampled(&self) {
+        self.inc_label(LabelValues::Sampled);
+    }
+
+    /// Count an event held back until there was room for it.
+    pub fn blocked(&self) {
+        self.inc_label(LabelValues::Blocked);
+    }
+
+    /// Count an event ignored because of a configured filter.
+    pub fn filtered(&self) {
+        self.inc_label(LabelValues::Filter);
+    }
+
+    fn count(&self, label: LabelValues) -> u64 {
+        self.counter
+            .get(&MetricEvents { label })
+            .map(|c| c.get())
+            .unwrap_or_default()
+    }
+
+    /// Count an event received before filters were fully loaded.
+    pub fn initializing(&self) {
+        self.inc_label(LabelValues::Initializing);
+    }
+
+    /// Count state discarded instead of being loaded.
+    pub fn reset(&self) {
+        self.inc_label(LabelValues::Reset);
+    }
+
+    /// Count a lookup that found what it was after in a cache.
+    pub fn hit(&self) {
+        self.inc_label(LabelValues::Hit);
+    }
+
+    /// Count a lookup that did not find what it was after in a cache.
+    pub fn miss(&self) {
+        self.inc_label(LabelValues::Miss);
+    }
+
+    /// Current value of the counter for dropped events.
+    pub fn dropped_count(&self) -> u64 {
+        self.count(LabelValues::Dropped)
+    }
+
+    #[cfg(test)]
+    pub fn dropped_reason_count(&self, reason: DropReason) -> u64 {
+        self.drops
+            .get(&DropLabels { reason })
+            .map(|c| c.get())
+            .unwrap_or_default()
+    }
+
+    pub fn added_count(&self) -> u64 {
+        self.count(LabelValues::Added)
+    }
+
+    #[cfg(test)]
+    pub fn blocked_count(&self) -> u64 {
+        self.count(LabelValues::Blocked)
+    }
+
+    #[cfg(test)]
+    pub fn ignored_count(&self) -> u64 {
+        self.count(LabelValues::Ignored)
+    }
+
+    #[cfg(test)]
+    pub fn errored_count(&self) -> u64 {
+        self.count(LabelValues::Error)
+    }
+
+    #[cfg(test)]
+    pub fn timed_out_count(&self) -> u64 {
+        self.count(LabelValues::Timeout)
+    }
+
+    #[cfg(test)]
+    pub fn reset_count(&self) -> u64 {
+        self.count(LabelValues::Reset)
+    }
+
+    #[cfg(all(test, feature = "bpf-test"))]
+    pub fn initializing_count(&self) -> u64 {
+        self.count(LabelValues::Initializing)
+    }
+}
+
+#[derive(Clone, Hash, Eq, Debug, PartialEq, EncodeLabelSet)]
+struct EndpointLabels {
+    endpoint: String,
+}
+
+#[derive(Debug, Clone, Default)]
+/// The endpoint of the server the grpc output is connected to, the
+/// only series of the family is set to 1 while connected. The
+/// connected gauge follows it for alerting without label matching.
+pub struct ActiveEndpoint {
+    endpoints: Family<EndpointLabels, Gauge>,
+    connected: Gauge,
+    current: Arc<Mutex<Option<String>>>,
+}
+
+impl ActiveEndpoint {
+    fn register(&self, reg: &mut Registry) {
+        reg.register(
+            "output_grpc_endpoint_active",
+            "Endpoint of the server the grpc output component is connected to",
+            self.endpoints.clone(),
+        );
+        reg.register(
+            "output_grpc_connected",
+            "Whether the grpc output component is connected to a server",
+            self.connected.clone(),
+        );
+    }
+
+    /// Mark `endpoint` as the one connected to, or none of them.
+    pub fn set(&self, endpoint: Option<&str>) {
+        self.endpoints.clear();
+        if let Some(endpoint) = endpoint {
+            self.endpoints
+                .get_or_create(&EndpointLabels {
+                    endpoint: endpoint.to_owned(),
+                })
+                .set(1);
+        }
+        self.connected.set(endpoint.is_some().into());
+        *self.current.lock().unwrap() = endpoint.map(str::to_owned);
+    }
+
+    /// The endpoint connected to, if any.
+    pub fn get(&self) -> Option<String> {
+        self.current.lock().unwrap().clone()
+    }
+
+    #[cfg(test)]
+    pub fn is_active(&self, endpoint: &str) -> bool {
+        self.endpoints
+            .get(&EndpointLabels {
+                endpoint: endpoint.to_owned(),
+            })
+            .is_some_and(|g| g.get() == 1)
+    }
+
+    #[cfg(test)]
+    pub fn is_connected(&self) -> bool {
+        self.connected.get() == 1
+    }
+}
+
+/// Output components events are delivered through.
+#[derive(Clone, Hash, Eq, Debug, PartialEq, EncodeLabelValue, Copy)]
+pub enum Sink {
+    Stdout,
+    Grpc,
+    #[cfg(feature = "otel")]
+    Otel,
+}
+
+impl Sink {
+    pub const ALL: &[Sink] = &[
+        Sink::Stdout,
+        Sink::Grpc,
+        #[cfg(feature = "otel")]
+        Sink::Otel,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Sink::Stdout => "stdout",
+            Sink::Grpc => "grpc",
+            #[cfg(feature = "otel")]
+            Sink::Otel => "otel",
+        }
+    }
+}
+
+#[derive(Clone, Hash, Eq, Debug, PartialEq, EncodeLabelSet)]
+struct SinkLabels {
+    sink: Sink,
+}
+
+#[derive(Debug, Clone, Default)]
+/// Unix time of the last successful delivery of each sink, a sink only
+/// gets a series once it delivered something.
+pub struct LastSuccess(Family<SinkLabels, Gauge>);
+
+impl LastSuccess {
+    fn register(&self, reg: &mut Registry) {
+        reg.register(
+            "output_last_success_timestamp_seconds",
+            "Unix time of the last event successfully delivered by each output component",
+            self.0.clone(),
+        );
+    }
+
+    /// Record a successful delivery by `sink` now.
+    pub fn touch(&self, sink: Sink) {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        self.0.get_or_create(&SinkLabels { sink }).set(now as i64);
+    }
+
+    /// Unix time of the last delivery by `sink`, if it delivered any.
+    pub fn get(&self, sink: Sink) -> Option<i64> {
+        self.0.get(&SinkLabels { sink }).map(|g| g.get())
+    }
+}
+
+impl EncodeLabelValue for CertKind {
+    fn encode(&self, encoder: &mut LabelValueEncoder) -> Result<(), std::fmt::Error> {
+        encoder.write_str(self.as_str())
+    }
+}
+
+#[derive(Clone, Hash, Eq, Debug, PartialEq, EncodeLabelSet)]
+struct CertLabels {
+    cert: CertKind,
+}
+
+#[derive(Debug, Clone, Default)]
+/// Unix time the certificates used by the grpc output expire at.
+pub struct CertExpiry(Family<CertLabels, Gauge>);
+
+impl CertExpiry {
+    fn register(&self, reg: &mut Registry) {
+        reg.register(
+            "tls_cert_expiry_timestamp_seconds",
+            "Unix time the client and CA certificates of the grpc output expire at",
+            self.0.clone(),
+        );
+    }
+
+    /// Export the earliest expiry of each kind of certificate, which
+    /// is the one that matters with several CAs.
+    pub fn set(&self, expiry: &[Expiry]) {
+        let mut earliest = HashMap::new();
+        for e in expiry {
+            earliest
+                .entry(e.kind)
+                .and_modify(|t: &mut i64| *t = (*t).min(e.timestamp))
+                .or_insert(e.timestamp);
+        }
+        self.0.clear();
+        for (cert, timestamp) in earliest {
+            self.0.get_or_create(&CertLabels { cert }).set(timestamp);
+        }
+    }
+
+    #[cfg(test)]
+    pub fn get(&self, cert: CertKind) -> Option<i64> {
+        self.0.get(&CertLabels { cert }).map(|g| g.get())
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -101,7 +448,16 @@ impl EventCounter {
 pub struct OutputMetrics {
     pub stdout: EventCounter,
     pub grpc: EventCounter,
+    pub grpc_dns: EventCounter,
+    pub grpc_endpoint: ActiveEndpoint,
     pub otel: EventCounter,
+    pub sinks: EventCounter,
+    pub overflow: EventCounter,
+    pub last_success: LastSuccess,
+    pub cert_expiry: CertExpiry,
+    /// Events of the busiest containers, counted once as they are
+    /// dispatched to the outputs.
+    pub containers: ContainerCounts,
 }
 
 impl OutputMetrics {
@@ -117,31 +473,211 @@ impl OutputMetrics {
             "Events processed by the grpc output component",
             &labels,
         );
+        let grpc_dns_counter = EventCounter::new(
+            "output_grpc_dns_resolutions",
+            "DNS resolutions of the sensor address by the grpc output component",
+            &[LabelValues::Added, LabelValues::Error, LabelValues::Timeout],
+        );
         let otel_counter = EventCounter::new(
             "output_otel_events",
             "Events processed by the otel output component",
             &labels,
         );
+        let sinks_counter = EventCounter::new(
+            "output_sink_events",
+            "Events processed by the sinks registered by embedders of fact",
+            &labels,
+        );
+        let overflow_counter = EventCounter::new(
+            "output_overflow_events",
+            "Events outputs could not keep up with, dropped in userspace or blocked until they caught up",
+            &[LabelValues::Dropped, LabelValues::Blocked],
+        );
 
         OutputMetrics {
             stdout: stdout_counter,
             grpc: grpc_counter,
+            grpc_dns: grpc_dns_counter,
+            grpc_endpoint: ActiveEndpoint::default(),
             otel: otel_counter,
+            sinks: sinks_counter,
+            overflow: overflow_counter,
+            last_success: LastSuccess::default(),
+            cert_expiry: CertExpiry::default(),
+            containers: ContainerCounts::default(),
         }
     }
 
+    /// Total number of events dropped across all outputs.
+    pub fn dropped_total(&self) -> u64 {
+        self.stdout.dropped_count()
+            + self.grpc.dropped_count()
+            + self.otel.dropped_count()
+            + self.sinks.dropped_count()
+    }
+
     fn register(&self, reg: &mut Registry) {
         self.stdout.register(reg);
         self.grpc.register(reg);
+        self.grpc_dns.register(reg);
+        self.grpc_endpoint.register(reg);
         self.otel.register(reg);
+        self.sinks.register(reg);
+        self.overflow.register(reg);
+        self.last_success.register(reg);
+        self.cert_expiry.register(reg);
+        self.containers.register(reg);
+    }
+}
+
+/// Background maintenance tasks sharing the CPU budget.
+#[derive(Clone, Hash, Eq, Debug, PartialEq, EncodeLabelValue, Copy)]
+pub enum MaintenanceTask {
+    HostScanner,
+}
+
+#[derive(Clone, Hash, Eq, Debug, PartialEq, EncodeLabelSet)]
+struct MaintenanceLabels {
+    task: MaintenanceTask,
+}
+
+type SecondsCounter = Family<MaintenanceLabels, Counter<f64, AtomicU64>>;
+
+#[derive(Debug, Clone, Default)]
+/// Consumption of the maintenance budget by each task.
+pub struct MaintenanceMetrics {
+    work: SecondsCounter,
+    throttled: SecondsCounter,
+}
+
+impl MaintenanceMetrics {
+    fn register(&self, reg: &mut Registry) {
+        reg.register(
+            "maintenance_work_seconds",
+            "Time spent doing background maintenance work",
+            self.work.clone(),
+        );
+        reg.register(
+            "maintenance_throttled_seconds",
+            "Time background maintenance tasks slept to stay within their CPU budget",
+            self.throttled.clone(),
+        );
+    }
+
+    pub fn work(&self, task: MaintenanceTask, d: Duration) {
+        self.work
+            .get_or_create(&MaintenanceLabels { task })
+            .inc_by(d.as_secs_f64());
+    }
+
+    pub fn throttled(&self, task: MaintenanceTask, d: Duration) {
+        self.throttled
+            .get_or_create(&MaintenanceLabels { task })
+            .inc_by(d.as_secs_f64());
+    }
+
+    #[cfg(test)]
+    pub fn work_seconds(&self, task: MaintenanceTask) -> f64 {
+        self.work.get_or_create(&MaintenanceLabels { task }).get()
+    }
+
+    #[cfg(test)]
+    pub fn throttled_seconds(&self, task: MaintenanceTask) -> f64 {
+        self.throttled
+            .get_or_create(&MaintenanceLabels { task })
+            .get()
+    }
+}
+
+impl EncodeLabelValue for ContainerIdResolution {
+    fn encode(&self, encoder: &mut LabelValueEncoder) -> Result<(), std::fmt::Error> {
+        encoder.write_str(self.as_str())
+    }
+}
+
+#[derive(Clone, Hash, Eq, Debug, PartialEq, EncodeLabelSet)]
+struct ResolutionLabels {
+    result: ContainerIdResolution,
+}
+
+#[derive(Debug, Clone, Default)]
+/// Processes read from the kernel by how their container ID was
+/// resolved, see `ContainerIdResolution`.
+pub struct ContainerIdMetrics(Family<ResolutionLabels, Counter>);
+
+impl ContainerIdMetrics {
+    fn register(&self, reg: &mut Registry) {
+        reg.register(
+            "container_id_resolution",
+            "Processes of events read from the kernel: in the host mount namespace, with their container ID resolved, or outside of it with no container ID found in their cgroup",
+            self.0.clone(),
+        );
+    }
+
+    pub fn inc(&self, result: ContainerIdResolution) {
+        self.0.get_or_create(&ResolutionLabels { result }).inc();
+    }
+
+    pub fn get(&self, result: ContainerIdResolution) -> u64 {
+        self.0
+            .get(&ResolutionLabels { result })
+            .map(|c| c.get())
+            .unwrap_or_default()
+    }
+
+    /// Share of the processes outside of the host mount namespace
+    /// with no container ID, 0 until there is one.
+    pub fn unresolved_ratio(&self) -> f64 {
+        let unresolved = self.get(ContainerIdResolution::Unresolved);
+        let total = unresolved + self.get(ContainerIdResolution::Resolved);
+        if total == 0 {
+            0.0
+        } else {
+            unresolved as f64 / total as f64
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+/// State of the stage reordering events by timestamp.
+pub struct ReorderMetrics {
+    /// Events currently held.
+    pub buffered: Gauge,
+    /// Largest time an event was read after one that happened later,
+    /// in nanoseconds.
+    pub max_skew: Gauge,
+}
+
+impl ReorderMetrics {
+    fn register(&self, reg: &mut Registry) {
+        reg.register(
+            "reorder_buffered_events",
+            "Events held to be released sorted by timestamp",
+            self.buffered.clone(),
+        );
+        reg.register(
+            "reorder_max_skew_nanoseconds",
+            "Largest time an event was read after one that happened later",
+            self.max_skew.clone(),
+        );
     }
 }
 
 pub struct Metrics {
     pub bpf_worker: EventCounter,
+    pub lineage_cache: EventCounter,
     pub rate_limiter: EventCounter,
+    pub existence_check: EventCounter,
+    pub generator: EventCounter,
+    pub state: EventCounter,
     pub output: OutputMetrics,
+    pub reorder: ReorderMetrics,
     pub host_scanner: HostScannerMetrics,
+    pub maintenance: MaintenanceMetrics,
+    pub collection_paused: Gauge,
+    pub redactions: Counter,
+    pub config: ConfigMetrics,
+    pub container_id_resolution: ContainerIdMetrics,
 }
 
 impl Metrics {
@@ -153,27 +689,87 @@ impl Metrics {
                 LabelValues::Added,
                 LabelValues::Dropped,
                 LabelValues::Ignored,
+                LabelValues::Sampled,
+                LabelValues::Filter,
+                LabelValues::Initializing,
             ],
         );
 
+        let lineage_cache = EventCounter::new(
+            "bpf_lineage_cache_events",
+            "Lineages of events read from the kernel: sent in full and added to the cache, left out and found in it, or left out and missing from it",
+            &[LabelValues::Added, LabelValues::Hit, LabelValues::Miss],
+        );
+
         let rate_limiter = EventCounter::new(
             "rate_limiter_events",
             "Events processed by the rate limiter",
             &[LabelValues::Added, LabelValues::Dropped, LabelValues::Error],
         );
 
+        let existence_check = EventCounter::new(
+            "enrich_existence_checks",
+            "Checks of whether the file of an event exists at emission",
+            &[
+                LabelValues::Added,
+                LabelValues::Ignored,
+                LabelValues::Error,
+                LabelValues::Timeout,
+            ],
+        );
+
+        let generator = EventCounter::new(
+            "generator_events",
+            "Synthetic events created by the generator",
+            &[LabelValues::Added],
+        );
+
+        let state = EventCounter::new(
+            "state_files",
+            "State files loaded from disk or discarded on startup",
+            &[LabelValues::Added, LabelValues::Reset],
+        );
+
         Metrics {
             bpf_worker,
+            lineage_cache,
             rate_limiter,
+            existence_check,
+            generator,
+            state,
             output: OutputMetrics::new(),
+            reorder: ReorderMetrics::default(),
             host_scanner: HostScannerMetrics::new(),
+            maintenance: MaintenanceMetrics::default(),
+            collection_paused: Gauge::default(),
+            redactions: Counter::default(),
+            config: ConfigMetrics::default(),
+            container_id_resolution: crate::event::process::CONTAINER_ID_RESOLUTION.clone(),
         }
     }
 
     fn register(&self, reg: &mut Registry) {
         self.bpf_worker.register(reg);
+        self.lineage_cache.register(reg);
         self.rate_limiter.register(reg);
+        self.existence_check.register(reg);
+        self.generator.register(reg);
+        self.state.register(reg);
         self.output.register(reg);
+        self.reorder.register(reg);
         self.host_scanner.register(reg);
+        self.maintenance.register(reg);
+        self.config.register(reg);
+        self.container_id_resolution.register(reg);
+        reg.register(
+            "collection_paused",
+            "Whether event collection is paused through the control endpoints",
+            self.collection_paused.clone(),
+        );
+        reg.register(
+            "args_redactions",
+            "Substrings of process arguments replaced by the redact_args patterns",
+            self.redactions.clone(),
+        );
     }
 }
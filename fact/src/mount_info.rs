@@ -0,0 +1,166 @@
+//! Parsing of the mount table in `/proc/self/mountinfo`.
+
+use std::{
+    ffi::OsString,
+    fs::read_to_string,
+    io,
+    os::unix::ffi::OsStringExt,
+    path::{Path, PathBuf},
+};
+
+/// Filesystem types accessed over the network, operations on them can
+/// block for as long as the server takes to answer.
+const REMOTE_FS_TYPES: &[&str] = &[
+    "9p",
+    "afs",
+    "beegfs",
+    "ceph",
+    "cifs",
+    "fuse.glusterfs",
+    "fuse.s3fs",
+    "fuse.sshfs",
+    "glusterfs",
+    "gpfs",
+    "lustre",
+    "nfs",
+    "nfs4",
+    "smb3",
+    "smbfs",
+];
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct MountEntry {
+    pub mount_point: PathBuf,
+    pub fs_type: String,
+}
+
+impl MountEntry {
+    pub fn is_remote(&self) -> bool {
+        REMOTE_FS_TYPES.contains(&self.fs_type.as_str())
+    }
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct MountInfo {
+    /// Mounts in the order they appear in the table, later mounts
+    /// shadow earlier ones on the same mount point.
+    entries: Vec<MountEntry>,
+}
+
+impl MountInfo {
+    pub fn read() -> io::Result<Self> {
+        Ok(MountInfo::parse(&read_to_string("/proc/self/mountinfo")?))
+    }
+
+    /// Parse the content of a mountinfo file, malformed lines are
+    /// skipped.
+    pub fn parse(content: &str) -> Self {
+        let entries = content.lines().filter_map(parse_line).collect();
+        MountInfo { entries }
+    }
+
+    /// Find the mount `path` is on.
+    pub fn find(&self, path: &Path) -> Option<&MountEntry> {
+        self.entries
+            .iter()
+            .filter(|e| path.starts_with(&e.mount_point))
+            .max_by_key(|e| e.mount_point.as_os_str().len())
+    }
+
+    /// Whether `path` is on a network filesystem.
+    pub fn is_remote(&self, path: &Path) -> bool {
+        self.find(path).is_some_and(MountEntry::is_remote)
+    }
+}
+
+/// Parse a line like:
+///
+/// `36 35 98:0 /mnt1 /mnt2 rw,noatime master:1 - ext3 /dev/root rw`
+///
+/// See proc_pid_mountinfo(5) for details on each field.
+fn parse_line(line: &str) -> Option<MountEntry> {
+    let (mount, fs) = line.split_once(" - ")?;
+    let mount_point = mount.split(' ').nth(4)?;
+    let fs_type = fs.split(' ').next()?;
+    Some(MountEntry {
+        mount_point: unescape(mount_point),
+        fs_type: fs_type.to_string(),
+    })
+}
+
+/// Undo the octal escaping of spaces, tabs, newlines and backslashes
+/// in mount points.
+fn unescape(s: &str) -> PathBuf {
+    let bytes = s.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'\\'
+            && let Some(octal) = s.get(i + 1..i + 4)
+            && let Ok(c) = u8::from_str_radix(octal, 8)
+        {
+            out.push(c);
+            i += 4;
+        } else {
+            out.push(bytes[i]);
+            i += 1;
+        }
+    }
+    PathBuf::from(OsString::from_vec(out))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MOUNTINFO: &str = "\
+22 1 253:0 / / rw,relatime shared:1 - xfs /dev/mapper/root rw
+23 22 0:21 / /proc rw,nosuid shared:5 - proc proc rw
+40 22 0:35 / /mnt/data rw,relatime shared:20 - nfs4 server:/export rw,vers=4.2
+41 40 253:2 / /mnt/data/local rw,relatime shared:21 - ext4 /dev/sdb1 rw
+42 22 0:36 / /mnt/with\\040space rw,relatime - cifs //server/share rw
+43 22 253:3 / /mnt/data rw,relatime - ext4 /dev/sdc1 rw
+garbage
+";
+
+    #[test]
+    fn parse() {
+        let info = MountInfo::parse(MOUNTINFO);
+        assert_eq!(info.entries.len(), 6);
+        assert_eq!(
+            info.entries[4],
+            MountEntry {
+                mount_point: PathBuf::from("/mnt/with space"),
+                fs_type: "cifs".into(),
+            }
+        );
+    }
+
+    #[test]
+    fn find() {
+        let info = MountInfo::parse(MOUNTINFO);
+        let fs_type = |p: &str| info.find(Path::new(p)).map(|e| e.fs_type.as_str());
+        assert_eq!(fs_type("/etc/passwd"), Some("xfs"));
+        assert_eq!(fs_type("/proc/self"), Some("proc"));
+        assert_eq!(fs_type("/mnt/data/local/file"), Some("ext4"));
+        assert_eq!(fs_type("/mnt/database"), Some("xfs"));
+        assert!(MountInfo::default().find(Path::new("/")).is_none());
+    }
+
+    #[test]
+    fn remote() {
+        let info = MountInfo::parse(MOUNTINFO);
+        assert!(info.is_remote(Path::new("/mnt/with space/file")));
+        assert!(!info.is_remote(Path::new("/etc/passwd")));
+        assert!(!info.is_remote(Path::new("/mnt/data/local/file")));
+
+        // The ext4 mount shadows the nfs one
+        assert!(!info.is_remote(Path::new("/mnt/data/file")));
+        let unshadowed: Vec<_> = MOUNTINFO
+            .lines()
+            .filter(|l| !l.starts_with("43 "))
+            .collect();
+        let unshadowed = MountInfo::parse(&unshadowed.join("\n"));
+        assert!(unshadowed.is_remote(Path::new("/mnt/data/file")));
+    }
+}
@@ -0,0 +1,135 @@
+//! Stable identifier of the node fact runs on.
+//!
+//! Hostnames change when nodes are renamed and are not unique in every
+//! fleet. A random ID is generated on the first start and kept in the
+//! state file set in `node_id_file`, so events from a node can be told
+//! apart and grouped across restarts. The ID is added to every event
+//! and sent to the gRPC server along with the hostname.
+//!
+//! If the state file can't be written, an ID is generated for the
+//! current run only.
+
+use std::{fs, path::Path, sync::OnceLock};
+
+use log::{info, warn};
+use uuid::Uuid;
+
+use crate::{
+    metrics::EventCounter,
+    state::{self, StateKind},
+};
+
+const KIND: StateKind = StateKind {
+    name: "node id",
+    version: 1,
+    migrations: &[],
+};
+
+static NODE_ID: OnceLock<String> = OnceLock::new();
+
+/// Load the ID from `path`, creating it if needed, and use it for the
+/// rest of the run.
+///
+/// Only the first call has an effect, later ones return the ID already
+/// in use.
+pub fn init(path: &Path, metrics: &EventCounter) -> &'static str {
+    NODE_ID.get_or_init(|| load_or_create(path, metrics))
+}
+
+/// ID of the node, empty until `init` is called.
+pub fn get() -> &'static str {
+    NODE_ID.get().map(String::as_str).unwrap_or_default()
+}
+
+/// Read the ID stored in `path`, or generate and store a new one.
+fn load_or_create(path: &Path, metrics: &EventCounter) -> String {
+    if let Some(payload) = state::open_or_reset(path, &KIND, metrics) {
+        match Uuid::try_parse_ascii(&payload) {
+            Ok(id) => {
+                info!("Using node ID {id} from {}", path.display());
+                return id.to_string();
+            }
+            Err(e) => warn!("Invalid node ID in {}: {e}", path.display()),
+        }
+    }
+
+    let id = Uuid::new_v4().to_string();
+    let res = match path.parent() {
+        Some(dir) => fs::create_dir_all(dir),
+        None => Ok(()),
+    }
+    .and_then(|_| state::write(path, &KIND, id.as_bytes()));
+    match res {
+        Ok(()) => info!("Created node ID {id} in {}", path.display()),
+        Err(e) => warn!(
+            "Failed to save node ID to {}: {e}. The node ID {id} will change on restart.",
+            path.display()
+        ),
+    }
+    id
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::TempDir;
+
+    use super::*;
+    use crate::metrics::Metrics;
+
+    #[test]
+    fn created_and_reused() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("state").join("node_id");
+        let metrics = Metrics::new().state;
+
+        // The first run creates the ID, along with its directory
+        let id = load_or_create(&path, &metrics);
+        assert!(Uuid::try_parse(&id).is_ok());
+        assert!(path.exists());
+
+        // Later runs reuse it
+        assert_eq!(load_or_create(&path, &metrics), id);
+        assert_eq!(load_or_create(&path, &metrics), id);
+        assert_eq!(metrics.reset_count(), 0);
+    }
+
+    #[test]
+    fn corrupted() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("node_id");
+        let metrics = Metrics::new().state;
+        let id = load_or_create(&path, &metrics);
+
+        // A damaged file is replaced with a new ID
+        let mut data = fs::read(&path).unwrap();
+        *data.last_mut().unwrap() ^= 0xff;
+        fs::write(&path, data).unwrap();
+        let new_id = load_or_create(&path, &metrics);
+        assert_ne!(new_id, id);
+        assert_eq!(metrics.reset_count(), 1);
+        assert_eq!(load_or_create(&path, &metrics), new_id);
+
+        // So is a valid file that does not hold an ID
+        state::write(&path, &KIND, b"not an id").unwrap();
+        let other_id = load_or_create(&path, &metrics);
+        assert_ne!(other_id, new_id);
+        assert!(Uuid::try_parse(&other_id).is_ok());
+        assert_eq!(load_or_create(&path, &metrics), other_id);
+    }
+
+    #[test]
+    fn not_writable() {
+        let dir = TempDir::new().unwrap();
+        // The parent of the state file is a file, it can't be created
+        let parent = dir.path().join("file");
+        fs::write(&parent, "").unwrap();
+        let path = parent.join("node_id");
+        let metrics = Metrics::new().state;
+
+        // An ephemeral ID is used
+        let id = load_or_create(&path, &metrics);
+        assert!(Uuid::try_parse(&id).is_ok());
+        assert!(!path.exists());
+        assert_ne!(load_or_create(&path, &metrics), id);
+    }
+}
@@ -0,0 +1,356 @@
+//! Fanout of the events to the outputs.
+//!
+//! Events are broadcast on a single channel by default, an output
+//! falling behind misses the oldest events, whichever container they
+//! come from. With `output.shards` set, events are spread over several
+//! channels by container ID and outputs read the channels in turn, so
+//! a container flooding its shard only makes the outputs miss events
+//! of that shard. Events of a container always go through the same
+//! shard, they keep their order.
+//!
+//! With the block overflow policy, the outputs tell the dispatcher
+//! when they receive an event so it can wait for them to make room.
+//! Receivers of `Pipeline::subscribe` can't, the outputs get channels
+//! of their own and those receivers miss events they are too slow
+//! for, like with the drop policy.
+
+use std::{
+    future::{Future, poll_fn},
+    hash::BuildHasher,
+    sync::Arc,
+    task::Poll,
+};
+
+use rustc_hash::FxBuildHasher;
+use tokio::sync::{
+    Notify,
+    broadcast::{
+        self,
+        error::{RecvError, SendError},
+    },
+};
+
+use crate::{config::OverflowPolicy, event::Event};
+
+type Sender = broadcast::Sender<Arc<Event>>;
+
+/// Sends the events to the receivers of every output.
+pub struct Dispatcher {
+    shards: Vec<Sender>,
+    /// Events held by each shard, broadcast channels round their
+    /// capacity up to a power of two.
+    capacity: usize,
+    /// Channel of the receivers from `Pipeline::subscribe`, when the
+    /// outputs read from shards instead.
+    external: Option<Sender>,
+    /// Signaled by the receivers of the outputs on every event they
+    /// receive.
+    received: Arc<Notify>,
+}
+
+impl Dispatcher {
+    /// Broadcast events on `events`, or spread them over `shards`
+    /// channels holding `depth` events each if there is more than one
+    /// or the outputs are waited for. Receivers of `events` get all
+    /// events either way.
+    pub fn new(events: Sender, shards: usize, depth: usize, policy: OverflowPolicy) -> Self {
+        let received = Arc::new(Notify::new());
+        if shards <= 1 && policy == OverflowPolicy::Drop {
+            return Dispatcher {
+                shards: vec![events],
+                capacity: crate::EVENT_CHANNEL_CAPACITY.next_power_of_two(),
+                external: None,
+                received,
+            };
+        }
+
+        Dispatcher {
+            shards: (0..shards.max(1))
+                .map(|_| broadcast::channel(depth).0)
+                .collect(),
+            capacity: depth.next_power_of_two(),
+            external: Some(events),
+            received,
+        }
+    }
+
+    fn shard_of(&self, event: &Event) -> usize {
+        FxBuildHasher.hash_one(event.get_container_id()) as usize % self.shards.len()
+    }
+
+    /// Receive events from every shard.
+    pub fn subscribe(&self) -> EventReceiver {
+        EventReceiver {
+            shards: self.shards.iter().map(Sender::subscribe).collect(),
+            next: 0,
+            received: Some(self.received.clone()),
+        }
+    }
+
+    /// Receivers of the outputs, along with the ones of `events` in a
+    /// single channel.
+    pub fn receiver_count(&self) -> usize {
+        self.shards[0].receiver_count()
+    }
+
+    /// Whether the slowest receiver of the shard of `event` is a full
+    /// channel behind, sending it overwrites an event they have not
+    /// seen yet.
+    pub fn is_full(&self, event: &Event) -> bool {
+        self.shards[self.shard_of(event)].len() >= self.capacity || self.external_full()
+    }
+
+    /// Whether any shard is full, the next event may overwrite one not
+    /// seen yet by an output whichever shard it goes to.
+    pub fn any_full(&self) -> bool {
+        self.shards.iter().any(|tx| tx.len() >= self.capacity)
+    }
+
+    /// Wait for an output to receive an event, making room in its
+    /// shard.
+    ///
+    /// Receptions while nobody waits are not lost, the next call
+    /// returns right away.
+    pub async fn received(&self) {
+        self.received.notified().await
+    }
+
+    fn external_full(&self) -> bool {
+        self.external
+            .as_ref()
+            .is_some_and(|tx| tx.len() >= crate::EVENT_CHANNEL_CAPACITY.next_power_of_two())
+    }
+
+    pub fn send(&self, event: Arc<Event>) -> Result<(), SendError<Arc<Event>>> {
+        if let Some(external) = &self.external
+            && external.receiver_count() > 0
+        {
+            let _ = external.send(event.clone());
+        }
+        self.shards[self.shard_of(&event)].send(event).map(|_| ())
+    }
+}
+
+/// Receiving end of the dispatcher for one output, reading the shards
+/// in turn.
+pub struct EventReceiver {
+    shards: Vec<broadcast::Receiver<Arc<Event>>>,
+    /// Shard read first by the next call to `recv`, after the one the
+    /// last event came from.
+    next: usize,
+    /// Dispatcher to signal on every event received, if it waits for
+    /// this receiver.
+    received: Option<Arc<Notify>>,
+}
+
+impl EventReceiver {
+    /// Receive the next event, or how many events of a shard were
+    /// missed. Closed once every shard is.
+    ///
+    /// Cancel safe, no event is lost when the future is dropped.
+    pub async fn recv(&mut self) -> Result<Arc<Event>, RecvError> {
+        let res = self.recv_shards().await;
+        if let Some(received) = &self.received {
+            received.notify_one();
+        }
+        res
+    }
+
+    async fn recv_shards(&mut self) -> Result<Arc<Event>, RecvError> {
+        loop {
+            match &mut self.shards[..] {
+                [] => return Err(RecvError::Closed),
+                [rx] => return rx.recv().await,
+                _ => {}
+            }
+
+            let n = self.shards.len();
+            let start = self.next;
+            let mut pending = self
+                .shards
+                .iter_mut()
+                .map(|rx| Box::pin(rx.recv()))
+                .collect::<Vec<_>>();
+            let (i, res) = poll_fn(|cx| {
+                for i in (start..n).chain(0..start) {
+                    if let Poll::Ready(res) = pending[i].as_mut().poll(cx) {
+                        return Poll::Ready((i, res));
+                    }
+                }
+                Poll::Pending
+            })
+            .await;
+            drop(pending);
+
+            match res {
+                Err(RecvError::Closed) => {
+                    self.shards.remove(i);
+                    self.next = i % self.shards.len().max(1);
+                }
+                res => {
+                    self.next = (i + 1) % n;
+                    return res;
+                }
+            }
+        }
+    }
+
+    /// Events waiting to be received.
+    pub fn len(&self) -> usize {
+        self.shards.iter().map(|rx| rx.len()).sum()
+    }
+}
+
+impl From<broadcast::Receiver<Arc<Event>>> for EventReceiver {
+    fn from(rx: broadcast::Receiver<Arc<Event>>) -> Self {
+        EventReceiver {
+            shards: vec![rx],
+            next: 0,
+            received: None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{path::Path, time::Duration};
+
+    use tokio::time::timeout;
+
+    use super::*;
+
+    fn event(container_id: Option<&str>) -> Arc<Event> {
+        let dir = Path::new("/");
+        let event = Event::inventory(dir, &dir.metadata().unwrap());
+        let mut value = serde_json::to_value(event).unwrap();
+        value["process"]["container_id"] = serde_json::json!(container_id);
+        Arc::new(serde_json::from_value(value).unwrap())
+    }
+
+    /// Two container IDs going through different shards of `dispatcher`.
+    fn containers(dispatcher: &Dispatcher) -> (String, String) {
+        let a = String::from("aaaaaaaaaaaa");
+        let shard = dispatcher.shard_of(&event(Some(&a)));
+        let b = (0..)
+            .map(|i| format!("{i:012x}"))
+            .find(|b| dispatcher.shard_of(&event(Some(b))) != shard)
+            .unwrap();
+        (a, b)
+    }
+
+    #[tokio::test]
+    async fn single_channel() {
+        let (events, mut external) = broadcast::channel(crate::EVENT_CHANNEL_CAPACITY);
+        let dispatcher = Dispatcher::new(events, 1, 4, OverflowPolicy::Drop);
+        let mut rx = dispatcher.subscribe();
+        assert_eq!(dispatcher.receiver_count(), 2);
+
+        dispatcher.send(event(Some("aaaaaaaaaaaa"))).unwrap();
+        dispatcher.send(event(None)).unwrap();
+        assert_eq!(rx.len(), 2);
+        for expected in [Some("aaaaaaaaaaaa"), None] {
+            assert_eq!(rx.recv().await.unwrap().get_container_id(), expected);
+            assert_eq!(external.recv().await.unwrap().get_container_id(), expected);
+        }
+    }
+
+    #[tokio::test]
+    async fn shards_isolated() {
+        let (events, mut external) = broadcast::channel(crate::EVENT_CHANNEL_CAPACITY);
+        let dispatcher = Dispatcher::new(events, 4, 8, OverflowPolicy::Drop);
+        let mut rx = dispatcher.subscribe();
+        let (a, b) = containers(&dispatcher);
+
+        // Container a floods its shard while b sends a few events
+        for i in 0..100 {
+            dispatcher.send(event(Some(&a))).unwrap();
+            if i % 25 == 0 {
+                dispatcher.send(event(Some(&b))).unwrap();
+            }
+        }
+        assert!(dispatcher.any_full());
+        assert!(dispatcher.is_full(&event(Some(&a))));
+        assert!(!dispatcher.is_full(&event(Some(&b))));
+
+        let mut received = (0, 0);
+        let mut lagged = 0;
+        while rx.len() > 0 {
+            match rx.recv().await {
+                Ok(event) if event.get_container_id() == Some(&a) => received.0 += 1,
+                Ok(_) => received.1 += 1,
+                Err(RecvError::Lagged(n)) => lagged += n,
+                Err(RecvError::Closed) => unreachable!(),
+            }
+        }
+        // Only events of a are missed
+        assert_eq!(received, (8, 4));
+        assert_eq!(lagged, 92);
+
+        // Receivers of the pipeline still get every event
+        let mut external_events = 0;
+        while external.try_recv().is_ok() {
+            external_events += 1;
+        }
+        assert_eq!(external_events, 104);
+    }
+
+    #[tokio::test]
+    async fn shards_ordered() {
+        let (events, _) = broadcast::channel(crate::EVENT_CHANNEL_CAPACITY);
+        let dispatcher = Dispatcher::new(events, 3, 16, OverflowPolicy::Drop);
+        let mut rx = dispatcher.subscribe();
+        let (a, b) = containers(&dispatcher);
+
+        let mut sent = Vec::new();
+        for i in 0..6 {
+            let container = if i % 3 == 0 { &b } else { &a };
+            let event = event(Some(container));
+            sent.push((container.clone(), event.clone()));
+            dispatcher.send(event).unwrap();
+        }
+
+        // Shards are read in turn, events of each container keep the
+        // order they were sent in
+        let mut received = Vec::new();
+        for _ in 0..sent.len() {
+            received.push(rx.recv().await.unwrap());
+        }
+        for container in [&a, &b] {
+            let sent = sent
+                .iter()
+                .filter(|(c, _)| c == container)
+                .map(|(_, e)| Arc::as_ptr(e))
+                .collect::<Vec<_>>();
+            let received = received
+                .iter()
+                .filter(|e| e.get_container_id() == Some(container.as_str()))
+                .map(Arc::as_ptr)
+                .collect::<Vec<_>>();
+            assert_eq!(sent, received);
+        }
+
+        drop(dispatcher);
+        assert_eq!(rx.recv().await.unwrap_err(), RecvError::Closed);
+    }
+
+    #[tokio::test]
+    async fn block_own_channel() {
+        let (events, external) = broadcast::channel(crate::EVENT_CHANNEL_CAPACITY);
+        let dispatcher = Dispatcher::new(events, 1, 4, OverflowPolicy::Block);
+        let mut rx = dispatcher.subscribe();
+        assert_eq!(dispatcher.receiver_count(), 1);
+
+        for _ in 0..4 {
+            dispatcher.send(event(None)).unwrap();
+        }
+        assert!(dispatcher.any_full());
+
+        rx.recv().await.unwrap();
+        timeout(Duration::from_secs(1), dispatcher.received())
+            .await
+            .expect("Reception was not signaled");
+        // Receivers of the pipeline are not waited for
+        assert!(!dispatcher.any_full());
+        assert_eq!(external.len(), 4);
+    }
+}
@@ -0,0 +1,521 @@
+//! Events as Falco alerts, for pipelines already parsing them.
+//!
+//! Falco writes its alerts as JSON objects with the values of the rule
+//! output in `output_fields`, named after its filter fields like
+//! `evt.type` or `fd.name`. Events are mapped to the closest fields,
+//! the ones Falco has no equivalent for are prefixed with `fact.`.
+//! Fields without a value for an event are left out of
+//! `output_fields`, and shown as `<NA>` in `output` like Falco does.
+
+use serde::Serialize;
+use serde_json::{Map, Value, json};
+
+use crate::event::{Event, FileData};
+
+/// Falco `evt.type` of each event type, after the system call making
+/// the change. Event types not in the table, like inventory and
+/// summary, keep their name.
+const EVT_TYPES: &[(&str, &str)] = &[
+    ("open", "open"),
+    ("creation", "creat"),
+    ("mkdir", "mkdir"),
+    ("rmdir", "rmdir"),
+    ("unlink", "unlink"),
+    ("permission", "chmod"),
+    ("ownership", "chown"),
+    ("rename", "rename"),
+    ("xattr_set", "setxattr"),
+    ("xattr_remove", "removexattr"),
+    ("acl", "setxattr"),
+    ("write", "write"),
+    ("attributes", "ioctl"),
+];
+
+type Getter = fn(&Event) -> Option<Value>;
+
+/// Fields of `output_fields`, with how they are read from an event.
+const FIELDS: &[(&str, Getter)] = &[
+    ("evt.time", |e| Some(e.get_timestamp().into())),
+    ("evt.type", |e| Some(evt_type(e).into())),
+    ("evt.res", |e| e.is_blocked().then(|| "EPERM".into())),
+    ("fd.name", |e| fd_name(e).map(Into::into)),
+    ("fs.path.source", |e| {
+        e.get_old_filename().map(|p| p.to_string_lossy().into())
+    }),
+    ("fs.path.target", |e| {
+        e.is_rename()
+            .then(|| e.get_filename().to_string_lossy().into())
+    }),
+    ("evt.arg.mode", |e| match e.get_file() {
+        FileData::Chmod(data) => Some(format!("{:04o}", data.new_mode()).into()),
+        _ => None,
+    }),
+    ("evt.arg.uid", |e| match e.get_file() {
+        FileData::Chown(data) => Some(data.new_uid().into()),
+        _ => None,
+    }),
+    ("evt.arg.gid", |e| match e.get_file() {
+        FileData::Chown(data) => Some(data.new_gid().into()),
+        _ => None,
+    }),
+    ("evt.arg.name", |e| match e.get_file() {
+        FileData::SetXattr(data) | FileData::RemoveXattr(data) => Some(data.xattr_name().into()),
+        _ => None,
+    }),
+    ("proc.name", |e| Some(e.get_process().comm().into())),
+    ("proc.exepath", |e| {
+        Some(e.get_process().exe_path().to_string_lossy().into())
+    }),
+    ("proc.cmdline", |e| Some(cmdline(e).into())),
+    ("proc.args", |e| Some(args(e).into())),
+    ("proc.pid", |e| Some(e.get_pid().into())),
+    ("user.uid", |e| Some(e.get_uid().into())),
+    ("user.name", |e| {
+        let name = e.get_process().username();
+        (!name.is_empty()).then(|| name.into())
+    }),
+    // Falco shows processes without a login UID as -1
+    ("user.loginuid", |e| {
+        Some(e.get_process().login_uid().map_or(-1, i64::from).into())
+    }),
+    ("group.gid", |e| Some(e.get_process().gid().into())),
+    ("container.id", |e| Some(container_id(e).into())),
+    ("fact.event_type", |e| Some(e.event_type().into())),
+    ("fact.host_path", |e| {
+        let path = e.get_host_path();
+        (!path.as_os_str().is_empty()).then(|| path.to_string_lossy().into())
+    }),
+    ("fact.flags.old", |e| match e.get_file() {
+        FileData::Attributes(data) => Some(json!(data.old_flags())),
+        _ => None,
+    }),
+    ("fact.flags.new", |e| match e.get_file() {
+        FileData::Attributes(data) => Some(json!(data.new_flags())),
+        _ => None,
+    }),
+];
+
+fn evt_type(event: &Event) -> &'static str {
+    let event_type = event.event_type();
+    EVT_TYPES
+        .iter()
+        .find(|(t, _)| *t == event_type)
+        .map_or(event_type, |(_, evt_type)| *evt_type)
+}
+
+/// Path of the file as seen by the process, or on the host for events
+/// of hooks without one.
+fn fd_name(event: &Event) -> Option<String> {
+    [event.get_filename(), event.get_host_path()]
+        .into_iter()
+        .find(|path| !path.as_os_str().is_empty())
+        .map(|path| path.to_string_lossy().into_owned())
+}
+
+/// Arguments of the process without the first one, unquoted like Falco
+/// shows them.
+fn args(event: &Event) -> String {
+    let args = event.get_process().args();
+    args.get(1..).unwrap_or_default().join(" ")
+}
+
+/// Name of the process followed by its arguments.
+fn cmdline(event: &Event) -> String {
+    let args = args(event);
+    let comm = event.get_process().comm();
+    if args.is_empty() {
+        comm.to_owned()
+    } else {
+        format!("{comm} {args}")
+    }
+}
+
+/// Falco reports processes outside of containers in the `host` one.
+fn container_id(event: &Event) -> &str {
+    event.get_container_id().unwrap_or("host")
+}
+
+/// Wall clock time `ns` since the epoch in RFC 3339 format, in UTC
+/// with nanoseconds like Falco writes it.
+fn rfc3339(ns: u64) -> String {
+    let secs = ns / 1_000_000_000;
+    let (days, secs) = ((secs / 86400) as i64, secs % 86400);
+
+    // Civil date of the days since 1970-01-01, see
+    // http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + i64::from(month <= 2);
+
+    format!(
+        "{year:04}-{month:02}-{day:02}T{:02}:{:02}:{:02}.{:09}Z",
+        secs / 3600,
+        secs % 3600 / 60,
+        secs % 60,
+        ns % 1_000_000_000
+    )
+}
+
+/// An event in the format of a Falco alert.
+#[derive(Debug, Serialize)]
+pub struct Alert<'a> {
+    hostname: &'a str,
+    output: String,
+    output_fields: Map<String, Value>,
+    priority: &'static str,
+    rule: &'static str,
+    source: &'static str,
+    tags: [&'static str; 1],
+    time: String,
+}
+
+impl<'a> From<&'a Event> for Alert<'a> {
+    fn from(event: &'a Event) -> Self {
+        let output_fields = FIELDS
+            .iter()
+            .filter_map(|(name, get)| Some((name.to_string(), get(event)?)))
+            .collect();
+
+        let process = event.get_process();
+        let user = match process.username() {
+            "" => process.uid().to_string(),
+            name => name.to_owned(),
+        };
+        let output = format!(
+            "{} {} (user={user} command={} container_id={})",
+            event.event_type(),
+            fd_name(event).as_deref().unwrap_or("<NA>"),
+            cmdline(event),
+            container_id(event),
+        );
+
+        Alert {
+            hostname: event.get_hostname(),
+            output,
+            output_fields,
+            priority: if event.is_blocked() {
+                "Warning"
+            } else {
+                "Notice"
+            },
+            rule: "File activity",
+            source: "fact",
+            tags: ["filesystem"],
+            time: rfc3339(event.get_timestamp()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use fact_ebpf::{event_t, file_activity_type_t};
+
+    use super::*;
+    use crate::event::{process::Process, test_utils::string_to_c_char_array};
+
+    /// 2023-11-14T22:13:20.123456789Z
+    const TIMESTAMP: u64 = 1_700_000_000_123_456_789;
+    const CONTAINER_ID: &str = "0123456789ab";
+
+    fn process(container_id: Option<&str>) -> Process {
+        serde_json::from_value(json!({
+            "comm": "cat",
+            "args": ["cat", "/etc/app.conf"],
+            "exe_path": "/usr/bin/cat",
+            "container_id": container_id,
+            "uid": 1000,
+            "username": "alice",
+            "gid": 1000,
+            "login_uid": 1000,
+            "pid": 4242,
+            "in_root_mount_ns": false,
+            "lineage": [],
+        }))
+        .unwrap()
+    }
+
+    fn finish(mut event: Event) -> Event {
+        event.set_timestamp(TIMESTAMP);
+        event.set_hostname("node-1");
+        event
+    }
+
+    /// An event read from the kernel for a process in `container_id`,
+    /// with `set` filling the fields of its type.
+    fn kernel_event_in(
+        container_id: Option<&str>,
+        type_: file_activity_type_t,
+        filename: &str,
+        host_path: &str,
+        set: impl FnOnce(&mut event_t),
+    ) -> Event {
+        let mut raw = event_t {
+            type_,
+            ..Default::default()
+        };
+        set(&mut raw);
+        let file = FileData::new(
+            type_,
+            string_to_c_char_array(filename),
+            raw.inode,
+            raw.parent_inode,
+            raw.monitored,
+            raw.blocked != 0,
+            raw.__bindgen_anon_1,
+        )
+        .unwrap();
+        let mut event = Event::from_parts(TIMESTAMP, process(container_id), file);
+        event.set_host_path(host_path.into());
+        finish(event)
+    }
+
+    fn kernel_event(
+        type_: file_activity_type_t,
+        filename: &str,
+        host_path: &str,
+        set: impl FnOnce(&mut event_t),
+    ) -> Event {
+        kernel_event_in(Some(CONTAINER_ID), type_, filename, host_path, set)
+    }
+
+    fn simple(type_: file_activity_type_t) -> Event {
+        kernel_event(type_, "/etc/app.conf", "/var/lib/app/app.conf", |_| {})
+    }
+
+    /// An event of every type, and edge cases, along with the alert
+    /// expected for them.
+    fn cases() -> Vec<(&'static str, Event, &'static str)> {
+        vec![
+            (
+                "open",
+                simple(file_activity_type_t::FILE_ACTIVITY_OPEN),
+                include_str!("testdata/falco/open.json"),
+            ),
+            (
+                "open_blocked",
+                kernel_event(
+                    file_activity_type_t::FILE_ACTIVITY_OPEN,
+                    "/etc/app.conf",
+                    "/var/lib/app/app.conf",
+                    |raw| raw.blocked = 1,
+                ),
+                include_str!("testdata/falco/open_blocked.json"),
+            ),
+            (
+                "open_host",
+                kernel_event_in(
+                    None,
+                    file_activity_type_t::FILE_ACTIVITY_OPEN,
+                    "/etc/app.conf",
+                    "/etc/app.conf",
+                    |_| {},
+                ),
+                include_str!("testdata/falco/open_host.json"),
+            ),
+            (
+                "open_no_host_path",
+                kernel_event(
+                    file_activity_type_t::FILE_ACTIVITY_OPEN,
+                    "/etc/app.conf",
+                    "",
+                    |_| {},
+                ),
+                include_str!("testdata/falco/open_no_host_path.json"),
+            ),
+            (
+                "creation",
+                simple(file_activity_type_t::FILE_ACTIVITY_CREATION),
+                include_str!("testdata/falco/creation.json"),
+            ),
+            (
+                "write",
+                simple(file_activity_type_t::FILE_ACTIVITY_WRITE),
+                include_str!("testdata/falco/write.json"),
+            ),
+            (
+                "mkdir",
+                kernel_event(
+                    file_activity_type_t::DIR_ACTIVITY_CREATION,
+                    "/etc/app",
+                    "/var/lib/app",
+                    |_| {},
+                ),
+                include_str!("testdata/falco/mkdir.json"),
+            ),
+            (
+                "rmdir",
+                kernel_event(
+                    file_activity_type_t::DIR_ACTIVITY_UNLINK,
+                    "/etc/app",
+                    "/var/lib/app",
+                    |_| {},
+                ),
+                include_str!("testdata/falco/rmdir.json"),
+            ),
+            (
+                "unlink",
+                simple(file_activity_type_t::FILE_ACTIVITY_UNLINK),
+                include_str!("testdata/falco/unlink.json"),
+            ),
+            (
+                "permission",
+                kernel_event(
+                    file_activity_type_t::FILE_ACTIVITY_CHMOD,
+                    "/etc/app.conf",
+                    "/var/lib/app/app.conf",
+                    |raw| {
+                        raw.__bindgen_anon_1.chmod.new = 0o640;
+                        raw.__bindgen_anon_1.chmod.old = 0o644;
+                    },
+                ),
+                include_str!("testdata/falco/permission.json"),
+            ),
+            (
+                "ownership",
+                kernel_event(
+                    file_activity_type_t::FILE_ACTIVITY_CHOWN,
+                    "/etc/app.conf",
+                    "/var/lib/app/app.conf",
+                    |raw| {
+                        raw.__bindgen_anon_1.chown.new.uid = 0;
+                        raw.__bindgen_anon_1.chown.new.gid = 10;
+                        raw.__bindgen_anon_1.chown.old.uid = 1000;
+                        raw.__bindgen_anon_1.chown.old.gid = 1000;
+                    },
+                ),
+                include_str!("testdata/falco/ownership.json"),
+            ),
+            (
+                "rename",
+                {
+                    let mut event = kernel_event(
+                        file_activity_type_t::FILE_ACTIVITY_RENAME,
+                        "/etc/app.conf",
+                        "/var/lib/app/app.conf",
+                        |raw| {
+                            raw.__bindgen_anon_1.rename.filename =
+                                string_to_c_char_array("/etc/app.conf.tmp");
+                        },
+                    );
+                    event.set_old_host_path("/var/lib/app/app.conf.tmp".into());
+                    event
+                },
+                include_str!("testdata/falco/rename.json"),
+            ),
+            (
+                "xattr_set",
+                kernel_event(
+                    file_activity_type_t::FILE_ACTIVITY_SETXATTR,
+                    "",
+                    "/var/lib/app/app.conf",
+                    |raw| {
+                        raw.__bindgen_anon_1.xattr.name = string_to_c_char_array("user.tag");
+                    },
+                ),
+                include_str!("testdata/falco/xattr_set.json"),
+            ),
+            (
+                "xattr_remove",
+                kernel_event(
+                    file_activity_type_t::FILE_ACTIVITY_REMOVEXATTR,
+                    "",
+                    "/var/lib/app/app.conf",
+                    |raw| {
+                        raw.__bindgen_anon_1.xattr.name = string_to_c_char_array("user.tag");
+                    },
+                ),
+                include_str!("testdata/falco/xattr_remove.json"),
+            ),
+            (
+                "acl",
+                kernel_event(
+                    file_activity_type_t::FILE_ACTIVITY_ACL_SET,
+                    "",
+                    "/var/lib/app/app.conf",
+                    |_| {},
+                ),
+                include_str!("testdata/falco/acl.json"),
+            ),
+            (
+                "attributes",
+                kernel_event(
+                    file_activity_type_t::FILE_ACTIVITY_ATTRIBUTES,
+                    "",
+                    "/var/lib/app/app.conf",
+                    |raw| {
+                        raw.__bindgen_anon_1.attributes.old = fact_ebpf::FACT_FS_IMMUTABLE_FL;
+                        raw.__bindgen_anon_1.attributes.new = 0;
+                    },
+                ),
+                include_str!("testdata/falco/attributes.json"),
+            ),
+            (
+                "inventory",
+                {
+                    let root = std::path::Path::new("/");
+                    finish(Event::inventory(root, &root.metadata().unwrap()))
+                },
+                include_str!("testdata/falco/inventory.json"),
+            ),
+            (
+                "summary",
+                finish(Event::summary(1, Vec::new())),
+                include_str!("testdata/falco/summary.json"),
+            ),
+            (
+                "pause",
+                finish(Event::pause(Some(1700000600))),
+                include_str!("testdata/falco/pause.json"),
+            ),
+        ]
+    }
+
+    #[test]
+    fn golden() {
+        let cases = cases();
+        for event_type in FileData::EVENT_TYPES {
+            assert!(
+                cases
+                    .iter()
+                    .any(|(_, event, _)| event.event_type() == event_type),
+                "No case for {event_type}"
+            );
+        }
+
+        for (name, event, expected) in cases {
+            let expected: Value = serde_json::from_str(expected).unwrap();
+            let alert = serde_json::to_value(Alert::from(&event)).unwrap();
+            assert_eq!(alert, expected, "Failed for {name}");
+        }
+    }
+
+    #[test]
+    fn evt_types() {
+        for (event_type, _) in EVT_TYPES {
+            assert!(
+                FileData::EVENT_TYPES.contains(event_type),
+                "Unknown event type {event_type}"
+            );
+        }
+    }
+
+    #[test]
+    fn timestamps() {
+        let tests = [
+            (0, "1970-01-01T00:00:00.000000000Z"),
+            (TIMESTAMP, "2023-11-14T22:13:20.123456789Z"),
+            // Leap day
+            (1_709_164_800_000_000_001, "2024-02-29T00:00:00.000000001Z"),
+            (951_868_799_999_999_999, "2000-02-29T23:59:59.999999999Z"),
+        ];
+        for (ns, expected) in tests {
+            assert_eq!(rfc3339(ns), expected);
+        }
+    }
+}
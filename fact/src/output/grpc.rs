@@ -1,30 +1,49 @@
-use std::{sync::Arc, time::Duration};
+use std::{
+    sync::Arc,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
 
 use anyhow::{Context, bail};
 use fact_api::file_activity_service_client::FileActivityServiceClient;
 use hyper_tls::HttpsConnector;
 use hyper_util::client::legacy::connect::HttpConnector;
 use log::{info, warn};
-use native_tls::{Certificate, Identity};
-use openssl::{ec::EcKey, pkey::PKey};
 use tokio::{
-    fs,
-    sync::{mpsc, oneshot, watch},
+    sync::{broadcast::error::RecvError, mpsc, oneshot, watch},
     task::JoinSet,
-    time::sleep,
+    time::{Instant, interval_at, sleep},
 };
-use tokio_stream::{
-    StreamExt,
-    wrappers::{BroadcastStream, errors::BroadcastStreamRecvError},
+use tokio_stream::{StreamExt, wrappers::ReceiverStream};
+use tonic::{
+    Request, Status,
+    codec::CompressionEncoding,
+    metadata::{AsciiMetadataValue, MetadataMap},
+    service::Interceptor,
+    transport::Channel,
 };
-use tonic::transport::Channel;
 
 use crate::{
-    config::{BackoffConfig, GrpcConfig},
-    metrics::EventCounter,
-    output::EventReceiver,
+    config::{BackoffConfig, GrpcCompression, GrpcConfig, is_metadata_value},
+    host_info,
+    metrics::{
+        ActiveEndpoint, CertExpiry, DropReason, EventCounter, LastSuccess, OutputMetrics, Sink,
+    },
+    node_id,
+    output::{
+        EventReceiver,
+        proxy::{self, ProxyConnector},
+        resolver::{CachingResolver, RESOLVE_TIMEOUT, RESOLVE_TTL, SystemLookup},
+    },
+    secret::Source,
+    tls::{ClientCerts, Expiry},
+    trace,
+    version::FACT_VERSION,
 };
 
+/// Interval between checks of the expiry of the certificates while a
+/// stream is open.
+const CERT_CHECK_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
 struct Backoff {
     initial: Duration,
     current: Duration,
@@ -108,20 +127,36 @@ pub struct Client {
     running: watch::Receiver<bool>,
     config: watch::Receiver<GrpcConfig>,
     metrics: EventCounter,
+    endpoint: ActiveEndpoint,
+    last_success: LastSuccess,
+    cert_expiry: CertExpiry,
+    resolver: CachingResolver,
+    proxy_env: proxy::Env,
 }
 
 impl Client {
     pub fn new(
         subscriber: mpsc::Sender<oneshot::Sender<EventReceiver>>,
         running: watch::Receiver<bool>,
-        metrics: EventCounter,
+        metrics: &OutputMetrics,
         config: watch::Receiver<GrpcConfig>,
     ) -> Self {
+        let resolver = CachingResolver::new(
+            SystemLookup,
+            RESOLVE_TIMEOUT,
+            RESOLVE_TTL,
+            metrics.grpc_dns.clone(),
+        );
         Client {
             subscriber,
             running,
             config,
-            metrics,
+            metrics: metrics.grpc.clone(),
+            endpoint: metrics.grpc_endpoint.clone(),
+            last_success: metrics.last_success.clone(),
+            cert_expiry: metrics.cert_expiry.clone(),
+            resolver,
+            proxy_env: proxy::system_env,
         }
     }
 
@@ -133,6 +168,7 @@ impl Client {
                 } else {
                     self.idle().await
                 };
+                self.endpoint.set(None);
 
                 match res {
                     Ok(true) => info!("Reloading gRPC configuration..."),
@@ -147,39 +183,60 @@ impl Client {
         });
     }
 
-    async fn get_connector(&self) -> anyhow::Result<Option<HttpsConnector<HttpConnector>>> {
-        let certs = {
+    /// Create a connector that gets the addresses for the sensor from
+    /// the cache in the resolver, instead of resolving them on every
+    /// connection attempt.
+    ///
+    /// Connections go through the proxy if one is configured for the
+    /// server.
+    fn http_connector(&self) -> ProxyConnector {
+        let mut http = HttpConnector::new_with_resolver(self.resolver.clone());
+        http.enforce_http(false);
+        http.set_nodelay(true);
+        let matcher = proxy::matcher(self.config.borrow().proxy(), self.proxy_env);
+        ProxyConnector::new(http, matcher)
+    }
+
+    /// Load the certificates from the configured directory, reporting
+    /// their expiry.
+    async fn load_certs(&self) -> anyhow::Result<Option<ClientCerts>> {
+        let (certs, passphrase_file, window) = {
             let config = self.config.borrow();
             let Some(certs) = config.certs() else {
                 return Ok(None);
             };
-            certs.to_owned()
+            (
+                Source::new(certs)?,
+                config.key_passphrase_file().map(Source::new).transpose()?,
+                config.cert_expiry_warning(),
+            )
+        };
+        let certs = ClientCerts::load(&certs, passphrase_file.as_ref()).await?;
+
+        let now = unix_now();
+        self.cert_expiry.set(certs.expiry());
+        for expiry in expiring(certs.expiry(), now, window) {
+            let verb = if expiry.timestamp <= now {
+                "expired"
+            } else {
+                "expires"
+            };
+            warn!(
+                "The {} certificate {:?} {verb} on {}",
+                expiry.kind, expiry.subject, expiry.not_after
+            );
+        }
+        Ok(Some(certs))
+    }
+
+    async fn get_connector(&self) -> anyhow::Result<Option<HttpsConnector<ProxyConnector>>> {
+        let Some(certs) = self.load_certs().await? else {
+            return Ok(None);
         };
-        let (ca, cert, key) = tokio::try_join!(
-            fs::read(certs.join("ca.pem")),
-            fs::read(certs.join("cert.pem")),
-            fs::read(certs.join("key.pem")),
-        )?;
-        let ca = Certificate::from_pem(&ca).context("Failed to parse CA")?;
-
-        // The key is in PKCS#1 format using EC algorithm, we need it
-        // in PKCS#8 format for native-tls, so we convert it here
-        let key = EcKey::private_key_from_pem(&key)?;
-        let key = PKey::from_ec_key(key)?;
-        let key = key.private_key_to_pem_pkcs8()?;
-
-        let id = Identity::from_pkcs8(&cert, &key).context("Failed to create TLS identity")?;
-        let connector = native_tls::TlsConnector::builder()
-            .add_root_certificate(ca)
-            .identity(id)
-            .request_alpns(&["h2"])
-            .build()?;
-        let connector = tokio_native_tls::TlsConnector::from(connector);
+        let connector = tokio_native_tls::TlsConnector::from(certs.tls_connector()?);
 
         // Wrap the TLS connector into the final HTTPs connector
-        let mut http = HttpConnector::new();
-        http.enforce_http(false);
-        let mut connector = HttpsConnector::from((http, connector));
+        let mut connector = HttpsConnector::from((self.http_connector(), connector));
         connector.https_only(true);
 
         Ok(Some(connector))
@@ -187,89 +244,179 @@ impl Client {
 
     async fn create_channel(
         &self,
-        connector: Option<HttpsConnector<HttpConnector>>,
+        url: &str,
+        connector: Option<HttpsConnector<ProxyConnector>>,
     ) -> anyhow::Result<Channel> {
-        let url = match self.config.borrow().url() {
-            Some(url) => url.to_string(),
-            None => bail!("Attempting to run gRPC client with no URL"),
-        };
-        let channel = Channel::from_shared(url)?;
+        let channel =
+            Channel::from_shared(url.to_owned())?.user_agent(format!("fact/{FACT_VERSION}"))?;
         let channel = match connector {
             Some(connector) => channel.connect_with_connector(connector).await?,
             None => {
                 warn!("Using unencrypted gRPC channel");
-                channel.connect().await?
+                channel
+                    .connect_with_connector(self.http_connector())
+                    .await?
             }
         };
         Ok(channel)
     }
 
+    /// Open a channel to the server, along with the token to
+    /// authenticate with if one is configured.
+    async fn connect(
+        &self,
+        url: &str,
+        connector: Option<HttpsConnector<ProxyConnector>>,
+    ) -> anyhow::Result<(Channel, BearerToken)> {
+        let token_file = self.config.borrow().token_file().map(Source::new);
+        let token = match token_file.transpose()? {
+            Some(source) => Some(read_token(&source).await?),
+            None => None,
+        };
+        let channel = self.create_channel(url, connector).await?;
+        Ok((channel, BearerToken(token)))
+    }
+
     async fn run(&mut self) -> anyhow::Result<bool> {
         let mut backoff = Backoff::from(&self.config.borrow().backoff);
+        let urls = self.config.borrow().urls().to_vec();
+        let mut endpoints = Endpoints::new(urls.len());
         loop {
             if self.subscriber.is_closed() {
                 info!("Channel closed, stopping gRPC output...");
                 return Ok(false);
             }
 
-            // Re-read certs on each connection attempt so rotated certificates
-            // on disk are picked up on the next reconnect.
+            // Re-read certs and token on each connection attempt so
+            // rotated ones on disk are picked up on the next reconnect.
             let connector = self.get_connector().await?;
-            info!("Attempting to connect to gRPC server...");
-            let channel = match self.create_channel(connector).await {
-                Ok(channel) => channel,
+            let url = &urls[endpoints.current()];
+            info!("Attempting to connect to gRPC server at {url}...");
+            let (channel, token) = match self.connect(url, connector).await {
+                Ok(connection) => connection,
                 Err(e) => {
+                    // Back off only once all endpoints have failed
+                    if !endpoints.failed() {
+                        let next = &urls[endpoints.current()];
+                        warn!("Failed to connect to {url}: {e:?}\nTrying {next}");
+                        continue;
+                    }
                     let Some(delay) = backoff.next() else {
                         bail!(
                             "Failed to connect to server: Reconnection attempts exhausted: {e:?}"
                         );
                     };
                     warn!("Failed to connect to server: {e:?}\nRetrying in {delay:?}");
-                    sleep(delay).await;
-                    continue;
+                    tokio::select! {
+                        _ = sleep(delay) => continue,
+                        _ = self.config.changed() => return Ok(true),
+                        _ = self.running.changed() => return Ok(*self.running.borrow()),
+                    }
                 }
             };
-            info!("Successfully connected to gRPC server");
+            info!("Successfully connected to gRPC server at {url}");
             backoff.reset();
+            endpoints.connected();
+            self.endpoint.set(Some(url));
+            self.last_success.touch(Sink::Grpc);
 
-            let mut client = FileActivityServiceClient::new(channel);
+            let mut client = FileActivityServiceClient::with_interceptor(channel, token);
+            if let Some(encoding) = encoding(self.config.borrow().compression()) {
+                client = client.send_compressed(encoding).accept_compressed(encoding);
+            }
+            let identity = identity(
+                host_info::get_hostname(),
+                node_id::get(),
+                self.config.borrow().cluster_id(),
+            );
 
-            let metrics = self.metrics.clone();
             let (tx, rx) = oneshot::channel();
             self.subscriber.send(tx).await?;
-            let rx = rx.await?;
-            let rx = BroadcastStream::new(rx).filter_map(move |event| match event {
-                Ok(event) => {
-                    metrics.added();
-                    let event = Arc::unwrap_or_clone(event);
-                    Some(event.into())
-                }
-                Err(BroadcastStreamRecvError::Lagged(n)) => {
-                    warn!("gRPC stream lagged, dropped {n} events");
-                    metrics.dropped_n(n);
-                    None
-                }
-            });
+            let mut events = rx.await?;
 
-            tokio::select! {
-                res = client.communicate(rx) => {
-                    match res {
-                        Ok(_) => info!("gRPC stream ended"),
-                        Err(_) if self.subscriber.is_closed() => {
-                            info!("Channel closed, stopping gRPC output...");
-                            return Ok(false);
+            // Events are handed to the stream one at a time, the ones
+            // still in the channel when it closes are known to be lost.
+            // The stream ends at the first None, once there are no more
+            // events to send.
+            let (stream_tx, stream_rx) = mpsc::channel(1);
+            let stream = ReceiverStream::new(stream_rx).map_while(|event| event);
+            let mut request = Request::new(stream);
+            *request.metadata_mut() = identity;
+
+            // Streams can outlive the certificates, their expiry is
+            // checked again while connected
+            let communicate = client.communicate(request);
+            tokio::pin!(communicate);
+            let mut cert_check =
+                interval_at(Instant::now() + CERT_CHECK_INTERVAL, CERT_CHECK_INTERVAL);
+            // The next event along with the span of its send, closed
+            // once the stream takes it
+            let mut next = None;
+            let mut closed = false;
+            loop {
+                tokio::select! {
+                    event = events.recv(), if next.is_none() && !closed => match event {
+                        Ok(event) => {
+                            // Kinds the Sensor API has no message for,
+                            // like writes and summaries, are skipped
+                            let event = Arc::unwrap_or_clone(event);
+                            if let Ok(event) = fact_api::FileActivity::try_from(event) {
+                                next = Some((event, trace::stage_span!("grpc_send")));
+                            }
+                        }
+                        Err(RecvError::Lagged(n)) => {
+                            warn!("gRPC stream lagged, dropped {n} events");
+                            self.metrics.dropped_n(DropReason::Lagged, n);
+                        }
+                        Err(RecvError::Closed) => {
+                            closed = true;
+                            let _ = stream_tx.send(None).await;
+                        }
+                    },
+                    permit = stream_tx.reserve(), if next.is_some() => {
+                        // Without a permit the stream is gone, which
+                        // communicate reports next
+                        if let Ok(permit) = permit {
+                            let (event, _sending) = next.take().unzip();
+                            permit.send(event);
+                            self.metrics.added();
+                            self.last_success.touch(Sink::Grpc);
                         }
-                        Err(e) => warn!("gRPC stream error: {e:?}"),
                     }
+                    res = &mut communicate => {
+                        let lost = events.len() + usize::from(next.is_some());
+                        if lost > 0 {
+                            warn!("gRPC stream closed, dropped {lost} events");
+                            self.metrics.dropped_n(DropReason::StreamClosed, lost as u64);
+                        }
+                        match res {
+                            Ok(_) => info!("gRPC stream ended"),
+                            Err(_) if self.subscriber.is_closed() => {
+                                info!("Channel closed, stopping gRPC output...");
+                                return Ok(false);
+                            }
+                            Err(e) => {
+                                warn!("gRPC stream error on {url}: {e:?}");
+                                endpoints.rotate();
+                            }
+                        }
+                        self.endpoint.set(None);
+                        break;
+                    }
+                    _ = cert_check.tick() => {
+                        if let Err(e) = self.load_certs().await {
+                            warn!("Failed to check the expiry of certificates: {e:?}");
+                        }
+                    }
+                    _ = self.config.changed() => return Ok(true),
+                    _ = self.running.changed() => return Ok(*self.running.borrow()),
                 }
-                _ = self.config.changed() => return Ok(true),
-                _ = self.running.changed() => return Ok(*self.running.borrow()),
             }
         }
     }
 
     pub(super) fn is_enabled(&self) -> bool {
-        self.config.borrow().url().is_some()
+        !self.config.borrow().urls().is_empty()
     }
 
     async fn idle(&mut self) -> anyhow::Result<bool> {
@@ -280,9 +427,174 @@ impl Client {
     }
 }
 
+/// Position in the list of endpoints of the server.
+///
+/// The endpoint connected to is kept until its stream fails, the next
+/// one in the list is tried after that.
+struct Endpoints {
+    len: usize,
+    current: usize,
+    failures: usize,
+}
+
+impl Endpoints {
+    fn new(len: usize) -> Self {
+        Endpoints {
+            len,
+            current: 0,
+            failures: 0,
+        }
+    }
+
+    fn current(&self) -> usize {
+        self.current
+    }
+
+    fn rotate(&mut self) {
+        self.current = (self.current + 1) % self.len;
+    }
+
+    /// Record a failed connection to the current endpoint and move to
+    /// the next one, returns whether all endpoints failed in a row.
+    fn failed(&mut self) -> bool {
+        self.rotate();
+        self.failures += 1;
+        if self.failures < self.len {
+            return false;
+        }
+        self.failures = 0;
+        true
+    }
+
+    fn connected(&mut self) {
+        self.failures = 0;
+    }
+}
+
+/// Adds the bearer token read from `grpc.token_file` to requests.
+#[derive(Clone)]
+struct BearerToken(Option<AsciiMetadataValue>);
+
+impl Interceptor for BearerToken {
+    fn call(&mut self, mut request: Request<()>) -> Result<Request<()>, Status> {
+        if let Some(token) = &self.0 {
+            request
+                .metadata_mut()
+                .insert("authorization", token.clone());
+        }
+        Ok(request)
+    }
+}
+
+/// Read the bearer token from `source` into the value of an
+/// authorization header.
+///
+/// Surrounding whitespace is not part of the token. The value is
+/// marked as sensitive so it is left out of debug output, errors never
+/// include the content of the file.
+async fn read_token(source: &Source) -> anyhow::Result<AsciiMetadataValue> {
+    let token = source
+        .read()
+        .await
+        .with_context(|| format!("Failed to read token from {source}"))?;
+    let Ok(token) = std::str::from_utf8(token.as_bytes()) else {
+        bail!("Token in {source} is not valid UTF-8");
+    };
+    let token = token.trim();
+    if token.is_empty() {
+        bail!("Token file {source} is empty");
+    }
+    if !is_metadata_value(token) {
+        bail!("Token in {source} has characters that cannot be sent as gRPC metadata");
+    }
+    let mut value = AsciiMetadataValue::try_from(format!("Bearer {token}"))?;
+    value.set_sensitive(true);
+    Ok(value)
+}
+
+fn unix_now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs() as i64
+}
+
+/// Certificates expiring within `window` of `now`, or already expired.
+fn expiring(expiry: &[Expiry], now: i64, window: Duration) -> impl Iterator<Item = &Expiry> {
+    let deadline = now.saturating_add(window.as_secs() as i64);
+    expiry.iter().filter(move |e| e.timestamp <= deadline)
+}
+
+fn encoding(compression: GrpcCompression) -> Option<CompressionEncoding> {
+    match compression {
+        GrpcCompression::None => None,
+        GrpcCompression::Gzip => Some(CompressionEncoding::Gzip),
+        GrpcCompression::Zstd => Some(CompressionEncoding::Zstd),
+    }
+}
+
+/// Metadata identifying the node to the server, sent when opening a
+/// stream.
+///
+/// The cluster ID is validated when the configuration is loaded, a
+/// hostname that cannot be sent is left out, as is the node ID before
+/// it is loaded.
+fn identity(hostname: &str, node_id: &str, cluster_id: Option<&str>) -> MetadataMap {
+    let mut metadata = MetadataMap::new();
+    match AsciiMetadataValue::try_from(hostname) {
+        Ok(value) if is_metadata_value(hostname) => {
+            metadata.insert("x-fact-hostname", value);
+        }
+        _ => warn!("Hostname {hostname:?} cannot be sent as gRPC metadata"),
+    }
+    if let Ok(value) = AsciiMetadataValue::try_from(node_id)
+        && !node_id.is_empty()
+    {
+        metadata.insert("x-fact-node-id", value);
+    }
+    if let Some(cluster_id) = cluster_id.and_then(|c| AsciiMetadataValue::try_from(c).ok()) {
+        metadata.insert("x-fact-cluster", cluster_id);
+    }
+    metadata
+}
+
 #[cfg(test)]
 mod tests {
+    use std::{
+        convert::Infallible,
+        net::SocketAddr,
+        path::{Path, PathBuf},
+        sync::{Arc, Mutex},
+    };
+
+    use fact_api::file_activity::File;
+    use fact_ebpf::{PATH_MAX, event_t, file_activity_type_t};
+    use http_body_util::Empty;
+    use hyper::{
+        Method, StatusCode,
+        body::{Bytes, Incoming},
+        header::PROXY_AUTHORIZATION,
+        server::conn::http1,
+        service::service_fn,
+    };
+    use hyper_util::rt::TokioIo;
+    use tempfile::TempDir;
+    use tokio::{
+        io::copy_bidirectional,
+        net::{TcpListener, TcpStream},
+        sync::broadcast,
+        task::JoinHandle,
+        time::timeout,
+    };
+
     use super::*;
+    use crate::{
+        config::FactConfig,
+        event::{Event, test_utils::string_to_c_char_array},
+        metrics::{Metrics, OutputMetrics},
+        output::mock_sensor::{Behavior, MockSensor, unused_addr},
+        tls::CertKind,
+    };
 
     #[test]
     fn backoff_exponential_2x() {
@@ -434,4 +746,567 @@ mod tests {
         assert_eq!(b.next(), Some(Duration::from_secs(60)));
         assert_eq!(b.next(), Some(Duration::from_secs(60)));
     }
+
+    #[test]
+    fn cert_expiry() {
+        const DAY: i64 = 24 * 60 * 60;
+        let now = 1_700_000_000;
+        let expiry = |kind, timestamp| Expiry {
+            kind,
+            subject: String::from("CN=fact"),
+            not_after: String::new(),
+            timestamp,
+        };
+        let certs = [
+            expiry(CertKind::Client, now + 3 * DAY),
+            expiry(CertKind::Ca, now + 30 * DAY),
+            expiry(CertKind::Ca, now - DAY),
+        ];
+
+        let window = Duration::from_secs(7 * DAY as u64);
+        let warned = expiring(&certs, now, window)
+            .map(|e| e.timestamp)
+            .collect::<Vec<_>>();
+        assert_eq!(warned, [now + 3 * DAY, now - DAY]);
+        assert_eq!(expiring(&certs, now, Duration::ZERO).count(), 1);
+
+        // The earliest CA is the one exported
+        let metrics = Metrics::new().output.cert_expiry;
+        metrics.set(&certs);
+        assert_eq!(metrics.get(CertKind::Client), Some(now + 3 * DAY));
+        assert_eq!(metrics.get(CertKind::Ca), Some(now - DAY));
+        metrics.set(&certs[..1]);
+        assert_eq!(metrics.get(CertKind::Ca), None);
+    }
+
+    fn grpc_config(url: &str) -> GrpcConfig {
+        let yaml = format!(
+            "grpc:\n  url: {url}\n  backoff:\n    initial: 0.01\n    max: 0.05\n    jitter: false"
+        );
+        FactConfig::try_from(yaml.as_str()).unwrap().grpc
+    }
+
+    /// A gRPC client fed by a broadcast channel, like the one from the
+    /// output component.
+    struct TestClient {
+        events: broadcast::Sender<Arc<Event>>,
+        running: watch::Sender<bool>,
+        config: watch::Sender<GrpcConfig>,
+        metrics: OutputMetrics,
+        tasks: JoinSet<anyhow::Result<()>>,
+        dir: TempDir,
+    }
+
+    impl TestClient {
+        fn start(config: GrpcConfig) -> Self {
+            let (events, _) = broadcast::channel(100);
+            let (running, running_rx) = watch::channel(true);
+            let (config, config_rx) = watch::channel(config);
+            let (subscriber, mut subscriptions) = mpsc::channel(10);
+
+            let mut tasks = JoinSet::new();
+            let metrics = Metrics::new().output;
+            let mut client = Client::new(subscriber, running_rx, &metrics, config_rx);
+            // Proxy variables of the environment running the tests
+            // are not used
+            client.proxy_env = |_| None;
+            client.start(&mut tasks);
+
+            let tx = events.clone();
+            tokio::spawn(async move {
+                while let Some(reply) = subscriptions.recv().await {
+                    let _: Result<_, _> = reply.send(tx.subscribe().into());
+                }
+            });
+
+            let dir = tempfile::tempdir().expect("Failed to create directory");
+            TestClient {
+                events,
+                running,
+                config,
+                metrics,
+                tasks,
+                dir,
+            }
+        }
+
+        /// Send an inventory event for `name`, returning its path.
+        fn send(&self, name: &str) -> PathBuf {
+            let path = self.dir.path().join(name);
+            std::fs::write(&path, name).unwrap();
+            let event = Event::inventory(&path, &path.metadata().unwrap());
+            self.events.send(Arc::new(event)).unwrap();
+            path
+        }
+
+        async fn stop(mut self) -> anyhow::Result<()> {
+            self.running.send_replace(false);
+            timeout(Duration::from_secs(10), self.tasks.join_next())
+                .await
+                .expect("Timed out stopping the client")
+                .expect("Client task not found")
+                .expect("Client task panicked")
+        }
+    }
+
+    /// Target and authorization of the tunnels opened by a proxy.
+    type Tunnels = Mutex<Vec<(String, Option<String>)>>;
+
+    /// A minimal HTTP proxy, only supporting CONNECT. The target and
+    /// authorization of every tunnel are recorded.
+    struct ConnectProxy {
+        addr: SocketAddr,
+        tunnels: Arc<Tunnels>,
+        server: JoinHandle<()>,
+    }
+
+    impl ConnectProxy {
+        async fn start() -> Self {
+            let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let addr = listener.local_addr().unwrap();
+            let tunnels = Arc::new(Mutex::new(Vec::new()));
+
+            let recorded = tunnels.clone();
+            let server = tokio::spawn(async move {
+                while let Ok((stream, _)) = listener.accept().await {
+                    let recorded = recorded.clone();
+                    let service = service_fn(move |req: hyper::Request<Incoming>| {
+                        let recorded = recorded.clone();
+                        async move { Ok::<_, Infallible>(ConnectProxy::tunnel(req, &recorded)) }
+                    });
+                    tokio::spawn(
+                        http1::Builder::new()
+                            .serve_connection(TokioIo::new(stream), service)
+                            .with_upgrades(),
+                    );
+                }
+            });
+
+            ConnectProxy {
+                addr,
+                tunnels,
+                server,
+            }
+        }
+
+        fn tunnel(
+            req: hyper::Request<Incoming>,
+            recorded: &Tunnels,
+        ) -> hyper::Response<Empty<Bytes>> {
+            let mut res = hyper::Response::new(Empty::new());
+            let Some(target) = req.uri().authority().map(|a| a.to_string()) else {
+                *res.status_mut() = StatusCode::BAD_REQUEST;
+                return res;
+            };
+            if req.method() != Method::CONNECT {
+                *res.status_mut() = StatusCode::METHOD_NOT_ALLOWED;
+                return res;
+            }
+            let auth = req
+                .headers()
+                .get(PROXY_AUTHORIZATION)
+                .and_then(|v| v.to_str().ok())
+                .map(str::to_owned);
+            recorded.lock().unwrap().push((target.clone(), auth));
+
+            tokio::spawn(async move {
+                let Ok(upgraded) = hyper::upgrade::on(req).await else {
+                    return;
+                };
+                let Ok(mut server) = TcpStream::connect(target).await else {
+                    return;
+                };
+                let _ = copy_bidirectional(&mut TokioIo::new(upgraded), &mut server).await;
+            });
+            res
+        }
+
+        fn tunnels(&self) -> Vec<(String, Option<String>)> {
+            self.tunnels.lock().unwrap().clone()
+        }
+    }
+
+    impl Drop for ConnectProxy {
+        fn drop(&mut self) {
+            self.server.abort();
+        }
+    }
+
+    fn path_of(msg: &fact_api::FileActivity) -> PathBuf {
+        let Some(File::Creation(creation)) = &msg.file else {
+            panic!("Unexpected message: {msg:?}");
+        };
+        PathBuf::from(&creation.activity.as_ref().unwrap().path)
+    }
+
+    #[tokio::test]
+    async fn proxy() {
+        let mut sensor = MockSensor::start(Behavior::default()).await;
+        let proxy = ConnectProxy::start().await;
+        let yaml = format!(
+            "grpc:\n  url: {}\n  proxy: http://user:s3cr3t@{}",
+            sensor.url(),
+            proxy.addr
+        );
+        let client = TestClient::start(FactConfig::try_from(yaml.as_str()).unwrap().grpc);
+
+        sensor.wait_streams(1).await;
+        let path = client.send("file");
+        assert_eq!(path_of(&sensor.next().await), path);
+
+        let target = sensor.url().trim_start_matches("http://").to_owned();
+        // user:s3cr3t
+        let auth = Some(String::from("Basic dXNlcjpzM2NyM3Q="));
+        assert_eq!(proxy.tunnels(), [(target, auth)]);
+
+        client.stop().await.unwrap();
+        sensor.stop().await;
+    }
+
+    #[tokio::test]
+    async fn connect_retry() {
+        // The sensor is not up yet when the client starts
+        let addr = unused_addr();
+        let client = TestClient::start(grpc_config(&format!("http://{addr}")));
+        tokio::time::sleep(Duration::from_millis(100)).await;
+
+        let mut sensor = MockSensor::start_at(
+            addr,
+            Behavior {
+                refuse: 2,
+                ..Default::default()
+            },
+        )
+        .await;
+        sensor.wait_streams(1).await;
+        assert_eq!(sensor.refused(), 2);
+        let last_success = &client.metrics.last_success;
+        let connected = last_success.get(Sink::Grpc).expect("No success recorded");
+        assert!(client.metrics.grpc_endpoint.is_connected());
+        let path = client.send("file");
+        assert_eq!(path_of(&sensor.next().await), path);
+        assert!(last_success.get(Sink::Grpc).unwrap() >= connected);
+        assert_eq!(last_success.get(Sink::Stdout), None);
+
+        let endpoint = client.metrics.grpc_endpoint.clone();
+        client.stop().await.unwrap();
+        assert!(!endpoint.is_connected());
+        sensor.stop().await;
+    }
+
+    #[tokio::test]
+    async fn failover() {
+        // The first endpoint is down when the client starts
+        let addr = unused_addr();
+        let mut second = MockSensor::start(Behavior {
+            close_after: Some(1),
+            ..Default::default()
+        })
+        .await;
+        let first_url = format!("http://{addr}");
+        let yaml = format!(
+            "grpc:\n  url: ['{first_url}', '{}']\n  backoff:\n    initial: 10\n    max: 20",
+            second.url()
+        );
+        let client = TestClient::start(FactConfig::try_from(yaml.as_str()).unwrap().grpc);
+        let endpoint = client.metrics.grpc_endpoint.clone();
+
+        // The next endpoint is tried right away, without backing off
+        second.wait_streams(1).await;
+        assert!(endpoint.is_active(&second.url()));
+        assert!(!endpoint.is_active(&first_url));
+
+        // A failed stream moves on to the next endpoint
+        let mut first = MockSensor::start_at(addr, Behavior::default()).await;
+        let path = client.send("file");
+        assert_eq!(path_of(&second.next().await), path);
+        first.wait_streams(1).await;
+        assert!(endpoint.is_active(&first_url));
+        assert!(!endpoint.is_active(&second.url()));
+        let path = client.send("other");
+        assert_eq!(path_of(&first.next().await), path);
+
+        client.stop().await.unwrap();
+        assert!(!endpoint.is_active(&first_url));
+        first.stop().await;
+        second.stop().await;
+    }
+
+    #[tokio::test]
+    async fn writes_not_sent() {
+        let mut sensor = MockSensor::start(Behavior::default()).await;
+        let client = TestClient::start(grpc_config(&sensor.url()));
+        sensor.wait_streams(1).await;
+
+        // The Sensor API has no message for writes or directories, they
+        // are skipped without ending the stream
+        for type_ in [
+            file_activity_type_t::FILE_ACTIVITY_WRITE,
+            file_activity_type_t::DIR_ACTIVITY_CREATION,
+        ] {
+            let event = event_t {
+                type_,
+                filename: string_to_c_char_array::<{ PATH_MAX as usize }>("/etc/passwd"),
+                ..Default::default()
+            };
+            let event = Event::try_from(&event).unwrap();
+            client.events.send(Arc::new(event)).unwrap();
+        }
+        let path = client.send("file");
+        assert_eq!(path_of(&sensor.next().await), path);
+
+        client.stop().await.unwrap();
+        sensor.stop().await;
+    }
+
+    #[tokio::test]
+    async fn identity_metadata() {
+        let mut sensor = MockSensor::start(Behavior::default()).await;
+        let yaml = format!(
+            "grpc:\n  url: {}\n  cluster_id: production-east",
+            sensor.url()
+        );
+        let client = TestClient::start(FactConfig::try_from(yaml.as_str()).unwrap().grpc);
+
+        sensor.wait_streams(1).await;
+        let metadata = sensor.metadata();
+        let user_agent = metadata.get("user-agent").unwrap().to_str().unwrap();
+        assert!(
+            user_agent.starts_with(&format!("fact/{FACT_VERSION}")),
+            "{user_agent}"
+        );
+        assert_eq!(
+            metadata.get("x-fact-hostname").unwrap(),
+            host_info::get_hostname()
+        );
+        assert_eq!(metadata.get("x-fact-cluster").unwrap(), "production-east");
+
+        // Without a cluster ID, only the hostname is sent
+        client.config.send_replace(grpc_config(&sensor.url()));
+        sensor.wait_streams(2).await;
+        let metadata = sensor.metadata();
+        assert!(metadata.get("x-fact-hostname").is_some());
+        assert!(metadata.get("x-fact-cluster").is_none());
+
+        client.stop().await.unwrap();
+        sensor.stop().await;
+    }
+
+    #[test]
+    fn identity_invalid_hostname() {
+        let metadata = identity("höst", "", Some("cluster"));
+        assert!(metadata.get("x-fact-hostname").is_none());
+        assert!(metadata.get("x-fact-node-id").is_none());
+        assert_eq!(metadata.get("x-fact-cluster").unwrap(), "cluster");
+    }
+
+    #[test]
+    fn identity_node_id() {
+        let node_id = "5b0c6c0e-1d5e-4d39-9d6b-4e4c5e0f6a51";
+        let metadata = identity("host", node_id, None);
+        assert_eq!(metadata.get("x-fact-hostname").unwrap(), "host");
+        assert_eq!(metadata.get("x-fact-node-id").unwrap(), node_id);
+        assert!(metadata.get("x-fact-cluster").is_none());
+    }
+
+    #[tokio::test]
+    async fn compression() {
+        let mut sensor = MockSensor::start(Behavior::default()).await;
+        let client = TestClient::start(grpc_config(&sensor.url()));
+        sensor.wait_streams(1).await;
+        assert!(sensor.metadata().get("grpc-encoding").is_none());
+
+        // Changing the compression reopens the stream with it
+        for (streams, compression) in [(2, "gzip"), (3, "zstd")] {
+            let yaml = format!(
+                "grpc:\n  url: {}\n  compression: {compression}",
+                sensor.url()
+            );
+            client
+                .config
+                .send_replace(FactConfig::try_from(yaml.as_str()).unwrap().grpc);
+            sensor.wait_streams(streams).await;
+            assert_eq!(sensor.metadata().get("grpc-encoding").unwrap(), compression);
+
+            let path = client.send(compression);
+            assert_eq!(path_of(&sensor.next().await), path);
+        }
+
+        client.stop().await.unwrap();
+        sensor.stop().await;
+    }
+
+    fn token_config(url: &str, token_file: &Path) -> GrpcConfig {
+        let yaml = format!(
+            "grpc:\n  url: {url}\n  token_file: {}\n  backoff:\n    initial: 0.01\n    max: 0.05\n    jitter: false",
+            token_file.display()
+        );
+        FactConfig::try_from(yaml.as_str()).unwrap().grpc
+    }
+
+    #[tokio::test]
+    async fn bearer_token() {
+        let mut sensor = MockSensor::start(Behavior {
+            close_after: Some(1),
+            ..Default::default()
+        })
+        .await;
+        let dir = TempDir::new().unwrap();
+        let token_file = dir.path().join("token");
+        std::fs::write(&token_file, "first\n").unwrap();
+        let client = TestClient::start(token_config(&sensor.url(), &token_file));
+
+        sensor.wait_streams(1).await;
+        assert_eq!(
+            sensor.metadata().get("authorization").unwrap(),
+            "Bearer first"
+        );
+        // Identity metadata is still sent along with the token
+        assert!(sensor.metadata().get("x-fact-hostname").is_some());
+
+        // The rotated token is used once the stream is reopened
+        std::fs::write(&token_file, "second").unwrap();
+        client.send("file");
+        sensor.next().await;
+        sensor.wait_streams(2).await;
+        assert_eq!(
+            sensor.metadata().get("authorization").unwrap(),
+            "Bearer second"
+        );
+
+        client.stop().await.unwrap();
+        sensor.stop().await;
+    }
+
+    #[tokio::test]
+    async fn bearer_token_missing() {
+        // Connecting is retried until the token can be read
+        let mut sensor = MockSensor::start(Behavior::default()).await;
+        let dir = TempDir::new().unwrap();
+        let token_file = dir.path().join("token");
+        let client = TestClient::start(token_config(&sensor.url(), &token_file));
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert!(sensor.metadata().is_empty());
+
+        std::fs::write(&token_file, "s3cr3t").unwrap();
+        sensor.wait_streams(1).await;
+        assert_eq!(
+            sensor.metadata().get("authorization").unwrap(),
+            "Bearer s3cr3t"
+        );
+
+        client.stop().await.unwrap();
+        sensor.stop().await;
+    }
+
+    #[tokio::test]
+    async fn read_token_errors() {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("token");
+        let source = Source::Path(path.clone());
+
+        let err = read_token(&source).await.unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            format!("Failed to read token from {}", path.display())
+        );
+
+        std::fs::write(&path, " \n").unwrap();
+        let err = read_token(&source).await.unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            format!("Token file {} is empty", path.display())
+        );
+
+        std::fs::write(&path, "s3cr3t\u{7f}").unwrap();
+        let err = read_token(&source).await.unwrap_err();
+        assert!(!format!("{err:?}").contains("s3cr3t"), "{err:?}");
+        std::fs::write(&path, b"s3cr3t\xff").unwrap();
+        let err = read_token(&source).await.unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            format!("Token in {} is not valid UTF-8", path.display())
+        );
+
+        // The token is left out of debug output
+        std::fs::write(&path, "s3cr3t\n").unwrap();
+        let token = read_token(&source).await.unwrap();
+        assert_eq!(token, "Bearer s3cr3t");
+        assert!(!format!("{token:?}").contains("s3cr3t"));
+    }
+
+    #[tokio::test]
+    async fn reconnect_on_stream_error() {
+        let mut sensor = MockSensor::start(Behavior {
+            close_after: Some(1),
+            ..Default::default()
+        })
+        .await;
+        let client = TestClient::start(grpc_config(&sensor.url()));
+
+        sensor.wait_streams(1).await;
+        let first = client.send("first");
+        assert_eq!(path_of(&sensor.next().await), first);
+
+        // The stream is closed after the first message, the client
+        // opens a new one.
+        sensor.wait_streams(2).await;
+        let second = client.send("second");
+        assert_eq!(path_of(&sensor.next().await), second);
+        // Events left behind by the closed stream, if any, are counted
+        // for it
+        let grpc = &client.metrics.grpc;
+        assert_eq!(
+            grpc.dropped_count(),
+            grpc.dropped_reason_count(DropReason::StreamClosed)
+        );
+
+        client.stop().await.unwrap();
+        sensor.stop().await;
+    }
+
+    #[tokio::test]
+    async fn reload_url() {
+        let mut old = MockSensor::start(Behavior::default()).await;
+        let mut new = MockSensor::start(Behavior::default()).await;
+        let client = TestClient::start(grpc_config(&old.url()));
+
+        old.wait_streams(1).await;
+        let path = client.send("old");
+        assert_eq!(path_of(&old.next().await), path);
+
+        client.config.send_replace(grpc_config(&new.url()));
+        new.wait_streams(1).await;
+        let path = client.send("new");
+        assert_eq!(path_of(&new.next().await), path);
+        old.assert_idle(Duration::from_millis(100)).await;
+
+        client.stop().await.unwrap();
+        old.stop().await;
+        new.stop().await;
+    }
+
+    #[tokio::test]
+    async fn shutdown() {
+        // A slow sensor does not hold back stopping the client
+        let mut sensor = MockSensor::start(Behavior {
+            delay: Duration::from_secs(60),
+            ..Default::default()
+        })
+        .await;
+        let client = TestClient::start(grpc_config(&sensor.url()));
+        sensor.wait_streams(1).await;
+        client.send("file");
+        client.stop().await.unwrap();
+        drop(sensor);
+
+        // Neither does retrying to connect
+        let client = TestClient::start(grpc_config(&format!("http://{}", unused_addr())));
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        client.stop().await.unwrap();
+
+        // Without a URL the client idles until stopped
+        let client = TestClient::start(GrpcConfig::default());
+        client.stop().await.unwrap();
+    }
 }
@@ -0,0 +1,222 @@
+//! A fake Sensor for testing the gRPC output.
+//!
+//! The server side of `FileActivityService` is implemented with tonic
+//! on a random local port. Received messages are recorded for tests to
+//! inspect, and the server can be scripted to misbehave in the ways a
+//! real Sensor might: refusing connections, closing streams early or
+//! being slow to answer.
+
+use std::{
+    io,
+    net::SocketAddr,
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicUsize, Ordering},
+    },
+    time::Duration,
+};
+
+use fact_api::{
+    Empty, FileActivity,
+    file_activity_service_server::{FileActivityService, FileActivityServiceServer},
+};
+use tokio::{
+    net::TcpListener,
+    sync::{mpsc, oneshot, watch},
+    task::JoinHandle,
+    time::{sleep, timeout},
+};
+use tokio_stream::wrappers::ReceiverStream;
+use tonic::{
+    Request, Response, Status, Streaming, codec::CompressionEncoding, metadata::MetadataMap,
+    transport::Server,
+};
+
+/// How long tests wait on the server before giving up.
+const WAIT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Get a local address nothing is listening on.
+pub fn unused_addr() -> SocketAddr {
+    std::net::TcpListener::bind("127.0.0.1:0")
+        .and_then(|l| l.local_addr())
+        .expect("Failed to get a free port")
+}
+
+/// Scripted misbehavior of the server.
+#[derive(Debug, Default, Clone)]
+pub struct Behavior {
+    /// Number of connections closed right after being accepted.
+    pub refuse: usize,
+    /// Close streams with an error after receiving this many messages.
+    pub close_after: Option<usize>,
+    /// Wait before reading from a new stream.
+    pub delay: Duration,
+}
+
+pub struct MockSensor {
+    addr: SocketAddr,
+    received: mpsc::UnboundedReceiver<FileActivity>,
+    streams: watch::Receiver<usize>,
+    metadata: Arc<Mutex<MetadataMap>>,
+    refused: Arc<AtomicUsize>,
+    shutdown: Option<oneshot::Sender<()>>,
+    accept: JoinHandle<()>,
+    server: JoinHandle<()>,
+}
+
+impl MockSensor {
+    pub async fn start(behavior: Behavior) -> Self {
+        MockSensor::start_at("127.0.0.1:0".parse().unwrap(), behavior).await
+    }
+
+    /// Start the server on a known address, for tests where clients
+    /// are started before the server.
+    pub async fn start_at(addr: SocketAddr, behavior: Behavior) -> Self {
+        let listener = TcpListener::bind(addr)
+            .await
+            .expect("Failed to bind mock sensor");
+        let addr = listener.local_addr().unwrap();
+
+        // Connections are accepted here instead of by tonic, so they
+        // can be refused before any gRPC happens on them.
+        let refused = Arc::new(AtomicUsize::new(0));
+        let (conn_tx, conn_rx) = mpsc::channel(16);
+        let accept = {
+            let refused = refused.clone();
+            let refuse = behavior.refuse;
+            tokio::spawn(async move {
+                while let Ok((stream, _)) = listener.accept().await {
+                    if refused.load(Ordering::Relaxed) < refuse {
+                        refused.fetch_add(1, Ordering::Relaxed);
+                        drop(stream);
+                        continue;
+                    }
+                    if conn_tx.send(Ok::<_, io::Error>(stream)).await.is_err() {
+                        break;
+                    }
+                }
+            })
+        };
+
+        let (received_tx, received) = mpsc::unbounded_channel();
+        let (streams_tx, streams) = watch::channel(0);
+        let metadata = Arc::new(Mutex::new(MetadataMap::new()));
+        let service = Service {
+            behavior,
+            received: received_tx,
+            streams: streams_tx,
+            metadata: metadata.clone(),
+        };
+        let (shutdown, shutdown_rx) = oneshot::channel::<()>();
+        let server = tokio::spawn(async move {
+            Server::builder()
+                .add_service(
+                    FileActivityServiceServer::new(service)
+                        .accept_compressed(CompressionEncoding::Gzip)
+                        .accept_compressed(CompressionEncoding::Zstd),
+                )
+                .serve_with_incoming_shutdown(ReceiverStream::new(conn_rx), async {
+                    let _ = shutdown_rx.await;
+                })
+                .await
+                .expect("Mock sensor failed");
+        });
+
+        MockSensor {
+            addr,
+            received,
+            streams,
+            metadata,
+            refused,
+            shutdown: Some(shutdown),
+            accept,
+            server,
+        }
+    }
+
+    pub fn url(&self) -> String {
+        format!("http://{}", self.addr)
+    }
+
+    /// Number of connections refused so far.
+    pub fn refused(&self) -> usize {
+        self.refused.load(Ordering::Relaxed)
+    }
+
+    /// Wait for the next message received on any stream.
+    pub async fn next(&mut self) -> FileActivity {
+        timeout(WAIT_TIMEOUT, self.received.recv())
+            .await
+            .expect("Timed out waiting for a message")
+            .expect("Mock sensor stopped")
+    }
+
+    /// Check no message was received in `d`.
+    pub async fn assert_idle(&mut self, d: Duration) {
+        if let Ok(Some(msg)) = timeout(d, self.received.recv()).await {
+            panic!("Unexpected message: {msg:?}");
+        }
+    }
+
+    /// Wait until `n` streams have been opened in total.
+    pub async fn wait_streams(&mut self, n: usize) {
+        timeout(WAIT_TIMEOUT, self.streams.wait_for(|s| *s >= n))
+            .await
+            .expect("Timed out waiting for streams")
+            .expect("Mock sensor stopped");
+    }
+
+    /// Metadata the last stream was opened with.
+    pub fn metadata(&self) -> MetadataMap {
+        self.metadata.lock().unwrap().clone()
+    }
+
+    /// Gracefully stop the server, waiting for streams to finish.
+    pub async fn stop(mut self) {
+        if let Some(shutdown) = self.shutdown.take() {
+            let _ = shutdown.send(());
+        }
+        self.accept.abort();
+        timeout(WAIT_TIMEOUT, &mut self.server)
+            .await
+            .expect("Timed out stopping the mock sensor")
+            .expect("Mock sensor panicked");
+    }
+}
+
+impl Drop for MockSensor {
+    fn drop(&mut self) {
+        self.accept.abort();
+        self.server.abort();
+    }
+}
+
+struct Service {
+    behavior: Behavior,
+    received: mpsc::UnboundedSender<FileActivity>,
+    streams: watch::Sender<usize>,
+    metadata: Arc<Mutex<MetadataMap>>,
+}
+
+#[tonic::async_trait]
+impl FileActivityService for Service {
+    async fn communicate(
+        &self,
+        request: Request<Streaming<FileActivity>>,
+    ) -> Result<Response<Empty>, Status> {
+        *self.metadata.lock().unwrap() = request.metadata().clone();
+        self.streams.send_modify(|n| *n += 1);
+        sleep(self.behavior.delay).await;
+
+        let mut stream = request.into_inner();
+        let mut count = 0;
+        while let Some(msg) = stream.message().await? {
+            let _ = self.received.send(msg);
+            count += 1;
+            if self.behavior.close_after.is_some_and(|m| count >= m) {
+                return Err(Status::unavailable("closing stream"));
+            }
+        }
+        Ok(Response::new(Empty {}))
+    }
+}
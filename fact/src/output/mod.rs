@@ -7,18 +7,49 @@ use tokio::{
 };
 
 use crate::{
-    config::{GrpcConfig, OTelConfig},
+    config::{GrpcConfig, OTelConfig, OverflowPolicy, StdoutFormat, StdoutMode},
     event::Event,
     flatten_task_result, join_all_tasks,
-    metrics::OutputMetrics,
+    metrics::{DropReason, OutputMetrics},
+    pipeline::EventSink,
 };
 
+mod dispatch;
+mod falco;
 mod grpc;
+#[cfg(test)]
+mod mock_sensor;
 #[cfg(feature = "otel")]
 mod otel;
+mod proxy;
+mod resolver;
+mod sink;
 mod stdout;
 
-type EventReceiver = broadcast::Receiver<Arc<Event>>;
+use dispatch::{Dispatcher, EventReceiver};
+
+/// Destinations of the events leaving the pipeline.
+pub struct Outputs {
+    pub grpc: watch::Receiver<GrpcConfig>,
+    pub otel: watch::Receiver<OTelConfig>,
+    /// When to write events to stdout.
+    pub stdout: StdoutMode,
+    /// How events are written to stdout.
+    pub stdout_format: StdoutFormat,
+    /// What to do with events when an output can't keep up.
+    pub overflow_policy: OverflowPolicy,
+    /// Channel events are broadcast on, receivers subscribed before
+    /// starting get all events.
+    pub events: broadcast::Sender<Arc<Event>>,
+    /// Channels the events are spread over by container for the
+    /// outputs, 1 broadcasts them on `events` unless the overflow
+    /// policy blocks.
+    pub shards: usize,
+    /// Events held by each shard.
+    pub shard_depth: usize,
+    /// Sinks registered by embedders of fact.
+    pub sinks: Vec<Box<dyn EventSink>>,
+}
 
 /// Starts all the output tasks.
 ///
@@ -28,66 +59,110 @@ pub fn start(
     task_set: &mut JoinSet<anyhow::Result<()>>,
     mut rx: mpsc::Receiver<Event>,
     metrics: OutputMetrics,
-    grpc_config: watch::Receiver<GrpcConfig>,
-    #[allow(unused)] otel_config: watch::Receiver<OTelConfig>,
-    stdout_enabled: bool,
+    outputs: Outputs,
 ) {
-    let (broad_tx, _) = broadcast::channel(100);
+    let Outputs {
+        grpc: grpc_config,
+        #[allow(unused)]
+            otel: otel_config,
+        stdout: stdout_mode,
+        stdout_format,
+        overflow_policy,
+        events,
+        shards,
+        shard_depth,
+        sinks,
+    } = outputs;
+    let dispatcher = Dispatcher::new(events, shards, shard_depth, overflow_policy);
+    // Receivers held outside of fact are not waited for on shutdown
+    let external_receivers = dispatcher.receiver_count();
     let (subs_req, mut subs_rx) = mpsc::channel(10);
     let (running, _) = watch::channel(true);
     let mut handles = JoinSet::new();
 
-    let grpc_client = grpc::Client::new(
-        subs_req.clone(),
-        running.subscribe(),
-        metrics.grpc.clone(),
-        grpc_config,
-    );
+    let grpc_client =
+        grpc::Client::new(subs_req.clone(), running.subscribe(), &metrics, grpc_config);
     #[allow(unused_mut)]
-    let mut non_stdout_enabled = grpc_client.is_enabled();
+    let mut non_stdout_enabled = grpc_client.is_enabled() || !sinks.is_empty();
     grpc_client.start(&mut handles);
 
+    for sink in sinks {
+        sink::Client::new(
+            sink,
+            dispatcher.subscribe(),
+            running.subscribe(),
+            metrics.sinks.clone(),
+        )
+        .start(&mut handles);
+    }
+
     #[cfg(feature = "otel")]
     {
         let otel_client = otel::Client::new(
             subs_req.clone(),
             running.subscribe(),
             metrics.otel.clone(),
+            metrics.last_success.clone(),
             otel_config,
         );
         non_stdout_enabled = non_stdout_enabled || otel_client.is_enabled();
         otel_client.start(&mut handles);
     }
 
-    // JSON client will only start if explicitly enabled or no other
-    // output is active at startup
-    if stdout_enabled || !non_stdout_enabled {
+    // JSON client will only start if explicitly enabled or, in auto
+    // mode, no other output is active at startup
+    let stdout_enabled = match stdout_mode {
+        StdoutMode::On => true,
+        StdoutMode::Off => false,
+        StdoutMode::Auto => !non_stdout_enabled,
+    };
+    if !stdout_enabled && !non_stdout_enabled {
+        warn!("stdout output is off and no other output is enabled, events will be dropped");
+    }
+    if stdout_enabled {
         stdout::Client::new(
-            broad_tx.subscribe(),
+            dispatcher.subscribe(),
             running.subscribe(),
+            stdout_format,
             metrics.stdout.clone(),
+            metrics.last_success.clone(),
         )
         .start(&mut handles);
     }
 
     task_set.spawn(async move {
         debug!("Starting output component...");
+        let mut blocked = false;
         let res = loop {
+            // The next event may go to any shard, all of them must have
+            // room for it
+            let wait = overflow_policy == OverflowPolicy::Block && dispatcher.any_full();
             tokio::select! {
-                event = rx.recv() => {
+                // Waiting leaves events in the channels behind us and,
+                // once those are full, in the ringbuffer, where the
+                // kernel counts what can't fit
+                event = rx.recv(), if !wait => {
                     let Some(event) = event else {
                         // Channel has been closed and no more messages
                         // are present.
                         break Ok(());
                     };
 
-                    if let Err(e) = broad_tx.send(Arc::new(event)) {
+                    if blocked {
+                        metrics.overflow.blocked();
+                        blocked = false;
+                    } else if dispatcher.is_full(&event) {
+                        metrics.overflow.dropped(DropReason::Lagged);
+                    }
+                    metrics.containers.record(&event);
+                    if let Err(e) = dispatcher.send(Arc::new(event)) {
                         warn!("Failed to forward output event: {e}");
                     }
                 }
+                _ = dispatcher.received(), if wait => blocked = true,
                 req = subs_rx.recv() => {
                     let Some(req) = req else { break Ok(()); };
-                    let rx = broad_tx.subscribe();
+                    let rx = dispatcher.subscribe();
                     if let Err(e) = req.send(rx) {
                         break Err(anyhow::anyhow!("Failed to subscribe worker: {e:?}"));
                     }
@@ -105,9 +180,11 @@ pub fn start(
         if res.is_ok() {
             // Wait for outputs to empty their channels before exiting
             // ourselves.
-            let receiver_count = broad_tx.receiver_count();
+            let receiver_count = dispatcher
+                .receiver_count()
+                .saturating_sub(external_receivers);
             drop(subs_rx);
-            drop(broad_tx);
+            drop(dispatcher);
 
             for _ in 0..receiver_count {
                 let Some(task_res) = handles.join_next().await else {
@@ -124,3 +201,177 @@ pub fn start(
         }
     });
 }
+
+#[cfg(test)]
+mod tests {
+    use std::{sync::Mutex, thread, time::Duration};
+
+    use tokio::task;
+
+    use super::*;
+    use crate::metrics::Metrics;
+
+    struct Counter(Arc<Mutex<usize>>);
+
+    impl EventSink for Counter {
+        fn name(&self) -> &str {
+            "counter"
+        }
+
+        fn handle(&mut self, _: &Event) -> anyhow::Result<()> {
+            *self.0.lock().unwrap() += 1;
+            Ok(())
+        }
+    }
+
+    /// Sink stuck on its first event for a while, long enough for the
+    /// broadcast channel to fill up.
+    struct Stalled(Arc<Mutex<usize>>);
+
+    impl EventSink for Stalled {
+        fn name(&self) -> &str {
+            "stalled"
+        }
+
+        fn handle(&mut self, _: &Event) -> anyhow::Result<()> {
+            let mut count = self.0.lock().unwrap();
+            if *count == 0 {
+                // Hand the other tasks of this thread to another one, so
+                // only the sink is stuck
+                task::block_in_place(|| thread::sleep(Duration::from_millis(200)));
+            }
+            *count += 1;
+            Ok(())
+        }
+    }
+
+    /// Run the output component over `n` events.
+    async fn output(n: usize, stdout: StdoutMode, sinks: Vec<Box<dyn EventSink>>) -> OutputMetrics {
+        output_with(n, stdout, OverflowPolicy::Drop, sinks).await
+    }
+
+    async fn output_with(
+        n: usize,
+        stdout: StdoutMode,
+        overflow_policy: OverflowPolicy,
+        sinks: Vec<Box<dyn EventSink>>,
+    ) -> OutputMetrics {
+        let metrics = Metrics::new().output;
+        let (_grpc_tx, grpc) = watch::channel(GrpcConfig::default());
+        let (_otel_tx, otel) = watch::channel(OTelConfig::default());
+        let (events, _) = broadcast::channel(crate::EVENT_CHANNEL_CAPACITY);
+        let (tx, rx) = mpsc::channel(n);
+        let mut task_set = JoinSet::new();
+        start(
+            &mut task_set,
+            rx,
+            metrics.clone(),
+            Outputs {
+                grpc,
+                otel,
+                stdout,
+                stdout_format: StdoutFormat::Fact,
+                overflow_policy,
+                events,
+                shards: 1,
+                shard_depth: crate::EVENT_CHANNEL_CAPACITY,
+                sinks,
+            },
+        );
+
+        let dir = tempfile::tempdir().unwrap();
+        let event = Event::inventory(dir.path(), &dir.path().metadata().unwrap());
+        for _ in 0..n {
+            tx.send(event.clone()).await.unwrap();
+        }
+        drop(tx);
+        join_all_tasks(task_set).await.unwrap();
+        metrics
+    }
+
+    fn counter() -> (Arc<Mutex<usize>>, Vec<Box<dyn EventSink>>) {
+        let count = Arc::new(Mutex::new(0));
+        (count.clone(), vec![Box::new(Counter(count))])
+    }
+
+    #[tokio::test]
+    async fn stdout_auto() {
+        // Without other outputs, events go to stdout
+        let metrics = output(3, StdoutMode::Auto, Vec::new()).await;
+        assert_eq!(metrics.stdout.added_count(), 3);
+
+        let (count, sinks) = counter();
+        let metrics = output(3, StdoutMode::Auto, sinks).await;
+        assert_eq!(*count.lock().unwrap(), 3);
+        assert_eq!(metrics.sinks.added_count(), 3);
+        assert_eq!(metrics.stdout.added_count(), 0);
+    }
+
+    #[tokio::test]
+    async fn stdout_on() {
+        let metrics = output(3, StdoutMode::On, Vec::new()).await;
+        assert_eq!(metrics.stdout.added_count(), 3);
+
+        let (count, sinks) = counter();
+        let metrics = output(3, StdoutMode::On, sinks).await;
+        assert_eq!(*count.lock().unwrap(), 3);
+        assert_eq!(metrics.stdout.added_count(), 3);
+    }
+
+    #[tokio::test]
+    async fn stdout_off() {
+        // Events go nowhere without other outputs
+        let metrics = output(3, StdoutMode::Off, Vec::new()).await;
+        assert_eq!(metrics.stdout.added_count(), 0);
+
+        let (count, sinks) = counter();
+        let metrics = output(3, StdoutMode::Off, sinks).await;
+        assert_eq!(*count.lock().unwrap(), 3);
+        assert_eq!(metrics.stdout.added_count(), 0);
+    }
+
+    fn stalled() -> (Arc<Mutex<usize>>, Vec<Box<dyn EventSink>>) {
+        let count = Arc::new(Mutex::new(0));
+        (count.clone(), vec![Box::new(Stalled(count))])
+    }
+
+    // Blocking the sink requires a multi-threaded runtime
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn overflow_drop() {
+        let n = 1000;
+        let (count, sinks) = stalled();
+        let metrics = output_with(n, StdoutMode::Off, OverflowPolicy::Drop, sinks).await;
+
+        // The sink misses the events sent while it was stuck
+        let count = *count.lock().unwrap() as u64;
+        let dropped = metrics.sinks.dropped_count();
+        assert!(dropped > 0);
+        assert_eq!(count + dropped, n as u64);
+        assert_eq!(
+            metrics.sinks.dropped_reason_count(DropReason::Lagged),
+            dropped
+        );
+        // The sink may catch up on an event while the dispatcher is
+        // sending the next one, which is then counted as dropped too
+        assert!((dropped..=dropped + 1).contains(&metrics.overflow.dropped_count()));
+        assert_eq!(
+            metrics.overflow.dropped_reason_count(DropReason::Lagged),
+            metrics.overflow.dropped_count()
+        );
+        assert_eq!(metrics.overflow.blocked_count(), 0);
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 2)]
+    async fn overflow_block() {
+        let n = 1000;
+        let (count, sinks) = stalled();
+        let metrics = output_with(n, StdoutMode::Off, OverflowPolicy::Block, sinks).await;
+
+        // The dispatcher waits for the sink, nothing is lost
+        assert_eq!(*count.lock().unwrap(), n);
+        assert_eq!(metrics.sinks.added_count(), n as u64);
+        assert_eq!(metrics.sinks.dropped_count(), 0);
+        assert_eq!(metrics.overflow.dropped_count(), 0);
+        assert!(metrics.overflow.blocked_count() > 0);
+    }
+}
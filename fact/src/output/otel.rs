@@ -11,13 +11,18 @@ use tokio::{
     task::JoinSet,
 };
 
-use crate::{config::OTelConfig, metrics::EventCounter, output::EventReceiver};
+use crate::{
+    config::OTelConfig,
+    metrics::{DropReason, EventCounter, LastSuccess, Sink},
+    output::EventReceiver,
+};
 
 pub(super) struct Client {
     subscriber: mpsc::Sender<oneshot::Sender<EventReceiver>>,
     running: watch::Receiver<bool>,
     config: watch::Receiver<OTelConfig>,
     metrics: EventCounter,
+    last_success: LastSuccess,
 }
 
 impl Client {
@@ -25,6 +30,7 @@ impl Client {
         subscriber: mpsc::Sender<oneshot::Sender<EventReceiver>>,
         running: watch::Receiver<bool>,
         metrics: EventCounter,
+        last_success: LastSuccess,
         config: watch::Receiver<OTelConfig>,
     ) -> Self {
         Client {
@@ -32,6 +38,7 @@ impl Client {
             running,
             config,
             metrics,
+            last_success,
         }
     }
 
@@ -98,6 +105,7 @@ impl Client {
                                 }
                             }
                             logger.emit(record);
+                            self.last_success.touch(Sink::Otel);
                         }
                         Err(RecvError::Closed) => {
                             info!("Channel closed, stopping oTel output...");
@@ -105,7 +113,7 @@ impl Client {
                         }
                         Err(RecvError::Lagged(n)) => {
                             warn!("oTel stream lagged, dropped {n} events");
-                            self.metrics.dropped_n(n);
+                            self.metrics.dropped_n(DropReason::Lagged, n);
                         }
                     }
                 }
@@ -129,3 +137,54 @@ impl Client {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use tokio::{sync::broadcast, time::timeout};
+
+    use super::*;
+    use crate::{config::FactConfig, event::Event, metrics::Metrics};
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn last_success() {
+        // Records are batched, nothing needs to listen on the endpoint
+        // for them to be emitted
+        let config = FactConfig::try_from("otel:\n  endpoint: http://127.0.0.1:1")
+            .unwrap()
+            .otel;
+        let (events, _) = broadcast::channel(10);
+        let (running, running_rx) = watch::channel(true);
+        let (_config, config_rx) = watch::channel(config);
+        let (subscriber, mut subscriptions) = mpsc::channel(10);
+        let metrics = Metrics::new().output;
+        let mut tasks = JoinSet::new();
+        Client::new(
+            subscriber,
+            running_rx,
+            metrics.otel.clone(),
+            metrics.last_success.clone(),
+            config_rx,
+        )
+        .start(&mut tasks);
+
+        let reply: oneshot::Sender<EventReceiver> = subscriptions.recv().await.unwrap();
+        reply.send(events.subscribe().into()).unwrap();
+        assert_eq!(metrics.last_success.get(Sink::Otel), None);
+
+        let dir = tempfile::tempdir().unwrap();
+        let event = Event::inventory(dir.path(), &dir.path().metadata().unwrap());
+        events.send(Arc::new(event)).unwrap();
+        timeout(Duration::from_secs(10), async {
+            while metrics.last_success.get(Sink::Otel).is_none() {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .expect("Timed out waiting for the event");
+
+        running.send_replace(false);
+        let _ = timeout(Duration::from_secs(30), tasks.join_next()).await;
+    }
+}
@@ -0,0 +1,392 @@
+//! DNS resolution for the gRPC output.
+//!
+//! Resolving the sensor address on every connection attempt means a
+//! slow or unresponsive DNS server stalls reconnects for as long as the
+//! system resolver takes to give up. Instead, addresses are resolved
+//! with a timeout and cached, connections use the cached addresses and
+//! expired entries are refreshed in the background while the stale
+//! addresses keep being served.
+
+use std::{
+    collections::HashMap,
+    future::Future,
+    io,
+    net::SocketAddr,
+    pin::Pin,
+    sync::{
+        Arc, Mutex,
+        atomic::{AtomicUsize, Ordering},
+    },
+    task::{Context, Poll},
+    time::{Duration, Instant},
+};
+
+use hyper_util::client::legacy::connect::dns::Name;
+use log::{debug, warn};
+use tokio::time::timeout;
+use tower_service::Service;
+
+use crate::metrics::EventCounter;
+
+/// Maximum time a single lookup is allowed to take.
+pub const RESOLVE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Time resolved addresses are used before being refreshed.
+pub const RESOLVE_TTL: Duration = Duration::from_secs(60);
+
+pub type LookupFuture = Pin<Box<dyn Future<Output = io::Result<Vec<SocketAddr>>> + Send>>;
+
+/// A source of addresses for a host name.
+///
+/// The port of the returned addresses is ignored, the one from the URL
+/// being connected to is used instead.
+pub trait Lookup: Send + Sync {
+    fn lookup(&self, host: &str) -> LookupFuture;
+}
+
+/// Lookup using the system resolver.
+pub struct SystemLookup;
+
+impl Lookup for SystemLookup {
+    fn lookup(&self, host: &str) -> LookupFuture {
+        let host = host.to_string();
+        Box::pin(async move { Ok(tokio::net::lookup_host((host, 0)).await?.collect()) })
+    }
+}
+
+struct Entry {
+    addrs: Vec<SocketAddr>,
+    resolved_at: Instant,
+    refreshing: bool,
+}
+
+struct Inner {
+    lookup: Box<dyn Lookup>,
+    timeout: Duration,
+    ttl: Duration,
+    cache: Mutex<HashMap<String, Entry>>,
+    next: AtomicUsize,
+    metrics: EventCounter,
+}
+
+/// A resolver caching the addresses of the hosts it is asked for.
+///
+/// Used as the resolver of the connectors for the gRPC channel, so the
+/// channel itself never performs DNS resolution. When a host resolves
+/// to multiple addresses, the order they are returned in is rotated on
+/// each call, spreading connections across them.
+#[derive(Clone)]
+pub struct CachingResolver(Arc<Inner>);
+
+impl CachingResolver {
+    pub fn new(
+        lookup: impl Lookup + 'static,
+        timeout: Duration,
+        ttl: Duration,
+        metrics: EventCounter,
+    ) -> Self {
+        CachingResolver(Arc::new(Inner {
+            lookup: Box::new(lookup),
+            timeout,
+            ttl,
+            cache: Mutex::new(HashMap::new()),
+            next: AtomicUsize::new(0),
+            metrics,
+        }))
+    }
+
+    /// Resolve `host`, only waiting for a lookup if no addresses are
+    /// cached for it.
+    pub async fn resolve(&self, host: &str) -> io::Result<Vec<SocketAddr>> {
+        let cached = {
+            let mut cache = self.0.cache.lock().unwrap();
+            cache.get_mut(host).map(|entry| {
+                if !entry.refreshing && entry.resolved_at.elapsed() >= self.0.ttl {
+                    entry.refreshing = true;
+                    self.refresh(host.to_string());
+                }
+                entry.addrs.clone()
+            })
+        };
+        if let Some(addrs) = cached {
+            return Ok(self.rotate(addrs));
+        }
+
+        let addrs = self.lookup(host).await?;
+        self.store(host, addrs.clone());
+        Ok(self.rotate(addrs))
+    }
+
+    fn refresh(&self, host: String) {
+        let resolver = self.clone();
+        tokio::spawn(async move {
+            match resolver.lookup(&host).await {
+                Ok(addrs) => resolver.store(&host, addrs),
+                Err(e) => {
+                    warn!("Keeping cached addresses for {host}: {e}");
+                    if let Some(entry) = resolver.0.cache.lock().unwrap().get_mut(&host) {
+                        entry.refreshing = false;
+                    }
+                }
+            }
+        });
+    }
+
+    async fn lookup(&self, host: &str) -> io::Result<Vec<SocketAddr>> {
+        let res = match timeout(self.0.timeout, self.0.lookup.lookup(host)).await {
+            Ok(Ok(addrs)) if addrs.is_empty() => Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("no addresses found for {host}"),
+            )),
+            Ok(res) => res,
+            Err(_) => {
+                self.0.metrics.timed_out();
+                Err(io::Error::new(
+                    io::ErrorKind::TimedOut,
+                    format!("resolving {host} timed out after {:?}", self.0.timeout),
+                ))
+            }
+        };
+
+        match &res {
+            Ok(addrs) => {
+                debug!("Resolved {host} to {addrs:?}");
+                self.0.metrics.added();
+            }
+            Err(e) => {
+                warn!("DNS resolution failed for {host}: {e}");
+                if e.kind() != io::ErrorKind::TimedOut {
+                    self.0.metrics.errored();
+                }
+            }
+        }
+        res
+    }
+
+    fn store(&self, host: &str, addrs: Vec<SocketAddr>) {
+        let entry = Entry {
+            addrs,
+            resolved_at: Instant::now(),
+            refreshing: false,
+        };
+        self.0.cache.lock().unwrap().insert(host.to_string(), entry);
+    }
+
+    fn rotate(&self, mut addrs: Vec<SocketAddr>) -> Vec<SocketAddr> {
+        let len = addrs.len();
+        if len > 1 {
+            let next = self.0.next.fetch_add(1, Ordering::Relaxed);
+            addrs.rotate_left(next % len);
+        }
+        addrs
+    }
+}
+
+impl Service<Name> for CachingResolver {
+    type Response = std::vec::IntoIter<SocketAddr>;
+    type Error = io::Error;
+    type Future = Pin<Box<dyn Future<Output = io::Result<Self::Response>> + Send>>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, name: Name) -> Self::Future {
+        let resolver = self.clone();
+        Box::pin(async move {
+            let addrs = resolver.resolve(name.as_str()).await?;
+            Ok(addrs.into_iter())
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        net::{IpAddr, Ipv4Addr},
+        sync::atomic::AtomicBool,
+    };
+
+    use hyper::Uri;
+    use hyper_util::client::legacy::connect::HttpConnector;
+    use tokio::{net::TcpListener, time::sleep};
+
+    use super::*;
+    use crate::metrics::Metrics;
+
+    #[derive(Clone, Default)]
+    struct MockLookup(Arc<MockState>);
+
+    #[derive(Default)]
+    struct MockState {
+        addrs: Mutex<Vec<SocketAddr>>,
+        delay: Mutex<Duration>,
+        fail: AtomicBool,
+        calls: AtomicUsize,
+    }
+
+    impl MockLookup {
+        fn new(addrs: &[[u8; 4]]) -> Self {
+            let lookup = MockLookup::default();
+            lookup.set_addrs(addrs);
+            lookup
+        }
+
+        fn set_addrs(&self, addrs: &[[u8; 4]]) {
+            *self.0.addrs.lock().unwrap() = addrs
+                .iter()
+                .map(|ip| SocketAddr::new(IpAddr::from(*ip), 0))
+                .collect();
+        }
+
+        fn set_delay(&self, delay: Duration) {
+            *self.0.delay.lock().unwrap() = delay;
+        }
+
+        fn calls(&self) -> usize {
+            self.0.calls.load(Ordering::Relaxed)
+        }
+    }
+
+    impl Lookup for MockLookup {
+        fn lookup(&self, _host: &str) -> LookupFuture {
+            let state = self.0.clone();
+            Box::pin(async move {
+                state.calls.fetch_add(1, Ordering::Relaxed);
+                let delay = *state.delay.lock().unwrap();
+                sleep(delay).await;
+                if state.fail.load(Ordering::Relaxed) {
+                    return Err(io::Error::other("lookup failed"));
+                }
+                Ok(state.addrs.lock().unwrap().clone())
+            })
+        }
+    }
+
+    fn resolver(lookup: &MockLookup, ttl: Duration) -> CachingResolver {
+        let metrics = Metrics::new().output.grpc_dns;
+        CachingResolver::new(lookup.clone(), Duration::from_millis(100), ttl, metrics)
+    }
+
+    fn ips(addrs: &[SocketAddr]) -> Vec<IpAddr> {
+        addrs.iter().map(SocketAddr::ip).collect()
+    }
+
+    #[tokio::test]
+    async fn cached() {
+        let lookup = MockLookup::new(&[[10, 0, 0, 1]]);
+        let resolver = resolver(&lookup, RESOLVE_TTL);
+
+        for _ in 0..3 {
+            let addrs = resolver.resolve("sensor").await.unwrap();
+            assert_eq!(ips(&addrs), [Ipv4Addr::new(10, 0, 0, 1)]);
+        }
+        assert_eq!(lookup.calls(), 1);
+
+        resolver.resolve("other").await.unwrap();
+        assert_eq!(lookup.calls(), 2);
+    }
+
+    #[tokio::test]
+    async fn round_robin() {
+        let lookup = MockLookup::new(&[[10, 0, 0, 1], [10, 0, 0, 2], [10, 0, 0, 3]]);
+        let resolver = resolver(&lookup, RESOLVE_TTL);
+
+        let mut first = Vec::new();
+        for _ in 0..3 {
+            let addrs = resolver.resolve("sensor").await.unwrap();
+            assert_eq!(addrs.len(), 3);
+            first.push(addrs[0].ip());
+        }
+        first.sort();
+        assert_eq!(
+            first,
+            [
+                Ipv4Addr::new(10, 0, 0, 1),
+                Ipv4Addr::new(10, 0, 0, 2),
+                Ipv4Addr::new(10, 0, 0, 3)
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn lookup_timeout() {
+        let lookup = MockLookup::new(&[[10, 0, 0, 1]]);
+        lookup.set_delay(Duration::from_secs(10));
+        let resolver = resolver(&lookup, RESOLVE_TTL);
+
+        let start = Instant::now();
+        let err = resolver.resolve("sensor").await.unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+        assert!(start.elapsed() < Duration::from_secs(1));
+    }
+
+    #[tokio::test]
+    async fn lookup_errors() {
+        let lookup = MockLookup::new(&[]);
+        let resolver = resolver(&lookup, RESOLVE_TTL);
+        let err = resolver.resolve("sensor").await.unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::NotFound);
+
+        lookup.0.fail.store(true, Ordering::Relaxed);
+        assert!(resolver.resolve("sensor").await.is_err());
+
+        // Failures are not cached
+        lookup.0.fail.store(false, Ordering::Relaxed);
+        lookup.set_addrs(&[[10, 0, 0, 1]]);
+        assert!(resolver.resolve("sensor").await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn background_refresh() {
+        let lookup = MockLookup::new(&[[10, 0, 0, 1]]);
+        let resolver = resolver(&lookup, Duration::ZERO);
+        resolver.resolve("sensor").await.unwrap();
+
+        // Expired entries are served while a slow refresh is going on
+        lookup.set_delay(Duration::from_millis(50));
+        lookup.set_addrs(&[[10, 0, 0, 2]]);
+        let start = Instant::now();
+        let addrs = resolver.resolve("sensor").await.unwrap();
+        assert!(start.elapsed() < Duration::from_millis(50));
+        assert_eq!(ips(&addrs), [Ipv4Addr::new(10, 0, 0, 1)]);
+
+        sleep(Duration::from_millis(200)).await;
+        lookup.set_delay(Duration::ZERO);
+        let addrs = resolver.resolve("sensor").await.unwrap();
+        assert_eq!(ips(&addrs), [Ipv4Addr::new(10, 0, 0, 2)]);
+
+        // Failed and timed out refreshes keep the cached addresses
+        lookup.0.fail.store(true, Ordering::Relaxed);
+        for _ in 0..3 {
+            let addrs = resolver.resolve("sensor").await.unwrap();
+            assert_eq!(ips(&addrs), [Ipv4Addr::new(10, 0, 0, 2)]);
+            sleep(Duration::from_millis(10)).await;
+        }
+        lookup.set_delay(Duration::from_secs(10));
+        let addrs = resolver.resolve("sensor").await.unwrap();
+        assert_eq!(ips(&addrs), [Ipv4Addr::new(10, 0, 0, 2)]);
+    }
+
+    #[tokio::test]
+    async fn connector() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let uri: Uri = format!("http://sensor.test:{port}").parse().unwrap();
+
+        let lookup = MockLookup::new(&[[127, 0, 0, 1]]);
+        let mut connector = HttpConnector::new_with_resolver(resolver(&lookup, RESOLVE_TTL));
+        connector
+            .call(uri.clone())
+            .await
+            .expect("Failed to connect to the resolved address");
+
+        // Reconnecting with a slow DNS server is bounded by the timeout
+        let lookup = MockLookup::new(&[[127, 0, 0, 1]]);
+        lookup.set_delay(Duration::from_secs(10));
+        let mut connector = HttpConnector::new_with_resolver(resolver(&lookup, RESOLVE_TTL));
+        let start = Instant::now();
+        assert!(connector.call(uri).await.is_err());
+        assert!(start.elapsed() < Duration::from_secs(1));
+    }
+}
@@ -0,0 +1,76 @@
+use log::{info, warn};
+use tokio::{
+    sync::{broadcast::error::RecvError, watch},
+    task::JoinSet,
+};
+
+use crate::{
+    metrics::{DropReason, EventCounter},
+    output::EventReceiver,
+    pipeline::EventSink,
+};
+
+/// Task driving a sink registered by an embedder.
+pub struct Client {
+    sink: Box<dyn EventSink>,
+    rx: EventReceiver,
+    running: watch::Receiver<bool>,
+    metrics: EventCounter,
+}
+
+impl Client {
+    pub fn new(
+        sink: Box<dyn EventSink>,
+        rx: EventReceiver,
+        running: watch::Receiver<bool>,
+        metrics: EventCounter,
+    ) -> Self {
+        Client {
+            sink,
+            rx,
+            running,
+            metrics,
+        }
+    }
+
+    pub fn start(mut self, task_set: &mut JoinSet<anyhow::Result<()>>) {
+        task_set.spawn(async move {
+            loop {
+                tokio::select! {
+                    event = self.rx.recv() => {
+                        let event = match event {
+                            Ok(event) => event,
+                            Err(RecvError::Closed) => {
+                                info!("Channel closed, stopping {} sink...", self.sink.name());
+                                break;
+                            }
+                            Err(RecvError::Lagged(n)) => {
+                                self.metrics.dropped_n(DropReason::Lagged, n);
+                                warn!("{} sink dropped {n} events", self.sink.name());
+                                continue;
+                            }
+                        };
+                        match self.sink.handle(&event) {
+                            Ok(()) => self.metrics.added(),
+                            Err(e) => {
+                                self.metrics.dropped(DropReason::SerializeError);
+                                warn!("{} sink failed to handle an event: {e:#}", self.sink.name());
+                            }
+                        }
+                    },
+                    _ = self.running.changed() => {
+                        if !*self.running.borrow() {
+                            info!("Stopping {} sink...", self.sink.name());
+                            break;
+                        }
+                    }
+                }
+            }
+
+            if let Err(e) = self.sink.flush() {
+                warn!("{} sink failed to flush: {e:#}", self.sink.name());
+            }
+            Ok(())
+        });
+    }
+}
@@ -4,20 +4,35 @@ use tokio::{
     task::JoinSet,
 };
 
-use crate::{metrics::EventCounter, output::EventReceiver};
+use crate::{
+    config::StdoutFormat,
+    metrics::{DropReason, EventCounter, LastSuccess, Sink},
+    output::{EventReceiver, falco::Alert},
+    trace,
+};
 
 pub struct Client {
     rx: EventReceiver,
     running: watch::Receiver<bool>,
+    format: StdoutFormat,
     metrics: EventCounter,
+    last_success: LastSuccess,
 }
 
 impl Client {
-    pub fn new(rx: EventReceiver, running: watch::Receiver<bool>, metrics: EventCounter) -> Self {
+    pub fn new(
+        rx: EventReceiver,
+        running: watch::Receiver<bool>,
+        format: StdoutFormat,
+        metrics: EventCounter,
+        last_success: LastSuccess,
+    ) -> Self {
         Client {
             rx,
             running,
+            format,
             metrics,
+            last_success,
         }
     }
 
@@ -33,18 +48,26 @@ impl Client {
                                 return Ok(());
                             }
                             Err(RecvError::Lagged(n)) => {
-                                self.metrics.dropped_n(n);
+                                self.metrics.dropped_n(DropReason::Lagged, n);
                                 warn!("Stdout worker dropped {n} events");
                                 continue;
                             }
                         };
-                        match serde_json::to_string(&*event) {
+                        let json = {
+                            let _span = trace::stage!("serialize");
+                            match self.format {
+                                StdoutFormat::Fact => serde_json::to_string(&*event),
+                                StdoutFormat::Falco => serde_json::to_string(&Alert::from(&*event)),
+                            }
+                        };
+                        match json {
                             Ok(event) => {
                                 self.metrics.added();
                                 println!("{event}");
+                                self.last_success.touch(Sink::Stdout);
                             }
                             Err(e) => {
-                                self.metrics.dropped();
+                                self.metrics.dropped(DropReason::SerializeError);
                                 warn!("There was an error serializing an event: {e}")
                             }
                         }
@@ -60,3 +83,46 @@ impl Client {
         });
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::{sync::Arc, time::Duration};
+
+    use tokio::{sync::broadcast, time::timeout};
+
+    use super::*;
+    use crate::{event::Event, metrics::Metrics};
+
+    #[tokio::test]
+    async fn last_success() {
+        let (tx, rx) = broadcast::channel(10);
+        let (running, running_rx) = watch::channel(true);
+        let metrics = Metrics::new().output;
+        let mut tasks = JoinSet::new();
+        Client::new(
+            rx.into(),
+            running_rx,
+            StdoutFormat::Fact,
+            metrics.stdout.clone(),
+            metrics.last_success.clone(),
+        )
+        .start(&mut tasks);
+        assert_eq!(metrics.last_success.get(Sink::Stdout), None);
+
+        let dir = tempfile::tempdir().unwrap();
+        let event = Event::inventory(dir.path(), &dir.path().metadata().unwrap());
+        tx.send(Arc::new(event)).unwrap();
+        timeout(Duration::from_secs(10), async {
+            while metrics.last_success.get(Sink::Stdout).is_none() {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .expect("Timed out waiting for the event");
+        assert!(metrics.last_success.get(Sink::Stdout).unwrap() > 0);
+        assert_eq!(metrics.last_success.get(Sink::Grpc), None);
+
+        running.send_replace(false);
+        tasks.join_next().await.unwrap().unwrap().unwrap();
+    }
+}
@@ -0,0 +1,272 @@
+//! Cooperative CPU budget for background maintenance work.
+//!
+//! Maintenance loops, like the periodic rescans of the host scanner,
+//! can keep a core busy for a long time on big hosts, competing with
+//! the processing of events. Loops get a [`Pacer`] from the shared
+//! [`Budget`] and call it between units of work. The time spent
+//! working since the last call is accounted against the budget and,
+//! once the loops collectively owe enough, the one calling sleeps so
+//! that maintenance uses at most `maintenance.cpu_budget_pct` percent
+//! of a core over time.
+//!
+//! Pacing is cooperative, a single long unit of work is never
+//! interrupted, it only makes the following sleep longer.
+
+use std::{
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use tokio::sync::watch;
+
+use crate::{
+    config::MaintenanceConfig,
+    metrics::{MaintenanceMetrics, MaintenanceTask},
+};
+
+/// Shortest sleep taken, smaller debts are carried over to avoid
+/// waking up constantly.
+const MIN_SLEEP: Duration = Duration::from_millis(10);
+
+/// Number of calls to [`Pacer::tick`] making up a batch of work.
+const BATCH: u32 = 128;
+
+/// Source of time for pacing, so tests can simulate it.
+pub trait Clock: Send + Sync {
+    fn now(&self) -> Instant;
+    fn sleep(&self, d: Duration);
+}
+
+/// The real clock, sleeping blocks the calling thread, just like the
+/// maintenance work being paced.
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+
+    fn sleep(&self, d: Duration) {
+        std::thread::sleep(d);
+    }
+}
+
+/// Budget shared by all maintenance tasks.
+#[derive(Clone)]
+pub struct Budget {
+    /// Sleep time owed by the maintenance tasks.
+    debt: Arc<Mutex<Duration>>,
+    config: watch::Receiver<MaintenanceConfig>,
+    clock: Arc<dyn Clock>,
+    metrics: MaintenanceMetrics,
+}
+
+impl Budget {
+    pub fn new(
+        config: watch::Receiver<MaintenanceConfig>,
+        clock: Arc<dyn Clock>,
+        metrics: MaintenanceMetrics,
+    ) -> Self {
+        Budget {
+            debt: Default::default(),
+            config,
+            clock,
+            metrics,
+        }
+    }
+
+    /// Get a pacer for `task`, its work is attributed to it in the
+    /// metrics.
+    pub fn pacer(&self, task: MaintenanceTask) -> Pacer {
+        Pacer {
+            budget: self.clone(),
+            task,
+            last: self.clock.now(),
+            ticks: 0,
+        }
+    }
+
+    /// Add the debt for `work` and take it all if it is worth
+    /// sleeping for.
+    fn charge(&self, work: Duration) -> Option<Duration> {
+        let pct = u32::from(self.config.borrow().cpu_budget_pct());
+        if pct >= 100 {
+            return None;
+        }
+
+        // Working for `work` is only allowed as pct% of the wall time,
+        // the rest has to be spent sleeping.
+        let owed = work * (100 - pct) / pct;
+        let mut debt = self.debt.lock().unwrap();
+        *debt += owed;
+        if *debt < MIN_SLEEP {
+            return None;
+        }
+        Some(std::mem::take(&mut *debt))
+    }
+}
+
+/// Per task handle for consuming the maintenance [`Budget`].
+pub struct Pacer {
+    budget: Budget,
+    task: MaintenanceTask,
+    /// End of the last pacing point, work is measured from here.
+    last: Instant,
+    ticks: u32,
+}
+
+impl Pacer {
+    /// Mark the start of a run of work, the time since the previous
+    /// run is not counted as work.
+    pub fn start(&mut self) {
+        self.last = self.budget.clock.now();
+        self.ticks = 0;
+    }
+
+    /// Count a small unit of work, like a single file, pacing every
+    /// batch of them.
+    pub fn tick(&mut self) {
+        self.ticks += 1;
+        if self.ticks >= BATCH {
+            self.pace();
+        }
+    }
+
+    /// Account the work done since the last pacing point, sleeping if
+    /// the maintenance tasks are over budget.
+    pub fn pace(&mut self) {
+        self.ticks = 0;
+        let clock = &self.budget.clock;
+        let work = clock.now().saturating_duration_since(self.last);
+        self.budget.metrics.work(self.task, work);
+
+        if let Some(sleep) = self.budget.charge(work) {
+            clock.sleep(sleep);
+            self.budget.metrics.throttled(self.task, sleep);
+        }
+        self.last = clock.now();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A clock that only moves when told to, sleeping moves it
+    /// forward.
+    struct MockClock(Mutex<Instant>);
+
+    impl MockClock {
+        fn advance(&self, d: Duration) {
+            *self.0.lock().unwrap() += d;
+        }
+    }
+
+    impl Clock for MockClock {
+        fn now(&self) -> Instant {
+            *self.0.lock().unwrap()
+        }
+
+        fn sleep(&self, d: Duration) {
+            self.advance(d);
+        }
+    }
+
+    fn budget(pct: u8) -> (Budget, Arc<MockClock>, watch::Sender<MaintenanceConfig>) {
+        let clock = Arc::new(MockClock(Mutex::new(Instant::now())));
+        let (tx, rx) = watch::channel(config(pct));
+        let budget = Budget::new(rx, clock.clone(), MaintenanceMetrics::default());
+        (budget, clock, tx)
+    }
+
+    fn config(pct: u8) -> MaintenanceConfig {
+        let yaml = format!("maintenance:\n  cpu_budget_pct: {pct}");
+        crate::config::FactConfig::try_from(yaml.as_str())
+            .unwrap()
+            .maintenance
+    }
+
+    /// Simulate a scan of `files` files taking `per_file` each,
+    /// returning the wall time it took.
+    fn scan(pacer: &mut Pacer, clock: &MockClock, files: u32, per_file: Duration) -> Duration {
+        let start = clock.now();
+        pacer.start();
+        for _ in 0..files {
+            clock.advance(per_file);
+            pacer.tick();
+        }
+        pacer.pace();
+        clock.now() - start
+    }
+
+    #[test]
+    fn heavy_scan() {
+        // 10s of work under a 10% budget is spread over 100s
+        let (budget, clock, _tx) = budget(10);
+        let mut pacer = budget.pacer(MaintenanceTask::HostScanner);
+        let elapsed = scan(&mut pacer, &clock, 100_000, Duration::from_micros(100));
+        assert!(
+            elapsed.abs_diff(Duration::from_secs(100)) < Duration::from_millis(100),
+            "{elapsed:?}"
+        );
+
+        let metrics = &budget.metrics;
+        let work = metrics.work_seconds(MaintenanceTask::HostScanner);
+        let throttled = metrics.throttled_seconds(MaintenanceTask::HostScanner);
+        assert!((work - 10.0).abs() < 0.01, "{work}");
+        assert!((throttled - 90.0).abs() < 0.1, "{throttled}");
+    }
+
+    #[test]
+    fn unlimited() {
+        let (budget, clock, _tx) = budget(100);
+        let mut pacer = budget.pacer(MaintenanceTask::HostScanner);
+        let elapsed = scan(&mut pacer, &clock, 10_000, Duration::from_micros(100));
+        assert_eq!(elapsed, Duration::from_secs(1));
+        assert_eq!(
+            budget
+                .metrics
+                .throttled_seconds(MaintenanceTask::HostScanner),
+            0.0
+        );
+    }
+
+    #[test]
+    fn idle_time_is_not_work() {
+        let (budget, clock, _tx) = budget(50);
+        let mut pacer = budget.pacer(MaintenanceTask::HostScanner);
+        clock.advance(Duration::from_secs(60));
+        let elapsed = scan(&mut pacer, &clock, 1000, Duration::from_millis(1));
+        assert!(
+            elapsed.abs_diff(Duration::from_secs(2)) < Duration::from_millis(20),
+            "{elapsed:?}"
+        );
+    }
+
+    #[test]
+    fn shared_and_reloaded() {
+        let (budget, clock, tx) = budget(25);
+        let mut first = budget.pacer(MaintenanceTask::HostScanner);
+        let mut second = budget.pacer(MaintenanceTask::HostScanner);
+
+        // Small amounts of work from different pacers add up to a
+        // single sleep.
+        let start = clock.now();
+        first.start();
+        clock.advance(Duration::from_millis(2));
+        first.pace();
+        assert_eq!(clock.now() - start, Duration::from_millis(2));
+        second.start();
+        clock.advance(Duration::from_millis(2));
+        second.pace();
+        assert_eq!(clock.now() - start, Duration::from_millis(16));
+
+        // A new budget applies to the next pacing point
+        tx.send_replace(config(50));
+        let elapsed = scan(&mut first, &clock, 1000, Duration::from_millis(1));
+        assert!(
+            elapsed.abs_diff(Duration::from_secs(2)) < Duration::from_millis(20),
+            "{elapsed:?}"
+        );
+    }
+}
@@ -0,0 +1,424 @@
+//! Pausing and resuming event collection at runtime.
+//!
+//! Maintenance operations like package upgrades or backups generate
+//! floods of events that are known in advance. Instead of changing the
+//! configuration, collection can be paused for a bounded amount of time
+//! through the control endpoints.
+//!
+//! While paused, the BPF programs stay attached and keep counting the
+//! events they would have sent, so kernel metrics still show the volume
+//! that was suppressed. Collection resumes on its own once the pause
+//! expires.
+//!
+//! Every pause and resume is recorded as an event sent through the
+//! outputs, so consumers can account for the gap in the events.
+
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use log::{debug, info, warn};
+use prometheus_client::metrics::gauge::Gauge;
+use tokio::{
+    sync::{mpsc, oneshot, watch},
+    task::{JoinHandle, JoinSet},
+    time::{Instant, sleep_until},
+};
+
+use crate::event::Event;
+
+/// Longest time collection can be paused for in a single request.
+pub const MAX_PAUSE: Duration = Duration::from_secs(24 * 60 * 60);
+
+#[derive(thiserror::Error, Debug, PartialEq)]
+pub enum PauseError {
+    #[error("pause duration must be greater than zero")]
+    ZeroDuration,
+    #[error("pause duration of {0:?} exceeds the maximum of {MAX_PAUSE:?}")]
+    TooLong(Duration),
+    #[error("pausing is only supported when monitoring with BPF")]
+    Unsupported,
+    #[error("failed to update the pause flag: {0}")]
+    Switch(String),
+    #[error("pause controller is not running")]
+    Stopped,
+}
+
+/// Something that can stop and restart the emission of events.
+pub trait PauseSwitch: Send {
+    fn set_paused(&mut self, paused: bool) -> anyhow::Result<()>;
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct PauseState {
+    /// When collection resumes on its own, `None` if not paused.
+    pub paused_until: Option<SystemTime>,
+}
+
+impl PauseState {
+    pub fn is_paused(&self) -> bool {
+        self.paused_until.is_some()
+    }
+
+    /// Seconds since the epoch at which collection resumes.
+    pub fn paused_until_secs(&self) -> Option<u64> {
+        self.paused_until
+            .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+            .map(|d| d.as_secs())
+    }
+}
+
+enum Request {
+    Pause(Duration),
+    Resume,
+}
+
+type Reply = oneshot::Sender<Result<PauseState, PauseError>>;
+
+/// Handle for sending requests to the pause controller.
+#[derive(Clone)]
+pub struct PauseHandle {
+    requests: mpsc::Sender<(Request, Reply)>,
+    state: watch::Receiver<PauseState>,
+}
+
+impl PauseHandle {
+    /// Pause collection for `duration`, a running pause is extended or
+    /// shortened to the new duration.
+    pub async fn pause(&self, duration: Duration) -> Result<PauseState, PauseError> {
+        if duration.is_zero() {
+            return Err(PauseError::ZeroDuration);
+        }
+        if duration > MAX_PAUSE {
+            return Err(PauseError::TooLong(duration));
+        }
+        self.request(Request::Pause(duration)).await
+    }
+
+    pub async fn resume(&self) -> Result<PauseState, PauseError> {
+        self.request(Request::Resume).await
+    }
+
+    pub fn state(&self) -> PauseState {
+        *self.state.borrow()
+    }
+
+    pub fn subscribe(&self) -> watch::Receiver<PauseState> {
+        self.state.clone()
+    }
+
+    async fn request(&self, request: Request) -> Result<PauseState, PauseError> {
+        let (tx, rx) = oneshot::channel();
+        self.requests
+            .send((request, tx))
+            .await
+            .map_err(|_| PauseError::Stopped)?;
+        rx.await.map_err(|_| PauseError::Stopped)?
+    }
+}
+
+pub struct PauseController {
+    switch: Option<Box<dyn PauseSwitch>>,
+    requests: mpsc::Receiver<(Request, Reply)>,
+    state: watch::Sender<PauseState>,
+    deadline: Option<(Instant, Instant)>,
+    gauge: Gauge,
+    running: watch::Receiver<bool>,
+    records: mpsc::Sender<Event>,
+}
+
+impl PauseController {
+    /// Create the controller, `switch` is `None` when events are not
+    /// coming from the BPF programs, in which case pausing is rejected.
+    ///
+    /// A record of every pause and resume is sent to `records`, to be
+    /// merged into the pipeline with [`records`].
+    pub fn new(
+        switch: Option<Box<dyn PauseSwitch>>,
+        gauge: Gauge,
+        running: watch::Receiver<bool>,
+        records: mpsc::Sender<Event>,
+    ) -> (Self, PauseHandle) {
+        let (tx, requests) = mpsc::channel(10);
+        let (state, state_rx) = watch::channel(PauseState::default());
+        let controller = PauseController {
+            switch,
+            requests,
+            state,
+            deadline: None,
+            gauge,
+            running,
+            records,
+        };
+        let handle = PauseHandle {
+            requests: tx,
+            state: state_rx,
+        };
+        (controller, handle)
+    }
+
+    pub fn start(mut self) -> JoinHandle<()> {
+        tokio::spawn(async move {
+            loop {
+                let deadline = self.deadline.map(|(_, until)| until);
+                tokio::select! {
+                    req = self.requests.recv() => {
+                        let Some((req, reply)) = req else {
+                            return;
+                        };
+                        let res = match req {
+                            Request::Pause(duration) => self.pause(duration),
+                            Request::Resume => self.resume(),
+                        };
+                        let _ = reply.send(res);
+                    }
+                    _ = sleep_until(deadline.unwrap_or_else(Instant::now)), if deadline.is_some() => {
+                        info!("Pause expired");
+                        if let Err(e) = self.resume() {
+                            warn!("Failed to resume event collection: {e}");
+                        }
+                    }
+                    _ = self.running.changed() => {
+                        if !*self.running.borrow() {
+                            info!("Stopping pause controller...");
+                            return;
+                        }
+                    }
+                }
+            }
+        })
+    }
+
+    fn set_paused(&mut self, paused: bool) -> Result<(), PauseError> {
+        let Some(switch) = self.switch.as_mut() else {
+            return Err(PauseError::Unsupported);
+        };
+        switch
+            .set_paused(paused)
+            .map_err(|e| PauseError::Switch(e.to_string()))
+    }
+
+    fn pause(&mut self, duration: Duration) -> Result<PauseState, PauseError> {
+        self.set_paused(true)?;
+
+        let now = Instant::now();
+        let since = self.deadline.map_or(now, |(since, _)| since);
+        self.deadline = Some((since, now + duration));
+        let state = PauseState {
+            paused_until: Some(SystemTime::now() + duration),
+        };
+        // The lifecycle of the pause is logged so the gap in the
+        // events can be accounted for.
+        warn!(
+            "Event collection paused for {duration:?}, resuming at {} (seconds since epoch)",
+            state.paused_until_secs().unwrap_or_default()
+        );
+        self.gauge.set(1);
+        self.state.send_replace(state);
+        self.record(Event::pause(state.paused_until_secs()));
+        Ok(state)
+    }
+
+    fn resume(&mut self) -> Result<PauseState, PauseError> {
+        let Some((since, _)) = self.deadline else {
+            return Ok(PauseState::default());
+        };
+        self.set_paused(false)?;
+
+        self.deadline = None;
+        warn!(
+            "Event collection resumed, events were not collected for {:?}",
+            since.elapsed()
+        );
+        self.gauge.set(0);
+        self.state.send_replace(PauseState::default());
+        self.record(Event::pause(None));
+        Ok(PauseState::default())
+    }
+
+    fn record(&self, event: Event) {
+        if let Err(e) = self.records.try_send(event) {
+            warn!("Failed to send pause record: {e}");
+        }
+    }
+}
+
+/// Merge the records of pauses and resumes into the events going to
+/// the outputs.
+pub fn records(
+    task_set: &mut JoinSet<anyhow::Result<()>>,
+    mut rx: mpsc::Receiver<Event>,
+    mut records: mpsc::Receiver<Event>,
+) -> mpsc::Receiver<Event> {
+    let (tx, output) = mpsc::channel(crate::EVENT_CHANNEL_CAPACITY);
+    task_set.spawn(async move {
+        debug!("Starting pause records...");
+        loop {
+            let event = tokio::select! {
+                event = rx.recv() => {
+                    let Some(event) = event else {
+                        info!("Stopping pause records...");
+                        return Ok(());
+                    };
+                    event
+                }
+                Some(record) = records.recv() => record,
+            };
+            if tx.send(event).await.is_err() {
+                info!("No pause records consumers left, stopping...");
+                return Ok(());
+            }
+        }
+    });
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use tokio::time::timeout;
+
+    use super::*;
+
+    #[derive(Clone, Default)]
+    struct MockSwitch(Arc<Mutex<Vec<bool>>>);
+
+    impl PauseSwitch for MockSwitch {
+        fn set_paused(&mut self, paused: bool) -> anyhow::Result<()> {
+            self.0.lock().unwrap().push(paused);
+            Ok(())
+        }
+    }
+
+    struct Controller {
+        handle: PauseHandle,
+        gauge: Gauge,
+        records: mpsc::Receiver<Event>,
+        _running: watch::Sender<bool>,
+    }
+
+    fn controller(switch: Option<MockSwitch>) -> Controller {
+        let gauge = Gauge::default();
+        let (running_tx, running_rx) = watch::channel(true);
+        let (records_tx, records) = mpsc::channel(10);
+        let switch = switch.map(|s| Box::new(s) as Box<dyn PauseSwitch>);
+        let (controller, handle) =
+            PauseController::new(switch, gauge.clone(), running_rx, records_tx);
+        controller.start();
+        Controller {
+            handle,
+            gauge,
+            records,
+            _running: running_tx,
+        }
+    }
+
+    #[tokio::test]
+    async fn pause_resume() {
+        let switch = MockSwitch::default();
+        let Controller {
+            handle,
+            gauge,
+            mut records,
+            ..
+        } = controller(Some(switch.clone()));
+
+        let state = handle.pause(Duration::from_secs(600)).await.unwrap();
+        assert!(state.is_paused());
+        assert!(handle.state().is_paused());
+        assert_eq!(gauge.get(), 1);
+        let record = records.try_recv().unwrap();
+        assert_eq!(record, Event::pause(state.paused_until_secs()));
+
+        let state = handle.resume().await.unwrap();
+        assert!(!state.is_paused());
+        assert!(!handle.state().is_paused());
+        assert_eq!(gauge.get(), 0);
+        assert_eq!(records.try_recv().unwrap(), Event::pause(None));
+
+        // Resuming while not paused does nothing
+        handle.resume().await.unwrap();
+        assert_eq!(*switch.0.lock().unwrap(), [true, false]);
+        assert!(records.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn auto_resume() {
+        let switch = MockSwitch::default();
+        let Controller {
+            handle,
+            gauge,
+            mut records,
+            ..
+        } = controller(Some(switch.clone()));
+        let mut state = handle.subscribe();
+
+        handle.pause(Duration::from_millis(100)).await.unwrap();
+        assert_eq!(gauge.get(), 1);
+        timeout(Duration::from_secs(2), state.wait_for(|s| !s.is_paused()))
+            .await
+            .expect("Pause did not expire")
+            .unwrap();
+        assert_eq!(gauge.get(), 0);
+        assert_eq!(*switch.0.lock().unwrap(), [true, false]);
+        assert!(records.try_recv().unwrap().is_pause());
+        assert_eq!(records.try_recv().unwrap(), Event::pause(None));
+
+        // A new request replaces the deadline of a running pause
+        handle.pause(Duration::from_millis(100)).await.unwrap();
+        handle.pause(Duration::from_secs(600)).await.unwrap();
+        tokio::time::sleep(Duration::from_millis(300)).await;
+        assert!(handle.state().is_paused());
+    }
+
+    #[tokio::test]
+    async fn duration_bounds() {
+        let Controller { handle, .. } = controller(Some(MockSwitch::default()));
+        assert_eq!(
+            handle.pause(Duration::ZERO).await,
+            Err(PauseError::ZeroDuration)
+        );
+        let too_long = MAX_PAUSE + Duration::from_secs(1);
+        assert_eq!(
+            handle.pause(too_long).await,
+            Err(PauseError::TooLong(too_long))
+        );
+        assert!(handle.pause(MAX_PAUSE).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn unsupported() {
+        let Controller {
+            handle,
+            gauge,
+            mut records,
+            ..
+        } = controller(None);
+        assert_eq!(
+            handle.pause(Duration::from_secs(1)).await,
+            Err(PauseError::Unsupported)
+        );
+        assert!(!handle.state().is_paused());
+        assert_eq!(gauge.get(), 0);
+        assert!(records.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn records_merged() {
+        let mut task_set = JoinSet::new();
+        let (tx, rx) = mpsc::channel(10);
+        let (records_tx, records_rx) = mpsc::channel(10);
+        let mut output = records(&mut task_set, rx, records_rx);
+
+        records_tx.send(Event::pause(Some(1000))).await.unwrap();
+        assert_eq!(output.recv().await.unwrap(), Event::pause(Some(1000)));
+
+        let event = Event::summary(0, Vec::new());
+        tx.send(event.clone()).await.unwrap();
+        assert_eq!(output.recv().await.unwrap(), event);
+
+        // Pipeline shutdown stops the task even with a live controller
+        drop(tx);
+        assert!(output.recv().await.is_none());
+        drop(records_tx);
+    }
+}
@@ -0,0 +1,206 @@
+//! Embedding the event pipeline of fact in another program.
+//!
+//! `fact::run` is a `Pipeline` with nothing registered on it. Programs
+//! embedding fact build one from a configuration, register their own
+//! sinks or subscribe to the events, and run it in place of `fact::run`:
+//!
+//! ```no_run
+//! use fact::{
+//!     config::FactConfig,
+//!     events::Event,
+//!     pipeline::{EventSink, Pipeline},
+//! };
+//!
+//! struct Printer;
+//!
+//! impl EventSink for Printer {
+//!     fn name(&self) -> &str {
+//!         "printer"
+//!     }
+//!
+//!     fn handle(&mut self, event: &Event) -> anyhow::Result<()> {
+//!         println!("{} {}", event.event_type(), event.get_filename().display());
+//!         Ok(())
+//!     }
+//! }
+//!
+//! # async fn example() -> anyhow::Result<()> {
+//! let config = FactConfig::new()?;
+//! Pipeline::new(config).sink(Printer).run().await
+//! # }
+//! ```
+//!
+//! The built-in outputs keep working as configured. Since events have a
+//! destination once a sink is registered, the stdout output only starts
+//! when `output.stdout` is `on`, like with the gRPC output.
+
+use std::sync::Arc;
+
+use tokio::sync::broadcast;
+
+use crate::{config::FactConfig, event::Event};
+
+/// Destination for the events leaving the pipeline, registered with
+/// `Pipeline::sink`.
+///
+/// Each sink is driven by a task of its own and gets every event in
+/// the order they were emitted. A sink falling behind misses events
+/// instead of slowing down the other outputs, missed events are
+/// counted as dropped in the `output_sink_events` metric. With
+/// `output.overflow_policy` set to `block`, the pipeline waits for the
+/// sink instead.
+///
+/// Methods are called from the async runtime of fact, they must not
+/// block for long.
+pub trait EventSink: Send + 'static {
+    /// Name of the sink, used in logs.
+    fn name(&self) -> &str;
+
+    /// Handle an event.
+    ///
+    /// Errors are logged and the event is counted as dropped, the sink
+    /// keeps getting events.
+    fn handle(&mut self, event: &Event) -> anyhow::Result<()>;
+
+    /// Called once no more events will be handled, before fact stops.
+    fn flush(&mut self) -> anyhow::Result<()> {
+        Ok(())
+    }
+}
+
+/// The fact event pipeline: reading events from the kernel, enriching
+/// them and sending them to the configured outputs.
+pub struct Pipeline {
+    config: FactConfig,
+    events: broadcast::Sender<Arc<Event>>,
+    sinks: Vec<Box<dyn EventSink>>,
+}
+
+impl Pipeline {
+    pub fn new(config: FactConfig) -> Self {
+        let (events, _) = broadcast::channel(crate::EVENT_CHANNEL_CAPACITY);
+        Pipeline {
+            config,
+            events,
+            sinks: Vec::new(),
+        }
+    }
+
+    /// Send events to `sink` as well as to the built-in outputs.
+    pub fn sink(mut self, sink: impl EventSink) -> Self {
+        self.sinks.push(Box::new(sink));
+        self
+    }
+
+    /// Receive the events leaving the pipeline.
+    ///
+    /// The receiver gets the events of the next call to `run`. Events
+    /// are kept for slow receivers up to a limit, after which they are
+    /// told how many events they missed. With `output.overflow_policy`
+    /// set to `block`, the pipeline waits for receivers at the limit
+    /// instead, so they must keep reading. The channel closes once the
+    /// pipeline stops.
+    pub fn subscribe(&self) -> broadcast::Receiver<Arc<Event>> {
+        self.events.subscribe()
+    }
+
+    /// Run the pipeline until fact is stopped, by a signal or by the
+    /// configuration, or fails.
+    pub async fn run(self) -> anyhow::Result<()> {
+        let Pipeline {
+            config,
+            events,
+            sinks,
+        } = self;
+        crate::run_pipeline(config, events, sinks).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::Write,
+        path::PathBuf,
+        sync::Mutex,
+        time::{Duration, Instant},
+    };
+
+    use fact_ebpf::{PATH_MAX, event_t};
+    use tokio::{sync::broadcast::error::RecvError, time::timeout};
+
+    use super::*;
+    use crate::event::test_utils::string_to_c_char_array;
+
+    #[derive(Default)]
+    struct Recorded {
+        files: Vec<PathBuf>,
+        flushed: bool,
+    }
+
+    struct Recorder(Arc<Mutex<Recorded>>);
+
+    impl EventSink for Recorder {
+        fn name(&self) -> &str {
+            "recorder"
+        }
+
+        fn handle(&mut self, event: &Event) -> anyhow::Result<()> {
+            let mut recorded = self.0.lock().unwrap();
+            assert!(!recorded.flushed);
+            recorded.files.push(event.get_filename().clone());
+            Ok(())
+        }
+
+        fn flush(&mut self) -> anyhow::Result<()> {
+            self.0.lock().unwrap().flushed = true;
+            Ok(())
+        }
+    }
+
+    fn replay_config(files: &[&str]) -> (FactConfig, tempfile::NamedTempFile) {
+        let mut file = tempfile::NamedTempFile::new().expect("Failed to create temp file");
+        for (i, path) in files.iter().enumerate() {
+            let event = event_t {
+                timestamp: i as u64,
+                filename: string_to_c_char_array::<{ PATH_MAX as usize }>(path),
+                ..Default::default()
+            };
+            let event = Event::try_from(&event).expect("Failed to parse event");
+            let line = serde_json::to_string(&event).expect("Failed to serialize event");
+            writeln!(file, "{line}").expect("Failed to write event");
+        }
+
+        let yaml = format!("replay: {}\nhotreload: false", file.path().display());
+        let config = FactConfig::try_from(yaml.as_str()).expect("Failed to parse config");
+        (config, file)
+    }
+
+    #[tokio::test]
+    async fn sinks_and_subscribers() {
+        let files = ["/etc/passwd", "/etc/shadow", "/etc/hosts"];
+        let (config, _file) = replay_config(&files);
+        let recorded = Arc::new(Mutex::new(Recorded::default()));
+
+        let pipeline = Pipeline::new(config).sink(Recorder(recorded.clone()));
+        let mut rx = pipeline.subscribe();
+        let start = Instant::now();
+        timeout(Duration::from_secs(10), pipeline.run())
+            .await
+            .expect("fact did not stop")
+            .expect("fact failed");
+        // Subscribers held outside are not waited for on shutdown
+        assert!(start.elapsed() < crate::SHUTDOWN_TIMEOUT);
+
+        {
+            let recorded = recorded.lock().unwrap();
+            assert_eq!(recorded.files, files.map(PathBuf::from));
+            assert!(recorded.flushed);
+        }
+
+        for file in files {
+            let event = rx.recv().await.expect("Missing event");
+            assert_eq!(event.get_filename(), &PathBuf::from(file));
+        }
+        assert!(matches!(rx.recv().await, Err(RecvError::Closed)));
+    }
+}
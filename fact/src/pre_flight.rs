@@ -1,28 +1,96 @@
-use std::fs::read_to_string;
+use std::{fs::read_to_string, path::Path};
 
 use anyhow::{Context, bail};
+use log::{info, warn};
 
-use crate::host_info::get_host_mount;
+use crate::{bpf, host_info::get_host_mount};
 
 fn have_bpf_lsm_inner(lsm_config: &str) -> anyhow::Result<()> {
-    if !lsm_config.split(',').any(|cap| cap == "bpf") {
+    if !lsm_config.trim().split(',').any(|cap| cap == "bpf") {
         bail!("BPF capability for LSM is not configured")
     }
     Ok(())
 }
 
-fn have_bpf_lsm() -> anyhow::Result<()> {
-    let lsm_config = get_host_mount().join("sys/kernel/security/lsm");
-    let lsm_config = read_to_string(lsm_config).context("Failed to read LSM configuration")?;
-    have_bpf_lsm_inner(&lsm_config)
+/// Get the value of a parameter in the kernel command line.
+///
+/// If the parameter is set multiple times, the last value is returned,
+/// matching the behavior of the kernel.
+fn cmdline_param<'a>(cmdline: &'a str, param: &str) -> Option<&'a str> {
+    cmdline
+        .split_whitespace()
+        .filter_map(|p| p.split_once('='))
+        .filter(|(name, _)| *name == param)
+        .map(|(_, value)| value)
+        .next_back()
+}
+
+/// Check if the kernel command line enables BPF LSM.
+///
+/// Returns true only if `lsm=` is set and includes bpf. The legacy
+/// `security=` parameter can only select a single major LSM, so it is
+/// never enough to enable bpf.
+fn cmdline_has_bpf_lsm(cmdline: &str) -> bool {
+    match cmdline_param(cmdline, "lsm") {
+        Some(lsm) if have_bpf_lsm_inner(lsm).is_ok() => {
+            info!("Kernel command line enables BPF LSM: lsm={lsm}");
+            true
+        }
+        Some(lsm) => {
+            info!("Kernel command line does not enable BPF LSM: lsm={lsm}");
+            false
+        }
+        None => {
+            match cmdline_param(cmdline, "security") {
+                Some(security) => info!("Kernel command line only sets security={security}"),
+                None => info!("Kernel command line does not set lsm="),
+            }
+            false
+        }
+    }
+}
+
+/// Check if BPF LSM is available on the host.
+///
+/// The LSM configuration in securityfs is the source of truth, but it
+/// is not always mounted in containerized deployments. When it is
+/// missing, the kernel command line is checked for the `lsm=`
+/// parameter and, as a last resort, `probe` is used to attempt
+/// attaching a BPF LSM program.
+fn have_bpf_lsm(
+    host_mount: &Path,
+    probe: impl FnOnce() -> anyhow::Result<()>,
+) -> anyhow::Result<()> {
+    let securityfs = host_mount.join("sys/kernel/security/lsm");
+    match read_to_string(&securityfs) {
+        Ok(lsm_config) => {
+            info!("Checking LSM configuration from {}", securityfs.display());
+            return have_bpf_lsm_inner(&lsm_config);
+        }
+        Err(e) => warn!("Failed to read {}: {e}", securityfs.display()),
+    }
+
+    let cmdline = host_mount.join("proc/cmdline");
+    match read_to_string(&cmdline) {
+        Ok(cmdline) if cmdline_has_bpf_lsm(&cmdline) => return Ok(()),
+        Ok(_) => {}
+        Err(e) => warn!("Failed to read {}: {e}", cmdline.display()),
+    }
+
+    info!("Probing BPF LSM support by attaching a test program");
+    probe().context("BPF LSM is not available")?;
+    info!("BPF LSM test program attached successfully");
+    Ok(())
 }
 
 pub fn pre_flight() -> anyhow::Result<()> {
-    have_bpf_lsm()
+    have_bpf_lsm(get_host_mount(), bpf::probe_lsm)
 }
 
 #[cfg(test)]
 mod tests {
+    use std::{cell::Cell, fs};
+
     use super::*;
 
     #[test]
@@ -51,4 +119,94 @@ mod tests {
             assert_eq!(available, res.is_ok());
         }
     }
+
+    #[test]
+    fn test_cmdline_has_bpf_lsm() {
+        let tests = [
+            ("BOOT_IMAGE=/vmlinuz root=/dev/sda1 ro", false),
+            ("root=/dev/sda1 lsm=lockdown,yama,bpf quiet", true),
+            ("root=/dev/sda1 lsm=lockdown,yama,selinux quiet", false),
+            ("lsm=lockdown,yama lsm=lockdown,bpf", true),
+            ("lsm=bpf lsm=lockdown,yama", false),
+            ("root=/dev/sda1 security=selinux", false),
+            ("root=/dev/sda1 lsm=bpf\n", true),
+            ("slsm=bpf", false),
+            ("", false),
+        ];
+
+        for (cmdline, expected) in tests {
+            assert_eq!(
+                cmdline_has_bpf_lsm(cmdline),
+                expected,
+                "Failed for {cmdline:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_have_bpf_lsm_fallbacks() {
+        const LSM: &str = "lockdown,capability,yama,bpf\n";
+        const LSM_NO_BPF: &str = "lockdown,capability,yama\n";
+        const CMDLINE: &str = "root=/dev/sda1 lsm=lockdown,capability,bpf\n";
+        const CMDLINE_NO_BPF: &str = "root=/dev/sda1 security=selinux\n";
+
+        let tests = [
+            (Some(LSM), None, None, true, "securityfs"),
+            (
+                Some(LSM_NO_BPF),
+                Some(CMDLINE),
+                None,
+                false,
+                "securityfs wins",
+            ),
+            (None, Some(CMDLINE), None, true, "cmdline"),
+            (
+                None,
+                Some(CMDLINE_NO_BPF),
+                Some(true),
+                true,
+                "probe after cmdline",
+            ),
+            (
+                None,
+                Some(CMDLINE_NO_BPF),
+                Some(false),
+                false,
+                "failed probe",
+            ),
+            (None, None, Some(true), true, "probe only"),
+            (None, None, Some(false), false, "nothing available"),
+        ];
+
+        for (lsm, cmdline, probe_res, expected, description) in tests {
+            let host_mount = tempfile::tempdir().expect("Failed to create host mount");
+            if let Some(lsm) = lsm {
+                let path = host_mount.path().join("sys/kernel/security");
+                fs::create_dir_all(&path).unwrap();
+                fs::write(path.join("lsm"), lsm).unwrap();
+            }
+            if let Some(cmdline) = cmdline {
+                let path = host_mount.path().join("proc");
+                fs::create_dir_all(&path).unwrap();
+                fs::write(path.join("cmdline"), cmdline).unwrap();
+            }
+
+            let probed = Cell::new(false);
+            let probe = || {
+                probed.set(true);
+                match probe_res {
+                    Some(true) => Ok(()),
+                    _ => bail!("Failed to attach"),
+                }
+            };
+
+            let res = have_bpf_lsm(host_mount.path(), probe);
+            assert_eq!(res.is_ok(), expected, "Failed for {description}");
+            assert_eq!(
+                probed.get(),
+                probe_res.is_some(),
+                "Unexpected probe for {description}"
+            );
+        }
+    }
 }
@@ -11,7 +11,7 @@ use tokio::{
 };
 
 use crate::event::Event;
-use crate::metrics::EventCounter;
+use crate::metrics::{DropReason, EventCounter};
 
 pub struct RateLimiter {
     // the governor::RateLimiter handles the actual rate limiting. For now
@@ -32,7 +32,7 @@ impl RateLimiter {
         metrics: EventCounter,
     ) -> anyhow::Result<(Self, mpsc::Receiver<Event>)> {
         let limiter = Self::build_limiter(*rate_config.borrow());
-        let (tx, output) = mpsc::channel(100);
+        let (tx, output) = mpsc::channel(crate::EVENT_CHANNEL_CAPACITY);
 
         let limiter = RateLimiter {
             limiter,
@@ -71,7 +71,7 @@ impl RateLimiter {
                         let Some(event) = event else { break; };
 
                         if let Some(limiter) = &self.limiter && limiter.check().is_err() {
-                            self.metrics.dropped();
+                            self.metrics.dropped(DropReason::RateLimited);
                             continue;
                         }
 
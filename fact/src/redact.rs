@@ -0,0 +1,277 @@
+//! Redaction of secrets in the arguments of processes.
+//!
+//! Command lines often carry secrets, like `--password=...` or tokens
+//! passed to curl. Substrings of the arguments matching any of the
+//! patterns in `redact_args` are replaced with `***` before events
+//! reach the outputs, so all of them get the same redacted data.
+//!
+//! Patterns are matched against the arguments joined with spaces, the
+//! way they would be typed, which allows a pattern to cover a flag and
+//! its value when they are separate arguments. Matches of different
+//! patterns that overlap are redacted as a single substring.
+
+use std::{fmt, ops::Range, str::FromStr};
+
+use log::{debug, info};
+use prometheus_client::metrics::counter::Counter;
+use regex::Regex;
+use tokio::{
+    sync::{mpsc, watch},
+    task::JoinSet,
+};
+
+use crate::event::Event;
+
+const REDACTED: &str = "***";
+
+/// A regular expression matching secrets in process arguments.
+#[derive(Clone)]
+pub struct RedactPattern(Regex);
+
+impl FromStr for RedactPattern {
+    type Err = regex::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Regex::new(s).map(RedactPattern)
+    }
+}
+
+// The regex is compiled from the pattern, comparing and printing the
+// pattern is enough.
+impl fmt::Debug for RedactPattern {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}", self.0.as_str())
+    }
+}
+
+impl PartialEq for RedactPattern {
+    fn eq(&self, other: &Self) -> bool {
+        self.0.as_str() == other.0.as_str()
+    }
+}
+
+impl Eq for RedactPattern {}
+
+/// Replace the substrings of `args` matching any of `patterns`.
+///
+/// Returns the number of substrings redacted.
+pub fn redact(patterns: &[RedactPattern], args: &mut [String]) -> u64 {
+    if patterns.is_empty() || args.is_empty() {
+        return 0;
+    }
+
+    let line = args.join(" ");
+    let mut matches = patterns
+        .iter()
+        .flat_map(|p| p.0.find_iter(&line).map(|m| m.range()))
+        .filter(|m| !m.is_empty())
+        .collect::<Vec<_>>();
+    if matches.is_empty() {
+        return 0;
+    }
+
+    matches.sort_by_key(|m| m.start);
+    let mut merged: Vec<Range<usize>> = Vec::with_capacity(matches.len());
+    for m in matches {
+        match merged.last_mut() {
+            Some(last) if m.start <= last.end => last.end = last.end.max(m.end),
+            _ => merged.push(m),
+        }
+    }
+
+    let mut start = 0;
+    for arg in args.iter_mut() {
+        let end = start + arg.len();
+        let mut redacted = String::new();
+        let mut pos = start;
+        for m in &merged {
+            let (from, to) = (m.start.max(start), m.end.min(end));
+            if from < to {
+                redacted.push_str(&line[pos..from]);
+                redacted.push_str(REDACTED);
+                pos = to;
+            }
+        }
+        if pos != start {
+            redacted.push_str(&line[pos..end]);
+            *arg = redacted;
+        }
+        // Skip the space joining the arguments
+        start = end + 1;
+    }
+    merged.len() as u64
+}
+
+/// Start a task redacting the arguments of the events going through
+/// it.
+pub fn start(
+    task_set: &mut JoinSet<anyhow::Result<()>>,
+    mut rx: mpsc::Receiver<Event>,
+    mut config: watch::Receiver<Vec<RedactPattern>>,
+    redactions: Counter,
+) -> mpsc::Receiver<Event> {
+    let (tx, output) = mpsc::channel(crate::EVENT_CHANNEL_CAPACITY);
+    let mut patterns = config.borrow_and_update().clone();
+    task_set.spawn(async move {
+        debug!("Starting argument redaction...");
+        loop {
+            tokio::select! {
+                event = rx.recv() => {
+                    let Some(mut event) = event else {
+                        info!("Stopping argument redaction...");
+                        return Ok(());
+                    };
+                    let n = redact(&patterns, event.args_mut());
+                    if n > 0 {
+                        redactions.inc_by(n);
+                    }
+                    if tx.send(event).await.is_err() {
+                        info!("No argument redaction consumers left, stopping...");
+                        return Ok(());
+                    }
+                }
+                Ok(_) = config.changed() => {
+                    patterns = config.borrow_and_update().clone();
+                }
+            }
+        }
+    });
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use fact_ebpf::{ARGS_MAX, PATH_MAX, event_t};
+    use tokio::time::timeout;
+
+    use super::*;
+    use crate::event::test_utils::string_to_c_char_array;
+
+    fn patterns(patterns: &[&str]) -> Vec<RedactPattern> {
+        patterns.iter().map(|p| p.parse().unwrap()).collect()
+    }
+
+    fn args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|a| a.to_string()).collect()
+    }
+
+    #[test]
+    fn redaction() {
+        let tests = [
+            (
+                &["--password=\\S+"][..],
+                &["mysql", "--password=hunter2", "-u", "root"][..],
+                &["mysql", "***", "-u", "root"][..],
+                1,
+                "Single pattern",
+            ),
+            (
+                &["--password=\\S+", "Bearer \\S+"],
+                &[
+                    "curl",
+                    "-H",
+                    "Authorization: Bearer abc.def",
+                    "--password=x",
+                ],
+                &["curl", "-H", "Authorization: ***", "***"],
+                2,
+                "Multiple patterns",
+            ),
+            (
+                &["token=[a-z]+", "[a-z]+=secret"],
+                &["app", "token=secret"],
+                &["app", "***"],
+                1,
+                "Overlapping matches",
+            ),
+            (
+                &["key=\\w+"],
+                &["app", "--opt", "key=a,key=b"],
+                &["app", "--opt", "***,***"],
+                2,
+                "Several matches in an argument",
+            ),
+            (
+                &["--token \\S+"],
+                &["cli", "--token", "s3cr3t", "run"],
+                &["cli", "***", "***", "run"],
+                1,
+                "Match across arguments",
+            ),
+            (
+                &["пароль=\\S+"],
+                &["приложение", "пароль=тест"],
+                &["приложение", "***"],
+                1,
+                "UTF-8",
+            ),
+            (
+                &["--password=\\S+"],
+                &["ls", "-l"],
+                &["ls", "-l"],
+                0,
+                "No match",
+            ),
+            (&["x*"], &["ls", "-l"], &["ls", "-l"], 0, "Empty matches"),
+        ];
+
+        for (p, input, expected, count, description) in tests {
+            let mut input = args(input);
+            assert_eq!(
+                redact(&patterns(p), &mut input),
+                count,
+                "Failed for {description}"
+            );
+            assert_eq!(input, args(expected), "Failed for {description}");
+        }
+    }
+
+    #[test]
+    fn invalid_pattern() {
+        assert!("--password=(\\S+".parse::<RedactPattern>().is_err());
+        assert!("[a-".parse::<RedactPattern>().is_err());
+    }
+
+    fn event(args: &str) -> Event {
+        let mut event = event_t {
+            filename: string_to_c_char_array::<{ PATH_MAX as usize }>("/etc/passwd"),
+            ..Default::default()
+        };
+        event.process.args = string_to_c_char_array::<{ ARGS_MAX as usize }>(args);
+        event.process.args_len = args.len() as u32;
+        Event::try_from(&event).unwrap()
+    }
+
+    #[tokio::test]
+    async fn reload() {
+        let (config_tx, config) = watch::channel(patterns(&["secret"]));
+        let (tx, rx) = mpsc::channel(10);
+        let redactions = Counter::default();
+        let mut task_set = JoinSet::new();
+        let mut rx = start(&mut task_set, rx, config, redactions.clone());
+
+        tx.send(event("echo\0secret\0")).await.unwrap();
+        let received = rx.recv().await.unwrap();
+        assert_eq!(received.get_process().args(), ["echo", "***"]);
+        assert_eq!(redactions.get(), 1);
+
+        config_tx.send_replace(patterns(&["echo"]));
+        timeout(Duration::from_secs(10), async {
+            loop {
+                tx.send(event("echo\0secret\0")).await.unwrap();
+                let received = rx.recv().await.unwrap();
+                if received.get_process().args() == ["***", "secret"] {
+                    break;
+                }
+            }
+        })
+        .await
+        .expect("Timed out waiting for the new patterns");
+
+        drop(tx);
+        assert!(rx.recv().await.is_none());
+        task_set.join_next().await.unwrap().unwrap().unwrap();
+    }
+}
@@ -0,0 +1,311 @@
+//! Reordering of events by timestamp.
+//!
+//! All CPUs write to the same ringbuffer and an event reserved on one
+//! CPU can be committed after one reserved later on another, so events
+//! are not always read in the order they happened, an unlink may be
+//! read before the open that preceded it. With a window configured,
+//! events are held for that long after being read and released sorted
+//! by timestamp. Events read more than a window late can't be put back
+//! in place, they are released right away.
+
+use std::{
+    collections::{BTreeMap, VecDeque},
+    time::Duration,
+};
+
+use log::{debug, info};
+use tokio::{
+    sync::{mpsc, watch},
+    task::JoinSet,
+    time::{Instant, sleep_until},
+};
+
+use crate::{event::Event, metrics::ReorderMetrics};
+
+/// Events held at most, the earliest ones are released before they
+/// are due past it.
+const BUFFER_MAX: usize = 4096;
+
+/// Events by timestamp, then order of arrival.
+type Key = (u64, u64);
+
+pub struct ReorderBuffer {
+    window: Duration,
+    events: BTreeMap<Key, Event>,
+    /// When the events held are due, in order of arrival. Events
+    /// released early leave their entry behind until it is due.
+    due: VecDeque<(Instant, Key)>,
+    arrivals: u64,
+    /// Latest timestamp read so far.
+    latest: u64,
+    metrics: ReorderMetrics,
+}
+
+impl ReorderBuffer {
+    pub fn new(window: Duration, metrics: ReorderMetrics) -> Self {
+        ReorderBuffer {
+            window,
+            events: BTreeMap::new(),
+            due: VecDeque::new(),
+            arrivals: 0,
+            latest: 0,
+            metrics,
+        }
+    }
+
+    /// Change the window, returning the events held until now.
+    pub fn set_window(&mut self, window: Duration) -> Vec<Event> {
+        self.window = window;
+        self.flush()
+    }
+
+    /// Hold `event`, read at `now`.
+    pub fn push(&mut self, event: Event, now: Instant) {
+        let timestamp = event.get_timestamp();
+        let skew = self.latest.saturating_sub(timestamp);
+        if skew as i64 > self.metrics.max_skew.get() {
+            self.metrics.max_skew.set(skew as i64);
+        }
+        self.latest = self.latest.max(timestamp);
+
+        let key = (timestamp, self.arrivals);
+        self.arrivals += 1;
+        self.events.insert(key, event);
+        // Events held for a window too long to be added to an instant
+        // are only released once the buffer is full or flushed
+        if let Some(due) = now.checked_add(self.window) {
+            self.due.push_back((due, key));
+        }
+        self.metrics.buffered.set(self.events.len() as i64);
+    }
+
+    /// When the next event is due, if any is held.
+    pub fn next_due(&self) -> Option<Instant> {
+        self.due.front().map(|(due, _)| *due)
+    }
+
+    /// Take the events due at `now`, along with the ones that happened
+    /// before them, sorted by timestamp.
+    pub fn pop_due(&mut self, now: Instant) -> Vec<Event> {
+        let mut last = None;
+        while let Some((due, key)) = self.due.front()
+            && *due <= now
+        {
+            last = last.max(Some(*key));
+            self.due.pop_front();
+        }
+
+        let mut released = match last {
+            Some((timestamp, arrival)) => {
+                let held = self.events.split_off(&(timestamp, arrival + 1));
+                std::mem::replace(&mut self.events, held)
+                    .into_values()
+                    .collect()
+            }
+            None => Vec::new(),
+        };
+        while self.events.len() > BUFFER_MAX
+            && let Some((_, event)) = self.events.pop_first()
+        {
+            released.push(event);
+        }
+        self.metrics.buffered.set(self.events.len() as i64);
+        released
+    }
+
+    /// Take all the events held, sorted by timestamp.
+    pub fn flush(&mut self) -> Vec<Event> {
+        self.due.clear();
+        self.metrics.buffered.set(0);
+        std::mem::take(&mut self.events).into_values().collect()
+    }
+}
+
+/// Start a task releasing the events going through it sorted by
+/// timestamp, once they have been held for the configured window.
+pub fn start(
+    task_set: &mut JoinSet<anyhow::Result<()>>,
+    mut rx: mpsc::Receiver<Event>,
+    mut config: watch::Receiver<Duration>,
+    metrics: ReorderMetrics,
+) -> mpsc::Receiver<Event> {
+    let (tx, output) = mpsc::channel(crate::EVENT_CHANNEL_CAPACITY);
+    let mut buffer = ReorderBuffer::new(*config.borrow_and_update(), metrics);
+    task_set.spawn(async move {
+        debug!("Starting event reordering...");
+        loop {
+            let due = buffer.next_due();
+            let released = tokio::select! {
+                event = rx.recv() => {
+                    let Some(event) = event else {
+                        info!("Stopping event reordering...");
+                        for event in buffer.flush() {
+                            if tx.send(event).await.is_err() {
+                                break;
+                            }
+                        }
+                        return Ok(());
+                    };
+                    let now = Instant::now();
+                    buffer.push(event, now);
+                    buffer.pop_due(now)
+                }
+                _ = sleep_until(due.unwrap_or_else(Instant::now)), if due.is_some() => {
+                    buffer.pop_due(Instant::now())
+                }
+                _ = config.changed() => {
+                    let window = *config.borrow_and_update();
+                    info!("Reordering events in a window of {window:?}");
+                    buffer.set_window(window)
+                }
+            };
+            for event in released {
+                if tx.send(event).await.is_err() {
+                    info!("No reordering consumers left, stopping...");
+                    return Ok(());
+                }
+            }
+        }
+    });
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use tokio::time::timeout;
+
+    use super::*;
+
+    fn event(timestamp: u64) -> Event {
+        let path = Path::new("/");
+        let mut event = Event::inventory(path, &path.metadata().unwrap());
+        event.set_timestamp(timestamp);
+        event
+    }
+
+    fn timestamps(events: Vec<Event>) -> Vec<u64> {
+        events.iter().map(Event::get_timestamp).collect()
+    }
+
+    #[test]
+    fn sorted() {
+        let window = Duration::from_millis(10);
+        let metrics = ReorderMetrics::default();
+        let mut buffer = ReorderBuffer::new(window, metrics.clone());
+        let start = Instant::now();
+
+        // Two CPUs interleaving their events
+        for (i, timestamp) in [1, 4, 2, 5, 3, 6].into_iter().enumerate() {
+            buffer.push(event(timestamp), start + Duration::from_millis(i as u64));
+        }
+        assert_eq!(metrics.buffered.get(), 6);
+        assert_eq!(metrics.max_skew.get(), 2);
+        assert_eq!(buffer.next_due(), Some(start + window));
+
+        // Nothing is due before the window is over
+        assert!(buffer.pop_due(start + window / 2).is_empty());
+
+        // Events are released once due, along with the earlier ones
+        // read after them
+        assert_eq!(timestamps(buffer.pop_due(start + window)), [1]);
+        let due = start + window + Duration::from_millis(1);
+        assert_eq!(timestamps(buffer.pop_due(due)), [2, 3, 4]);
+        assert_eq!(metrics.buffered.get(), 2);
+
+        // Events read too late are released in the next batch
+        buffer.push(event(0), due);
+        let due = start + window + Duration::from_millis(5);
+        assert_eq!(timestamps(buffer.pop_due(due)), [0, 5, 6]);
+        assert_eq!(metrics.max_skew.get(), 6);
+        assert_eq!(metrics.buffered.get(), 0);
+    }
+
+    #[test]
+    fn bounded() {
+        let mut buffer = ReorderBuffer::new(Duration::from_secs(60), ReorderMetrics::default());
+        let now = Instant::now();
+        for timestamp in (0..BUFFER_MAX as u64 + 2).rev() {
+            buffer.push(event(timestamp), now);
+        }
+        assert_eq!(timestamps(buffer.pop_due(now)), [0, 1]);
+        assert_eq!(buffer.flush().len(), BUFFER_MAX);
+        assert!(buffer.flush().is_empty());
+    }
+
+    #[test]
+    fn forever() {
+        let mut buffer = ReorderBuffer::new(Duration::MAX, ReorderMetrics::default());
+        let now = Instant::now();
+        for timestamp in [2, 1] {
+            buffer.push(event(timestamp), now);
+        }
+        assert_eq!(buffer.next_due(), None);
+        assert!(buffer.pop_due(now + Duration::from_secs(3600)).is_empty());
+        assert_eq!(timestamps(buffer.flush()), [1, 2]);
+    }
+
+    #[test]
+    fn disabled() {
+        let mut buffer = ReorderBuffer::new(Duration::ZERO, ReorderMetrics::default());
+        let now = Instant::now();
+        for timestamp in [3, 1, 2] {
+            buffer.push(event(timestamp), now);
+            assert_eq!(timestamps(buffer.pop_due(now)), [timestamp]);
+        }
+    }
+
+    #[tokio::test]
+    async fn stage() {
+        let window = Duration::from_millis(50);
+        let (tx, rx) = mpsc::channel(10);
+        let (config, config_rx) = watch::channel(window);
+        let metrics = ReorderMetrics::default();
+        let mut tasks = JoinSet::new();
+        let mut output = start(&mut tasks, rx, config_rx, metrics.clone());
+
+        let sent = Instant::now();
+        for timestamp in [2, 1, 3] {
+            tx.send(event(timestamp)).await.unwrap();
+        }
+        let mut received = Vec::new();
+        for _ in 0..3 {
+            received.push(output.recv().await.unwrap());
+        }
+        assert_eq!(timestamps(received), [1, 2, 3]);
+        // Events are held for the window and not much longer
+        let latency = sent.elapsed();
+        assert!(latency >= window, "{latency:?}");
+        assert!(latency < window * 10, "{latency:?}");
+
+        // Held events are released when the window changes
+        tx.send(event(5)).await.unwrap();
+        tx.send(event(4)).await.unwrap();
+        while metrics.buffered.get() < 2 {
+            tokio::time::sleep(Duration::from_millis(1)).await;
+        }
+        config.send_replace(Duration::from_secs(60));
+        let first = timeout(Duration::from_secs(10), output.recv())
+            .await
+            .unwrap();
+        assert_eq!(first.unwrap().get_timestamp(), 4);
+        assert_eq!(output.recv().await.unwrap().get_timestamp(), 5);
+
+        // And right away on shutdown
+        tx.send(event(7)).await.unwrap();
+        tx.send(event(6)).await.unwrap();
+        drop(tx);
+        let rest = timeout(Duration::from_secs(10), async {
+            let mut rest = Vec::new();
+            while let Some(event) = output.recv().await {
+                rest.push(event);
+            }
+            rest
+        })
+        .await
+        .unwrap();
+        assert_eq!(timestamps(rest), [6, 7]);
+        tasks.join_next().await.unwrap().unwrap().unwrap();
+    }
+}
@@ -1,4 +1,7 @@
-use std::path::Path;
+use std::{
+    path::Path,
+    time::{Duration, Instant},
+};
 
 use anyhow::Context;
 use log::{info, warn};
@@ -6,22 +9,50 @@ use tokio::{
     io::{AsyncBufReadExt, BufReader},
     sync::{mpsc, watch},
     task::JoinSet,
+    time::sleep_until,
 };
 
-use crate::event::Event;
+use crate::{
+    config::ReplayOptions,
+    event::{self, Event},
+    events,
+};
+
+/// Reproduces the original time between replayed events.
+///
+/// The first event is sent right away, every other event is delayed
+/// by the time elapsed since the first one when it was recorded.
+/// Events recorded out of order are sent right away.
+#[derive(Debug, Default)]
+struct Pacer {
+    origin: Option<(u64, Instant)>,
+}
+
+impl Pacer {
+    /// Get the instant at which an event with the given timestamp
+    /// needs to be sent.
+    fn deadline(&mut self, timestamp: u64, now: Instant) -> Instant {
+        let (first, start) = *self.origin.get_or_insert((timestamp, now));
+        start + Duration::from_nanos(timestamp.saturating_sub(first))
+    }
+}
 
 pub fn start(
     task_set: &mut JoinSet<anyhow::Result<()>>,
     path: &Path,
-    running: watch::Receiver<bool>,
+    options: &ReplayOptions,
+    mut running: watch::Receiver<bool>,
 ) -> anyhow::Result<mpsc::Receiver<Event>> {
     anyhow::ensure!(
         path.exists(),
         "Replay file does not exist: {}",
         path.display()
     );
-    let (tx, rx) = mpsc::channel(100);
+    let (tx, rx) = mpsc::channel(crate::EVENT_CHANNEL_CAPACITY);
     let path = path.to_owned();
+    let mut pacer = options.pace().then(Pacer::default);
+    let rewrite_timestamps = options.rewrite_timestamps();
+    let hostname = options.hostname().map(event::intern);
 
     task_set.spawn(async move {
         let file = tokio::fs::File::open(&path)
@@ -36,20 +67,30 @@ pub fn start(
                 break;
             }
 
-            let value: serde_json::Value = match serde_json::from_str(&line) {
-                Ok(v) => v,
+            let mut event = match events::parse_line(&line) {
+                Ok(event) => event,
                 Err(e) => {
-                    warn!("Failed to parse JSON: {e}");
+                    warn!("Failed to deserialize event: {e}");
                     continue;
                 }
             };
-            match serde_json::from_value::<Event>(value) {
-                Ok(event) => {
-                    if tx.send(event).await.is_err() {
-                        break;
-                    }
+
+            if let Some(pacer) = &mut pacer {
+                let deadline = pacer.deadline(event.get_timestamp(), Instant::now());
+                tokio::select! {
+                    _ = sleep_until(deadline.into()) => {},
+                    _ = running.changed() => break,
                 }
-                Err(e) => warn!("Failed to deserialize event: {e}"),
+            }
+            if rewrite_timestamps {
+                event.set_timestamp(event::now_ns());
+            }
+            if let Some(hostname) = hostname {
+                event.set_hostname(hostname);
+            }
+
+            if tx.send(event).await.is_err() {
+                break;
             }
         }
 
@@ -59,3 +100,118 @@ pub fn start(
 
     Ok(rx)
 }
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use fact_ebpf::{PATH_MAX, event_t};
+
+    use super::*;
+    use crate::event::test_utils::string_to_c_char_array;
+
+    #[test]
+    fn pacer() {
+        let start = Instant::now();
+        let mut pacer = Pacer::default();
+        let sec = 1_000_000_000;
+
+        assert_eq!(pacer.deadline(10 * sec, start), start);
+        assert_eq!(
+            pacer.deadline(12 * sec, start + Duration::from_millis(100)),
+            start + Duration::from_secs(2)
+        );
+        assert_eq!(
+            pacer.deadline(11 * sec, start + Duration::from_secs(3)),
+            start + Duration::from_secs(1)
+        );
+        // Events recorded before the first one are not delayed
+        assert_eq!(pacer.deadline(5 * sec, start), start);
+    }
+
+    fn recorded_events() -> Vec<Event> {
+        ["/etc/passwd", "/etc/shadow", "/etc/hosts"]
+            .into_iter()
+            .enumerate()
+            .map(|(i, path)| {
+                let event = event_t {
+                    timestamp: i as u64 * 1_000_000,
+                    filename: string_to_c_char_array::<{ PATH_MAX as usize }>(path),
+                    ..Default::default()
+                };
+                Event::try_from(&event).expect("Failed to parse event")
+            })
+            .collect()
+    }
+
+    async fn replay(events: &[Event], options: ReplayOptions) -> Vec<Event> {
+        let mut file = tempfile::NamedTempFile::new().expect("Failed to create temp file");
+        for event in events {
+            let line = serde_json::to_string(event).expect("Failed to serialize event");
+            writeln!(file, "{line}").expect("Failed to write event");
+        }
+        writeln!(file, "not json").expect("Failed to write invalid line");
+
+        let mut task_set = JoinSet::new();
+        let (_running_tx, running_rx) = watch::channel(true);
+        let mut rx = start(&mut task_set, file.path(), &options, running_rx)
+            .expect("Failed to start replay");
+
+        let mut replayed = Vec::new();
+        while let Some(event) = rx.recv().await {
+            replayed.push(event);
+        }
+        task_set
+            .join_next()
+            .await
+            .expect("Replay task missing")
+            .expect("Replay task panicked")
+            .expect("Replay task failed");
+        replayed
+    }
+
+    #[tokio::test]
+    async fn round_trip() {
+        let events = recorded_events();
+        let replayed = replay(&events, ReplayOptions::default()).await;
+
+        assert_eq!(replayed, events);
+        for (replayed, event) in replayed.iter().zip(&events) {
+            assert_eq!(replayed.get_timestamp(), event.get_timestamp());
+        }
+    }
+
+    #[tokio::test]
+    async fn rewrite() {
+        let events = recorded_events();
+        let options = ReplayOptions {
+            rewrite_timestamps: Some(true),
+            hostname: Some("replayed.example.com".into()),
+            ..Default::default()
+        };
+        let before = event::now_ns();
+        let replayed = replay(&events, options).await;
+
+        assert_eq!(replayed.len(), events.len());
+        for (mut replayed, mut event) in replayed.into_iter().zip(events) {
+            assert!(replayed.get_timestamp() >= before);
+            event.set_hostname("replayed.example.com");
+            replayed.set_timestamp(event.get_timestamp());
+            assert_eq!(replayed, event);
+        }
+    }
+
+    #[tokio::test]
+    async fn paced() {
+        let events = recorded_events();
+        let options = ReplayOptions {
+            pace: Some(true),
+            ..Default::default()
+        };
+        let start = Instant::now();
+        let replayed = replay(&events, options).await;
+
+        assert_eq!(replayed, events);
+        assert!(start.elapsed() >= Duration::from_millis(2));
+    }
+}
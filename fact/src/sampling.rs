@@ -0,0 +1,234 @@
+//! Deterministic sampling of high-volume event types.
+//!
+//! Some event types, like opens, are only needed for a statistical
+//! picture of file activity. Sampling rules keep one out of every N
+//! events of a type, optionally only under a path prefix.
+//!
+//! Instead of sampling events at random, the decision is taken from a
+//! hash of the process, the file and a coarse time bucket. This way,
+//! the same process repeatedly touching the same file is consistently
+//! kept or dropped within a bucket, so a flow is either fully visible
+//! or not at all, which is easier to reason about when extrapolating.
+
+use std::{cmp::Reverse, os::unix::ffi::OsStrExt};
+
+use fact_ebpf::inode_key_t;
+
+use crate::{config::SamplingRule, event::Event};
+
+/// Size of the time buckets used for sampling decisions.
+const BUCKET_NS: u64 = 10_000_000_000;
+
+/// Sampling rules ready to be matched against events.
+#[derive(Debug, Default)]
+pub struct Sampler {
+    /// Rules with their path prefixes as bytes, longest prefixes first.
+    rules: Vec<(&'static str, Vec<u8>, u32)>,
+}
+
+impl Sampler {
+    pub fn new(rules: &[SamplingRule]) -> Self {
+        let mut rules: Vec<_> = rules
+            .iter()
+            .map(|r| {
+                let prefix = r
+                    .path
+                    .as_ref()
+                    .map(|p| p.as_os_str().as_bytes().to_vec())
+                    .unwrap_or_default();
+                (r.event, prefix, r.rate)
+            })
+            .collect();
+        // The sort is stable, for rules with the same prefix the first
+        // one configured wins.
+        rules.sort_by_key(|(_, prefix, _)| Reverse(prefix.len()));
+        Sampler { rules }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    /// Get the sample rate applying to `event`, `None` if the event is
+    /// not sampled.
+    pub fn rate(&self, event: &Event) -> Option<u32> {
+        self.rate_for(
+            event.event_type(),
+            event.get_filename().as_os_str().as_bytes(),
+        )
+    }
+
+    fn rate_for(&self, event_type: &str, filename: &[u8]) -> Option<u32> {
+        self.rules
+            .iter()
+            .find(|(t, prefix, _)| *t == event_type && filename.starts_with(prefix))
+            .map(|(_, _, rate)| *rate)
+            .filter(|rate| *rate > 1)
+    }
+
+    /// Decide whether `event` is kept.
+    ///
+    /// Kept events that are subject to sampling get the rate they were
+    /// sampled at set on them.
+    pub fn sample(&self, event: &mut Event) -> bool {
+        let Some(rate) = self.rate(event) else {
+            return true;
+        };
+        if !keep(
+            event.get_pid(),
+            event.get_inode(),
+            event.get_timestamp(),
+            rate,
+        ) {
+            return false;
+        }
+        event.set_sample_rate(rate);
+        true
+    }
+}
+
+/// Deterministically decide if an event is kept with a 1/`rate`
+/// probability.
+pub fn keep(pid: u32, inode: &inode_key_t, timestamp: u64, rate: u32) -> bool {
+    let bucket = timestamp / BUCKET_NS;
+    let mut h = mix(u64::from(pid));
+    h = mix(h ^ inode.inode);
+    h = mix(h ^ inode.dev);
+    h = mix(h ^ bucket);
+    h.is_multiple_of(u64::from(rate))
+}
+
+/// Finalizer of the splitmix64 generator, spreads the bits of the input
+/// evenly across the output.
+fn mix(mut x: u64) -> u64 {
+    x = x.wrapping_add(0x9e3779b97f4a7c15);
+    x = (x ^ (x >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    x = (x ^ (x >> 27)).wrapping_mul(0x94d049bb133111eb);
+    x ^ (x >> 31)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::*;
+
+    fn inode(inode: u64) -> inode_key_t {
+        inode_key_t { inode, dev: 42 }
+    }
+
+    fn rule(event: &'static str, path: Option<&str>, rate: u32) -> SamplingRule {
+        SamplingRule {
+            event,
+            path: path.map(PathBuf::from),
+            rate,
+        }
+    }
+
+    #[test]
+    fn deterministic() {
+        for i in 0..1000 {
+            let expected = keep(i, &inode(u64::from(i) * 7), 0, 10);
+            // Same flow in the same bucket, same decision
+            for ts in (0..BUCKET_NS).step_by(BUCKET_NS as usize / 10) {
+                assert_eq!(keep(i, &inode(u64::from(i) * 7), ts, 10), expected);
+            }
+        }
+    }
+
+    #[test]
+    fn rate() {
+        for rate in [2, 10, 100] {
+            let mut kept: u32 = 0;
+            let n = 100_000;
+            for i in 0..n {
+                if keep(1000 + i, &inode(u64::from(i)), 0, rate) {
+                    kept += 1;
+                }
+            }
+            let expected = n / rate;
+            let tolerance = expected / 10;
+            assert!(
+                kept.abs_diff(expected) <= tolerance,
+                "rate 1/{rate}: kept {kept}, expected around {expected}"
+            );
+        }
+
+        // A rate of 1 keeps everything
+        assert!((0..1000).all(|i| keep(i, &inode(1), 0, 1)));
+    }
+
+    #[test]
+    fn buckets() {
+        // Decisions for a flow change from one bucket to the next
+        let kept = (0..1000)
+            .filter(|b| keep(1, &inode(1), b * BUCKET_NS, 2))
+            .count();
+        assert!((400..600).contains(&kept), "{kept}");
+    }
+
+    #[test]
+    fn rules() {
+        let sampler = Sampler::new(&[
+            rule("open", None, 100),
+            rule("open", Some("/var/log"), 1000),
+            rule("open", Some("/etc"), 1),
+            rule("unlink", Some("/tmp"), 10),
+        ]);
+
+        let tests = [
+            ("open", "/usr/bin/ls", Some(100)),
+            ("open", "/var/log/syslog", Some(1000)),
+            // A rate of 1 disables sampling for the prefix
+            ("open", "/etc/passwd", None),
+            ("unlink", "/tmp/file", Some(10)),
+            ("unlink", "/var/log/syslog", None),
+            ("creation", "/tmp/file", None),
+        ];
+        for (event_type, path, expected) in tests {
+            assert_eq!(
+                sampler.rate_for(event_type, path.as_bytes()),
+                expected,
+                "{event_type} {path}"
+            );
+        }
+    }
+
+    #[test]
+    fn sample_events() {
+        let dir = tempfile::tempdir().expect("Failed to create directory");
+        let path = dir.path().join("file");
+        std::fs::write(&path, "").unwrap();
+        let metadata = path.metadata().unwrap();
+
+        let event = Event::inventory(&path, &metadata);
+        let sampler = Sampler::new(&[rule("inventory", None, 2)]);
+        let mut kept = 0;
+        for bucket in 0..20 {
+            let mut sampled = event.clone();
+            sampled.set_timestamp(bucket * BUCKET_NS);
+            let expected = keep(
+                sampled.get_pid(),
+                sampled.get_inode(),
+                sampled.get_timestamp(),
+                2,
+            );
+            assert_eq!(sampler.sample(&mut sampled), expected);
+            if expected {
+                kept += 1;
+                let sampled = serde_json::to_value(sampled).unwrap();
+                assert_eq!(sampled["sample_rate"], 2);
+            }
+        }
+        assert!(kept > 0 && kept < 20, "{kept}");
+
+        // Events not matching any rule are kept untouched
+        let sampler = Sampler::new(&[rule("open", None, 1000)]);
+        let mut unsampled = event.clone();
+        assert!(sampler.sample(&mut unsampled));
+        let event = serde_json::to_value(unsampled).unwrap();
+        assert!(event.get("sample_rate").is_none());
+
+        assert!(Sampler::default().is_empty());
+    }
+}
@@ -0,0 +1,251 @@
+//! Sources of the secrets used to connect to the gRPC server.
+//!
+//! `grpc.certs`, `grpc.key_passphrase_file` and `grpc.token_file` are
+//! usually paths on disk, they also accept:
+//! - `fd:<n>`, a file descriptor inherited from the parent process. It
+//!   is a directory for `grpc.certs` and a file, like a memfd, for the
+//!   others.
+//! - A plain name, like `fact-certs`, looked up in the directory set in
+//!   `$CREDENTIALS_DIRECTORY`, where systemd places the credentials of
+//!   a service. Without the variable, names are relative paths.
+//!
+//! Inherited descriptors are read through `/proc/self/fd`, which opens
+//! the file again from its start instead of sharing the offset of the
+//! descriptor, so they can be read on every reconnection.
+//!
+//! The contents are held in [`Secret`], which is never printed and is
+//! overwritten with zeros when dropped.
+
+use std::{
+    env, fmt,
+    os::fd::RawFd,
+    path::{Component, Path, PathBuf},
+    ptr,
+    sync::atomic::{Ordering, compiler_fence},
+};
+
+/// Environment variable systemd sets to the directory holding the
+/// credentials of a service.
+pub const CREDENTIALS_DIRECTORY: &str = "CREDENTIALS_DIRECTORY";
+
+const FD_PREFIX: &str = "fd:";
+
+#[derive(thiserror::Error, Debug, PartialEq, Eq)]
+pub enum SourceError {
+    #[error("invalid file descriptor {0:?}, expected fd:<n> with n a non-negative number")]
+    InvalidFd(String),
+}
+
+/// Where a secret is read from.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Source {
+    /// A path on disk.
+    Path(PathBuf),
+    /// A file descriptor inherited from the parent process.
+    Fd(RawFd),
+    /// A credential in the directory set by systemd.
+    Credential { dir: PathBuf, name: String },
+}
+
+impl Source {
+    /// Resolve a configured value, looking up plain names in
+    /// `$CREDENTIALS_DIRECTORY` if it is set.
+    pub fn new(value: &Path) -> Result<Self, SourceError> {
+        let credentials = env::var_os(CREDENTIALS_DIRECTORY).map(PathBuf::from);
+        Source::resolve(value, credentials.as_deref())
+    }
+
+    fn resolve(value: &Path, credentials: Option<&Path>) -> Result<Self, SourceError> {
+        if let Some(fd) = value.to_str().and_then(|v| v.strip_prefix(FD_PREFIX)) {
+            return match fd.parse::<RawFd>() {
+                Ok(fd) if fd >= 0 => Ok(Source::Fd(fd)),
+                _ => Err(SourceError::InvalidFd(value.display().to_string())),
+            };
+        }
+
+        let mut components = value.components();
+        if let Some(dir) = credentials
+            && let (Some(Component::Normal(name)), None) = (components.next(), components.next())
+            && let Some(name) = name.to_str()
+        {
+            return Ok(Source::Credential {
+                dir: dir.to_owned(),
+                name: name.to_owned(),
+            });
+        }
+        Ok(Source::Path(value.to_owned()))
+    }
+
+    /// Path the secret is read from.
+    pub fn path(&self) -> PathBuf {
+        match self {
+            Source::Path(path) => path.clone(),
+            Source::Fd(fd) => PathBuf::from(format!("/proc/self/fd/{fd}")),
+            Source::Credential { dir, name } => dir.join(name),
+        }
+    }
+
+    /// Read the whole secret, for sources that are files.
+    pub async fn read(&self) -> std::io::Result<Secret> {
+        read(&self.path()).await
+    }
+}
+
+impl fmt::Display for Source {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Source::Path(path) => write!(f, "{}", path.display()),
+            Source::Fd(fd) => write!(f, "{FD_PREFIX}{fd}"),
+            Source::Credential { dir, name } => {
+                write!(f, "credential {name} in {}", dir.display())
+            }
+        }
+    }
+}
+
+/// Read the file in `path` into a [`Secret`].
+pub async fn read(path: &Path) -> std::io::Result<Secret> {
+    tokio::fs::read(path).await.map(Secret::from)
+}
+
+/// The contents of a secret, overwritten with zeros when dropped.
+///
+/// Debug output never includes the contents.
+pub struct Secret(Vec<u8>);
+
+impl Secret {
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.0
+    }
+
+    /// Remove a single trailing newline, which editors add to files
+    /// and is not part of passphrases.
+    pub fn trim_newline(&mut self) {
+        if self.0.ends_with(b"\n") {
+            self.0.pop();
+            if self.0.ends_with(b"\r") {
+                self.0.pop();
+            }
+        }
+    }
+}
+
+impl From<Vec<u8>> for Secret {
+    fn from(value: Vec<u8>) -> Self {
+        Secret(value)
+    }
+}
+
+impl fmt::Debug for Secret {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Secret(..)")
+    }
+}
+
+impl Drop for Secret {
+    fn drop(&mut self) {
+        // Bytes removed with trim_newline are past the length, the
+        // whole allocation is cleared.
+        self.0.clear();
+        for byte in self.0.spare_capacity_mut() {
+            // SAFETY: the pointer comes from a valid reference into
+            // the allocation of the vector.
+            unsafe { ptr::write_volatile(byte.as_mut_ptr(), 0) };
+        }
+        compiler_fence(Ordering::SeqCst);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs::File, os::fd::AsRawFd};
+
+    use super::*;
+
+    #[test]
+    fn resolve() {
+        let credentials = Path::new("/run/credentials/fact.service");
+        let tests = [
+            (
+                "/etc/certs",
+                None,
+                Source::Path(PathBuf::from("/etc/certs")),
+            ),
+            (
+                "/etc/certs",
+                Some(credentials),
+                Source::Path(PathBuf::from("/etc/certs")),
+            ),
+            ("certs", None, Source::Path(PathBuf::from("certs"))),
+            (
+                "certs",
+                Some(credentials),
+                Source::Credential {
+                    dir: credentials.to_owned(),
+                    name: String::from("certs"),
+                },
+            ),
+            // Only single names are credentials
+            (
+                "secrets/certs",
+                Some(credentials),
+                Source::Path(PathBuf::from("secrets/certs")),
+            ),
+            ("fd:3", None, Source::Fd(3)),
+            ("fd:0", Some(credentials), Source::Fd(0)),
+        ];
+        for (value, credentials, expected) in tests {
+            assert_eq!(
+                Source::resolve(Path::new(value), credentials),
+                Ok(expected),
+                "{value}"
+            );
+        }
+
+        for value in ["fd:", "fd:-1", "fd:three", "fd: 3"] {
+            assert_eq!(
+                Source::resolve(Path::new(value), None),
+                Err(SourceError::InvalidFd(value.to_owned())),
+            );
+        }
+    }
+
+    #[test]
+    fn path() {
+        assert_eq!(Source::Fd(7).path(), Path::new("/proc/self/fd/7"));
+        let credential = Source::Credential {
+            dir: PathBuf::from("/run/credentials/fact.service"),
+            name: String::from("token"),
+        };
+        assert_eq!(
+            credential.path(),
+            Path::new("/run/credentials/fact.service/token")
+        );
+        assert_eq!(
+            credential.to_string(),
+            "credential token in /run/credentials/fact.service"
+        );
+        assert_eq!(Source::Fd(7).to_string(), "fd:7");
+    }
+
+    #[tokio::test]
+    async fn read_fd() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("token");
+        std::fs::write(&path, "s3cr3t\n").unwrap();
+        let file = File::open(&path).unwrap();
+        let source = Source::resolve(
+            Path::new(&format!("fd:{}", file.as_raw_fd())),
+            Some(dir.path()),
+        )
+        .unwrap();
+
+        // Every read starts from the beginning of the file
+        for _ in 0..2 {
+            let mut secret = source.read().await.unwrap();
+            secret.trim_newline();
+            assert_eq!(secret.as_bytes(), b"s3cr3t");
+        }
+        assert_eq!(format!("{:?}", source.read().await.unwrap()), "Secret(..)");
+    }
+}
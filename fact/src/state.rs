@@ -0,0 +1,386 @@
+//! Versioned envelope for state kept on disk across restarts.
+//!
+//! Every file fact persists is wrapped in the same envelope, so a file
+//! written by a different fact version is detected before its payload
+//! is interpreted:
+//!
+//! | Field           | Encoding                                 |
+//! |-----------------|------------------------------------------|
+//! | magic           | `FACT`                                   |
+//! | envelope format | u16                                      |
+//! | payload format  | u32                                      |
+//! | writer          | u16 length + version of fact that wrote  |
+//! | payload length  | u64                                      |
+//! | checksum        | u64, FNV-1a of the payload               |
+//! | payload         | payload length bytes                     |
+//!
+//! Integers are little endian.
+//!
+//! State is a cache of things fact can rebuild, so it is never a reason
+//! to fail startup. Files that cannot be read, migrated or understood
+//! are removed and fact starts with fresh state.
+
+use std::{
+    fs::{self, File},
+    io::{self, Write},
+    path::{Path, PathBuf},
+};
+
+use log::{info, warn};
+use thiserror::Error;
+
+use crate::{metrics::EventCounter, version::FACT_VERSION};
+
+const MAGIC: &[u8; 4] = b"FACT";
+
+/// Version of the envelope itself, independent of the payloads.
+const ENVELOPE_FORMAT: u16 = 1;
+
+/// Upgrade a payload to the next format version.
+pub type Migration = fn(Vec<u8>) -> anyhow::Result<Vec<u8>>;
+
+/// A kind of state persisted by fact.
+pub struct StateKind {
+    /// Name used in logs.
+    pub name: &'static str,
+    /// Current version of the payload format, starting at 1.
+    pub version: u32,
+    /// `migrations[n]` upgrades a payload from version `n + 1` to
+    /// `n + 2`. Versions without a migration are reset instead.
+    pub migrations: &'static [Migration],
+}
+
+#[derive(Debug, Error)]
+pub enum StateError {
+    #[error("not a fact state file")]
+    BadMagic,
+    #[error("unsupported envelope format {0}")]
+    UnsupportedEnvelope(u16),
+    #[error("file is truncated")]
+    Truncated,
+    #[error("checksum mismatch")]
+    Checksum,
+    #[error("written by fact {writer} with newer format {version}")]
+    Newer { version: u32, writer: String },
+    #[error("no migration from format {0}")]
+    NoMigration(u32),
+    #[error("migration from format {version} failed: {error:#}")]
+    Migration { version: u32, error: anyhow::Error },
+    #[error("io error: {0}")]
+    Io(#[from] io::Error),
+}
+
+#[derive(Debug, PartialEq)]
+struct Envelope {
+    version: u32,
+    writer: String,
+    payload: Vec<u8>,
+}
+
+impl Envelope {
+    fn encode(&self) -> Vec<u8> {
+        let writer = self.writer.as_bytes();
+        let writer = &writer[..writer.len().min(u16::MAX as usize)];
+        let mut out = Vec::with_capacity(28 + writer.len() + self.payload.len());
+        out.extend_from_slice(MAGIC);
+        out.extend_from_slice(&ENVELOPE_FORMAT.to_le_bytes());
+        out.extend_from_slice(&self.version.to_le_bytes());
+        out.extend_from_slice(&(writer.len() as u16).to_le_bytes());
+        out.extend_from_slice(writer);
+        out.extend_from_slice(&(self.payload.len() as u64).to_le_bytes());
+        out.extend_from_slice(&checksum(&self.payload).to_le_bytes());
+        out.extend_from_slice(&self.payload);
+        out
+    }
+
+    fn decode(data: &[u8]) -> Result<Self, StateError> {
+        let mut reader = Reader(data);
+        if reader.take(MAGIC.len())? != MAGIC {
+            return Err(StateError::BadMagic);
+        }
+        let format = u16::from_le_bytes(reader.array()?);
+        if format != ENVELOPE_FORMAT {
+            return Err(StateError::UnsupportedEnvelope(format));
+        }
+        let version = u32::from_le_bytes(reader.array()?);
+        let writer_len = u16::from_le_bytes(reader.array()?);
+        let writer = String::from_utf8_lossy(reader.take(writer_len.into())?).into_owned();
+        let payload_len = u64::from_le_bytes(reader.array()?);
+        let sum = u64::from_le_bytes(reader.array()?);
+        let payload_len = usize::try_from(payload_len).map_err(|_| StateError::Truncated)?;
+        let payload = reader.take(payload_len)?;
+        if checksum(payload) != sum {
+            return Err(StateError::Checksum);
+        }
+        Ok(Envelope {
+            version,
+            writer,
+            payload: payload.to_vec(),
+        })
+    }
+}
+
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, n: usize) -> Result<&'a [u8], StateError> {
+        if self.0.len() < n {
+            return Err(StateError::Truncated);
+        }
+        let (head, tail) = self.0.split_at(n);
+        self.0 = tail;
+        Ok(head)
+    }
+
+    fn array<const N: usize>(&mut self) -> Result<[u8; N], StateError> {
+        Ok(self.take(N)?.try_into().unwrap())
+    }
+}
+
+/// FNV-1a, enough to catch torn writes and bit rot.
+fn checksum(data: &[u8]) -> u64 {
+    data.iter().fold(0xcbf29ce484222325, |hash, b| {
+        (hash ^ u64::from(*b)).wrapping_mul(0x100000001b3)
+    })
+}
+
+/// Read the payload of `path`, upgrading it to the current format of
+/// `kind` if needed.
+///
+/// Returns `None` if there is no state to start from, either because
+/// the file does not exist or because it had to be reset.
+pub fn open_or_reset(path: &Path, kind: &StateKind, metrics: &EventCounter) -> Option<Vec<u8>> {
+    let data = match fs::read(path) {
+        Ok(data) => data,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return None,
+        Err(e) => {
+            reset(path, kind, &e.into(), metrics);
+            return None;
+        }
+    };
+
+    match open(path, &data, kind) {
+        Ok(payload) => {
+            metrics.added();
+            Some(payload)
+        }
+        Err(e) => {
+            reset(path, kind, &e, metrics);
+            None
+        }
+    }
+}
+
+fn open(path: &Path, data: &[u8], kind: &StateKind) -> Result<Vec<u8>, StateError> {
+    let Envelope {
+        mut version,
+        writer,
+        mut payload,
+    } = Envelope::decode(data)?;
+    if version > kind.version {
+        return Err(StateError::Newer { version, writer });
+    }
+    if version == kind.version {
+        return Ok(payload);
+    }
+
+    let from = version;
+    while version < kind.version {
+        let migration = version
+            .checked_sub(1)
+            .and_then(|i| kind.migrations.get(i as usize))
+            .ok_or(StateError::NoMigration(version))?;
+        payload = migration(payload).map_err(|error| StateError::Migration { version, error })?;
+        version += 1;
+    }
+    info!(
+        "Migrated {} state in {} from format {from} written by fact {writer} to format {version}",
+        kind.name,
+        path.display()
+    );
+
+    // Persist the result so the migration only happens once, the
+    // migrated payload is still usable if this fails.
+    if let Err(e) = write(path, kind, &payload) {
+        warn!("Failed to save migrated {} state: {e}", kind.name);
+    }
+    Ok(payload)
+}
+
+fn reset(path: &Path, kind: &StateKind, reason: &StateError, metrics: &EventCounter) {
+    warn!(
+        "Discarding {} state in {}: {reason}. Starting with fresh state.",
+        kind.name,
+        path.display()
+    );
+    metrics.reset();
+    if let Err(e) = fs::remove_file(path)
+        && e.kind() != io::ErrorKind::NotFound
+    {
+        warn!("Failed to remove {}: {e}", path.display());
+    }
+}
+
+/// Atomically replace the content of `path` with `payload` in the
+/// current format of `kind`.
+pub fn write(path: &Path, kind: &StateKind, payload: &[u8]) -> io::Result<()> {
+    let envelope = Envelope {
+        version: kind.version,
+        writer: FACT_VERSION.to_string(),
+        payload: payload.to_vec(),
+    };
+
+    let mut tmp = PathBuf::from(path);
+    tmp.as_mut_os_string().push(".tmp");
+    let mut file = File::create(&tmp)?;
+    file.write_all(&envelope.encode())?;
+    file.sync_all()?;
+    fs::rename(&tmp, path)
+}
+
+#[cfg(test)]
+mod tests {
+    use tempfile::TempDir;
+
+    use crate::metrics::Metrics;
+
+    use super::*;
+
+    const MIGRATIONS: &[Migration] = &[
+        |mut p| {
+            p.extend_from_slice(b"-v2");
+            Ok(p)
+        },
+        |mut p| {
+            p.extend_from_slice(b"-v3");
+            Ok(p)
+        },
+    ];
+
+    const KIND: StateKind = StateKind {
+        name: "test",
+        version: 3,
+        migrations: MIGRATIONS,
+    };
+
+    fn write_raw(path: &Path, version: u32, payload: &[u8]) {
+        let envelope = Envelope {
+            version,
+            writer: "0.0.1".into(),
+            payload: payload.to_vec(),
+        };
+        fs::write(path, envelope.encode()).unwrap();
+    }
+
+    fn setup() -> (TempDir, PathBuf, EventCounter) {
+        let dir = TempDir::new().unwrap();
+        let path = dir.path().join("state");
+        (dir, path, Metrics::new().state)
+    }
+
+    #[test]
+    fn current() {
+        let (_dir, path, metrics) = setup();
+        assert_eq!(open_or_reset(&path, &KIND, &metrics), None);
+        assert_eq!(metrics.reset_count(), 0);
+
+        write(&path, &KIND, b"payload").unwrap();
+        assert_eq!(
+            open_or_reset(&path, &KIND, &metrics).as_deref(),
+            Some(&b"payload"[..])
+        );
+        assert_eq!(metrics.added_count(), 1);
+
+        let envelope = Envelope::decode(&fs::read(&path).unwrap()).unwrap();
+        assert_eq!(envelope.writer, FACT_VERSION);
+    }
+
+    #[test]
+    fn migrated() {
+        let (_dir, path, metrics) = setup();
+        write_raw(&path, 1, b"v1");
+        assert_eq!(
+            open_or_reset(&path, &KIND, &metrics).as_deref(),
+            Some(&b"v1-v2-v3"[..])
+        );
+
+        // The migrated state is saved in the current format
+        let envelope = Envelope::decode(&fs::read(&path).unwrap()).unwrap();
+        assert_eq!(envelope.version, 3);
+        assert_eq!(envelope.payload, b"v1-v2-v3");
+
+        write_raw(&path, 2, b"v2");
+        assert_eq!(
+            open_or_reset(&path, &KIND, &metrics).as_deref(),
+            Some(&b"v2-v3"[..])
+        );
+        assert_eq!(metrics.reset_count(), 0);
+    }
+
+    #[test]
+    fn reset() {
+        const FAILING: &[Migration] = &[|_| anyhow::bail!("bad payload")];
+        let failing = StateKind {
+            name: "failing",
+            version: 2,
+            migrations: FAILING,
+        };
+        let unmigrated = StateKind {
+            name: "unmigrated",
+            version: 2,
+            migrations: &[],
+        };
+
+        let (_dir, path, metrics) = setup();
+        let mut valid = Envelope {
+            version: 3,
+            writer: "0.0.1".into(),
+            payload: b"payload".to_vec(),
+        }
+        .encode();
+        let len = valid.len();
+        let mut bad_magic = valid.clone();
+        bad_magic[0] = b'X';
+        let mut bad_format = valid.clone();
+        bad_format[4] = 2;
+
+        let tests: [(&str, Vec<u8>, &StateKind); 8] = [
+            ("newer", valid.clone(), &unmigrated),
+            ("bad magic", bad_magic, &KIND),
+            ("bad envelope format", bad_format, &KIND),
+            ("truncated", valid[..len - 1].to_vec(), &KIND),
+            ("empty", Vec::new(), &KIND),
+            (
+                "no migration",
+                valid.clone(),
+                &StateKind {
+                    version: 4,
+                    ..unmigrated
+                },
+            ),
+            (
+                "failed migration",
+                {
+                    let mut v = valid.clone();
+                    v[6] = 1;
+                    v
+                },
+                &failing,
+            ),
+            (
+                "checksum",
+                {
+                    valid[len - 1] ^= 0xff;
+                    valid
+                },
+                &KIND,
+            ),
+        ];
+        for (i, (name, data, kind)) in tests.into_iter().enumerate() {
+            fs::write(&path, data).unwrap();
+            assert_eq!(open_or_reset(&path, kind, &metrics), None, "{name}");
+            assert!(!path.exists(), "{name}");
+            assert_eq!(metrics.reset_count(), i as u64 + 1, "{name}");
+        }
+    }
+}
@@ -0,0 +1,364 @@
+//! Periodic summaries of the activity on the monitored paths.
+//!
+//! Busy hosts report more events than some consumers want to store.
+//! With `summary_interval` set, events are counted by the monitored
+//! path they matched, their type and whether they came from a
+//! container, and a summary event with the counts is sent through the
+//! outputs every interval. With `summary_only`, the summaries replace
+//! the events they count.
+//!
+//! Events are matched against the monitored paths the same way labels
+//! are resolved, with a longest prefix match, and follow the paths
+//! configured at the time they are counted.
+
+use std::{
+    cmp::Reverse, collections::BTreeMap, os::unix::ffi::OsStrExt, path::PathBuf, time::Duration,
+};
+
+use fact_ebpf::glob_prefix;
+use log::{debug, info};
+use tokio::{
+    sync::{mpsc, watch},
+    task::JoinSet,
+    time::{Instant, Interval, MissedTickBehavior, interval_at},
+};
+
+use crate::{
+    config::SummaryConfig,
+    event::{Event, SummaryEntry, now_ns},
+};
+
+/// Key of the counts, the monitored path, event type and whether the
+/// event came from a container.
+type Key = (String, &'static str, bool);
+
+/// Counts of the events seen since the last summary.
+#[derive(Debug)]
+pub struct Aggregator {
+    /// Prefixes of the monitored paths with the path as configured,
+    /// longest prefixes first.
+    prefixes: Vec<(Vec<u8>, String)>,
+    counts: BTreeMap<Key, u64>,
+    start: u64,
+}
+
+impl Aggregator {
+    pub fn new(paths: &[PathBuf]) -> Self {
+        let mut aggregator = Aggregator {
+            prefixes: Vec::new(),
+            counts: BTreeMap::new(),
+            start: now_ns(),
+        };
+        aggregator.set_paths(paths);
+        aggregator
+    }
+
+    /// Count the events from now on against `paths`.
+    ///
+    /// Events already counted keep the path they matched.
+    pub fn set_paths(&mut self, paths: &[PathBuf]) {
+        let mut prefixes: Vec<(Vec<u8>, String)> = Vec::new();
+        for path in paths {
+            let name = path.display().to_string();
+            let prefix = glob_prefix(&name).to_vec();
+
+            // Paths with the same prefix are a single entry in the
+            // kernel, the last one configured wins.
+            match prefixes.iter_mut().find(|(p, _)| *p == prefix) {
+                Some((_, n)) => *n = name,
+                None => prefixes.push((prefix, name)),
+            }
+        }
+        prefixes.sort_by_key(|(prefix, _)| Reverse(prefix.len()));
+        self.prefixes = prefixes;
+    }
+
+    pub fn add(&mut self, event: &Event) {
+        let filename = event.get_filename().as_os_str().as_bytes();
+        let path = self
+            .prefixes
+            .iter()
+            .find(|(prefix, _)| filename.starts_with(prefix))
+            .map(|(_, path)| path.clone())
+            .unwrap_or_default();
+        let container = event.get_process().container_id().is_some();
+        *self
+            .counts
+            .entry((path, event.event_type(), container))
+            .or_default() += 1;
+    }
+
+    /// Take the counts into a summary event and start a new period.
+    ///
+    /// Returns `None` if no events were counted.
+    pub fn take(&mut self) -> Option<Event> {
+        let start = std::mem::replace(&mut self.start, now_ns());
+        if self.counts.is_empty() {
+            return None;
+        }
+
+        let entries = std::mem::take(&mut self.counts)
+            .into_iter()
+            .map(|((path, event_type, container), count)| SummaryEntry {
+                path,
+                event_type: event_type.to_owned(),
+                container,
+                count,
+            })
+            .collect();
+        Some(Event::summary(start, entries))
+    }
+}
+
+fn ticker(period: Duration) -> Option<Interval> {
+    if period.is_zero() {
+        return None;
+    }
+    let mut ticker = interval_at(Instant::now() + period, period);
+    ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    Some(ticker)
+}
+
+async fn tick(ticker: &mut Option<Interval>) {
+    match ticker {
+        Some(ticker) => {
+            ticker.tick().await;
+        }
+        None => std::future::pending().await,
+    }
+}
+
+/// Start a task counting the events going through it and sending
+/// summaries of them every `summary_interval`.
+///
+/// Events are forwarded unless `summary_only` is set. The counts left
+/// are sent in a last summary once the input closes.
+pub fn start(
+    task_set: &mut JoinSet<anyhow::Result<()>>,
+    mut rx: mpsc::Receiver<Event>,
+    mut config: watch::Receiver<SummaryConfig>,
+    mut paths: watch::Receiver<Vec<PathBuf>>,
+) -> mpsc::Receiver<Event> {
+    let (tx, output) = mpsc::channel(crate::EVENT_CHANNEL_CAPACITY);
+    let mut summary = *config.borrow_and_update();
+    let mut aggregator = Aggregator::new(&paths.borrow_and_update());
+    task_set.spawn(async move {
+        debug!("Starting event summaries...");
+        let mut ticker = ticker(summary.interval);
+        loop {
+            tokio::select! {
+                event = rx.recv() => {
+                    let Some(event) = event else {
+                        info!("Stopping event summaries...");
+                        if summary.enabled()
+                            && let Some(event) = aggregator.take()
+                        {
+                            let _ = tx.send(event).await;
+                        }
+                        return Ok(());
+                    };
+                    if summary.enabled() {
+                        aggregator.add(&event);
+                        if summary.only {
+                            continue;
+                        }
+                    }
+                    if tx.send(event).await.is_err() {
+                        info!("No event summaries consumers left, stopping...");
+                        return Ok(());
+                    }
+                }
+                _ = tick(&mut ticker) => {
+                    if let Some(event) = aggregator.take()
+                        && tx.send(event).await.is_err()
+                    {
+                        info!("No event summaries consumers left, stopping...");
+                        return Ok(());
+                    }
+                }
+                Ok(_) = config.changed() => {
+                    let new = *config.borrow_and_update();
+                    if new.interval != summary.interval {
+                        ticker = self::ticker(new.interval);
+                    }
+                    if !summary.enabled() {
+                        // Start counting from now on
+                        aggregator.take();
+                    } else if !new.enabled()
+                        && let Some(event) = aggregator.take()
+                        && tx.send(event).await.is_err()
+                    {
+                        info!("No event summaries consumers left, stopping...");
+                        return Ok(());
+                    }
+                    summary = new;
+                }
+                Ok(_) = paths.changed() => {
+                    aggregator.set_paths(&paths.borrow_and_update());
+                }
+            }
+        }
+    });
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use fact_ebpf::{PATH_MAX, event_t, file_activity_type_t};
+    use tokio::time::timeout;
+
+    use super::*;
+    use crate::event::{FileData, test_utils::string_to_c_char_array};
+
+    const CONTAINER_CGROUP: &str = "/kubepods/burstable/pod7cd3dba6-e475-11e9-8f99-42010a8a00d2/2bc55a8cae1704a733ba5d785d146bbed9610483380507cbf00c96b32bb637e1";
+
+    fn event(path: &str, type_: file_activity_type_t, container: bool) -> Event {
+        let mut event = event_t {
+            filename: string_to_c_char_array::<{ PATH_MAX as usize }>(path),
+            type_,
+            ..Default::default()
+        };
+        if container {
+            event.process.memory_cgroup =
+                string_to_c_char_array::<{ PATH_MAX as usize }>(CONTAINER_CGROUP);
+        }
+        Event::try_from(&event).unwrap()
+    }
+
+    fn open(path: &str) -> Event {
+        event(path, file_activity_type_t::FILE_ACTIVITY_OPEN, false)
+    }
+
+    fn paths(paths: &[&str]) -> Vec<PathBuf> {
+        paths.iter().map(PathBuf::from).collect()
+    }
+
+    fn entry(path: &str, event_type: &str, container: bool, count: u64) -> SummaryEntry {
+        SummaryEntry {
+            path: path.to_owned(),
+            event_type: event_type.to_owned(),
+            container,
+            count,
+        }
+    }
+
+    fn entries(event: &Event) -> &[SummaryEntry] {
+        let FileData::Summary(data) = event.get_file() else {
+            panic!("Not a summary: {event:?}");
+        };
+        data.entries()
+    }
+
+    #[test]
+    fn aggregation() {
+        let mut aggregator = Aggregator::new(&paths(&["/etc/**/*", "/etc/ssh/**/*", "/usr/bin"]));
+        assert!(aggregator.take().is_none());
+
+        aggregator.add(&open("/etc/passwd"));
+        aggregator.add(&open("/etc/hosts"));
+        aggregator.add(&open("/etc/ssh/sshd_config"));
+        aggregator.add(&open("/usr/bin/ls"));
+        aggregator.add(&open("/tmp/file"));
+        aggregator.add(&event(
+            "/etc/passwd",
+            file_activity_type_t::FILE_ACTIVITY_CREATION,
+            false,
+        ));
+        aggregator.add(&event(
+            "/etc/passwd",
+            file_activity_type_t::FILE_ACTIVITY_OPEN,
+            true,
+        ));
+
+        let summary = aggregator.take().unwrap();
+        assert!(summary.is_summary());
+        assert_eq!(summary.event_type(), "summary");
+        assert_eq!(
+            entries(&summary),
+            [
+                entry("", "open", false, 1),
+                entry("/etc/**/*", "creation", false, 1),
+                entry("/etc/**/*", "open", false, 2),
+                entry("/etc/**/*", "open", true, 1),
+                entry("/etc/ssh/**/*", "open", false, 1),
+                entry("/usr/bin", "open", false, 1),
+            ]
+        );
+
+        // Counts start over after a summary
+        assert!(aggregator.take().is_none());
+        aggregator.add(&open("/etc/passwd"));
+        assert_eq!(
+            entries(&aggregator.take().unwrap()),
+            [entry("/etc/**/*", "open", false, 1)]
+        );
+    }
+
+    #[test]
+    fn paths_reload() {
+        let mut aggregator = Aggregator::new(&paths(&["/etc"]));
+        aggregator.add(&open("/etc/ssh/sshd_config"));
+        aggregator.set_paths(&paths(&["/etc", "/etc/ssh"]));
+        aggregator.add(&open("/etc/ssh/sshd_config"));
+
+        assert_eq!(
+            entries(&aggregator.take().unwrap()),
+            [
+                entry("/etc", "open", false, 1),
+                entry("/etc/ssh", "open", false, 1),
+            ]
+        );
+    }
+
+    async fn recv(rx: &mut mpsc::Receiver<Event>) -> Event {
+        timeout(Duration::from_secs(10), rx.recv())
+            .await
+            .expect("Timed out waiting for an event")
+            .expect("Channel closed")
+    }
+
+    #[tokio::test]
+    async fn summaries() {
+        let config = SummaryConfig {
+            interval: Duration::from_millis(50),
+            only: false,
+        };
+        let (_config_tx, config) = watch::channel(config);
+        let (_paths_tx, paths) = watch::channel(paths(&["/etc"]));
+        let (tx, rx) = mpsc::channel(10);
+        let mut task_set = JoinSet::new();
+        let mut rx = start(&mut task_set, rx, config, paths);
+
+        // Events are forwarded and counted
+        tx.send(open("/etc/passwd")).await.unwrap();
+        assert_eq!(recv(&mut rx).await.get_filename(), "/etc/passwd");
+        let summary = recv(&mut rx).await;
+        assert_eq!(entries(&summary), [entry("/etc", "open", false, 1)]);
+
+        drop(tx);
+        assert!(rx.recv().await.is_none());
+        task_set.join_next().await.unwrap().unwrap().unwrap();
+    }
+
+    #[tokio::test]
+    async fn summary_only() {
+        let config = SummaryConfig {
+            interval: Duration::from_secs(3600),
+            only: true,
+        };
+        let (_config_tx, config) = watch::channel(config);
+        let (_paths_tx, paths) = watch::channel(paths(&["/etc"]));
+        let (tx, rx) = mpsc::channel(10);
+        let mut task_set = JoinSet::new();
+        let mut rx = start(&mut task_set, rx, config, paths);
+
+        // Only the counts are sent, once the input closes
+        tx.send(open("/etc/passwd")).await.unwrap();
+        tx.send(open("/etc/hosts")).await.unwrap();
+        drop(tx);
+        let summary = recv(&mut rx).await;
+        assert_eq!(entries(&summary), [entry("/etc", "open", false, 2)]);
+        assert!(rx.recv().await.is_none());
+        task_set.join_next().await.unwrap().unwrap().unwrap();
+    }
+}
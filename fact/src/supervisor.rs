@@ -0,0 +1,278 @@
+//! Supervision of the tasks running alongside the event pipeline.
+//!
+//! The stages of the pipeline own the channels connecting them and
+//! cannot be restarted on their own, any of them exiting stops fact.
+//! Helper tasks, like the endpoints server or the config reloader, are
+//! only expected to exit once fact is stopping. The supervisor logs the
+//! ones exiting early, restarts those that can be restarted and reports
+//! a failure when a task cannot be restarted or keeps failing.
+
+use std::{
+    collections::HashMap,
+    time::{Duration, Instant},
+};
+
+use anyhow::anyhow;
+use log::{info, warn};
+use tokio::{
+    task::{self, AbortHandle, JoinError, JoinHandle, JoinSet},
+    time::timeout,
+};
+
+/// Period over which failures of a restarted task are counted.
+const RESTART_WINDOW: Duration = Duration::from_secs(60);
+
+type Start = Box<dyn FnMut() -> JoinHandle<()> + Send>;
+
+struct Restart {
+    start: Start,
+    /// Restarts allowed within the window before giving up.
+    max: usize,
+    failures: Vec<Instant>,
+}
+
+struct Supervised {
+    name: &'static str,
+    /// Handle on the task itself, rather than the one awaiting it.
+    inner: AbortHandle,
+    restart: Option<Restart>,
+}
+
+pub struct Supervisor {
+    tasks: JoinSet<Result<(), JoinError>>,
+    supervised: HashMap<task::Id, Supervised>,
+    window: Duration,
+}
+
+impl Supervisor {
+    pub fn new() -> Self {
+        Supervisor {
+            tasks: JoinSet::new(),
+            supervised: HashMap::new(),
+            window: RESTART_WINDOW,
+        }
+    }
+
+    /// Supervise a task that is not restarted, its exit is reported as
+    /// a failure.
+    pub fn watch(&mut self, name: &'static str, handle: JoinHandle<()>) {
+        self.add(name, handle, None);
+    }
+
+    /// Supervise a task started by calling `start`, which is called
+    /// again every time the task exits, up to `max` times a minute.
+    pub fn restart(
+        &mut self,
+        name: &'static str,
+        max: usize,
+        mut start: impl FnMut() -> JoinHandle<()> + Send + 'static,
+    ) {
+        let handle = start();
+        let restart = Restart {
+            start: Box::new(start),
+            max,
+            failures: Vec::new(),
+        };
+        self.add(name, handle, Some(restart));
+    }
+
+    fn add(&mut self, name: &'static str, handle: JoinHandle<()>, restart: Option<Restart>) {
+        let inner = handle.abort_handle();
+        let id = self.tasks.spawn(handle).id();
+        self.supervised.insert(
+            id,
+            Supervised {
+                name,
+                inner,
+                restart,
+            },
+        );
+    }
+
+    /// Wait for a task to exit for good, restarting tasks in the
+    /// meantime.
+    ///
+    /// Never returns if no task is supervised. This method is cancel
+    /// safe, it can be used in a `tokio::select!` loop.
+    pub async fn failed(&mut self) -> anyhow::Error {
+        loop {
+            let Some(res) = self.tasks.join_next_with_id().await else {
+                return std::future::pending().await;
+            };
+            let (id, res) = match res {
+                Ok((id, res)) => (id, res),
+                Err(e) => (e.id(), Err(e)),
+            };
+            let Some(mut task) = self.supervised.remove(&id) else {
+                continue;
+            };
+            match res {
+                Ok(()) => warn!("{} task exited unexpectedly", task.name),
+                Err(e) => warn!("{} task failed: {e}", task.name),
+            }
+
+            let Some(restart) = task.restart.as_mut() else {
+                return anyhow!("{} task stopped", task.name);
+            };
+            let now = Instant::now();
+            restart
+                .failures
+                .retain(|failure| now.duration_since(*failure) < self.window);
+            restart.failures.push(now);
+            if restart.failures.len() > restart.max {
+                return anyhow!(
+                    "{} task failed {} times in {:?}",
+                    task.name,
+                    restart.failures.len(),
+                    self.window
+                );
+            }
+
+            info!("Restarting {} task...", task.name);
+            let handle = (restart.start)();
+            self.add(task.name, handle, task.restart);
+        }
+    }
+
+    /// Wait for all tasks to exit once they have been told to stop.
+    ///
+    /// Tasks still running after `limit` are aborted.
+    pub async fn shutdown(mut self, limit: Duration) {
+        let res = timeout(limit, async {
+            while let Some(res) = self.tasks.join_next_with_id().await {
+                let (id, res) = match res {
+                    Ok((id, res)) => (id, res),
+                    Err(e) => (e.id(), Err(e)),
+                };
+                if let Some(task) = self.supervised.remove(&id)
+                    && let Err(e) = res
+                {
+                    warn!("{} task failed while stopping: {e}", task.name);
+                }
+            }
+        })
+        .await;
+
+        if res.is_err() {
+            for task in self.supervised.values() {
+                warn!("{} task did not stop in time, aborting it", task.name);
+                task.inner.abort();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{
+        Arc,
+        atomic::{AtomicUsize, Ordering},
+    };
+
+    use tokio::sync::{oneshot, watch};
+
+    use super::*;
+
+    /// Start function for a task panicking the first `panics` times it
+    /// is started, it runs until stopped afterwards.
+    fn flaky(
+        panics: usize,
+        running: watch::Receiver<bool>,
+    ) -> (Arc<AtomicUsize>, impl FnMut() -> JoinHandle<()> + Send) {
+        let starts = Arc::new(AtomicUsize::new(0));
+        let counter = starts.clone();
+        let start = move || {
+            let start = counter.fetch_add(1, Ordering::SeqCst);
+            let mut running = running.clone();
+            tokio::spawn(async move {
+                if start < panics {
+                    panic!("Failure {start}");
+                }
+                let _ = running.wait_for(|r| !*r).await;
+            })
+        };
+        (starts, start)
+    }
+
+    #[tokio::test]
+    async fn restart() {
+        let (_running_tx, running) = watch::channel(true);
+        let (starts, start) = flaky(2, running);
+        let mut supervisor = Supervisor::new();
+        supervisor.restart("flaky", 2, start);
+
+        // Both panics are absorbed by restarts
+        let res = timeout(Duration::from_millis(200), supervisor.failed()).await;
+        assert!(res.is_err(), "Unexpected failure: {:?}", res.unwrap());
+        assert_eq!(starts.load(Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test]
+    async fn escalate() {
+        let (_running_tx, running) = watch::channel(true);
+        let (starts, start) = flaky(usize::MAX, running);
+        let mut supervisor = Supervisor::new();
+        supervisor.restart("flaky", 2, start);
+
+        let e = timeout(Duration::from_secs(1), supervisor.failed())
+            .await
+            .expect("Repeated failures were not reported");
+        assert_eq!(e.to_string(), "flaky task failed 3 times in 60s");
+        assert_eq!(starts.load(Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test]
+    async fn window() {
+        // Failures outside of the window are forgotten
+        let (_running_tx, running) = watch::channel(true);
+        let (starts, start) = flaky(5, running);
+        let mut supervisor = Supervisor::new();
+        supervisor.window = Duration::ZERO;
+        supervisor.restart("flaky", 1, start);
+
+        let res = timeout(Duration::from_millis(200), supervisor.failed()).await;
+        assert!(res.is_err(), "Unexpected failure: {:?}", res.unwrap());
+        assert_eq!(starts.load(Ordering::SeqCst), 6);
+    }
+
+    #[tokio::test]
+    async fn watch() {
+        let mut supervisor = Supervisor::new();
+        supervisor.watch("idle", tokio::spawn(std::future::pending()));
+        supervisor.watch("panicking", tokio::spawn(async { panic!("Failure") }));
+
+        let e = timeout(Duration::from_secs(1), supervisor.failed())
+            .await
+            .expect("Exit was not reported");
+        assert_eq!(e.to_string(), "panicking task stopped");
+    }
+
+    #[tokio::test]
+    async fn shutdown() {
+        let (running_tx, running) = watch::channel(true);
+        let (starts, start) = flaky(0, running);
+        let (stuck_tx, stuck_rx) = oneshot::channel::<()>();
+        let mut supervisor = Supervisor::new();
+        supervisor.restart("stopping", 2, start);
+        supervisor.watch(
+            "stuck",
+            tokio::spawn(async move {
+                let _tx = stuck_tx;
+                std::future::pending::<()>().await
+            }),
+        );
+
+        running_tx.send(false).unwrap();
+        timeout(
+            Duration::from_secs(1),
+            supervisor.shutdown(Duration::from_millis(100)),
+        )
+        .await
+        .expect("Shutdown is not bounded");
+
+        // Tasks exiting during shutdown are not restarted, stuck ones
+        // are aborted
+        assert_eq!(starts.load(Ordering::SeqCst), 1);
+        assert!(stuck_rx.await.is_err());
+    }
+}
@@ -0,0 +1,355 @@
+//! Spans around the stages of the event pipeline, for finding where the
+//! latency of events adds up.
+//!
+//! Stages are marked with `stage!`, which opens a span with the
+//! `tracing` feature and does nothing otherwise, so the default build
+//! keeps logging with `log` only. With the feature, spans are exported
+//! over OTLP when `otel.traces.endpoint` is set, and events recorded
+//! with `tracing` in fact are handed to the logger set up by
+//! `init_log`, so the logs read the same with and without the feature.
+
+use log::warn;
+
+use crate::config::TracesConfig;
+
+/// Open a span for a stage of the pipeline, closed when the returned
+/// guard is dropped. The guard must not be held across an `.await`.
+#[cfg(feature = "tracing")]
+macro_rules! stage {
+    ($name:literal) => {
+        tracing::debug_span!($name).entered()
+    };
+}
+
+#[cfg(not(feature = "tracing"))]
+macro_rules! stage {
+    ($name:literal) => {
+        $crate::trace::NoSpan
+    };
+}
+
+/// Create a span for a stage of the pipeline spanning awaits, without
+/// entering it. The stage ends when the span is dropped.
+#[cfg(feature = "tracing")]
+macro_rules! stage_span {
+    ($name:literal) => {
+        tracing::debug_span!($name)
+    };
+}
+
+#[cfg(not(feature = "tracing"))]
+macro_rules! stage_span {
+    ($name:literal) => {
+        $crate::trace::NoSpan
+    };
+}
+
+pub(crate) use {stage, stage_span};
+
+/// Stands for the spans of the stages without the `tracing` feature.
+#[cfg(not(feature = "tracing"))]
+pub struct NoSpan;
+
+/// Flushes the spans left to export when dropped.
+pub struct Tracing {
+    #[cfg(feature = "tracing")]
+    provider: Option<opentelemetry_sdk::trace::SdkTracerProvider>,
+}
+
+#[cfg(not(feature = "tracing"))]
+pub fn init(config: &TracesConfig) -> anyhow::Result<Tracing> {
+    if config.endpoint().is_some() {
+        warn!("otel.traces.endpoint is set, but fact was built without the tracing feature");
+    }
+    Ok(Tracing {})
+}
+
+/// Install the subscriber of `tracing`, exporting spans to the endpoint
+/// in `config` if there is one.
+#[cfg(feature = "tracing")]
+pub fn init(config: &TracesConfig) -> anyhow::Result<Tracing> {
+    use opentelemetry::trace::TracerProvider;
+    use tracing_subscriber::layer::SubscriberExt;
+
+    let provider = config
+        .endpoint()
+        .map(|endpoint| exporter::provider(endpoint, config.sample_ratio()))
+        .transpose()?;
+    let otel = provider
+        .as_ref()
+        .map(|provider| tracing_opentelemetry::layer().with_tracer(provider.tracer("fact")));
+    let subscriber = tracing_subscriber::registry()
+        .with(bridge::LogBridge::new(log::logger(), provider.is_some()))
+        .with(otel);
+    if tracing::subscriber::set_global_default(subscriber).is_err() {
+        warn!("A tracing subscriber is already installed, spans are not exported");
+    }
+    if let Some(endpoint) = config.endpoint() {
+        log::info!(
+            "Exporting spans to {endpoint}, sampling {}",
+            config.sample_ratio()
+        );
+    }
+    Ok(Tracing { provider })
+}
+
+#[cfg(feature = "tracing")]
+impl Drop for Tracing {
+    fn drop(&mut self) {
+        if let Some(provider) = self.provider.take()
+            && let Err(e) = provider.shutdown()
+        {
+            warn!("Failed to flush spans: {e}");
+        }
+    }
+}
+
+#[cfg(feature = "tracing")]
+mod exporter {
+    use opentelemetry_otlp::{Protocol, SpanExporter, WithExportConfig};
+    use opentelemetry_sdk::{
+        Resource,
+        trace::{Sampler, SdkTracerProvider},
+    };
+
+    pub(super) fn provider(endpoint: &str, sample_ratio: f64) -> anyhow::Result<SdkTracerProvider> {
+        let exporter = SpanExporter::builder()
+            .with_http()
+            .with_protocol(Protocol::HttpBinary)
+            .with_endpoint(endpoint)
+            .build()?;
+        // Stages nested in a sampled one are kept along with it
+        let sampler = Sampler::ParentBased(Box::new(Sampler::TraceIdRatioBased(sample_ratio)));
+        Ok(SdkTracerProvider::builder()
+            .with_batch_exporter(exporter)
+            .with_sampler(sampler)
+            .with_resource(Resource::builder().with_service_name("fact").build())
+            .build())
+    }
+}
+
+#[cfg(feature = "tracing")]
+mod bridge {
+    use std::fmt::{self, Write};
+
+    use tracing::{
+        Level, Metadata, Subscriber,
+        field::{Field, Visit},
+        subscriber::Interest,
+    };
+    use tracing_subscriber::layer::{Context, Layer};
+
+    /// Hands the events recorded with `tracing` to a `log` logger.
+    ///
+    /// Only events of fact are handed over, other crates never logged
+    /// theirs. Spans are dropped unless they are exported.
+    pub(super) struct LogBridge {
+        logger: &'static dyn log::Log,
+        spans: bool,
+    }
+
+    impl LogBridge {
+        pub(super) fn new(logger: &'static dyn log::Log, spans: bool) -> Self {
+            LogBridge { logger, spans }
+        }
+
+        fn wanted(&self, metadata: &Metadata<'_>) -> bool {
+            match metadata.is_span() {
+                true => self.spans,
+                false => metadata.target().split("::").next() == Some("fact"),
+            }
+        }
+    }
+
+    impl<S: Subscriber> Layer<S> for LogBridge {
+        fn register_callsite(&self, metadata: &'static Metadata<'static>) -> Interest {
+            match self.wanted(metadata) {
+                true => Interest::always(),
+                false => Interest::never(),
+            }
+        }
+
+        fn enabled(&self, metadata: &Metadata<'_>, _ctx: Context<'_, S>) -> bool {
+            self.wanted(metadata)
+        }
+
+        fn on_event(&self, event: &tracing::Event<'_>, _ctx: Context<'_, S>) {
+            let metadata = event.metadata();
+            let level = match *metadata.level() {
+                Level::ERROR => log::Level::Error,
+                Level::WARN => log::Level::Warn,
+                Level::INFO => log::Level::Info,
+                Level::DEBUG => log::Level::Debug,
+                Level::TRACE => log::Level::Trace,
+            };
+            let log_metadata = log::Metadata::builder()
+                .level(level)
+                .target(metadata.target())
+                .build();
+            if !self.logger.enabled(&log_metadata) {
+                return;
+            }
+
+            let mut message = Message::default();
+            event.record(&mut message);
+            self.logger.log(
+                &log::Record::builder()
+                    .metadata(log_metadata)
+                    .args(format_args!("{}", message.0))
+                    .module_path(metadata.module_path())
+                    .file(metadata.file())
+                    .line(metadata.line())
+                    .build(),
+            );
+        }
+    }
+
+    /// The message of an event, followed by its other fields.
+    #[derive(Default)]
+    struct Message(String);
+
+    impl Visit for Message {
+        fn record_str(&mut self, field: &Field, value: &str) {
+            match field.name() {
+                "message" => self.0.insert_str(0, value),
+                name => {
+                    let _ = write!(self.0, " {name}={value}");
+                }
+            }
+        }
+
+        fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
+            match field.name() {
+                "message" => self.0.insert_str(0, &format!("{value:?}")),
+                name => {
+                    let _ = write!(self.0, " {name}={value:?}");
+                }
+            }
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use std::sync::Mutex;
+
+        use tracing_subscriber::layer::SubscriberExt;
+
+        use super::*;
+
+        /// A logger keeping what would be written by `init_log`.
+        #[derive(Default)]
+        struct Capture(Mutex<Vec<String>>);
+
+        impl log::Log for Capture {
+            fn enabled(&self, metadata: &log::Metadata<'_>) -> bool {
+                metadata.level() <= log::Level::Info
+            }
+
+            fn log(&self, record: &log::Record<'_>) {
+                self.0.lock().unwrap().push(format!(
+                    "[{:<5}] ({}:{}) {}",
+                    record.level(),
+                    record.file().unwrap_or_default(),
+                    record.line().unwrap_or_default(),
+                    record.args()
+                ));
+            }
+
+            fn flush(&self) {}
+        }
+
+        #[test]
+        fn bridged() {
+            let capture: &'static Capture = Box::leak(Box::default());
+            let subscriber = tracing_subscriber::registry().with(LogBridge::new(capture, false));
+            let (line, entries) = tracing::subscriber::with_default(subscriber, || {
+                let entries = 3;
+                let line = line!() + 1;
+                tracing::info!("Inode map has {entries} entries");
+                tracing::warn!(path = "/etc", "Failed to scan");
+                tracing::debug!("Not logged at info");
+                tracing::info!(target: "h2", "Not logged before either");
+                (line, entries)
+            });
+
+            let file = file!();
+            assert_eq!(
+                *capture.0.lock().unwrap(),
+                [
+                    format!("[INFO ] ({file}:{line}) Inode map has {entries} entries"),
+                    format!("[WARN ] ({file}:{}) Failed to scan path=/etc", line + 1),
+                ]
+            );
+        }
+    }
+}
+
+#[cfg(all(test, feature = "tracing"))]
+mod tests {
+    use std::{
+        path::Path,
+        sync::{Arc, Mutex},
+    };
+
+    use fact_ebpf::{event_t, file_activity_type_t, inode_key_t};
+    use tracing::{Subscriber, span};
+    use tracing_subscriber::{
+        layer::{Context, Layer, SubscriberExt},
+        registry::LookupSpan,
+    };
+
+    use crate::{
+        event::Event,
+        inode_map::{InodeMap, Source},
+    };
+
+    /// Name of a span and of its parent.
+    type Opened = (&'static str, Option<&'static str>);
+
+    /// Records the spans opened.
+    #[derive(Clone, Default)]
+    struct Recorder(Arc<Mutex<Vec<Opened>>>);
+
+    impl<S: Subscriber + for<'a> LookupSpan<'a>> Layer<S> for Recorder {
+        fn on_new_span(&self, attrs: &span::Attributes<'_>, id: &span::Id, ctx: Context<'_, S>) {
+            let parent = ctx
+                .span(id)
+                .and_then(|span| span.parent())
+                .map(|parent| parent.name());
+            self.0
+                .lock()
+                .unwrap()
+                .push((attrs.metadata().name(), parent));
+        }
+    }
+
+    #[test]
+    fn stages() {
+        let recorder = Recorder::default();
+        let subscriber = tracing_subscriber::registry().with(recorder.clone());
+        tracing::subscriber::with_default(subscriber, || {
+            let inode = inode_key_t { inode: 1, dev: 2 };
+            let mut inodes = InodeMap::new();
+            inodes.insert(inode, Path::new("/etc/hosts"), Source::StartupScan);
+
+            let mut event = Event::try_from(&event_t {
+                type_: file_activity_type_t::FILE_ACTIVITY_OPEN,
+                inode,
+                ..Default::default()
+            })
+            .unwrap();
+            event.set_host_path(inodes.get(event.get_inode()).unwrap());
+            let _ = fact_api::FileActivity::try_from(event);
+        });
+
+        assert_eq!(
+            *recorder.0.lock().unwrap(),
+            [
+                ("parse", None),
+                ("container_id", Some("parse")),
+                ("host_path", None),
+                ("serialize", None),
+            ]
+        );
+    }
+}
@@ -260,6 +260,10 @@ def fact(
                 'bind': '/etc/stackrox/fact.yml',
                 'mode': 'ro',
             },
+            '/run/fact': {
+                'bind': '/run/fact',
+                'mode': 'rw',
+            },
         },
     )
 
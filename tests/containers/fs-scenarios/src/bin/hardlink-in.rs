@@ -0,0 +1,29 @@
+use std::{
+    fs::{hard_link, OpenOptions},
+    io::Write,
+    path::Path,
+};
+
+use fs_scenarios::{create, marker, monitored_dir};
+
+fn main() {
+    let dir = monitored_dir();
+    let outside = Path::new("/tmp/hardlink-in.txt");
+    let link = dir.join("hardlink-in.txt");
+
+    create(outside);
+    marker("link", &link);
+    hard_link(outside, &link).expect("Failed to link test file");
+
+    marker("write", &link);
+    let mut f = OpenOptions::new()
+        .write(true)
+        .open(&link)
+        .expect("Failed to open linked file");
+    f.write_all(b"Written through the link")
+        .expect("Failed to write to linked file");
+    drop(f);
+
+    // Tells the events of the link apart from events not sent yet
+    create(&dir.join("hardlink-in.done"));
+}
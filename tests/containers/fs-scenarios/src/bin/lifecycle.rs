@@ -0,0 +1,13 @@
+use std::fs::remove_file;
+
+use fs_scenarios::{create, marker, monitored_dir};
+
+fn main() {
+    let dir = monitored_dir();
+    for i in 0..3 {
+        let path = dir.join(format!("lifecycle-{i}.txt"));
+        create(&path);
+        marker("delete", &path);
+        remove_file(&path).expect("Failed to remove test file");
+    }
+}
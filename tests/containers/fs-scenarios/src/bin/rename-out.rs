@@ -0,0 +1,14 @@
+use std::{fs::rename, path::Path};
+
+use fs_scenarios::{create, marker, monitored_dir};
+
+fn main() {
+    let from = monitored_dir().join("rename-out.txt");
+    // The monitored directory is under /tmp, so the file is not moved
+    // across mounts
+    let to = Path::new("/tmp/rename-out.txt");
+
+    create(&from);
+    marker("rename", to);
+    rename(&from, to).expect("Failed to rename test file");
+}
@@ -0,0 +1,13 @@
+use std::fs::rename;
+
+use fs_scenarios::{create, marker, monitored_dir};
+
+fn main() {
+    let dir = monitored_dir();
+    let from = dir.join("rename-within.txt");
+    let to = dir.join("rename-within-renamed.txt");
+
+    create(&from);
+    marker("rename", &to);
+    rename(&from, &to).expect("Failed to rename test file");
+}
@@ -0,0 +1,66 @@
+//! Give a name to a file opened with O_TMPFILE, the way files are made
+//! to appear in a directory without being created there.
+
+use std::{
+    ffi::{c_char, c_int, CString},
+    fs::OpenOptions,
+    io::Write,
+    os::unix::{
+        ffi::OsStrExt,
+        fs::{MetadataExt, OpenOptionsExt},
+        io::AsRawFd,
+    },
+};
+
+use fs_scenarios::{marker, monitored_dir};
+
+// Values on x86_64 and aarch64
+const O_TMPFILE: c_int = 0o20200000;
+const AT_FDCWD: c_int = -100;
+const AT_SYMLINK_FOLLOW: c_int = 0x400;
+
+extern "C" {
+    fn linkat(
+        olddirfd: c_int,
+        oldpath: *const c_char,
+        newdirfd: c_int,
+        newpath: *const c_char,
+        flags: c_int,
+    ) -> c_int;
+}
+
+fn main() {
+    let dir = monitored_dir();
+    let mut f = OpenOptions::new()
+        .write(true)
+        .mode(0o600)
+        .custom_flags(O_TMPFILE)
+        .open(&dir)
+        .expect("Failed to open temporary file");
+    // Unnamed files are reported under the inode number the kernel
+    // names them after
+    let ino = f.metadata().expect("Failed to stat temporary file").ino();
+    marker("tmpfile", &dir.join(format!("#{ino}")));
+    f.write_all(b"This is a test")
+        .expect("Failed to write to temporary file");
+
+    let path = dir.join("tmpfile-link.txt");
+    marker("link", &path);
+    let old = CString::new(format!("/proc/self/fd/{}", f.as_raw_fd())).unwrap();
+    let new = CString::new(path.as_os_str().as_bytes()).unwrap();
+    let res = unsafe {
+        linkat(
+            AT_FDCWD,
+            old.as_ptr(),
+            AT_FDCWD,
+            new.as_ptr(),
+            AT_SYMLINK_FOLLOW,
+        )
+    };
+    assert_eq!(
+        res,
+        0,
+        "Failed to link temporary file: {}",
+        std::io::Error::last_os_error()
+    );
+}
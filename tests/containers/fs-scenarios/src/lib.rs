@@ -0,0 +1,32 @@
+//! Helpers shared by the scenarios, each one a binary in `src/bin`.
+//!
+//! Scenarios take the monitored directory as their only argument and
+//! print a marker before each step, the tests check the markers to
+//! tell a scenario that didn't run from events that went missing.
+
+use std::{
+    fs::File,
+    io::Write,
+    path::{Path, PathBuf},
+};
+
+/// The monitored directory passed to the scenario.
+pub fn monitored_dir() -> PathBuf {
+    std::env::args()
+        .nth(1)
+        .expect("Monitored directory not provided")
+        .into()
+}
+
+/// Print the marker of a step acting on `path`.
+pub fn marker(step: &str, path: &Path) {
+    println!("marker: {step} {}", path.display());
+}
+
+/// Create `path`, write to it and close it.
+pub fn create(path: &Path) {
+    marker("create", path);
+    let mut f = File::create(path).expect("Failed to create test file");
+    f.write_all(b"This is a test")
+        .expect("Failed to write to test file");
+}
@@ -17,6 +17,10 @@ except ImportError:
 import utils
 
 
+# Login UID of processes not started from a login
+AUDIT_UNSET = pow(2, 32) - 1
+
+
 def extract_container_id(cgroup: str) -> str:
     if (scope_idx := cgroup.rfind('.scope')) != -1:
         cgroup = cgroup[:scope_idx]
@@ -47,6 +51,8 @@ class EventType(Enum):
     XATTR_SET = 7
     XATTR_REMOVE = 8
     ACL = 9
+    WRITE = 10
+    ATTRIBUTES = 11
 
 
 # POSIX ACL type values matching the AclType proto enum.
@@ -76,7 +82,7 @@ class Process:
         args: str,
         name: str,
         container_id: str,
-        loginuid: int,
+        loginuid: int | None,
     ):
         self._pid: int | None = pid
         self._uid: int = uid
@@ -85,7 +91,7 @@ class Process:
         self._args: str = args
         self._name: str = name
         self._container_id: str = container_id
-        self._loginuid: int = loginuid
+        self._loginuid: int | None = loginuid
 
     @classmethod
     def from_proc(cls, pid: int | None = None):
@@ -126,6 +132,8 @@ class Process:
 
         with open(os.path.join(proc_dir, 'loginuid')) as f:
             loginuid = int(f.read())
+            if loginuid == AUDIT_UNSET:
+                loginuid = None
 
         return Process(
             pid=pid,
@@ -150,7 +158,7 @@ class Process:
             pid=None,
             uid=0,
             gid=0,
-            loginuid=pow(2, 32) - 1,
+            loginuid=None,
             exe_path=exe_path,
             args=args,
             name=name,
@@ -186,7 +194,7 @@ class Process:
         return self._container_id
 
     @property
-    def loginuid(self) -> int:
+    def loginuid(self) -> int | None:
         return self._loginuid
 
     def diff(self, other: Process) -> dict | None:
@@ -217,7 +225,18 @@ class Process:
             self.container_id,
             other.container_id,
         )
-        Event._diff_field(diff, 'loginuid', self.loginuid, other.loginuid)
+        # gRPC messages send an unset login UID as 0
+        Event._diff_field(
+            diff, 'loginuid', self.loginuid or 0, other.loginuid or 0
+        )
+
+        elif self.event_type == EventType.ATTRIBUTES:
+            Event._diff_field(
+                diff, 'old_flags', self.old_flags, other.old_flags
+            )
+            Event._diff_field(
+                diff, 'new_flags', self.new_flags, other.new_flags
+            )
 
         return diff if diff else None
 
@@ -248,9 +267,12 @@ class Event:
         owner_gid: int | None = None,
         old_file: str | Pattern[str] | None = None,
         old_host_path: str | Pattern[str] | None = None,
+        overwrote_existing: bool | None = None,
         xattr_name: str | None = None,
         acl_type: int | None = None,
         acl_entries: list[dict] | None = None,
+        old_flags: list[str] | None = None,
+        new_flags: list[str] | None = None,
     ):
         self._type: EventType = event_type
         self._process: Process = process
@@ -261,9 +283,12 @@ class Event:
         self._owner_gid: int | None = owner_gid
         self._old_file: str | Pattern[str] | None = old_file
         self._old_host_path: str | Pattern[str] | None = old_host_path
+        self._overwrote_existing: bool | None = overwrote_existing
         self._xattr_name: str | None = xattr_name
         self._acl_type: int | None = acl_type
         self._acl_entries: list[dict] | None = acl_entries
+        self._old_flags: list[str] | None = old_flags
+        self._new_flags: list[str] | None = new_flags
 
     @property
     def event_type(self) -> EventType:
@@ -301,6 +326,10 @@ class Event:
     def old_host_path(self) -> str | Pattern[str] | None:
         return self._old_host_path
 
+    @property
+    def overwrote_existing(self) -> bool | None:
+        return self._overwrote_existing
+
     @property
     def xattr_name(self) -> str | None:
         return self._xattr_name
@@ -313,6 +342,14 @@ class Event:
     def acl_entries(self) -> list[dict] | None:
         return self._acl_entries
 
+    @property
+    def old_flags(self) -> list[str] | None:
+        return self._old_flags
+
+    @property
+    def new_flags(self) -> list[str] | None:
+        return self._new_flags
+
     @classmethod
     def _diff_field(cls, diff: dict, name: str, expected: Any, actual: Any):
         if expected != actual:
@@ -385,6 +422,18 @@ class Event:
             Event._diff_path(
                 diff, 'old_host_path', self.old_host_path, other.old_host_path
             )
+            # The gRPC messages do not carry the flag, it is only
+            # compared when both sides have it.
+            if (
+                self.overwrote_existing is not None
+                and other.overwrote_existing is not None
+            ):
+                Event._diff_field(
+                    diff,
+                    'overwrote_existing',
+                    self.overwrote_existing,
+                    other.overwrote_existing,
+                )
 
         if self.event_type == EventType.PERMISSION:
             Event._diff_field(diff, 'mode', self.mode, other.mode)
@@ -435,6 +484,8 @@ class Event:
                 f', old_file="{self.old_file}"'
                 f', old_host_path="{self.old_host_path}"'
             )
+            if self.overwrote_existing is not None:
+                s += f', overwrote_existing={self.overwrote_existing}'
 
         if self.event_type in (EventType.XATTR_SET, EventType.XATTR_REMOVE):
             s += f', xattr_name="{self.xattr_name}"'
@@ -443,6 +494,9 @@ class Event:
             s += f', acl_type={self.acl_type}'
             s += f', acl_entries={self.acl_entries}'
 
+        if self.event_type == EventType.ATTRIBUTES:
+            s += f', old_flags={self.old_flags}, new_flags={self.new_flags}'
+
         s += ')'
 
         return s
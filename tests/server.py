@@ -48,6 +48,8 @@ EVENT_TYPE_MAP: dict[str, EventType] = {
     'xattr_set': EventType.XATTR_SET,
     'xattr_remove': EventType.XATTR_REMOVE,
     'acl': EventType.ACL,
+    'write': EventType.WRITE,
+    'attributes': EventType.ATTRIBUTES,
 }
 
 
@@ -363,7 +365,7 @@ class OtlpServer(EventServer):
             args=args,
             name=proc_data.get('comm', ''),
             container_id=proc_data.get('container_id', ''),
-            loginuid=proc_data.get('login_uid', 0),
+            loginuid=proc_data.get('login_uid'),
         )
 
         kwargs: dict[str, Any] = {
@@ -374,6 +376,9 @@ class OtlpServer(EventServer):
             old = file_data.get('old', {})
             kwargs['old_file'] = old.get('filename', '')
             kwargs['old_host_path'] = old.get('host_path', '')
+            kwargs['overwrote_existing'] = file_data.get(
+                'overwrote_existing', False
+            )
         elif event_type == EventType.PERMISSION:
             kwargs['mode'] = file_data.get('new_mode')
         elif event_type == EventType.OWNERSHIP:
@@ -387,6 +392,9 @@ class OtlpServer(EventServer):
                 OtlpServer._acl_entry_translate(entry)
                 for entry in file_data.get('entries', [])
             ]
+        elif event_type == EventType.ATTRIBUTES:
+            kwargs['old_flags'] = file_data.get('old_flags', [])
+            kwargs['new_flags'] = file_data.get('new_flags', [])
 
         return Event(
             process=process,
@@ -0,0 +1,161 @@
+"""Tests for attribute flag change events.
+
+Flags are changed with chattr, which goes through the FS_IOC_SETFLAGS
+ioctl. The gRPC API has no message for these events, they are only
+checked on the OTLP output.
+"""
+
+from __future__ import annotations
+
+import os
+import shutil
+import subprocess
+import tempfile
+
+import pytest
+
+from event import Event, EventType, Process
+from server import EventServer
+from utils import btf_has_symbol
+
+
+def _chattr_supported() -> bool:
+    """Check whether flags can be changed on the filesystem under cwd."""
+    if shutil.which('chattr') is None:
+        return False
+    fd, path = tempfile.mkstemp(dir=os.getcwd())
+    try:
+        res = subprocess.run(['chattr', '-a', path], capture_output=True)
+        return res.returncode == 0
+    finally:
+        os.close(fd)
+        os.unlink(path)
+
+
+pytestmark = [
+    pytest.mark.skipif(
+        not btf_has_symbol('bpf_lsm_file_ioctl'),
+        reason='kernel does not support file_ioctl LSM hook',
+    ),
+    pytest.mark.skipif(
+        not _chattr_supported(),
+        reason='filesystem does not support attribute flags',
+    ),
+]
+
+
+@pytest.fixture(autouse=True)
+def otlp_only(server: EventServer):
+    if server.output_mode == 'grpc':
+        pytest.skip('attribute changes are not part of the gRPC messages')
+
+
+def chattr(*args: str) -> Process:
+    """
+    Run chattr and build the process expected for it.
+    """
+    subprocess.run(['chattr', *args], check=True)
+
+    current = Process.from_proc()
+    exe_path = shutil.which('chattr')
+    assert exe_path is not None
+    return Process(
+        pid=None,
+        uid=current.uid,
+        gid=current.gid,
+        exe_path=os.path.realpath(exe_path),
+        args=' '.join(['chattr', *args]),
+        name='chattr',
+        container_id=current.container_id,
+        loginuid=current.loginuid,
+    )
+
+
+def attributes_event(
+    process: Process, path: str, old: list[str], new: list[str]
+) -> Event:
+    return Event(
+        process=process,
+        event_type=EventType.ATTRIBUTES,
+        file='',
+        host_path=path,
+        old_flags=old,
+        new_flags=new,
+    )
+
+
+def test_immutable(
+    test_file: str,
+    server: EventServer,
+):
+    """
+    Tests that setting and clearing the immutable flag on a monitored
+    file are reported.
+
+    The test_file fixture creates a file before fact starts, so it is
+    picked up by the initial scan and its inode is already tracked.
+    """
+    try:
+        set_process = chattr('+i', test_file)
+    finally:
+        clear_process = chattr('-i', test_file)
+
+    server.wait_events(
+        skip=('open', 'xattr', 'acl'),
+        events=[
+            attributes_event(set_process, test_file, [], ['immutable']),
+            attributes_event(clear_process, test_file, ['immutable'], []),
+        ],
+    )
+
+
+def test_append_only(
+    test_file: str,
+    server: EventServer,
+):
+    """
+    Tests that the append-only flag is reported along with the
+    immutable one when both are set.
+    """
+    try:
+        set_process = chattr('+a', test_file)
+        both_process = chattr('+i', test_file)
+    finally:
+        clear_process = chattr('-i', '-a', test_file)
+
+    server.wait_events(
+        skip=('open', 'xattr', 'acl'),
+        events=[
+            attributes_event(set_process, test_file, [], ['append']),
+            attributes_event(
+                both_process, test_file, ['append'], ['immutable', 'append']
+            ),
+            attributes_event(
+                clear_process, test_file, ['immutable', 'append'], []
+            ),
+        ],
+    )
+
+
+def test_other_flags_ignored(
+    test_file: str,
+    server: EventServer,
+):
+    """
+    Tests that changing flags other than immutable, append-only and
+    fs-verity is not reported.
+    """
+    try:
+        chattr('+d', test_file)
+        chattr('-d', test_file)
+        set_process = chattr('+i', test_file)
+    finally:
+        clear_process = chattr('-i', test_file)
+
+    server.wait_events(
+        skip=('open', 'xattr', 'acl'),
+        events=[
+            attributes_event(set_process, test_file, [], ['immutable']),
+            attributes_event(clear_process, test_file, ['immutable'], []),
+        ],
+    )
@@ -7,10 +7,10 @@ from time import sleep
 import docker.models.containers
 import pytest
 import requests
-import yaml
 
 from event import Event, EventType, Process
 from server import EventServer, GrpcServer
+from utils import reload_config
 
 DEFAULT_URL = 'http://127.0.0.1:9000'
 
@@ -20,18 +20,6 @@ def assert_endpoint(endpoint: str, status_code: int = 200):
     assert resp.status_code == status_code
 
 
-def reload_config(
-    fact: docker.models.containers.Container,
-    config: dict,
-    file: str,
-    delay: float = 0.5,
-):
-    with open(file, 'w') as f:
-        yaml.dump(config, f)
-    fact.kill('SIGHUP')
-    sleep(delay)
-
-
 cases = [('metrics', 'expose_metrics'), ('health_check', 'health_check')]
 
 
@@ -0,0 +1,133 @@
+from __future__ import annotations
+
+import os
+import shutil
+import subprocess
+
+import docker.models.containers
+import pytest
+
+from event import Event, EventType, Process
+from server import EventServer
+from utils import get_metric_value, reload_config
+
+
+def rm_process(path: str) -> Process:
+    """
+    Build the process expected for running `rm` from the tests.
+    """
+    current = Process.from_proc()
+    exe_path = shutil.which('rm')
+    assert exe_path is not None
+    return Process(
+        pid=None,
+        uid=current.uid,
+        gid=current.gid,
+        exe_path=os.path.realpath(exe_path),
+        args=f'rm {path}',
+        name='rm',
+        container_id=current.container_id,
+        loginuid=current.loginuid,
+    )
+
+
+def get_blocked_unlinks(fact_config: tuple[dict, str]) -> int:
+    value = get_metric_value(
+        fact_config,
+        'kernel_path_unlink_events',
+        {'label': 'Blocked'},
+    )
+    return int(value) if value is not None else 0
+
+
+def test_unlink_blocked(
+    fact: docker.models.containers.Container,
+    fact_config: tuple[dict, str],
+    ignored_dir: str,
+    server: EventServer,
+):
+    """
+    Removing an enforced protected file fails with EPERM, the attempt
+    is still reported.
+    """
+    fut = os.path.join(ignored_dir, 'protected.txt')
+    with open(fut, 'w') as f:
+        f.write('This is protected')
+
+    config, config_file = fact_config
+    config['protected_paths'] = [{'path': fut, 'enforce': True}]
+    config['enforcement_enabled'] = True
+    reload_config(fact, config, config_file)
+
+    res = subprocess.run(['rm', fut], capture_output=True, text=True)
+    assert res.returncode != 0
+    assert 'Operation not permitted' in res.stderr
+    assert os.path.exists(fut)
+
+    server.wait_events(
+        [
+            Event(
+                process=rm_process(fut),
+                event_type=EventType.UNLINK,
+                file=fut,
+                host_path=fut,
+            ),
+        ]
+    )
+    assert get_blocked_unlinks(fact_config) > 0
+
+    # Write opens are blocked too
+    with pytest.raises(PermissionError), open(fut, 'w') as f:
+        f.write('Overwritten')
+
+    # Lift enforcement so the directory can be cleaned up
+    config['enforcement_enabled'] = False
+    reload_config(fact, config, config_file)
+
+
+def test_enforcement_disabled(
+    fact: docker.models.containers.Container,
+    fact_config: tuple[dict, str],
+    ignored_dir: str,
+    server: EventServer,
+):
+    """
+    Protected paths are only reported without the global switch and
+    enforcement can be lifted with a configuration reload.
+    """
+    fut = os.path.join(ignored_dir, 'protected.txt')
+    with open(fut, 'w') as f:
+        f.write('This is protected')
+
+    config, config_file = fact_config
+    config['protected_paths'] = [{'path': fut, 'enforce': True}]
+    config['enforcement_enabled'] = True
+    reload_config(fact, config, config_file)
+
+    res = subprocess.run(['rm', fut], capture_output=True, text=True)
+    assert res.returncode != 0
+
+    config['enforcement_enabled'] = False
+    reload_config(fact, config, config_file)
+
+    res = subprocess.run(['rm', fut], capture_output=True, text=True)
+    assert res.returncode == 0, res.stderr
+    assert not os.path.exists(fut)
+
+    process = rm_process(fut)
+    server.wait_events(
+        [
+            Event(
+                process=process,
+                event_type=EventType.UNLINK,
+                file=fut,
+                host_path=fut,
+            ),
+            Event(
+                process=process,
+                event_type=EventType.UNLINK,
+                file=fut,
+                host_path=fut,
+            ),
+        ]
+    )
@@ -2,6 +2,8 @@ from __future__ import annotations
 
 import multiprocessing as mp
 import os
+import shutil
+import subprocess
 from multiprocessing.synchronize import Event as MpEvent
 
 import docker.models.containers
@@ -143,6 +145,79 @@ def test_ignored(test_file: str, ignored_dir: str, server: EventServer):
     server.wait_events([e])
 
 
+def mapped_libc() -> str:
+    """
+    Path of the C library mapped by the current process.
+    """
+    with open('/proc/self/maps') as f:
+        for line in f:
+            path = line.split()[-1]
+            if os.path.basename(path).startswith(('libc.so', 'libc-')):
+                return path
+    raise RuntimeError('libc is not mapped')
+
+
+def test_loader_opens(monitored_dir: str, server: EventServer):
+    """
+    Tests that executing a binary and the dynamic loader opening its
+    libraries out of a monitored directory are not captured.
+
+    Both the exec and the library opens are read-only, only the files
+    being copied into place must show up in the server.
+
+    Args:
+        monitored_dir: Temporary directory path for creating the test file.
+        server: The server instance to communicate with.
+    """
+    exe = os.path.join(monitored_dir, 'true')
+    true_path = shutil.which('true')
+    assert true_path is not None
+    shutil.copyfile(true_path, exe)
+    mode = 0o755
+    os.chmod(exe, mode)
+
+    libc = os.path.join(monitored_dir, 'libc.so.6')
+    shutil.copyfile(mapped_libc(), libc)
+
+    subprocess.run([exe], env={'LD_LIBRARY_PATH': monitored_dir}, check=True)
+
+    # Any event from the exec would show up before this one
+    fut = os.path.join(monitored_dir, 'done.txt')
+    with open(fut, 'w') as f:
+        f.write('This is a test')
+
+    process = Process.from_proc()
+    events = [
+        Event(
+            process=process,
+            event_type=EventType.CREATION,
+            file=exe,
+            host_path=exe,
+        ),
+        Event(
+            process=process,
+            event_type=EventType.PERMISSION,
+            file=exe,
+            host_path=exe,
+            mode=mode,
+        ),
+        Event(
+            process=process,
+            event_type=EventType.CREATION,
+            file=libc,
+            host_path=libc,
+        ),
+        Event(
+            process=process,
+            event_type=EventType.CREATION,
+            file=fut,
+            host_path=fut,
+        ),
+    ]
+
+    server.wait_events(events)
+
+
 def do_test(fut: str, stop_event: MpEvent):
     with open(fut, 'w') as f:
         f.write('This is a test')
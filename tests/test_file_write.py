@@ -0,0 +1,110 @@
+from __future__ import annotations
+
+import os
+from time import sleep
+
+import pytest
+import yaml
+
+from event import Event, EventType, Process
+from server import EventServer
+
+
+@pytest.fixture
+def fact_config(fact_config: tuple[dict, str]):
+    """
+    Enable the optional file_permission program, BPF programs are only
+    loaded on startup.
+    """
+    config, config_file = fact_config
+    config['bpf'] = {'programs': {'file_permission': {'enabled': True}}}
+    with open(config_file, 'w') as f:
+        yaml.dump(config, f)
+    return config, config_file
+
+
+def writes(server: EventServer, event: Event) -> list[Event]:
+    """
+    The gRPC API has no message for writes, they are not sent to it.
+    """
+    if server.output_mode == 'grpc':
+        return []
+    return [event]
+
+
+def test_write_sometimes(
+    test_file: str,
+    server: EventServer,
+):
+    """
+    Tests that opening a file for writing is only followed by a write
+    event when something is written, and that consecutive writes are
+    reported once.
+
+    Args:
+        test_file: File monitored on the host.
+        server: The server instance to communicate with.
+    """
+    process = Process.from_proc()
+
+    fd = os.open(test_file, os.O_RDWR)
+    os.write(fd, b'first')
+    os.write(fd, b'second')
+    os.close(fd)
+
+    fd = os.open(test_file, os.O_RDWR)
+    os.close(fd)
+
+    opened = Event(
+        process=process,
+        event_type=EventType.OPEN,
+        file=test_file,
+        host_path=test_file,
+    )
+    written = Event(
+        process=process,
+        event_type=EventType.WRITE,
+        file=test_file,
+        host_path=test_file,
+    )
+    server.wait_events([opened] + writes(server, written) + [opened])
+
+
+def test_write_after_window(
+    test_file: str,
+    server: EventServer,
+):
+    """
+    Tests that writes are reported again once the deduplication window
+    of the previous one is over.
+
+    Args:
+        test_file: File monitored on the host.
+        server: The server instance to communicate with.
+    """
+    process = Process.from_proc()
+
+    fd = os.open(test_file, os.O_RDWR)
+    os.write(fd, b'first')
+    sleep(1.5)
+    os.write(fd, b'second')
+    os.close(fd)
+
+    events = [
+        Event(
+            process=process,
+            event_type=EventType.OPEN,
+            file=test_file,
+            host_path=test_file,
+        ),
+    ]
+    events += writes(
+        server,
+        Event(
+            process=process,
+            event_type=EventType.WRITE,
+            file=test_file,
+            host_path=test_file,
+        ),
+    ) * 2
+    server.wait_events(events)
@@ -63,6 +63,7 @@ def test_rename(
                 host_path=fut,
                 old_file=old_fut,
                 old_host_path=old_fut,
+                overwrote_existing=False,
             ),
             Event(
                 process=p,
@@ -71,6 +72,52 @@ def test_rename(
                 host_path=old_fut,
                 old_file=fut,
                 old_host_path=fut,
+                overwrote_existing=False,
+            ),
+        ],
+    )
+
+
+def test_rename_overwrite(monitored_dir: str, server: EventServer):
+    """
+    Tests renaming a file onto an existing one, which is reported as
+    replacing it.
+
+    Args:
+        monitored_dir: Temporary directory path for creating the test file.
+        server: The server instance to communicate with.
+    """
+    source = os.path.join(monitored_dir, 'source.txt')
+    target = os.path.join(monitored_dir, 'target.txt')
+
+    for path in (source, target):
+        with open(path, 'w') as f:
+            f.write('This is a test')
+    os.rename(source, target)
+
+    p = Process.from_proc()
+    server.wait_events(
+        [
+            Event(
+                process=p,
+                event_type=EventType.CREATION,
+                file=source,
+                host_path=source,
+            ),
+            Event(
+                process=p,
+                event_type=EventType.CREATION,
+                file=target,
+                host_path=target,
+            ),
+            Event(
+                process=p,
+                event_type=EventType.RENAME,
+                file=target,
+                host_path=target,
+                old_file=source,
+                old_host_path=source,
+                overwrote_existing=True,
             ),
         ],
     )
@@ -0,0 +1,135 @@
+from __future__ import annotations
+
+import json
+import os
+from itertools import count
+from time import sleep
+
+import docker
+import docker.models.containers
+import pytest
+
+from conftest import dump_logs
+
+
+@pytest.fixture
+def bounded_fact(
+    request: pytest.FixtureRequest,
+    docker_client: docker.DockerClient,
+    monitored_dir: str,
+    logs_dir: str,
+):
+    """
+    Run a second fact container that stops on its own, writing events
+    to stdout.
+
+    Returns a function that takes the extra arguments for fact and
+    returns the started container.
+    """
+    image = request.config.getoption('--image')
+    assert isinstance(image, str)
+    containers: list[docker.models.containers.Container] = []
+
+    def run(*args: str) -> docker.models.containers.Container:
+        container = docker_client.containers.run(
+            image,
+            ['--paths', f'{monitored_dir}/**/*', '--json', *args],
+            detach=True,
+            environment={
+                'FACT_LOGLEVEL': 'debug',
+                'FACT_HOST_MOUNT': '/host',
+            },
+            name='fact-bounded',
+            network_mode='host',
+            privileged=True,
+            volumes={
+                '/': {
+                    'bind': '/host',
+                    'mode': 'ro',
+                },
+            },
+        )
+        containers.append(container)
+        return container
+
+    yield run
+
+    for container in containers:
+        container.stop(timeout=1)
+        dump_logs(container, os.path.join(logs_dir, 'fact-bounded.log'))
+        container.remove()
+
+
+def generate_events(
+    container: docker.models.containers.Container,
+    monitored_dir: str,
+):
+    """
+    Keep writing files in the monitored directory until fact exits.
+    """
+    for i in count():
+        container.reload()
+        if container.status == 'exited':
+            return
+        with open(os.path.join(monitored_dir, f'file_{i}.txt'), 'w') as f:
+            f.write(f'test {i}')
+        sleep(0.05)
+
+
+def stdout_events(container: docker.models.containers.Container) -> list:
+    """
+    Parse the events written by fact to stdout, failing on incomplete
+    lines.
+    """
+    output = container.logs(stdout=True, stderr=False).decode('utf-8')
+    assert output == '' or output.endswith('\n'), 'Incomplete final line'
+    return [json.loads(line) for line in output.splitlines()]
+
+
+def test_run_for(bounded_fact, monitored_dir: str):
+    container = bounded_fact('--run-for', '3')
+    generate_events(container, monitored_dir)
+
+    exit_status = container.wait(timeout=10)
+    assert exit_status['StatusCode'] == 0
+    assert len(stdout_events(container)) > 0
+
+
+def test_max_events(bounded_fact, monitored_dir: str):
+    container = bounded_fact('--max-events', '5')
+    generate_events(container, monitored_dir)
+
+    exit_status = container.wait(timeout=10)
+    assert exit_status['StatusCode'] == 0
+    assert len(stdout_events(container)) == 5
+
+
+def test_existence_check(bounded_fact, monitored_dir: str):
+    container = bounded_fact(
+        '--run-for', '3', '--enrich-existence-check', 'true'
+    )
+    for i in count():
+        container.reload()
+        if container.status == 'exited':
+            break
+        with open(os.path.join(monitored_dir, f'kept_{i}.txt'), 'w') as f:
+            f.write(f'test {i}')
+        deleted = os.path.join(monitored_dir, f'deleted_{i}.txt')
+        with open(deleted, 'w') as f:
+            f.write(f'test {i}')
+        os.remove(deleted)
+        sleep(0.05)
+
+    exit_status = container.wait(timeout=10)
+    assert exit_status['StatusCode'] == 0
+
+    exists: dict[str, set[str]] = {'kept': set(), 'deleted': set()}
+    for event in stdout_events(container):
+        if 'exists_at_emit' not in event:
+            continue
+        [data] = event['file'].values()
+        prefix = os.path.basename(data['filename']).split('_')[0]
+        exists[prefix].add(event['exists_at_emit'])
+
+    assert exists['kept'] == {'true'}
+    assert 'false' in exists['deleted']
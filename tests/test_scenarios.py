@@ -0,0 +1,287 @@
+from __future__ import annotations
+
+import os
+from shutil import rmtree
+from tempfile import mkdtemp
+
+import docker
+import docker.models.containers
+import docker.models.images
+import pytest
+
+from conftest import dump_logs
+from event import Event, EventType, Process
+from server import EventServer
+
+# Where the scenarios find the monitored directory, the directory
+# holding it is mounted as /tmp so files can be moved out of it without
+# crossing mounts.
+CONTAINER_DIR = '/tmp/monitored'
+
+
+@pytest.fixture
+def scenario_dir():
+    """
+    Create the directory mounted in the scenario containers.
+    """
+    tmp = mkdtemp(prefix='fact-test-', dir=os.getcwd())
+    yield tmp
+    rmtree(tmp)
+
+
+@pytest.fixture
+def monitored_dir(scenario_dir: str):
+    """
+    Monitor a directory inside the one mounted in the containers, the
+    rest of it is not monitored.
+    """
+    monitored = os.path.join(scenario_dir, 'monitored')
+    os.mkdir(monitored)
+    return monitored
+
+
+@pytest.fixture(scope='module')
+def build_fs_scenarios(docker_client: docker.DockerClient):
+    image, _ = docker_client.images.build(
+        path='containers/fs-scenarios',
+        tag='fs-scenarios:latest',
+        dockerfile='Containerfile',
+    )
+    return image
+
+
+class Scenario:
+    """
+    A scenario run to completion in a container.
+
+    Args:
+        name: Name of the scenario binary.
+        container_id: Short ID of the container it ran in.
+        markers: Steps printed by the scenario, paths are the ones seen
+            in the container.
+    """
+
+    def __init__(self, name: str, container_id: str, markers: list[str]):
+        self.name = name
+        self.markers = markers
+        self.process = Process.in_container(
+            exe_path=f'/usr/local/bin/{name}',
+            args=f'{name} {CONTAINER_DIR}',
+            name=name,
+            container_id=container_id,
+        )
+
+
+@pytest.fixture
+def run_scenario(
+    fact: docker.models.containers.Container,
+    scenario_dir: str,
+    logs_dir: str,
+    docker_client: docker.DockerClient,
+    build_fs_scenarios: docker.models.images.Image,
+):
+    """
+    Run a scenario, returning once it exited successfully.
+    """
+    containers = []
+
+    def run(name: str) -> Scenario:
+        container = docker_client.containers.run(
+            build_fs_scenarios.tags[0],
+            [name, CONTAINER_DIR],
+            detach=True,
+            volumes={
+                scenario_dir: {
+                    'bind': '/tmp',
+                    'mode': 'z',
+                },
+            },
+            name=name,
+        )
+        containers.append(container)
+        exit_status = container.wait(timeout=10)
+        assert exit_status['StatusCode'] == 0, container.logs()
+        assert container.id is not None
+
+        logs = container.logs().decode('utf-8')
+        markers = [
+            line.removeprefix('marker: ')
+            for line in logs.splitlines()
+            if line.startswith('marker: ')
+        ]
+        return Scenario(name, container.id[:12], markers)
+
+    yield run
+
+    for container in containers:
+        dump_logs(container, os.path.join(logs_dir, f'{container.name}.log'))
+        container.remove()
+
+
+def test_rename_within(
+    monitored_dir: str,
+    server: EventServer,
+    run_scenario,
+):
+    """
+    A file renamed inside the monitored directory keeps being reported
+    under its new host path.
+    """
+    old = 'rename-within.txt'
+    new = 'rename-within-renamed.txt'
+    scenario = run_scenario('rename-within')
+    assert scenario.markers == [
+        f'create {CONTAINER_DIR}/{old}',
+        f'rename {CONTAINER_DIR}/{new}',
+    ]
+
+    server.wait_events(
+        [
+            Event(
+                process=scenario.process,
+                event_type=EventType.CREATION,
+                file=f'{CONTAINER_DIR}/{old}',
+                host_path=os.path.join(monitored_dir, old),
+            ),
+            Event(
+                process=scenario.process,
+                event_type=EventType.RENAME,
+                file=f'{CONTAINER_DIR}/{new}',
+                host_path=os.path.join(monitored_dir, new),
+                old_file=f'{CONTAINER_DIR}/{old}',
+                old_host_path=os.path.join(monitored_dir, old),
+            ),
+        ]
+    )
+
+
+def test_rename_out(
+    monitored_dir: str,
+    server: EventServer,
+    run_scenario,
+):
+    """
+    A file moved out of the monitored directory is reported without a
+    host path for its new location.
+    """
+    name = 'rename-out.txt'
+    scenario = run_scenario('rename-out')
+    assert scenario.markers == [
+        f'create {CONTAINER_DIR}/{name}',
+        f'rename /tmp/{name}',
+    ]
+
+    server.wait_events(
+        [
+            Event(
+                process=scenario.process,
+                event_type=EventType.CREATION,
+                file=f'{CONTAINER_DIR}/{name}',
+                host_path=os.path.join(monitored_dir, name),
+            ),
+            Event(
+                process=scenario.process,
+                event_type=EventType.RENAME,
+                file=f'/tmp/{name}',
+                host_path='',
+                old_file=f'{CONTAINER_DIR}/{name}',
+                old_host_path=os.path.join(monitored_dir, name),
+            ),
+        ]
+    )
+
+
+def test_hardlink_in(
+    monitored_dir: str,
+    server: EventServer,
+    run_scenario,
+):
+    """
+    Links are not reported and the inode of a file linked from outside
+    is not tracked, so writes through the link are dropped for lacking
+    a host path. Only the file created afterwards is reported.
+    """
+    name = 'hardlink-in.txt'
+    scenario = run_scenario('hardlink-in')
+    assert scenario.markers == [
+        f'create /tmp/{name}',
+        f'link {CONTAINER_DIR}/{name}',
+        f'write {CONTAINER_DIR}/{name}',
+        f'create {CONTAINER_DIR}/hardlink-in.done',
+    ]
+
+    server.wait_events(
+        [
+            Event(
+                process=scenario.process,
+                event_type=EventType.CREATION,
+                file=f'{CONTAINER_DIR}/hardlink-in.done',
+                host_path=os.path.join(monitored_dir, 'hardlink-in.done'),
+            ),
+        ]
+    )
+
+
+def test_lifecycle(
+    monitored_dir: str,
+    server: EventServer,
+    run_scenario,
+):
+    """
+    Files created, written, closed and deleted one after the other are
+    each reported once on creation and once on deletion.
+    """
+    scenario = run_scenario('lifecycle')
+    names = [f'lifecycle-{i}.txt' for i in range(3)]
+    assert scenario.markers == [
+        f'{step} {CONTAINER_DIR}/{name}'
+        for name in names
+        for step in ('create', 'delete')
+    ]
+
+    server.wait_events(
+        [
+            Event(
+                process=scenario.process,
+                event_type=event_type,
+                file=f'{CONTAINER_DIR}/{name}',
+                host_path=os.path.join(monitored_dir, name),
+            )
+            for name in names
+            for event_type in (EventType.CREATION, EventType.UNLINK)
+        ]
+    )
+
+
+def test_tmpfile_link(
+    monitored_dir: str,
+    server: EventServer,
+    run_scenario,
+):
+    """
+    A file opened with O_TMPFILE is reported when created without a
+    name, and again when linkat gives it one in the monitored directory.
+    """
+    scenario = run_scenario('tmpfile-link')
+    assert len(scenario.markers) == 2, scenario.markers
+    unnamed = scenario.markers[0].removeprefix('tmpfile ')
+    unnamed_name = os.path.basename(unnamed)
+    name = 'tmpfile-link.txt'
+    assert scenario.markers[1] == f'link {CONTAINER_DIR}/{name}'
+
+    server.wait_events(
+        [
+            Event(
+                process=scenario.process,
+                event_type=EventType.CREATION,
+                file=unnamed,
+                host_path=os.path.join(monitored_dir, unnamed_name),
+            ),
+            Event(
+                process=scenario.process,
+                event_type=EventType.CREATION,
+                file=f'{CONTAINER_DIR}/{name}',
+                host_path=os.path.join(monitored_dir, name),
+            ),
+        ]
+    )
@@ -2,8 +2,11 @@ from __future__ import annotations
 
 import os
 import re
+from time import sleep
 
+import docker.models.containers
 import requests
+import yaml
 
 
 def join_path_with_filename(directory: str, filename: str | bytes):
@@ -139,3 +142,24 @@ def get_metric_value(
                 return parts[-1]
 
     return None
+
+
+def reload_config(
+    fact: docker.models.containers.Container,
+    config: dict,
+    file: str,
+    delay: float = 0.5,
+):
+    """
+    Write a new configuration for fact and signal it to reload it.
+
+    Args:
+        fact: The fact container
+        config: The configuration to write
+        file: Path of the configuration file fact reads
+        delay: Seconds to wait for the new configuration to be applied
+    """
+    with open(file, 'w') as f:
+        yaml.dump(config, f)
+    fact.kill('SIGHUP')
+    sleep(delay)